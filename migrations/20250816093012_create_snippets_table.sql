@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Create Snippets Table
+CREATE TABLE snippets(
+  name TEXT NOT NULL,
+  PRIMARY KEY (name),
+  content TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  updated_at timestamptz NOT NULL
+);
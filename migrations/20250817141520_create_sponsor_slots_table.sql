@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Create Sponsor Slots Table
+CREATE TABLE sponsor_slots(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  sponsor_name TEXT NOT NULL,
+  creative_html TEXT NOT NULL,
+  target_url TEXT NOT NULL,
+  starts_at timestamptz NOT NULL,
+  ends_at timestamptz NOT NULL,
+  impressions BIGINT NOT NULL DEFAULT 0,
+  opens BIGINT NOT NULL DEFAULT 0,
+  clicks BIGINT NOT NULL DEFAULT 0,
+  created_at timestamptz NOT NULL,
+  CHECK (ends_at > starts_at)
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE subscription_tokens ADD COLUMN redirect_to TEXT NULL;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Create Push Subscriptions Table
+CREATE TABLE push_subscriptions(
+  endpoint TEXT NOT NULL,
+  PRIMARY KEY (endpoint),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+  p256dh TEXT NOT NULL,
+  auth TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
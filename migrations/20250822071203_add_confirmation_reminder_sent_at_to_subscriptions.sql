@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Pending subscribers get at most one reminder to confirm their subscription
+ALTER TABLE subscriptions ADD COLUMN confirmation_reminder_sent_at timestamptz NULL;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Switches flipped at runtime, e.g. to move between schema versions during a rolling deploy
+CREATE TABLE runtime_flags(
+  name TEXT NOT NULL,
+  PRIMARY KEY (name),
+  enabled BOOLEAN NOT NULL,
+  updated_at timestamptz NOT NULL
+);
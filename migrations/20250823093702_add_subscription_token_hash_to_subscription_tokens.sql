@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Hex-encoded SHA-256 of the token, so that plaintext tokens can eventually be dropped
+ALTER TABLE subscription_tokens ADD COLUMN subscription_token_hash TEXT NULL UNIQUE;
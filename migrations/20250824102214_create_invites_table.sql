@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Create Invites Table
+CREATE TABLE invites(
+  code TEXT NOT NULL,
+  PRIMARY KEY (code),
+  max_uses INTEGER NOT NULL CHECK (max_uses > 0),
+  uses INTEGER NOT NULL DEFAULT 0,
+  expires_at timestamptz NULL,
+  created_at timestamptz NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- The invite that brought the subscriber in, if any
+ALTER TABLE subscriptions ADD COLUMN invite_code TEXT NULL REFERENCES invites (code);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Create Broadcast Deliveries Table
+CREATE TABLE broadcast_deliveries(
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  sent_at timestamptz NOT NULL
+);
+CREATE INDEX broadcast_deliveries_subscriber_id_sent_at_idx
+  ON broadcast_deliveries (subscriber_id, sent_at);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Answers to the extra signup fields configured in `application.signup_fields`
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Create SMS Registrations Table
+CREATE TABLE sms_registrations(
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+  PRIMARY KEY (subscriber_id),
+  phone_number TEXT NOT NULL,
+  -- Pending verification, if any
+  verification_code_hash TEXT NULL,
+  verification_code_expires_at timestamptz NULL,
+  failed_verification_attempts INTEGER NOT NULL DEFAULT 0,
+  verified_at timestamptz NULL,
+  -- SMS opt-in is tracked apart from the email subscription
+  opted_in BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at timestamptz NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Create Branding Table: a single row, there is only one list
+CREATE TABLE branding(
+  id BOOLEAN NOT NULL DEFAULT TRUE CHECK (id),
+  PRIMARY KEY (id),
+  logo_url TEXT NULL,
+  accent_color TEXT NULL,
+  footer_address TEXT NULL,
+  social_links JSONB NOT NULL DEFAULT '[]',
+  updated_at timestamptz NOT NULL
+);
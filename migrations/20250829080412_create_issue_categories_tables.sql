@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Create Issue Categories Tables
+CREATE TABLE issue_categories(
+  name TEXT NOT NULL,
+  PRIMARY KEY (name),
+  description TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+-- Subscribers get every category unless they opted out of it
+CREATE TABLE category_opt_outs(
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  category TEXT NOT NULL REFERENCES issue_categories (name) ON DELETE CASCADE,
+  PRIMARY KEY (subscriber_id, category)
+);
@@ -0,0 +1,2 @@
+-- Set only while PII encryption is enabled: encrypted emails can't be compared directly.
+ALTER TABLE subscriptions ADD COLUMN email_blind_index TEXT NULL UNIQUE;
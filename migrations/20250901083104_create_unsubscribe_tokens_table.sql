@@ -0,0 +1,6 @@
+-- One token per subscriber, embedded in every issue they get.
+CREATE TABLE unsubscribe_tokens(
+  subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+  unsubscribe_token TEXT NOT NULL UNIQUE,
+  created_at timestamptz NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Publishers, who authenticate to send out issues.
+CREATE TABLE users(
+   user_id uuid PRIMARY KEY,
+   username TEXT NOT NULL UNIQUE,
+   password_hash TEXT NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Every issue that was published, as it was rendered before personalisation.
+CREATE TABLE newsletter_issues(
+   newsletter_issue_id uuid PRIMARY KEY,
+   title TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   published_at timestamptz NOT NULL,
+   -- NULL once the publisher has been deleted.
+   author_id uuid NULL REFERENCES users (user_id) ON DELETE SET NULL
+);
+CREATE INDEX newsletter_issues_published_at_idx ON newsletter_issues (published_at);
+-- NULL for deliveries from before issues were kept, and for smoke tests.
+ALTER TABLE broadcast_deliveries
+  ADD COLUMN newsletter_issue_id uuid NULL
+  REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE;
+CREATE INDEX broadcast_deliveries_newsletter_issue_id_idx
+  ON broadcast_deliveries (newsletter_issue_id);
@@ -0,0 +1,12 @@
+-- What happened to each recipient of an issue.
+CREATE TABLE newsletter_delivery_attempts(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   status TEXT NOT NULL CHECK (status IN ('sent', 'failed', 'bounced')),
+   -- Why a failed attempt failed.
+   error TEXT NULL,
+   attempted_at timestamptz NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX newsletter_delivery_attempts_subscriber_id_attempted_at_idx
+  ON newsletter_delivery_attempts (subscriber_id, attempted_at);
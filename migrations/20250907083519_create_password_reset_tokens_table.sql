@@ -0,0 +1,9 @@
+-- Where password reset links are sent. Optional: users without one can only be reset with `create-user`.
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE;
+
+-- Only a hash of each token is kept, like for confirmation tokens.
+CREATE TABLE password_reset_tokens(
+   password_reset_token_hash TEXT PRIMARY KEY,
+   user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+   expires_at timestamptz NOT NULL
+);
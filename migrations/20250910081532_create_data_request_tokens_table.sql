@@ -0,0 +1,15 @@
+-- Emailed to subscribers who ask for their data, to export or erase it. Hashed, like
+-- confirmation tokens.
+CREATE TABLE data_request_tokens(
+   data_request_token_hash TEXT PRIMARY KEY,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NOT NULL
+);
+
+-- Erasures carried out on a subscriber's request. Nothing that identifies them is kept.
+CREATE TABLE subscriber_erasures(
+   subscriber_id uuid PRIMARY KEY,
+   requested_at timestamptz NOT NULL,
+   erased_at timestamptz NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Confirmation links stop working once their token expires. Tokens handed out before
+-- this get the full lifetime from now.
+ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '7 days';
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at DROP DEFAULT;
@@ -0,0 +1,24 @@
+-- Several newsletters can be run from one deployment. Subscribers and issues belong
+-- to one of them; everything that existed before belongs to the default one.
+CREATE TABLE newsletters(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+INSERT INTO newsletters (id, slug, name, created_at)
+VALUES ('00000000-0000-0000-0000-000000000001', 'default', 'our newsletter', now());
+
+ALTER TABLE subscriptions ADD COLUMN newsletter_id uuid NOT NULL
+  DEFAULT '00000000-0000-0000-0000-000000000001' REFERENCES newsletters (id);
+ALTER TABLE subscriptions ALTER COLUMN newsletter_id DROP DEFAULT;
+-- The same address can subscribe to each newsletter once.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_blind_index_key;
+ALTER TABLE subscriptions ADD UNIQUE (newsletter_id, email);
+ALTER TABLE subscriptions ADD UNIQUE (newsletter_id, email_blind_index);
+
+ALTER TABLE newsletter_issues ADD COLUMN newsletter_id uuid NOT NULL
+  DEFAULT '00000000-0000-0000-0000-000000000001' REFERENCES newsletters (id);
+ALTER TABLE newsletter_issues ALTER COLUMN newsletter_id DROP DEFAULT;
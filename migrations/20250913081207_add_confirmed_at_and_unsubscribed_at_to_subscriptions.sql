@@ -0,0 +1,5 @@
+-- When subscribers followed their confirmation link, and when they unsubscribed, for the
+-- subscription funnel stats. Both stay NULL for what happened before they were recorded,
+-- and `confirmed_at` for subscribers imported as confirmed.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
@@ -0,0 +1,5 @@
+-- What a user may do through the admin APIs: `viewer`s read, `editor`s also publish and
+-- import, `admin`s also delete subscribers and manage users. Everyone was an admin so far.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin'
+    CHECK (role IN ('admin', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
@@ -0,0 +1,27 @@
+-- Opens and clicks of issues, for lists that turned tracking on. Off by default: it tells
+-- us who read what, which subscribers of some lists would rather we didn't know.
+ALTER TABLE newsletters ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+-- Whether the issue went out with tracked links and an open pixel, so that issues sent
+-- without them don't count as never opened.
+ALTER TABLE newsletter_issues ADD COLUMN tracked BOOLEAN NOT NULL DEFAULT false;
+
+-- The links of a tracked issue, numbered in the order they appear in its HTML.
+CREATE TABLE newsletter_issue_links(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   link_id INTEGER NOT NULL,
+   url TEXT NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, link_id)
+);
+
+CREATE TABLE tracking_events(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+   -- The link clicked, for clicks.
+   link_id INTEGER NULL,
+   occurred_at timestamptz NOT NULL
+);
+CREATE INDEX tracking_events_newsletter_issue_id_idx ON tracking_events (newsletter_issue_id);
+CREATE INDEX tracking_events_subscriber_id_idx ON tracking_events (subscriber_id);
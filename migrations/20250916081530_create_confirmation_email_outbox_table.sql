@@ -0,0 +1,18 @@
+-- Confirmation emails waiting to be sent, written in the same transaction as the token they
+-- carry: a subscriber can't be stored without their email being, even if we crash before
+-- sending it. Rotating or expiring the token takes its pending emails with it.
+CREATE TABLE confirmation_email_outbox(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   subscription_token TEXT NOT NULL
+      REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+   enqueued_at timestamptz NOT NULL,
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at timestamptz NOT NULL,
+   last_error TEXT NULL,
+   delivered_at timestamptz NULL
+);
+CREATE INDEX confirmation_email_outbox_due_idx
+   ON confirmation_email_outbox (next_attempt_at) WHERE delivered_at IS NULL;
+CREATE INDEX confirmation_email_outbox_subscription_token_idx
+   ON confirmation_email_outbox (subscription_token);
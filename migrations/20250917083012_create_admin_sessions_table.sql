@@ -0,0 +1,9 @@
+-- Admin sessions, when they are kept in Postgres rather than in the cache. Expired ones
+-- are never loaded, and are deleted by the `session_cleanup` job.
+CREATE TABLE admin_sessions(
+   session_key TEXT NOT NULL,
+   PRIMARY KEY (session_key),
+   state JSONB NOT NULL,
+   expires_at timestamptz NOT NULL
+);
+CREATE INDEX admin_sessions_expires_at_idx ON admin_sessions (expires_at);
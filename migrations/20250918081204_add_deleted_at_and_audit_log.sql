@@ -0,0 +1,26 @@
+-- Deleting a subscriber keeps their row, marked with when it was deleted, so that their
+-- history can still be looked up. Only an erasure requested by the subscriber removes it.
+ALTER TABLE subscriptions ADD COLUMN deleted_at timestamptz NULL;
+-- A deleted subscriber's address can sign up again.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_newsletter_id_email_key;
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_newsletter_id_email_blind_index_key;
+CREATE UNIQUE INDEX subscriptions_newsletter_id_email_key
+  ON subscriptions (newsletter_id, email) WHERE deleted_at IS NULL;
+CREATE UNIQUE INDEX subscriptions_newsletter_id_email_blind_index_key
+  ON subscriptions (newsletter_id, email_blind_index) WHERE deleted_at IS NULL;
+
+-- Every state transition of a subscriber: what happened, who did it, and from where.
+CREATE TABLE audit_log(
+   id BIGSERIAL PRIMARY KEY,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   event TEXT NOT NULL CHECK (event IN (
+      'subscribed', 'imported', 'admitted', 'confirmed', 'unsubscribed', 'bounced', 'deleted'
+   )),
+   -- `subscriber`, `user` (then `user_id` says which), `email_provider`, or the job's name.
+   actor TEXT NOT NULL,
+   user_id uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+   -- The client's address, for changes made over HTTP.
+   source_ip TEXT NULL,
+   occurred_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_log_subscriber_id_idx ON audit_log (subscriber_id, occurred_at);
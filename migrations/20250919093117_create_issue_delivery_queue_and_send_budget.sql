@@ -0,0 +1,25 @@
+-- Recipients of an issue that didn't fit in the send budget when it was published, for the
+-- `issue_delivery` job to send to as the budget refills. Deleting the issue or the
+-- subscriber takes them with it.
+CREATE TABLE issue_delivery_queue(
+   newsletter_issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   PRIMARY KEY (newsletter_issue_id, subscriber_id),
+   enqueued_at timestamptz NOT NULL
+);
+CREATE INDEX issue_delivery_queue_enqueued_at_idx ON issue_delivery_queue (enqueued_at);
+CREATE INDEX issue_delivery_queue_subscriber_id_idx ON issue_delivery_queue (subscriber_id);
+
+-- The sponsor slots injected into an issue, to count the impressions of deliveries made
+-- after it was published.
+ALTER TABLE newsletter_issues ADD COLUMN sponsor_slot_ids uuid[] NOT NULL DEFAULT '{}';
+
+-- The token bucket of `email_client.delivery.send_budget`, shared by every instance and kept
+-- across restarts. A single row, created when the budget is first used.
+CREATE TABLE send_budget(
+   id BOOLEAN NOT NULL DEFAULT TRUE CHECK (id),
+   PRIMARY KEY (id),
+   tokens DOUBLE PRECISION NOT NULL,
+   refilled_at timestamptz NOT NULL
+);
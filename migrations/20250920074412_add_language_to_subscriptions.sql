@@ -0,0 +1,3 @@
+-- The language the subscriber asked for on signup, as a lowercase BCP 47 tag such as `fr`
+-- or `pt-br`. Their emails and pages fall back to English when there is no translation.
+ALTER TABLE subscriptions ADD COLUMN language TEXT NULL;
@@ -0,0 +1,22 @@
+-- Queued deliveries are leased by the worker sending them: claiming one pushes its
+-- `execute_after` past the visibility timeout, so that other workers skip it, and a worker
+-- that dies mid-send leaves it to be claimed again once the lease runs out. `n_retries`
+-- counts the attempts made before the next one, failed or abandoned.
+ALTER TABLE issue_delivery_queue
+   ADD COLUMN n_retries INTEGER NOT NULL DEFAULT 0,
+   ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+CREATE INDEX issue_delivery_queue_execute_after_idx ON issue_delivery_queue (execute_after);
+
+-- Queued deliveries that ran out of retries, or whose recipient can't be read, kept for
+-- an operator to look into rather than retried forever.
+CREATE TABLE issue_delivery_dead_letters(
+   newsletter_issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   PRIMARY KEY (newsletter_issue_id, subscriber_id),
+   n_retries INTEGER NOT NULL,
+   last_error TEXT NOT NULL,
+   dead_lettered_at timestamptz NOT NULL
+);
+CREATE INDEX issue_delivery_dead_letters_subscriber_id_idx
+   ON issue_delivery_dead_letters (subscriber_id);
@@ -0,0 +1,18 @@
+-- The address a subscriber asked to move to, until they follow the link emailed to it:
+-- their current address keeps getting everything meanwhile. One per subscriber, asking
+-- again replaces it. `new_email` is stored like `subscriptions.email`, encrypted or not,
+-- and the token hashed, like data request tokens.
+CREATE TABLE pending_email_changes(
+   subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+   new_email TEXT NOT NULL,
+   new_email_blind_index TEXT NULL,
+   token_hash TEXT NOT NULL UNIQUE,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NOT NULL
+);
+
+ALTER TABLE audit_log DROP CONSTRAINT audit_log_event_check;
+ALTER TABLE audit_log ADD CONSTRAINT audit_log_event_check CHECK (event IN (
+   'subscribed', 'imported', 'admitted', 'confirmed', 'unsubscribed', 'bounced', 'deleted',
+   'email_changed'
+));
@@ -0,0 +1,5 @@
+-- Private issues are only for the subscribers they were sent to: they are left out of the
+-- public feed and archive of their list.
+ALTER TABLE newsletter_issues ADD COLUMN private BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX newsletter_issues_archive_idx ON newsletter_issues (newsletter_id, published_at)
+   WHERE NOT private;
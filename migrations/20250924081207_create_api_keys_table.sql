@@ -0,0 +1,25 @@
+-- Keys for scripts and CI pipelines to call the API with, as `Authorization: Bearer`. Only
+-- their SHA-256 is stored: the key itself is shown once, when it is created. A key acts as
+-- the admin who created it, and goes with them. Revoked keys are kept, along with their
+-- usage.
+CREATE TABLE api_keys(
+   id uuid PRIMARY KEY,
+   name TEXT NOT NULL,
+   key_hash TEXT NOT NULL UNIQUE,
+   scopes TEXT[] NOT NULL CHECK (cardinality(scopes) > 0 AND scopes <@ ARRAY['publish']),
+   created_by uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NULL,
+   last_used_at timestamptz NULL,
+   revoked_at timestamptz NULL
+);
+
+-- Every request a key authenticated, for audit.
+CREATE TABLE api_key_usage(
+   id BIGSERIAL PRIMARY KEY,
+   api_key_id uuid NOT NULL REFERENCES api_keys (id) ON DELETE CASCADE,
+   route TEXT NOT NULL,
+   source_ip TEXT NULL,
+   used_at timestamptz NOT NULL
+);
+CREATE INDEX api_key_usage_api_key_id_idx ON api_key_usage (api_key_id, used_at);
@@ -0,0 +1,5 @@
+-- Subscribers left out of an issue by the frequency cap are recorded along with those it
+-- was sent to, so that it can be told why they didn't get it.
+ALTER TABLE newsletter_delivery_attempts DROP CONSTRAINT newsletter_delivery_attempts_status_check;
+ALTER TABLE newsletter_delivery_attempts ADD CONSTRAINT newsletter_delivery_attempts_status_check
+   CHECK (status IN ('sent', 'failed', 'bounced', 'skipped_frequency_cap'));
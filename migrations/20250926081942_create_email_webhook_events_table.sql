@@ -0,0 +1,11 @@
+-- Providers send webhooks at least once and in no particular order. Events already handled
+-- are remembered by their id, and a delivery attempt keeps the time of the provider event
+-- that last changed it, so that an older event arriving late can be told apart.
+CREATE TABLE email_webhook_events(
+   event_id TEXT PRIMARY KEY,
+   received_at timestamptz NOT NULL DEFAULT now()
+);
+ALTER TABLE newsletter_delivery_attempts ADD COLUMN provider_event_at timestamptz;
+ALTER TABLE newsletter_delivery_attempts DROP CONSTRAINT newsletter_delivery_attempts_status_check;
+ALTER TABLE newsletter_delivery_attempts ADD CONSTRAINT newsletter_delivery_attempts_status_check
+   CHECK (status IN ('sent', 'delivered', 'failed', 'bounced', 'skipped_frequency_cap'));
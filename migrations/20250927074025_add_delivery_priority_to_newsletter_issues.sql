@@ -0,0 +1,6 @@
+-- The `issue_delivery` job takes turns between the issues it has deliveries queued for,
+-- starting with the one whose turn was longest ago, and gives each a number of batches
+-- per turn equal to its priority.
+ALTER TABLE newsletter_issues ADD COLUMN delivery_priority SMALLINT NOT NULL DEFAULT 1
+   CHECK (delivery_priority BETWEEN 1 AND 10);
+ALTER TABLE newsletter_issues ADD COLUMN last_dequeued_at timestamptz;
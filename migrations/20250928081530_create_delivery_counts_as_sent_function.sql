@@ -0,0 +1,5 @@
+-- What an issue counts as sent, for every report of its deliveries to agree: accepted by
+-- the provider and not bounced since. Failed, bounced and skipped attempts are counted apart.
+CREATE FUNCTION delivery_counts_as_sent(status TEXT) RETURNS boolean
+   LANGUAGE sql IMMUTABLE
+   AS $$ SELECT status IN ('sent', 'delivered') $$;
@@ -0,0 +1,5 @@
+-- The id the provider gave the message an issue was sent in: its webhooks say which
+-- message they are about by it, and are matched with the delivery it belongs to.
+ALTER TABLE newsletter_delivery_attempts ADD COLUMN provider_message_id TEXT;
+CREATE INDEX newsletter_delivery_attempts_provider_message_id_idx
+  ON newsletter_delivery_attempts (provider_message_id);
@@ -0,0 +1,14 @@
+-- The operational emails each user gets at their `email`. Publish summaries are opt-in;
+-- the others are on until turned off.
+ALTER TABLE users ADD COLUMN notifications TEXT[] NOT NULL
+   DEFAULT '{new_login, password_changed, delivery_failures}'
+   CHECK (notifications <@ '{new_login, password_changed, publish_completed, delivery_failures}');
+
+-- The browsers each user has logged in from, by a hash of their `User-Agent`, to tell
+-- them when one they haven't used before does.
+CREATE TABLE user_login_devices(
+   user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+   device_hash TEXT NOT NULL,
+   PRIMARY KEY (user_id, device_hash),
+   first_seen_at timestamptz NOT NULL
+);
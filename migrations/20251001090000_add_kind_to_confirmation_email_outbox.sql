@@ -0,0 +1,4 @@
+-- Reminders to confirm go through the outbox too: they are retried like confirmation emails.
+ALTER TABLE confirmation_email_outbox
+   ADD COLUMN kind TEXT NOT NULL DEFAULT 'confirmation'
+   CHECK (kind IN ('confirmation', 'reminder'));
@@ -0,0 +1,4 @@
+-- Who an issue was published without: `{"emails": [...], "tags": [...]}`. Emails are stored
+-- like those of subscribers, encrypted when PII encryption is on.
+ALTER TABLE newsletter_issues
+   ADD COLUMN exclusions JSONB NOT NULL DEFAULT '{"emails": [], "tags": []}';
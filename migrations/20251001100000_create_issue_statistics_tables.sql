@@ -0,0 +1,22 @@
+-- What the `issue_compaction` job keeps of an issue once its sent delivery attempts and its
+-- tracking events are deleted. `opened` and `clicked` count distinct subscribers, like the
+-- reports computed from the events; `unsubscribed` the recipients who unsubscribed before
+-- the next issue of the list, as of the compaction.
+CREATE TABLE issue_statistics (
+   newsletter_issue_id uuid PRIMARY KEY
+      REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+   sent BIGINT NOT NULL,
+   opened BIGINT NOT NULL,
+   clicked BIGINT NOT NULL,
+   unsubscribed BIGINT NOT NULL,
+   compacted_at timestamptz NOT NULL
+);
+
+-- The opens and clicks of each subscriber in issues that were compacted, which their own
+-- engagement still counts.
+CREATE TABLE subscriber_engagement_totals (
+   subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+   opens BIGINT NOT NULL,
+   clicks BIGINT NOT NULL,
+   last_engaged_at timestamptz NOT NULL
+);
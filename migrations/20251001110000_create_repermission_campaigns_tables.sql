@@ -0,0 +1,32 @@
+-- Campaigns asking the subscribers of a list with a tag whether they still want its issues,
+-- e.g. after importing an old list. Those who were asked and didn't click to stay by
+-- `closes_at` are unsubscribed by the `repermission_campaigns` job, which then closes it.
+CREATE TABLE repermission_campaigns(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   newsletter_id uuid NOT NULL REFERENCES newsletters (id) ON DELETE CASCADE,
+   tag TEXT NOT NULL,
+   created_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+   created_at timestamptz NOT NULL,
+   closes_at timestamptz NOT NULL,
+   closed_at timestamptz NULL
+);
+CREATE INDEX repermission_campaigns_open_idx
+   ON repermission_campaigns (closes_at) WHERE closed_at IS NULL;
+
+-- Who a campaign asks, as of when it started. `token` is the one their "stay subscribed"
+-- link carries, signed. Emails that failed are retried by the job a few times.
+CREATE TABLE repermission_requests(
+   campaign_id uuid NOT NULL REFERENCES repermission_campaigns (id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+   PRIMARY KEY (campaign_id, subscriber_id),
+   token TEXT NOT NULL UNIQUE,
+   attempts INTEGER NOT NULL DEFAULT 0,
+   last_attempt_at timestamptz NULL,
+   last_error TEXT NULL,
+   sent_at timestamptz NULL,
+   stayed_at timestamptz NULL,
+   unsubscribed_at timestamptz NULL
+);
+CREATE INDEX repermission_requests_unsent_idx
+   ON repermission_requests (campaign_id) WHERE sent_at IS NULL;
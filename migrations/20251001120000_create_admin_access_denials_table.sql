@@ -0,0 +1,10 @@
+-- Every request to `/admin` that `admin_access` blocked for coming from outside its networks,
+-- for audit.
+CREATE TABLE admin_access_denials(
+   id BIGSERIAL PRIMARY KEY,
+   route TEXT NOT NULL,
+   -- `NULL` if the client's address couldn't be told.
+   source_ip TEXT NULL,
+   denied_at timestamptz NOT NULL
+);
+CREATE INDEX admin_access_denials_denied_at_idx ON admin_access_denials (denied_at);
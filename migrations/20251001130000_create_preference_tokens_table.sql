@@ -0,0 +1,11 @@
+-- One token per confirmed subscriber, for the links to their preferences: issue categories,
+-- Web Push, SMS and email changes. Confirmation tokens are deleted once used.
+CREATE TABLE preference_tokens(
+  subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+  preference_token TEXT NOT NULL UNIQUE,
+  created_at timestamptz NOT NULL
+);
+-- Those who already confirmed get a preference link with their next issue.
+DELETE FROM subscription_tokens t
+USING subscriptions s
+WHERE s.id = t.subscriber_id AND s.status <> 'pending_confirmation';
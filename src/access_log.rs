@@ -0,0 +1,49 @@
+//! One line per finished request, emitted on its own target so that access logs
+//! can be filtered and shipped apart from application logs.
+use actix_web::HttpMessage;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use std::time::Instant;
+use tracing_actix_web::RequestId;
+
+/// The `tracing` target access log events are emitted on.
+pub const ACCESS_LOG_TARGET: &str = "access_log";
+
+/// Must run inside `TracingLogger`, which assigns the request id.
+pub async fn record_access(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started_at = Instant::now();
+    // The route pattern rather than the path, so that tokens and ids stay out of the logs.
+    let route = req.match_pattern();
+    let method = req.method().clone();
+    let client_ip = req.connection_info().peer_addr().map(str::to_owned);
+    let request_id = req.extensions().get::<RequestId>().copied();
+
+    let outcome = next.call(req).await;
+    let (status, bytes) = match &outcome {
+        Ok(response) => (
+            response.status(),
+            match response.response().body().size() {
+                BodySize::None => Some(0),
+                BodySize::Sized(bytes) => Some(bytes),
+                // Streamed bodies aren't measured.
+                BodySize::Stream => None,
+            },
+        ),
+        Err(error) => (error.as_response_error().status_code(), None),
+    };
+    tracing::info!(
+        target: ACCESS_LOG_TARGET,
+        method = %method,
+        route = route.as_deref(),
+        status = status.as_u16(),
+        bytes,
+        latency_ms = started_at.elapsed().as_secs_f64() * 1000.0,
+        client_ip = client_ip.as_deref(),
+        request_id = request_id.map(tracing::field::display),
+    );
+    outcome
+}
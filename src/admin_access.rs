@@ -0,0 +1,167 @@
+//! Restricts every route that takes a permission in `authorization::POLICY`, the admin
+//! pages and APIs and publishing, to a list of networks, for deployments that only expose
+//! them over a VPN.
+//!
+//! Behind a reverse proxy every request comes from the proxy: when the peer is one of
+//! `application.trusted_proxies`, the client is taken from `X-Forwarded-For` instead.
+//!
+//! Blocked requests are logged in `admin_access_denials`.
+use crate::authorization::{access, route_pattern};
+use crate::startup::TrustedProxies;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use ipnet::IpNet;
+use sqlx::PgPool;
+use std::net::IpAddr;
+
+pub struct AdminAllowlist {
+    pub allowed_networks: Vec<IpNet>,
+}
+
+impl AdminAllowlist {
+    fn allows(&self, ip: IpAddr) -> bool {
+        self.allowed_networks.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// The address a request was made from. Walks `X-Forwarded-For` from the right for as long
+/// as the hops are trusted proxies: anything left of the first untrusted hop is client-controlled.
+/// `None` if a hop that matters can't be parsed.
+pub fn client_ip(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let Some(forwarded_for) = forwarded_for.filter(|_| is_trusted(&peer)) else {
+        return Some(peer);
+    };
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        client = hop.trim().parse().ok()?;
+        if !is_trusted(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// The address `request` was made from, taking `X-Forwarded-For` into account behind the
+/// `TrustedProxies` of the app.
+pub fn request_client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    let forwarded_for = request
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok());
+    let trusted_proxies = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|proxies| proxies.0.as_slice())
+        .unwrap_or_default();
+    request
+        .peer_addr()
+        .and_then(|peer| client_ip(peer.ip(), forwarded_for, trusted_proxies))
+}
+
+/// Answers requests to routes that take a permission from outside the allowlist with a 403,
+/// and logs them. Public routes, and paths nothing is served at, go through untouched.
+pub async fn restrict_admin_access(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let allowlist = req.app_data::<web::Data<Option<AdminAllowlist>>>().cloned();
+    let is_admin = route_pattern(&req)
+        .and_then(|pattern| access(req.method(), &pattern))
+        .is_some_and(|access| access.permission().is_some());
+    if let Some(allowlist) = &allowlist
+        && let Some(allowlist) = allowlist.get_ref()
+        && is_admin
+    {
+        let client = request_client_ip(req.request());
+        if !client.is_some_and(|ip| allowlist.allows(ip)) {
+            let path = req.match_info().as_str();
+            tracing::warn!(
+                client_ip = client.map(tracing::field::display),
+                method = %req.method(),
+                path,
+                "Blocked an admin request from outside the allowlist",
+            );
+            // The request is blocked regardless.
+            if let Some(pool) = req.app_data::<web::Data<PgPool>>()
+                && let Err(error) = record_denial(pool, &req, path, client).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    "Failed to log a blocked admin request"
+                );
+            }
+            let response = HttpResponse::Forbidden().finish();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[tracing::instrument(name = "Log a blocked admin request", skip(pool, req))]
+async fn record_denial(
+    pool: &PgPool,
+    req: &ServiceRequest,
+    path: &str,
+    client: Option<IpAddr>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_access_denials (route, source_ip, denied_at)
+        VALUES ($1, $2, now())
+        "#,
+        format!("{} {path}", req.method()),
+        client.map(|ip| ip.to_string())
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert a blocked admin request.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client_ip;
+    use ipnet::IpNet;
+
+    fn proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn forwarding_headers_are_ignored_unless_the_peer_is_a_trusted_proxy() {
+        let client = client_ip("203.0.113.9".parse().unwrap(), Some("10.8.0.2"), &proxies());
+        assert_eq!(client, Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn the_client_is_the_first_untrusted_hop_from_the_right() {
+        let client = client_ip(
+            "10.0.0.1".parse().unwrap(),
+            Some("192.168.1.1, 198.51.100.7, 10.0.0.2"),
+            &proxies(),
+        );
+        assert_eq!(client, Some("198.51.100.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn the_peer_is_the_client_without_forwarding_headers() {
+        let client = client_ip("10.0.0.1".parse().unwrap(), None, &proxies());
+        assert_eq!(client, Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn unparseable_hops_leave_the_client_unknown() {
+        let client = client_ip("10.0.0.1".parse().unwrap(), Some("unknown"), &proxies());
+        assert_eq!(client, None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Operational emails to users: a login from a browser they haven't used before, a password
+//! change, how a publish went, and sends of an issue failing past
+//! `admin_notifications.delivery_failure_threshold`.
+//!
+//! Each user picks the ones they get, in `users.notifications`, and only users with an
+//! email address get any. They are rendered from the `email/system/` templates.
+use crate::admin_access::request_client_ip;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::startup::AdminNotifications;
+use crate::templates::EmailTemplates;
+use actix_web::HttpRequest;
+use actix_web::http::header::USER_AGENT;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// What users can turn on and off, by the name of their template.
+pub const NOTIFICATIONS: &[&str] = &[
+    "new_login",
+    "password_changed",
+    "publish_completed",
+    "delivery_failures",
+];
+
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+pub enum AdminNotification<'a> {
+    NewLogin {
+        user_agent: &'a str,
+        client_ip: Option<IpAddr>,
+    },
+    PasswordChanged {
+        client_ip: Option<IpAddr>,
+    },
+    PublishCompleted {
+        title: &'a str,
+        delivered: i64,
+        queued: i64,
+        failed: i64,
+        skipped_over_frequency_cap: i64,
+    },
+    // Goes to every admin who asked for it, not only to the author of the issue.
+    DeliveryFailures {
+        title: &'a str,
+        failed: i64,
+        attempted: i64,
+        threshold_percent: f64,
+    },
+}
+
+impl AdminNotification<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            AdminNotification::NewLogin { .. } => "new_login",
+            AdminNotification::PasswordChanged { .. } => "password_changed",
+            AdminNotification::PublishCompleted { .. } => "publish_completed",
+            AdminNotification::DeliveryFailures { .. } => "delivery_failures",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct NotificationEmail<'a> {
+    username: &'a str,
+    #[serde(flatten)]
+    notification: &'a AdminNotification<'a>,
+}
+
+/// Emails `notification` to `user_id`, and to the admins for delivery failures, if they
+/// have it turned on. Does nothing when notifications aren't configured. Failures are
+/// logged rather than returned: a notification never fails what it is about.
+#[tracing::instrument(
+    name = "Notify users",
+    skip_all,
+    fields(%user_id, notification = notification.name())
+)]
+pub async fn notify(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    settings: &AdminNotifications,
+    user_id: Uuid,
+    notification: AdminNotification<'_>,
+) {
+    if settings.0.is_none() {
+        return;
+    }
+    if let Err(error) = send(pool, email_client, templates, user_id, &notification).await {
+        tracing::warn!(error.cause_chain = ?error, "Failed to notify users");
+    }
+}
+
+async fn send(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    user_id: Uuid,
+    notification: &AdminNotification<'_>,
+) -> Result<(), anyhow::Error> {
+    let to_admins = matches!(notification, AdminNotification::DeliveryFailures { .. });
+    let recipients = sqlx::query!(
+        r#"
+        SELECT username, email AS "email!" FROM users
+        WHERE email IS NOT NULL AND $2 = ANY(notifications)
+          AND (user_id = $1 OR ($3 AND role = 'admin'))
+        "#,
+        user_id,
+        notification.name(),
+        to_admins
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the users to notify.")?;
+    for recipient in recipients {
+        let email = SubscriberEmail::parse(recipient.email)
+            .map_err(anyhow::Error::msg)
+            .context("The stored email of the user is invalid.")?;
+        let (subject, body) = templates.system_notification(
+            notification.name(),
+            &NotificationEmail {
+                username: &recipient.username,
+                notification,
+            },
+        )?;
+        email_client
+            .send_email(
+                &email,
+                &subject,
+                &body.html,
+                &body.text,
+                MessageCategory::Transactional,
+            )
+            .await
+            .context("Failed to send a notification to a user.")?;
+    }
+    Ok(())
+}
+
+/// Tells `user_id` they just logged in, if it was from a browser they hadn't used before.
+pub async fn notify_of_login(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    settings: &AdminNotifications,
+    user_id: Uuid,
+    request: &HttpRequest,
+) {
+    if settings.0.is_none() {
+        return;
+    }
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("an unknown browser");
+    match is_new_login_device(pool, user_id, user_agent).await {
+        Ok(true) => {
+            let notification = AdminNotification::NewLogin {
+                user_agent,
+                client_ip: request_client_ip(request),
+            };
+            notify(
+                pool,
+                email_client,
+                templates,
+                settings,
+                user_id,
+                notification,
+            )
+            .await;
+        }
+        Ok(false) => {}
+        Err(error) => {
+            tracing::warn!(error.cause_chain = ?error, "Failed to check the device of a login");
+        }
+    }
+}
+
+/// Remembers the browser `user_id` logged in with, by its `User-Agent`. `true` if it is one
+/// they hadn't used before; the first browser a user logs in with isn't counted as new.
+#[tracing::instrument(name = "Record a login device", skip(pool, user_agent))]
+async fn is_new_login_device(
+    pool: &PgPool,
+    user_id: Uuid,
+    user_agent: &str,
+) -> Result<bool, anyhow::Error> {
+    let device_hash = format!("{:x}", Sha256::digest(user_agent.as_bytes()));
+    sqlx::query_scalar!(
+        r#"
+        WITH known AS (
+            SELECT COUNT(*) AS devices FROM user_login_devices WHERE user_id = $1
+        ), inserted AS (
+            INSERT INTO user_login_devices (user_id, device_hash, first_seen_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+        )
+        SELECT EXISTS (SELECT 1 FROM inserted) AND (SELECT devices FROM known) > 0 AS "new!"
+        "#,
+        user_id,
+        device_hash
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to record the device of a login.")
+}
@@ -0,0 +1,213 @@
+//! Errors as API clients get them: RFC 7807 `application/problem+json` documents.
+//!
+//! Route-specific errors keep their own enums, for handlers to match on and for the logs,
+//! and build an `ApiError` to answer with. Every document carries the `trace_id` of its
+//! request: the request id `TracingLogger` records on the request's span, so that a
+//! client reporting a problem points straight at its logs.
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, UrlencodedError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use serde_json::{Map, Value};
+use tracing_actix_web::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// Makes the request id available to the `ApiError`s built while handling the request.
+/// Must run inside `TracingLogger`, which assigns the request id.
+pub async fn scope_trace_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let trace_id = req.extensions().get::<RequestId>().map(|id| id.to_string());
+    match trace_id {
+        Some(trace_id) => TRACE_ID.scope(trace_id, next.call(req)).await,
+        None => next.call(req).await,
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    // What kind of problem this is, e.g. `validation-error`; the `type` is built from it.
+    problem: &'static str,
+    detail: String,
+    // Members specific to the problem, e.g. the form field at fault.
+    extensions: Map<String, Value>,
+    // Set on a 401, to ask for `Basic` credentials for this realm.
+    realm: Option<&'static str>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, problem: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            problem,
+            detail: detail.into(),
+            extensions: Map::new(),
+            realm: None,
+        }
+    }
+
+    pub fn validation(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "validation-error", detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not-found", detail)
+    }
+
+    pub fn unauthorized(realm: &'static str) -> Self {
+        Self {
+            realm: Some(realm),
+            ..Self::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "Authentication failed.",
+            )
+        }
+    }
+
+    /// The user is authenticated, but their role doesn't allow this.
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", detail)
+    }
+
+    /// What went wrong stays in the logs: the client only gets the `trace_id` to find it by.
+    pub fn unexpected() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Something went wrong on our side.",
+        )
+    }
+
+    /// The body is over the `limit`, in bytes, of its route.
+    pub fn payload_too_large(limit: usize) -> Self {
+        Self::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload-too-large",
+            format!("The body of the request can be at most {limit} bytes."),
+        )
+        .with_extension("limit", limit)
+    }
+
+    pub fn with_extension(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// For `JsonConfig::error_handler`: bodies over the limit get a 413 problem, other
+/// errors keep the answer of actix.
+pub fn json_payload_error(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    match error {
+        JsonPayloadError::OverflowKnownLength { limit, .. }
+        | JsonPayloadError::Overflow { limit } => ApiError::payload_too_large(limit).into(),
+        error => error.into(),
+    }
+}
+
+/// For `FormConfig::error_handler`, like `json_payload_error`.
+pub fn form_payload_error(error: UrlencodedError, _: &HttpRequest) -> actix_web::Error {
+    match error {
+        UrlencodedError::Overflow { limit, .. } => ApiError::payload_too_large(limit).into(),
+        error => error.into(),
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut document = self.extensions.clone();
+        document.insert("type".into(), format!("/problems/{}", self.problem).into());
+        document.insert(
+            "title".into(),
+            self.status.canonical_reason().unwrap_or_default().into(),
+        );
+        document.insert("status".into(), self.status.as_u16().into());
+        document.insert("detail".into(), self.detail.clone().into());
+        if let Ok(trace_id) = TRACE_ID.try_with(String::clone) {
+            document.insert("trace_id".into(), trace_id.into());
+        }
+
+        let mut response = HttpResponse::build(self.status);
+        if let Some(realm) = self.realm
+            && let Ok(challenge) = HeaderValue::from_str(&format!(r#"Basic realm="{realm}""#))
+        {
+            response.insert_header((header::WWW_AUTHENTICATE, challenge));
+        }
+        response
+            .content_type(PROBLEM_JSON)
+            .body(Value::Object(document).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiError, TRACE_ID};
+    use actix_web::ResponseError;
+    use actix_web::body::to_bytes;
+
+    async fn document(error: ApiError) -> serde_json::Value {
+        let response = error.error_response();
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/problem+json"
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn problems_carry_the_trace_id_of_their_request() {
+        let error = ApiError::validation("The name is empty.").with_extension("field", "name");
+
+        let document = TRACE_ID.scope("a-request-id".into(), document(error)).await;
+
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "type": "/problems/validation-error",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "The name is empty.",
+                "field": "name",
+                "trace_id": "a-request-id",
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn unauthorized_problems_ask_for_credentials() {
+        let response = ApiError::unauthorized("publish").error_response();
+
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers().get("WWW-Authenticate").unwrap(),
+            r#"Basic realm="publish""#
+        );
+        assert!(
+            document(ApiError::unexpected())
+                .await
+                .get("trace_id")
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Keys for scripts and CI pipelines, which have no session and shouldn't hold a password:
+//! they call the API with `Authorization: Bearer <key>`. A key acts as the admin who created
+//! it, only for what its scopes allow, and until it expires or is revoked. Every request a
+//! key authenticates is logged in `api_key_usage`.
+use crate::audit_log::{Actor, Source};
+use crate::authentication::{AuthError, AuthenticatedUser, Role};
+use actix_web::http::header::HeaderMap;
+use actix_web::{HttpMessage, HttpRequest};
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Lets secret scanners, and people, recognise a leaked key.
+const API_KEY_PREFIX: &str = "z2p_";
+const API_KEY_SECRET_LENGTH: usize = 40;
+
+/// What a key may be used for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Publishes issues with `POST /newsletters`.
+    Publish,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Publish => "publish",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Result<Self, String> {
+        match scope {
+            "publish" => Ok(ApiScope::Publish),
+            other => Err(format!(
+                "`{other}` is not a scope: the only one is `publish`."
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A request made with a key whose scopes fall short of what it needs.
+#[derive(thiserror::Error, Debug)]
+#[error("This API key doesn't have the `{0}` scope.")]
+pub struct MissingScope(pub ApiScope);
+
+/// A live key a request was made with.
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub scopes: Vec<ApiScope>,
+    /// The admin who created it, with their current role.
+    pub user: AuthenticatedUser,
+}
+
+impl ApiKey {
+    pub fn require(&self, scope: ApiScope) -> Result<(), MissingScope> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(MissingScope(scope))
+        }
+    }
+}
+
+/// A new key, as shown once to the admin who creates it.
+pub fn generate_api_key() -> String {
+    let mut rng = thread_rng();
+    let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(API_KEY_SECRET_LENGTH)
+        .collect();
+    format!("{API_KEY_PREFIX}{secret}")
+}
+
+/// Hex-encoded SHA-256, as stored in `api_keys.key_hash`. Keys are random enough that a
+/// slow hash, as for passwords, would buy nothing.
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// The key of an `Authorization: Bearer` header, `None` for any other scheme.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// The key a request was made with, `None` if it has no `Authorization: Bearer` header.
+/// Unknown, expired and revoked keys fail as invalid credentials. Records the key and its
+/// user on the current span, logs the request in `api_key_usage`, and puts the user in the
+/// request's extensions.
+pub async fn authenticate_api_key(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Option<ApiKey>, AuthError> {
+    let Some(key) = bearer_token(request.headers()) else {
+        return Ok(None);
+    };
+    let row = sqlx::query!(
+        r#"
+        SELECT k.id, k.scopes, k.created_by, u.role
+        FROM api_keys k JOIN users u ON u.user_id = k.created_by
+        WHERE k.key_hash = $1
+            AND k.revoked_at IS NULL
+            AND (k.expires_at IS NULL OR k.expires_at > now())
+        "#,
+        hash_api_key(key)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve an API key.")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, expired or revoked API key."))
+    })?;
+    let current_span = tracing::Span::current();
+    current_span.record("api_key_id", tracing::field::display(&row.id));
+    current_span.record("user_id", tracing::field::display(&row.created_by));
+    let scopes = row
+        .scopes
+        .iter()
+        .map(|scope| ApiScope::parse(scope))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(anyhow::Error::msg)?;
+    let role = Role::parse(&row.role).map_err(anyhow::Error::msg)?;
+    let api_key = ApiKey {
+        id: row.id,
+        scopes,
+        user: AuthenticatedUser {
+            user_id: row.created_by,
+            role,
+        },
+    };
+    record_usage(pool, request, &api_key).await?;
+    request.extensions_mut().insert(api_key.user);
+    Ok(Some(api_key))
+}
+
+#[tracing::instrument(name = "Record the use of an API key", skip_all)]
+async fn record_usage(
+    pool: &PgPool,
+    request: &HttpRequest,
+    api_key: &ApiKey,
+) -> Result<(), anyhow::Error> {
+    let source = Source::request(Actor::User(api_key.user.user_id), request);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO api_key_usage (api_key_id, route, source_ip, used_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        api_key.id,
+        format!("{} {}", request.method(), request.path()),
+        source.ip.map(|ip| ip.to_string())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to log the use of an API key.")?;
+    sqlx::query!(
+        r#"UPDATE api_keys SET last_used_at = now() WHERE id = $1"#,
+        api_key.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update when an API key was last used.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to log the use of an API key.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{API_KEY_PREFIX, bearer_token, generate_api_key};
+    use actix_web::http::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+
+    fn headers(authorization: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        headers
+    }
+
+    #[test]
+    fn keys_are_prefixed_and_distinct() {
+        let (first, second) = (generate_api_key(), generate_api_key());
+        assert!(first.starts_with(API_KEY_PREFIX));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn only_bearer_credentials_are_api_keys() {
+        assert_eq!(bearer_token(&headers("Bearer z2p_abc")), Some("z2p_abc"));
+        assert_eq!(bearer_token(&headers("Basic dXNlcjpwYXNz")), None);
+        assert_eq!(bearer_token(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! The history of each subscriber: every change of their status, who made it and, over
+//! HTTP, from which address. Deleting a subscriber only marks them as deleted, so their
+//! history is still there to tell why they stopped receiving emails.
+use crate::admin_access::request_client_ip;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriberEvent {
+    // Signed up, pending confirmation or onto the waitlist.
+    Subscribed,
+    // Added by an import.
+    Imported,
+    // Let in from the waitlist.
+    Admitted,
+    Confirmed,
+    Unsubscribed,
+    // Their address bounced.
+    Bounced,
+    Deleted,
+    // Moved to the address they confirmed.
+    EmailChanged,
+}
+
+impl SubscriberEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SubscriberEvent::Subscribed => "subscribed",
+            SubscriberEvent::Imported => "imported",
+            SubscriberEvent::Admitted => "admitted",
+            SubscriberEvent::Confirmed => "confirmed",
+            SubscriberEvent::Unsubscribed => "unsubscribed",
+            SubscriberEvent::Bounced => "bounced",
+            SubscriberEvent::Deleted => "deleted",
+            SubscriberEvent::EmailChanged => "email_changed",
+        }
+    }
+}
+
+/// Who made a change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Actor {
+    Subscriber,
+    User(Uuid),
+    EmailProvider,
+    // A request to an endpoint without authentication: the source address is all there is
+    // to go on. Admissions from the waitlist were recorded this way before they took a user.
+    Anonymous,
+    // A scheduled job, by name.
+    Job(&'static str),
+}
+
+impl Actor {
+    fn name(&self) -> &'static str {
+        match self {
+            Actor::Subscriber => "subscriber",
+            Actor::User(_) => "user",
+            Actor::EmailProvider => "email_provider",
+            Actor::Anonymous => "anonymous",
+            Actor::Job(name) => name,
+        }
+    }
+
+    fn user_id(&self) -> Option<Uuid> {
+        match self {
+            Actor::User(user_id) => Some(*user_id),
+            _ => None,
+        }
+    }
+}
+
+/// Who made a change and, for changes made over HTTP, the client's address.
+#[derive(Clone, Copy, Debug)]
+pub struct Source {
+    pub actor: Actor,
+    pub ip: Option<IpAddr>,
+}
+
+impl Source {
+    /// A change `actor` made with `request`. Behind `application.trusted_proxies`, the
+    /// client's address is taken from `X-Forwarded-For`.
+    pub fn request(actor: Actor, request: &HttpRequest) -> Self {
+        Self {
+            actor,
+            ip: request_client_ip(request),
+        }
+    }
+
+    pub fn job(name: &'static str) -> Self {
+        Self {
+            actor: Actor::Job(name),
+            ip: None,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Record a subscriber event", skip(executor))]
+pub async fn record(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    event: SubscriberEvent,
+    source: &Source,
+) -> Result<(), sqlx::Error> {
+    record_all(executor, &[subscriber_id], event, source).await
+}
+
+/// Records the same event for each of `subscriber_ids`.
+pub async fn record_all(
+    executor: impl PgExecutor<'_>,
+    subscriber_ids: &[Uuid],
+    event: SubscriberEvent,
+    source: &Source,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (subscriber_id, event, actor, user_id, source_ip, occurred_at)
+        SELECT subscriber_id, $2, $3, $4, $5, now() FROM UNNEST($1::uuid[]) AS subscriber_id
+        "#,
+        subscriber_ids,
+        event.name(),
+        source.actor.name(),
+        source.actor.user_id(),
+        source.ip.map(|ip| ip.to_string())
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditEntry {
+    pub event: String,
+    pub actor: String,
+    // For changes made by a user, unless they were deleted since.
+    pub username: Option<String>,
+    pub source_ip: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Oldest first.
+pub async fn subscriber_history(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT a.event, a.actor, u.username AS "username?", a.source_ip, a.occurred_at
+        FROM audit_log a LEFT JOIN users u ON u.user_id = a.user_id
+        WHERE a.subscriber_id = $1
+        ORDER BY a.occurred_at, a.id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
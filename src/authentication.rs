@@ -0,0 +1,382 @@
+//! Publishers log in with a username and password, hashed with Argon2id.
+use crate::api_error::ApiError;
+use crate::api_keys::{ApiScope, authenticate_api_key};
+use crate::routes::authenticate_publisher;
+use crate::session::TypedSession;
+use crate::telemetry::spawn_blocking_with_tracing;
+use actix_web::dev::ServiceRequest;
+use actix_web::{FromRequest, web};
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+// Argon2 takes passwords of any length, but hashing a huge one still costs us.
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// What a user may do, each role allowing everything the ones below it do.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reads subscribers and past issues.
+    Viewer,
+    /// Also publishes issues and imports subscribers.
+    Editor,
+    /// Also deletes subscribers and manages users.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Result<Self, String> {
+        match role {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!(
+                "`{other}` is not a role: it is either `admin`, `editor` or `viewer`."
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A request made by a user whose role falls short of what it needs.
+#[derive(thiserror::Error, Debug)]
+#[error("This requires the `{required}` role, and you are `{role}`.")]
+pub struct Forbidden {
+    pub required: Role,
+    pub role: Role,
+}
+
+/// The user a request was made by. Put in the request's extensions by
+/// `authorization::authorize_requests` on routes that take a permission.
+#[derive(Copy, Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+}
+
+impl AuthenticatedUser {
+    pub fn require(&self, required: Role) -> Result<(), Forbidden> {
+        if self.role >= required {
+            Ok(())
+        } else {
+            Err(Forbidden {
+                required,
+                role: self.role,
+            })
+        }
+    }
+}
+
+/// The user logged in on the request's session, unless they have since been deleted.
+pub(crate) async fn session_user(
+    req: &mut ServiceRequest,
+) -> Result<Option<AuthenticatedUser>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let user_id = session
+        .get_user_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let role = match (user_id, req.app_data::<web::Data<PgPool>>()) {
+        (Some(user_id), Some(pool)) => get_role(user_id, pool)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+        _ => None,
+    };
+    Ok(user_id
+        .zip(role)
+        .map(|(user_id, role)| AuthenticatedUser { user_id, role }))
+}
+
+/// The user who created the API key the request carries, if the key has `scope`: 403 if it
+/// doesn't. `admin_user` without a key.
+pub(crate) async fn key_or_admin_user(
+    req: &mut ServiceRequest,
+    scope: ApiScope,
+) -> Result<AuthenticatedUser, actix_web::Error> {
+    let pool = pool(req)?;
+    match authenticate_api_key(req.request(), &pool)
+        .await
+        .map_err(auth_error)?
+    {
+        Some(api_key) => {
+            api_key
+                .require(scope)
+                .map_err(|e| ApiError::forbidden(e.to_string()))?;
+            Ok(api_key.user)
+        }
+        None => admin_user(req).await,
+    }
+}
+
+/// The user of the session, or of the `Basic` credentials without one: 401 without either.
+pub(crate) async fn admin_user(
+    req: &mut ServiceRequest,
+) -> Result<AuthenticatedUser, actix_web::Error> {
+    if let Some(user) = session_user(req).await? {
+        return Ok(user);
+    }
+    let pool = pool(req)?;
+    authenticate_publisher(req.request(), &pool)
+        .await
+        .map_err(auth_error)
+}
+
+fn pool(req: &ServiceRequest) -> Result<web::Data<PgPool>, actix_web::Error> {
+    req.app_data::<web::Data<PgPool>>()
+        .cloned()
+        .context("The database pool is missing.")
+        .map_err(actix_web::error::ErrorInternalServerError)
+}
+
+fn auth_error(e: AuthError) -> actix_web::Error {
+    match e {
+        AuthError::InvalidCredentials(_) => ApiError::unauthorized("publish").into(),
+        AuthError::UnexpectedError(e) => {
+            tracing::error!(error = ?e, "Failed to authenticate an admin request");
+            ApiError::unexpected().into()
+        }
+    }
+}
+
+/// The id of the user the credentials belong to.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    // Unknown usernames are checked against a dummy hash too, so that they take as long
+    // to reject as wrong passwords and can't be told apart by timing.
+    let mut expected_password_hash = SecretString::from(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+    );
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Verify password hash", skip_all)]
+fn verify_password_hash(
+    expected_password_hash: SecretString,
+    password_candidate: SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, SecretString)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, SecretString::from(row.password_hash)));
+    Ok(row)
+}
+
+/// The role of the user, `None` if there is no such user.
+#[tracing::instrument(name = "Get role", skip(pool))]
+pub async fn get_role(user_id: Uuid, pool: &PgPool) -> Result<Option<Role>, anyhow::Error> {
+    let role = sqlx::query_scalar!(r#"SELECT role FROM users WHERE user_id = $1"#, user_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform a query to retrieve the role of a user.")?;
+    role.map(|role| Role::parse(&role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// Adds a publisher, or resets their password if they already exist. Their email, where
+/// password reset links go, and their role are only replaced when given; new users are
+/// admins unless told otherwise.
+#[tracing::instrument(name = "Store a user", skip(password, pool))]
+pub async fn store_user(
+    username: &str,
+    email: Option<&str>,
+    role: Option<Role>,
+    password: SecretString,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, COALESCE($5, 'admin'))
+        ON CONFLICT (username) DO UPDATE
+        SET password_hash = EXCLUDED.password_hash,
+            email = COALESCE(EXCLUDED.email, users.email),
+            role = COALESCE($5, users.role)
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        email,
+        role.as_ref().map(Role::as_str),
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to store the user.")
+}
+
+/// Replaces the password of a user with `password`, hashed.
+#[tracing::instrument(name = "Change password", skip(password, executor))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: SecretString,
+    executor: impl PgExecutor<'_>,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to change the password of a user.")?;
+    Ok(())
+}
+
+/// Why `password` can't be used as a new password, if it can't. Length is all that's
+/// checked, as composition rules do little more than make passwords harder to remember.
+pub fn check_password_strength(password: &SecretString) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at least {MIN_PASSWORD_LENGTH} characters long."
+        ));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at most {MAX_PASSWORD_LENGTH} characters long."
+        ));
+    }
+    Ok(())
+}
+
+/// A PHC string, with the parameters the hash was computed with.
+pub fn compute_password_hash(password: SecretString) -> Result<SecretString, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(|e| anyhow::anyhow!("Failed to hash the password: {e}"))?
+    .to_string();
+    Ok(SecretString::from(password_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hash_verifies_its_own_password_only() {
+        let hash = compute_password_hash(SecretString::from("correct horse")).unwrap();
+
+        assert!(verify_password_hash(hash.clone(), SecretString::from("correct horse")).is_ok());
+        assert!(matches!(
+            verify_password_hash(hash, SecretString::from("battery staple")),
+            Err(AuthError::InvalidCredentials(_))
+        ));
+    }
+
+    #[test]
+    fn passwords_must_be_neither_too_short_nor_too_long() {
+        assert!(check_password_strength(&SecretString::from("a".repeat(11))).is_err());
+        assert!(check_password_strength(&SecretString::from("a".repeat(12))).is_ok());
+        assert!(check_password_strength(&SecretString::from("a".repeat(128))).is_ok());
+        assert!(check_password_strength(&SecretString::from("a".repeat(129))).is_err());
+    }
+
+    #[test]
+    fn roles_allow_what_the_roles_below_them_do() {
+        let editor = AuthenticatedUser {
+            user_id: Uuid::new_v4(),
+            role: Role::Editor,
+        };
+
+        assert!(editor.require(Role::Viewer).is_ok());
+        assert!(editor.require(Role::Editor).is_ok());
+        assert!(editor.require(Role::Admin).is_err());
+        assert_eq!(Role::parse(Role::Admin.as_str()), Ok(Role::Admin));
+        assert!(Role::parse("owner").is_err());
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        let first = compute_password_hash(SecretString::from("correct horse")).unwrap();
+        let second = compute_password_hash(SecretString::from("correct horse")).unwrap();
+
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+}
@@ -0,0 +1,409 @@
+//! Who may call which route.
+//!
+//! `POLICY` gives every route `startup::run` serves, public ones included, the `Access` it
+//! takes, and `authorize_requests` enforces it on every request the app gets: a route the
+//! policy doesn't know is refused rather than served to anyone.
+use crate::api_error::ApiError;
+use crate::api_keys::ApiScope;
+use crate::authentication::{Role, admin_user, key_or_admin_user, session_user};
+use crate::routes::no_matching_route;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::LOCATION;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse};
+
+/// What a route lets a user do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Use the dashboard, and change their own password and notifications.
+    ManageOwnAccount,
+    /// Read subscribers, past issues, stats and settings.
+    Read,
+    /// Publish, preview and test-send issues.
+    Publish,
+    /// Import and tag subscribers, and hand out invites.
+    EditSubscribers,
+    /// Change lists, categories, snippets, sponsors and branding.
+    EditSettings,
+    /// Delete and unsubscribe subscribers.
+    RemoveSubscribers,
+    /// Manage users and API keys.
+    ManageUsers,
+    /// Flip runtime flags and run maintenance checks and smoke tests.
+    Operate,
+}
+
+impl Permission {
+    /// The least role that has the permission.
+    pub fn role(&self) -> Role {
+        match self {
+            Permission::ManageOwnAccount | Permission::Read => Role::Viewer,
+            Permission::Publish | Permission::EditSubscribers | Permission::EditSettings => {
+                Role::Editor
+            }
+            Permission::RemoveSubscribers | Permission::ManageUsers | Permission::Operate => {
+                Role::Admin
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Anyone: subscribers following links, browsers, probes, and callers the handler
+    /// checks itself, like the email provider's signed webhooks.
+    Public,
+    /// A JSON API for a user whose role has the permission, from a logged-in session or
+    /// `Basic` credentials: 401 without either.
+    Requires(Permission),
+    /// `Requires`, or an API key with the scope, acting as the user who created it.
+    RequiresOrKey(Permission, ApiScope),
+    /// A page for a logged-in user whose role has the permission: anyone else is sent to the
+    /// login page.
+    Page(Permission),
+}
+
+impl Access {
+    /// The permission the route takes, `None` if it's public.
+    pub fn permission(&self) -> Option<Permission> {
+        match self {
+            Access::Public => None,
+            Access::Requires(permission)
+            | Access::RequiresOrKey(permission, _)
+            | Access::Page(permission) => Some(*permission),
+        }
+    }
+}
+
+use Access::{Page, Public, Requires, RequiresOrKey};
+use Permission::*;
+
+/// Every route pattern, method and who may call it.
+pub const POLICY: &[(&str, &str, Access)] = &[
+    ("/health_check", "GET", Public),
+    ("/health_check/ready", "GET", Public),
+    ("/metrics", "GET", Public),
+    ("/subscriptions", "POST", Public),
+    ("/subscriptions/confirm", "GET", Public),
+    ("/subscriptions/resend_confirmation", "POST", Public),
+    ("/subscriptions/unsubscribe", "GET", Public),
+    ("/subscriptions/stay", "GET", Public),
+    ("/subscriptions/fields", "GET", Public),
+    ("/subscriptions/challenge", "GET", Public),
+    ("/subscriptions/categories", "GET", Public),
+    ("/subscriptions/categories", "PUT", Public),
+    ("/subscriptions/categories", "POST", Public),
+    ("/subscriptions/data_request", "POST", Public),
+    ("/subscriptions/export", "GET", Public),
+    ("/subscriptions/erase", "GET", Public),
+    ("/subscriptions/erase", "POST", Public),
+    ("/subscriptions/change_email", "POST", Public),
+    ("/subscriptions/change_email/confirm", "GET", Public),
+    ("/newsletters", "GET", Requires(Read)),
+    (
+        "/newsletters",
+        "POST",
+        RequiresOrKey(Publish, ApiScope::Publish),
+    ),
+    ("/newsletters/preview", "POST", Requires(Publish)),
+    ("/newsletters/test_send", "POST", Requires(Publish)),
+    ("/newsletters/{newsletter_issue_id}", "GET", Requires(Read)),
+    (
+        "/newsletters/{newsletter_slug}/subscriptions",
+        "POST",
+        Public,
+    ),
+    (
+        "/newsletters/{newsletter_slug}/subscriptions/resend_confirmation",
+        "POST",
+        Public,
+    ),
+    ("/feed.xml", "GET", Public),
+    ("/archive", "GET", Public),
+    ("/archive/{newsletter_issue_id}", "GET", Public),
+    ("/newsletters/{newsletter_slug}/feed.xml", "GET", Public),
+    ("/newsletters/{newsletter_slug}/archive", "GET", Public),
+    ("/login", "GET", Public),
+    ("/login", "POST", Public),
+    ("/password_reset", "GET", Public),
+    ("/password_reset", "POST", Public),
+    ("/password_reset/confirm", "GET", Public),
+    ("/password_reset/confirm", "POST", Public),
+    ("/admin/dashboard", "GET", Page(ManageOwnAccount)),
+    ("/admin/dashboard/subscribers", "GET", Page(Read)),
+    ("/admin/dashboard/stats", "GET", Page(Read)),
+    ("/admin/console.js", "GET", Page(ManageOwnAccount)),
+    ("/admin/newsletters", "GET", Page(Publish)),
+    ("/admin/newsletters", "POST", Page(Publish)),
+    ("/admin/newsletters/preview", "POST", Page(Publish)),
+    ("/admin/logout", "POST", Page(ManageOwnAccount)),
+    ("/admin/password", "GET", Page(ManageOwnAccount)),
+    ("/admin/password", "POST", Page(ManageOwnAccount)),
+    (
+        "/admin/newsletters/render_preview",
+        "POST",
+        Requires(Publish),
+    ),
+    ("/push/public_key", "GET", Public),
+    ("/push/subscribe", "POST", Public),
+    ("/sms/register", "POST", Public),
+    ("/sms/verify", "POST", Public),
+    ("/sms/opt_out", "POST", Public),
+    ("/admin/snippets", "GET", Requires(Read)),
+    ("/admin/snippets", "POST", Requires(EditSettings)),
+    ("/admin/snippets/{name}", "GET", Requires(Read)),
+    ("/admin/snippets/{name}", "PUT", Requires(EditSettings)),
+    ("/admin/snippets/{name}", "DELETE", Requires(EditSettings)),
+    ("/admin/sponsors", "GET", Requires(Read)),
+    ("/admin/sponsors", "POST", Requires(EditSettings)),
+    (
+        "/admin/sponsors/{slot_id}",
+        "DELETE",
+        Requires(EditSettings),
+    ),
+    ("/admin/sponsors/{slot_id}/report", "GET", Requires(Read)),
+    ("/admin/branding", "GET", Requires(Read)),
+    ("/admin/branding", "PUT", Requires(EditSettings)),
+    ("/admin/categories", "GET", Requires(Read)),
+    ("/admin/categories", "POST", Requires(EditSettings)),
+    ("/admin/categories/{name}", "DELETE", Requires(EditSettings)),
+    ("/admin/lists", "GET", Requires(Read)),
+    ("/admin/lists", "POST", Requires(EditSettings)),
+    ("/admin/lists/{slug}", "PUT", Requires(EditSettings)),
+    ("/admin/lists/{slug}", "DELETE", Requires(EditSettings)),
+    ("/admin/api_keys", "GET", Requires(ManageUsers)),
+    ("/admin/api_keys", "POST", Requires(ManageUsers)),
+    (
+        "/admin/api_keys/{api_key_id}",
+        "DELETE",
+        Requires(ManageUsers),
+    ),
+    ("/admin/users", "GET", Requires(ManageUsers)),
+    ("/admin/users", "POST", Requires(ManageUsers)),
+    ("/admin/users/{username}", "DELETE", Requires(ManageUsers)),
+    ("/admin/users/{username}/role", "PUT", Requires(ManageUsers)),
+    ("/admin/subscribers", "GET", Requires(Read)),
+    (
+        "/admin/subscribers/import",
+        "POST",
+        Requires(EditSubscribers),
+    ),
+    (
+        "/admin/subscribers/unsubscribe",
+        "POST",
+        Requires(RemoveSubscribers),
+    ),
+    ("/admin/subscribers/{subscriber_id}", "GET", Requires(Read)),
+    (
+        "/admin/subscribers/{subscriber_id}",
+        "DELETE",
+        Requires(RemoveSubscribers),
+    ),
+    (
+        "/admin/subscribers/{subscriber_id}/history",
+        "GET",
+        Requires(Read),
+    ),
+    (
+        "/admin/subscribers/{subscriber_id}/timeline",
+        "GET",
+        Requires(Read),
+    ),
+    ("/admin/calendar", "GET", Requires(Read)),
+    ("/admin/tags", "GET", Requires(Read)),
+    (
+        "/admin/tags/engaged_readers",
+        "POST",
+        Requires(EditSubscribers),
+    ),
+    ("/admin/repermission_campaigns", "GET", Requires(Read)),
+    (
+        "/admin/repermission_campaigns",
+        "POST",
+        Requires(RemoveSubscribers),
+    ),
+    (
+        "/admin/repermission_campaigns/{campaign_id}",
+        "GET",
+        Requires(Read),
+    ),
+    (
+        "/admin/subscribers/{subscriber_id}/tags",
+        "GET",
+        Requires(Read),
+    ),
+    (
+        "/admin/subscribers/{subscriber_id}/tags",
+        "POST",
+        Requires(EditSubscribers),
+    ),
+    (
+        "/admin/subscribers/{subscriber_id}/tags/{tag}",
+        "DELETE",
+        Requires(EditSubscribers),
+    ),
+    ("/admin/notifications", "GET", Requires(ManageOwnAccount)),
+    ("/admin/notifications", "PUT", Requires(ManageOwnAccount)),
+    ("/admin/flags", "GET", Requires(Read)),
+    ("/admin/flags/{name}", "PUT", Requires(Operate)),
+    ("/admin/invites", "GET", Requires(Read)),
+    ("/admin/invites", "POST", Requires(EditSubscribers)),
+    ("/admin/waitlist/admit", "POST", Requires(EditSubscribers)),
+    ("/admin/stats", "GET", Requires(Read)),
+    ("/admin/stats/confirmations", "GET", Requires(Read)),
+    ("/admin/stats/issues/compare", "GET", Requires(Read)),
+    ("/admin/stats/panics", "GET", Requires(Read)),
+    ("/admin/scheduler/jobs", "GET", Requires(Read)),
+    (
+        "/admin/maintenance/consistency_check",
+        "POST",
+        Requires(Operate),
+    ),
+    ("/admin/archive/export", "GET", Requires(Read)),
+    ("/admin/archive/mailbox", "GET", Requires(Read)),
+    ("/admin/smoke_test", "POST", Requires(Operate)),
+    ("/email/webhooks/bounce", "POST", Public),
+    ("/email/webhooks/delivery", "POST", Public),
+    ("/sponsors/{slot_id}/click", "GET", Public),
+    ("/sponsors/{slot_id}/open", "GET", Public),
+    (
+        "/t/{newsletter_issue_id}/{subscriber_id}/{link_id}",
+        "GET",
+        Public,
+    ),
+    ("/o/{newsletter_issue_id}/{subscriber_id}", "GET", Public),
+];
+
+/// Who may call `method` on the route `pattern`, `None` if the policy doesn't say.
+pub fn access(method: &Method, pattern: &str) -> Option<Access> {
+    // Test-only: registered by `startup::run` with the `fault-injection` feature.
+    #[cfg(feature = "fault-injection")]
+    if pattern == "/_faults" {
+        return Some(Access::Public);
+    }
+    POLICY
+        .iter()
+        .find(|(route, route_method, _)| *route == pattern && *route_method == method.as_str())
+        .map(|(_, _, access)| *access)
+}
+
+/// The pattern of the route `req` is routed to, if any. Matched against the path routing
+/// sees, with percent-encoded characters decoded, so that `/%61dmin` can't slip past: the
+/// request's own `match_pattern` only knows the raw path until it has been routed.
+pub fn route_pattern(req: &ServiceRequest) -> Option<String> {
+    req.request()
+        .resource_map()
+        .match_pattern(req.match_info().as_str())
+}
+
+/// Checks every request against `POLICY` before it gets to its route: the user it's made by
+/// must have the permission the route takes, and is put in the request's extensions. Paths
+/// nothing is served at go through to their 404, and methods the policy doesn't list for a
+/// route get a 405. A route the policy doesn't know at all is refused with a 403.
+pub async fn authorize_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    match authorize(&mut req).await {
+        Ok(None) => next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body),
+        Ok(Some(response)) => Ok(req.into_response(response).map_into_right_body()),
+        Err(e) => Ok(req.error_response(e).map_into_right_body()),
+    }
+}
+
+/// `None` if the request may go through to its route, or the response it gets instead.
+async fn authorize(req: &mut ServiceRequest) -> Result<Option<HttpResponse>, actix_web::Error> {
+    let Some(pattern) = route_pattern(req) else {
+        return Ok(None);
+    };
+    let Some(access) = access(req.method(), &pattern) else {
+        if POLICY.iter().any(|(route, ..)| *route == pattern) {
+            return Ok(Some(no_matching_route(req.request().clone()).await));
+        }
+        tracing::error!(
+            method = %req.method(),
+            pattern,
+            "No authorization decision for a served route"
+        );
+        return Err(ApiError::forbidden("This route doesn't say who may call it.").into());
+    };
+    let Some(permission) = access.permission() else {
+        return Ok(None);
+    };
+    let user = match access {
+        Page(_) => match session_user(req).await? {
+            Some(user) => user,
+            None => {
+                let response = HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login"))
+                    .finish();
+                return Ok(Some(response));
+            }
+        },
+        RequiresOrKey(_, scope) => key_or_admin_user(req, scope).await?,
+        Public | Requires(_) => admin_user(req).await?,
+    };
+    user.require(permission.role())
+        .map_err(|e| ApiError::forbidden(e.to_string()))?;
+    req.extensions_mut().insert(user);
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, POLICY, Permission, authorize_requests};
+    use crate::authentication::Role;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    #[test]
+    fn every_route_and_method_has_one_decision() {
+        for (i, (route, method, _)) in POLICY.iter().enumerate() {
+            assert!(
+                !POLICY[..i]
+                    .iter()
+                    .any(|(r, m, _)| r == route && m == method),
+                "{method} {route} is listed twice"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn routes_missing_from_the_policy_are_refused() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(authorize_requests))
+                .route("/health_check", web::get().to(HttpResponse::Ok))
+                .route("/admin/unlisted", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |uri| call_service(&app, TestRequest::get().uri(uri).to_request());
+
+        assert_eq!(get("/health_check").await.status().as_u16(), 200);
+        assert_eq!(get("/admin/unlisted").await.status().as_u16(), 403);
+        assert_eq!(get("/%61dmin/unlisted").await.status().as_u16(), 403);
+    }
+
+    #[test]
+    fn admin_routes_are_never_public() {
+        for (route, method, access) in POLICY {
+            if route.starts_with("/admin") {
+                assert_ne!(*access, Access::Public, "{method} {route}");
+            }
+        }
+    }
+
+    #[test]
+    fn only_admins_remove_subscribers_and_manage_users() {
+        assert_eq!(Permission::Read.role(), Role::Viewer);
+        assert_eq!(Permission::Publish.role(), Role::Editor);
+        assert_eq!(Permission::RemoveSubscribers.role(), Role::Admin);
+        assert_eq!(Permission::ManageUsers.role(), Role::Admin);
+    }
+}
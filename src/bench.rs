@@ -0,0 +1,192 @@
+//! `cargo run --release --features bench -- bench-send --count 100000 --concurrency 64`
+//!
+//! Sends a synthetic issue to `count` recipients through `IssueDelivery`, the way publishing
+//! and the `issue_delivery` job do, against a local mock provider, and reports throughput and
+//! latency percentiles, so regressions in the send path show up as numbers. The issue is
+//! rendered like a published one: snippets resolved, sanitized, a sponsor block injected and
+//! links tracked. Then each send is personalised, put in the newsletter template and sent with
+//! `EmailClient`.
+//!
+//! Nothing is recorded: deliveries go to `NoDeliveryRecords` rather than Postgres, so that no
+//! database is needed and only the send path is measured.
+use crate::content_checks::sanitize;
+use crate::crypto::KeyRing;
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::routes::{ConfirmedSubscriber, IssueDelivery, NoDeliveryRecords, RenderedIssue};
+use crate::signed_tokens::TokenSigner;
+use crate::snippets::resolve_snippets;
+use crate::sponsors::{ActiveSponsorSlot, inject_sponsor_blocks};
+use crate::templates::EmailTemplates;
+use crate::tracking::IssueTracking;
+use actix_web::{App, HttpResponse, HttpServer, web};
+use futures_util::{StreamExt, stream};
+use secrecy::SecretString;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const BASE_URL: &str = "http://127.0.0.1";
+
+struct BenchOptions {
+    count: usize,
+    concurrency: usize,
+}
+
+impl BenchOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            count: 10_000,
+            concurrency: 32,
+        };
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} expects a value.", flag))?;
+            let value: usize = value
+                .parse()
+                .map_err(|_| format!("{} is not a valid value for {}.", value, flag))?;
+            match flag.as_str() {
+                "--count" => options.count = value,
+                "--concurrency" => options.concurrency = value.max(1),
+                other => return Err(format!("Unknown option {}.", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Run the `bench-send` command. `args` are the arguments following the command name.
+pub async fn run(args: impl Iterator<Item = String>) -> Result<(), std::io::Error> {
+    let options = BenchOptions::parse(args)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    // A provider stand-in that accepts every email without doing any work,
+    // so that we only measure our side of the pipeline.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let provider_url = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+    let provider = HttpServer::new(|| App::new().route("/email", web::post().to(HttpResponse::Ok)))
+        .listen(listener)?
+        .run();
+    let provider_handle = provider.handle();
+    tokio::spawn(provider);
+
+    let email_client = EmailClient::new(
+        provider_url,
+        SubscriberEmail::parse("bench@example.com".into()).unwrap(),
+        SecretString::from("bench-token"),
+        Duration::from_secs(10),
+    );
+    let templates = EmailTemplates::load("templates").map_err(std::io::Error::other)?;
+    let keys = bench_keys();
+    let token_signer = TokenSigner::new(bench_keys(), None);
+    let action_base_url = ActionBaseUrl::parse(BASE_URL.into(), false)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let issue = render_bench_issue(&keys).map_err(std::io::Error::other)?;
+    let newsletter_issue_id = Uuid::new_v4();
+    let tracking = IssueTracking::new(&issue.html, BASE_URL, &keys, newsletter_issue_id);
+    let delivery = IssueDelivery {
+        records: &NoDeliveryRecords,
+        email_sender: &email_client,
+        templates: &templates,
+        token_signer: &token_signer,
+        action_base_url: &action_base_url,
+        newsletter: "Bench",
+        issue: &issue,
+        tracking: Some(&tracking),
+        title: "Benchmark issue",
+        newsletter_issue_id,
+    };
+    let recipients: Vec<_> = (0..options.count)
+        .map(|i| ConfirmedSubscriber {
+            id: Uuid::new_v4(),
+            email: SubscriberEmail::parse(format!("reader-{i}@example.com")).unwrap(),
+            name: format!("Reader {i}"),
+            recent_deliveries: 0,
+        })
+        .collect();
+
+    let started_at = Instant::now();
+    // Driven like publishing drives a batch.
+    let outcomes: Vec<_> = stream::iter(&recipients)
+        .map(|subscriber| async {
+            let sent_at = Instant::now();
+            let outcome = delivery.send_to(subscriber).await;
+            (sent_at.elapsed(), outcome.is_ok())
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    let elapsed = started_at.elapsed();
+    provider_handle.stop(true).await;
+
+    let failures = outcomes.iter().filter(|(_, sent)| !sent).count();
+    let mut latencies: Vec<_> = outcomes.into_iter().map(|(latency, _)| latency).collect();
+    latencies.sort_unstable();
+    println!(
+        "sent {} emails in {:.2?} with concurrency {} ({} failed)",
+        latencies.len(),
+        elapsed,
+        options.concurrency,
+        failures
+    );
+    println!(
+        "throughput: {:.0} emails/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    for (label, quantile) in [("p50", 0.50), ("p90", 0.90), ("p99", 0.99), ("max", 1.0)] {
+        println!("{}: {:.2?}", label, percentile(&latencies, quantile));
+    }
+    Ok(())
+}
+
+fn bench_keys() -> KeyRing {
+    KeyRing::new(
+        "bench".into(),
+        HashMap::from([("bench".to_string(), SecretString::from("b".repeat(32)))]),
+    )
+    .unwrap()
+}
+
+/// What `render_issue` makes of an issue with a snippet, a link and a sponsor slot, without
+/// the database it loads them from.
+fn render_bench_issue(keys: &KeyRing) -> Result<RenderedIssue, anyhow::Error> {
+    let snippets = HashMap::from([(
+        "footer".to_string(),
+        "You are receiving this because you subscribed.".to_string(),
+    )]);
+    let html = resolve_snippets(
+        "<p>Hello {{ subscriber.name }}, read <a href=\"https://example.com/article\">this</a>.</p>{{> footer }}",
+        &snippets,
+    )?;
+    let text = resolve_snippets(
+        "Hello {{ subscriber.name }}, read https://example.com/article\n{{> footer }}",
+        &snippets,
+    )?;
+    let content = sanitize(&html, &text)?;
+    let sponsor_slots = vec![ActiveSponsorSlot {
+        id: Uuid::new_v4(),
+        sponsor_name: "Bench".into(),
+        creative_html: "<a href=\"{{ sponsor_link }}\">Sponsor</a>".into(),
+    }];
+    let (html, text) =
+        inject_sponsor_blocks(&content.html, &content.text, &sponsor_slots, BASE_URL, keys);
+    Ok(RenderedIssue {
+        html,
+        text,
+        sponsor_slots,
+        compliance_error: None,
+        links: content.links,
+        warnings: content.warnings,
+    })
+}
+
+/// `sorted` must be sorted in ascending order.
+fn percentile(sorted: &[Duration], quantile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+    sorted[index]
+}
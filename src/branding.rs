@@ -0,0 +1,296 @@
+//! The newsletter's look: a logo above every issue, and a footer with the sender's
+//! address and social links below it, in the accent color.
+//!
+//! Broadcasts must carry the sender's postal address (CAN-SPAM): issues can't be published
+//! until `footer_address` is set. `{{ sender.address }}` places it in the body as well.
+use crate::merge_fields::escape;
+use sqlx::PgPool;
+use sqlx::types::Json;
+
+pub const SENDER_ADDRESS_FIELD: &str = "{{ sender.address }}";
+const MAX_FOOTER_ADDRESS_LENGTH: usize = 500;
+const MAX_SOCIAL_LINK_LABEL_LENGTH: usize = 50;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Branding {
+    #[serde(default)]
+    pub logo_url: Option<String>,
+    // `#rrggbb`
+    #[serde(default)]
+    pub accent_color: Option<String>,
+    #[serde(default)]
+    pub footer_address: Option<String>,
+    #[serde(default)]
+    pub social_links: Vec<SocialLink>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SocialLink {
+    pub label: String,
+    pub url: String,
+}
+
+impl Branding {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(logo_url) = &self.logo_url {
+            validate_url(logo_url)?;
+        }
+        if let Some(accent_color) = &self.accent_color {
+            let is_hex_color = accent_color
+                .strip_prefix('#')
+                .is_some_and(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+            if !is_hex_color {
+                return Err(format!(
+                    "{accent_color} is not a valid accent color: use #rrggbb."
+                ));
+            }
+        }
+        if let Some(footer_address) = &self.footer_address
+            && footer_address.chars().count() > MAX_FOOTER_ADDRESS_LENGTH
+        {
+            return Err(format!(
+                "The footer address must be at most {MAX_FOOTER_ADDRESS_LENGTH} characters long."
+            ));
+        }
+        for link in &self.social_links {
+            let label_length = link.label.trim().chars().count();
+            if label_length == 0 || label_length > MAX_SOCIAL_LINK_LABEL_LENGTH {
+                return Err(format!(
+                    "Social link labels must be between 1 and {MAX_SOCIAL_LINK_LABEL_LENGTH} characters long."
+                ));
+            }
+            validate_url(&link.url)?;
+        }
+        Ok(())
+    }
+
+    /// Why an issue with this branding can't be sent out, if it can't.
+    pub fn compliance_error(&self) -> Option<String> {
+        let has_postal_address = self
+            .footer_address
+            .as_deref()
+            .is_some_and(|address| !address.trim().is_empty());
+        (!has_postal_address).then(|| {
+            "A postal address is required in the footer of every issue. \
+            Set `footer_address` in the branding settings."
+                .to_string()
+        })
+    }
+
+    fn html_header(&self) -> String {
+        match &self.logo_url {
+            Some(logo_url) => format!(
+                "<p><img src=\"{}\" alt=\"\" style=\"max-height: 60px\" /></p>",
+                escape(logo_url)
+            ),
+            None => String::new(),
+        }
+    }
+
+    fn html_footer(&self) -> String {
+        if self.footer_address.is_none() && self.social_links.is_empty() {
+            return String::new();
+        }
+        let accent_color = self.accent_color.as_deref().unwrap_or("#666666");
+        let mut lines = Vec::new();
+        if let Some(footer_address) = &self.footer_address {
+            lines.push(html_address(footer_address));
+        }
+        if !self.social_links.is_empty() {
+            let links: Vec<_> = self
+                .social_links
+                .iter()
+                .map(|link| {
+                    format!(
+                        "<a href=\"{}\" style=\"color: {accent_color}\">{}</a>",
+                        escape(&link.url),
+                        escape(&link.label)
+                    )
+                })
+                .collect();
+            lines.push(links.join(" · "));
+        }
+        format!(
+            "<hr style=\"border: 0; border-top: 2px solid {accent_color}\" />\
+            <p style=\"color: #666666; font-size: 12px\">{}</p>",
+            lines.join("<br />")
+        )
+    }
+
+    fn text_footer(&self) -> String {
+        if self.footer_address.is_none() && self.social_links.is_empty() {
+            return String::new();
+        }
+        let mut footer = String::from("\n\n--");
+        if let Some(footer_address) = &self.footer_address {
+            footer.push('\n');
+            footer.push_str(footer_address);
+        }
+        for link in &self.social_links {
+            footer.push_str(&format!("\n{}: {}", link.label, link.url));
+        }
+        footer
+    }
+}
+
+fn html_address(address: &str) -> String {
+    escape(address).replace('\n', "<br />")
+}
+
+fn validate_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => Ok(()),
+        _ => Err(format!("{url} is not a valid http(s) url.")),
+    }
+}
+
+/// Wrap the HTML and plain-text bodies of an issue in the newsletter's branding.
+/// Without a postal address, `{{ sender.address }}` is left in place.
+pub fn apply_branding(html: &str, text: &str, branding: &Branding) -> (String, String) {
+    let (html, text) = match &branding.footer_address {
+        Some(address) => (
+            html.replace(SENDER_ADDRESS_FIELD, &html_address(address)),
+            text.replace(SENDER_ADDRESS_FIELD, address),
+        ),
+        None => (html.to_owned(), text.to_owned()),
+    };
+    (
+        format!(
+            "{}{}{}",
+            branding.html_header(),
+            html,
+            branding.html_footer()
+        ),
+        format!("{}{}", text, branding.text_footer()),
+    )
+}
+
+/// Unbranded until an admin sets it up.
+#[tracing::instrument(name = "Load branding", skip(pool))]
+pub async fn load_branding(pool: &PgPool) -> Result<Branding, sqlx::Error> {
+    let branding = sqlx::query!(
+        r#"
+        SELECT logo_url, accent_color, footer_address,
+            social_links AS "social_links: Json<Vec<SocialLink>>"
+        FROM branding
+        "#
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| Branding {
+        logo_url: row.logo_url,
+        accent_color: row.accent_color,
+        footer_address: row.footer_address,
+        social_links: row.social_links.0,
+    })
+    .unwrap_or_default();
+    Ok(branding)
+}
+
+#[tracing::instrument(name = "Save branding", skip(pool, branding))]
+pub async fn save_branding(pool: &PgPool, branding: &Branding) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO branding (id, logo_url, accent_color, footer_address, social_links, updated_at)
+        VALUES (TRUE, $1, $2, $3, $4, now())
+        ON CONFLICT (id) DO UPDATE
+        SET logo_url = EXCLUDED.logo_url,
+            accent_color = EXCLUDED.accent_color,
+            footer_address = EXCLUDED.footer_address,
+            social_links = EXCLUDED.social_links,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        branding.logo_url,
+        branding.accent_color,
+        branding.footer_address,
+        Json(&branding.social_links) as _,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Branding, SocialLink, apply_branding};
+    use claim::{assert_err, assert_ok};
+
+    fn branding() -> Branding {
+        Branding {
+            logo_url: Some("https://example.com/logo.png".into()),
+            accent_color: Some("#ff6600".into()),
+            footer_address: Some("1 Main St\nSpringfield".into()),
+            social_links: vec![SocialLink {
+                label: "Mastodon".into(),
+                url: "https://example.social/@news".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn issues_are_left_untouched_without_branding() {
+        let (html, text) = apply_branding("<p>Hi</p>", "Hi", &Branding::default());
+        assert_eq!(html, "<p>Hi</p>");
+        assert_eq!(text, "Hi");
+    }
+
+    #[test]
+    fn the_logo_goes_above_the_issue_and_the_footer_below() {
+        let (html, text) = apply_branding("<p>Hi</p>", "Hi", &branding());
+        assert!(html.starts_with("<p><img src=\"https://example.com/logo.png\""));
+        assert!(html.contains("1 Main St<br />Springfield"));
+        assert!(html.contains("color: #ff6600\">Mastodon</a>"));
+        assert!(text.ends_with("1 Main St\nSpringfield\nMastodon: https://example.social/@news"));
+    }
+
+    #[test]
+    fn the_footer_is_escaped_in_html() {
+        let branding = Branding {
+            footer_address: Some("<b>Acme</b>".into()),
+            ..Branding::default()
+        };
+        let (html, _) = apply_branding("", "", &branding);
+        assert!(html.contains("&lt;b&gt;Acme&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn the_sender_address_field_is_replaced_in_the_body() {
+        let (html, text) = apply_branding(
+            "<p>{{ sender.address }}</p>",
+            "Write to {{ sender.address }}",
+            &branding(),
+        );
+        assert!(html.contains("<p>1 Main St<br />Springfield</p>"));
+        assert!(text.starts_with("Write to 1 Main St\nSpringfield"));
+    }
+
+    #[test]
+    fn issues_cannot_go_out_without_a_postal_address() {
+        assert!(branding().compliance_error().is_none());
+        assert!(Branding::default().compliance_error().is_some());
+        let blank_address = Branding {
+            footer_address: Some("  ".into()),
+            ..Branding::default()
+        };
+        assert!(blank_address.compliance_error().is_some());
+    }
+
+    #[test]
+    fn accent_colors_must_be_hex() {
+        assert_ok!(branding().validate());
+        let branding = Branding {
+            accent_color: Some("red; background: url(x)".into()),
+            ..Branding::default()
+        };
+        assert_err!(branding.validate());
+    }
+
+    #[test]
+    fn links_must_be_http() {
+        let branding = Branding {
+            logo_url: Some("javascript:alert(1)".into()),
+            ..Branding::default()
+        };
+        assert_err!(branding.validate());
+    }
+}
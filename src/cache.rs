@@ -0,0 +1,349 @@
+//! A key-value cache with per-entry expiry, shared by the features that need one.
+//!
+//! Use the in-memory backend for a single instance. With several replicas, point them all
+//! at Redis so that they see the same entries, e.g. the same rate-limit counters.
+use anyhow::Context;
+use moka::Expiry;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[async_trait::async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, anyhow::Error>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), anyhow::Error>;
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error>;
+
+    /// Atomically add one to the counter stored at `key` and return its new value.
+    /// A missing counter starts from zero and expires after `ttl`; incrementing an
+    /// existing counter leaves its expiry alone.
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, anyhow::Error>;
+
+    /// Atomically take a token from the bucket stored at `key`, which holds up to
+    /// `capacity` tokens, and at least one, and gets one back every `refill`. A missing
+    /// bucket is full.
+    /// Returns `None` if a token was taken, or how long until the bucket has one again.
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, anyhow::Error>;
+
+    /// Fails if the backend can't be reached.
+    async fn ping(&self) -> Result<(), anyhow::Error>;
+}
+
+pub struct InMemoryCache {
+    entries: moka::future::Cache<String, Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    value: String,
+    expires_at: Instant,
+}
+
+struct ExpiresAt;
+
+impl Expiry<String, Entry> for ExpiresAt {
+    fn expire_after_create(&self, _: &String, entry: &Entry, now: Instant) -> Option<Duration> {
+        Some(entry.expires_at.saturating_duration_since(now))
+    }
+
+    fn expire_after_update(
+        &self,
+        _: &String,
+        entry: &Entry,
+        now: Instant,
+        _: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(entry.expires_at.saturating_duration_since(now))
+    }
+}
+
+impl InMemoryCache {
+    /// Least recently used entries are evicted past `max_capacity`.
+    pub fn new(max_capacity: u64) -> Self {
+        Self {
+            entries: moka::future::Cache::builder()
+                .max_capacity(max_capacity)
+                .expire_after(ExpiresAt)
+                .build(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Cache for InMemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self.entries.get(key).await.map(|entry| entry.value))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), anyhow::Error> {
+        let entry = Entry {
+            value: value.to_owned(),
+            expires_at: Instant::now() + ttl,
+        };
+        self.entries.insert(key.to_owned(), entry).await;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.entries.invalidate(key).await;
+        Ok(())
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, anyhow::Error> {
+        let entry = self
+            .entries
+            .entry_by_ref(key)
+            .and_upsert_with(|existing| async move {
+                let (count, expires_at) = match existing.map(|e| e.into_value()) {
+                    Some(entry) => (entry.value.parse::<u64>().unwrap_or(0), entry.expires_at),
+                    None => (0, Instant::now() + ttl),
+                };
+                Entry {
+                    value: (count + 1).to_string(),
+                    expires_at,
+                }
+            })
+            .await;
+        entry
+            .into_value()
+            .value
+            .parse()
+            .context("The cached value is not a counter.")
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let now = unix_millis(SystemTime::now());
+        let mut wait = None;
+        let wait_ref = &mut wait;
+        self.entries
+            .entry_by_ref(key)
+            .and_upsert_with(|existing| async move {
+                let existing = existing.map(|e| e.into_value());
+                let ready_at = existing
+                    .as_ref()
+                    .and_then(|entry| entry.value.parse().ok())
+                    .unwrap_or(now);
+                match take_token(ready_at, now, capacity, refill) {
+                    Ok(ready_at) => Entry {
+                        value: ready_at.to_string(),
+                        expires_at: Instant::now() + Duration::from_millis(ready_at - now),
+                    },
+                    Err(retry_after) => {
+                        *wait_ref = Some(retry_after);
+                        // A missing bucket is full, so it is one that exists.
+                        existing.expect("Only a stored bucket can be empty")
+                    }
+                }
+            })
+            .await;
+        Ok(wait)
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+pub struct RedisCache {
+    // Reconnects on its own; cheap to clone, one per call.
+    connection: ConnectionManager,
+}
+
+impl RedisCache {
+    pub async fn connect(uri: &str) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(uri).context("Invalid Redis uri.")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis.")?;
+        Ok(Self { connection })
+    }
+}
+
+#[async_trait::async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        connection
+            .get(key)
+            .await
+            .context("Failed to read from Redis.")
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        connection
+            .set_ex(key, value, expiry_seconds(ttl))
+            .await
+            .context("Failed to write to Redis.")
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        connection
+            .del(key)
+            .await
+            .context("Failed to delete from Redis.")
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        // Create the counter with its expiry first, so that it can't outlive `ttl`
+        // if we were to die between the two commands.
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("EX")
+            .arg(expiry_seconds(ttl))
+            .arg("NX")
+            .ignore()
+            .incr(key, 1)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to increment a counter in Redis.")?;
+        Ok(count)
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        // `take_token` below, with the clock of Redis so that replicas agree on the time.
+        let retry_after: u64 = redis::cmd("EVAL")
+            .arg(TAKE_TOKEN_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(capacity.max(1))
+            .arg(refill.as_millis().max(1) as u64)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to take a token from a bucket in Redis.")?;
+        Ok((retry_after > 0).then(|| Duration::from_millis(retry_after)))
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING")
+            .query_async::<()>(&mut connection)
+            .await
+            .context("Failed to ping Redis.")
+    }
+}
+
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local refill = tonumber(ARGV[2])
+local burst = refill * tonumber(ARGV[1])
+local ready_at = math.max(tonumber(redis.call('GET', KEYS[1]) or now), now) + refill
+if ready_at - now > burst then
+    return ready_at - now - burst
+end
+redis.call('SET', KEYS[1], ready_at, 'PX', ready_at - now)
+return 0
+"#;
+
+/// The bucket is kept as the time, in milliseconds since the epoch, at which it will be
+/// full again: each token taken pushes that `refill` further out, and it can't be more
+/// than `capacity` refills away. Returns the new time, or how long to wait for a token.
+fn take_token(ready_at: u64, now: u64, capacity: u32, refill: Duration) -> Result<u64, Duration> {
+    let refill = refill.as_millis().max(1) as u64;
+    // A bucket holds at least one token.
+    let burst = refill * u64::from(capacity.max(1));
+    let ready_at = ready_at.max(now) + refill;
+    if ready_at - now > burst {
+        Err(Duration::from_millis(ready_at - now - burst))
+    } else {
+        Ok(ready_at)
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Redis expiries have a granularity of a second, and must be positive.
+fn expiry_seconds(ttl: Duration) -> u64 {
+    ttl.as_secs().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, InMemoryCache};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn values_can_be_set_read_and_deleted() {
+        let cache = InMemoryCache::new(100);
+        cache
+            .set("key", "value", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(cache.get("key").await.unwrap().as_deref(), Some("value"));
+        cache.delete("key").await.unwrap();
+        assert_eq!(cache.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn values_expire_after_their_ttl() {
+        let cache = InMemoryCache::new(100);
+        cache
+            .set("key", "value", Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn counters_keep_the_expiry_they_were_created_with() {
+        let cache = InMemoryCache::new(100);
+        let ttl = Duration::from_millis(100);
+        assert_eq!(cache.increment("counter", ttl).await.unwrap(), 1);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(cache.increment("counter", ttl).await.unwrap(), 2);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(cache.increment("counter", ttl).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn buckets_refill_one_token_at_a_time() {
+        let cache = InMemoryCache::new(100);
+        let refill = Duration::from_millis(100);
+        assert_eq!(cache.take_token("bucket", 2, refill).await.unwrap(), None);
+        assert_eq!(cache.take_token("bucket", 2, refill).await.unwrap(), None);
+        let wait = cache
+            .take_token("bucket", 2, refill)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(wait > Duration::from_millis(50) && wait <= refill);
+        tokio::time::sleep(wait).await;
+        assert_eq!(cache.take_token("bucket", 2, refill).await.unwrap(), None);
+        assert!(
+            cache
+                .take_token("bucket", 2, refill)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+}
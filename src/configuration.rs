@@ -1,16 +1,128 @@
-use crate::domain::SubscriberEmail;
+use crate::admin_access::AdminAllowlist;
+use crate::cache::{Cache, InMemoryCache, RedisCache};
+use crate::crypto::KeyRing;
+use crate::domain::{ActionBaseUrl, DomainError, SignupField, SubscriberEmail};
+use crate::email_client::{
+    CircuitBreakerSettings, DeliverySettings, EmailClient, EmailRoute, MessageCategory, RetryPolicy,
+};
+use crate::pii::PiiCipher;
+use crate::preflight::OnMigrationDrift;
+use crate::rate_limit::RateLimiter;
+use crate::session::{AdminSessionStore, CacheSessionStore, PostgresSessionStore};
+use crate::signed_tokens::TokenSigner;
+use crate::signup_verification::{CaptchaProvider, CaptchaVerifier, ProofOfWork, SignupVerifier};
+use crate::sms_client::SmsClient;
+use crate::web_push::WebPushClient;
+use actix_web::cookie::Key;
 use config::{Config, File};
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
-use sqlx::ConnectOptions;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::{ConnectOptions, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
+    // Read-heavy queries go to the primary when this section is missing.
+    #[serde(default)]
+    pub database_replica: Option<DatabaseSettings>,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub signing: SigningSettings,
+    // Defaults to an in-memory cache.
+    #[serde(default)]
+    pub cache: CacheSettings,
+    // Spans are only logged unless an OpenTelemetry collector is configured.
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    // Where admin sessions are kept. Defaults to the cache.
+    #[serde(default)]
+    pub session_store: SessionStoreBackend,
+    // Per-IP limit on `/subscriptions/confirm`, against token guessing.
+    pub confirmation_rate_limit: RateLimitSettings,
+    // Per-IP limit on `POST /subscriptions`: every signup costs a confirmation email.
+    pub subscription_rate_limit: RateLimitSettings,
+    // Signups don't have to prove they aren't bots when this section is missing.
+    #[serde(default)]
+    pub signup_verification: Option<SignupVerificationSettings>,
+    // Per-IP limit on `POST /login`, against password guessing.
+    pub login_rate_limit: RateLimitSettings,
+    // Per-subscriber limit on `POST /subscriptions/resend_confirmation`, so that nobody can be
+    // flooded with confirmation emails.
+    pub resend_confirmation_rate_limit: RateLimitSettings,
+    // Web Push is disabled unless a VAPID keypair is configured.
+    #[serde(default)]
+    pub web_push: Option<WebPushSettings>,
+    // The SMS channel is disabled unless a provider is configured.
+    #[serde(default)]
+    pub sms: Option<SmsSettings>,
+    // Reminders for pending subscribers are disabled when this section is missing.
+    #[serde(default)]
+    pub confirmation_reminders: Option<ConfirmationReminderSettings>,
+    // Expired confirmation tokens and subscribers who never confirmed are kept
+    // when this section is missing.
+    #[serde(default)]
+    pub token_cleanup: Option<TokenCleanupSettings>,
+    // Issues keep every delivery attempt and tracking event when this section is missing.
+    #[serde(default)]
+    pub issue_compaction: Option<IssueCompactionSettings>,
+    // No cap on how many issues a subscriber gets when this section is missing.
+    #[serde(default)]
+    pub frequency_cap: Option<FrequencyCapSettings>,
+    #[serde(default)]
+    pub scheduler: SchedulerSettings,
+    // Subscriber emails and names are stored in plaintext when this section is missing.
+    #[serde(default)]
+    pub pii_encryption: Option<PiiEncryptionSettings>,
+    // `/admin` is open to every address when this section is missing.
+    #[serde(default)]
+    pub admin_access: Option<AdminAccessSettings>,
+    // `POST /admin/smoke_test` is disabled when this section is missing.
+    #[serde(default)]
+    pub smoke_test: Option<SmokeTestSettings>,
+    // Users get no operational emails when this section is missing.
+    #[serde(default)]
+    pub admin_notifications: Option<AdminNotificationSettings>,
+    // `POST /email/webhooks/bounce` is disabled when this section is missing.
+    #[serde(default)]
+    pub email_webhooks: Option<EmailWebhookSettings>,
+    // Browsers on other origins can't call the API when this section is missing.
+    #[serde(default)]
+    pub cors: Option<CorsSettings>,
+    #[serde(default)]
+    pub security_headers: SecurityHeaderSettings,
+    #[serde(default)]
+    pub content_checks: ContentCheckSettings,
+    // Taken from APP_ENVIRONMENT rather than from the configuration files.
+    #[serde(skip)]
+    pub environment: Environment,
+}
+
+impl Settings {
+    /// Falls back to `application.base_url` when no dedicated action domain is configured.
+    pub fn action_base_url(&self) -> Result<ActionBaseUrl, String> {
+        let url = self
+            .application
+            .action_base_url
+            .clone()
+            .unwrap_or_else(|| self.application.base_url.clone());
+        ActionBaseUrl::parse(url, matches!(self.environment, Environment::Prod))
+    }
+
+    pub fn pii_cipher(&self) -> Result<PiiCipher, String> {
+        match &self.pii_encryption {
+            Some(settings) => PiiCipher::new(
+                settings.current_key_id.clone(),
+                settings.keys.clone(),
+                settings.blind_index_key.clone(),
+            ),
+            None => Ok(PiiCipher::disabled()),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -18,7 +130,161 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
-    pub base_url: String
+    pub base_url: String,
+    // Hosts that `redirect_to` continuations on the subscribe form may point to.
+    #[serde(default)]
+    pub redirect_allowed_hosts: Vec<String>,
+    // Base URL of confirmation links, if they should live on their own domain.
+    #[serde(default)]
+    pub action_base_url: Option<String>,
+    // What to do at startup when the database migrations don't match the ones we were built with.
+    #[serde(default)]
+    pub on_migration_drift: OnMigrationDrift,
+    // Pending and confirmed subscribers past which new signups are waitlisted. No cap if unset.
+    #[serde(default)]
+    pub max_active_subscribers: Option<u64>,
+    // Require an invite code to subscribe.
+    #[serde(default)]
+    pub invite_only: bool,
+    // Extra fields of the subscribe form, on top of name and email.
+    #[serde(default)]
+    pub signup_fields: Vec<SignupField>,
+    // Reverse proxies whose `X-Forwarded-For` header is believed.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    // Signs and encrypts admin session cookies. At least 64 bytes long.
+    pub session_key: SecretString,
+    // Where the email templates are, e.g. `email/confirmation.html`.
+    pub templates_dir: String,
+    // How long in-flight requests, then running jobs, get to finish on shutdown.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+    // How long the subscription funnel stats are cached for; they are recomputed every time if 0.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub stats_cache_seconds: u64,
+    // Largest request bodies accepted; larger ones get a 413.
+    #[serde(default)]
+    pub payload_limits: PayloadLimitSettings,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PayloadLimitSettings {
+    // JSON bodies, e.g. of the admin APIs.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub json_bytes: usize,
+    // Form bodies, e.g. of `POST /subscriptions` and the login form.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub form_bytes: usize,
+    // Bodies carrying the content of an issue, JSON or form: to publish, preview or
+    // test-send it.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub issue_bytes: usize,
+}
+
+impl Default for PayloadLimitSettings {
+    fn default() -> Self {
+        Self {
+            json_bytes: 256 * 1024,
+            form_bytes: 16 * 1024,
+            issue_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CorsSettings {
+    // Such as `https://app.example.com`, without a path.
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "CorsSettings::default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    // Whether requests from those origins may carry cookies and HTTP credentials.
+    #[serde(default)]
+    pub allow_credentials: bool,
+    // How long browsers may keep the answer to a preflight request.
+    #[serde(default = "CorsSettings::default_max_age_seconds")]
+    pub max_age_seconds: usize,
+}
+
+impl CorsSettings {
+    fn default_allowed_methods() -> Vec<String> {
+        vec!["GET".into(), "POST".into()]
+    }
+
+    fn default_max_age_seconds() -> usize {
+        3600
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SecurityHeaderSettings {
+    // How long browsers only use https for this domain once they have seen it. Only sent when
+    // `base_url` is https; 0 to never send it.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hsts_max_age_seconds: u64,
+    // The `Content-Security-Policy` of the admin pages.
+    pub admin_content_security_policy: String,
+}
+
+impl Default for SecurityHeaderSettings {
+    fn default() -> Self {
+        Self {
+            hsts_max_age_seconds: 365 * 24 * 60 * 60,
+            admin_content_security_policy: "default-src 'self'; form-action 'self'; \
+                frame-ancestors 'none'; base-uri 'none'"
+                .into(),
+        }
+    }
+}
+
+/// What issues are checked for when they are published. The findings are warnings: the
+/// issue goes out regardless.
+#[derive(Deserialize, Clone)]
+pub struct ContentCheckSettings {
+    // Whether every link and image of an issue is fetched, to warn about those that are broken.
+    pub resolve_links: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub link_timeout_milliseconds: u64,
+    // Issues scoring this much or more for spam get a warning.
+    pub spam_score_threshold: f32,
+    // Issues whose text is this similar (from 0 to 1) to one already sent get a warning.
+    #[serde(default = "ContentCheckSettings::default_similarity_threshold")]
+    pub similarity_threshold: f32,
+}
+
+impl ContentCheckSettings {
+    pub fn link_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.link_timeout_milliseconds)
+    }
+
+    fn default_similarity_threshold() -> f32 {
+        0.8
+    }
+}
+
+impl Default for ContentCheckSettings {
+    fn default() -> Self {
+        Self {
+            resolve_links: true,
+            link_timeout_milliseconds: 3000,
+            spam_score_threshold: 5.0,
+            similarity_threshold: Self::default_similarity_threshold(),
+        }
+    }
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    pub fn stats_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.stats_cache_seconds)
+    }
+
+    pub fn session_key(&self) -> Result<Key, String> {
+        Key::try_from(self.session_key.expose_secret().as_bytes())
+            .map_err(|_| "The session key must be at least 64 bytes long.".to_string())
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -30,6 +296,40 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    #[serde(default)]
+    pub pool: PoolSettings,
+    // Retries of the first connection at startup, e.g. while Postgres is still booting.
+    #[serde(default = "DatabaseSettings::default_connect_retry")]
+    pub connect_retry: RetryPolicy,
+    // Apply pending migrations before serving, rather than leaving it to a deploy step.
+    #[serde(default)]
+    pub migrate_on_startup: bool,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    // Kept open even when idle, once the first connection has been made.
+    pub min_connections: u32,
+    // How long a query waits for a free connection before failing.
+    pub acquire_timeout_milliseconds: u64,
+    // Connections idle for longer are closed, down to `min_connections`.
+    pub idle_timeout_seconds: u64,
+    // Queries running for longer are cancelled by Postgres. No limit if unset.
+    #[serde(default)]
+    pub statement_timeout_milliseconds: Option<u64>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_milliseconds: 2000,
+            idle_timeout_seconds: 600,
+            statement_timeout_milliseconds: None,
+        }
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -53,10 +353,14 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     // E.g. `APP_APPLICATION__PORT=5001` would set `Settings.application.port`
 
     // Deserialize into your Settings struct
-    settings.try_deserialize::<Settings>()
+    let mut settings = settings.try_deserialize::<Settings>()?;
+    settings.environment = environment;
+    Ok(settings)
 }
 
+#[derive(Clone, Copy, Default)]
 pub enum Environment {
+    #[default]
     Local,
     Prod,
 }
@@ -96,11 +400,19 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
 
+    fn default_connect_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_milliseconds: 250,
+            max_backoff_milliseconds: 5000,
+        }
+    }
+
     pub fn with_db(&self) -> PgConnectOptions {
         let mut options = self.without_db().database(&self.database_name);
         options = options.log_statements(tracing::log::LevelFilter::Trace);
@@ -114,10 +426,381 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub authorization_token: SecretString,
     pub timeout_milliseconds: u64,
+    // Per-category overrides, e.g. a dedicated Postmark message stream for broadcasts.
+    #[serde(default)]
+    pub routes: HashMap<MessageCategory, EmailRoute>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    #[serde(default)]
+    pub delivery: DeliverySettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SigningSettings {
+    // Key used to sign new values. The other keys are only used to verify,
+    // so that a rotated-out key keeps working for links that are already out there.
+    pub current_key_id: String,
+    pub keys: HashMap<String, SecretString>,
+    // Links sent before confirmation and unsubscribe tokens were signed keep working
+    // until then. Unset, unsigned tokens are rejected.
+    #[serde(default)]
+    pub unsigned_tokens_accepted_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SigningSettings {
+    pub fn key_ring(&self) -> Result<KeyRing, String> {
+        KeyRing::new(self.current_key_id.clone(), self.keys.clone())
+    }
+
+    pub fn token_signer(&self) -> Result<TokenSigner, String> {
+        Ok(TokenSigner::new(
+            self.key_ring()?,
+            self.unsigned_tokens_accepted_until,
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AdminAccessSettings {
+    // CIDR blocks, e.g. the VPN's, that may reach the routes that take a permission.
+    pub allowed_networks: Vec<IpNet>,
+}
+
+impl AdminAccessSettings {
+    pub fn allowlist(&self) -> AdminAllowlist {
+        AdminAllowlist {
+            allowed_networks: self.allowed_networks.clone(),
+        }
+    }
+}
+
+/// HTTP Basic credentials the email provider sends its webhook calls with.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailWebhookSettings {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AdminNotificationSettings {
+    // Share of an issue's sends, from 0 to 1, that may fail at publish before users who
+    // asked to be are alerted.
+    #[serde(default = "AdminNotificationSettings::default_delivery_failure_threshold")]
+    pub delivery_failure_threshold: f64,
+}
+
+impl AdminNotificationSettings {
+    fn default_delivery_failure_threshold() -> f64 {
+        0.05
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmokeTestSettings {
+    // Where smoke test emails go, plus-addressed per run. Must accept `+` addresses.
+    pub sink_email: String,
+}
+
+impl SmokeTestSettings {
+    pub fn sink(&self) -> Result<SubscriberEmail, DomainError> {
+        SubscriberEmail::parse(self.sink_email.clone())
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PiiEncryptionSettings {
+    // Key used to encrypt new values. The other keys are only used to decrypt
+    // values that were encrypted before a rotation.
+    pub current_key_id: String,
+    // Base64-encoded 32-byte AES keys.
+    pub keys: HashMap<String, SecretString>,
+    // Keys the HMAC that emails are looked up by. Rotating it means recomputing every index.
+    pub blind_index_key: SecretString,
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct TelemetrySettings {
+    // Base URL of an OpenTelemetry collector taking OTLP over HTTP, e.g. Jaeger or Tempo at
+    // `http://localhost:4318`: spans are exported to its `/v1/traces`.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum CacheSettings {
+    Memory {
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        max_capacity: u64,
+    },
+    // Shared by every replica pointed at the same Redis.
+    Redis {
+        uri: SecretString,
+    },
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings::Memory {
+            max_capacity: 100_000,
+        }
+    }
+}
+
+impl CacheSettings {
+    pub async fn cache(&self) -> Result<Arc<dyn Cache>, anyhow::Error> {
+        Ok(match self {
+            CacheSettings::Memory { max_capacity } => Arc::new(InMemoryCache::new(*max_capacity)),
+            CacheSettings::Redis { uri } => {
+                Arc::new(RedisCache::connect(uri.expose_secret()).await?)
+            }
+        })
+    }
+}
+
+// Sessions an in-memory session store keeps before evicting the least recently used.
+const MEMORY_SESSION_CAPACITY: u64 = 10_000;
+
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum SessionStoreBackend {
+    // Wherever the `cache` section points.
+    #[default]
+    Cache,
+    // Only seen by this instance, and lost when it restarts: for tests and single instances.
+    Memory,
+    Redis {
+        uri: SecretString,
+    },
+    // For deployments without Redis. Expired sessions are deleted by the `session_cleanup` job.
+    Postgres,
+}
+
+impl SessionStoreBackend {
+    pub async fn store(
+        &self,
+        cache: Arc<dyn Cache>,
+        pool: &PgPool,
+    ) -> Result<AdminSessionStore, anyhow::Error> {
+        let cache: Arc<dyn Cache> = match self {
+            SessionStoreBackend::Cache => cache,
+            SessionStoreBackend::Memory => Arc::new(InMemoryCache::new(MEMORY_SESSION_CAPACITY)),
+            SessionStoreBackend::Redis { uri } => {
+                Arc::new(RedisCache::connect(uri.expose_secret()).await?)
+            }
+            SessionStoreBackend::Postgres => {
+                return Ok(AdminSessionStore::Postgres(PostgresSessionStore::new(
+                    pool.clone(),
+                )));
+            }
+        };
+        Ok(AdminSessionStore::Cache(CacheSessionStore::new(cache)))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignupVerificationSettings {
+    Hcaptcha(CaptchaSettings),
+    Turnstile(CaptchaSettings),
+    // Solved by the form's script: nothing for subscribers to do, but every signup costs
+    // about `2^difficulty_bits` hashes.
+    ProofOfWork {
+        difficulty_bits: u32,
+        challenge_lifetime_seconds: u64,
+    },
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CaptchaSettings {
+    pub secret: SecretString,
+    // Where tokens are checked. The provider's own endpoint if unset.
+    #[serde(default)]
+    pub verify_url: Option<String>,
+    pub timeout_milliseconds: u64,
+}
+
+impl SignupVerificationSettings {
+    pub fn verifier(
+        self,
+        signing: &SigningSettings,
+        cache: Arc<dyn Cache>,
+    ) -> Result<SignupVerifier, String> {
+        let captcha = |provider: CaptchaProvider, settings: CaptchaSettings| {
+            SignupVerifier::Captcha(CaptchaVerifier::new(
+                provider,
+                settings
+                    .verify_url
+                    .unwrap_or_else(|| provider.default_verify_url().into()),
+                settings.secret,
+                std::time::Duration::from_millis(settings.timeout_milliseconds),
+            ))
+        };
+        Ok(match self {
+            SignupVerificationSettings::Hcaptcha(settings) => {
+                captcha(CaptchaProvider::Hcaptcha, settings)
+            }
+            SignupVerificationSettings::Turnstile(settings) => {
+                captcha(CaptchaProvider::Turnstile, settings)
+            }
+            SignupVerificationSettings::ProofOfWork {
+                difficulty_bits,
+                challenge_lifetime_seconds,
+            } => {
+                if difficulty_bits > 32 {
+                    return Err("Proof-of-work difficulty can't be over 32 bits.".into());
+                }
+                SignupVerifier::ProofOfWork(ProofOfWork::new(
+                    signing.key_ring()?,
+                    cache,
+                    difficulty_bits,
+                    std::time::Duration::from_secs(challenge_lifetime_seconds),
+                ))
+            }
+        })
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub max_requests: u32,
+    pub window_seconds: u64,
+}
+
+impl RateLimitSettings {
+    pub fn limiter(&self, cache: Arc<dyn Cache>, scope: &'static str) -> RateLimiter {
+        RateLimiter::new(
+            cache,
+            scope,
+            self.max_requests,
+            std::time::Duration::from_secs(self.window_seconds),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WebPushSettings {
+    // Base64url-encoded P-256 private key; the public half is derived from it.
+    pub vapid_private_key: SecretString,
+    // Contact URI (`mailto:` or `https:`) that push services can use to reach us.
+    pub subject: String,
+    pub timeout_milliseconds: u64,
+}
+
+impl WebPushSettings {
+    pub fn client(&self) -> Result<WebPushClient, String> {
+        WebPushClient::new(
+            &self.vapid_private_key,
+            self.subject.clone(),
+            std::time::Duration::from_millis(self.timeout_milliseconds),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmsSettings {
+    // Root of a Twilio-compatible API, e.g. `https://api.twilio.com/2010-04-01`.
+    pub base_url: String,
+    pub account_sid: String,
+    pub auth_token: SecretString,
+    // The number or sender id messages are sent from.
+    pub sender: String,
+    pub timeout_milliseconds: u64,
+}
+
+impl SmsSettings {
+    pub fn client(self) -> SmsClient {
+        SmsClient::new(
+            self.base_url,
+            self.account_sid,
+            self.auth_token,
+            self.sender,
+            std::time::Duration::from_millis(self.timeout_milliseconds),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ConfirmationReminderSettings {
+    // How long after signing up a pending subscriber gets their one and only reminder.
+    pub delay_hours: i64,
+    // Recipients on these domains are never reminded.
+    #[serde(default)]
+    pub suppressed_domains: Vec<String>,
+}
+
+impl ConfirmationReminderSettings {
+    pub fn delay(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.delay_hours)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TokenCleanupSettings {
+    // Subscribers still pending this long after signing up (or being admitted from the
+    // waitlist) are deleted.
+    pub prune_pending_after_days: i64,
+}
+
+impl TokenCleanupSettings {
+    pub fn prune_pending_after(&self) -> chrono::Duration {
+        chrono::Duration::days(self.prune_pending_after_days)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IssueCompactionSettings {
+    // How long after it was published, and once nothing of it is left queued, an issue is
+    // compacted. Bounces and opens reported later than this aren't counted.
+    pub grace_period_days: i64,
+}
+
+impl IssueCompactionSettings {
+    pub fn grace_period(&self) -> chrono::Duration {
+        chrono::Duration::days(self.grace_period_days)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FrequencyCapSettings {
+    // Issues a subscriber can get within any window; further ones skip them.
+    pub max_emails: i64,
+    pub window_hours: i64,
+}
+
+impl FrequencyCapSettings {
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.window_hours)
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct SchedulerSettings {
+    // Cron expression (`sec min hour day-of-month month day-of-week`) of each periodic job,
+    // by job name. Jobs without one don't run.
+    #[serde(default)]
+    pub jobs: HashMap<String, String>,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.authorization_token,
+            timeout,
+        )
+        .with_routes(self.routes)
+        .with_retry_policy(self.retry)
+        .with_circuit_breaker(self.circuit_breaker)
+        .with_delivery(self.delivery)
+    }
+
+    pub fn sender(&self) -> Result<SubscriberEmail, DomainError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
@@ -0,0 +1,339 @@
+//! The outbox of confirmation emails, so that every new subscriber gets one at least once.
+//!
+//! The email is enqueued in the same transaction as the token it carries. Once that is
+//! committed, the request that subscribed tries to deliver it straight away; whatever it
+//! didn't deliver, because sending failed or because we crashed in between, is picked up
+//! by the `confirmation_outbox` job.
+//!
+//! The `confirmation_reminders` job enqueues its reminders here too, so that they are
+//! retried the same way.
+use crate::configuration::Settings;
+use crate::confirmation_reminders::send_reminder_email;
+use crate::database;
+use crate::domain::{ActionBaseUrl, Language, NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::pii::PiiCipher;
+use crate::routes::send_confirmation_email;
+use crate::scheduler::Job;
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use crate::templates::EmailTemplates;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+// Between attempts at an email that failed, doubling up to `MAX_RETRY_DELAY`. They stop
+// when the token expires: the outbox entry goes with it.
+const INITIAL_RETRY_DELAY: Duration = Duration::minutes(1);
+const MAX_RETRY_DELAY: Duration = Duration::hours(1);
+
+/// Delivers the confirmation emails left in the outbox, as a scheduled job.
+pub struct ConfirmationOutbox {
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    templates: EmailTemplates,
+    token_signer: TokenSigner,
+    pii_cipher: PiiCipher,
+    action_base_url: ActionBaseUrl,
+}
+
+impl ConfirmationOutbox {
+    pub fn from_configuration(
+        configuration: &Settings,
+        pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            pool,
+            email_client,
+            templates: EmailTemplates::load(&configuration.application.templates_dir)?,
+            token_signer: configuration
+                .signing
+                .token_signer()
+                .map_err(anyhow::Error::msg)?,
+            pii_cipher: configuration.pii_cipher().map_err(anyhow::Error::msg)?,
+            action_base_url: configuration
+                .action_base_url()
+                .map_err(anyhow::Error::msg)?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for ConfirmationOutbox {
+    fn name(&self) -> &'static str {
+        "confirmation_outbox"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        let summary = relay_confirmation_emails(
+            &self.pool,
+            &self.email_client,
+            &self.templates,
+            &self.token_signer,
+            &self.pii_cipher,
+            &self.action_base_url,
+        )
+        .await?;
+        if summary.delivered > 0 || summary.failed > 0 {
+            tracing::info!(
+                delivered = summary.delivered,
+                failed = summary.failed,
+                "Relayed confirmation emails from the outbox"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RelaySummary {
+    pub delivered: u64,
+    pub failed: u64,
+}
+
+/// Enqueues the confirmation email carrying `subscription_token`, to be delivered once
+/// `transaction` is committed. Returns the id of the outbox entry.
+#[tracing::instrument(name = "Enqueue a confirmation email", skip_all)]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Uuid, sqlx::Error> {
+    enqueue(transaction, subscription_token, "confirmation").await
+}
+
+/// Like `enqueue_confirmation_email`, for a reminder to confirm with `subscription_token`.
+#[tracing::instrument(name = "Enqueue a confirmation reminder", skip_all)]
+pub async fn enqueue_confirmation_reminder(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Uuid, sqlx::Error> {
+    enqueue(transaction, subscription_token, "reminder").await
+}
+
+async fn enqueue(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+    kind: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let outbox_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_outbox
+            (id, subscription_token, kind, enqueued_at, next_attempt_at)
+        VALUES ($1, $2, $3, now(), now())
+        "#,
+        outbox_id,
+        subscription_token,
+        kind
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(outbox_id)
+}
+
+/// Delivers one confirmation email from the outbox, right after it was enqueued. `false` if
+/// it wasn't delivered, in which case the `confirmation_outbox` job tries again later.
+#[tracing::instrument(name = "Deliver a confirmation email", skip_all, fields(%outbox_id))]
+pub async fn deliver_confirmation_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    outbox_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let outcome = deliver_next(
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        Some(outbox_id),
+    )
+    .await?;
+    Ok(outcome == Some(true))
+}
+
+/// Delivers every confirmation email of the outbox that is due.
+#[tracing::instrument(name = "Relay confirmation emails", skip_all)]
+pub async fn relay_confirmation_emails(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+) -> Result<RelaySummary, anyhow::Error> {
+    let mut summary = RelaySummary {
+        delivered: 0,
+        failed: 0,
+    };
+    // Failed emails are pushed back past now: each entry comes up at most once per run.
+    while let Some(delivered) = deliver_next(
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        None,
+    )
+    .await?
+    {
+        if delivered {
+            summary.delivered += 1;
+        } else {
+            summary.failed += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Sends the next due email of the outbox, or `outbox_id` if it is still due, and records
+/// the outcome: `None` if there was nothing to send. The entry stays locked while it is
+/// sent, so that the request and the job (or two instances) don't both send it; if we
+/// crash before recording that it was delivered, it is sent again.
+async fn deliver_next(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    outbox_id: Option<Uuid>,
+) -> Result<Option<bool>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let Some(entry) = sqlx::query!(
+        r#"
+        SELECT o.id, o.subscription_token, o.kind, o.attempts, s.email, s.name, s.language,
+            n.name AS newsletter
+        FROM confirmation_email_outbox o
+        JOIN subscription_tokens t ON t.subscription_token = o.subscription_token
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE o.delivered_at IS NULL
+          AND o.next_attempt_at <= now()
+          AND ($1::uuid IS NULL OR o.id = $1)
+        ORDER BY o.next_attempt_at
+        LIMIT 1
+        FOR UPDATE OF o SKIP LOCKED
+        "#,
+        outbox_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch a confirmation email from the outbox.")?
+    else {
+        return Ok(None);
+    };
+
+    let sent = async {
+        let email = pii_cipher
+            .decrypt(&entry.email)
+            .and_then(|email| SubscriberEmail::parse(email).map_err(anyhow::Error::msg))
+            .context("The stored email of the subscriber is invalid.")?;
+        if entry.kind == "reminder" {
+            return send_reminder_email(
+                email_client,
+                &email,
+                action_base_url.as_ref(),
+                &token_signer.sign(TokenPurpose::Subscription, &entry.subscription_token),
+            )
+            .await
+            .context("Failed to send a confirmation reminder.");
+        }
+        let name = pii_cipher
+            .decrypt(&entry.name)
+            .and_then(|name| SubscriberName::parse(name).map_err(anyhow::Error::msg))
+            .context("The stored name of the subscriber is invalid.")?;
+        send_confirmation_email(
+            email_client,
+            templates,
+            token_signer,
+            &entry.newsletter,
+            NewSubscriber {
+                email,
+                name,
+                language: entry
+                    .language
+                    .as_deref()
+                    .and_then(|l| Language::parse(l).ok()),
+            },
+            action_base_url.as_ref(),
+            &entry.subscription_token,
+        )
+        .await
+    }
+    .await;
+
+    let delivered = match sent {
+        Ok(()) => {
+            sqlx::query!(
+                r#"
+                UPDATE confirmation_email_outbox
+                SET delivered_at = now(), attempts = attempts + 1, last_error = NULL
+                WHERE id = $1
+                "#,
+                entry.id
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to mark a confirmation email as delivered.")?;
+            true
+        }
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                outbox_id = %entry.id,
+                attempts = entry.attempts + 1,
+                "Failed to deliver a confirmation email, it will be retried"
+            );
+            sqlx::query!(
+                r#"
+                UPDATE confirmation_email_outbox
+                SET attempts = attempts + 1, last_error = $2, next_attempt_at = $3
+                WHERE id = $1
+                "#,
+                entry.id,
+                format!("{error:#}"),
+                Utc::now() + retry_delay(entry.attempts + 1)
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to record a failed confirmation email.")?;
+            false
+        }
+    };
+    database::commit(transaction)
+        .await
+        .context("Failed to commit SQL transaction to deliver a confirmation email.")?;
+    Ok(Some(delivered))
+}
+
+/// How long to wait after the `attempts`-th failed attempt at an email.
+fn retry_delay(attempts: i32) -> Duration {
+    let doublings = attempts.clamp(1, 16) - 1;
+    (INITIAL_RETRY_DELAY * 2i32.pow(doublings as u32)).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_RETRY_DELAY, retry_delay};
+    use chrono::Duration;
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        assert_eq!(retry_delay(1), Duration::minutes(1));
+        assert_eq!(retry_delay(2), Duration::minutes(2));
+        assert_eq!(retry_delay(4), Duration::minutes(8));
+        assert_eq!(retry_delay(7), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(i32::MAX), MAX_RETRY_DELAY);
+    }
+}
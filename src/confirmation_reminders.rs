@@ -0,0 +1,238 @@
+//! Reminders to confirm, for subscribers still pending a while after they signed up.
+//!
+//! A reminder is written to the confirmation outbox in the same transaction that marks the
+//! subscriber as reminded, then delivered like a confirmation email: right away, and by the
+//! `confirmation_outbox` job if that failed.
+use crate::configuration::{ConfirmationReminderSettings, Settings};
+use crate::confirmation_outbox::{deliver_confirmation_email, enqueue_confirmation_reminder};
+use crate::database;
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::{EmailClient, EmailError, MessageCategory};
+use crate::pii::PiiCipher;
+use crate::scheduler::Job;
+use crate::signed_tokens::TokenSigner;
+use crate::templates::EmailTemplates;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+struct PendingSubscriber {
+    id: Uuid,
+    email: String,
+    subscription_token: String,
+}
+
+/// Reminds pending subscribers to confirm their subscription, as a scheduled job.
+pub struct ConfirmationReminders {
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    templates: EmailTemplates,
+    token_signer: TokenSigner,
+    pii_cipher: PiiCipher,
+    action_base_url: ActionBaseUrl,
+    settings: ConfirmationReminderSettings,
+}
+
+impl ConfirmationReminders {
+    /// `None` if reminders are disabled in the configuration.
+    pub fn from_configuration(
+        configuration: &Settings,
+        pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let Some(settings) = configuration.confirmation_reminders.clone() else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            pool,
+            email_client,
+            templates: EmailTemplates::load(&configuration.application.templates_dir)?,
+            token_signer: configuration
+                .signing
+                .token_signer()
+                .map_err(anyhow::Error::msg)?,
+            pii_cipher: configuration.pii_cipher().map_err(anyhow::Error::msg)?,
+            action_base_url: configuration
+                .action_base_url()
+                .map_err(anyhow::Error::msg)?,
+            settings,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for ConfirmationReminders {
+    fn name(&self) -> &'static str {
+        "confirmation_reminders"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        send_due_reminders(
+            &self.pool,
+            &self.email_client,
+            &self.templates,
+            &self.token_signer,
+            &self.pii_cipher,
+            &self.action_base_url,
+            &self.settings,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Remind every subscriber that has been pending for longer than the configured delay and
+/// hasn't been reminded yet. Returns the number of reminders enqueued: those that couldn't
+/// be delivered right away are left to the `confirmation_outbox` job.
+#[tracing::instrument(name = "Send confirmation reminders", skip_all)]
+pub async fn send_due_reminders(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    settings: &ConfirmationReminderSettings,
+) -> Result<usize, anyhow::Error> {
+    let suppressed_domains: Vec<String> = settings
+        .suppressed_domains
+        .iter()
+        .map(|d| d.to_lowercase())
+        .collect();
+    let due = get_due_subscribers(pool, Utc::now() - settings.delay())
+        .await
+        .context("Failed to fetch the subscribers due a confirmation reminder.")?;
+
+    let mut enqueued = 0;
+    for subscriber in due {
+        let email = match pii_cipher
+            .decrypt(&subscriber.email)
+            .map_err(|e| e.to_string())
+            .and_then(|email| SubscriberEmail::parse(email).map_err(|e| e.to_string()))
+        {
+            Ok(email) => email,
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Skipping a pending subscriber. Their stored contact details are invalid",
+                );
+                continue;
+            }
+        };
+        // Filtered here rather than in SQL: the stored email may be encrypted.
+        let domain = email.as_ref().rsplit_once('@').map(|(_, domain)| domain);
+        if domain.is_some_and(|domain| suppressed_domains.contains(&domain.to_lowercase())) {
+            continue;
+        }
+        let Some(outbox_id) = enqueue_reminder(pool, &subscriber).await? else {
+            // Another worker got to them first.
+            continue;
+        };
+        enqueued += 1;
+        deliver_confirmation_email(
+            pool,
+            email_client,
+            templates,
+            token_signer,
+            pii_cipher,
+            action_base_url,
+            outbox_id,
+        )
+        .await?;
+    }
+    Ok(enqueued)
+}
+
+#[tracing::instrument(name = "Get subscribers due a confirmation reminder", skip(pool))]
+async fn get_due_subscribers(
+    pool: &PgPool,
+    subscribed_before: chrono::DateTime<Utc>,
+) -> Result<Vec<PendingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingSubscriber,
+        r#"
+        SELECT DISTINCT ON (s.id) s.id, s.email, t.subscription_token
+        FROM subscriptions s
+        JOIN subscription_tokens t ON t.subscriber_id = s.id
+        WHERE s.status = 'pending_confirmation'
+          AND s.confirmation_reminder_sent_at IS NULL
+          AND s.subscribed_at <= $1
+          AND t.expires_at > now()
+        ORDER BY s.id
+        "#,
+        subscribed_before
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Marks the subscriber as reminded and enqueues their reminder, together. `None` if they
+/// had been reminded already.
+async fn enqueue_reminder(
+    pool: &PgPool,
+    subscriber: &PendingSubscriber,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let claimed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET confirmation_reminder_sent_at = $2
+        WHERE id = $1 AND confirmation_reminder_sent_at IS NULL
+        "#,
+        subscriber.id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record a confirmation reminder.")?
+    .rows_affected();
+    if claimed == 0 {
+        return Ok(None);
+    }
+    let outbox_id = enqueue_confirmation_reminder(&mut transaction, &subscriber.subscription_token)
+        .await
+        .context("Failed to enqueue a confirmation reminder.")?;
+    database::commit(transaction)
+        .await
+        .context("Failed to commit SQL transaction to enqueue a confirmation reminder.")?;
+    Ok(Some(outbox_id))
+}
+
+#[tracing::instrument(
+    name = "Send a confirmation reminder",
+    skip(email_client, subscription_token)
+)]
+pub(crate) async fn send_reminder_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), EmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let plain_body = format!(
+        "You signed up for our newsletter but haven't confirmed your subscription yet.\n\
+        Visit {} to confirm it.",
+        confirmation_link
+    );
+    let html_body = format!(
+        "You signed up for our newsletter but haven't confirmed your subscription yet.<br />\
+        Click <a href=\"{}\">here</a> to confirm it.",
+        confirmation_link
+    );
+    email_client
+        .send_email(
+            recipient,
+            "Please confirm your subscription",
+            &html_body,
+            &plain_body,
+            MessageCategory::Transactional,
+        )
+        .await
+}
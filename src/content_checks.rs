@@ -0,0 +1,559 @@
+//! What an issue goes through before it is sent: its HTML is stripped of what isn't safe in
+//! an email (scripts, frames, forms, event handlers, `javascript:` links), its plain-text part
+//! is generated from the HTML when it has none, and its links are checked to be absolute and
+//! to resolve. Along with a spam score, what was found is reported as warnings when the issue
+//! is published, rather than keeping it from going out. So are issues whose text is mostly
+//! that of one already sent, which is more often a mistake than a re-run.
+use crate::configuration::ContentCheckSettings;
+use futures_util::{StreamExt, stream};
+use lol_html::{RewriteStrSettings, element, rewrite_str};
+use reqwest::{StatusCode, Url};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::LazyLock;
+use uuid::Uuid;
+
+// Removed along with what they contain.
+const UNSAFE_ELEMENTS: &str =
+    "script, iframe, frame, frameset, object, embed, applet, base, input, button, select, textarea";
+// Attributes whose value is a URL, and so could be a `javascript:` one.
+const URL_ATTRIBUTES: &[&str] = &[
+    "href",
+    "src",
+    "action",
+    "formaction",
+    "background",
+    "poster",
+];
+// Any base tells relative links from invalid ones.
+static RELATIVE_LINK_BASE: LazyLock<Url> =
+    LazyLock::new(|| Url::parse("https://example.com/").unwrap());
+// The width of the generated plain-text part.
+const TEXT_WIDTH: usize = 78;
+const LINK_CHECK_CONCURRENCY: usize = 8;
+// Each phrase found adds a point to the spam score.
+const SPAM_PHRASES: &[&str] = &[
+    "100% free",
+    "act now",
+    "buy now",
+    "cash bonus",
+    "click here",
+    "congratulations",
+    "double your",
+    "earn money",
+    "free money",
+    "guaranteed",
+    "limited time",
+    "no cost",
+    "order now",
+    "risk-free",
+    "urgent",
+    "winner",
+];
+// Spam filters distrust links whose destination is hidden.
+const LINK_SHORTENERS: &[&str] = &["bit.ly", "goo.gl", "ow.ly", "t.co", "tinyurl.com"];
+// Texts are compared on the runs of this many words they share.
+const SHINGLE_WORDS: usize = 3;
+// The more hashes, the closer a signature's similarity to that of the texts themselves.
+const MINHASH_HASHES: usize = 64;
+// At most this many similar issues are reported, the most similar first.
+const MAX_SIMILAR_ISSUES: usize = 5;
+
+/// Something about an issue that may keep it from reaching inboxes as intended.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct ContentWarning {
+    // `unsafe_html_removed`, `relative_link`, `invalid_link`, `broken_link`, `spam_triggers` or
+    // `similar_content`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// The HTML of an issue, safe to send, and its plain-text part.
+#[derive(Debug)]
+pub struct SanitizedContent {
+    pub html: String,
+    pub text: String,
+    // The absolute http(s) links and images of the HTML, without merge fields: the ones
+    // `ContentChecker` can resolve.
+    pub links: Vec<String>,
+    pub warnings: Vec<ContentWarning>,
+}
+
+/// Strips what isn't safe from `html`, and generates the plain-text part from it when `text`
+/// is blank.
+pub fn sanitize(html: &str, text: &str) -> Result<SanitizedContent, anyhow::Error> {
+    let removed = RefCell::new(BTreeSet::new());
+    let link_targets = RefCell::new(Vec::new());
+    let html = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!(UNSAFE_ELEMENTS, |el| {
+                    removed.borrow_mut().insert(format!("<{}>", el.tag_name()));
+                    el.remove();
+                    Ok(())
+                }),
+                // Forms can't be submitted from most email clients: their content is kept.
+                element!("form", |el| {
+                    removed.borrow_mut().insert("<form>".into());
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+                element!("*", |el| {
+                    let names: Vec<String> = el.attributes().iter().map(|a| a.name()).collect();
+                    for name in names {
+                        if name.starts_with("on") {
+                            removed.borrow_mut().insert(format!("`{name}` attributes"));
+                            el.remove_attribute(&name);
+                        } else if URL_ATTRIBUTES.contains(&name.as_str()) {
+                            let value = el.get_attribute(&name).unwrap_or_default();
+                            if let Some(scheme) = unsafe_scheme(&el.tag_name(), &name, &value) {
+                                removed.borrow_mut().insert(format!("`{scheme}` links"));
+                                el.remove_attribute(&name);
+                            }
+                        }
+                    }
+                    Ok(())
+                }),
+                element!("a[href], img[src]", |el| {
+                    let attribute = if el.tag_name() == "a" { "href" } else { "src" };
+                    if let Some(target) = el.get_attribute(attribute) {
+                        link_targets.borrow_mut().push(target.replace("&amp;", "&"));
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    let mut warnings = Vec::new();
+    let removed = removed.into_inner();
+    if !removed.is_empty() {
+        warnings.push(ContentWarning {
+            code: "unsafe_html_removed",
+            message: format!(
+                "Removed what isn't safe in an email: {}.",
+                removed.into_iter().collect::<Vec<_>>().join(", ")
+            ),
+        });
+    }
+    let mut links = Vec::new();
+    let mut seen = BTreeSet::new();
+    for target in link_targets.into_inner() {
+        if !seen.insert(target.clone()) {
+            continue;
+        }
+        match check_link(&target) {
+            Ok(true) => links.push(target),
+            Ok(false) => {}
+            Err(warning) => warnings.push(warning),
+        }
+    }
+    let text = if text.trim().is_empty() {
+        html2text::config::plain()
+            .no_link_wrapping()
+            .string_from_read(html.as_bytes(), TEXT_WIDTH)?
+    } else {
+        text.to_owned()
+    };
+    Ok(SanitizedContent {
+        html,
+        text,
+        links,
+        warnings,
+    })
+}
+
+/// The scheme of `value` if it can run code, or smuggle a document in. Browsers ignore
+/// whitespace and control characters in a scheme, and so does this.
+fn unsafe_scheme(tag_name: &str, attribute: &str, value: &str) -> Option<&'static str> {
+    let value: String = value
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if value.starts_with("javascript:") {
+        Some("javascript:")
+    } else if value.starts_with("vbscript:") {
+        Some("vbscript:")
+    } else if value.starts_with("data:")
+        // Inline images are fine, if not shown everywhere.
+        && !(tag_name == "img" && attribute == "src" && value.starts_with("data:image/"))
+    {
+        Some("data:")
+    } else {
+        None
+    }
+}
+
+/// Whether `target` should be resolved, or why it won't work in an email. Anchors, merge
+/// fields and links other than http(s) ones, such as `mailto:`, aren't resolved.
+fn check_link(target: &str) -> Result<bool, ContentWarning> {
+    let target = target.trim();
+    if target.starts_with('#') || target.starts_with("{{") {
+        return Ok(false);
+    }
+    match Url::parse(target) {
+        Ok(url) => Ok(matches!(url.scheme(), "http" | "https") && !target.contains("{{")),
+        // Whatever would be a valid link from a page is a relative one.
+        Err(_) if RELATIVE_LINK_BASE.join(target).is_ok() => Err(ContentWarning {
+            code: "relative_link",
+            message: format!(
+                "`{target}` is a relative link: it leads nowhere from an inbox, \
+                use a full URL."
+            ),
+        }),
+        Err(e) => Err(ContentWarning {
+            code: "invalid_link",
+            message: format!("`{target}` is not a valid link: {e}."),
+        }),
+    }
+}
+
+/// How an issue fared in the checks, reported when it is published.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ContentReview {
+    // 0 for an issue without any of the traits of spam; the higher, the more spam filters are
+    // likely to flag it.
+    pub spam_score: f32,
+    // Issues already sent whose text is over the similarity threshold.
+    pub similar_issues: Vec<SimilarIssue>,
+    pub warnings: Vec<ContentWarning>,
+}
+
+/// An issue already sent, to compare new ones with.
+pub struct SentIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text: String,
+}
+
+/// An issue already sent whose text is much like that of the one being checked.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct SimilarIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    // From 0, for texts without a run of words in common, to 1.
+    pub similarity: f32,
+}
+
+/// Resolves links and scores issues for spam, with `content_checks` settings.
+pub struct ContentChecker {
+    // `None` when links aren't resolved.
+    http_client: Option<reqwest::Client>,
+    spam_score_threshold: f32,
+    similarity_threshold: f32,
+}
+
+impl ContentChecker {
+    pub fn new(settings: &ContentCheckSettings) -> Self {
+        let http_client = settings.resolve_links.then(|| {
+            reqwest::Client::builder()
+                .timeout(settings.link_timeout())
+                .build()
+                .unwrap()
+        });
+        Self {
+            http_client,
+            spam_score_threshold: settings.spam_score_threshold,
+            similarity_threshold: settings.similarity_threshold,
+        }
+    }
+
+    /// The warnings of `content`, along with those about links that don't resolve, a spam
+    /// score over the threshold and issues in `sent` that are too similar. `text` is the
+    /// plain-text part as recipients get it.
+    #[tracing::instrument(name = "Review the content of an issue", skip_all)]
+    pub async fn review(
+        &self,
+        title: &str,
+        text: &str,
+        links: &[String],
+        warnings: &[ContentWarning],
+        sent: &[SentIssue],
+    ) -> ContentReview {
+        let mut warnings = warnings.to_vec();
+        if let Some(http_client) = &self.http_client {
+            let broken: Vec<_> = stream::iter(links)
+                .map(|link| resolve(http_client, link))
+                .buffered(LINK_CHECK_CONCURRENCY)
+                .collect()
+                .await;
+            warnings.extend(broken.into_iter().flatten());
+        }
+        let (spam_score, reasons) = spam_score(title, text, links);
+        if spam_score >= self.spam_score_threshold {
+            warnings.push(ContentWarning {
+                code: "spam_triggers",
+                message: format!(
+                    "The issue scores {spam_score:.1} for spam, from {}: spam filters may \
+                    flag it.",
+                    reasons.join(", ")
+                ),
+            });
+        }
+        let similar_issues = self.similar_issues(text, sent);
+        if let Some(most_similar) = similar_issues.first() {
+            warnings.push(ContentWarning {
+                code: "similar_content",
+                message: format!(
+                    "The issue is {:.0}% the same as \"{}\" ({}), already sent: check it \
+                    isn't going out again by mistake.",
+                    most_similar.similarity * 100.0,
+                    most_similar.title,
+                    most_similar.newsletter_issue_id
+                ),
+            });
+        }
+        ContentReview {
+            spam_score,
+            similar_issues,
+            warnings,
+        }
+    }
+
+    /// The issues of `sent` at least as similar to `text` as the threshold, the most similar
+    /// first.
+    fn similar_issues(&self, text: &str, sent: &[SentIssue]) -> Vec<SimilarIssue> {
+        let Some(signature) = minhash(text) else {
+            return Vec::new();
+        };
+        let mut similar: Vec<_> = sent
+            .iter()
+            .filter_map(|issue| {
+                let similarity = similarity(&signature, &minhash(&issue.text)?);
+                (similarity >= self.similarity_threshold).then(|| SimilarIssue {
+                    newsletter_issue_id: issue.newsletter_issue_id,
+                    title: issue.title.clone(),
+                    similarity,
+                })
+            })
+            .collect();
+        similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        similar.truncate(MAX_SIMILAR_ISSUES);
+        similar
+    }
+}
+
+/// The MinHash signature of the runs of `SHINGLE_WORDS` words of `text`, ignoring case and
+/// punctuation, or `None` for a text without words. A text shorter than a run is one.
+fn minhash(text: &str) -> Option<[u64; MINHASH_HASHES]> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let mut signature = [u64::MAX; MINHASH_HASHES];
+    for shingle in words.windows(SHINGLE_WORDS.min(words.len())) {
+        let mut hasher = DefaultHasher::new();
+        shingle.hash(&mut hasher);
+        let hash = hasher.finish();
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(mix(hash ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+        }
+    }
+    Some(signature)
+}
+
+/// An estimate of the share of the runs of words of two texts they have in common.
+fn similarity(a: &[u64; MINHASH_HASHES], b: &[u64; MINHASH_HASHES]) -> f32 {
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    same as f32 / MINHASH_HASHES as f32
+}
+
+// The finalizer of splitmix64: a different hash of a shingle for each seed it's mixed with.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// A warning if `link` can't be fetched. Servers that don't take `HEAD` requests get a `GET`.
+async fn resolve(http_client: &reqwest::Client, link: &str) -> Option<ContentWarning> {
+    let mut response = http_client.head(link).send().await;
+    if let Ok(r) = &response
+        && matches!(
+            r.status(),
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        )
+    {
+        response = http_client.get(link).send().await;
+    }
+    let message = match response {
+        Ok(response)
+            if !response.status().is_client_error() && !response.status().is_server_error() =>
+        {
+            return None;
+        }
+        Ok(response) => format!("`{link}` answered {}.", response.status()),
+        Err(e) if e.is_timeout() => format!("`{link}` did not answer in time."),
+        Err(_) => format!("`{link}` could not be reached."),
+    };
+    tracing::warn!("{message}");
+    Some(ContentWarning {
+        code: "broken_link",
+        message,
+    })
+}
+
+/// A rough take on how spammy an issue looks, with what made it so: trigger phrases, a
+/// title in capitals, shouting, exclamation marks and shortened links.
+pub fn spam_score(title: &str, text: &str, links: &[String]) -> (f32, Vec<String>) {
+    let mut score = 0.0;
+    let mut reasons = Vec::new();
+
+    let content = format!("{title}\n{text}").to_lowercase();
+    let phrases: Vec<_> = SPAM_PHRASES
+        .iter()
+        .filter(|phrase| content.contains(*phrase))
+        .map(|phrase| format!("\"{phrase}\""))
+        .collect();
+    if !phrases.is_empty() {
+        score += phrases.len() as f32;
+        reasons.push(format!("the phrases {}", phrases.join(", ")));
+    }
+
+    let title_letters: Vec<char> = title.chars().filter(|c| c.is_alphabetic()).collect();
+    if title_letters.len() >= 4 && title_letters.iter().all(|c| c.is_uppercase()) {
+        score += 2.0;
+        reasons.push("a title in capitals".into());
+    }
+
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .filter(|word| word.chars().filter(|c| c.is_alphabetic()).count() >= 4)
+        .collect();
+    let shouted = words
+        .iter()
+        .filter(|word| !word.chars().any(|c| c.is_lowercase()))
+        .count();
+    if words.len() >= 10 && shouted * 5 > words.len() {
+        score += 1.5;
+        reasons.push("words in capitals".into());
+    }
+
+    if title.contains('!') || text.contains("!!") || text.matches('!').count() > 3 {
+        score += 1.0;
+        reasons.push("exclamation marks".into());
+    }
+
+    let shortened = links.iter().any(|link| {
+        Url::parse(link)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .is_some_and(|host| LINK_SHORTENERS.contains(&host.as_str()))
+    });
+    if shortened {
+        score += 1.0;
+        reasons.push("shortened links".into());
+    }
+
+    (score, reasons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minhash, sanitize, similarity, spam_score};
+
+    #[test]
+    fn unsafe_html_is_removed_and_the_rest_kept_as_it_was() {
+        let html = "<p onclick=\"steal()\">Hi {{ subscriber.name }}</p>\
+            <script>steal()</script>\
+            <a href=\" JaVa\tscript:steal()\">Read</a>\
+            <iframe src=\"https://example.com\"></iframe>\
+            <form action=\"https://example.com\"><b>Reply</b></form>\
+            <img src=\"data:image/png;base64,AAAA\">";
+
+        let content = sanitize(html, "Hi").unwrap();
+
+        assert_eq!(
+            content.html,
+            "<p>Hi {{ subscriber.name }}</p><a>Read</a><b>Reply</b>\
+            <img src=\"data:image/png;base64,AAAA\">"
+        );
+        assert_eq!(content.warnings.len(), 1);
+        assert_eq!(
+            content.warnings[0].message,
+            "Removed what isn't safe in an email: <form>, <iframe>, <script>, \
+            `javascript:` links, `onclick` attributes."
+        );
+    }
+
+    #[test]
+    fn links_must_be_absolute() {
+        let html = "<a href=\"https://example.com/a?x=1&amp;y=2\">A</a>\
+            <a href=\"/about\">About</a>\
+            <a href=\"#top\">Top</a>\
+            <a href=\"mailto:editor@example.com\">Mail</a>\
+            <a href=\"https://example.com/?e={{ subscriber.email }}\">Mine</a>\
+            <img src=\"logo.png\">";
+
+        let content = sanitize(html, "text").unwrap();
+
+        assert_eq!(content.links, ["https://example.com/a?x=1&y=2"]);
+        let codes: Vec<_> = content.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, ["relative_link", "relative_link"]);
+        assert!(content.warnings[0].message.contains("`/about`"));
+        assert!(content.warnings[1].message.contains("`logo.png`"));
+    }
+
+    #[test]
+    fn the_text_part_is_generated_when_blank() {
+        let html = "<h1>Title</h1><p>Hi {{ subscriber.name }}, \
+            <a href=\"https://example.com\">read on</a>.</p>";
+
+        let generated = sanitize(html, " \n").unwrap().text;
+        let given = sanitize(html, "Hi there").unwrap().text;
+
+        assert!(generated.contains("Title"));
+        assert!(generated.contains("Hi {{ subscriber.name }}"));
+        assert!(generated.contains("https://example.com"));
+        assert_eq!(given, "Hi there");
+    }
+
+    #[test]
+    fn spammy_issues_score_higher() {
+        let (plain, reasons) = spam_score(
+            "This week in Rust",
+            "A few links we liked.",
+            &["https://example.com".into()],
+        );
+        assert_eq!(plain, 0.0);
+        assert!(reasons.is_empty());
+
+        let (spammy, reasons) = spam_score(
+            "WINNER",
+            "Congratulations!!! Click here to claim your prize, act now.",
+            &["https://bit.ly/abc".into()],
+        );
+        assert!(spammy >= 5.0, "{spammy}: {reasons:?}");
+        assert!(reasons.contains(&"a title in capitals".to_owned()));
+        assert!(reasons.contains(&"shortened links".to_owned()));
+    }
+
+    #[test]
+    fn recycled_texts_are_more_similar_than_new_ones() {
+        let sent = minhash(
+            "This week: async traits, the new borrow checker and how to profile a slow \
+            build, along with the crates we liked.",
+        )
+        .unwrap();
+        let recycled = minhash(
+            "THIS WEEK -- async traits, the new borrow checker and how to profile a slow \
+            build, along with the crates we liked. See you next week!",
+        )
+        .unwrap();
+        let new = minhash("A new release is out, with faster incremental builds.").unwrap();
+
+        assert_eq!(similarity(&sent, &sent), 1.0);
+        assert!(similarity(&sent, &recycled) >= 0.8);
+        assert!(similarity(&sent, &new) < 0.2);
+        assert!(minhash(" -- ").is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Lets browser apps served from other origins call the API, e.g. a single-page app signing
+//! readers up with `POST /subscriptions`. Without a `cors` section, no other origin can:
+//! preflight requests are refused and responses carry no CORS headers.
+//!
+//! Requests from an origin that isn't allowed still reach their handler, as they would
+//! without CORS: it is the browser that keeps the response from the page.
+use crate::configuration::CorsSettings;
+use actix_cors::Cors;
+use actix_web::http::{Method, header};
+
+/// Fails on settings `actix-cors` would only reject once the server is running.
+pub fn validate(settings: &CorsSettings) -> Result<(), String> {
+    for origin in &settings.allowed_origins {
+        let url = reqwest::Url::parse(origin)
+            .map_err(|_| format!("{origin} is not a valid CORS origin."))?;
+        if !matches!(url.scheme(), "http" | "https")
+            || url.origin().ascii_serialization() != *origin
+        {
+            return Err(format!(
+                "{origin} is not an origin: use a scheme and a host, such as https://app.example.com."
+            ));
+        }
+    }
+    for method in &settings.allowed_methods {
+        Method::from_bytes(method.as_bytes())
+            .map_err(|_| format!("{method} is not a valid HTTP method."))?;
+    }
+    Ok(())
+}
+
+/// Built by every worker, as `Cors` can't be shared between threads.
+pub fn cors(settings: Option<&CorsSettings>) -> Cors {
+    let Some(settings) = settings else {
+        return Cors::default();
+    };
+    let mut cors = settings
+        .allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_methods(settings.allowed_methods.iter().map(String::as_str))
+        .allowed_headers([header::ACCEPT, header::AUTHORIZATION, header::CONTENT_TYPE])
+        .max_age(settings.max_age_seconds);
+    if settings.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::configuration::CorsSettings;
+
+    fn settings(origin: &str, method: &str) -> CorsSettings {
+        CorsSettings {
+            allowed_origins: vec![origin.into()],
+            allowed_methods: vec![method.into()],
+            allow_credentials: false,
+            max_age_seconds: 3600,
+        }
+    }
+
+    #[test]
+    fn origins_are_a_scheme_a_host_and_an_optional_port() {
+        for origin in ["https://app.example.com", "http://localhost:3000"] {
+            assert!(validate(&settings(origin, "POST")).is_ok(), "{origin}");
+        }
+        for origin in [
+            "*",
+            "app.example.com",
+            "https://app.example.com/",
+            "https://app.example.com/signup",
+            "ftp://app.example.com",
+        ] {
+            assert!(validate(&settings(origin, "POST")).is_err(), "{origin}");
+        }
+    }
+
+    #[test]
+    fn methods_must_be_valid() {
+        assert!(validate(&settings("https://app.example.com", "PUT")).is_ok());
+        assert!(validate(&settings("https://app.example.com", "NOT A METHOD")).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! HMAC-SHA256 signatures for values we hand out and later need to trust again:
+//! tracking links today, and any webhook payload or token that needs signing.
+//!
+//! Every signature is prefixed with the id of the key that produced it, so keys can be
+//! rotated: sign with a new current key while keeping the old ones around for verification.
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+pub struct KeyRing {
+    current_key_id: String,
+    keys: HashMap<String, SecretString>,
+}
+
+impl KeyRing {
+    pub fn new(
+        current_key_id: String,
+        keys: HashMap<String, SecretString>,
+    ) -> Result<Self, String> {
+        if let Some(id) = keys.keys().find(|id| id.is_empty() || id.contains('.')) {
+            return Err(format!("`{}` is not a valid signing key id.", id));
+        }
+        if let Some((id, _)) = keys.iter().find(|(_, key)| key.expose_secret().len() < 32) {
+            return Err(format!(
+                "The signing key `{}` must be at least 32 bytes long.",
+                id
+            ));
+        }
+        if !keys.contains_key(&current_key_id) {
+            return Err(format!(
+                "There is no signing key with id `{}`.",
+                current_key_id
+            ));
+        }
+        Ok(Self {
+            current_key_id,
+            keys,
+        })
+    }
+
+    /// Sign `message` with the current key.
+    /// `purpose` scopes the signature, so that a signature produced for one feature
+    /// can never be replayed against another.
+    pub fn sign(&self, purpose: &str, message: &[u8]) -> String {
+        let key = &self.keys[&self.current_key_id];
+        format!(
+            "{}.{}",
+            self.current_key_id,
+            URL_SAFE_NO_PAD.encode(mac(key, purpose, message).finalize().into_bytes())
+        )
+    }
+
+    /// Check, in constant time, that `signature` was produced by `sign` with any known key.
+    pub fn verify(&self, purpose: &str, message: &[u8], signature: &str) -> bool {
+        let Some((key_id, tag)) = signature.split_once('.') else {
+            return false;
+        };
+        let (Some(key), Ok(tag)) = (self.keys.get(key_id), URL_SAFE_NO_PAD.decode(tag)) else {
+            return false;
+        };
+        mac(key, purpose, message).verify_slice(&tag).is_ok()
+    }
+}
+
+fn mac(key: &SecretString, purpose: &str, message: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(purpose.as_bytes());
+    // The separator keeps ("ab", "c") and ("a", "bc") apart.
+    mac.update(&[0]);
+    mac.update(message);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyRing;
+    use claim::assert_err;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+
+    fn key_ring(current_key_id: &str) -> KeyRing {
+        let keys = HashMap::from([
+            ("old".to_string(), SecretString::from("o".repeat(32))),
+            ("new".to_string(), SecretString::from("n".repeat(32))),
+        ]);
+        KeyRing::new(current_key_id.into(), keys).unwrap()
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let keys = key_ring("new");
+        let signature = keys.sign("test", b"hello");
+        assert!(signature.starts_with("new."));
+        assert!(keys.verify("test", b"hello", &signature));
+    }
+
+    #[test]
+    fn tampered_messages_and_signatures_are_rejected() {
+        let keys = key_ring("new");
+        let signature = keys.sign("test", b"hello");
+        assert!(!keys.verify("test", b"hellO", &signature));
+        assert!(!keys.verify("test", b"hello", &signature.replacen("new.", "old.", 1)));
+        assert!(!keys.verify("test", b"hello", "new.not-a-signature"));
+        assert!(!keys.verify("test", b"hello", ""));
+    }
+
+    #[test]
+    fn signatures_are_scoped_to_their_purpose() {
+        let keys = key_ring("new");
+        let signature = keys.sign("sponsor-click", b"hello");
+        assert!(!keys.verify("sponsor-open", b"hello", &signature));
+    }
+
+    #[test]
+    fn signatures_from_a_rotated_out_key_are_still_accepted() {
+        let signature = key_ring("old").sign("test", b"hello");
+        assert!(key_ring("new").verify("test", b"hello", &signature));
+    }
+
+    #[test]
+    fn signatures_from_unknown_keys_are_rejected() {
+        let signature = key_ring("old").sign("test", b"hello");
+        let keys = KeyRing::new(
+            "new".into(),
+            HashMap::from([("new".to_string(), SecretString::from("n".repeat(32)))]),
+        )
+        .unwrap();
+        assert!(!keys.verify("test", b"hello", &signature));
+    }
+
+    #[test]
+    fn the_current_key_must_exist() {
+        assert_err!(KeyRing::new("missing".into(), HashMap::new()));
+    }
+
+    #[test]
+    fn short_keys_are_rejected() {
+        let keys = HashMap::from([("k".to_string(), SecretString::from("short"))]);
+        assert_err!(KeyRing::new("k".into(), keys));
+    }
+}
@@ -0,0 +1,125 @@
+//! The Postgres connection pools: waiting for the database at startup, watching for
+//! requests queuing up for a connection, and sending read-heavy queries to a replica.
+use crate::email_client::RetryPolicy;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SATURATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// How long reads stay on the primary after the replica could not be reached.
+const REPLICA_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The pool is lazy: this makes the first connection, retrying with backoff so that the
+/// application can be started before Postgres is ready to take connections.
+#[tracing::instrument(name = "Wait for the database", skip_all)]
+pub async fn wait_for_database(pool: &PgPool, retry: &RetryPolicy) -> Result<(), anyhow::Error> {
+    let mut attempt = 1;
+    loop {
+        match pool.acquire().await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < retry.max_attempts => {
+                let backoff = retry.backoff(attempt);
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    attempt,
+                    backoff_milliseconds = backoff.as_millis() as u64,
+                    "Failed to connect to the database, retrying"
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to connect to the database after {attempt} attempt(s).")
+                });
+            }
+        }
+    }
+}
+
+/// Commits `transaction`. Used where a commit failing matters, so that fault injection can
+/// drop it there.
+pub async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    #[cfg(feature = "fault-injection")]
+    let transaction = {
+        let mut transaction = transaction;
+        crate::fault_injection::before_commit(&mut transaction).await?;
+        transaction
+    };
+    transaction.commit().await
+}
+
+/// Logs when every connection of the pool is in use, so that queries wait for one (and
+/// fail past the acquire timeout), and when that is over. Stops once the pool is closed.
+pub fn watch_pool_saturation(pool: PgPool) {
+    tokio::spawn(async move {
+        let max_connections = pool.options().get_max_connections();
+        let mut interval = tokio::time::interval(SATURATION_CHECK_INTERVAL);
+        let mut saturated = false;
+        while !pool.is_closed() {
+            interval.tick().await;
+            let now_saturated = pool.size() >= max_connections && pool.num_idle() == 0;
+            match (saturated, now_saturated) {
+                (false, true) => tracing::warn!(
+                    max_connections,
+                    "The database pool is saturated: queries are waiting for a connection"
+                ),
+                (true, false) => tracing::info!("The database pool is no longer saturated"),
+                _ => {}
+            }
+            saturated = now_saturated;
+        }
+    });
+}
+
+/// Where read-heavy queries go: the replica when one is configured, the primary otherwise.
+/// What is read from the replica can lag a little behind what was just written.
+#[derive(Clone)]
+pub struct ReadPool {
+    primary: PgPool,
+    replica: Option<PgPool>,
+    // Reads go to the primary until then, after the replica could not be reached.
+    replica_down_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ReadPool {
+    pub fn new(primary: PgPool, replica: Option<PgPool>) -> Self {
+        Self {
+            primary,
+            replica,
+            replica_down_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The replica if it takes connections, the primary otherwise: the replica is not
+    /// tried again for `REPLICA_RETRY_INTERVAL` once it failed.
+    pub async fn get(&self) -> &PgPool {
+        let Some(replica) = &self.replica else {
+            return &self.primary;
+        };
+        let down_until = *self.replica_down_until.lock().unwrap();
+        if down_until.is_some_and(|until| Instant::now() < until) {
+            return &self.primary;
+        }
+        match replica.acquire().await {
+            Ok(_) => {
+                if down_until.is_some() {
+                    tracing::info!("The database replica is reachable again");
+                    *self.replica_down_until.lock().unwrap() = None;
+                }
+                replica
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    retry_in_seconds = REPLICA_RETRY_INTERVAL.as_secs(),
+                    "Failed to connect to the database replica, reading from the primary"
+                );
+                *self.replica_down_until.lock().unwrap() =
+                    Some(Instant::now() + REPLICA_RETRY_INTERVAL);
+                &self.primary
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/// The base URL of the links we email out to act on a subscription, e.g. confirmation links.
+/// It can live on its own domain, separate from `base_url`, so that those links can be
+/// pinned: the endpoints they point to reject requests for any other host.
+#[derive(Debug)]
+pub struct ActionBaseUrl {
+    url: String,
+    host: String,
+}
+
+impl ActionBaseUrl {
+    /// `require_https` should be set in production: links sent over plain HTTP
+    /// would leak subscription tokens to anyone on the path.
+    pub fn parse(s: String, require_https: bool) -> Result<ActionBaseUrl, String> {
+        let url = reqwest::Url::parse(&s)
+            .map_err(|_| format!("{} is not a valid action base url.", s))?;
+        match url.scheme() {
+            "https" => {}
+            "http" if !require_https => {}
+            _ => return Err(format!("{} must be an https url.", s)),
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} does not have a host.", s))?
+            .to_lowercase();
+        Ok(Self {
+            url: s.trim_end_matches('/').to_owned(),
+            host,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Whether a request's `Host` (with or without a port) is the action domain.
+    pub fn matches_host(&self, request_host: &str) -> bool {
+        let request_host = match request_host.rsplit_once(':') {
+            // Leave IPv6 literals such as `[::1]` alone.
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => request_host,
+        };
+        request_host.eq_ignore_ascii_case(&self.host)
+    }
+}
+
+impl AsRef<str> for ActionBaseUrl {
+    fn as_ref(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ActionBaseUrl;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn http_urls_are_accepted_outside_of_production() {
+        assert_ok!(ActionBaseUrl::parse("http://127.0.0.1".into(), false));
+    }
+
+    #[test]
+    fn http_urls_are_rejected_in_production() {
+        assert_err!(ActionBaseUrl::parse(
+            "http://links.example.com".into(),
+            true
+        ));
+        assert_ok!(ActionBaseUrl::parse(
+            "https://links.example.com".into(),
+            true
+        ));
+    }
+
+    #[test]
+    fn trailing_slashes_are_dropped() {
+        let url = ActionBaseUrl::parse("https://links.example.com/".into(), true).unwrap();
+        assert_eq!(url.as_ref(), "https://links.example.com");
+    }
+
+    #[test]
+    fn request_hosts_are_matched_with_or_without_a_port() {
+        let url = ActionBaseUrl::parse("https://Links.example.com".into(), true).unwrap();
+        assert!(url.matches_host("links.example.com"));
+        assert!(url.matches_host("LINKS.example.com:8443"));
+        assert!(!url.matches_host("www.example.com"));
+        assert!(!url.matches_host("links.example.com.evil.com"));
+    }
+}
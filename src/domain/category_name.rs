@@ -0,0 +1,48 @@
+#[derive(Debug)]
+pub struct CategoryName(String);
+
+impl CategoryName {
+    /// Category names show up in preference links and publish requests,
+    /// so we keep them to lowercase ASCII letters, digits, `_` and `-`.
+    pub fn parse(s: String) -> Result<CategoryName, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+        if is_empty || is_too_long || has_invalid_characters {
+            Err(format!("{} is not a valid category name.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for CategoryName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::CategoryName;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(CategoryName::parse("".to_string()));
+    }
+
+    #[test]
+    fn names_with_uppercase_or_whitespace_are_rejected() {
+        for name in ["Essays", "product updates"] {
+            assert_err!(CategoryName::parse(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_name_is_parsed_successfully() {
+        assert_ok!(CategoryName::parse("product-updates".to_string()));
+    }
+}
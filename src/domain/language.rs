@@ -0,0 +1,73 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Language(String);
+
+impl Language {
+    /// A BCP 47 tag such as `fr` or `pt-BR`, kept lowercase with `-` between subtags
+    /// (`pt_BR` is accepted too): templates are looked up by it, in a directory of that name.
+    pub fn parse(s: &str) -> Result<Language, String> {
+        let tag = s.trim().to_ascii_lowercase().replace('_', "-");
+        let mut subtags = tag.split('-');
+        let primary = subtags.next().unwrap_or_default();
+        let is_valid = (2..=3).contains(&primary.len())
+            && primary.chars().all(|c| c.is_ascii_lowercase())
+            && subtags.all(|subtag| {
+                (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            && tag.len() <= 35;
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!("{} is not a valid language tag.", s))
+        }
+    }
+
+    /// The tag, then less and less specific ones: `pt-br`, then `pt`.
+    pub fn fallbacks(&self) -> impl Iterator<Item = &str> {
+        let tag = self.0.as_str();
+        tag.match_indices('-')
+            .map(|(end, _)| &tag[..end])
+            .chain(std::iter::once(tag))
+            .rev()
+    }
+}
+
+impl AsRef<str> for Language {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Language;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn tags_are_normalised() {
+        assert_ok_eq!(Language::parse("pt_BR").map(|l| l.0), "pt-br");
+        assert_ok_eq!(Language::parse(" FR ").map(|l| l.0), "fr");
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        for tag in [
+            "",
+            "f",
+            "french-language",
+            "fr-",
+            "fr/../en",
+            "zh-hant-abcdefghi",
+        ] {
+            assert_err!(Language::parse(tag));
+        }
+    }
+
+    #[test]
+    fn fallbacks_go_from_the_most_to_the_least_specific_tag() {
+        let language = Language::parse("zh-hant-tw").unwrap();
+        assert_eq!(
+            language.fallbacks().collect::<Vec<_>>(),
+            vec!["zh-hant-tw", "zh-hant", "zh"]
+        );
+    }
+}
@@ -1,7 +1,27 @@
+mod action_base_url;
+mod category_name;
+mod language;
 mod new_subscriber;
+mod newsletter_slug;
+mod phone_number;
+mod redirect_target;
+mod signup_attributes;
+mod snippet_name;
 mod subscriber_email;
 mod subscriber_name;
+mod tag_name;
+pub mod validation;
 
+pub use action_base_url::ActionBaseUrl;
+pub use category_name::CategoryName;
+pub use language::Language;
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_slug::NewsletterSlug;
+pub use phone_number::PhoneNumber;
+pub use redirect_target::RedirectTarget;
+pub use signup_attributes::{SignupAttributes, SignupField};
+pub use snippet_name::SnippetName;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use tag_name::{Segment, TagName};
+pub use validation::DomainError;
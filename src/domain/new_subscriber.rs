@@ -1,7 +1,11 @@
+use crate::domain::Language;
 use crate::domain::SubscriberEmail;
 use crate::domain::SubscriberName;
 
+#[derive(Clone)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    // What their emails and pages are written in, when there is a translation.
+    pub language: Option<Language>,
 }
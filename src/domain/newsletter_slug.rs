@@ -0,0 +1,48 @@
+#[derive(Debug)]
+pub struct NewsletterSlug(String);
+
+impl NewsletterSlug {
+    /// Slugs are part of the subscribe URL of a newsletter,
+    /// so we keep them to lowercase ASCII letters, digits and `-`.
+    pub fn parse(s: String) -> Result<NewsletterSlug, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if is_empty || is_too_long || has_invalid_characters {
+            Err(format!("{} is not a valid newsletter slug.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for NewsletterSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::NewsletterSlug;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(NewsletterSlug::parse("".to_string()));
+    }
+
+    #[test]
+    fn slugs_that_dont_fit_in_a_url_segment_are_rejected() {
+        for slug in ["Rust Weekly", "rust/weekly", "rust_weekly"] {
+            assert_err!(NewsletterSlug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_slug_is_parsed_successfully() {
+        assert_ok!(NewsletterSlug::parse("rust-weekly".to_string()));
+    }
+}
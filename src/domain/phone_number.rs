@@ -0,0 +1,60 @@
+/// A phone number in E.164 format, e.g. `+14155550123`: what SMS providers expect.
+#[derive(Debug)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    pub fn parse(s: String) -> Result<PhoneNumber, String> {
+        // Spaces, dashes and parentheses are how people write numbers, not part of them.
+        let normalised: String = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '(' | ')'))
+            .collect();
+        let is_valid = normalised.strip_prefix('+').is_some_and(|digits| {
+            (8..=15).contains(&digits.len())
+                && !digits.starts_with('0')
+                && digits.chars().all(|c| c.is_ascii_digit())
+        });
+        if is_valid {
+            Ok(Self(normalised))
+        } else {
+            Err(format!(
+                "{} is not a valid phone number: use the international format, e.g. +14155550123.",
+                s
+            ))
+        }
+    }
+}
+
+impl AsRef<str> for PhoneNumber {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::PhoneNumber;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn international_numbers_are_accepted() {
+        assert_ok!(PhoneNumber::parse("+14155550123".into()));
+    }
+
+    #[test]
+    fn formatting_characters_are_stripped() {
+        let number = PhoneNumber::parse("+1 (415) 555-0123".into()).unwrap();
+        assert_eq!(number.as_ref(), "+14155550123");
+    }
+
+    #[test]
+    fn numbers_without_a_country_code_are_rejected() {
+        assert_err!(PhoneNumber::parse("4155550123".into()));
+        assert_err!(PhoneNumber::parse("+0415555012".into()));
+    }
+
+    #[test]
+    fn numbers_with_letters_are_rejected() {
+        assert_err!(PhoneNumber::parse("+1415CALLNOW".into()));
+    }
+}
@@ -0,0 +1,73 @@
+/// A URL that a new subscriber is sent to once they have confirmed their subscription.
+/// Only hosts that the operator has explicitly allowed are accepted, otherwise
+/// the confirmation page could be abused as an open redirect.
+#[derive(Debug)]
+pub struct RedirectTarget(String);
+
+impl RedirectTarget {
+    pub fn parse(s: String, allowed_hosts: &[String]) -> Result<RedirectTarget, String> {
+        let url =
+            reqwest::Url::parse(&s).map_err(|_| format!("{} is not a valid redirect url.", s))?;
+        let is_http = url.scheme() == "http" || url.scheme() == "https";
+        let is_allowed = url.host_str().is_some_and(|host| {
+            allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        });
+        if is_http && is_allowed {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not an allowed redirect url.", s))
+        }
+    }
+}
+
+impl AsRef<str> for RedirectTarget {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::RedirectTarget;
+    use claim::{assert_err, assert_ok};
+
+    fn allowed_hosts() -> Vec<String> {
+        vec!["example.com".into()]
+    }
+
+    #[test]
+    fn urls_on_an_allowed_host_are_accepted() {
+        let target = "https://example.com/thank-you?ref=newsletter".to_string();
+        assert_ok!(RedirectTarget::parse(target, &allowed_hosts()));
+    }
+
+    #[test]
+    fn hosts_are_compared_case_insensitively() {
+        let target = "https://EXAMPLE.com/thank-you".to_string();
+        assert_ok!(RedirectTarget::parse(target, &allowed_hosts()));
+    }
+
+    #[test]
+    fn urls_on_other_hosts_are_rejected() {
+        for target in [
+            "https://evil.com/thank-you",
+            "https://example.com.evil.com/",
+            "https://evil.com/?next=https://example.com",
+        ] {
+            assert_err!(RedirectTarget::parse(target.into(), &allowed_hosts()));
+        }
+    }
+
+    #[test]
+    fn non_http_urls_are_rejected() {
+        let target = "javascript://example.com/%0Aalert(1)".to_string();
+        assert_err!(RedirectTarget::parse(target, &allowed_hosts()));
+    }
+
+    #[test]
+    fn relative_urls_are_rejected() {
+        assert_err!(RedirectTarget::parse("/thank-you".into(), &allowed_hosts()));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+// Fields the subscribe form always has: extra fields can't reuse their names.
+const RESERVED_FIELD_NAMES: [&str; 4] = ["name", "email", "redirect_to", "invite_code"];
+
+/// An extra field of the subscribe form, configured by the operator.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SignupField {
+    pub name: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+}
+
+fn default_max_length() -> usize {
+    256
+}
+
+impl SignupField {
+    /// Checked once at startup, so that a bad configuration doesn't surface as failed signups.
+    pub fn validate_all(fields: &[SignupField]) -> Result<(), String> {
+        for (i, field) in fields.iter().enumerate() {
+            if field.name.is_empty()
+                || !field
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!(
+                    "{:?} is not a valid signup field name: use letters, digits and underscores.",
+                    field.name
+                ));
+            }
+            if RESERVED_FIELD_NAMES.contains(&field.name.as_str()) {
+                return Err(format!("{} is a built-in signup field.", field.name));
+            }
+            if fields[..i].iter().any(|other| other.name == field.name) {
+                return Err(format!("The signup field {} is defined twice.", field.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A new subscriber's answers to the configured signup fields.
+/// Fields that aren't configured are dropped; blank answers count as missing.
+#[derive(Debug, Default)]
+pub struct SignupAttributes(serde_json::Map<String, serde_json::Value>);
+
+impl SignupAttributes {
+    pub fn parse(
+        submitted: &HashMap<String, String>,
+        fields: &[SignupField],
+    ) -> Result<SignupAttributes, String> {
+        let mut attributes = serde_json::Map::new();
+        for field in fields {
+            match submitted.get(&field.name).map(|value| value.trim()) {
+                Some(value) if !value.is_empty() => {
+                    if value.chars().count() > field.max_length {
+                        return Err(format!(
+                            "{} must be at most {} characters long.",
+                            field.name, field.max_length
+                        ));
+                    }
+                    attributes.insert(field.name.clone(), value.into());
+                }
+                _ if field.required => return Err(format!("{} is required.", field.name)),
+                _ => {}
+            }
+        }
+        Ok(Self(attributes))
+    }
+
+    pub fn into_json(self) -> serde_json::Value {
+        serde_json::Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{SignupAttributes, SignupField};
+    use claim::{assert_err, assert_ok};
+    use std::collections::HashMap;
+
+    fn company(required: bool) -> Vec<SignupField> {
+        vec![SignupField {
+            name: "company".into(),
+            required,
+            max_length: 10,
+        }]
+    }
+
+    fn submitted(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn a_missing_required_field_is_rejected() {
+        assert_err!(SignupAttributes::parse(&submitted(&[]), &company(true)));
+        assert_err!(SignupAttributes::parse(
+            &submitted(&[("company", "  ")]),
+            &company(true)
+        ));
+    }
+
+    #[test]
+    fn a_missing_optional_field_is_accepted() {
+        assert_ok!(SignupAttributes::parse(&submitted(&[]), &company(false)));
+    }
+
+    #[test]
+    fn an_answer_longer_than_the_limit_is_rejected() {
+        assert_err!(SignupAttributes::parse(
+            &submitted(&[("company", "Acme Corporation")]),
+            &company(false)
+        ));
+    }
+
+    #[test]
+    fn unconfigured_fields_are_dropped() {
+        let attributes = SignupAttributes::parse(
+            &submitted(&[("company", "Acme"), ("submit", "Sign up")]),
+            &company(false),
+        )
+        .unwrap();
+        assert_eq!(
+            attributes.into_json(),
+            serde_json::json!({"company": "Acme"})
+        );
+    }
+
+    #[test]
+    fn built_in_field_names_cannot_be_configured() {
+        let fields = vec![SignupField {
+            name: "email".into(),
+            required: false,
+            max_length: 10,
+        }];
+        assert_err!(SignupField::validate_all(&fields));
+    }
+}
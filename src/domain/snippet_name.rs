@@ -0,0 +1,58 @@
+#[derive(Debug)]
+pub struct SnippetName(String);
+
+impl SnippetName {
+    /// Snippet names are referenced as `{{> name }}` inside issue bodies,
+    /// so we keep them to lowercase ASCII letters, digits, `_` and `-`.
+    pub fn parse(s: String) -> Result<SnippetName, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+        if is_empty || is_too_long || has_invalid_characters {
+            Err(format!("{} is not a valid snippet name.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for SnippetName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SnippetName;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(SnippetName::parse("".to_string()));
+    }
+
+    #[test]
+    fn a_64_character_long_name_is_valid() {
+        assert_ok!(SnippetName::parse("a".repeat(64)));
+    }
+
+    #[test]
+    fn a_name_longer_than_64_characters_is_rejected() {
+        assert_err!(SnippetName::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn names_with_uppercase_or_whitespace_are_rejected() {
+        for name in ["Footer", "sponsor block", "bio}}"] {
+            assert_err!(SnippetName::parse(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_name_is_parsed_successfully() {
+        assert_ok!(SnippetName::parse("footer_blurb-2".to_string()));
+    }
+}
@@ -1,14 +1,26 @@
-use validator::ValidateEmail;
+use crate::domain::validation::{DomainError, EmailRules};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if s.validate_email() {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+    pub fn parse(s: String) -> Result<SubscriberEmail, DomainError> {
+        Self::parse_with(s, &EmailRules::default())
+    }
+
+    pub fn parse_with(s: String, rules: &EmailRules) -> Result<SubscriberEmail, DomainError> {
+        rules.validate(&s)?;
+        Ok(Self(s))
+    }
+
+    /// `u***@example.com`: enough to recognise who it is, not to write to them.
+    pub fn masked(&self) -> String {
+        match self.0.split_once('@') {
+            Some((local_part, domain)) => {
+                let first: String = local_part.chars().take(1).collect();
+                format!("{first}***@{domain}")
+            }
+            None => "***".into(),
         }
     }
 }
@@ -34,6 +46,12 @@ mod tests {
     use fake::faker::internet::en::SafeEmail;
     use quickcheck::Arbitrary;
 
+    #[test]
+    fn masked_emails_keep_the_first_character_and_the_domain() {
+        let email = SubscriberEmail::parse("ursula@domain.com".to_string()).unwrap();
+        assert_eq!(email.masked(), "u***@domain.com");
+    }
+
     #[test]
     fn empty_string_is_rejected() {
         let email = "".to_string();
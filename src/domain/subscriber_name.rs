@@ -1,19 +1,16 @@
-use unicode_segmentation::UnicodeSegmentation;
+use crate::domain::validation::{DomainError, NameRules};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberName(String);
 
 impl SubscriberName {
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
-        let is_empty_or_whitespace = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s.chars().any(|g| forbidden_characters.contains(&g));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
-        } else {
-            Ok(Self(s))
-        }
+    pub fn parse(s: String) -> Result<SubscriberName, DomainError> {
+        Self::parse_with(s, &NameRules::default())
+    }
+
+    pub fn parse_with(s: String, rules: &NameRules) -> Result<SubscriberName, DomainError> {
+        rules.validate(&s)?;
+        Ok(Self(s))
     }
 }
 
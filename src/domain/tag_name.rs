@@ -0,0 +1,126 @@
+/// The most tags a subscriber can pick for themselves when signing up.
+const MAX_SIGNUP_TAGS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagName(String);
+
+impl TagName {
+    /// Tags end up in publish requests and admin URLs, so we keep them to
+    /// lowercase ASCII letters, digits, `_` and `-`, like category names.
+    pub fn parse(s: String) -> Result<TagName, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+        if is_empty || is_too_long || has_invalid_characters {
+            Err(format!("{} is not a valid tag.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+
+    /// A comma-separated list, as signup forms send it, e.g. `rust,beginner`.
+    /// Blank entries and duplicates are dropped.
+    pub fn parse_list(s: &str) -> Result<Vec<TagName>, String> {
+        let mut tags = s
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| TagName::parse(tag.to_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        tags.sort();
+        tags.dedup();
+        if tags.len() > MAX_SIGNUP_TAGS {
+            return Err(format!("Pick at most {} tags.", MAX_SIGNUP_TAGS));
+        }
+        Ok(tags)
+    }
+}
+
+impl AsRef<str> for TagName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Subscribers with any, or all, of a set of tags.
+#[derive(Debug)]
+pub struct Segment {
+    tags: Vec<TagName>,
+    match_all: bool,
+}
+
+impl Segment {
+    pub fn new(mut tags: Vec<TagName>, match_all: bool) -> Result<Segment, String> {
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            return Err("A segment needs at least one tag.".into());
+        }
+        Ok(Self { tags, match_all })
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.iter().map(|tag| tag.0.clone()).collect()
+    }
+
+    /// How many of the tags a subscriber needs to be in the segment.
+    pub fn required_matches(&self) -> i64 {
+        if self.match_all {
+            self.tags.len() as i64
+        } else {
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{Segment, TagName};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(TagName::parse("".to_string()));
+    }
+
+    #[test]
+    fn tags_with_uppercase_or_whitespace_are_rejected() {
+        for tag in ["Rust", "early adopter"] {
+            assert_err!(TagName::parse(tag.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_tag_is_parsed_successfully() {
+        assert_ok!(TagName::parse("early-adopter".to_string()));
+    }
+
+    #[test]
+    fn lists_are_trimmed_and_deduplicated() {
+        let tags = TagName::parse_list(" rust, beginner,,rust ").unwrap();
+        let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+        assert_eq!(tags, vec!["beginner", "rust"]);
+    }
+
+    #[test]
+    fn segments_need_a_tag() {
+        assert_err!(Segment::new(vec![], false));
+    }
+
+    #[test]
+    fn matching_all_tags_requires_every_distinct_one() {
+        let tags = TagName::parse_list("rust,go,rust").unwrap();
+        assert_eq!(
+            Segment::new(tags.clone(), true).unwrap().required_matches(),
+            2
+        );
+        assert_eq!(Segment::new(tags, false).unwrap().required_matches(), 1);
+    }
+
+    #[test]
+    fn one_invalid_tag_rejects_the_list() {
+        assert_err!(TagName::parse_list("rust,Not Valid"));
+    }
+}
@@ -0,0 +1,3 @@
+//! The validation rules live in the `zero2prod-validation` crate, which has no dependency on
+//! tokio or sqlx so that it can also be compiled to wasm32 for client-side validation.
+pub use zero2prod_validation::*;
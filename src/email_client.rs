@@ -1,12 +1,339 @@
 use crate::domain::SubscriberEmail;
-use reqwest::Client;
+use crate::issue_delivery::DeliveryQueueSettings;
+use crate::metrics::{InFlightSend, record_email_send_failure};
+use crate::send_budget::SendBudgetSettings;
+use crate::telemetry::{outbound_request_span, trace_context_headers};
+use rand::Rng;
+use reqwest::{Client, StatusCode};
 use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::Instrument;
 
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: SecretString,
+    routes: HashMap<MessageCategory, EmailRoute>,
+    retry_policy: RetryPolicy,
+    circuit_breaker: Option<CircuitBreaker>,
+    delivery: DeliverySettings,
+    lanes: Lanes,
+    throttle: Option<Throttle>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error("The email provider is short-circuited after failing repeatedly.")]
+    CircuitOpen,
+    #[error("Failed to send the email.")]
+    RequestError(#[from] reqwest::Error),
+}
+
+/// How `send_email` retries transient failures: 5xx and 429 responses, timeouts
+/// and connection errors. Other failures, e.g. a rejected recipient, are final.
+///
+/// Also how the first database connection is retried at startup.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RetryPolicy {
+    // Including the first one: 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff_milliseconds: u64,
+    pub max_backoff_milliseconds: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_milliseconds: 100,
+            max_backoff_milliseconds: 2000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Doubles with every retry, up to the maximum, with up to half of it left to chance
+    /// so that requests that failed together don't all retry together.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff_milliseconds
+            .saturating_mul(1 << (retry - 1).min(16))
+            .min(self.max_backoff_milliseconds);
+        let jitter = rand::thread_rng().gen_range(0..=ceiling / 2);
+        Duration::from_millis(ceiling - jitter)
+    }
+}
+
+/// How issues are sent out: `concurrency` emails at a time, in batches of `batch_size`.
+/// Recipients whose send failed, or who are over the `send_budget`, are queued for the
+/// `issue_delivery` job, which leases and retries them as `queue` says.
+///
+/// Issues being sent at the same time share the `concurrency`. Transactional emails go
+/// through a lane of their own, up to `transactional_concurrency` at a time, so that they
+/// don't wait for the sends of an issue.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct DeliverySettings {
+    pub concurrency: usize,
+    #[serde(default = "default_transactional_concurrency")]
+    pub transactional_concurrency: usize,
+    pub batch_size: usize,
+    // The provider's rate limit, which every email counts against. No limit if unset.
+    #[serde(default)]
+    pub max_messages_per_second: Option<u32>,
+    // The provider's hourly quota of issue deliveries. No quota if unset.
+    #[serde(default)]
+    pub send_budget: Option<SendBudgetSettings>,
+    #[serde(default)]
+    pub queue: DeliveryQueueSettings,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        Self {
+            concurrency: 10,
+            transactional_concurrency: default_transactional_concurrency(),
+            batch_size: 100,
+            max_messages_per_second: None,
+            send_budget: None,
+            queue: DeliveryQueueSettings::default(),
+        }
+    }
+}
+
+fn default_transactional_concurrency() -> usize {
+    5
+}
+
+/// Which sends an email waits behind. Transactional emails, e.g. confirmations and password
+/// resets, are sent ahead of bulk ones, i.e. issues.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lane {
+    Transactional,
+    Bulk,
+}
+
+impl MessageCategory {
+    pub fn lane(self) -> Lane {
+        match self {
+            MessageCategory::Transactional => Lane::Transactional,
+            MessageCategory::Broadcast | MessageCategory::ReEngagement => Lane::Bulk,
+        }
+    }
+}
+
+/// How many sends of each lane may be in flight at once.
+struct Lanes {
+    transactional: Semaphore,
+    bulk: Semaphore,
+}
+
+impl Lanes {
+    fn new(delivery: &DeliverySettings) -> Self {
+        Self {
+            transactional: Semaphore::new(delivery.transactional_concurrency.max(1)),
+            bulk: Semaphore::new(delivery.concurrency.max(1)),
+        }
+    }
+
+    async fn enter(&self, lane: Lane) -> SemaphorePermit<'_> {
+        let semaphore = match lane {
+            Lane::Transactional => &self.transactional,
+            Lane::Bulk => &self.bulk,
+        };
+        semaphore
+            .acquire()
+            .await
+            .expect("The semaphores of the lanes are never closed.")
+    }
+}
+
+/// Spaces sends evenly, at most one per `interval`.
+struct Throttle {
+    interval: Duration,
+    slots: Mutex<Slots>,
+}
+
+struct Slots {
+    next: Instant,
+    next_transactional: Instant,
+}
+
+impl Throttle {
+    fn new(messages_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / messages_per_second.max(1),
+            slots: Mutex::new(Slots {
+                next: Instant::now(),
+                next_transactional: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits for the next free slot. Slots are handed out in the order they are asked for,
+    /// except that transactional sends go ahead of the bulk ones and push them back a slot.
+    /// Bulk sends already waiting keep theirs: there are at most `concurrency` of them, so
+    /// the rate goes over by no more than that.
+    async fn wait(&self, lane: Lane) {
+        let slot = self.reserve(lane);
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    fn reserve(&self, lane: Lane) -> Instant {
+        let mut slots = self.slots.lock().unwrap();
+        let now = Instant::now();
+        match lane {
+            Lane::Bulk => {
+                let slot = slots.next.max(now);
+                slots.next = slot + self.interval;
+                slot
+            }
+            Lane::Transactional => {
+                let slot = slots.next_transactional.max(now);
+                slots.next_transactional = slot + self.interval;
+                slots.next = slots.next.max(slot) + self.interval;
+                slot
+            }
+        }
+    }
+}
+
+/// Stops sending to a provider for a while after it failed too many times in a row,
+/// so that requests fail fast instead of each waiting through its retries.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct CircuitBreakerSettings {
+    // Consecutive sends that failed even after their retries.
+    pub failure_threshold: u32,
+    // How long the provider is short-circuited before a trial request is let through.
+    pub open_seconds: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_seconds: 30,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    // Short-circuited no longer: the next request decides whether it closes again.
+    HalfOpen,
+}
+
+struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    // By provider base url: a category routed through a provider of its own
+    // keeps working while another provider is down.
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+impl Circuit {
+    fn state(&self) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if Instant::now() < open_until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Whether a request to `provider` may go out now.
+    fn allow(&self, provider: &str) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(provider.to_string()).or_default();
+        match circuit.state() {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            // One trial request at a time.
+            CircuitState::HalfOpen => !std::mem::replace(&mut circuit.trial_in_flight, true),
+        }
+    }
+
+    fn record_success(&self, provider: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(provider.to_string()).or_default();
+        if circuit.open_until.is_some() {
+            tracing::info!(
+                provider,
+                circuit_breaker.state = ?CircuitState::Closed,
+                "The email provider recovered"
+            );
+        }
+        *circuit = Circuit::default();
+    }
+
+    fn record_failure(&self, provider: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(provider.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        circuit.trial_in_flight = false;
+        // A failed trial request opens the circuit again straight away.
+        if circuit.open_until.is_some()
+            || circuit.consecutive_failures >= self.settings.failure_threshold
+        {
+            circuit.open_until =
+                Some(Instant::now() + Duration::from_secs(self.settings.open_seconds));
+            tracing::warn!(
+                provider,
+                circuit_breaker.state = ?CircuitState::Open,
+                consecutive_failures = circuit.consecutive_failures,
+                open_seconds = self.settings.open_seconds,
+                "Short-circuiting the email provider"
+            );
+        }
+    }
+
+    fn state(&self, provider: &str) -> CircuitState {
+        self.circuits
+            .lock()
+            .unwrap()
+            .get(provider)
+            .map_or(CircuitState::Closed, Circuit::state)
+    }
+}
+
+/// The kind of traffic an email belongs to.
+/// Each category can be routed through its own provider server or message stream,
+/// so that a bad broadcast can't hurt the deliverability of confirmation emails.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageCategory {
+    Transactional,
+    Broadcast,
+    ReEngagement,
+}
+
+/// Overrides applied to every email of a given `MessageCategory`.
+/// Unset fields fall back to the client-wide defaults.
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct EmailRoute {
+    pub base_url: Option<String>,
+    pub authorization_token: Option<SecretString>,
+    pub message_stream: Option<String>,
 }
 
 #[derive(serde::Serialize)]
@@ -18,6 +345,15 @@ struct SendEmailRequest<'a> {
     html_body: &'a str,
     text_body: &'a str,
     // converted to &str to avoid copying the strings into the heap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_stream: Option<&'a str>,
+}
+
+/// What the provider answers a send with.
+#[derive(serde::Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
 }
 
 impl EmailClient {
@@ -34,44 +370,287 @@ impl EmailClient {
             base_url,
             sender,
             authorization_token,
+            routes: HashMap::new(),
+            retry_policy: RetryPolicy::no_retries(),
+            circuit_breaker: None,
+            lanes: Lanes::new(&DeliverySettings::default()),
+            delivery: DeliverySettings::default(),
+            throttle: None,
         }
     }
 
+    pub fn with_delivery(mut self, delivery: DeliverySettings) -> Self {
+        self.throttle = delivery.max_messages_per_second.map(Throttle::new);
+        self.lanes = Lanes::new(&delivery);
+        self.delivery = delivery;
+        self
+    }
+
+    pub fn delivery(&self) -> &DeliverySettings {
+        &self.delivery
+    }
+
+    /// The address emails are sent from.
+    pub fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, settings: CircuitBreakerSettings) -> Self {
+        self.circuit_breaker = Some(CircuitBreaker {
+            settings,
+            circuits: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Fails if the provider that transactional emails go through can't be reached.
+    /// Any answer will do, even an error: it means that the provider is up.
+    pub async fn ping(&self) -> Result<(), reqwest::Error> {
+        self.http_client
+            .get(self.base_url_for(MessageCategory::Transactional))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// The state of the circuit of the provider that `category` is sent through.
+    pub fn circuit_state(&self, category: MessageCategory) -> CircuitState {
+        self.circuit_breaker
+            .as_ref()
+            .map_or(CircuitState::Closed, |breaker| {
+                breaker.state(self.base_url_for(category))
+            })
+    }
+
+    fn base_url_for(&self, category: MessageCategory) -> &str {
+        self.routes
+            .get(&category)
+            .and_then(|r| r.base_url.as_deref())
+            .unwrap_or(&self.base_url)
+    }
+
+    /// Route each `MessageCategory` according to `routes`.
+    /// Categories without a route use the client-wide defaults.
+    pub fn with_routes(mut self, routes: HashMap<MessageCategory, EmailRoute>) -> Self {
+        self.routes = routes;
+        self
+    }
+
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
+        category: MessageCategory,
+    ) -> Result<(), EmailError> {
+        self.send_email_with_id(recipient, subject, html_content, text_content, category)
+            .await?;
+        Ok(())
+    }
+
+    /// Also returns the id the provider gave the message, which its webhooks refer to it by;
+    /// `None` if its answer doesn't carry one.
+    pub async fn send_email_with_id(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        category: MessageCategory,
+    ) -> Result<Option<String>, EmailError> {
+        let route = self.routes.get(&category);
+        let base_url = self.base_url_for(category);
+        let lane = category.lane();
+        let started_at = Instant::now();
+        let _permit = self.lanes.enter(lane).await;
+        if let Some(throttle) = &self.throttle {
+            throttle.wait(lane).await;
+        }
+        let _in_flight = InFlightSend::new(lane, started_at.elapsed());
+        #[cfg(feature = "fault-injection")]
+        if let Some(delay) = crate::fault_injection::email_delay() {
+            tokio::time::sleep(delay).await;
+        }
+        if let Some(breaker) = &self.circuit_breaker
+            && !breaker.allow(base_url)
+        {
+            record_email_send_failure(category);
+            return Err(EmailError::CircuitOpen);
+        }
+        let authorization_token = route
+            .and_then(|r| r.authorization_token.as_ref())
+            .unwrap_or(&self.authorization_token);
+
+        let url = format!("{}/email", base_url);
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
-            subject: subject,
+            subject,
             html_body: html_content,
             text_body: text_content,
+            message_stream: route.and_then(|r| r.message_stream.as_deref()),
         };
 
-        self.http_client
-            .post(&url)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?; // if the server returns a 500 or 400, the request will fail and return an error
+        let mut attempt = 1;
+        let outcome = loop {
+            let span = outbound_request_span("POST", &url);
+            let outcome = self
+                .http_client
+                .post(&url)
+                .header(
+                    "X-Postmark-Server-Token",
+                    authorization_token.expose_secret(),
+                )
+                .headers(trace_context_headers(&span))
+                .json(&request_body)
+                .send()
+                .instrument(span.clone())
+                .await
+                .inspect(|response| {
+                    span.record("http.response.status_code", response.status().as_u16());
+                })
+                // if the server returns a 500 or 400, the request will fail and return an error
+                .and_then(|response| response.error_for_status());
+            match outcome {
+                Err(e) if is_transient(&e) && attempt < self.retry_policy.max_attempts => {
+                    let backoff = self.retry_policy.backoff(attempt);
+                    tracing::warn!(
+                        error.message = %e,
+                        attempt,
+                        backoff_milliseconds = backoff.as_millis() as u64,
+                        "Retrying a failed email request"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                outcome => break outcome,
+            }
+        };
 
-        Ok(())
+        if let Some(breaker) = &self.circuit_breaker {
+            match &outcome {
+                Err(e) if is_transient(e) => breaker.record_failure(base_url),
+                // Even a rejected email means the provider is up.
+                _ => breaker.record_success(base_url),
+            }
+        }
+        if outcome.is_err() {
+            record_email_send_failure(category);
+        }
+        let response = outcome?;
+        Ok(response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id))
     }
 }
 
+/// Where emails go: the provider, or a recorder when nothing should actually be sent.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Returns the provider's id for the message, if there is one.
+    async fn send_email_with_id(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        category: MessageCategory,
+    ) -> Result<Option<String>, EmailError>;
+}
+
+#[async_trait::async_trait]
+impl EmailSender for EmailClient {
+    async fn send_email_with_id(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        category: MessageCategory,
+    ) -> Result<Option<String>, EmailError> {
+        EmailClient::send_email_with_id(
+            self,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            category,
+        )
+        .await
+    }
+}
+
+/// An email that was recorded rather than sent.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct RecordedEmail {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Sends nothing: counts the emails it is given and keeps the first one, for dry runs.
+#[derive(Default)]
+pub struct EmailRecorder {
+    recorded: Mutex<(usize, Option<RecordedEmail>)>,
+}
+
+impl EmailRecorder {
+    pub fn count(&self) -> usize {
+        self.recorded.lock().unwrap().0
+    }
+
+    pub fn first(&self) -> Option<RecordedEmail> {
+        self.recorded.lock().unwrap().1.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for EmailRecorder {
+    async fn send_email_with_id(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        _category: MessageCategory,
+    ) -> Result<Option<String>, EmailError> {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.0 += 1;
+        recorded.1.get_or_insert_with(|| RecordedEmail {
+            to: recipient.as_ref().to_owned(),
+            subject: subject.to_owned(),
+            html: html_content.to_owned(),
+            text: text_content.to_owned(),
+        });
+        Ok(None)
+    }
+}
+
+/// Failures that another attempt might not run into.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout()
+        || e.is_connect()
+        || e.status().is_some_and(|status| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{
+        CircuitBreakerSettings, CircuitState, DeliverySettings, EmailClient, EmailError,
+        EmailRecorder, EmailRoute, EmailSender, Lane, MessageCategory, RetryPolicy, Throttle,
+    };
     use claim::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -79,7 +658,7 @@ mod tests {
     use secrecy::SecretString;
     use wiremock::Request;
     use wiremock::matchers::any;
-    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::matchers::{body_partial_json, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     struct SendEmailBodyMatcher;
@@ -146,7 +725,13 @@ mod tests {
 
         // Act
         let _ = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Transactional,
+            )
             .await;
 
         // Assert
@@ -164,12 +749,52 @@ mod tests {
             .await;
         // Act
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Transactional,
+            )
             .await;
         // Assert
         assert_ok!(outcome);
     }
 
+    #[tokio::test]
+    async fn send_email_with_id_returns_the_id_the_provider_gave_the_message() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+                "Message": "OK",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let message_id = email_client
+            .send_email_with_id(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Broadcast,
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
     #[tokio::test]
     async fn send_email_fails_if_the_server_returns_500() {
         // Arrange
@@ -183,7 +808,13 @@ mod tests {
             .await;
         // Act
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Transactional,
+            )
             .await;
         // Assert
         assert_err!(outcome);
@@ -207,9 +838,370 @@ mod tests {
 
         // Act
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Transactional,
+            )
             .await;
         // Assert
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn send_email_uses_the_route_configured_for_its_category() {
+        // Arrange
+        let default_server = MockServer::start().await;
+        let broadcast_server = MockServer::start().await;
+        let routes = [(
+            MessageCategory::Broadcast,
+            EmailRoute {
+                base_url: Some(broadcast_server.uri()),
+                authorization_token: Some(SecretString::from("broadcast-token")),
+                message_stream: Some("broadcast".into()),
+            },
+        )]
+        .into_iter()
+        .collect();
+        let email_client = email_client(default_server.uri()).with_routes(routes);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&default_server)
+            .await;
+        Mock::given(header("X-Postmark-Server-Token", "broadcast-token"))
+            .and(body_partial_json(
+                serde_json::json!({ "MessageStream": "broadcast" }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&broadcast_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Broadcast,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn categories_without_a_route_use_the_defaults() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let routes = [(
+            MessageCategory::Broadcast,
+            EmailRoute {
+                message_stream: Some("broadcast".into()),
+                ..EmailRoute::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        let email_client = email_client(mock_server.uri()).with_routes(routes);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                MessageCategory::Transactional,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body.get("MessageStream").is_none());
+    }
+
+    /// Retries without waiting around in between.
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff_milliseconds: 1,
+            max_backoff_milliseconds: 1,
+        }
+    }
+
+    async fn send_transactional_email(email_client: &EmailClient) -> Result<(), EmailError> {
+        send_email(email_client, MessageCategory::Transactional).await
+    }
+
+    async fn send_email(
+        email_client: &EmailClient,
+        category: MessageCategory,
+    ) -> Result<(), EmailError> {
+        email_client
+            .send_email(&email(), &subject(), &content(), &content(), category)
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_server_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_retry_policy(retry_policy(3));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = send_transactional_email(&email_client).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_the_last_attempt() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_retry_policy(retry_policy(3));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = send_transactional_email(&email_client).await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_client_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_retry_policy(retry_policy(3));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = send_transactional_email(&email_client).await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_with_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_milliseconds: 100,
+            max_backoff_milliseconds: 1000,
+        };
+        for _ in 0..100 {
+            let first = policy.backoff(1).as_millis();
+            let second = policy.backoff(2).as_millis();
+            let last = policy.backoff(9).as_millis();
+            assert!((50..=100).contains(&first), "{first}");
+            assert!((100..=200).contains(&second), "{second}");
+            assert!((500..=1000).contains(&last), "{last}");
+        }
+    }
+
+    #[test]
+    fn transactional_sends_go_ahead_of_bulk_ones_waiting_for_the_rate_limit() {
+        let throttle = Throttle::new(10);
+        let interval = std::time::Duration::from_millis(100);
+        let bulk: Vec<_> = (0..3).map(|_| throttle.reserve(Lane::Bulk)).collect();
+
+        let transactional = throttle.reserve(Lane::Transactional);
+        let next_bulk = throttle.reserve(Lane::Bulk);
+
+        assert!(transactional < bulk[1]);
+        // Pushed back a slot, so that the rate stays the same.
+        assert_eq!(next_bulk - bulk[2], 2 * interval);
+    }
+
+    #[tokio::test]
+    async fn transactional_emails_do_not_wait_for_the_bulk_lane() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_delivery(DeliverySettings {
+            concurrency: 1,
+            ..DeliverySettings::default()
+        });
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(150)),
+            )
+            .mount(&mock_server)
+            .await;
+        let started_at = std::time::Instant::now();
+
+        // Act
+        let (bulk, transactional) = tokio::join!(
+            async {
+                let _ = tokio::join!(
+                    send_email(&email_client, MessageCategory::Broadcast),
+                    send_email(&email_client, MessageCategory::Broadcast),
+                );
+                started_at.elapsed()
+            },
+            async {
+                send_transactional_email(&email_client).await.unwrap();
+                started_at.elapsed()
+            }
+        );
+
+        // Assert
+        // The bulk lane took one email at a time, the transactional one went out alongside.
+        assert!(bulk >= std::time::Duration::from_millis(300), "{bulk:?}");
+        assert!(
+            transactional < std::time::Duration::from_millis(290),
+            "{transactional:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_circuit_opens_after_repeated_failures() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_circuit_breaker(CircuitBreakerSettings {
+                failure_threshold: 2,
+                open_seconds: 60,
+            });
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            // The third send never reaches the provider.
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        for _ in 0..2 {
+            assert_err!(send_transactional_email(&email_client).await);
+        }
+        let outcome = send_transactional_email(&email_client).await;
+
+        // Assert
+        assert!(matches!(outcome, Err(EmailError::CircuitOpen)));
+        assert_eq!(
+            email_client.circuit_state(MessageCategory::Transactional),
+            CircuitState::Open
+        );
+    }
+
+    #[tokio::test]
+    async fn a_successful_trial_request_closes_the_circuit() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_circuit_breaker(CircuitBreakerSettings {
+                failure_threshold: 1,
+                open_seconds: 0,
+            });
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        assert_err!(send_transactional_email(&email_client).await);
+        assert_eq!(
+            email_client.circuit_state(MessageCategory::Transactional),
+            CircuitState::HalfOpen
+        );
+
+        // Act
+        let outcome = send_transactional_email(&email_client).await;
+
+        // Assert
+        assert_ok!(outcome);
+        assert_eq!(
+            email_client.circuit_state(MessageCategory::Transactional),
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn client_errors_do_not_count_towards_the_circuit() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_circuit_breaker(CircuitBreakerSettings {
+                failure_threshold: 1,
+                open_seconds: 60,
+            });
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        for _ in 0..2 {
+            assert_err!(send_transactional_email(&email_client).await);
+        }
+
+        // Assert
+        assert_eq!(
+            email_client.circuit_state(MessageCategory::Transactional),
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn the_recorder_counts_emails_and_keeps_the_first_one() {
+        // Arrange
+        let recorder = EmailRecorder::default();
+        let first = email();
+
+        // Act
+        for recipient in [first.clone(), email()] {
+            recorder
+                .send_email_with_id(
+                    &recipient,
+                    "Subject",
+                    "<p>Body</p>",
+                    "Body",
+                    MessageCategory::Broadcast,
+                )
+                .await
+                .unwrap();
+        }
+
+        // Assert
+        assert_eq!(recorder.count(), 2);
+        assert_eq!(recorder.first().unwrap().to, first.as_ref());
+    }
 }
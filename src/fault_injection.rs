@@ -0,0 +1,151 @@
+//! `cargo test --features fault-injection`
+//!
+//! Faults that integration tests arm on a running application, to exercise the error paths
+//! of real code rather than of mocks: the Nth database connection dropping before its first
+//! query, a transaction dropping as it commits, and slow email sends. Tests arm them with
+//! `POST /_faults` and read back what hasn't fired yet with `GET /_faults`; code run outside
+//! of a request, like `deliver_queued_issues`, picks them up inside `Faults::scope`.
+//!
+//! Faults only apply to requests of the application they were armed on, so that tests
+//! running side by side in one process don't trip over each other's.
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, web};
+use sqlx::{Executor, PgConnection};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+// Postgres closes a session idle for longer than its timeout: waiting well past it makes
+// sure the connection is gone by the time it is used.
+const CONNECTION_DROP_WAIT: Duration = Duration::from_millis(50);
+
+tokio::task_local! {
+    static FAULTS: Faults;
+}
+
+/// What to break, as posted to `/_faults`. Posting a plan replaces the one armed before:
+/// `{}` disarms everything.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FaultPlan {
+    /// The Nth connection taken from the pool from now on drops before its first query.
+    /// Queries on the pool take one each; a transaction takes one for all of its queries.
+    pub fail_connection: Option<u64>,
+    /// The next transaction committed with `database::commit` drops instead, rolled back.
+    pub drop_commit: bool,
+    /// Every email sent waits this long before going to the provider.
+    pub email_delay_milliseconds: Option<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct Faults(Arc<ArmedFaults>);
+
+#[derive(Default)]
+struct ArmedFaults {
+    // Connections left to take before the one that fails, 0 when disarmed.
+    connections_until_failure: AtomicU64,
+    drop_commit: AtomicBool,
+    email_delay_milliseconds: AtomicU64,
+}
+
+impl Faults {
+    pub fn arm(&self, plan: &FaultPlan) {
+        let faults = &self.0;
+        faults
+            .connections_until_failure
+            .store(plan.fail_connection.unwrap_or(0), Ordering::SeqCst);
+        faults.drop_commit.store(plan.drop_commit, Ordering::SeqCst);
+        faults
+            .email_delay_milliseconds
+            .store(plan.email_delay_milliseconds.unwrap_or(0), Ordering::SeqCst);
+    }
+
+    /// What is still armed: a connection failure or a dropped commit is gone once it fired.
+    pub fn armed(&self) -> FaultPlan {
+        let faults = &self.0;
+        let non_zero = |value: &AtomicU64| Some(value.load(Ordering::SeqCst)).filter(|v| *v > 0);
+        FaultPlan {
+            fail_connection: non_zero(&faults.connections_until_failure),
+            drop_commit: faults.drop_commit.load(Ordering::SeqCst),
+            email_delay_milliseconds: non_zero(&faults.email_delay_milliseconds),
+        }
+    }
+
+    /// Runs `future` with these faults, for code that doesn't run as part of a request.
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        FAULTS.scope(self.clone(), future).await
+    }
+
+    fn take_connection_failure(&self) -> bool {
+        self.0
+            .connections_until_failure
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            == Ok(1)
+    }
+
+    fn take_dropped_commit(&self) -> bool {
+        self.0.drop_commit.swap(false, Ordering::SeqCst)
+    }
+}
+
+fn current() -> Option<Faults> {
+    FAULTS.try_with(Faults::clone).ok()
+}
+
+/// Makes the faults armed on the application apply while handling the request.
+pub async fn scope_faults(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    match req.app_data::<web::Data<Faults>>() {
+        Some(faults) => faults.get_ref().clone().scope(next.call(req)).await,
+        None => next.call(req).await,
+    }
+}
+
+pub async fn arm_faults(plan: web::Json<FaultPlan>, faults: web::Data<Faults>) -> HttpResponse {
+    faults.arm(&plan);
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn armed_faults(faults: web::Data<Faults>) -> HttpResponse {
+    HttpResponse::Ok().json(faults.armed())
+}
+
+/// Run as a connection is taken from the pool, by the pool's `after_connect` and
+/// `before_acquire` hooks: drops it when it is the one meant to fail. The query that
+/// then fails is the caller's, the way it would when Postgres goes away.
+pub async fn on_checkout(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    if current().is_some_and(|faults| faults.take_connection_failure()) {
+        tracing::warn!("Injected fault: dropping a database connection");
+        connection
+            .execute("SET SESSION idle_session_timeout = 1")
+            .await?;
+        tokio::time::sleep(CONNECTION_DROP_WAIT).await;
+    }
+    Ok(())
+}
+
+/// Run by `database::commit` before it commits: when a dropped commit is armed, the
+/// connection drops so that the commit fails and Postgres rolls the transaction back.
+pub async fn before_commit(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    if current().is_some_and(|faults| faults.take_dropped_commit()) {
+        tracing::warn!("Injected fault: dropping a transaction as it commits");
+        connection
+            .execute("SET LOCAL idle_in_transaction_session_timeout = 1")
+            .await?;
+        tokio::time::sleep(CONNECTION_DROP_WAIT).await;
+    }
+    Ok(())
+}
+
+/// How long an email waits before being sent.
+pub fn email_delay() -> Option<Duration> {
+    current()
+        .map(|faults| faults.0.email_delay_milliseconds.load(Ordering::SeqCst))
+        .filter(|milliseconds| *milliseconds > 0)
+        .map(Duration::from_millis)
+}
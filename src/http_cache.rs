@@ -0,0 +1,194 @@
+//! Conditional GETs for read-only routes, so that browsers and CDNs can keep what they
+//! fetched: responses carry a strong `ETag`, a hash of their body, and a `Last-Modified`
+//! when the route knows when its content last changed. A request revalidating a copy that
+//! is still current gets a `304 Not Modified` without the body.
+use crate::api_error::ApiError;
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, ETag, EntityTag, Header, HttpDate, IfModifiedSince,
+    IfNoneMatch, LastModified,
+};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+// Bytes of the SHA-256 of the body kept in the `ETag`.
+const ETAG_LENGTH: usize = 16;
+
+/// Who may keep a copy of a response. Either way, copies are revalidated before use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Audience {
+    /// Shared caches too, e.g. a CDN in front of public pages.
+    Public,
+    /// Only the browser that fetched it, for responses behind authentication.
+    Private,
+}
+
+impl Audience {
+    fn cache_control(self) -> CacheControl {
+        let audience = match self {
+            Audience::Public => CacheDirective::Public,
+            Audience::Private => CacheDirective::Private,
+        };
+        CacheControl(vec![audience, CacheDirective::NoCache])
+    }
+}
+
+/// `value` as JSON, or a `304` if the request already has it.
+pub fn json<T: serde::Serialize>(
+    request: &HttpRequest,
+    value: &T,
+    last_modified: Option<DateTime<Utc>>,
+    audience: Audience,
+) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => respond(
+            request,
+            header::ContentType::json(),
+            body,
+            last_modified,
+            audience,
+        ),
+        Err(error) => {
+            tracing::error!(error.cause_chain = ?error, "Failed to serialize a response");
+            ApiError::unexpected().error_response()
+        }
+    }
+}
+
+/// `body`, or a `304` if the request already has it.
+pub fn respond(
+    request: &HttpRequest,
+    content_type: header::ContentType,
+    body: Vec<u8>,
+    last_modified: Option<DateTime<Utc>>,
+    audience: Audience,
+) -> HttpResponse {
+    let etag = etag_of(&body);
+    // HTTP dates have no fractions of a second: compared as they will be sent.
+    let last_modified = last_modified
+        .and_then(|modified| DateTime::from_timestamp(modified.timestamp(), 0))
+        .map(|modified| HttpDate::from(SystemTime::from(modified)));
+    let not_modified = is_not_modified(request, &etag, last_modified);
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(audience.cache_control());
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+fn etag_of(body: &[u8]) -> EntityTag {
+    let digest = Sha256::digest(body);
+    EntityTag::new_strong(URL_SAFE_NO_PAD.encode(&digest[..ETAG_LENGTH]))
+}
+
+/// Whether the copy the request revalidates is still current. `If-None-Match` wins over
+/// `If-Modified-Since`, which is only looked at without it (RFC 9110, 13.2.2).
+fn is_not_modified(
+    request: &HttpRequest,
+    etag: &EntityTag,
+    last_modified: Option<HttpDate>,
+) -> bool {
+    if request.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(etags)) => etags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+    match (last_modified, IfModifiedSince::parse(request)) {
+        (Some(last_modified), Ok(IfModifiedSince(since))) => last_modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Audience, respond};
+    use actix_web::http::header::{self, ContentType};
+    use actix_web::test::TestRequest;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn get(
+        headers: &[(header::HeaderName, &str)],
+        last_modified: Option<DateTime<Utc>>,
+    ) -> actix_web::HttpResponse {
+        let request = headers
+            .iter()
+            .fold(TestRequest::get(), |request, header| {
+                request.insert_header(header.clone())
+            })
+            .to_http_request();
+        respond(
+            &request,
+            ContentType::json(),
+            b"{}".to_vec(),
+            last_modified,
+            Audience::Public,
+        )
+    }
+
+    fn etag() -> String {
+        let response = get(&[], None);
+        response
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn matching_etags_are_not_modified() {
+        assert_eq!(get(&[], None).status().as_u16(), 200);
+        let etag = etag();
+        let weak = format!("W/{etag}");
+        for if_none_match in [etag.as_str(), &format!("\"other\", {weak}"), "*"] {
+            let response = get(&[(header::IF_NONE_MATCH, if_none_match)], None);
+            assert_eq!(response.status().as_u16(), 304, "{if_none_match}");
+        }
+        let response = get(&[(header::IF_NONE_MATCH, "\"other\"")], None);
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    #[test]
+    fn if_modified_since_is_only_used_without_if_none_match() {
+        let modified = DateTime::from_timestamp(1_700_000_000, 500_000_000).unwrap();
+        let at = |time: DateTime<Utc>| time.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let same = at(modified);
+        let earlier = at(modified - Duration::seconds(1));
+
+        let response = get(&[(header::IF_MODIFIED_SINCE, &same)], Some(modified));
+        assert_eq!(response.status().as_u16(), 304);
+        let response = get(&[(header::IF_MODIFIED_SINCE, &earlier)], Some(modified));
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(
+            response.headers().get(header::LAST_MODIFIED).unwrap(),
+            same.as_str()
+        );
+        let response = get(
+            &[
+                (header::IF_NONE_MATCH, "\"other\""),
+                (header::IF_MODIFIED_SINCE, &same),
+            ],
+            Some(modified),
+        );
+        assert_eq!(response.status().as_u16(), 200);
+        let response = get(&[(header::IF_MODIFIED_SINCE, &same)], None);
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
@@ -0,0 +1,55 @@
+//! Invite codes, required to subscribe when the newsletter is invite-only.
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
+use sqlx::{Postgres, Transaction};
+
+const INVITE_CODE_LENGTH: usize = 12;
+
+/// Upper-case only, so that codes survive being read out or typed by hand.
+pub fn generate_invite_code() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_uppercase())
+        .take(INVITE_CODE_LENGTH)
+        .collect()
+}
+
+/// Use up one of the invite's uses. Returns `false` if the code doesn't exist,
+/// has expired or has been used up.
+#[tracing::instrument(name = "Redeem an invite", skip(transaction))]
+pub async fn redeem_invite(
+    transaction: &mut Transaction<'_, Postgres>,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let redeemed = sqlx::query!(
+        r#"
+        UPDATE invites SET uses = uses + 1
+        WHERE code = $1 AND uses < max_uses AND (expires_at IS NULL OR expires_at > now())
+        "#,
+        code
+    )
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+    Ok(redeemed == 1)
+}
+
+/// Codes are matched regardless of case and surrounding whitespace.
+pub fn normalise_invite_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_invite_code;
+
+    #[test]
+    fn invite_codes_are_upper_case_alphanumeric() {
+        let code = generate_invite_code();
+        assert_eq!(code.len(), 12);
+        assert!(
+            code.chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Compacts the per-recipient rows of old issues into per-issue statistics, as a scheduled
+//! job.
+//!
+//! Once nothing of an issue is left queued and its grace period has passed, the attempts that
+//! went through and every tracking event of it are deleted, after being counted into
+//! `issue_statistics` (and each subscriber's opens and clicks into
+//! `subscriber_engagement_totals`), which the reports add back. Failed, bounced and skipped
+//! attempts are kept, as are dead letters and `broadcast_deliveries`, which the frequency cap
+//! and the list of recipients of an issue go by.
+use crate::configuration::{IssueCompactionSettings, Settings};
+use crate::scheduler::Job;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+pub struct IssueCompaction {
+    pool: PgPool,
+    settings: IssueCompactionSettings,
+}
+
+impl IssueCompaction {
+    /// `None` if compaction is disabled in the configuration.
+    pub fn from_configuration(configuration: &Settings, pool: PgPool) -> Option<Self> {
+        let settings = configuration.issue_compaction.clone()?;
+        Some(Self { pool, settings })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for IssueCompaction {
+    fn name(&self) -> &'static str {
+        "issue_compaction"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        compact_issues(&self.pool, &self.settings).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CompactionSummary {
+    pub compacted_issues: u64,
+    pub deleted_attempts: u64,
+    pub deleted_events: u64,
+}
+
+/// Compacts every issue that is due, each in a transaction of its own.
+#[tracing::instrument(name = "Compact issues", skip_all)]
+pub async fn compact_issues(
+    pool: &PgPool,
+    settings: &IssueCompactionSettings,
+) -> Result<CompactionSummary, anyhow::Error> {
+    let mut summary = CompactionSummary {
+        compacted_issues: 0,
+        deleted_attempts: 0,
+        deleted_events: 0,
+    };
+    let published_before = Utc::now() - settings.grace_period();
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        // Locked so that another instance running the job skips it.
+        let Some(newsletter_issue_id) = sqlx::query_scalar!(
+            r#"
+            SELECT i.newsletter_issue_id FROM newsletter_issues i
+            WHERE i.published_at <= $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM issue_statistics s
+                  WHERE s.newsletter_issue_id = i.newsletter_issue_id
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM issue_delivery_queue q
+                  WHERE q.newsletter_issue_id = i.newsletter_issue_id
+              )
+            ORDER BY i.published_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            published_before
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch the next issue to compact.")?
+        else {
+            break;
+        };
+        let (attempts, events) = compact_issue(&mut transaction, newsletter_issue_id).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to compact an issue.")?;
+        summary.compacted_issues += 1;
+        summary.deleted_attempts += attempts;
+        summary.deleted_events += events;
+    }
+    tracing::info!(
+        summary.compacted_issues,
+        summary.deleted_attempts,
+        summary.deleted_events,
+        "Compacted issues"
+    );
+    Ok(summary)
+}
+
+/// The number of attempts and of events deleted.
+#[tracing::instrument(name = "Compact an issue", skip(connection))]
+async fn compact_issue(
+    connection: &mut PgConnection,
+    newsletter_issue_id: Uuid,
+) -> Result<(u64, u64), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        WITH issue AS (
+            SELECT i.newsletter_issue_id, i.published_at,
+                (SELECT MIN(n.published_at) FROM newsletter_issues n
+                 WHERE n.newsletter_id = i.newsletter_id
+                   AND n.published_at > i.published_at) AS next_published_at
+            FROM newsletter_issues i WHERE i.newsletter_issue_id = $1
+        )
+        INSERT INTO issue_statistics
+            (newsletter_issue_id, sent, opened, clicked, unsubscribed, compacted_at)
+        SELECT $1,
+            (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = $1 AND delivery_counts_as_sent(a.status)),
+            (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = $1),
+            (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = $1 AND t.kind = 'click'),
+            (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             JOIN subscriptions s ON s.id = a.subscriber_id
+             WHERE a.newsletter_issue_id = $1
+               AND delivery_counts_as_sent(a.status)
+               AND s.unsubscribed_at >= issue.published_at
+               AND (issue.next_published_at IS NULL
+                    OR s.unsubscribed_at < issue.next_published_at)),
+            now()
+        FROM issue
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to store the statistics of an issue.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_engagement_totals (subscriber_id, opens, clicks, last_engaged_at)
+        SELECT subscriber_id,
+            COUNT(*) FILTER (WHERE kind = 'open'),
+            COUNT(*) FILTER (WHERE kind = 'click'),
+            MAX(occurred_at)
+        FROM tracking_events WHERE newsletter_issue_id = $1
+        GROUP BY subscriber_id
+        ON CONFLICT (subscriber_id) DO UPDATE SET
+            opens = subscriber_engagement_totals.opens + EXCLUDED.opens,
+            clicks = subscriber_engagement_totals.clicks + EXCLUDED.clicks,
+            last_engaged_at = GREATEST(
+                subscriber_engagement_totals.last_engaged_at, EXCLUDED.last_engaged_at
+            )
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to add up the engagement of the readers of an issue.")?;
+    let events = sqlx::query!(
+        "DELETE FROM tracking_events WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to delete the tracking events of an issue.")?
+    .rows_affected();
+    let attempts = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_delivery_attempts
+        WHERE newsletter_issue_id = $1 AND delivery_counts_as_sent(status)
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to delete the delivery attempts of an issue.")?
+    .rows_affected();
+    Ok((attempts, events))
+}
@@ -0,0 +1,546 @@
+//! Deliveries of issues that didn't fit in the send budget when they were published.
+//!
+//! Publishing sends to as many recipients as the budget allows and queues the others in
+//! `issue_delivery_queue`. The `issue_delivery` job works through the queue as the budget
+//! refills: a large issue spreads over as many hours as the provider's quota needs.
+//!
+//! Issues being delivered at the same time take turns, so that a large one doesn't hold up
+//! a small announcement published after it. Each turn goes to the issue whose last turn was
+//! longest ago, and is as many batches as the priority it was published with.
+//!
+//! The job runs on every instance at once. Each batch of deliveries is leased with
+//! `FOR UPDATE SKIP LOCKED` by pushing their `execute_after` past the visibility timeout:
+//! other workers skip them, and those of a worker that died mid-batch come back once the
+//! lease runs out. Failed deliveries are retried with a backoff, then dead-lettered.
+use crate::configuration::Settings;
+use crate::crypto::KeyRing;
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::metrics::PendingDeliveries;
+use crate::pii::PiiCipher;
+use crate::routes::{ConfirmedSubscriber, IssueDelivery, RenderedIssue};
+use crate::scheduler::Job;
+use crate::send_budget;
+use crate::signed_tokens::TokenSigner;
+use crate::sponsors::record_impressions;
+use crate::templates::EmailTemplates;
+use crate::tracking::IssueTracking;
+use anyhow::Context;
+use futures_util::{StreamExt, stream};
+use sqlx::PgPool;
+use std::error::Error;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How queued deliveries are leased and retried. A lease has to outlast a whole turn, that is
+/// as many batches as the highest priority, sends and retries included. A delivery that fails
+/// is tried again `retry_backoff_seconds` later, doubling with every retry up to an hour, and
+/// is dead-lettered once it has failed `max_retries` more times.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct DeliveryQueueSettings {
+    pub visibility_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub retry_backoff_seconds: u64,
+}
+
+impl Default for DeliveryQueueSettings {
+    fn default() -> Self {
+        Self {
+            visibility_timeout_seconds: 300,
+            max_retries: 3,
+            retry_backoff_seconds: 60,
+        }
+    }
+}
+
+impl DeliveryQueueSettings {
+    /// How long to wait before retry number `retry`, the first one being 1.
+    fn retry_backoff_seconds(&self, retry: i32) -> f64 {
+        let doublings = (retry - 1).clamp(0, 16) as u32;
+        self.retry_backoff_seconds
+            .saturating_mul(1 << doublings)
+            .min(3600) as f64
+    }
+}
+
+/// Delivers the queued recipients of issues, as a scheduled job.
+pub struct IssueDeliveryQueue {
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    templates: EmailTemplates,
+    key_ring: KeyRing,
+    token_signer: TokenSigner,
+    pii_cipher: PiiCipher,
+    base_url: String,
+    action_base_url: ActionBaseUrl,
+}
+
+impl IssueDeliveryQueue {
+    /// Runs without a send budget too, to drain what was queued before it was removed.
+    pub fn from_configuration(
+        configuration: &Settings,
+        pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            pool,
+            email_client,
+            templates: EmailTemplates::load(&configuration.application.templates_dir)?,
+            key_ring: configuration
+                .signing
+                .key_ring()
+                .map_err(anyhow::Error::msg)?,
+            token_signer: configuration
+                .signing
+                .token_signer()
+                .map_err(anyhow::Error::msg)?,
+            pii_cipher: configuration.pii_cipher().map_err(anyhow::Error::msg)?,
+            base_url: configuration.application.base_url.clone(),
+            action_base_url: configuration
+                .action_base_url()
+                .map_err(anyhow::Error::msg)?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for IssueDeliveryQueue {
+    fn name(&self) -> &'static str {
+        "issue_delivery"
+    }
+
+    // Deliveries are leased one batch at a time.
+    fn exclusive(&self) -> bool {
+        false
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        let summary = deliver_queued_issues(
+            &self.pool,
+            &self.email_client,
+            &self.templates,
+            &self.key_ring,
+            &self.token_signer,
+            &self.pii_cipher,
+            &self.base_url,
+            &self.action_base_url,
+        )
+        .await?;
+        if summary.delivered > 0 || summary.failed > 0 || summary.dead_lettered > 0 {
+            tracing::info!(
+                delivered = summary.delivered,
+                failed = summary.failed,
+                dead_lettered = summary.dead_lettered,
+                "Delivered queued newsletter issues"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueueSummary {
+    pub delivered: u64,
+    // Failed attempts, retried later unless they were dead-lettered.
+    pub failed: u64,
+    pub dead_lettered: u64,
+}
+
+/// Queues the deliveries of an issue to `subscriber_ids`.
+#[tracing::instrument(name = "Queue deliveries of an issue", skip(pool, subscriber_ids))]
+pub(crate) async fn enqueue_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, enqueued_at)
+        SELECT $1, subscriber_id, now() FROM UNNEST($2::uuid[]) AS subscriber_id
+        "#,
+        newsletter_issue_id,
+        subscriber_ids
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Delivers as much of the queue as is due and the send budget allows. Safe to run on
+/// several instances at once: each delivery is leased by the worker that sends it.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "Deliver queued newsletter issues", skip_all)]
+pub async fn deliver_queued_issues(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    key_ring: &KeyRing,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    base_url: &str,
+    action_base_url: &ActionBaseUrl,
+) -> Result<QueueSummary, anyhow::Error> {
+    let settings = email_client.delivery();
+    let mut summary = QueueSummary {
+        delivered: 0,
+        failed: 0,
+        dead_lettered: 0,
+    };
+    loop {
+        drop_departed_recipients(pool)
+            .await
+            .context("Failed to drop the queued deliveries of departed subscribers.")?;
+        let Some(issue) = next_queued_issue(pool)
+            .await
+            .context("Failed to fetch the next queued newsletter issue.")?
+        else {
+            break;
+        };
+        let mut wanted = (settings.batch_size.max(1) * issue.delivery_priority as usize)
+            .min(issue.queued as usize);
+        if let Some(budget) = &settings.send_budget {
+            // Another worker can lease some of these deliveries in between: the tokens they
+            // would have used are lost, which only errs on the side of the quota.
+            wanted = send_budget::take(pool, budget, wanted)
+                .await
+                .context("Failed to take from the send budget.")?;
+            if wanted == 0 {
+                break;
+            }
+        }
+        let leases = lease_deliveries(
+            pool,
+            pii_cipher,
+            issue.newsletter_issue_id,
+            wanted,
+            &settings.queue,
+        )
+        .await
+        .context("Failed to lease the queued deliveries of a newsletter issue.")?;
+        if leases.is_empty() {
+            break;
+        }
+
+        let rendered = RenderedIssue {
+            html: issue.html_content,
+            text: issue.text_content,
+            // Impressions are counted below, from the slots stored with the issue.
+            sponsor_slots: Vec::new(),
+            compliance_error: None,
+            // Reviewed when it was published.
+            links: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let tracking = issue.tracked.then(|| {
+            IssueTracking::new(
+                &rendered.html,
+                base_url,
+                key_ring,
+                issue.newsletter_issue_id,
+            )
+        });
+        let delivery = IssueDelivery {
+            records: pool,
+            email_sender: email_client,
+            templates,
+            token_signer,
+            action_base_url,
+            newsletter: &issue.newsletter,
+            issue: &rendered,
+            tracking: tracking.as_ref(),
+            title: &issue.title,
+            newsletter_issue_id: issue.newsletter_issue_id,
+        };
+        let mut deliverable = Vec::with_capacity(leases.len());
+        for lease in leases {
+            if lease.n_retries > settings.queue.max_retries as i32 {
+                // Its last attempt never finished, e.g. the worker crashed mid-send.
+                dead_letter(pool, &lease, "The lease of the last attempt ran out.")
+                    .await
+                    .context("Failed to dead-letter a queued delivery.")?;
+                summary.dead_lettered += 1;
+                continue;
+            }
+            match &lease.recipient {
+                Ok(_) => deliverable.push(lease),
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        subscriber_id = %lease.subscriber_id,
+                        "Dead-lettering a queued recipient. Their stored contact details are invalid",
+                    );
+                    dead_letter(pool, &lease, "The stored contact details are invalid.")
+                        .await
+                        .context("Failed to dead-letter a queued delivery.")?;
+                    summary.dead_lettered += 1;
+                }
+            }
+        }
+        let mut pending_deliveries = PendingDeliveries::new(deliverable.len());
+        // Built up front rather than in `map`: a closure over references isn't `Send` enough
+        // for a job.
+        let sends: Vec<_> = deliverable
+            .iter()
+            .filter_map(|lease| {
+                let send = delivery.send_to(lease.recipient.as_ref().ok()?);
+                Some(async move { (lease, send.await) })
+            })
+            .collect();
+        let outcomes: Vec<_> = stream::iter(sends)
+            .buffer_unordered(settings.concurrency.max(1))
+            .collect()
+            .await;
+        let mut delivered = 0;
+        for (lease, outcome) in outcomes {
+            pending_deliveries.attempted();
+            match outcome {
+                Ok(()) => {
+                    delivered += 1;
+                    release(pool, lease)
+                        .await
+                        .context("Failed to remove a delivery from the queue.")?;
+                }
+                Err(error) => {
+                    summary.failed += 1;
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        newsletter_issue_id = %issue.newsletter_issue_id,
+                        n_retries = lease.n_retries,
+                        "Failed to deliver a queued newsletter issue",
+                    );
+                    if lease.n_retries >= settings.queue.max_retries as i32 {
+                        dead_letter(pool, lease, &root_cause(&error))
+                            .await
+                            .context("Failed to dead-letter a queued delivery.")?;
+                        summary.dead_lettered += 1;
+                    } else {
+                        retry_later(
+                            pool,
+                            lease,
+                            settings.queue.retry_backoff_seconds(lease.n_retries + 1),
+                        )
+                        .await
+                        .context("Failed to reschedule a queued delivery.")?;
+                    }
+                }
+            }
+        }
+        summary.delivered += delivered;
+        record_impressions(pool, &issue.sponsor_slot_ids, delivered as i64)
+            .await
+            .context("Failed to record sponsor impressions.")?;
+    }
+    Ok(summary)
+}
+
+/// The innermost error, without the contact details that outer ones mention.
+fn root_cause(error: &dyn Error) -> String {
+    let mut cause = error;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+struct QueuedIssue {
+    newsletter_issue_id: Uuid,
+    queued: i64,
+    delivery_priority: i16,
+    title: String,
+    html_content: String,
+    text_content: String,
+    tracked: bool,
+    sponsor_slot_ids: Vec<Uuid>,
+    // The name of its list, for the footer.
+    newsletter: String,
+}
+
+/// Takes the turn of the issue with deliveries that are due, i.e. neither leased nor waiting
+/// for a retry, whose last turn was longest ago. Issues that haven't had one go first, oldest
+/// first.
+async fn next_queued_issue(pool: &PgPool) -> Result<Option<QueuedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        QueuedIssue,
+        r#"
+        WITH next AS (
+            SELECT i.newsletter_issue_id, COUNT(*) AS queued
+            FROM issue_delivery_queue q
+            JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+            WHERE q.execute_after <= now()
+            GROUP BY i.newsletter_issue_id
+            ORDER BY i.last_dequeued_at NULLS FIRST, MIN(q.enqueued_at), i.newsletter_issue_id
+            LIMIT 1
+        )
+        UPDATE newsletter_issues i SET last_dequeued_at = now()
+        FROM next, newsletters n
+        WHERE i.newsletter_issue_id = next.newsletter_issue_id AND n.id = i.newsletter_id
+        RETURNING i.newsletter_issue_id, next.queued AS "queued!", i.delivery_priority,
+            i.title, i.html_content, i.text_content, i.tracked, i.sponsor_slot_ids,
+            n.name AS newsletter
+        "#
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// A queued delivery, leased by this worker until its visibility timeout.
+struct Lease {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    // The attempts made before this one. Bumped when leasing, so that an attempt that never
+    // finishes counts too, and checked when letting go of the delivery: a worker whose lease
+    // ran out, and was taken over, leaves it alone.
+    n_retries: i32,
+    recipient: Result<ConfirmedSubscriber, anyhow::Error>,
+}
+
+/// Leases up to `limit` of the due deliveries of an issue, oldest first, skipping those
+/// another worker is leasing at the same moment.
+async fn lease_deliveries(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    newsletter_issue_id: Uuid,
+    limit: usize,
+    settings: &DeliveryQueueSettings,
+) -> Result<Vec<Lease>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue q
+        SET n_retries = q.n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        FROM (
+            SELECT newsletter_issue_id, subscriber_id FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND execute_after <= now()
+            ORDER BY enqueued_at, subscriber_id
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        ) due
+        JOIN subscriptions s ON s.id = due.subscriber_id
+        WHERE q.newsletter_issue_id = due.newsletter_issue_id
+            AND q.subscriber_id = due.subscriber_id
+        RETURNING q.subscriber_id, q.n_retries - 1 AS "n_retries!", s.email, s.name
+        "#,
+        newsletter_issue_id,
+        limit as i64,
+        settings.visibility_timeout_seconds as f64
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let recipient = (|| {
+                let email = SubscriberEmail::parse(pii_cipher.decrypt(&r.email)?)
+                    .map_err(|error| anyhow::anyhow!(error))?;
+                Ok(ConfirmedSubscriber {
+                    id: r.subscriber_id,
+                    email,
+                    name: pii_cipher.decrypt(&r.name)?,
+                    recent_deliveries: 0,
+                })
+            })();
+            Lease {
+                newsletter_issue_id,
+                subscriber_id: r.subscriber_id,
+                n_retries: r.n_retries,
+                recipient,
+            }
+        })
+        .collect())
+}
+
+/// Subscribers who unsubscribed or were deleted since the issue was published don't get it.
+async fn drop_departed_recipients(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue q
+        USING subscriptions s
+        WHERE s.id = q.subscriber_id AND (s.status <> 'confirmed' OR s.deleted_at IS NOT NULL)
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Removes a delivery that went out from the queue.
+async fn release(pool: &PgPool, lease: &Lease) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2 AND n_retries = $3
+        "#,
+        lease.newsletter_issue_id,
+        lease.subscriber_id,
+        lease.n_retries + 1
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Makes a failed delivery due again in `backoff_seconds`.
+async fn retry_later(
+    pool: &PgPool,
+    lease: &Lease,
+    backoff_seconds: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue SET execute_after = now() + make_interval(secs => $4)
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2 AND n_retries = $3
+        "#,
+        lease.newsletter_issue_id,
+        lease.subscriber_id,
+        lease.n_retries + 1,
+        backoff_seconds
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Moves a delivery that won't go out from the queue to `issue_delivery_dead_letters`.
+#[tracing::instrument(name = "Dead-letter a queued delivery", skip(pool, lease), fields(subscriber_id = %lease.subscriber_id))]
+async fn dead_letter(pool: &PgPool, lease: &Lease, last_error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH dead AS (
+            DELETE FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND subscriber_id = $2 AND n_retries = $3
+            RETURNING newsletter_issue_id, subscriber_id, n_retries
+        )
+        INSERT INTO issue_delivery_dead_letters
+            (newsletter_issue_id, subscriber_id, n_retries, last_error, dead_lettered_at)
+        SELECT newsletter_issue_id, subscriber_id, n_retries, $4, now() FROM dead
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            dead_lettered_at = EXCLUDED.dead_lettered_at
+        "#,
+        lease.newsletter_issue_id,
+        lease.subscriber_id,
+        lease.n_retries + 1,
+        last_error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryQueueSettings;
+
+    #[test]
+    fn the_retry_backoff_doubles_up_to_an_hour() {
+        let settings = DeliveryQueueSettings {
+            retry_backoff_seconds: 60,
+            ..DeliveryQueueSettings::default()
+        };
+        assert_eq!(settings.retry_backoff_seconds(1), 60.0);
+        assert_eq!(settings.retry_backoff_seconds(3), 240.0);
+        assert_eq!(settings.retry_backoff_seconds(10), 3600.0);
+        assert_eq!(settings.retry_backoff_seconds(100), 3600.0);
+    }
+}
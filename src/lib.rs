@@ -1,6 +1,53 @@
+pub mod access_log;
+pub mod admin_access;
+pub mod admin_notifications;
+pub mod api_error;
+pub mod api_keys;
+pub mod audit_log;
+pub mod authentication;
+pub mod authorization;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod branding;
+pub mod cache;
 pub mod configuration;
+pub mod confirmation_outbox;
+pub mod confirmation_reminders;
+pub mod content_checks;
+pub mod cors;
+pub mod crypto;
+pub mod database;
 pub mod domain;
 pub mod email_client;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
+pub mod http_cache;
+pub mod invites;
+pub mod issue_compaction;
+pub mod issue_delivery;
+pub mod merge_fields;
+pub mod metrics;
+pub mod panics;
+pub mod pii;
+pub mod preflight;
+pub mod rate_limit;
+pub mod repermission_campaigns;
 pub mod routes;
+pub mod runtime_flags;
+pub mod scheduler;
+pub mod security_headers;
+pub mod send_budget;
+pub mod session;
+pub mod shutdown;
+pub mod signed_tokens;
+pub mod signup_verification;
+pub mod sms_client;
+pub mod snippets;
+pub mod sponsors;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
+pub mod token_cleanup;
+pub mod tracking;
+pub mod waitlist;
+pub mod web_push;
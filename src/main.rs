@@ -1,18 +1,128 @@
-use zero2prod::startup::Application;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use zero2prod::authentication::{Role, store_user};
+use zero2prod::database::wait_for_database;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::pii::encrypt_stored_pii;
+use zero2prod::preflight::run_migrations;
+use zero2prod::scheduler::Scheduler;
+use zero2prod::shutdown::Shutdown;
+use zero2prod::startup::{Application, get_connection_pool};
 use zero2prod::{
     configuration::get_configuration,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, otlp_tracer_provider},
 };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+    #[cfg(feature = "bench")]
+    if std::env::args().nth(1).as_deref() == Some("bench-send") {
+        return zero2prod::bench::run(std::env::args().skip(2)).await;
+    }
 
+    // Access logs share stdout with application logs unless they are given a file of their own.
+    let access_log_sink = match std::env::var("ACCESS_LOG_PATH") {
+        Ok(path) => BoxMakeWriter::new(Arc::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        Err(_) => BoxMakeWriter::new(std::io::stdout),
+    };
     // Panic if we can't read configuration
     let configuration = get_configuration().expect("Failed to read configuration.");
+    let tracer_provider = otlp_tracer_provider("zero2prod", &configuration.telemetry)
+        .map_err(std::io::Error::other)?;
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        access_log_sink,
+        tracer_provider.as_ref(),
+    );
+    init_subscriber(subscriber);
+
+    // Applies pending migrations, then exits: for deploy jobs that migrate before rolling out.
+    if std::env::args().nth(1).as_deref() == Some("--migrate-only") {
+        let pool = get_connection_pool(&configuration.database);
+        wait_for_database(&pool, &configuration.database.connect_retry)
+            .await
+            .map_err(std::io::Error::other)?;
+        run_migrations(&pool).await.map_err(std::io::Error::other)?;
+        println!("The database is up to date.");
+        return Ok(());
+    }
+
+    // Encrypts subscribers stored before `pii_encryption` was configured, then exits.
+    if std::env::args().nth(1).as_deref() == Some("encrypt-pii") {
+        let pii_cipher = configuration.pii_cipher().map_err(std::io::Error::other)?;
+        let pool = get_connection_pool(&configuration.database);
+        let encrypted = encrypt_stored_pii(&pool, &pii_cipher, 500)
+            .await
+            .map_err(std::io::Error::other)?;
+        println!("Encrypted the details of {encrypted} subscribers.");
+        return Ok(());
+    }
+
+    // Adds a publisher (or resets their password), reading the password from stdin, then exits.
+    if std::env::args().nth(1).as_deref() == Some("create-user") {
+        let (role, args): (Vec<String>, Vec<String>) = std::env::args()
+            .skip(2)
+            .partition(|arg| arg.starts_with("--role="));
+        let role = role
+            .last()
+            .map(|role| Role::parse(&role["--role=".len()..]))
+            .transpose()
+            .map_err(std::io::Error::other)?;
+        let mut args = args.into_iter();
+        let username = args.next().ok_or_else(|| {
+            std::io::Error::other(
+                "Usage: zero2prod create-user <username> [email] [--role=admin|editor|viewer]",
+            )
+        })?;
+        let email = args
+            .next()
+            .map(SubscriberEmail::parse)
+            .transpose()
+            .map_err(|e| std::io::Error::other(format!("Invalid email: {e}")))?;
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        let password = password.trim_end_matches(['\r', '\n']);
+        if password.is_empty() {
+            return Err(std::io::Error::other("The password must not be empty."));
+        }
+        let pool = get_connection_pool(&configuration.database);
+        let email = email.as_ref().map(|email| email.as_ref());
+        let user_id = store_user(&username, email, role, password.into(), &pool)
+            .await
+            .map_err(std::io::Error::other)?;
+        println!("Stored user {username} ({user_id}).");
+        return Ok(());
+    }
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let shutdown_timeout = configuration.application.shutdown_timeout();
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = Arc::new(configuration.email_client.clone().client());
+    let scheduler =
+        Scheduler::from_configuration(&configuration, pool.clone(), email_client.clone())
+            .map_err(std::io::Error::other)?;
+    let application = Application::build_with_shared(configuration, pool, email_client).await?;
+    let shutdown = Shutdown::on_signal();
+    let scheduler = tokio::spawn(scheduler.run_until_shutdown(shutdown.clone()));
+    application.run_until_shutdown(shutdown).await?;
+    // Requests are drained: jobs that are still running get as long to finish.
+    if tokio::time::timeout(shutdown_timeout, scheduler)
+        .await
+        .is_err()
+    {
+        tracing::warn!("Scheduled jobs were still running at the end of the shutdown timeout");
+    }
+    tracing::info!("Shut down");
+    if let Some(tracer_provider) = tracer_provider
+        && let Err(e) = tracer_provider.shutdown()
+    {
+        eprintln!("Failed to flush the spans left to export: {e}");
+    }
+    std::io::stdout().flush()?;
     Ok(())
 }
@@ -0,0 +1,159 @@
+//! Per-subscriber merge fields: `{{ subscriber.name }}` in an issue body is replaced with
+//! the recipient's name when the issue is sent to them.
+
+const FIELD_PREFIX: &str = "subscriber.";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MergeFieldError {
+    #[error("`{0}` is not a subscriber attribute. Use `subscriber.name` or `subscriber.email`.")]
+    UnknownAttribute(String),
+    #[error("A merge field is missing its closing `}}}}`.")]
+    UnterminatedField,
+}
+
+/// The recipient attributes merge fields can refer to.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+}
+
+impl Recipient<'_> {
+    /// A stand-in recipient, to check an issue before it goes out to real subscribers.
+    pub fn persona() -> Recipient<'static> {
+        Recipient {
+            name: "Ursula Le Guin",
+            email: "ursula@example.com",
+        }
+    }
+
+    /// Whoever reads an issue on the web, where it isn't addressed to anyone.
+    pub fn reader() -> Recipient<'static> {
+        Recipient {
+            name: "reader",
+            email: "",
+        }
+    }
+
+    fn attribute(&self, attribute: &str) -> Option<&str> {
+        match attribute {
+            "name" => Some(self.name),
+            "email" => Some(self.email),
+            _ => None,
+        }
+    }
+}
+
+/// Replace every `{{ subscriber.<attribute> }}` field in `body`.
+/// Values are HTML-escaped when `escape_html` is set.
+///
+/// Fields that can't be resolved are left as they are and reported, so that a preview can
+/// show them in place; a body with errors must not be sent.
+/// Other `{{ ... }}` markers are not merge fields and are left alone.
+pub fn render_merge_fields(
+    body: &str,
+    recipient: &Recipient<'_>,
+    escape_html: bool,
+) -> (String, Vec<MergeFieldError>) {
+    let mut output = String::with_capacity(body.len());
+    let mut errors = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        if !after_open.trim_start().starts_with(FIELD_PREFIX) {
+            output.push_str(&rest[..start + 2]);
+            rest = after_open;
+            continue;
+        }
+        let Some(end) = after_open.find("}}") else {
+            errors.push(MergeFieldError::UnterminatedField);
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let field = after_open[..end].trim();
+        match recipient.attribute(&field[FIELD_PREFIX.len()..]) {
+            Some(value) if escape_html => output.push_str(&escape(value)),
+            Some(value) => output.push_str(value),
+            None => {
+                errors.push(MergeFieldError::UnknownAttribute(field.into()));
+                output.push_str(&rest[start..start + 2 + end + 2]);
+            }
+        }
+        rest = &after_open[end + 2..];
+    }
+    output.push_str(rest);
+    (output, errors)
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergeFieldError, Recipient, render_merge_fields};
+
+    fn recipient() -> Recipient<'static> {
+        Recipient {
+            name: "<Ursula>",
+            email: "ursula@example.com",
+        }
+    }
+
+    #[test]
+    fn fields_are_replaced_with_recipient_attributes() {
+        let (body, errors) = render_merge_fields(
+            "Hi {{ subscriber.name }} ({{subscriber.email}})",
+            &recipient(),
+            false,
+        );
+        assert!(errors.is_empty());
+        assert_eq!(body, "Hi <Ursula> (ursula@example.com)");
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let (body, _) = render_merge_fields("<p>Hi {{ subscriber.name }}</p>", &recipient(), true);
+        assert_eq!(body, "<p>Hi &lt;Ursula&gt;</p>");
+    }
+
+    #[test]
+    fn other_markers_are_left_alone() {
+        let template = "Hello {{ there }} {{ sponsor_link }}";
+        let (body, errors) = render_merge_fields(template, &recipient(), false);
+        assert!(errors.is_empty());
+        assert_eq!(body, template);
+    }
+
+    #[test]
+    fn unknown_attributes_are_reported_and_kept_in_place() {
+        let (body, errors) = render_merge_fields(
+            "Hi {{ subscriber.first_name }}, {{ subscriber.name }}",
+            &recipient(),
+            false,
+        );
+        assert_eq!(body, "Hi {{ subscriber.first_name }}, <Ursula>");
+        assert_eq!(
+            errors,
+            vec![MergeFieldError::UnknownAttribute(
+                "subscriber.first_name".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn unterminated_fields_are_reported() {
+        let (_, errors) = render_merge_fields("Hi {{ subscriber.name", &recipient(), false);
+        assert_eq!(errors, vec![MergeFieldError::UnterminatedField]);
+    }
+}
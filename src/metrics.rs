@@ -0,0 +1,393 @@
+//! Prometheus metrics, in the text exposition format served at `/metrics`.
+//!
+//! Request and email metrics are kept in-process, so every replica reports its own and
+//! they add up across replicas. Subscriber counts are read from the database on each scrape.
+use crate::email_client::{Lane, MessageCategory};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Stands in for the route of requests that match none, so that scanners probing
+// random paths can't blow up the number of series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Default)]
+struct RouteStats {
+    responses: BTreeMap<u16, u64>,
+    // Per bucket, not cumulative: they are added up when rendered.
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+struct Metrics {
+    // By method and route pattern.
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+    email_send_failures: Mutex<BTreeMap<&'static str, u64>>,
+    discarded_webhook_events: Mutex<BTreeMap<&'static str, u64>>,
+    lanes: Mutex<BTreeMap<&'static str, LaneStats>>,
+    pending_deliveries: AtomicI64,
+}
+
+#[derive(Default)]
+struct LaneStats {
+    in_flight: i64,
+    wait_seconds_sum: f64,
+    wait_count: u64,
+}
+
+static METRICS: Metrics = Metrics {
+    routes: Mutex::new(BTreeMap::new()),
+    email_send_failures: Mutex::new(BTreeMap::new()),
+    discarded_webhook_events: Mutex::new(BTreeMap::new()),
+    lanes: Mutex::new(BTreeMap::new()),
+    pending_deliveries: AtomicI64::new(0),
+};
+
+/// Counts every response and how long it took, by method and route pattern.
+pub async fn record_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started_at = Instant::now();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+    let method = req.method().to_string();
+
+    let outcome = next.call(req).await;
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(error) => error.as_response_error().status_code(),
+    };
+    record_request(
+        method,
+        route,
+        status.as_u16(),
+        started_at.elapsed().as_secs_f64(),
+    );
+    outcome
+}
+
+fn record_request(method: String, route: String, status: u16, latency: f64) {
+    let mut routes = METRICS.routes.lock().unwrap();
+    let stats = routes.entry((method, route)).or_default();
+    *stats.responses.entry(status).or_default() += 1;
+    if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| latency <= *bound) {
+        stats.latency_buckets[bucket] += 1;
+    }
+    stats.latency_sum += latency;
+    stats.latency_count += 1;
+}
+
+/// An email that could not be sent, after retries.
+pub fn record_email_send_failure(category: MessageCategory) {
+    let category = match category {
+        MessageCategory::Transactional => "transactional",
+        MessageCategory::Broadcast => "broadcast",
+        MessageCategory::ReEngagement => "re_engagement",
+    };
+    *METRICS
+        .email_send_failures
+        .lock()
+        .unwrap()
+        .entry(category)
+        .or_default() += 1;
+}
+
+/// An email being sent, from the moment its lane let it through until dropped.
+pub struct InFlightSend(&'static str);
+
+impl InFlightSend {
+    /// `waited` is how long it took to get through the lane and the rate limit.
+    pub fn new(lane: Lane, waited: Duration) -> Self {
+        let lane = match lane {
+            Lane::Transactional => "transactional",
+            Lane::Bulk => "bulk",
+        };
+        let mut lanes = METRICS.lanes.lock().unwrap();
+        let stats = lanes.entry(lane).or_default();
+        stats.in_flight += 1;
+        stats.wait_seconds_sum += waited.as_secs_f64();
+        stats.wait_count += 1;
+        Self(lane)
+    }
+}
+
+impl Drop for InFlightSend {
+    fn drop(&mut self) {
+        if let Some(stats) = METRICS.lanes.lock().unwrap().get_mut(self.0) {
+            stats.in_flight -= 1;
+        }
+    }
+}
+
+/// Why an email provider webhook was ignored.
+pub enum DiscardedWebhookEvent {
+    /// The provider sent it before.
+    Duplicate,
+    /// A newer event about the same delivery was handled first.
+    OutOfOrder,
+    /// It is about a message that isn't an issue we sent, e.g. a confirmation email.
+    UnknownMessage,
+}
+
+pub fn record_discarded_webhook_event(reason: DiscardedWebhookEvent) {
+    let reason = match reason {
+        DiscardedWebhookEvent::Duplicate => "duplicate",
+        DiscardedWebhookEvent::OutOfOrder => "out_of_order",
+        DiscardedWebhookEvent::UnknownMessage => "unknown_message",
+    };
+    *METRICS
+        .discarded_webhook_events
+        .lock()
+        .unwrap()
+        .entry(reason)
+        .or_default() += 1;
+}
+
+/// Recipients of an issue being published who haven't been attempted yet.
+/// Whatever is left when dropped, e.g. when publishing fails halfway, is given up on.
+pub struct PendingDeliveries(i64);
+
+impl PendingDeliveries {
+    pub fn new(recipients: usize) -> Self {
+        let recipients = recipients as i64;
+        METRICS
+            .pending_deliveries
+            .fetch_add(recipients, Ordering::Relaxed);
+        Self(recipients)
+    }
+
+    pub fn attempted(&mut self) {
+        if self.0 > 0 {
+            METRICS.pending_deliveries.fetch_sub(1, Ordering::Relaxed);
+            self.0 -= 1;
+        }
+    }
+}
+
+impl Drop for PendingDeliveries {
+    fn drop(&mut self) {
+        METRICS
+            .pending_deliveries
+            .fetch_sub(self.0, Ordering::Relaxed);
+    }
+}
+
+/// Every metric, in the Prometheus text exposition format.
+#[tracing::instrument(name = "Render metrics", skip(pool))]
+pub async fn render_metrics(pool: &PgPool) -> Result<String, anyhow::Error> {
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!" FROM subscriptions
+        WHERE deleted_at IS NULL
+        GROUP BY status ORDER BY status
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count subscribers by status.")?;
+
+    let mut out = String::new();
+    writeln!(out, "# HELP subscribers Subscribers, by status.")?;
+    writeln!(out, "# TYPE subscribers gauge")?;
+    for row in subscribers {
+        writeln!(
+            out,
+            "subscribers{{status=\"{}\"}} {}",
+            escape_label(&row.status),
+            row.count
+        )?;
+    }
+    render_in_process_metrics(&mut out)?;
+    Ok(out)
+}
+
+fn render_in_process_metrics(out: &mut String) -> Result<(), std::fmt::Error> {
+    writeln!(
+        out,
+        "# HELP newsletter_deliveries_pending Recipients of issues being published, not yet attempted."
+    )?;
+    writeln!(out, "# TYPE newsletter_deliveries_pending gauge")?;
+    writeln!(
+        out,
+        "newsletter_deliveries_pending {}",
+        METRICS.pending_deliveries.load(Ordering::Relaxed)
+    )?;
+
+    writeln!(
+        out,
+        "# HELP email_send_failures_total Emails that could not be sent, after retries."
+    )?;
+    writeln!(out, "# TYPE email_send_failures_total counter")?;
+    for (category, count) in METRICS.email_send_failures.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "email_send_failures_total{{category=\"{category}\"}} {count}"
+        )?;
+    }
+
+    let lanes = METRICS.lanes.lock().unwrap();
+    writeln!(
+        out,
+        "# HELP email_lane_sends_in_flight Emails being sent, by lane."
+    )?;
+    writeln!(out, "# TYPE email_lane_sends_in_flight gauge")?;
+    for (lane, stats) in lanes.iter() {
+        writeln!(
+            out,
+            "email_lane_sends_in_flight{{lane=\"{lane}\"}} {}",
+            stats.in_flight
+        )?;
+    }
+    writeln!(
+        out,
+        "# HELP email_lane_wait_seconds Time emails waited for their lane and the rate limit, by lane."
+    )?;
+    writeln!(out, "# TYPE email_lane_wait_seconds summary")?;
+    for (lane, stats) in lanes.iter() {
+        writeln!(
+            out,
+            "email_lane_wait_seconds_sum{{lane=\"{lane}\"}} {}",
+            stats.wait_seconds_sum
+        )?;
+        writeln!(
+            out,
+            "email_lane_wait_seconds_count{{lane=\"{lane}\"}} {}",
+            stats.wait_count
+        )?;
+    }
+    drop(lanes);
+
+    writeln!(
+        out,
+        "# HELP email_webhook_events_discarded_total Email provider webhooks ignored, as duplicates or out of order."
+    )?;
+    writeln!(out, "# TYPE email_webhook_events_discarded_total counter")?;
+    for (reason, count) in METRICS.discarded_webhook_events.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "email_webhook_events_discarded_total{{reason=\"{reason}\"}} {count}"
+        )?;
+    }
+
+    let routes = METRICS.routes.lock().unwrap();
+    writeln!(
+        out,
+        "# HELP http_requests_total Responses, by route and status."
+    )?;
+    writeln!(out, "# TYPE http_requests_total counter")?;
+    for ((method, route), stats) in routes.iter() {
+        let labels = format!(
+            "method=\"{}\",route=\"{}\"",
+            escape_label(method),
+            escape_label(route)
+        );
+        for (status, count) in &stats.responses {
+            writeln!(
+                out,
+                "http_requests_total{{{labels},status=\"{status}\"}} {count}"
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "# HELP http_request_duration_seconds Time to respond, by route."
+    )?;
+    writeln!(out, "# TYPE http_request_duration_seconds histogram")?;
+    for ((method, route), stats) in routes.iter() {
+        let labels = format!(
+            "method=\"{}\",route=\"{}\"",
+            escape_label(method),
+            escape_label(route)
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.latency_buckets) {
+            cumulative += count;
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+            )?;
+        }
+        writeln!(
+            out,
+            "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+            stats.latency_count
+        )?;
+        writeln!(
+            out,
+            "http_request_duration_seconds_sum{{{labels}}} {}",
+            stats.latency_sum
+        )?;
+        writeln!(
+            out,
+            "http_request_duration_seconds_count{{{labels}}} {}",
+            stats.latency_count
+        )?;
+    }
+    Ok(())
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered() -> String {
+        let mut out = String::new();
+        render_in_process_metrics(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn latency_buckets_are_cumulative() {
+        record_request("GET".into(), "/test/buckets".into(), 200, 0.007);
+        record_request("GET".into(), "/test/buckets".into(), 500, 0.3);
+
+        let out = rendered();
+
+        let labels = r#"method="GET",route="/test/buckets""#;
+        assert!(out.contains(&format!(
+            r#"http_requests_total{{{labels},status="200"}} 1"#
+        )));
+        assert!(out.contains(&format!(
+            r#"http_requests_total{{{labels},status="500"}} 1"#
+        )));
+        assert!(out.contains(&format!(
+            r#"http_request_duration_seconds_bucket{{{labels},le="0.005"}} 0"#
+        )));
+        assert!(out.contains(&format!(
+            r#"http_request_duration_seconds_bucket{{{labels},le="0.01"}} 1"#
+        )));
+        assert!(out.contains(&format!(
+            r#"http_request_duration_seconds_bucket{{{labels},le="0.5"}} 2"#
+        )));
+        assert!(out.contains(&format!(
+            r#"http_request_duration_seconds_count{{{labels}}} 2"#
+        )));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
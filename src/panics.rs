@@ -0,0 +1,138 @@
+//! Panics are caught where they would otherwise take something down with them:
+//! a request handler, dropping the connection, or a background worker, stopping the process.
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse, ResponseError};
+use futures_util::FutureExt;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing_actix_web::RequestId;
+
+/// Caught panics since the application started.
+#[derive(serde::Serialize)]
+pub struct PanicCounts {
+    handlers: AtomicU64,
+    workers: AtomicU64,
+}
+
+pub static PANICS: PanicCounts = PanicCounts {
+    handlers: AtomicU64::new(0),
+    workers: AtomicU64::new(0),
+};
+
+/// Turns a panicking handler into a 500 carrying the request id, so that the client
+/// gets an answer and the logs can be searched for what went wrong.
+/// Must run inside `TracingLogger`, which assigns the request id.
+pub async fn catch_panics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.to_string());
+    // The request is lost with the panicking handler: the 500 is built from an error instead.
+    match AssertUnwindSafe(next.call(req)).catch_unwind().await {
+        Ok(outcome) => outcome,
+        Err(payload) => {
+            PANICS.handlers.fetch_add(1, Ordering::Relaxed);
+            tracing::error!(
+                panic.message = panic_message(&*payload),
+                "A request handler panicked"
+            );
+            Err(HandlerPanicked { request_id }.into())
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The request handler panicked.")]
+pub struct HandlerPanicked {
+    request_id: Option<String>,
+}
+
+impl ResponseError for HandlerPanicked {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": "internal_server_error",
+            "request_id": self.request_id,
+        }))
+    }
+}
+
+/// Run one unit of a background worker's work. Returns `None` if it panicked.
+pub async fn catch_worker_panic<F: Future>(worker: &'static str, task: F) -> Option<F::Output> {
+    match AssertUnwindSafe(task).catch_unwind().await {
+        Ok(output) => Some(output),
+        Err(payload) => {
+            PANICS.workers.fetch_add(1, Ordering::Relaxed);
+            tracing::error!(
+                worker,
+                panic.message = panic_message(&*payload),
+                "A background worker panicked"
+            );
+            None
+        }
+    }
+}
+
+// `panic!` payloads are a `&str` or a `String`, unless raised with `panic_any`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PANICS, catch_panics, catch_worker_panic};
+    use actix_web::body::to_bytes;
+    use actix_web::dev::Service;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, HttpResponse, test, web};
+    use std::sync::atomic::Ordering;
+
+    async fn panicking_handler() -> HttpResponse {
+        panic!("boom")
+    }
+
+    #[actix_web::test]
+    async fn a_panicking_handler_gets_a_500() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(catch_panics))
+                .route("/", web::get().to(panicking_handler)),
+        )
+        .await;
+        let before = PANICS.handlers.load(Ordering::Relaxed);
+
+        let error = app
+            .call(test::TestRequest::get().to_request())
+            .await
+            .err()
+            .expect("The panic was not turned into an error.");
+
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 500);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal_server_error");
+        assert!(PANICS.handlers.load(Ordering::Relaxed) > before);
+    }
+
+    #[actix_web::test]
+    async fn a_panicking_worker_task_is_reported_as_none() {
+        assert_eq!(catch_worker_panic("test", async { 1 }).await, Some(1));
+        assert_eq!(
+            catch_worker_panic("test", async { panic!("boom") }).await,
+            None::<()>
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Optional encryption of subscriber emails and names at rest, with AES-256-GCM.
+//!
+//! Encrypted values are stored as `(pii){key_id}:{base64(nonce || ciphertext)}`. `(` can't
+//! start a subscriber name or email address, so values without the prefix are plaintext:
+//! rows written before encryption was turned on read back as they are until
+//! `encrypt_stored_pii` gets to them.
+//!
+//! Ciphertexts are randomised, so emails are looked up through a blind index instead:
+//! an HMAC of the lowercased address, stored in `subscriptions.email_blind_index`.
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const PREFIX: &str = "(pii)";
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts subscriber PII on its way into the database and decrypts it on its way out.
+/// Disabled, it passes values through untouched.
+pub struct PiiCipher(Option<Keys>);
+
+struct Keys {
+    current_key_id: String,
+    ciphers: HashMap<String, Aes256Gcm>,
+    blind_index_key: SecretString,
+}
+
+// Key ids only: the keys themselves stay out of logs.
+impl std::fmt::Debug for PiiCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(keys) => f
+                .debug_struct("PiiCipher")
+                .field("current_key_id", &keys.current_key_id)
+                .field("key_ids", &keys.ciphers.keys().collect::<Vec<_>>())
+                .finish(),
+            None => f.write_str("PiiCipher(disabled)"),
+        }
+    }
+}
+
+impl PiiCipher {
+    pub fn disabled() -> Self {
+        Self(None)
+    }
+
+    /// `keys` are base64-encoded 32-byte AES keys, by id. New values are encrypted with the
+    /// current key; the others are kept to decrypt values encrypted before a rotation.
+    pub fn new(
+        current_key_id: String,
+        keys: HashMap<String, SecretString>,
+        blind_index_key: SecretString,
+    ) -> Result<Self, String> {
+        let mut ciphers = HashMap::with_capacity(keys.len());
+        for (id, key) in &keys {
+            if id.is_empty() || id.contains(':') {
+                return Err(format!("`{id}` is not a valid PII encryption key id."));
+            }
+            let key = base64::engine::general_purpose::STANDARD
+                .decode(key.expose_secret())
+                .ok()
+                .filter(|key| key.len() == 32)
+                .ok_or_else(|| {
+                    format!("The PII encryption key `{id}` must be 32 bytes, base64-encoded.")
+                })?;
+            ciphers.insert(id.clone(), Aes256Gcm::new_from_slice(&key).unwrap());
+        }
+        if !ciphers.contains_key(&current_key_id) {
+            return Err(format!(
+                "There is no PII encryption key with id `{current_key_id}`."
+            ));
+        }
+        if blind_index_key.expose_secret().len() < 32 {
+            return Err("The blind index key must be at least 32 bytes long.".into());
+        }
+        Ok(Self(Some(Keys {
+            current_key_id,
+            ciphers,
+            blind_index_key,
+        })))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn encrypt(&self, value: &str) -> String {
+        let Some(keys) = &self.0 else {
+            return value.to_owned();
+        };
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = keys.ciphers[&keys.current_key_id]
+            .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+            .expect("Failed to encrypt a value with AES-GCM.");
+        format!(
+            "{PREFIX}{}:{}",
+            keys.current_key_id,
+            STANDARD_NO_PAD.encode([nonce.as_slice(), &ciphertext].concat())
+        )
+    }
+
+    /// Plaintext values are returned as they are, whether or not encryption is enabled.
+    pub fn decrypt(&self, stored: &str) -> Result<String, anyhow::Error> {
+        let Some(encrypted) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_owned());
+        };
+        let keys = self
+            .0
+            .as_ref()
+            .context("The value is encrypted, but PII encryption is not configured.")?;
+        let (key_id, payload) = encrypted
+            .split_once(':')
+            .context("The encrypted value is malformed.")?;
+        let cipher = keys
+            .ciphers
+            .get(key_id)
+            .with_context(|| format!("There is no PII encryption key with id `{key_id}`."))?;
+        let payload = STANDARD_NO_PAD
+            .decode(payload)
+            .context("The encrypted value is not valid base64.")?;
+        if payload.len() < NONCE_LENGTH {
+            anyhow::bail!("The encrypted value is too short.");
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt a value with key `{key_id}`."))?;
+        String::from_utf8(plaintext).context("The decrypted value is not valid UTF-8.")
+    }
+
+    /// `None` when encryption is disabled: emails are looked up as they are then.
+    pub fn email_index(&self, email: &str) -> Option<String> {
+        let keys = self.0.as_ref()?;
+        // `KeyInit` is in scope for AES-GCM, hence the qualified call.
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(keys.blind_index_key.expose_secret().as_bytes())
+                .expect("HMAC can take a key of any size");
+        mac.update(email.to_lowercase().as_bytes());
+        Some(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Encrypt the emails and names of subscribers stored before encryption was turned on,
+/// `batch_size` rows at a time. Returns how many subscribers were encrypted.
+#[tracing::instrument(name = "Encrypt stored PII", skip(pool, cipher))]
+pub async fn encrypt_stored_pii(
+    pool: &PgPool,
+    cipher: &PiiCipher,
+    batch_size: i64,
+) -> Result<u64, anyhow::Error> {
+    if !cipher.is_enabled() {
+        anyhow::bail!("PII encryption is not configured.");
+    }
+    let mut encrypted = 0;
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, email, name FROM subscriptions
+            WHERE email_blind_index IS NULL
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            batch_size
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to fetch subscribers with plaintext PII.")?;
+        if rows.is_empty() {
+            return Ok(encrypted);
+        }
+        for row in rows {
+            let email = cipher.decrypt(&row.email)?;
+            let name = cipher.decrypt(&row.name)?;
+            store_encrypted(&mut transaction, cipher, row.id, &email, &name).await?;
+            encrypted += 1;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to encrypt stored PII.")?;
+    }
+}
+
+async fn store_encrypted(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cipher: &PiiCipher,
+    subscriber_id: Uuid,
+    email: &str,
+    name: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET email = $1, name = $2, email_blind_index = $3 WHERE id = $4"#,
+        cipher.encrypt(email),
+        cipher.encrypt(name),
+        cipher.email_index(email),
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store encrypted PII.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PiiCipher;
+    use claim::{assert_err, assert_ok};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    fn cipher(current_key_id: &str) -> PiiCipher {
+        let keys = HashMap::from([
+            ("old".to_string(), SecretString::from(KEY)),
+            (
+                "new".to_string(),
+                SecretString::from("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA="),
+            ),
+        ]);
+        PiiCipher::new(
+            current_key_id.into(),
+            keys,
+            SecretString::from("blind-index-key-blind-index-key-"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn values_round_trip_and_are_not_stored_in_plaintext() {
+        let cipher = cipher("new");
+        let encrypted = cipher.encrypt("ursula@example.com");
+        assert!(!encrypted.contains("ursula"));
+        assert_ne!(encrypted, cipher.encrypt("ursula@example.com"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "ursula@example.com");
+    }
+
+    #[test]
+    fn values_encrypted_with_a_rotated_out_key_can_still_be_decrypted() {
+        let encrypted = cipher("old").encrypt("Ursula");
+        assert_eq!(cipher("new").decrypt(&encrypted).unwrap(), "Ursula");
+    }
+
+    #[test]
+    fn plaintext_values_are_passed_through() {
+        assert_eq!(cipher("new").decrypt("Ursula").unwrap(), "Ursula");
+        assert_eq!(PiiCipher::disabled().encrypt("Ursula"), "Ursula");
+    }
+
+    #[test]
+    fn encrypted_values_cannot_be_read_without_the_keys() {
+        let encrypted = cipher("new").encrypt("Ursula");
+        assert_err!(PiiCipher::disabled().decrypt(&encrypted));
+    }
+
+    #[test]
+    fn the_email_index_ignores_case() {
+        let cipher = cipher("new");
+        assert_eq!(
+            cipher.email_index("Ursula@Example.com"),
+            cipher.email_index("ursula@example.com")
+        );
+        assert_eq!(
+            PiiCipher::disabled().email_index("ursula@example.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes() {
+        let keys = HashMap::from([("k".to_string(), SecretString::from("c2hvcnQ="))]);
+        assert_err!(PiiCipher::new(
+            "k".into(),
+            keys,
+            SecretString::from("blind-index-key-blind-index-key-")
+        ));
+        let keys = HashMap::from([("k".to_string(), SecretString::from(KEY))]);
+        assert_ok!(PiiCipher::new(
+            "k".into(),
+            keys,
+            SecretString::from("blind-index-key-blind-index-key-")
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! Checks run before the application starts serving traffic.
+//!
+//! After a partial deploy the database can be ahead of or behind the binary, and the
+//! queries compiled into it would fail at runtime rather than at startup.
+use sqlx::PgPool;
+use sqlx::migrate::{MigrateError, Migrator};
+use std::collections::HashMap;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MigrationDrift {
+    #[error("Migration {0} ({1}) has not been applied to the database.")]
+    Pending(i64, String),
+    #[error("Migration {0} is applied to the database but unknown to this build.")]
+    Unknown(i64),
+    #[error("Migration {0} was changed after it was applied.")]
+    ChecksumMismatch(i64),
+    #[error("Migration {0} failed part-way through.")]
+    Failed(i64),
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMigrationDrift {
+    #[default]
+    Warn,
+    Fail,
+}
+
+/// Apply the migrations embedded in this build that the database is missing. Instances
+/// starting together take turns: the migrator holds an advisory lock while it runs.
+#[tracing::instrument(name = "Run pending migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await
+}
+
+/// Compare the migrations applied to the database with the ones embedded in this build.
+#[tracing::instrument(name = "Check for migration drift", skip(pool))]
+pub async fn detect_migration_drift(pool: &PgPool) -> Result<Vec<MigrationDrift>, sqlx::Error> {
+    let has_migrations_table: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    let applied: HashMap<i64, (Vec<u8>, bool)> = if has_migrations_table {
+        sqlx::query_as::<_, (i64, Vec<u8>, bool)>(
+            "SELECT version, checksum, success FROM _sqlx_migrations",
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(version, checksum, success)| (version, (checksum, success)))
+        .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let known: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .collect();
+    let mut drift = Vec::new();
+    for migration in &known {
+        match applied.get(&migration.version) {
+            None => drift.push(MigrationDrift::Pending(
+                migration.version,
+                migration.description.to_string(),
+            )),
+            Some((_, false)) => drift.push(MigrationDrift::Failed(migration.version)),
+            Some((checksum, true)) if checksum[..] != migration.checksum[..] => {
+                drift.push(MigrationDrift::ChecksumMismatch(migration.version))
+            }
+            Some(_) => {}
+        }
+    }
+    let mut unknown: Vec<_> = applied
+        .keys()
+        .filter(|version| known.iter().all(|m| m.version != **version))
+        .copied()
+        .collect();
+    unknown.sort();
+    drift.extend(unknown.into_iter().map(MigrationDrift::Unknown));
+    Ok(drift)
+}
+
+/// Report migration drift, and refuse to start on it when `policy` says so.
+pub async fn check_migrations(pool: &PgPool, policy: OnMigrationDrift) -> Result<(), String> {
+    let drift = match detect_migration_drift(pool).await {
+        Ok(drift) => drift,
+        Err(error) => {
+            let message = format!("Failed to check for migration drift: {}", error);
+            return match policy {
+                OnMigrationDrift::Warn => {
+                    tracing::warn!("{}", message);
+                    Ok(())
+                }
+                OnMigrationDrift::Fail => Err(message),
+            };
+        }
+    };
+    for item in &drift {
+        tracing::warn!("{}", item);
+    }
+    match (policy, drift.is_empty()) {
+        (OnMigrationDrift::Fail, false) => Err(format!(
+            "The database schema does not match this build ({} migration(s) out of sync).",
+            drift.len()
+        )),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,119 @@
+//! Per-client rate limiting with token buckets, kept in the shared cache.
+//!
+//! Each client can make `max_requests` at once, and gets one more every
+//! `window / max_requests` after that. With the in-memory cache each replica has its own
+//! buckets; use the Redis backend for a limit that holds across all of them.
+use crate::admin_access::request_client_ip;
+use crate::cache::Cache;
+use actix_web::HttpResponse;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::RETRY_AFTER;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct RateLimiter {
+    cache: Arc<dyn Cache>,
+    // Keeps the counters of different limiters apart in the cache.
+    scope: &'static str,
+    max_requests: u32,
+    window: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(
+        cache: Arc<dyn Cache>,
+        scope: &'static str,
+        max_requests: u32,
+        window: Duration,
+    ) -> Self {
+        Self {
+            cache,
+            scope,
+            max_requests: max_requests.max(1),
+            window,
+        }
+    }
+
+    /// Record a request from `client`.
+    /// Returns how long the client has to wait for its next request if it is over its limit.
+    pub async fn check(&self, client: impl Display) -> Result<(), Duration> {
+        let key = format!("rate_limit:{}:{}", self.scope, client);
+        let refill = self.window / self.max_requests;
+        match self.cache.take_token(&key, self.max_requests, refill).await {
+            Ok(Some(retry_after)) => Err(retry_after),
+            Ok(None) => Ok(()),
+            // Better to let some requests through than to turn everyone away.
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    scope = self.scope,
+                    "Failed to count a request against its rate limit"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A `429` telling the client when to come back, if the client that sent `req` is over
+/// `limiter`'s limit. For rate-limiting middleware to answer with.
+pub async fn reject_over_limit(
+    limiter: &RateLimiter,
+    req: &ServiceRequest,
+) -> Option<HttpResponse> {
+    // `X-Forwarded-For` only counts when a trusted proxy set it: clients control it otherwise.
+    let client_ip = request_client_ip(req.request())?;
+    let retry_after = limiter.check(client_ip).await.err()?;
+    Some(too_many_requests(retry_after))
+}
+
+/// A `429` telling the client to come back after `retry_after`, in whole seconds.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((
+            RETRY_AFTER,
+            retry_after.as_secs_f64().ceil().max(1.0) as u64,
+        ))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use crate::cache::InMemoryCache;
+    use claim::{assert_err, assert_ok};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn limiter(max_requests: u32) -> RateLimiter {
+        RateLimiter::new(
+            Arc::new(InMemoryCache::new(100)),
+            "test",
+            max_requests,
+            Duration::from_secs(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected_until_a_token_comes_back() {
+        let limiter = limiter(2);
+        assert_ok!(limiter.check("a").await);
+        assert_ok!(limiter.check("a").await);
+        // One token every half second.
+        let retry_after = limiter.check("a").await.unwrap_err();
+        assert!(retry_after > Duration::from_millis(400));
+        assert!(retry_after <= Duration::from_millis(500));
+        tokio::time::sleep(retry_after).await;
+        assert_ok!(limiter.check("a").await);
+        assert_err!(limiter.check("a").await);
+    }
+
+    #[tokio::test]
+    async fn clients_are_limited_independently() {
+        let limiter = limiter(1);
+        assert_ok!(limiter.check("a").await);
+        assert_err!(limiter.check("a").await);
+        assert_ok!(limiter.check("b").await);
+    }
+}
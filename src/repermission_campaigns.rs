@@ -0,0 +1,341 @@
+//! Re-permission campaigns: asking the subscribers of a list with a tag whether they still
+//! want its issues, e.g. after importing an old list, and unsubscribing those who don't
+//! answer.
+//!
+//! An admin starts a campaign with `POST /admin/repermission_campaigns`, which records who
+//! it asks. The `repermission_campaigns` job sends them their "stay subscribed" email,
+//! within the send budget and retrying failed emails on its next runs, then closes the
+//! campaigns whose window is over: whoever got the email and didn't click is unsubscribed.
+//! Those it couldn't reach are left alone.
+use crate::audit_log::{Source, SubscriberEvent, record_all};
+use crate::configuration::Settings;
+use crate::database;
+use crate::domain::{ActionBaseUrl, Language, SubscriberEmail, SubscriberName};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::pii::PiiCipher;
+use crate::scheduler::Job;
+use crate::send_budget;
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use crate::templates::{EmailTemplates, RepermissionEmail};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+const JOB_NAME: &str = "repermission_campaigns";
+/// Emails are given up on after this many failed attempts.
+pub const MAX_ATTEMPTS: i32 = 5;
+
+/// Sends the emails of re-permission campaigns and closes them, as a scheduled job.
+pub struct RepermissionCampaigns {
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    templates: EmailTemplates,
+    token_signer: TokenSigner,
+    pii_cipher: PiiCipher,
+    action_base_url: ActionBaseUrl,
+}
+
+impl RepermissionCampaigns {
+    pub fn from_configuration(
+        configuration: &Settings,
+        pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            pool,
+            email_client,
+            templates: EmailTemplates::load(&configuration.application.templates_dir)?,
+            token_signer: configuration
+                .signing
+                .token_signer()
+                .map_err(anyhow::Error::msg)?,
+            pii_cipher: configuration.pii_cipher().map_err(anyhow::Error::msg)?,
+            action_base_url: configuration
+                .action_base_url()
+                .map_err(anyhow::Error::msg)?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for RepermissionCampaigns {
+    fn name(&self) -> &'static str {
+        JOB_NAME
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        let summary = run_repermission_campaigns(
+            &self.pool,
+            &self.email_client,
+            &self.templates,
+            &self.token_signer,
+            &self.pii_cipher,
+            &self.action_base_url,
+        )
+        .await?;
+        if summary != CampaignSummary::default() {
+            tracing::info!(
+                sent = summary.sent,
+                failed = summary.failed,
+                unsubscribed = summary.unsubscribed,
+                "Ran re-permission campaigns"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CampaignSummary {
+    pub sent: u64,
+    pub failed: u64,
+    pub unsubscribed: u64,
+}
+
+/// Sends the emails that are due, then closes the campaigns whose window is over.
+#[tracing::instrument(name = "Run re-permission campaigns", skip_all)]
+pub async fn run_repermission_campaigns(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+) -> Result<CampaignSummary, anyhow::Error> {
+    let mut summary = CampaignSummary::default();
+    // Emails that failed are tried again on the next run, not on this one.
+    let started_at = Utc::now();
+    while let Some((sent, failed)) = send_batch(
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        started_at,
+    )
+    .await?
+    {
+        summary.sent += sent;
+        summary.failed += failed;
+    }
+    summary.unsubscribed = close_due_campaigns(pool).await?;
+    Ok(summary)
+}
+
+/// Sends a batch of the emails of open campaigns, as many as the send budget allows,
+/// returning how many were sent and how many failed: `None` once there is nothing left to
+/// send, or no budget left. They stay locked while they are sent, so that two instances
+/// don't both send them.
+async fn send_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    started_at: chrono::DateTime<Utc>,
+) -> Result<Option<(u64, u64)>, anyhow::Error> {
+    let settings = email_client.delivery();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let requests = sqlx::query!(
+        r#"
+        SELECT r.campaign_id, r.subscriber_id, r.token, r.attempts, s.email, s.name, s.language,
+            n.name AS newsletter, c.closes_at
+        FROM repermission_requests r
+        JOIN repermission_campaigns c ON c.id = r.campaign_id
+        JOIN subscriptions s ON s.id = r.subscriber_id
+        JOIN newsletters n ON n.id = c.newsletter_id
+        WHERE r.sent_at IS NULL
+          AND r.attempts < $1
+          AND (r.last_attempt_at IS NULL OR r.last_attempt_at < $2)
+          AND c.closed_at IS NULL AND c.closes_at > now()
+          AND s.status = 'confirmed' AND s.deleted_at IS NULL
+        ORDER BY c.created_at, r.subscriber_id
+        LIMIT $3
+        FOR UPDATE OF r SKIP LOCKED
+        "#,
+        MAX_ATTEMPTS,
+        started_at,
+        settings.batch_size.max(1) as i64
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the re-permission emails to send.")?;
+    let mut wanted = requests.len();
+    if let Some(budget) = &settings.send_budget
+        && wanted > 0
+    {
+        wanted = send_budget::take(pool, budget, wanted)
+            .await
+            .context("Failed to take from the send budget.")?;
+    }
+    if wanted == 0 {
+        return Ok(None);
+    }
+
+    let (mut sent, mut failed) = (0, 0);
+    // Those over the budget are left for later, untouched.
+    for request in requests.into_iter().take(wanted) {
+        let outcome = async {
+            let email = pii_cipher
+                .decrypt(&request.email)
+                .and_then(|email| SubscriberEmail::parse(email).map_err(anyhow::Error::msg))
+                .context("The stored email of the subscriber is invalid.")?;
+            let name = pii_cipher
+                .decrypt(&request.name)
+                .and_then(|name| SubscriberName::parse(name).map_err(anyhow::Error::msg))
+                .context("The stored name of the subscriber is invalid.")?;
+            let stay_link = format!(
+                "{}/subscriptions/stay?token={}",
+                action_base_url.as_ref(),
+                token_signer.sign(TokenPurpose::Repermission, &request.token)
+            );
+            let language = request
+                .language
+                .as_deref()
+                .and_then(|l| Language::parse(l).ok());
+            let (subject, body) = templates.repermission(
+                &RepermissionEmail {
+                    newsletter: &request.newsletter,
+                    name: name.as_ref(),
+                    stay_link: &stay_link,
+                    closes_on: &request.closes_at.format("%B %-d, %Y").to_string(),
+                },
+                language.as_ref(),
+            )?;
+            email_client
+                .send_email(
+                    &email,
+                    &subject,
+                    &body.html,
+                    &body.text,
+                    MessageCategory::ReEngagement,
+                )
+                .await
+                .context("Failed to send a re-permission email.")
+        }
+        .await;
+        let error = match outcome {
+            Ok(()) => {
+                sent += 1;
+                None
+            }
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    campaign_id = %request.campaign_id,
+                    attempts = request.attempts + 1,
+                    "Failed to send a re-permission email"
+                );
+                failed += 1;
+                Some(format!("{error:#}"))
+            }
+        };
+        sqlx::query!(
+            r#"
+            UPDATE repermission_requests SET
+                attempts = attempts + 1,
+                last_attempt_at = now(),
+                last_error = $3,
+                sent_at = CASE WHEN $3::text IS NULL THEN now() END
+            WHERE campaign_id = $1 AND subscriber_id = $2
+            "#,
+            request.campaign_id,
+            request.subscriber_id,
+            error
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to record a re-permission email.")?;
+    }
+    database::commit(transaction)
+        .await
+        .context("Failed to commit SQL transaction to send re-permission emails.")?;
+    Ok(Some((sent, failed)))
+}
+
+/// Unsubscribes, campaign by campaign, the subscribers who were asked and didn't click to
+/// stay in time. Returns how many were unsubscribed.
+#[tracing::instrument(name = "Close re-permission campaigns", skip_all)]
+async fn close_due_campaigns(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut unsubscribed = 0;
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        let Some(campaign_id) = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM repermission_campaigns
+            WHERE closed_at IS NULL AND closes_at <= now()
+            ORDER BY closes_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch the next re-permission campaign to close.")?
+        else {
+            break;
+        };
+        // The requests are updated first: a click that comes in meanwhile either lands
+        // before, and is seen here, or finds the request closed.
+        let subscriber_ids = sqlx::query_scalar!(
+            r#"
+            UPDATE repermission_requests SET unsubscribed_at = now()
+            WHERE campaign_id = $1
+              AND sent_at IS NOT NULL AND stayed_at IS NULL AND unsubscribed_at IS NULL
+              AND subscriber_id IN (
+                  SELECT id FROM subscriptions WHERE status = 'confirmed' AND deleted_at IS NULL
+              )
+            RETURNING subscriber_id
+            "#,
+            campaign_id
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to close the requests of a re-permission campaign.")?;
+        let subscriber_ids = sqlx::query_scalar!(
+            r#"
+            UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()
+            WHERE id = ANY($1) AND status = 'confirmed' AND deleted_at IS NULL
+            RETURNING id
+            "#,
+            &subscriber_ids
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to unsubscribe the subscribers who didn't stay.")?;
+        record_all(
+            &mut *transaction,
+            &subscriber_ids,
+            SubscriberEvent::Unsubscribed,
+            &Source::job(JOB_NAME),
+        )
+        .await
+        .context("Failed to record the unsubscriptions of a re-permission campaign.")?;
+        sqlx::query!(
+            "UPDATE repermission_campaigns SET closed_at = now() WHERE id = $1",
+            campaign_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to close a re-permission campaign.")?;
+        database::commit(transaction)
+            .await
+            .context("Failed to commit SQL transaction to close a re-permission campaign.")?;
+        tracing::info!(
+            %campaign_id,
+            unsubscribed = subscriber_ids.len(),
+            "Closed a re-permission campaign"
+        );
+        unsubscribed += subscriber_ids.len() as u64;
+    }
+    Ok(unsubscribed)
+}
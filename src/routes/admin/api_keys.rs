@@ -0,0 +1,197 @@
+//! API keys, for scripts and CI pipelines to publish with. Admins only, and not with a key:
+//! keys can't manage keys.
+use crate::api_error::ApiError;
+use crate::api_keys::{ApiScope, generate_api_key, hash_api_key};
+use crate::authentication::AuthenticatedUser;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// A key, without the key itself.
+#[derive(serde::Serialize)]
+pub struct ApiKeySummary {
+    id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    // The admin it acts as.
+    created_by: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewApiKeyData {
+    name: String,
+    scopes: Vec<String>,
+    // Keys without one work until they are revoked.
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct NewApiKey {
+    id: Uuid,
+    // Only ever shown here.
+    key: String,
+    name: String,
+    scopes: Vec<ApiScope>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "List API keys", skip_all)]
+pub async fn list_api_keys(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiKeyError> {
+    let api_keys = sqlx::query_as!(
+        ApiKeySummary,
+        r#"
+        SELECT k.id, k.name, k.scopes, u.username AS created_by, k.created_at, k.expires_at,
+            k.last_used_at, k.revoked_at
+        FROM api_keys k JOIN users u ON u.user_id = k.created_by
+        ORDER BY k.created_at, k.id
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch API keys from the database.")?;
+    Ok(HttpResponse::Ok().json(api_keys))
+}
+
+/// The key acts as the admin creating it.
+#[tracing::instrument(
+    name = "Create an API key",
+    skip_all,
+    fields(name = %body.name)
+)]
+pub async fn create_api_key(
+    user: web::ReqData<AuthenticatedUser>,
+    body: web::Json<NewApiKeyData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiKeyError> {
+    let NewApiKeyData {
+        name,
+        scopes,
+        expires_at,
+    } = body.into_inner();
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiKeyError::ValidationError(format!(
+            "API key names must be between 1 and {MAX_NAME_LENGTH} characters long."
+        )));
+    }
+    let mut scopes = scopes
+        .iter()
+        .map(|scope| ApiScope::parse(scope))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiKeyError::ValidationError)?;
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(ApiKeyError::ValidationError(
+            "An API key needs at least one scope.".into(),
+        ));
+    }
+    let created_at = Utc::now();
+    if expires_at.is_some_and(|expires_at| expires_at <= created_at) {
+        return Err(ApiKeyError::ValidationError(
+            "An API key can't expire in the past.".into(),
+        ));
+    }
+    let id = Uuid::new_v4();
+    let key = generate_api_key();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_keys (id, name, key_hash, scopes, created_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        id,
+        name,
+        hash_api_key(&key),
+        &scopes
+            .iter()
+            .map(|scope| scope.as_str().to_owned())
+            .collect::<Vec<_>>(),
+        user.user_id,
+        created_at,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the API key in the database.")?;
+    Ok(HttpResponse::Created().json(NewApiKey {
+        id,
+        key,
+        name: name.to_owned(),
+        scopes,
+        created_at,
+        expires_at,
+    }))
+}
+
+/// The key stops working right away. It is kept, along with its usage.
+#[tracing::instrument(
+    name = "Revoke an API key",
+    skip_all,
+    fields(api_key_id = %api_key_id)
+)]
+pub async fn revoke_api_key(
+    api_key_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiKeyError> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = COALESCE(revoked_at, now())
+        WHERE id = $1
+        "#,
+        *api_key_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke the API key.")?
+    .rows_affected();
+    if revoked == 0 {
+        return Err(ApiKeyError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiKeyError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The API key does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiKeyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiKeyError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiKeyError::NotFound => StatusCode::NOT_FOUND,
+            ApiKeyError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            ApiKeyError::ValidationError(e) => ApiError::validation(e),
+            ApiKeyError::NotFound => ApiError::not_found(self.to_string()),
+            ApiKeyError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,297 @@
+//! The public archive of a list as a static site in a zip, for operators who want to mirror
+//! it elsewhere or keep it offline.
+//!
+//! Its pages are rendered by the templates of the archive served here, with the links
+//! between them made relative: `index.html` lists the issues, each of them is at
+//! `issues/{newsletter_issue_id}.html`, and the images they show are bundled under `assets/`.
+//! Images that can't be fetched are left linked to where they were; so is the feed, which is
+//! only of use where it is served.
+use crate::api_error::ApiError;
+use crate::database::ReadPool;
+use crate::routes::{ArchivedIssue, DEFAULT_LIST_SLUG, error_chain_fmt, get_newsletter, list_path};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{ArchivePage, ArchivedIssuePage, EmailTemplates};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use futures_util::{StreamExt, stream};
+use lol_html::{RewriteStrSettings, element, rewrite_str};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Write};
+use std::time::Duration;
+use uuid::Uuid;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const ASSET_TIMEOUT: Duration = Duration::from_secs(10);
+const ASSET_CONCURRENCY: usize = 8;
+// Larger images stay linked to rather than bloat the bundle.
+const MAX_ASSET_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveExportQuery {
+    // The default list when missing.
+    newsletter: Option<String>,
+}
+
+/// The public issues of a list as a zip of static pages. Private issues are left out.
+#[tracing::instrument(
+    name = "Export the archive of a list",
+    skip_all,
+    fields(newsletter = query.newsletter)
+)]
+pub async fn export_archive(
+    query: web::Query<ArchiveExportQuery>,
+    read_pool: web::Data<ReadPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveExportError> {
+    let read_pool = read_pool.get().await;
+    let slug = query.newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(read_pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            ArchiveExportError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, html_content, published_at FROM newsletter_issues
+        WHERE newsletter_id = $1 AND NOT private
+        ORDER BY published_at DESC, newsletter_issue_id
+        "#,
+        newsletter.id
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the issues of the archive.")?;
+
+    let mut sources = BTreeSet::new();
+    for row in &rows {
+        sources.extend(image_sources(&row.html_content)?);
+    }
+    let assets = fetch_assets(sources).await?;
+    let bundled: HashMap<String, String> = assets
+        .iter()
+        .map(|(source, (name, _))| (source.clone(), format!("../{name}")))
+        .collect();
+    let issues = rows
+        .into_iter()
+        .map(|row| {
+            let html_content = rewrite_image_sources(&row.html_content, &bundled)?;
+            Ok(ArchivedIssue::new(
+                issue_path(row.newsletter_issue_id),
+                row.newsletter_issue_id,
+                row.title,
+                Some(html_content),
+                row.published_at,
+            ))
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    let mut bundle = ZipWriter::new(Cursor::new(Vec::new()));
+    let index = templates
+        .archive_page(&ArchivePage {
+            newsletter: &newsletter.name,
+            issues: &issues,
+            feed_link: &format!("{}{}/feed.xml", base_url.0, list_path(&newsletter.slug)),
+        })
+        .context("Failed to render the archive.")?;
+    add_file(&mut bundle, "index.html", index.as_bytes())?;
+    for issue in &issues {
+        let path = issue_path(issue.newsletter_issue_id());
+        let page = templates
+            .archived_issue_page(&ArchivedIssuePage {
+                newsletter: &newsletter.name,
+                archive_link: "../index.html",
+                issue,
+            })
+            .context("Failed to render an archived issue.")?;
+        add_file(&mut bundle, &path, page.as_bytes())?;
+    }
+    for (name, content) in assets.values() {
+        add_file(&mut bundle, name, content)?;
+    }
+    let bundle = bundle
+        .finish()
+        .context("Failed to finish the archive bundle.")?
+        .into_inner();
+    tracing::info!(
+        issues = issues.len(),
+        assets = assets.len(),
+        bytes = bundle.len(),
+        "Exported the archive"
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}-archive.zip",
+                newsletter.slug
+            ))],
+        })
+        .body(bundle))
+}
+
+fn issue_path(newsletter_issue_id: Uuid) -> String {
+    format!("issues/{newsletter_issue_id}.html")
+}
+
+fn add_file(
+    bundle: &mut ZipWriter<Cursor<Vec<u8>>>,
+    name: &str,
+    content: &[u8],
+) -> Result<(), anyhow::Error> {
+    bundle
+        .start_file(name, SimpleFileOptions::default())
+        .with_context(|| format!("Failed to add `{name}` to the archive bundle."))?;
+    bundle
+        .write_all(content)
+        .with_context(|| format!("Failed to write `{name}` into the archive bundle."))
+}
+
+/// The absolute http(s) sources of the images of `html`.
+fn image_sources(html: &str) -> Result<Vec<String>, anyhow::Error> {
+    let sources = RefCell::new(Vec::new());
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                if let Some(source) = el.get_attribute("src")
+                    && Url::parse(&source)
+                        .is_ok_and(|url| ["http", "https"].contains(&url.scheme()))
+                {
+                    sources.borrow_mut().push(source);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .context("Failed to parse the HTML of an issue.")?;
+    Ok(sources.into_inner())
+}
+
+/// Points the images of `html` that were bundled at their copy.
+fn rewrite_image_sources(
+    html: &str,
+    bundled: &HashMap<String, String>,
+) -> Result<String, anyhow::Error> {
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                if let Some(path) = el.get_attribute("src").and_then(|src| bundled.get(&src)) {
+                    el.set_attribute("src", path)?;
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .context("Failed to rewrite the images of an issue.")
+}
+
+/// The images of `sources` that could be fetched, by their source: the name they are
+/// bundled under, and their content.
+async fn fetch_assets(
+    sources: BTreeSet<String>,
+) -> Result<HashMap<String, (String, Vec<u8>)>, anyhow::Error> {
+    let http_client = reqwest::Client::builder()
+        .timeout(ASSET_TIMEOUT)
+        .build()
+        .context("Failed to build the HTTP client for the assets of the archive.")?;
+    Ok(stream::iter(sources)
+        .map(|source| {
+            let http_client = &http_client;
+            async move {
+                let content = fetch_asset(http_client, &source).await?;
+                Some((source.clone(), (asset_name(&source), content)))
+            }
+        })
+        .buffer_unordered(ASSET_CONCURRENCY)
+        .filter_map(|asset| async move { asset })
+        .collect()
+        .await)
+}
+
+async fn fetch_asset(http_client: &reqwest::Client, source: &str) -> Option<Vec<u8>> {
+    let content = async {
+        http_client
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    }
+    .await;
+    match content {
+        Ok(content) if content.len() <= MAX_ASSET_BYTES => Some(content.to_vec()),
+        Ok(content) => {
+            tracing::warn!(
+                source,
+                bytes = content.len(),
+                "Left a large image out of the bundle"
+            );
+            None
+        }
+        Err(error) => {
+            tracing::warn!(source, error = %error, "Failed to fetch an image of the archive");
+            None
+        }
+    }
+}
+
+/// `assets/` followed by a digest of `source`, keeping its extension for browsers to go by.
+fn asset_name(source: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(source.as_bytes()));
+    let extension = Url::parse(source)
+        .ok()
+        .and_then(|url| {
+            let file = url.path_segments()?.next_back()?.to_owned();
+            let (_, extension) = file.rsplit_once('.')?;
+            (!extension.is_empty()
+                && extension.len() <= 5
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+            .then(|| format!(".{}", extension.to_ascii_lowercase()))
+        })
+        .unwrap_or_default();
+    format!("assets/{}{extension}", &digest[..16])
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveExportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveExportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveExportError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ArchiveExportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            ArchiveExportError::ValidationError(e) => ApiError::validation(e),
+            ArchiveExportError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,53 @@
+use crate::branding::{Branding, load_branding, save_branding};
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[tracing::instrument(name = "Get branding", skip(pool))]
+pub async fn get_branding(pool: web::Data<PgPool>) -> Result<HttpResponse, BrandingError> {
+    let branding = load_branding(&pool)
+        .await
+        .context("Failed to load the branding.")?;
+    Ok(HttpResponse::Ok().json(branding))
+}
+
+/// Replaces the whole branding: fields left out are cleared.
+#[tracing::instrument(name = "Update branding", skip(body, pool))]
+pub async fn update_branding(
+    body: web::Json<Branding>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BrandingError> {
+    let branding = body.into_inner();
+    branding
+        .validate()
+        .map_err(BrandingError::ValidationError)?;
+    save_branding(&pool, &branding)
+        .await
+        .context("Failed to save the branding.")?;
+    Ok(HttpResponse::Ok().json(branding))
+}
+
+#[derive(thiserror::Error)]
+pub enum BrandingError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for BrandingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for BrandingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BrandingError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            BrandingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Unsubscribing a pasted list of addresses at once, e.g. those a former provider had as
+//! unsubscribed, or those who asked to be removed by replying to an issue.
+//!
+//! A request without a `confirmation` only previews what would happen, and answers with
+//! the `confirmation` to send the same list again with. It signs the subscribers the list
+//! matched: if they changed in between, e.g. one of them signed up, the preview is stale
+//! and has to be made again.
+use crate::audit_log::{Actor, Source, SubscriberEvent, record_all};
+use crate::authentication::AuthenticatedUser;
+use crate::crypto::KeyRing;
+use crate::domain::SubscriberEmail;
+use crate::pii::PiiCipher;
+use crate::routes::{DEFAULT_LIST_SLUG, SubscriberError, get_newsletter};
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_ADDRESSES: usize = 100_000;
+// Subscribers unsubscribed per transaction.
+const BATCH_SIZE: usize = 500;
+const CONFIRMATION_PURPOSE: &str = "bulk-unsubscribe";
+
+#[derive(serde::Deserialize)]
+pub struct BulkUnsubscribeData {
+    // One address per line, or separated by commas or semicolons.
+    emails: String,
+    // The slug of the list to unsubscribe them from; the default list if there is none.
+    #[serde(default)]
+    newsletter: Option<String>,
+    // From the preview of the same list.
+    #[serde(default)]
+    confirmation: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BulkUnsubscribePreview {
+    dry_run: bool,
+    // Subscribers of the list who would be unsubscribed.
+    matched: usize,
+    // Subscribers of the list who already unsubscribed or bounced.
+    already_unsubscribed: usize,
+    // Valid addresses with no subscriber on the list.
+    unknown: usize,
+    // Entries that aren't email addresses.
+    invalid: Vec<String>,
+    confirmation: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct BulkUnsubscribeReport {
+    dry_run: bool,
+    unsubscribed: u64,
+}
+
+/// Previews, or with a `confirmation` carries out, the unsubscription of every subscriber
+/// of the list among `emails`. Each batch is unsubscribed in a transaction along with its
+/// `unsubscribed` events: unsubscribed subscribers get no more issues, like those who used
+/// the link in an issue. Admins only.
+#[tracing::instrument(
+    name = "Unsubscribe subscribers in bulk",
+    skip_all,
+    fields(confirmed = body.confirmation.is_some())
+)]
+pub async fn bulk_unsubscribe(
+    request: HttpRequest,
+    user: web::ReqData<AuthenticatedUser>,
+    body: web::Json<BulkUnsubscribeData>,
+    pool: web::Data<PgPool>,
+    pii_cipher: web::Data<PiiCipher>,
+    key_ring: web::Data<KeyRing>,
+) -> Result<HttpResponse, SubscriberError> {
+    let BulkUnsubscribeData {
+        emails,
+        newsletter,
+        confirmation,
+    } = body.into_inner();
+    let slug = newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(&pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            SubscriberError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let (emails, invalid) = parse_emails(&emails)?;
+    let indexes: Vec<String> = emails
+        .iter()
+        .filter_map(|email| pii_cipher.email_index(email.as_ref()))
+        .collect();
+    let emails: Vec<&str> = emails.iter().map(AsRef::as_ref).collect();
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT id, email, email_blind_index, status FROM subscriptions
+        WHERE newsletter_id = $1 AND deleted_at IS NULL
+          AND (lower(email) = ANY($2) OR email_blind_index = ANY($3))
+        ORDER BY id
+        "#,
+        newsletter.id,
+        &emails as &[&str],
+        &indexes
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to look up the subscribers to unsubscribe.")?;
+    let (matched, already_unsubscribed): (Vec<_>, Vec<_>) = subscribers
+        .iter()
+        .partition(|subscriber| !["unsubscribed", "bounced"].contains(&&*subscriber.status));
+    let matched: Vec<Uuid> = matched
+        .into_iter()
+        .map(|subscriber| subscriber.id)
+        .collect();
+    let signed_ids = signed_message(newsletter.id, &matched);
+
+    let Some(confirmation) = confirmation else {
+        // Addresses can be stored encrypted: only their index or plaintext tells them apart.
+        let known = emails
+            .iter()
+            .filter(|email| {
+                let index = pii_cipher.email_index(email);
+                subscribers.iter().any(|subscriber| {
+                    subscriber.email.to_lowercase() == **email
+                        || (index.is_some() && subscriber.email_blind_index == index)
+                })
+            })
+            .count();
+        return Ok(HttpResponse::Ok().json(BulkUnsubscribePreview {
+            dry_run: true,
+            matched: matched.len(),
+            already_unsubscribed: already_unsubscribed.len(),
+            unknown: emails.len() - known,
+            invalid,
+            confirmation: key_ring.sign(CONFIRMATION_PURPOSE, signed_ids.as_bytes()),
+        }));
+    };
+    if !key_ring.verify(CONFIRMATION_PURPOSE, signed_ids.as_bytes(), &confirmation) {
+        return Err(SubscriberError::Conflict(
+            "The subscribers of the list have changed since the preview, or it was of another \
+            list: preview it again."
+                .into(),
+        ));
+    }
+
+    let source = Source::request(Actor::User(user.user_id), &request);
+    let mut unsubscribed = 0;
+    for batch in matched.chunks(BATCH_SIZE) {
+        unsubscribed += unsubscribe_batch(&pool, batch, &source).await?;
+    }
+    tracing::info!(unsubscribed, "Unsubscribed subscribers in bulk");
+    Ok(HttpResponse::Ok().json(BulkUnsubscribeReport {
+        dry_run: false,
+        unsubscribed,
+    }))
+}
+
+/// The valid addresses, without duplicates, and the entries that aren't addresses.
+fn parse_emails(emails: &str) -> Result<(Vec<SubscriberEmail>, Vec<String>), SubscriberError> {
+    let mut valid = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in emails
+        .split(['\n', ',', ';'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        match SubscriberEmail::parse(entry.to_lowercase()) {
+            Ok(email) => valid.push(email),
+            Err(_) => invalid.push(entry.to_owned()),
+        }
+    }
+    // Addresses are matched case-insensitively, as blind indexes are.
+    valid.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    valid.dedup_by(|a, b| a.as_ref() == b.as_ref());
+    if valid.is_empty() {
+        return Err(SubscriberError::ValidationError(
+            "There is no email address to unsubscribe.".into(),
+        ));
+    }
+    if valid.len() > MAX_ADDRESSES {
+        return Err(SubscriberError::ValidationError(format!(
+            "Unsubscribe at most {MAX_ADDRESSES} addresses at once."
+        )));
+    }
+    Ok((valid, invalid))
+}
+
+/// What the confirmation of a preview signs: the list, and the subscribers it would
+/// unsubscribe from it.
+fn signed_message(newsletter_id: Uuid, subscriber_ids: &[Uuid]) -> String {
+    let mut message = newsletter_id.to_string();
+    for subscriber_id in subscriber_ids {
+        message.push(',');
+        message.push_str(&subscriber_id.to_string());
+    }
+    message
+}
+
+/// Subscribers who unsubscribed in the meantime are skipped, and not recorded twice.
+async fn unsubscribe_batch(
+    pool: &PgPool,
+    subscriber_ids: &[Uuid],
+    source: &Source,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let unsubscribed = sqlx::query_scalar!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()
+        WHERE id = ANY($1) AND status NOT IN ('unsubscribed', 'bounced') AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        subscriber_ids
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe a batch of subscribers.")?;
+    record_all(
+        &mut *transaction,
+        &unsubscribed,
+        SubscriberEvent::Unsubscribed,
+        source,
+    )
+    .await
+    .context("Failed to record the unsubscription of a batch of subscribers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a batch of subscribers.")?;
+    Ok(unsubscribed.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_emails;
+
+    #[test]
+    fn pasted_lists_are_split_and_deduplicated() {
+        let (valid, invalid) =
+            parse_emails("ursula@example.com\nURSULA@example.com, octavia@example.com;nope\n\n")
+                .unwrap();
+
+        let valid: Vec<&str> = valid.iter().map(AsRef::as_ref).collect();
+        assert_eq!(valid, vec!["octavia@example.com", "ursula@example.com"]);
+        assert_eq!(invalid, vec!["nope"]);
+    }
+
+    #[test]
+    fn lists_without_an_address_are_rejected() {
+        assert!(parse_emails("nope\n").is_err());
+    }
+}
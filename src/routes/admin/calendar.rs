@@ -0,0 +1,253 @@
+//! The issues of a month by day, for an editorial calendar: those that went out, and those
+//! whose deliveries are still queued.
+//!
+//! Issues are published as soon as they are written, so there are no drafts nor scheduled
+//! issues to show yet, nor recurring slots.
+use crate::api_error::ApiError;
+use crate::database::ReadPool;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use uuid::Uuid;
+
+// Issues with at least this many recipients are large sends.
+const LARGE_SEND_RECIPIENTS: i64 = 1_000;
+// Large sends published closer together than this get a warning.
+const CONFLICT_WINDOW_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize)]
+pub struct CalendarQuery {
+    // `YYYY-MM`; the current month when missing.
+    month: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Calendar {
+    month: String,
+    // Only the days with issues, in order.
+    days: Vec<CalendarDay>,
+    warnings: Vec<CalendarWarning>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CalendarDay {
+    date: NaiveDate,
+    issues: Vec<CalendarIssue>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CalendarIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    // The slug of its list.
+    newsletter: String,
+    published_at: DateTime<Utc>,
+    // `sending` while some of its deliveries are queued, `sent` once none are.
+    status: IssueStatus,
+    // Those it was delivered to, and those still queued.
+    recipients: i64,
+    queued: i64,
+    priority: i16,
+    private: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Sending,
+    Sent,
+}
+
+#[derive(serde::Serialize)]
+pub struct CalendarWarning {
+    code: &'static str,
+    message: String,
+    newsletter_issue_ids: [Uuid; 2],
+}
+
+fn parse_month(month: Option<&str>) -> Result<NaiveDate, String> {
+    let Some(month) = month else {
+        let today = Utc::now().date_naive();
+        return Ok(today.with_day(1).unwrap_or(today));
+    };
+    NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d")
+        .map_err(|_| format!("`{month}` is not a month: use `YYYY-MM`."))
+}
+
+/// The issues published in a month (UTC days), with a warning for each two large sends
+/// published close together, as they compete for the send budget.
+#[tracing::instrument(
+    name = "Get the issue calendar",
+    skip(query, read_pool),
+    fields(month = query.month)
+)]
+pub async fn issue_calendar(
+    query: web::Query<CalendarQuery>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, CalendarError> {
+    let read_pool = read_pool.get().await;
+    let first_day = parse_month(query.month.as_deref()).map_err(CalendarError::ValidationError)?;
+    let next_month = first_day
+        .checked_add_months(chrono::Months::new(1))
+        .ok_or_else(|| CalendarError::ValidationError("The month is out of range.".into()))?;
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.newsletter_issue_id, i.title, n.slug AS newsletter, i.published_at,
+            i.delivery_priority, i.private,
+            (SELECT COUNT(*) FROM broadcast_deliveries d
+             WHERE d.newsletter_issue_id = i.newsletter_issue_id) AS "delivered!",
+            (SELECT COUNT(*) FROM issue_delivery_queue q
+             WHERE q.newsletter_issue_id = i.newsletter_issue_id) AS "queued!"
+        FROM newsletter_issues i
+        JOIN newsletters n ON n.id = i.newsletter_id
+        WHERE i.published_at >= $1 AND i.published_at < $2
+        ORDER BY i.published_at, i.newsletter_issue_id
+        "#,
+        first_day.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        next_month.and_hms_opt(0, 0, 0).unwrap().and_utc()
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the issues of the month.")?
+    .into_iter()
+    .map(|row| CalendarIssue {
+        newsletter_issue_id: row.newsletter_issue_id,
+        title: row.title,
+        newsletter: row.newsletter,
+        published_at: row.published_at,
+        status: if row.queued > 0 {
+            IssueStatus::Sending
+        } else {
+            IssueStatus::Sent
+        },
+        recipients: row.delivered + row.queued,
+        queued: row.queued,
+        priority: row.delivery_priority,
+        private: row.private,
+    })
+    .collect::<Vec<_>>();
+
+    let warnings = conflicts(&issues);
+    let mut days: Vec<CalendarDay> = Vec::new();
+    for issue in issues {
+        let date = issue.published_at.date_naive();
+        match days.last_mut() {
+            Some(day) if day.date == date => day.issues.push(issue),
+            _ => days.push(CalendarDay {
+                date,
+                issues: vec![issue],
+            }),
+        }
+    }
+    Ok(HttpResponse::Ok().json(Calendar {
+        month: first_day.format("%Y-%m").to_string(),
+        days,
+        warnings,
+    }))
+}
+
+/// Each two large sends, in publication order, published less than the window apart.
+fn conflicts(issues: &[CalendarIssue]) -> Vec<CalendarWarning> {
+    let large = issues
+        .iter()
+        .filter(|issue| issue.recipients >= LARGE_SEND_RECIPIENTS)
+        .collect::<Vec<_>>();
+    let mut warnings = Vec::new();
+    for (index, earlier) in large.iter().enumerate() {
+        for later in &large[index + 1..] {
+            let gap = later.published_at - earlier.published_at;
+            if gap >= Duration::hours(CONFLICT_WINDOW_HOURS) {
+                break;
+            }
+            warnings.push(CalendarWarning {
+                code: "large_sends_close_together",
+                message: format!(
+                    "`{}` ({} recipients) and `{}` ({} recipients) went out {} minutes apart.",
+                    earlier.title,
+                    earlier.recipients,
+                    later.title,
+                    later.recipients,
+                    gap.num_minutes()
+                ),
+                newsletter_issue_ids: [earlier.newsletter_issue_id, later.newsletter_issue_id],
+            });
+        }
+    }
+    warnings
+}
+
+#[derive(thiserror::Error)]
+pub enum CalendarError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CalendarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for CalendarError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CalendarError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            CalendarError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            CalendarError::ValidationError(e) => ApiError::validation(e),
+            CalendarError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CalendarIssue, IssueStatus, conflicts};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    fn issue(title: &str, published_at: &str, recipients: i64) -> CalendarIssue {
+        CalendarIssue {
+            newsletter_issue_id: Uuid::new_v4(),
+            title: title.into(),
+            newsletter: "default".into(),
+            published_at: published_at.parse::<DateTime<Utc>>().unwrap(),
+            status: IssueStatus::Sent,
+            recipients,
+            queued: 0,
+            priority: 1,
+            private: false,
+        }
+    }
+
+    #[test]
+    fn large_sends_close_together_are_flagged() {
+        let issues = [
+            issue("Monday", "2026-10-05T09:00:00Z", 5_000),
+            issue("Small", "2026-10-05T10:00:00Z", 10),
+            issue("Tuesday", "2026-10-06T08:00:00Z", 2_000),
+            issue("Friday", "2026-10-09T09:00:00Z", 5_000),
+        ];
+
+        let warnings = conflicts(&issues);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].newsletter_issue_ids,
+            [issues[0].newsletter_issue_id, issues[2].newsletter_issue_id]
+        );
+        assert_eq!(
+            warnings[0].message,
+            "`Monday` (5000 recipients) and `Tuesday` (2000 recipients) went out 1380 minutes apart."
+        );
+    }
+}
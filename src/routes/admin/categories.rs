@@ -0,0 +1,124 @@
+use crate::database::ReadPool;
+use crate::domain::CategoryName;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+pub struct IssueCategory {
+    name: String,
+    description: String,
+    // Confirmed subscribers that haven't opted out of it.
+    subscribers: i64,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewCategoryData {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[tracing::instrument(name = "List issue categories", skip(pool))]
+pub async fn list_categories(pool: web::Data<ReadPool>) -> Result<HttpResponse, CategoryError> {
+    let categories = sqlx::query_as!(
+        IssueCategory,
+        r#"
+        SELECT c.name, c.description, c.created_at, (
+            SELECT COUNT(*) FROM subscriptions s
+            WHERE s.status = 'confirmed' AND s.deleted_at IS NULL AND NOT EXISTS (
+                SELECT 1 FROM category_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.category = c.name
+            )
+        ) AS "subscribers!"
+        FROM issue_categories c
+        ORDER BY c.name
+        "#
+    )
+    .fetch_all(pool.get().await)
+    .await
+    .context("Failed to fetch issue categories from the database.")?;
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+#[tracing::instrument(name = "Create an issue category", skip(body, pool), fields(category = %body.name))]
+pub async fn create_category(
+    body: web::Json<NewCategoryData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, CategoryError> {
+    let body = body.into_inner();
+    let name = CategoryName::parse(body.name).map_err(CategoryError::ValidationError)?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO issue_categories (name, description, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        name.as_ref(),
+        body.description.trim()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the issue category in the database.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(CategoryError::Conflict(format!(
+            "A category named `{}` already exists.",
+            name.as_ref()
+        )));
+    }
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Subscribers' opt-outs of the category go with it.
+#[tracing::instrument(name = "Delete an issue category", skip(pool))]
+pub async fn delete_category(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, CategoryError> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM issue_categories WHERE name = $1"#,
+        name.as_str()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete the issue category from the database.")?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(CategoryError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum CategoryError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The category does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CategoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for CategoryError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CategoryError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            CategoryError::NotFound => StatusCode::NOT_FOUND,
+            CategoryError::Conflict(_) => StatusCode::CONFLICT,
+            CategoryError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! The home of the server-rendered admin console, and the script its pages share.
+use crate::authentication::AuthenticatedUser;
+use crate::authorization::Permission;
+use crate::routes::error_chain_fmt;
+use crate::templates::{DashboardPage, EmailTemplates};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const CONSOLE_SCRIPT: &str = include_str!("../../../static/console.js");
+
+pub async fn admin_dashboard(
+    user: web::ReqData<AuthenticatedUser>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, DashboardError> {
+    let user = user.into_inner();
+    let username = get_username(user.user_id, &pool).await?;
+    let page = templates.dashboard_page(&DashboardPage {
+        username: &username,
+        // Viewers can't publish, so they aren't offered to.
+        can_publish: user.require(Permission::Publish.role()).is_ok(),
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// Swaps the fragments of console pages in, see `static/console.js`.
+pub async fn admin_console_script() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(CONSOLE_SCRIPT)
+}
+
+/// Whether the request came from the console script, which only wants a fragment back.
+pub(crate) fn wants_fragment(request: &HttpRequest) -> bool {
+    request.headers().contains_key("HX-Request")
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub(crate) async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    sqlx::query_scalar!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")
+}
+
+#[derive(thiserror::Error)]
+pub enum DashboardError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DashboardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DashboardError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DashboardError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
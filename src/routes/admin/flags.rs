@@ -0,0 +1,140 @@
+use crate::api_error::ApiError;
+use crate::routes::error_chain_fmt;
+use crate::runtime_flags::{RuntimeFlag, enabled_flags, is_enabled, set_flag};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[derive(serde::Serialize)]
+pub struct FlagState {
+    name: &'static str,
+    enabled: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FlagUpdate {
+    enabled: bool,
+}
+
+#[tracing::instrument(name = "List runtime flags", skip(pool))]
+pub async fn list_flags(pool: web::Data<PgPool>) -> Result<HttpResponse, FlagError> {
+    let enabled = enabled_flags(&pool)
+        .await
+        .context("Failed to fetch runtime flags.")?;
+    let flags: Vec<_> = RuntimeFlag::ALL
+        .into_iter()
+        .map(|flag| FlagState {
+            name: flag.name(),
+            enabled: enabled.contains(&flag),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(flags))
+}
+
+/// Flags change how every instance works: they take an admin.
+#[tracing::instrument(name = "Update a runtime flag", skip(body, pool))]
+pub async fn update_flag(
+    name: web::Path<String>,
+    body: web::Json<FlagUpdate>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, FlagError> {
+    let flag = RuntimeFlag::parse(&name).ok_or(FlagError::NotFound)?;
+    let enabled = body.enabled;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    check_transition(&mut transaction, flag, enabled).await?;
+    set_flag(&mut *transaction, flag, enabled)
+        .await
+        .context("Failed to update the runtime flag.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a runtime flag.")?;
+    Ok(HttpResponse::Ok().json(FlagState {
+        name: flag.name(),
+        enabled,
+    }))
+}
+
+/// Token hashes can only be read once they are written for every token,
+/// and must keep being written for as long as they are read.
+async fn check_transition(
+    transaction: &mut Transaction<'_, Postgres>,
+    flag: RuntimeFlag,
+    enabled: bool,
+) -> Result<(), FlagError> {
+    match (flag, enabled) {
+        (RuntimeFlag::ReadTokenHashes, true) => {
+            if !is_enabled(&mut **transaction, RuntimeFlag::WriteTokenHashes)
+                .await
+                .context("Failed to check a runtime flag.")?
+            {
+                return Err(FlagError::Conflict(
+                    "Enable `write_token_hashes` before reading token hashes.".into(),
+                ));
+            }
+            // Tokens created before hashes were written, or by an older version of the
+            // application, don't have one yet.
+            sqlx::query!(
+                r#"
+                UPDATE subscription_tokens
+                SET subscription_token_hash = encode(sha256(convert_to(subscription_token, 'UTF8')), 'hex')
+                WHERE subscription_token_hash IS NULL
+                "#
+            )
+            .execute(&mut **transaction)
+            .await
+            .context("Failed to backfill subscription token hashes.")?;
+        }
+        (RuntimeFlag::WriteTokenHashes, false) => {
+            let reading_hashes = is_enabled(&mut **transaction, RuntimeFlag::ReadTokenHashes)
+                .await
+                .context("Failed to check a runtime flag.")?;
+            if reading_hashes {
+                return Err(FlagError::Conflict(
+                    "Disable `read_token_hashes` before you stop writing token hashes.".into(),
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum FlagError {
+    #[error("There is no such runtime flag.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for FlagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for FlagError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FlagError::NotFound => StatusCode::NOT_FOUND,
+            FlagError::Conflict(_) => StatusCode::CONFLICT,
+            FlagError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            FlagError::NotFound => ApiError::not_found(self.to_string()),
+            FlagError::Conflict(e) => ApiError::new(StatusCode::CONFLICT, "conflict", e),
+            FlagError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
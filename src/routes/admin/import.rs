@@ -0,0 +1,399 @@
+//! Bulk imports of subscribers, e.g. an export from another newsletter provider.
+//!
+//! The upload is a `file` field, either a JSON array of `{"email": ..., "name": ...}` or a CSV
+//! with a header row. CSVs can name their columns `email` and `name`, or the way Mailchimp
+//! exports them: `Email Address`, `First Name` and `Last Name`.
+use crate::audit_log::{Actor, Source, SubscriberEvent, record};
+use crate::authentication::AuthenticatedUser;
+use crate::domain::{
+    ActionBaseUrl, DomainError, NewSubscriber, SignupAttributes, SubscriberEmail, SubscriberName,
+};
+use crate::email_client::EmailClient;
+use crate::pii::PiiCipher;
+use crate::routes::{
+    DEFAULT_LIST_SLUG, Newsletter, SubscribeError, SubscriberError, generate_subscription_token,
+    get_newsletter, hash_subscription_token, insert_subscriber, send_confirmation_email,
+    store_token,
+};
+use crate::runtime_flags::{RuntimeFlag, is_enabled};
+use crate::signed_tokens::TokenSigner;
+use crate::templates::EmailTemplates;
+use actix_multipart::Multipart;
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use futures_util::StreamExt;
+use sqlx::PgPool;
+
+const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct ImportQuery {
+    // `confirmed` (the default) or `pending_confirmation`, to email everyone a
+    // confirmation link first.
+    #[serde(default = "confirmed")]
+    status: String,
+    // The slug of the list to import into; the default list if there is none.
+    #[serde(default)]
+    newsletter: Option<String>,
+}
+
+fn confirmed() -> String {
+    "confirmed".into()
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize)]
+struct ImportRow {
+    email: String,
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportReport {
+    imported: u64,
+    // The rows that weren't imported, and why.
+    errors: Vec<RowError>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RowError {
+    // Counted from 1, without the CSV header.
+    row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    // `DomainError::code` for the name and email, `duplicate` for addresses already
+    // subscribed to the list, and `malformed` for rows that can't be read.
+    error: &'static str,
+    message: String,
+}
+
+/// Every row is imported on its own: invalid rows and duplicates are reported and skipped,
+/// the others go in. Imports are not held to the subscriber cap. Editors and admins only.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip_all,
+    fields(status = %query.status)
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<ImportQuery>,
+    payload: Multipart,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, SubscriberError> {
+    let source = Source::request(Actor::User(user.user_id), &request);
+    let send_confirmations = match query.status.as_str() {
+        "confirmed" => false,
+        "pending_confirmation" => true,
+        status => {
+            return Err(SubscriberError::ValidationError(format!(
+                "Subscribers can be imported as `confirmed` or `pending_confirmation`, not `{status}`."
+            )));
+        }
+    };
+    let slug = query.newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(&pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            SubscriberError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let contents = read_upload(payload).await?;
+    let rows = parse_rows(&contents).map_err(SubscriberError::ValidationError)?;
+
+    let mut report = ImportReport {
+        imported: 0,
+        errors: Vec::new(),
+    };
+    for (index, row) in rows.enumerate() {
+        let row_number = index + 1;
+        let new_subscriber = match row.map_err(RowError::malformed).and_then(validate_row) {
+            Ok(new_subscriber) => new_subscriber,
+            Err(error) => {
+                report.errors.push(RowError {
+                    row: row_number,
+                    ..error
+                });
+                continue;
+            }
+        };
+        match import_subscriber(
+            &pool,
+            &pii_cipher,
+            &newsletter,
+            &new_subscriber,
+            send_confirmations,
+            &source,
+        )
+        .await?
+        {
+            Imported::Confirmed => report.imported += 1,
+            Imported::Pending { subscription_token } => {
+                report.imported += 1;
+                if let Err(error) = send_confirmation_email(
+                    &email_client,
+                    &templates,
+                    &token_signer,
+                    &newsletter.name,
+                    new_subscriber,
+                    action_base_url.as_ref().as_ref(),
+                    &subscription_token,
+                )
+                .await
+                {
+                    // They are imported either way: confirmation reminders cover a failed email.
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        row = row_number,
+                        "Failed to send a confirmation email to an imported subscriber",
+                    );
+                }
+            }
+            Imported::Duplicate => report.errors.push(RowError {
+                row: row_number,
+                field: Some("email"),
+                error: "duplicate",
+                message: "The email address is already subscribed to the list.".into(),
+            }),
+        }
+    }
+    tracing::info!(
+        imported = report.imported,
+        errors = report.errors.len(),
+        "Imported subscribers"
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The contents of the `file` field.
+async fn read_upload(mut payload: Multipart) -> Result<Vec<u8>, SubscriberError> {
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| SubscriberError::ValidationError(e.to_string()))?;
+        if field.name() != Some("file") {
+            continue;
+        }
+        let mut contents = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| SubscriberError::ValidationError(e.to_string()))?;
+            if contents.len() + chunk.len() > MAX_UPLOAD_BYTES {
+                return Err(SubscriberError::ValidationError(format!(
+                    "Imports can be at most {} MiB.",
+                    MAX_UPLOAD_BYTES / 1024 / 1024
+                )));
+            }
+            contents.extend_from_slice(&chunk);
+        }
+        return Ok(contents);
+    }
+    Err(SubscriberError::ValidationError(
+        "The subscribers to import are expected in a `file` field.".into(),
+    ))
+}
+
+/// The rows of a JSON array or, for anything else, of a CSV. Fails if the file as a whole
+/// can't be read, e.g. a CSV without an email column.
+fn parse_rows(
+    contents: &[u8],
+) -> Result<Box<dyn Iterator<Item = Result<ImportRow, String>> + '_>, String> {
+    let contents = contents.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(contents);
+    if contents.trim_ascii_start().starts_with(b"[") {
+        let values: Vec<serde_json::Value> = serde_json::from_slice(contents)
+            .map_err(|e| format!("The JSON array of subscribers is invalid: {e}"))?;
+        return Ok(Box::new(values.into_iter().map(|value| {
+            serde_json::from_value(value).map_err(|e| e.to_string())
+        })));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("The CSV header is invalid: {e}"))?
+        .iter()
+        .map(str::to_lowercase)
+        .collect();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let email_column = column(&["email", "email address"])
+        .ok_or("The CSV has no `email` or `Email Address` column.")?;
+    let name_column = column(&["name"]);
+    let first_name_column = column(&["first name"]);
+    let last_name_column = column(&["last name"]);
+    Ok(Box::new(reader.into_records().map(move |record| {
+        let record = record.map_err(|e| e.to_string())?;
+        let get = |column: Option<usize>| column.and_then(|i| record.get(i)).unwrap_or_default();
+        let name = match name_column {
+            Some(_) => get(name_column).to_owned(),
+            None => format!("{} {}", get(first_name_column), get(last_name_column))
+                .trim()
+                .to_owned(),
+        };
+        Ok(ImportRow {
+            email: get(Some(email_column)).to_owned(),
+            name,
+        })
+    })))
+}
+
+fn validate_row(row: ImportRow) -> Result<NewSubscriber, RowError> {
+    let email = SubscriberEmail::parse(row.email).map_err(|e| RowError::field("email", e))?;
+    let name = SubscriberName::parse(row.name).map_err(|e| RowError::field("name", e))?;
+    Ok(NewSubscriber {
+        email,
+        name,
+        language: None,
+    })
+}
+
+impl RowError {
+    // The row number is filled in by the caller.
+    fn field(field: &'static str, error: DomainError) -> Self {
+        Self {
+            row: 0,
+            field: Some(field),
+            error: error.code(),
+            message: error.to_string(),
+        }
+    }
+
+    fn malformed(message: String) -> Self {
+        Self {
+            row: 0,
+            field: None,
+            error: "malformed",
+            message,
+        }
+    }
+}
+
+enum Imported {
+    Confirmed,
+    // With the confirmation token to email them.
+    Pending { subscription_token: String },
+    // The address is already subscribed to the list.
+    Duplicate,
+}
+
+async fn import_subscriber(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    newsletter: &Newsletter,
+    new_subscriber: &NewSubscriber,
+    pending: bool,
+    source: &Source,
+) -> Result<Imported, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let status = if pending {
+        "pending_confirmation"
+    } else {
+        "confirmed"
+    };
+    let subscriber_id = match insert_subscriber(
+        &mut transaction,
+        pii_cipher,
+        newsletter.id,
+        new_subscriber,
+        status,
+        None,
+        SignupAttributes::default(),
+    )
+    .await
+    {
+        Ok(subscriber_id) => subscriber_id,
+        Err(SubscribeError::DuplicateSubscriber { .. }) => return Ok(Imported::Duplicate),
+        Err(e) => return Err(anyhow::Error::new(e)),
+    };
+    record(
+        &mut *transaction,
+        subscriber_id,
+        SubscriberEvent::Imported,
+        source,
+    )
+    .await
+    .context("Failed to record the import of a subscriber.")?;
+    let imported = if pending {
+        let token = generate_subscription_token();
+        let token_hash = is_enabled(&mut *transaction, RuntimeFlag::WriteTokenHashes)
+            .await
+            .context("Failed to check a runtime flag.")?
+            .then(|| hash_subscription_token(&token));
+        store_token(
+            &mut transaction,
+            subscriber_id,
+            &token,
+            token_hash.as_deref(),
+            None,
+        )
+        .await
+        .context("Failed to store the confirmation token of an imported subscriber.")?;
+        Imported::Pending {
+            subscription_token: token,
+        }
+    } else {
+        Imported::Confirmed
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import a subscriber.")?;
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportRow, parse_rows};
+
+    fn rows(contents: &str) -> Vec<Result<ImportRow, String>> {
+        parse_rows(contents.as_bytes()).unwrap().collect()
+    }
+
+    fn row(email: &str, name: &str) -> Result<ImportRow, String> {
+        Ok(ImportRow {
+            email: email.into(),
+            name: name.into(),
+        })
+    }
+
+    #[test]
+    fn csv_columns_are_found_by_name() {
+        assert_eq!(
+            rows("Name,Email\nUrsula Le Guin, ursula@example.com\n"),
+            vec![row("ursula@example.com", "Ursula Le Guin")]
+        );
+    }
+
+    #[test]
+    fn mailchimp_exports_are_understood() {
+        assert_eq!(
+            rows(
+                "\u{feff}Email Address,First Name,Last Name,MEMBER_RATING\n\
+                ursula@example.com,Ursula,Le Guin,2\n\
+                octavia@example.com,Octavia,,2\n"
+            ),
+            vec![
+                row("ursula@example.com", "Ursula Le Guin"),
+                row("octavia@example.com", "Octavia"),
+            ]
+        );
+    }
+
+    #[test]
+    fn csvs_without_an_email_column_are_rejected() {
+        assert!(parse_rows(b"name,phone\nUrsula,555-0100\n").is_err());
+    }
+
+    #[test]
+    fn json_rows_are_checked_one_by_one() {
+        let rows = rows(r#"[{"email": "ursula@example.com", "name": "Ursula"}, {"email": 1}]"#);
+        assert_eq!(rows[0], row("ursula@example.com", "Ursula"));
+        assert!(rows[1].is_err());
+    }
+}
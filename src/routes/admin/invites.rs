@@ -0,0 +1,125 @@
+use crate::database::ReadPool;
+use crate::invites::generate_invite_code;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+const MAX_INVITES_PER_REQUEST: u32 = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct NewInvitesData {
+    #[serde(default = "one")]
+    count: u32,
+    #[serde(default = "one")]
+    max_uses: u32,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(serde::Serialize)]
+pub struct CreatedInvites {
+    codes: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Invite {
+    code: String,
+    max_uses: i32,
+    uses: i32,
+    expires_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    // Subscribers that signed up with this invite.
+    subscribers: i64,
+}
+
+impl NewInvitesData {
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_INVITES_PER_REQUEST).contains(&self.count) {
+            return Err(format!(
+                "Create between 1 and {} invites at a time.",
+                MAX_INVITES_PER_REQUEST
+            ));
+        }
+        if self.max_uses == 0 || self.max_uses > i32::MAX as u32 {
+            return Err("An invite must allow at least one use.".into());
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Err("The invites would already have expired.".into());
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Create invites", skip(body, pool), fields(count = body.count))]
+pub async fn create_invites(
+    body: web::Json<NewInvitesData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InviteError> {
+    body.validate().map_err(InviteError::ValidationError)?;
+    let codes: Vec<String> = (0..body.count).map(|_| generate_invite_code()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO invites (code, max_uses, expires_at, created_at)
+        SELECT code, $2, $3, $4 FROM UNNEST($1::text[]) AS code
+        "#,
+        &codes,
+        body.max_uses as i32,
+        body.expires_at,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the invites in the database.")?;
+    Ok(HttpResponse::Ok().json(CreatedInvites { codes }))
+}
+
+#[tracing::instrument(name = "List invites", skip(pool))]
+pub async fn list_invites(pool: web::Data<ReadPool>) -> Result<HttpResponse, InviteError> {
+    let invites = sqlx::query_as!(
+        Invite,
+        r#"
+        SELECT i.code, i.max_uses, i.uses, i.expires_at, i.created_at,
+            COUNT(s.id) AS "subscribers!"
+        FROM invites i
+        LEFT JOIN subscriptions s ON s.invite_code = i.code AND s.deleted_at IS NULL
+        GROUP BY i.code
+        ORDER BY i.created_at DESC, i.code
+        "#
+    )
+    .fetch_all(pool.get().await)
+    .await
+    .context("Failed to fetch invites from the database.")?;
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+#[derive(thiserror::Error)]
+pub enum InviteError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for InviteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for InviteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            InviteError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            InviteError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
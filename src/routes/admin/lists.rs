@@ -0,0 +1,208 @@
+use crate::database::ReadPool;
+use crate::domain::NewsletterSlug;
+use crate::routes::{DEFAULT_LIST_SLUG, error_chain_fmt};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Serialize)]
+pub struct List {
+    slug: String,
+    name: String,
+    // Confirmed ones only.
+    subscribers: i64,
+    issues: i64,
+    // Whether opens and clicks of its issues are tracked.
+    tracking: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewListData {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    tracking: bool,
+}
+
+/// What isn't given is left as it is.
+#[derive(serde::Deserialize)]
+pub struct ListUpdateData {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    tracking: Option<bool>,
+}
+
+/// The name shows up in the emails of the list, e.g. "Welcome to {name}".
+fn parse_name(name: &str) -> Result<&str, ListError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ListError::ValidationError(format!(
+            "List names must be between 1 and {MAX_NAME_LENGTH} characters long."
+        )));
+    }
+    Ok(name)
+}
+
+#[tracing::instrument(name = "List newsletters", skip(pool))]
+pub async fn list_lists(pool: web::Data<ReadPool>) -> Result<HttpResponse, ListError> {
+    let lists = sqlx::query_as!(
+        List,
+        r#"
+        SELECT n.slug, n.name, n.tracking_enabled AS tracking, n.created_at, (
+            SELECT COUNT(*) FROM subscriptions s
+            WHERE s.newsletter_id = n.id AND s.status = 'confirmed' AND s.deleted_at IS NULL
+        ) AS "subscribers!", (
+            SELECT COUNT(*) FROM newsletter_issues i WHERE i.newsletter_id = n.id
+        ) AS "issues!"
+        FROM newsletters n
+        ORDER BY n.slug
+        "#
+    )
+    .fetch_all(pool.get().await)
+    .await
+    .context("Failed to fetch newsletters from the database.")?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[tracing::instrument(name = "Create a newsletter", skip(body, pool), fields(slug = %body.slug))]
+pub async fn create_list(
+    body: web::Json<NewListData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    let slug = NewsletterSlug::parse(body.slug.clone()).map_err(ListError::ValidationError)?;
+    let name = parse_name(&body.name)?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO newsletters (id, slug, name, tracking_enabled, created_at)
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name,
+        body.tracking
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the newsletter in the database.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(ListError::Conflict(format!(
+            "A list with the slug `{}` already exists.",
+            slug.as_ref()
+        )));
+    }
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Only the name and tracking can change: the slug is in subscribe URLs out there.
+/// Turning tracking off also stops recording opens and clicks of issues already sent.
+#[tracing::instrument(name = "Update a newsletter", skip(body, pool))]
+pub async fn update_list(
+    slug: web::Path<String>,
+    body: web::Json<ListUpdateData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    let name = body.name.as_deref().map(parse_name).transpose()?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletters
+        SET name = COALESCE($2, name), tracking_enabled = COALESCE($3, tracking_enabled)
+        WHERE slug = $1
+        "#,
+        slug.as_str(),
+        name,
+        body.tracking
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the newsletter.")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ListError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lists that still have subscribers or issues are kept: those would have nowhere to go.
+#[tracing::instrument(name = "Delete a newsletter", skip(pool))]
+pub async fn delete_list(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    if slug.as_str() == DEFAULT_LIST_SLUG {
+        return Err(ListError::Conflict(
+            "The default list can't be deleted.".into(),
+        ));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let list = sqlx::query!(
+        r#"
+        SELECT id,
+            -- Deleted subscribers count: their records are kept.
+            EXISTS (SELECT 1 FROM subscriptions s WHERE s.newsletter_id = n.id) AS "has_subscribers!",
+            EXISTS (SELECT 1 FROM newsletter_issues i WHERE i.newsletter_id = n.id) AS "has_issues!"
+        FROM newsletters n WHERE slug = $1
+        FOR UPDATE
+        "#,
+        slug.as_str()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the newsletter.")?
+    .ok_or(ListError::NotFound)?;
+    if list.has_subscribers || list.has_issues {
+        return Err(ListError::Conflict(format!(
+            "The list `{}` still has subscribers or issues.",
+            slug.as_str()
+        )));
+    }
+    sqlx::query!(r#"DELETE FROM newsletters WHERE id = $1"#, list.id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the newsletter from the database.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a newsletter.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum ListError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The list does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ListError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ListError::NotFound => StatusCode::NOT_FOUND,
+            ListError::Conflict(_) => StatusCode::CONFLICT,
+            ListError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
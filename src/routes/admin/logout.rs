@@ -0,0 +1,8 @@
+use crate::routes::see_other;
+use crate::session::TypedSession;
+use actix_web::HttpResponse;
+
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    session.log_out();
+    see_other("/login")
+}
@@ -0,0 +1,236 @@
+//! Every issue of a list as an mbox, for operators to import their sending history into a
+//! mail client or an archival system.
+//!
+//! Each issue is one message, rendered the way the `issue_delivery` job renders a stored
+//! issue, for a reader it wasn't addressed to: `To: undisclosed-recipients:;`, merge fields
+//! filled in as on the archive, no tracking, and an unsubscribe link that leads nowhere.
+use crate::api_error::ApiError;
+use crate::database::ReadPool;
+use crate::domain::ActionBaseUrl;
+use crate::email_client::EmailClient;
+use crate::merge_fields::Recipient;
+use crate::routes::{
+    DEFAULT_LIST_SLUG, FooterLinks, PREVIEW_TOKEN, RenderedIssue, error_chain_fmt, get_newsletter,
+    preferences_link, unsubscribe_link,
+};
+use crate::signed_tokens::TokenSigner;
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{EmailBody, EmailTemplates};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use std::fmt::Write;
+use uuid::Uuid;
+
+// The longest line base64 bodies are wrapped at, as MIME asks.
+const BASE64_LINE: usize = 76;
+// How many bytes of a non-ASCII subject go in each of its encoded words, so that none is
+// longer than the 75 characters allowed.
+const ENCODED_WORD_BYTES: usize = 45;
+
+#[derive(serde::Deserialize)]
+pub struct MailboxExportQuery {
+    // The default list when missing.
+    newsletter: Option<String>,
+}
+
+/// The issues of a list, private ones included, as an mbox of one message each, oldest
+/// first.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Export the issues of a list as a mailbox",
+    skip_all,
+    fields(newsletter = query.newsletter)
+)]
+pub async fn export_mailbox(
+    query: web::Query<MailboxExportQuery>,
+    read_pool: web::Data<ReadPool>,
+    templates: web::Data<EmailTemplates>,
+    email_client: web::Data<EmailClient>,
+    token_signer: web::Data<TokenSigner>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, MailboxExportError> {
+    let read_pool = read_pool.get().await;
+    let slug = query.newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(read_pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            MailboxExportError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, html_content, text_content, published_at
+        FROM newsletter_issues
+        WHERE newsletter_id = $1
+        ORDER BY published_at, newsletter_issue_id
+        "#,
+        newsletter.id
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the issues of the list.")?;
+
+    let sender = email_client.sender().as_ref();
+    let domain = Url::parse(&base_url.0)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_else(|| "localhost".into());
+    let unsubscribe_link = unsubscribe_link(&action_base_url, &token_signer, PREVIEW_TOKEN);
+    let preferences_link = preferences_link(&action_base_url, &token_signer, PREVIEW_TOKEN);
+    let mut mailbox = String::new();
+    for issue in &issues {
+        let rendered = RenderedIssue {
+            html: issue.html_content.clone(),
+            text: issue.text_content.clone(),
+            // Sponsor blocks were injected into the stored content when it was published.
+            sponsor_slots: Vec::new(),
+            compliance_error: None,
+            links: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let email = rendered
+            .email(
+                &templates,
+                &newsletter.name,
+                &Recipient::reader(),
+                &FooterLinks {
+                    unsubscribe: &unsubscribe_link,
+                    preferences: &preferences_link,
+                },
+                None,
+            )
+            .context("Failed to render an issue for the mailbox.")?;
+        write_message(
+            &mut mailbox,
+            sender,
+            &format!("<{}@{domain}>", issue.newsletter_issue_id),
+            &issue.title,
+            issue.published_at,
+            &email,
+            issue.newsletter_issue_id,
+        );
+    }
+    tracing::info!(issues = issues.len(), "Exported the issues as a mailbox");
+    Ok(HttpResponse::Ok()
+        .content_type("application/mbox")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}-issues.mbox",
+                newsletter.slug
+            ))],
+        })
+        .body(mailbox))
+}
+
+/// Appends an issue to `mailbox` as a `multipart/alternative` message, after the `From ` line
+/// that separates messages. Its parts are base64, so no line of it can be mistaken for one.
+fn write_message(
+    mailbox: &mut String,
+    sender: &str,
+    message_id: &str,
+    subject: &str,
+    published_at: DateTime<Utc>,
+    email: &EmailBody,
+    newsletter_issue_id: Uuid,
+) {
+    let boundary = format!("=_{}", newsletter_issue_id.simple());
+    // Writing to a `String` can't fail.
+    let _ = write!(
+        mailbox,
+        "From {sender} {}\n\
+        From: {sender}\n\
+        To: undisclosed-recipients:;\n\
+        Subject: {}\n\
+        Date: {}\n\
+        Message-ID: {message_id}\n\
+        MIME-Version: 1.0\n\
+        Content-Type: multipart/alternative; boundary=\"{boundary}\"\n\
+        \n",
+        published_at.format("%a %b %e %H:%M:%S %Y"),
+        encode_header(subject),
+        published_at.to_rfc2822(),
+    );
+    for (content_type, content) in [("text/plain", &email.text), ("text/html", &email.html)] {
+        let _ = write!(
+            mailbox,
+            "--{boundary}\n\
+            Content-Type: {content_type}; charset=utf-8\n\
+            Content-Transfer-Encoding: base64\n\
+            \n\
+            {}\n",
+            wrap_base64(content)
+        );
+    }
+    let _ = write!(mailbox, "--{boundary}--\n\n");
+}
+
+/// `value` as is if it is printable ASCII, in RFC 2047 encoded words otherwise.
+fn encode_header(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return value.to_owned();
+    }
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        if index + c.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&value[start..index]);
+            start = index;
+        }
+    }
+    words.push(&value[start..]);
+    words
+        .iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join("\n ")
+}
+
+fn wrap_base64(content: &str) -> String {
+    STANDARD
+        .encode(content)
+        .as_bytes()
+        .chunks(BASE64_LINE)
+        // Base64 is ASCII.
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(thiserror::Error)]
+pub enum MailboxExportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MailboxExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for MailboxExportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MailboxExportError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            MailboxExportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            MailboxExportError::ValidationError(e) => ApiError::validation(e),
+            MailboxExportError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
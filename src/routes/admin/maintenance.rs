@@ -0,0 +1,222 @@
+//! Checks that the tables which are meant to agree with each other do, and optionally
+//! repairs what can be repaired.
+//!
+//! Every check that can be repaired is a `DELETE`. They run in a single transaction,
+//! which is rolled back unless a repair was asked for. That way a report counts exactly
+//! the rows a repair would remove.
+use crate::api_error::ApiError;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ConsistencyCheckQuery {
+    #[serde(default)]
+    repair: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsistencyReport {
+    repaired: bool,
+    // Confirmation tokens, push subscriptions and SMS registrations of deleted subscribers,
+    // which their deletion should have removed.
+    contact_points_of_deleted_subscribers: u64,
+    // Unsent confirmation emails to subscribers who are no longer pending: they confirmed,
+    // unsubscribed or were deleted since.
+    confirmation_emails_of_settled_subscribers: u64,
+    // Queued deliveries to subscribers who are no longer confirmed, or were deleted.
+    departed_queue_entries: u64,
+    // Queued deliveries of an issue that the subscriber was already sent.
+    queue_entries_already_sent: u64,
+    // Issues whose recorded deliveries disagree with their delivery attempts. These are
+    // reported, never repaired: nothing says which of the two records is right.
+    mismatched_delivery_counts: Vec<DeliveryCountMismatch>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryCountMismatch {
+    newsletter_issue_id: Uuid,
+    // Rows in `broadcast_deliveries`, which the frequency cap counts.
+    recorded_deliveries: i64,
+    // Attempts the provider accepted, bounced ones included, as each of them was recorded
+    // as a delivery when it went out.
+    accepted_attempts: i64,
+}
+
+/// Reports the inconsistencies found, and with `?repair=true` removes the rows that cause
+/// them, except for mismatched delivery counts. Admins only.
+#[tracing::instrument(name = "Check consistency", skip_all, fields(repair = query.repair))]
+pub async fn consistency_check(
+    query: web::Query<ConsistencyCheckQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, MaintenanceError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let contact_points_of_deleted_subscribers =
+        delete_contact_points_of_deleted_subscribers(&mut transaction).await?;
+    let confirmation_emails_of_settled_subscribers = sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_outbox o
+        USING subscription_tokens t, subscriptions s
+        WHERE t.subscription_token = o.subscription_token AND s.id = t.subscriber_id
+          AND o.delivered_at IS NULL
+          AND (s.status <> 'pending_confirmation' OR s.deleted_at IS NOT NULL)
+        "#
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to check the confirmation outbox.")?
+    .rows_affected();
+    let departed_queue_entries = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue q
+        USING subscriptions s
+        WHERE s.id = q.subscriber_id AND (s.status <> 'confirmed' OR s.deleted_at IS NOT NULL)
+        "#
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to check the delivery queue for departed subscribers.")?
+    .rows_affected();
+    let queue_entries_already_sent = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue q
+        USING newsletter_delivery_attempts a
+        WHERE a.newsletter_issue_id = q.newsletter_issue_id
+          AND a.subscriber_id = q.subscriber_id
+          AND (delivery_counts_as_sent(a.status) OR a.status = 'bounced')
+        "#
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to check the delivery queue for deliveries already sent.")?
+    .rows_affected();
+    let mismatched_delivery_counts = sqlx::query_as!(
+        DeliveryCountMismatch,
+        r#"
+        WITH recorded AS (
+            SELECT newsletter_issue_id, COUNT(*) AS deliveries FROM broadcast_deliveries
+            WHERE newsletter_issue_id IS NOT NULL
+            GROUP BY newsletter_issue_id
+        ), accepted AS (
+            -- Compacted issues keep their bounced attempts, and the count of the others.
+            SELECT newsletter_issue_id, SUM(attempts)::BIGINT AS attempts FROM (
+                SELECT newsletter_issue_id, COUNT(*) AS attempts FROM newsletter_delivery_attempts
+                WHERE delivery_counts_as_sent(status) OR status = 'bounced'
+                GROUP BY newsletter_issue_id
+                UNION ALL
+                SELECT newsletter_issue_id, sent FROM issue_statistics
+            ) counts
+            GROUP BY newsletter_issue_id
+        )
+        SELECT
+            COALESCE(r.newsletter_issue_id, a.newsletter_issue_id) AS "newsletter_issue_id!",
+            COALESCE(r.deliveries, 0) AS "recorded_deliveries!",
+            COALESCE(a.attempts, 0) AS "accepted_attempts!"
+        FROM recorded r FULL JOIN accepted a USING (newsletter_issue_id)
+        WHERE r.deliveries IS DISTINCT FROM a.attempts
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to compare the delivery counts of issues.")?;
+
+    if query.repair {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to repair inconsistencies.")?;
+    } else {
+        transaction
+            .rollback()
+            .await
+            .context("Failed to roll back the consistency check.")?;
+    }
+    let report = ConsistencyReport {
+        repaired: query.repair,
+        contact_points_of_deleted_subscribers,
+        confirmation_emails_of_settled_subscribers,
+        departed_queue_entries,
+        queue_entries_already_sent,
+        mismatched_delivery_counts,
+    };
+    tracing::info!(
+        repaired = report.repaired,
+        report.contact_points_of_deleted_subscribers,
+        report.confirmation_emails_of_settled_subscribers,
+        report.departed_queue_entries,
+        report.queue_entries_already_sent,
+        mismatched_delivery_counts = report.mismatched_delivery_counts.len(),
+        "Checked consistency"
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn delete_contact_points_of_deleted_subscribers(
+    connection: &mut PgConnection,
+) -> Result<u64, anyhow::Error> {
+    let tokens = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens t USING subscriptions s
+        WHERE s.id = t.subscriber_id AND s.deleted_at IS NOT NULL
+        "#
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to check the confirmation tokens of deleted subscribers.")?
+    .rows_affected();
+    let push_subscriptions = sqlx::query!(
+        r#"
+        DELETE FROM push_subscriptions p USING subscriptions s
+        WHERE s.id = p.subscriber_id AND s.deleted_at IS NOT NULL
+        "#
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to check the push subscriptions of deleted subscribers.")?
+    .rows_affected();
+    let sms_registrations = sqlx::query!(
+        r#"
+        DELETE FROM sms_registrations r USING subscriptions s
+        WHERE s.id = r.subscriber_id AND s.deleted_at IS NOT NULL
+        "#
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to check the SMS registrations of deleted subscribers.")?
+    .rows_affected();
+    Ok(tokens + push_subscriptions + sms_registrations)
+}
+
+#[derive(thiserror::Error)]
+pub enum MaintenanceError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MaintenanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for MaintenanceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MaintenanceError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            MaintenanceError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,55 @@
+mod api_keys;
+mod archive_export;
+mod branding;
+mod bulk_unsubscribe;
+mod calendar;
+mod categories;
+mod dashboard;
+mod flags;
+mod import;
+mod invites;
+mod lists;
+mod logout;
+mod mailbox_export;
+mod maintenance;
+mod notifications;
+mod password;
+mod preview;
+mod publish;
+mod repermission_campaigns;
+mod smoke_test;
+mod snippets;
+mod sponsors;
+mod stats;
+mod subscribers;
+mod tags;
+mod users;
+mod waitlist;
+
+pub use api_keys::*;
+pub use archive_export::*;
+pub use branding::*;
+pub use bulk_unsubscribe::*;
+pub use calendar::*;
+pub use categories::*;
+pub use dashboard::*;
+pub use flags::*;
+pub use import::*;
+pub use invites::*;
+pub use lists::*;
+pub use logout::*;
+pub use mailbox_export::*;
+pub use maintenance::*;
+pub use notifications::*;
+pub use password::*;
+pub use preview::*;
+pub use publish::*;
+pub use repermission_campaigns::*;
+pub use smoke_test::*;
+pub use snippets::*;
+pub use sponsors::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use tags::*;
+pub use users::*;
+pub use waitlist::*;
@@ -0,0 +1,102 @@
+//! The operational emails each user gets, which they choose for themselves.
+use crate::admin_notifications::NOTIFICATIONS;
+use crate::api_error::ApiError;
+use crate::authentication::AuthenticatedUser;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+pub struct NotificationPreferences {
+    // Where they are sent; users without an address get none.
+    email: Option<String>,
+    notifications: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NotificationPreferencesUpdate {
+    notifications: Vec<String>,
+}
+
+#[tracing::instrument(name = "Get notification preferences", skip_all)]
+pub async fn get_notification_preferences(
+    user: web::ReqData<AuthenticatedUser>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NotificationError> {
+    let preferences = sqlx::query_as!(
+        NotificationPreferences,
+        r#"SELECT email, notifications FROM users WHERE user_id = $1"#,
+        user.user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to fetch the notification preferences of the user.")?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+/// Replaces the notifications the user gets with `notifications`.
+#[tracing::instrument(name = "Update notification preferences", skip_all)]
+pub async fn update_notification_preferences(
+    user: web::ReqData<AuthenticatedUser>,
+    body: web::Json<NotificationPreferencesUpdate>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NotificationError> {
+    let mut notifications = body.into_inner().notifications;
+    if let Some(unknown) = notifications
+        .iter()
+        .find(|notification| !NOTIFICATIONS.contains(&notification.as_str()))
+    {
+        return Err(NotificationError::ValidationError(format!(
+            "There is no `{unknown}` notification; they are {}.",
+            NOTIFICATIONS.join(", ")
+        )));
+    }
+    notifications.sort();
+    notifications.dedup();
+    let preferences = sqlx::query_as!(
+        NotificationPreferences,
+        r#"
+        UPDATE users SET notifications = $2 WHERE user_id = $1
+        RETURNING email, notifications
+        "#,
+        user.user_id,
+        &notifications
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to update the notification preferences of the user.")?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[derive(thiserror::Error)]
+pub enum NotificationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for NotificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for NotificationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            NotificationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            NotificationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            NotificationError::ValidationError(e) => ApiError::validation(e),
+            NotificationError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
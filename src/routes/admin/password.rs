@@ -0,0 +1,110 @@
+//! Lets a logged-in admin rotate their own password.
+use crate::admin_access::request_client_ip;
+use crate::admin_notifications::{AdminNotification, notify};
+use crate::authentication::{
+    AuthError, AuthenticatedUser, Credentials, change_password, check_password_strength,
+    validate_credentials,
+};
+use crate::email_client::EmailClient;
+use crate::routes::{DashboardError, get_username, see_other};
+use crate::session::TypedSession;
+use crate::startup::AdminNotifications;
+use crate::templates::{EmailTemplates, FormPage};
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordData {
+    current_password: SecretString,
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+pub async fn change_password_form(
+    session: TypedSession,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, DashboardError> {
+    let flash = session
+        .take_flash()
+        .context("Failed to read the session.")?;
+    let page = templates.password_page(&FormPage {
+        flash: flash.as_deref(),
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// Goes back to the form either way, with what happened in a flash message.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Change password",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn change_password_from_form(
+    request: HttpRequest,
+    user: web::ReqData<AuthenticatedUser>,
+    form: web::Form<ChangePasswordData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    admin_notifications: web::Data<AdminNotifications>,
+) -> Result<HttpResponse, DashboardError> {
+    let user_id = user.user_id;
+    let ChangePasswordData {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+    let rejection = if new_password.expose_secret() != new_password_check.expose_secret() {
+        Some("You entered two different new passwords - the field values must match.".into())
+    } else {
+        check_password_strength(&new_password).err()
+    };
+    if let Some(message) = rejection {
+        return flash_and_go_back(&session, &message);
+    }
+
+    let username = get_username(user_id, &pool).await?;
+    let credentials = Credentials {
+        username,
+        password: current_password,
+    };
+    match validate_credentials(credentials, &pool).await {
+        Ok(_) => {}
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected a password change");
+            return flash_and_go_back(&session, "The current password is incorrect.");
+        }
+        Err(AuthError::UnexpectedError(e)) => return Err(e.into()),
+    }
+    change_password(user_id, new_password, pool.get_ref()).await?;
+    let notification = AdminNotification::PasswordChanged {
+        client_ip: request_client_ip(&request),
+    };
+    notify(
+        &pool,
+        &email_client,
+        &templates,
+        &admin_notifications,
+        user_id,
+        notification,
+    )
+    .await;
+    flash_and_go_back(&session, "Your password has been changed.")
+}
+
+fn flash_and_go_back(
+    session: &TypedSession,
+    message: &str,
+) -> Result<HttpResponse, DashboardError> {
+    session
+        .insert_flash(message)
+        .context("Failed to store a message in the session.")?;
+    Ok(see_other("/admin/password"))
+}
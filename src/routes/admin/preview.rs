@@ -0,0 +1,254 @@
+use crate::api_error::ApiError;
+use crate::authentication::{AuthenticatedUser, Role};
+use crate::crypto::KeyRing;
+use crate::database::ReadPool;
+use crate::merge_fields::{Recipient, render_merge_fields};
+use crate::pii::PiiCipher;
+use crate::routes::{
+    Content, PublishError, Targeting, error_chain_fmt, render_issue, resolve_audience,
+};
+use crate::startup::{ApplicationBaseUrl, FrequencyCap};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use rand::seq::SliceRandom;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Render `content` for an existing subscriber, for a made-up `persona`,
+/// or for a default persona if neither is given.
+#[derive(serde::Deserialize)]
+pub struct PreviewData {
+    content: Content,
+    subscriber_id: Option<Uuid>,
+    persona: Option<Persona>,
+    // Who the issue would go to if it was published with this targeting.
+    audience: Option<AudienceData>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AudienceData {
+    #[serde(flatten)]
+    targeting: Targeting,
+    #[serde(default = "default_sample_size")]
+    sample_size: usize,
+}
+
+const MAX_SAMPLE_SIZE: usize = 50;
+
+fn default_sample_size() -> usize {
+    10
+}
+
+#[derive(serde::Deserialize)]
+pub struct Persona {
+    name: String,
+    email: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct Preview {
+    html: String,
+    text: String,
+    // Merge fields that could not be resolved. They are left in place in `html` and `text`.
+    errors: Vec<String>,
+    // What would get the issue rejected on publish, besides merge fields.
+    compliance_errors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audience: Option<AudiencePreview>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AudiencePreview {
+    recipients: usize,
+    skipped_over_frequency_cap: i64,
+    // Random recipients, to check the targeting picks the expected people. Their emails are
+    // masked unless the preview was asked for by an admin.
+    sample: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "Render a newsletter preview",
+    skip(
+        body,
+        user,
+        pool,
+        read_pool,
+        base_url,
+        key_ring,
+        pii_cipher,
+        frequency_cap
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn render_preview(
+    body: web::Json<PreviewData>,
+    user: web::ReqData<AuthenticatedUser>,
+    pool: web::Data<PgPool>,
+    read_pool: web::Data<ReadPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    pii_cipher: web::Data<PiiCipher>,
+    frequency_cap: web::Data<FrequencyCap>,
+) -> Result<HttpResponse, PreviewError> {
+    let PreviewData {
+        content,
+        subscriber_id,
+        persona,
+        audience,
+    } = body.into_inner();
+    let persona = match (subscriber_id, persona) {
+        (Some(subscriber_id), _) => Some(
+            get_subscriber(&pool, &pii_cipher, subscriber_id)
+                .await?
+                .ok_or(PreviewError::SubscriberNotFound)?,
+        ),
+        (None, persona) => persona,
+    };
+    let recipient = match &persona {
+        Some(persona) => Recipient {
+            name: &persona.name,
+            email: &persona.email,
+        },
+        None => Recipient::persona(),
+    };
+
+    // Sponsor impressions are only recorded on publish: previews don't count.
+    let issue = render_issue(&pool, &content, &base_url.0, &key_ring).await?;
+    let (html, html_errors) = render_merge_fields(&issue.html, &recipient, true);
+    let (text, text_errors) = render_merge_fields(&issue.text, &recipient, false);
+    let errors = html_errors
+        .into_iter()
+        .chain(text_errors)
+        .map(|e| e.to_string())
+        .collect();
+    let audience = match audience {
+        Some(audience) => Some(
+            preview_audience(
+                &pool,
+                &read_pool,
+                &pii_cipher,
+                &audience,
+                &frequency_cap,
+                user.require(Role::Admin).is_ok(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(Preview {
+        html,
+        text,
+        errors,
+        compliance_errors: issue.compliance_error.into_iter().collect(),
+        audience,
+    }))
+}
+
+async fn preview_audience(
+    pool: &PgPool,
+    read_pool: &ReadPool,
+    pii_cipher: &PiiCipher,
+    audience: &AudienceData,
+    frequency_cap: &FrequencyCap,
+    unmasked: bool,
+) -> Result<AudiencePreview, PreviewError> {
+    if audience.sample_size > MAX_SAMPLE_SIZE {
+        return Err(PreviewError::ValidationError(format!(
+            "The audience sample can have at most {MAX_SAMPLE_SIZE} recipients."
+        )));
+    }
+    let resolved = resolve_audience(
+        pool,
+        read_pool,
+        pii_cipher,
+        &audience.targeting,
+        frequency_cap.0.as_ref(),
+    )
+    .await?;
+    let sample = resolved
+        .recipients
+        .choose_multiple(&mut rand::thread_rng(), audience.sample_size)
+        .map(|recipient| {
+            if unmasked {
+                recipient.email.as_ref().to_owned()
+            } else {
+                recipient.email.masked()
+            }
+        })
+        .collect();
+    Ok(AudiencePreview {
+        recipients: resolved.recipients.len(),
+        skipped_over_frequency_cap: resolved.over_frequency_cap.len() as i64,
+        sample,
+    })
+}
+
+#[tracing::instrument(
+    name = "Get a subscriber to preview an issue for",
+    skip(pool, pii_cipher)
+)]
+async fn get_subscriber(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    subscriber_id: Uuid,
+) -> Result<Option<Persona>, PreviewError> {
+    let Some(row) = sqlx::query!(
+        r#"SELECT name, email FROM subscriptions WHERE id = $1 AND deleted_at IS NULL"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscriber from the database.")?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(Persona {
+        name: pii_cipher.decrypt(&row.name)?,
+        email: pii_cipher.decrypt(&row.email)?,
+    }))
+}
+
+#[derive(thiserror::Error)]
+pub enum PreviewError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The subscriber does not exist.")]
+    SubscriberNotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<PublishError> for PreviewError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::ValidationError(e) => PreviewError::ValidationError(e),
+            PublishError::UnexpectedError(e) => PreviewError::UnexpectedError(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for PreviewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreviewError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreviewError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreviewError::SubscriberNotFound => StatusCode::NOT_FOUND,
+            PreviewError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            PreviewError::ValidationError(e) => ApiError::validation(e),
+            PreviewError::SubscriberNotFound => ApiError::not_found(self.to_string()),
+            PreviewError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,194 @@
+//! The issue composer of the admin dashboard: `POST /newsletters` for people rather than
+//! scripts, with a preview of the issue before it goes out.
+use crate::authentication::AuthenticatedUser;
+use crate::content_checks::ContentChecker;
+use crate::crypto::KeyRing;
+use crate::database::ReadPool;
+use crate::domain::ActionBaseUrl;
+use crate::email_client::EmailClient;
+use crate::merge_fields::Recipient;
+use crate::pii::PiiCipher;
+use crate::routes::{
+    BodyData, Content, DashboardError, PublishError, Targeting, default_priority, publish_issue,
+    render_test_email, see_other,
+};
+use crate::session::TypedSession;
+use crate::signed_tokens::TokenSigner;
+use crate::sms_client::SmsClient;
+use crate::startup::{AdminNotifications, ApplicationBaseUrl, FrequencyCap};
+use crate::templates::{EmailTemplates, FormPage, IssuePreviewFragment};
+use crate::web_push::WebPushClient;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct PublishFormData {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+pub async fn publish_newsletter_form(
+    session: TypedSession,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, DashboardError> {
+    let flash = session
+        .take_flash()
+        .context("Failed to read the session.")?;
+    let page = templates.composer_page(&FormPage {
+        flash: flash.as_deref(),
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// The issue of the form as the stand-in persona would get it, as a fragment of the form's
+/// page. An issue that can't be published says why instead.
+#[tracing::instrument(name = "Preview a newsletter issue from the dashboard", skip_all)]
+pub async fn preview_newsletter_from_form(
+    form: web::Form<PublishFormData>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, DashboardError> {
+    let content = Content {
+        html: form.html_content.clone(),
+        text: form.text_content.clone(),
+    };
+    let email = match render_test_email(
+        None,
+        &content,
+        &Recipient::persona(),
+        &pool,
+        &templates,
+        &base_url,
+        &key_ring,
+        &action_base_url,
+    )
+    .await
+    {
+        Ok(email) => Ok(email),
+        Err(PublishError::ValidationError(e)) => Err(e),
+        Err(PublishError::UnexpectedError(e)) => return Err(e.into()),
+    };
+    let fragment = match &email {
+        Ok(email) => IssuePreviewFragment {
+            error: None,
+            subject: &form.title,
+            html: &email.html,
+            text: &email.text,
+        },
+        Err(e) => IssuePreviewFragment {
+            error: Some(e),
+            subject: &form.title,
+            html: "",
+            text: "",
+        },
+    };
+    let fragment = templates.issue_preview(&fragment)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(fragment))
+}
+
+/// Goes back to the form either way, with what happened in a flash message. Editors and
+/// admins only, like `POST /newsletters`.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the dashboard",
+    skip_all,
+    fields(user_id = %user.user_id)
+)]
+pub async fn publish_newsletter_from_form(
+    user: web::ReqData<AuthenticatedUser>,
+    form: web::Form<PublishFormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    read_pool: web::Data<ReadPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    web_push: web::Data<Option<WebPushClient>>,
+    sms_client: web::Data<Option<SmsClient>>,
+    content_checker: web::Data<ContentChecker>,
+    // Grouped, as handlers take at most 16 extractors.
+    (frequency_cap, admin_notifications): (web::Data<FrequencyCap>, web::Data<AdminNotifications>),
+) -> Result<HttpResponse, DashboardError> {
+    let PublishFormData {
+        title,
+        html_content,
+        text_content,
+    } = form.into_inner();
+    let body = BodyData {
+        title,
+        content: Content {
+            html: html_content,
+            text: text_content,
+        },
+        targeting: Targeting::default(),
+        also_sms: false,
+        dry_run: false,
+        private: false,
+        priority: default_priority(),
+    };
+    let outcome = publish_issue(
+        &body,
+        user.user_id,
+        &pool,
+        &read_pool,
+        &email_client,
+        &templates,
+        &base_url,
+        &key_ring,
+        &token_signer,
+        &pii_cipher,
+        &action_base_url,
+        web_push.as_ref().as_ref(),
+        sms_client.as_ref().as_ref(),
+        frequency_cap.0.as_ref(),
+        &content_checker,
+        &admin_notifications,
+    )
+    .await;
+    let message = match outcome {
+        Ok(summary) => {
+            let mut message = if summary.queued > 0 {
+                format!(
+                    "The newsletter issue has been published to {} subscribers; {} more will \
+                    get it as the send budget allows.",
+                    summary.delivered, summary.queued
+                )
+            } else {
+                format!(
+                    "The newsletter issue has been published to {} subscribers.",
+                    summary.delivered
+                )
+            };
+            if summary.failed > 0 {
+                message.push_str(&format!(
+                    " Sending to {} of them failed and will be retried.",
+                    summary.failed
+                ));
+            }
+            for warning in &summary.review.warnings {
+                message.push_str(&format!(" Warning: {}", warning.message));
+            }
+            message
+        }
+        Err(PublishError::ValidationError(e)) => format!("The issue was not published: {e}"),
+        Err(PublishError::UnexpectedError(e)) => return Err(e.into()),
+    };
+    session
+        .insert_flash(&message)
+        .context("Failed to store a message in the session.")?;
+    Ok(see_other("/admin/newsletters"))
+}
@@ -0,0 +1,237 @@
+use crate::api_error::ApiError;
+use crate::authentication::AuthenticatedUser;
+use crate::domain::TagName;
+use crate::repermission_campaigns::MAX_ATTEMPTS;
+use crate::routes::{
+    DEFAULT_LIST_SLUG, error_chain_fmt, generate_subscription_token, get_newsletter,
+};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_WINDOW_DAYS: i64 = 90;
+
+#[derive(serde::Deserialize)]
+pub struct RepermissionCampaignData {
+    // The subscribers of the list with this tag are asked.
+    tag: String,
+    // The slug of the list; the default list if there is none.
+    #[serde(default)]
+    newsletter: Option<String>,
+    // How long they have to click, from now.
+    window_days: i64,
+}
+
+/// How far a campaign got. `awaiting` were sent the email and haven't clicked, nor been
+/// unsubscribed; `failed` couldn't be sent it, and are left alone.
+#[derive(serde::Serialize)]
+pub struct RepermissionCampaignReport {
+    campaign_id: Uuid,
+    newsletter: String,
+    tag: String,
+    created_at: DateTime<Utc>,
+    closes_at: DateTime<Utc>,
+    closed_at: Option<DateTime<Utc>>,
+    requested: i64,
+    sent: i64,
+    failed: i64,
+    stayed: i64,
+    awaiting: i64,
+    unsubscribed: i64,
+}
+
+/// Asks every confirmed subscriber of the list with `tag` whether they want to stay. The
+/// `repermission_campaigns` job sends the emails, and unsubscribes those who didn't click
+/// once `window_days` are over. Admins only, as it unsubscribes.
+#[tracing::instrument(
+    name = "Start a re-permission campaign",
+    skip_all,
+    fields(tag = %body.tag, window_days = body.window_days)
+)]
+pub async fn start_repermission_campaign(
+    user: web::ReqData<AuthenticatedUser>,
+    body: web::Json<RepermissionCampaignData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, RepermissionCampaignError> {
+    let RepermissionCampaignData {
+        tag,
+        newsletter,
+        window_days,
+    } = body.into_inner();
+    let tag = TagName::parse(tag).map_err(RepermissionCampaignError::ValidationError)?;
+    if !(1..=MAX_WINDOW_DAYS).contains(&window_days) {
+        return Err(RepermissionCampaignError::ValidationError(format!(
+            "The window must be between 1 and {MAX_WINDOW_DAYS} days."
+        )));
+    }
+    let slug = newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(&pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            RepermissionCampaignError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_ids = sqlx::query_scalar!(
+        r#"
+        SELECT s.id FROM subscriptions s
+        JOIN subscriber_tags t ON t.subscriber_id = s.id
+        WHERE s.newsletter_id = $1 AND t.tag = $2
+          AND s.status = 'confirmed' AND s.deleted_at IS NULL
+        "#,
+        newsletter.id,
+        tag.as_ref()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscribers to ask.")?;
+    if subscriber_ids.is_empty() {
+        return Err(RepermissionCampaignError::ValidationError(format!(
+            "No confirmed subscriber of the list has the `{}` tag.",
+            tag.as_ref()
+        )));
+    }
+    let campaign_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO repermission_campaigns
+            (id, newsletter_id, tag, created_by, created_at, closes_at)
+        VALUES ($1, $2, $3, $4, now(), $5)
+        "#,
+        campaign_id,
+        newsletter.id,
+        tag.as_ref(),
+        user.user_id,
+        Utc::now() + Duration::days(window_days)
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the re-permission campaign.")?;
+    let tokens: Vec<String> = subscriber_ids
+        .iter()
+        .map(|_| generate_subscription_token())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO repermission_requests (campaign_id, subscriber_id, token)
+        SELECT $1, * FROM UNNEST($2::uuid[], $3::text[])
+        "#,
+        campaign_id,
+        &subscriber_ids,
+        &tokens
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store who the re-permission campaign asks.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to start a re-permission campaign.")?;
+    tracing::info!(
+        %campaign_id,
+        requested = subscriber_ids.len(),
+        "Started a re-permission campaign"
+    );
+
+    let report = campaign_reports(&pool, Some(campaign_id))
+        .await?
+        .pop()
+        .context("The re-permission campaign just started is missing.")?;
+    Ok(HttpResponse::Created().json(report))
+}
+
+/// Most recent first.
+#[tracing::instrument(name = "List re-permission campaigns", skip(pool))]
+pub async fn list_repermission_campaigns(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, RepermissionCampaignError> {
+    let reports = campaign_reports(&pool, None).await?;
+    Ok(HttpResponse::Ok().json(reports))
+}
+
+#[tracing::instrument(name = "Get a re-permission campaign", skip(pool))]
+pub async fn get_repermission_campaign(
+    campaign_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, RepermissionCampaignError> {
+    let report = campaign_reports(&pool, Some(campaign_id.into_inner()))
+        .await?
+        .pop()
+        .ok_or(RepermissionCampaignError::NotFound)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Of every campaign, or of `campaign_id` only.
+async fn campaign_reports(
+    pool: &PgPool,
+    campaign_id: Option<Uuid>,
+) -> Result<Vec<RepermissionCampaignReport>, anyhow::Error> {
+    sqlx::query_as!(
+        RepermissionCampaignReport,
+        r#"
+        SELECT c.id AS campaign_id, n.slug AS newsletter, c.tag, c.created_at, c.closes_at,
+            c.closed_at,
+            COUNT(r.subscriber_id) AS "requested!",
+            COUNT(r.sent_at) AS "sent!",
+            COUNT(*) FILTER (WHERE r.sent_at IS NULL AND r.attempts >= $2) AS "failed!",
+            COUNT(r.stayed_at) AS "stayed!",
+            COUNT(*) FILTER (
+                WHERE r.sent_at IS NOT NULL AND r.stayed_at IS NULL AND r.unsubscribed_at IS NULL
+            ) AS "awaiting!",
+            COUNT(r.unsubscribed_at) AS "unsubscribed!"
+        FROM repermission_campaigns c
+        JOIN newsletters n ON n.id = c.newsletter_id
+        LEFT JOIN repermission_requests r ON r.campaign_id = c.id
+        WHERE $1::uuid IS NULL OR c.id = $1
+        GROUP BY c.id, n.slug
+        ORDER BY c.created_at DESC, c.id
+        "#,
+        campaign_id,
+        MAX_ATTEMPTS
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the progress of re-permission campaigns.")
+}
+
+#[derive(thiserror::Error)]
+pub enum RepermissionCampaignError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("No re-permission campaign with this id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for RepermissionCampaignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for RepermissionCampaignError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RepermissionCampaignError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            RepermissionCampaignError::NotFound => StatusCode::NOT_FOUND,
+            RepermissionCampaignError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            RepermissionCampaignError::ValidationError(e) => ApiError::validation(e),
+            RepermissionCampaignError::NotFound => ApiError::not_found(self.to_string()),
+            RepermissionCampaignError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,323 @@
+//! A synthetic run through signup, confirmation and delivery, for uptime monitors to call.
+//!
+//! Emails go to the configured sink address, plus-addressed so that every run gets a
+//! subscriber of its own. The subscriber is erased again whether the run passes or not,
+//! history included.
+use crate::api_error::ApiError;
+use crate::audit_log::Source;
+use crate::domain::{
+    ActionBaseUrl, NewSubscriber, SignupAttributes, SubscriberEmail, SubscriberName,
+};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::pii::PiiCipher;
+use crate::routes::admin::purge_subscriber;
+use crate::routes::newsletter::record_delivery;
+use crate::routes::{
+    Newsletter, SubscriberToken, SubscriptionKind, confirm_subscriber, error_chain_fmt,
+    generate_subscription_token, hash_subscription_token, insert_subscriber,
+    send_confirmation_email, store_token,
+};
+use crate::runtime_flags::{RuntimeFlag, is_enabled};
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use crate::startup::SmokeTestSink;
+use crate::templates::EmailTemplates;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::Instant;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct SmokeTestReport {
+    passed: bool,
+    // In the order they ran. A failed step is the last one: the others depend on it.
+    steps: Vec<SmokeTestStep>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SmokeTestStep {
+    name: &'static str,
+    passed: bool,
+    duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl SmokeTestReport {
+    async fn step<T>(
+        &mut self,
+        name: &'static str,
+        step: impl Future<Output = Result<T, anyhow::Error>>,
+    ) -> Option<T> {
+        let started_at = Instant::now();
+        let outcome = step.await;
+        if let Err(error) = &outcome {
+            tracing::error!(error.cause_chain = ?error, step = name, "A smoke test step failed");
+        }
+        self.steps.push(SmokeTestStep {
+            name,
+            passed: outcome.is_ok(),
+            duration_ms: started_at.elapsed().as_secs_f64() * 1000.0,
+            error: outcome.as_ref().err().map(|e| format!("{e:#}")),
+        });
+        outcome.ok()
+    }
+}
+
+/// 200 if every step passed, 503 otherwise, with the report either way.
+/// The subscriber signs up to the default list. Takes an admin.
+#[tracing::instrument(
+    name = "Run a smoke test",
+    skip(
+        newsletter,
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        sink
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn run_smoke_test(
+    newsletter: Newsletter,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    sink: web::Data<SmokeTestSink>,
+) -> Result<HttpResponse, SmokeTestError> {
+    let Some(sink) = &sink.0 else {
+        return Err(SmokeTestError::NotEnabled);
+    };
+    let run_id = &Uuid::new_v4().simple().to_string()[..12];
+    let new_subscriber = NewSubscriber {
+        email: disposable_address(sink, run_id)?,
+        name: SubscriberName::parse(format!("Smoke test {run_id}")).map_err(anyhow::Error::msg)?,
+        language: None,
+    };
+
+    let mut report = SmokeTestReport {
+        passed: false,
+        steps: Vec::new(),
+    };
+    let subscribed = report
+        .step(
+            "subscribe",
+            subscribe(&pool, &pii_cipher, &newsletter, &new_subscriber),
+        )
+        .await;
+    if let Some((subscriber_id, token)) = subscribed {
+        // Cleaned up however far the run got.
+        let _ = run_steps(
+            &mut report,
+            &pool,
+            &email_client,
+            &templates,
+            &token_signer,
+            &action_base_url,
+            &newsletter,
+            &new_subscriber,
+            subscriber_id,
+            &token,
+        )
+        .await;
+        report
+            .step("clean_up", async {
+                erase_subscriber(&pool, subscriber_id).await
+            })
+            .await;
+    }
+    report.passed = report.steps.iter().all(|step| step.passed);
+
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(HttpResponse::build(status).json(report))
+}
+
+/// `None` as soon as a step fails.
+#[allow(clippy::too_many_arguments)]
+async fn run_steps(
+    report: &mut SmokeTestReport,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    action_base_url: &ActionBaseUrl,
+    newsletter: &Newsletter,
+    new_subscriber: &NewSubscriber,
+    subscriber_id: Uuid,
+    token: &str,
+) -> Option<()> {
+    report
+        .step("send_confirmation", async {
+            send_confirmation_email(
+                email_client,
+                templates,
+                token_signer,
+                &newsletter.name,
+                new_subscriber.clone(),
+                action_base_url.as_ref(),
+                token,
+            )
+            .await
+            .context("Failed to send the confirmation email.")
+        })
+        .await?;
+    report
+        .step("confirm", async {
+            // As the confirmation link carries it.
+            let signed_token = token_signer.sign(TokenPurpose::Subscription, token);
+            let resolved =
+                SubscriberToken::<SubscriptionKind>::resolve(pool, token_signer, &signed_token)
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!("Failed to resolve the confirmation token: {e}")
+                    })?;
+            if resolved.subscriber_id != subscriber_id {
+                anyhow::bail!("The confirmation token belongs to another subscriber.");
+            }
+            confirm_subscriber(pool, subscriber_id, &Source::job("smoke_test"))
+                .await
+                .context("Failed to confirm the subscriber.")
+        })
+        .await?;
+    report
+        .step("deliver_issue", async {
+            email_client
+                .send_email(
+                    &new_subscriber.email,
+                    "Smoke test",
+                    "<p>This is a smoke test issue.</p>",
+                    "This is a smoke test issue.",
+                    MessageCategory::Broadcast,
+                )
+                .await
+                .context("Failed to send the test issue.")?;
+            record_delivery(pool, subscriber_id, None)
+                .await
+                .context("Failed to record the delivery.")
+        })
+        .await?;
+    report
+        .step("verify_delivery", async {
+            let deliveries = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM broadcast_deliveries WHERE subscriber_id = $1"#,
+                subscriber_id
+            )
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch delivery records.")?;
+            if deliveries != 1 {
+                anyhow::bail!("Expected 1 delivery record, found {deliveries}.");
+            }
+            Ok(())
+        })
+        .await
+}
+
+/// `sink+smoke-{run_id}@domain`
+fn disposable_address(
+    sink: &SubscriberEmail,
+    run_id: &str,
+) -> Result<SubscriberEmail, anyhow::Error> {
+    let (local_part, domain) = sink
+        .as_ref()
+        .rsplit_once('@')
+        .context("The smoke test sink address has no domain.")?;
+    SubscriberEmail::parse(format!("{local_part}+smoke-{run_id}@{domain}"))
+        .map_err(anyhow::Error::msg)
+}
+
+async fn erase_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    purge_subscriber(&mut transaction, subscriber_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase the smoke test subscriber.")
+}
+
+async fn subscribe(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    newsletter: &Newsletter,
+    new_subscriber: &NewSubscriber,
+) -> Result<(Uuid, String), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = insert_subscriber(
+        &mut transaction,
+        pii_cipher,
+        newsletter.id,
+        new_subscriber,
+        "pending_confirmation",
+        None,
+        SignupAttributes::default(),
+    )
+    .await
+    .context("Failed to insert the smoke test subscriber.")?;
+    let token = generate_subscription_token();
+    let token_hash = is_enabled(&mut *transaction, RuntimeFlag::WriteTokenHashes)
+        .await
+        .context("Failed to check a runtime flag.")?
+        .then(|| hash_subscription_token(&token));
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &token,
+        token_hash.as_deref(),
+        None,
+    )
+    .await
+    .context("Failed to store the confirmation token.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store the smoke test subscriber.")?;
+    Ok((subscriber_id, token))
+}
+
+#[derive(thiserror::Error)]
+pub enum SmokeTestError {
+    #[error("Smoke tests are not enabled.")]
+    NotEnabled,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SmokeTestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SmokeTestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SmokeTestError::NotEnabled => StatusCode::NOT_FOUND,
+            SmokeTestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            SmokeTestError::NotEnabled => ApiError::not_found(self.to_string()),
+            SmokeTestError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
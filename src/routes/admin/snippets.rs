@@ -0,0 +1,198 @@
+use crate::domain::SnippetName;
+use crate::routes::error_chain_fmt;
+use crate::snippets::{load_snippets, referenced_snippets, resolve_snippets};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+pub struct Snippet {
+    name: String,
+    content: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewSnippetData {
+    name: String,
+    content: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SnippetContent {
+    content: String,
+}
+
+#[tracing::instrument(name = "List snippets", skip(pool))]
+pub async fn list_snippets(pool: web::Data<PgPool>) -> Result<HttpResponse, SnippetError> {
+    let snippets = sqlx::query_as!(
+        Snippet,
+        r#"SELECT name, content, created_at, updated_at FROM snippets ORDER BY name"#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch snippets from the database.")?;
+    Ok(HttpResponse::Ok().json(snippets))
+}
+
+#[tracing::instrument(name = "Get a snippet", skip(pool))]
+pub async fn get_snippet(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SnippetError> {
+    let snippet = sqlx::query_as!(
+        Snippet,
+        r#"SELECT name, content, created_at, updated_at FROM snippets WHERE name = $1"#,
+        name.as_str()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the snippet from the database.")?
+    .ok_or(SnippetError::NotFound)?;
+    Ok(HttpResponse::Ok().json(snippet))
+}
+
+#[tracing::instrument(name = "Create a snippet", skip(body, pool), fields(snippet_name = %body.name))]
+pub async fn create_snippet(
+    body: web::Json<NewSnippetData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SnippetError> {
+    let body = body.into_inner();
+    let name = SnippetName::parse(body.name).map_err(SnippetError::ValidationError)?;
+    check_references(&pool, &name, &body.content).await?;
+
+    let now = Utc::now();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO snippets (name, content, created_at, updated_at)
+        VALUES ($1, $2, $3, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        name.as_ref(),
+        body.content,
+        now
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the snippet in the database.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(SnippetError::Conflict(format!(
+            "A snippet named `{}` already exists.",
+            name.as_ref()
+        )));
+    }
+    Ok(HttpResponse::Created().finish())
+}
+
+#[tracing::instrument(name = "Update a snippet", skip(body, pool))]
+pub async fn update_snippet(
+    name: web::Path<String>,
+    body: web::Json<SnippetContent>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SnippetError> {
+    let name = SnippetName::parse(name.into_inner()).map_err(|_| SnippetError::NotFound)?;
+    check_references(&pool, &name, &body.content).await?;
+
+    let updated = sqlx::query!(
+        r#"UPDATE snippets SET content = $1, updated_at = $2 WHERE name = $3"#,
+        body.content,
+        Utc::now(),
+        name.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the snippet in the database.")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(SnippetError::NotFound);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Delete a snippet", skip(pool))]
+pub async fn delete_snippet(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SnippetError> {
+    let snippets = load_snippets(&pool)
+        .await
+        .context("Failed to load snippets from the database.")?;
+    if !snippets.contains_key(name.as_str()) {
+        return Err(SnippetError::NotFound);
+    }
+
+    // Deleting a snippet that another snippet includes would break every
+    // issue that renders the dependent one.
+    let mut dependents: Vec<&str> = snippets
+        .iter()
+        .filter(|(_, content)| {
+            referenced_snippets(content)
+                .map(|names| names.contains(&name.as_str()))
+                .unwrap_or(false)
+        })
+        .map(|(dependent, _)| dependent.as_str())
+        .collect();
+    if !dependents.is_empty() {
+        dependents.sort_unstable();
+        return Err(SnippetError::Conflict(format!(
+            "The snippet `{}` is still used by: {}.",
+            name.as_str(),
+            dependents.join(", ")
+        )));
+    }
+
+    sqlx::query!(r#"DELETE FROM snippets WHERE name = $1"#, name.as_str())
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to delete the snippet from the database.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Make sure `content` only references snippets that exist and does not
+/// (directly or indirectly) include the snippet being saved.
+async fn check_references(
+    pool: &PgPool,
+    name: &SnippetName,
+    content: &str,
+) -> Result<(), SnippetError> {
+    let mut snippets = load_snippets(pool)
+        .await
+        .context("Failed to load snippets from the database.")?;
+    snippets.insert(name.as_ref().to_owned(), content.to_owned());
+    resolve_snippets(content, &snippets)
+        .map_err(|e| SnippetError::ValidationError(e.to_string()))?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum SnippetError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The snippet does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SnippetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SnippetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SnippetError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SnippetError::NotFound => StatusCode::NOT_FOUND,
+            SnippetError::Conflict(_) => StatusCode::CONFLICT,
+            SnippetError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::database::ReadPool;
+use crate::routes::error_chain_fmt;
+use crate::sponsors::SPONSOR_LINK_PLACEHOLDER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct SponsorSlotData {
+    sponsor_name: String,
+    creative_html: String,
+    target_url: String,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CreatedSponsorSlot {
+    id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+pub struct SponsorReport {
+    id: Uuid,
+    sponsor_name: String,
+    target_url: String,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    impressions: i64,
+    opens: i64,
+    clicks: i64,
+}
+
+impl SponsorSlotData {
+    fn validate(&self) -> Result<(), String> {
+        if self.sponsor_name.trim().is_empty() {
+            return Err("The sponsor name cannot be empty.".into());
+        }
+        if !self.creative_html.contains(SPONSOR_LINK_PLACEHOLDER) {
+            return Err(format!(
+                "The creative must contain `{}` so that clicks can be tracked.",
+                SPONSOR_LINK_PLACEHOLDER
+            ));
+        }
+        match reqwest::Url::parse(&self.target_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            _ => {
+                return Err(format!("{} is not a valid target URL.", self.target_url));
+            }
+        }
+        if self.ends_at <= self.starts_at {
+            return Err("The slot must end after it starts.".into());
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Create a sponsor slot", skip(body, pool), fields(sponsor_name = %body.sponsor_name))]
+pub async fn create_sponsor_slot(
+    body: web::Json<SponsorSlotData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SponsorError> {
+    body.validate().map_err(SponsorError::ValidationError)?;
+    let slot_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO sponsor_slots (
+            id, sponsor_name, creative_html, target_url, starts_at, ends_at, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        slot_id,
+        body.sponsor_name,
+        body.creative_html,
+        body.target_url,
+        body.starts_at,
+        body.ends_at,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the sponsor slot in the database.")?;
+    Ok(HttpResponse::Created().json(CreatedSponsorSlot { id: slot_id }))
+}
+
+#[tracing::instrument(name = "List sponsor slots", skip(pool))]
+pub async fn list_sponsor_slots(pool: web::Data<ReadPool>) -> Result<HttpResponse, SponsorError> {
+    let reports = sqlx::query_as!(
+        SponsorReport,
+        r#"
+        SELECT id, sponsor_name, target_url, starts_at, ends_at, impressions, opens, clicks
+        FROM sponsor_slots
+        ORDER BY starts_at DESC
+        "#
+    )
+    .fetch_all(pool.get().await)
+    .await
+    .context("Failed to fetch sponsor slots from the database.")?;
+    Ok(HttpResponse::Ok().json(reports))
+}
+
+#[tracing::instrument(name = "Get a sponsor slot report", skip(pool))]
+pub async fn get_sponsor_report(
+    slot_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SponsorError> {
+    let report = sqlx::query_as!(
+        SponsorReport,
+        r#"
+        SELECT id, sponsor_name, target_url, starts_at, ends_at, impressions, opens, clicks
+        FROM sponsor_slots
+        WHERE id = $1
+        "#,
+        *slot_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the sponsor slot from the database.")?
+    .ok_or(SponsorError::NotFound)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[tracing::instrument(name = "Delete a sponsor slot", skip(pool))]
+pub async fn delete_sponsor_slot(
+    slot_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SponsorError> {
+    let deleted = sqlx::query!(r#"DELETE FROM sponsor_slots WHERE id = $1"#, *slot_id)
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to delete the sponsor slot from the database.")?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(SponsorError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum SponsorError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The sponsor slot does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SponsorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SponsorError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SponsorError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SponsorError::NotFound => StatusCode::NOT_FOUND,
+            SponsorError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::api_error::ApiError;
+use crate::cache::Cache;
+use crate::database::ReadPool;
+use crate::panics::PANICS;
+use crate::routes::{ConfirmationRejections, error_chain_fmt};
+use crate::scheduler::job_statuses;
+use crate::templates::{EmailTemplates, StatsPage};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+const MAX_DAYS: u32 = 365;
+
+/// Where the funnel stats are kept between computations, shared by every replica with Redis.
+pub struct StatsCache {
+    pub cache: Arc<dyn Cache>,
+    // Not cached at all if zero.
+    pub ttl: Duration,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FunnelQuery {
+    // How many days back the stats go, today included.
+    #[serde(default = "default_days")]
+    days: u32,
+}
+
+fn default_days() -> u32 {
+    30
+}
+
+#[derive(serde::Serialize)]
+pub struct FunnelStats {
+    generated_at: DateTime<Utc>,
+    days: u32,
+    daily: Vec<DailyStats>,
+    // Of the subscribers who signed up in the window, the share who confirmed.
+    confirmation_rate: Option<f64>,
+    // Between signing up and following the confirmation link, for confirmations in the window.
+    time_to_confirm_seconds: Percentiles,
+    // Unsubscriptions in the window, over the confirmed subscribers plus those who left.
+    unsubscribe_rate: Option<f64>,
+    // Issues published in the window, most recent first.
+    issues: Vec<IssueDeliveryStats>,
+}
+
+/// Days are in UTC.
+#[derive(serde::Serialize)]
+pub struct DailyStats {
+    day: NaiveDate,
+    subscribed: i64,
+    confirmed: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Percentiles {
+    p50: Option<f64>,
+    p90: Option<f64>,
+    p99: Option<f64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueDeliveryStats {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    sent: i64,
+    failed: i64,
+    bounced: i64,
+    // The share of attempts that were sent and didn't bounce.
+    success_rate: Option<f64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConfirmationStats {
+    pending: i64,
+    confirmed: i64,
+    // Subscribers that were sent a confirmation reminder, whatever their current status.
+    reminded: i64,
+    // The uplift: subscribers that only confirmed after being reminded.
+    confirmed_after_reminder: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ConfirmationStatsResponse<'a> {
+    #[serde(flatten)]
+    subscribers: ConfirmationStats,
+    // Counted in memory: they reset when the application restarts.
+    rejected_attempts: &'a ConfirmationRejections,
+}
+
+#[tracing::instrument(name = "Get confirmation stats", skip(pool, rejections))]
+pub async fn confirmation_stats(
+    pool: web::Data<ReadPool>,
+    rejections: web::Data<ConfirmationRejections>,
+) -> Result<HttpResponse, StatsError> {
+    let stats = sqlx::query_as!(
+        ConfirmationStats,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'pending_confirmation') AS "pending!",
+            COUNT(*) FILTER (WHERE status = 'confirmed') AS "confirmed!",
+            COUNT(*) FILTER (WHERE confirmation_reminder_sent_at IS NOT NULL) AS "reminded!",
+            COUNT(*) FILTER (
+                WHERE status = 'confirmed' AND confirmation_reminder_sent_at IS NOT NULL
+            ) AS "confirmed_after_reminder!"
+        FROM subscriptions
+        WHERE deleted_at IS NULL
+        "#
+    )
+    .fetch_one(pool.get().await)
+    .await
+    .context("Failed to compute confirmation stats.")?;
+    Ok(HttpResponse::Ok().json(ConfirmationStatsResponse {
+        subscribers: stats,
+        rejected_attempts: &rejections,
+    }))
+}
+
+/// The subscription funnel over the last `days`, computed by Postgres and then served
+/// from the cache until it expires.
+#[tracing::instrument(name = "Get funnel stats", skip_all, fields(days = query.days))]
+pub async fn funnel_stats(
+    query: web::Query<FunnelQuery>,
+    pool: web::Data<ReadPool>,
+    stats_cache: web::Data<StatsCache>,
+) -> Result<HttpResponse, StatsError> {
+    let stats = cached_funnel_stats(query.days, &pool, &stats_cache).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(stats))
+}
+
+/// The same stats as `GET /admin/stats`, as a page of the admin console.
+#[tracing::instrument(name = "Show the stats page", skip_all, fields(days = query.days))]
+pub async fn admin_stats_page(
+    query: web::Query<FunnelQuery>,
+    pool: web::Data<ReadPool>,
+    stats_cache: web::Data<StatsCache>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, StatsError> {
+    let stats = cached_funnel_stats(query.days, &pool, &stats_cache).await?;
+    let stats = serde_json::from_str(&stats).context("Failed to parse the funnel stats.")?;
+    let page = templates.stats_page(&StatsPage { stats: &stats })?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// The funnel stats as JSON, as they are cached.
+async fn cached_funnel_stats(
+    days: u32,
+    pool: &ReadPool,
+    stats_cache: &StatsCache,
+) -> Result<String, StatsError> {
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(StatsError::ValidationError(format!(
+            "Stats go between 1 and {MAX_DAYS} days back."
+        )));
+    }
+    let key = format!("stats:funnel:{days}");
+    let caching = !stats_cache.ttl.is_zero();
+    // The stats can always be recomputed: a cache that is down only makes them slower.
+    let cached = if caching {
+        stats_cache.cache.get(&key).await.unwrap_or_else(|error| {
+            tracing::warn!(error.cause_chain = ?error, "Failed to read the cached funnel stats");
+            None
+        })
+    } else {
+        None
+    };
+    match cached {
+        Some(stats) => Ok(stats),
+        None => {
+            let stats = serde_json::to_string(&compute_funnel_stats(pool.get().await, days).await?)
+                .context("Failed to serialize the funnel stats.")?;
+            if caching
+                && let Err(error) = stats_cache.cache.set(&key, &stats, stats_cache.ttl).await
+            {
+                tracing::warn!(error.cause_chain = ?error, "Failed to cache the funnel stats");
+            }
+            Ok(stats)
+        }
+    }
+}
+
+/// Waitlisted subscribers haven't been asked to confirm yet: they aren't signups until admitted.
+async fn compute_funnel_stats(pool: &PgPool, days: u32) -> Result<FunnelStats, anyhow::Error> {
+    let generated_at = Utc::now();
+    let since = (generated_at.date_naive() - Days::new(u64::from(days) - 1))
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is a valid time.")
+        .and_utc();
+
+    let daily = sqlx::query_as!(
+        DailyStats,
+        r#"
+        WITH days AS (
+            SELECT generate_series(
+                ($1::timestamptz AT TIME ZONE 'UTC')::date,
+                (now() AT TIME ZONE 'UTC')::date,
+                interval '1 day'
+            )::date AS day
+        ),
+        subscribed AS (
+            SELECT (subscribed_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM subscriptions
+            WHERE subscribed_at >= $1 AND status <> 'waitlisted' AND deleted_at IS NULL
+            GROUP BY 1
+        ),
+        confirmed AS (
+            SELECT (confirmed_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM subscriptions WHERE confirmed_at >= $1 AND deleted_at IS NULL GROUP BY 1
+        ),
+        unsubscribed AS (
+            SELECT (unsubscribed_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM subscriptions WHERE unsubscribed_at >= $1 AND deleted_at IS NULL GROUP BY 1
+        )
+        SELECT
+            days.day AS "day!",
+            COALESCE(subscribed.count, 0) AS "subscribed!",
+            COALESCE(confirmed.count, 0) AS "confirmed!",
+            COALESCE(unsubscribed.count, 0) AS "unsubscribed!"
+        FROM days
+        LEFT JOIN subscribed USING (day)
+        LEFT JOIN confirmed USING (day)
+        LEFT JOIN unsubscribed USING (day)
+        ORDER BY days.day
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to compute the daily subscription stats.")?;
+
+    let funnel = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (
+                WHERE subscribed_at >= $1 AND status <> 'waitlisted'
+            ) AS "signups!",
+            COUNT(*) FILTER (
+                WHERE subscribed_at >= $1 AND (status = 'confirmed' OR confirmed_at IS NOT NULL)
+            ) AS "confirmed_signups!",
+            percentile_cont(0.5) WITHIN GROUP (
+                ORDER BY EXTRACT(EPOCH FROM confirmed_at - subscribed_at)::float8
+            ) FILTER (WHERE confirmed_at >= $1) AS p50,
+            percentile_cont(0.9) WITHIN GROUP (
+                ORDER BY EXTRACT(EPOCH FROM confirmed_at - subscribed_at)::float8
+            ) FILTER (WHERE confirmed_at >= $1) AS p90,
+            percentile_cont(0.99) WITHIN GROUP (
+                ORDER BY EXTRACT(EPOCH FROM confirmed_at - subscribed_at)::float8
+            ) FILTER (WHERE confirmed_at >= $1) AS p99,
+            COUNT(*) FILTER (WHERE unsubscribed_at >= $1) AS "unsubscribed!",
+            COUNT(*) FILTER (
+                WHERE status = 'confirmed' OR unsubscribed_at >= $1
+            ) AS "audience!"
+        FROM subscriptions
+        WHERE deleted_at IS NULL
+        "#,
+        since
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to compute the subscription funnel.")?;
+
+    let issues = sqlx::query!(
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at,
+            COALESCE(st.sent, 0)
+                + COUNT(a.*) FILTER (WHERE delivery_counts_as_sent(a.status)) AS "sent!",
+            COUNT(*) FILTER (WHERE a.status = 'failed') AS "failed!",
+            COUNT(*) FILTER (WHERE a.status = 'bounced') AS "bounced!"
+        FROM newsletter_issues i
+        LEFT JOIN newsletter_delivery_attempts a USING (newsletter_issue_id)
+        LEFT JOIN issue_statistics st ON st.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.published_at >= $1
+        GROUP BY i.newsletter_issue_id, st.sent
+        ORDER BY i.published_at DESC
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to compute the delivery stats of recent issues.")?
+    .into_iter()
+    .map(|r| IssueDeliveryStats {
+        newsletter_issue_id: r.newsletter_issue_id,
+        title: r.title,
+        published_at: r.published_at,
+        sent: r.sent,
+        failed: r.failed,
+        bounced: r.bounced,
+        success_rate: ratio(r.sent, r.sent + r.failed + r.bounced),
+    })
+    .collect();
+
+    Ok(FunnelStats {
+        generated_at,
+        days,
+        daily,
+        confirmation_rate: ratio(funnel.confirmed_signups, funnel.signups),
+        time_to_confirm_seconds: Percentiles {
+            p50: funnel.p50,
+            p90: funnel.p90,
+            p99: funnel.p99,
+        },
+        unsubscribe_rate: ratio(funnel.unsubscribed, funnel.audience),
+        issues,
+    })
+}
+
+// `None` rather than a division by zero.
+fn ratio(part: i64, whole: i64) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
+
+/// Counted in memory: they reset when the application restarts.
+pub async fn panic_stats() -> HttpResponse {
+    HttpResponse::Ok().json(&PANICS)
+}
+
+/// Schedule, next run and last outcome of each job this instance schedules.
+pub async fn scheduler_status() -> HttpResponse {
+    HttpResponse::Ok().json(job_statuses())
+}
+
+#[derive(thiserror::Error)]
+pub enum StatsError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StatsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for StatsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StatsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            StatsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            StatsError::ValidationError(e) => ApiError::validation(e),
+            StatsError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
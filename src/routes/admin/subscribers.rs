@@ -0,0 +1,742 @@
+//! Look up and remove subscribers, without going to the database by hand.
+use crate::api_error::ApiError;
+use crate::audit_log::{Actor, AuditEntry, Source, SubscriberEvent, record, subscriber_history};
+use crate::authentication::AuthenticatedUser;
+use crate::database::ReadPool;
+use crate::pii::PiiCipher;
+use crate::routes::{error_chain_fmt, wants_fragment};
+use crate::templates::{EmailTemplates, SubscribersPage};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const MAX_PER_PAGE: u32 = 100;
+
+const STATUSES: &[&str] = &[
+    "pending_confirmation",
+    "confirmed",
+    "waitlisted",
+    "unsubscribed",
+    "bounced",
+];
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct SubscriberQuery {
+    #[serde(default = "first_page")]
+    page: u32,
+    #[serde(default = "default_per_page")]
+    per_page: u32,
+    #[serde(default)]
+    status: Option<String>,
+    // Part of an email or name, case-insensitively.
+    #[serde(default)]
+    search: Option<String>,
+    // The slug of a list, to only list its subscribers.
+    #[serde(default)]
+    newsletter: Option<String>,
+}
+
+fn first_page() -> u32 {
+    1
+}
+
+fn default_per_page() -> u32 {
+    20
+}
+
+impl SubscriberQuery {
+    fn validate(&self) -> Result<(), String> {
+        if self.page == 0 {
+            return Err("Pages are numbered from 1.".into());
+        }
+        if !(1..=MAX_PER_PAGE).contains(&self.per_page) {
+            return Err(format!(
+                "List between 1 and {} subscribers per page.",
+                MAX_PER_PAGE
+            ));
+        }
+        if let Some(status) = &self.status
+            && !STATUSES.contains(&status.as_str())
+        {
+            return Err(format!(
+                "`{status}` is not a subscriber status: use one of {}.",
+                STATUSES.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    fn offset(&self) -> i64 {
+        i64::from(self.page - 1) * i64::from(self.per_page)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberSummary>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberSummary {
+    subscriber_id: Uuid,
+    // The slug of the list they subscribed to.
+    newsletter: String,
+    // `None` if the stored value can't be decrypted, e.g. after its key was dropped.
+    email: Option<String>,
+    name: Option<String>,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// What `GET /admin/subscribers/{id}` can add to a subscriber.
+const INCLUDES: &[&str] = &["deliveries", "engagement"];
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberDetailQuery {
+    // Comma-separated, from `INCLUDES`.
+    #[serde(default)]
+    include: String,
+}
+
+impl SubscriberDetailQuery {
+    fn parse(&self) -> Result<Vec<&str>, String> {
+        self.include
+            .split(',')
+            .map(str::trim)
+            .filter(|include| !include.is_empty())
+            .map(|include| match INCLUDES.contains(&include) {
+                true => Ok(include),
+                false => Err(format!(
+                    "`{include}` can't be included: use any of {}.",
+                    INCLUDES.join(", ")
+                )),
+            })
+            .collect()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    #[serde(flatten)]
+    summary: SubscriberSummary,
+    attributes: serde_json::Value,
+    tags: Vec<String>,
+    deliveries: i64,
+    last_delivered_at: Option<DateTime<Utc>>,
+    // With `?include=deliveries`: the last 5 issues sent to them, most recent first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recent_deliveries: Option<Vec<RecentDelivery>>,
+    // With `?include=engagement`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    engagement: Option<SubscriberEngagement>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RecentDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    // The outcome of the latest attempt, as in the timeline without its `issue_` prefix.
+    status: String,
+    attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberEngagement {
+    opens: i64,
+    clicks: i64,
+    last_engaged_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TimelineQuery {
+    #[serde(default = "first_page")]
+    page: u32,
+    #[serde(default = "default_per_page")]
+    per_page: u32,
+}
+
+impl TimelineQuery {
+    fn validate(&self) -> Result<(), String> {
+        if self.page == 0 {
+            return Err("Pages are numbered from 1.".into());
+        }
+        if !(1..=MAX_PER_PAGE).contains(&self.per_page) {
+            return Err(format!(
+                "List between 1 and {} events per page.",
+                MAX_PER_PAGE
+            ));
+        }
+        Ok(())
+    }
+
+    fn offset(&self) -> i64 {
+        i64::from(self.page - 1) * i64::from(self.per_page)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberTimeline {
+    subscriber_id: Uuid,
+    events: Vec<TimelineEntry>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct TimelineEntry {
+    event: String,
+    occurred_at: DateTime<Utc>,
+    // Who changed the subscriber's status, for the events of their history.
+    actor: Option<String>,
+    username: Option<String>,
+    // The issue sent, opened or clicked.
+    newsletter_issue_id: Option<Uuid>,
+    title: Option<String>,
+    // The link clicked.
+    url: Option<String>,
+    // Why a delivery failed.
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberHistory {
+    subscriber_id: Uuid,
+    status: String,
+    deleted_at: Option<DateTime<Utc>>,
+    events: Vec<AuditEntry>,
+}
+
+/// Most recent first.
+///
+/// With encryption at rest, encrypted subscribers only match a search for their whole
+/// email address: their names and emails can't be searched in the database.
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(query, read_pool, pii_cipher),
+    fields(
+        page = query.page,
+        per_page = query.per_page
+    )
+)]
+pub async fn list_subscribers(
+    query: web::Query<SubscriberQuery>,
+    read_pool: web::Data<ReadPool>,
+    pii_cipher: web::Data<PiiCipher>,
+) -> Result<HttpResponse, SubscriberError> {
+    query.validate().map_err(SubscriberError::ValidationError)?;
+    let subscribers = fetch_subscribers(read_pool.get().await, &pii_cipher, &query).await?;
+    Ok(HttpResponse::Ok().json(subscribers))
+}
+
+/// The same list as `GET /admin/subscribers`, as a page of the admin console. The console
+/// script only gets the table back when it changes the filters or the page.
+#[tracing::instrument(
+    name = "Show the subscribers page",
+    skip(request, query, read_pool, pii_cipher, templates),
+    fields(
+        page = query.page,
+        per_page = query.per_page
+    )
+)]
+pub async fn admin_subscribers_page(
+    request: HttpRequest,
+    query: web::Query<SubscriberQuery>,
+    read_pool: web::Data<ReadPool>,
+    pii_cipher: web::Data<PiiCipher>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, SubscriberError> {
+    let mut query = query.into_inner();
+    // The filter form sends an empty status for any.
+    query.status = query.status.filter(|status| !status.is_empty());
+    query.validate().map_err(SubscriberError::ValidationError)?;
+    let results = fetch_subscribers(read_pool.get().await, &pii_cipher, &query).await?;
+    let page_link = |page| {
+        serde_urlencoded::to_string(SubscriberQuery {
+            page,
+            ..query.clone()
+        })
+        .map(|parameters| format!("/admin/dashboard/subscribers?{parameters}"))
+        .context("Failed to encode the link to a page of subscribers.")
+    };
+    let page = SubscribersPage {
+        results: &results,
+        statuses: STATUSES,
+        status: query.status.as_deref(),
+        search: query.search.as_deref(),
+        previous_page: &page_link(query.page.saturating_sub(1).max(1))?,
+        next_page: &page_link(query.page + 1)?,
+    };
+    let body = match wants_fragment(&request) {
+        true => templates.subscriber_rows(&page),
+        false => templates.subscribers_page(&page),
+    }?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// `query` must be valid.
+async fn fetch_subscribers(
+    read_pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    query: &SubscriberQuery,
+) -> Result<SubscriberPage, anyhow::Error> {
+    let search = query
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|search| !search.is_empty());
+    let pattern = search.map(|search| format!("%{}%", escape_like(search)));
+    let email_index = search.and_then(|search| pii_cipher.email_index(search));
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT s.id, s.email, s.name, s.status, s.subscribed_at, n.slug AS newsletter
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.deleted_at IS NULL
+          AND ($1::text IS NULL OR s.status = $1)
+          AND ($2::text IS NULL
+            OR (s.email NOT LIKE '(pii)%' AND s.email ILIKE $2)
+            OR (s.name NOT LIKE '(pii)%' AND s.name ILIKE $2)
+            OR s.email_blind_index = $3)
+          AND ($6::text IS NULL OR n.slug = $6)
+        ORDER BY s.subscribed_at DESC, s.id
+        LIMIT $4 OFFSET $5
+        "#,
+        query.status,
+        pattern,
+        email_index,
+        i64::from(query.per_page),
+        query.offset(),
+        query.newsletter
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch subscribers from the database.")?;
+    let total = count_subscribers(
+        read_pool,
+        query.status.as_deref(),
+        pattern.as_deref(),
+        email_index.as_deref(),
+        query.newsletter.as_deref(),
+    )
+    .await
+    .context("Failed to count subscribers.")?;
+    let subscribers = rows
+        .into_iter()
+        .map(|row| SubscriberSummary {
+            subscriber_id: row.id,
+            newsletter: row.newsletter,
+            email: decrypt(pii_cipher, row.id, &row.email),
+            name: decrypt(pii_cipher, row.id, &row.name),
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+        })
+        .collect();
+    Ok(SubscriberPage {
+        subscribers,
+        page: query.page,
+        per_page: query.per_page,
+        total,
+    })
+}
+
+async fn count_subscribers(
+    pool: &PgPool,
+    status: Option<&str>,
+    pattern: Option<&str>,
+    email_index: Option<&str>,
+    newsletter: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.deleted_at IS NULL
+          AND ($1::text IS NULL OR s.status = $1)
+          AND ($2::text IS NULL
+            OR (s.email NOT LIKE '(pii)%' AND s.email ILIKE $2)
+            OR (s.name NOT LIKE '(pii)%' AND s.name ILIKE $2)
+            OR s.email_blind_index = $3)
+          AND ($4::text IS NULL OR n.slug = $4)
+        "#,
+        status,
+        pattern,
+        email_index,
+        newsletter
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// The subscriber with their tags and delivery count, in a single query. Their latest
+/// deliveries and their engagement are only looked up when `?include=` asks for them.
+#[tracing::instrument(name = "Get a subscriber", skip(query, pool, pii_cipher))]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    query: web::Query<SubscriberDetailQuery>,
+    pool: web::Data<PgPool>,
+    pii_cipher: web::Data<PiiCipher>,
+) -> Result<HttpResponse, SubscriberError> {
+    let include = query.parse().map_err(SubscriberError::ValidationError)?;
+    let include_deliveries = include.contains(&"deliveries");
+    let include_engagement = include.contains(&"engagement");
+    let subscriber_id = subscriber_id.into_inner();
+    let row = sqlx::query!(
+        r#"
+        SELECT s.email, s.name, s.status, s.subscribed_at, s.attributes, n.slug AS newsletter,
+            ARRAY(
+                SELECT tag FROM subscriber_tags WHERE subscriber_id = s.id ORDER BY tag
+            ) AS "tags!",
+            d.deliveries AS "deliveries!", d.last_delivered_at,
+            CASE WHEN $2 THEN (
+                SELECT COALESCE(jsonb_agg(recent ORDER BY recent.attempted_at DESC), '[]')
+                FROM (
+                    -- The latest attempt at each issue.
+                    SELECT * FROM (
+                        SELECT DISTINCT ON (a.newsletter_issue_id)
+                            a.newsletter_issue_id, i.title, a.status, a.attempted_at
+                        FROM newsletter_delivery_attempts a
+                        JOIN newsletter_issues i
+                            ON i.newsletter_issue_id = a.newsletter_issue_id
+                        WHERE a.subscriber_id = s.id
+                        ORDER BY a.newsletter_issue_id, a.attempted_at DESC
+                    ) latest
+                    ORDER BY attempted_at DESC
+                    LIMIT 5
+                ) recent
+            ) END AS "recent_deliveries: Json<Vec<RecentDelivery>>",
+            e.opens + COALESCE(et.opens, 0) AS "opens!",
+            e.clicks + COALESCE(et.clicks, 0) AS "clicks!",
+            GREATEST(e.last_engaged_at, et.last_engaged_at) AS last_engaged_at
+        FROM subscriptions s
+        JOIN newsletters n ON n.id = s.newsletter_id
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS deliveries, MAX(sent_at) AS last_delivered_at
+            FROM broadcast_deliveries WHERE subscriber_id = s.id
+        ) d
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) FILTER (WHERE kind = 'open') AS opens,
+                COUNT(*) FILTER (WHERE kind = 'click') AS clicks,
+                MAX(occurred_at) AS last_engaged_at
+            FROM tracking_events WHERE $3 AND subscriber_id = s.id
+        ) e
+        -- What is left of the events of compacted issues.
+        LEFT JOIN subscriber_engagement_totals et ON $3 AND et.subscriber_id = s.id
+        WHERE s.id = $1 AND s.deleted_at IS NULL
+        "#,
+        subscriber_id,
+        include_deliveries,
+        include_engagement
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the subscriber from the database.")?
+    .ok_or(SubscriberError::NotFound)?;
+    Ok(HttpResponse::Ok().json(Subscriber {
+        summary: SubscriberSummary {
+            subscriber_id,
+            newsletter: row.newsletter,
+            email: decrypt(&pii_cipher, subscriber_id, &row.email),
+            name: decrypt(&pii_cipher, subscriber_id, &row.name),
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+        },
+        attributes: row.attributes,
+        tags: row.tags,
+        deliveries: row.deliveries,
+        last_delivered_at: row.last_delivered_at,
+        recent_deliveries: row.recent_deliveries.map(|deliveries| deliveries.0),
+        engagement: include_engagement.then_some(SubscriberEngagement {
+            opens: row.opens,
+            clicks: row.clicks,
+            last_engaged_at: row.last_engaged_at,
+        }),
+    }))
+}
+
+/// Marks the subscriber as deleted: they get nothing more, and can sign up again. Their
+/// record and history are kept, see `GET /admin/subscribers/{id}/history`. Admins only.
+#[tracing::instrument(name = "Delete a subscriber", skip(request, user, pool))]
+pub async fn remove_subscriber(
+    request: HttpRequest,
+    user: web::ReqData<AuthenticatedUser>,
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let source = Source::request(Actor::User(user.user_id), &request);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    if !delete_subscriber(&mut transaction, subscriber_id.into_inner(), &source).await? {
+        return Err(SubscriberError::NotFound);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The subscriber's status changes, oldest first, deleted subscribers included.
+#[tracing::instrument(name = "Get the history of a subscriber", skip(pool))]
+pub async fn get_subscriber_history(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = sqlx::query!(
+        r#"SELECT status, deleted_at FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the subscriber from the database.")?
+    .ok_or(SubscriberError::NotFound)?;
+    let events = subscriber_history(&pool, subscriber_id)
+        .await
+        .context("Failed to fetch the history of the subscriber.")?;
+    Ok(HttpResponse::Ok().json(SubscriberHistory {
+        subscriber_id,
+        status: subscriber.status,
+        deleted_at: subscriber.deleted_at,
+        events,
+    }))
+}
+
+/// Everything that happened to the subscriber, most recent first, deleted subscribers
+/// included: the changes of their history, what became of every issue sent to them
+/// (`issue_sent`, `issue_delivered`, `issue_failed`, `issue_bounced` or
+/// `issue_skipped_frequency_cap`), and their opens and clicks.
+#[tracing::instrument(
+    name = "Get the timeline of a subscriber",
+    skip(query, read_pool),
+    fields(page = query.page, per_page = query.per_page)
+)]
+pub async fn get_subscriber_timeline(
+    subscriber_id: web::Path<Uuid>,
+    query: web::Query<TimelineQuery>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let read_pool = read_pool.get().await;
+    query.validate().map_err(SubscriberError::ValidationError)?;
+    let subscriber_id = subscriber_id.into_inner();
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM subscriptions WHERE id = $1) AS "exists!""#,
+        subscriber_id
+    )
+    .fetch_one(read_pool)
+    .await
+    .context("Failed to fetch the subscriber from the database.")?;
+    if !exists {
+        return Err(SubscriberError::NotFound);
+    }
+    let events = sqlx::query_as!(
+        TimelineEntry,
+        r#"
+        SELECT event AS "event!", occurred_at AS "occurred_at!", actor, username,
+            newsletter_issue_id, title, url, error
+        FROM (
+            SELECT a.event, a.occurred_at, a.actor, u.username,
+                NULL::uuid AS newsletter_issue_id, NULL AS title, NULL AS url, NULL AS error
+            FROM audit_log a LEFT JOIN users u ON u.user_id = a.user_id
+            WHERE a.subscriber_id = $1
+            UNION ALL
+            SELECT 'issue_' || d.status, d.attempted_at, NULL, NULL,
+                d.newsletter_issue_id, i.title, NULL, d.error
+            FROM newsletter_delivery_attempts d
+            JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+            WHERE d.subscriber_id = $1
+            UNION ALL
+            SELECT CASE t.kind WHEN 'open' THEN 'opened' ELSE 'clicked' END, t.occurred_at,
+                NULL, NULL, t.newsletter_issue_id, i.title, l.url, NULL
+            FROM tracking_events t
+            JOIN newsletter_issues i ON i.newsletter_issue_id = t.newsletter_issue_id
+            LEFT JOIN newsletter_issue_links l
+                ON l.newsletter_issue_id = t.newsletter_issue_id AND l.link_id = t.link_id
+            WHERE t.subscriber_id = $1
+        ) timeline
+        ORDER BY occurred_at DESC, event
+        LIMIT $2 OFFSET $3
+        "#,
+        subscriber_id,
+        i64::from(query.per_page),
+        query.offset()
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the timeline of the subscriber.")?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM audit_log WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM newsletter_delivery_attempts WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM tracking_events WHERE subscriber_id = $1) AS "total!"
+        "#,
+        subscriber_id
+    )
+    .fetch_one(read_pool)
+    .await
+    .context("Failed to count the events of the subscriber.")?;
+    Ok(HttpResponse::Ok().json(SubscriberTimeline {
+        subscriber_id,
+        events,
+        page: query.page,
+        per_page: query.per_page,
+        total,
+    }))
+}
+
+/// Marks the subscriber as deleted within `transaction`, and drops the ways of reaching
+/// them: tokens, push subscriptions and SMS registration. `false` if there was no such
+/// subscriber, or they were already deleted.
+pub(crate) async fn delete_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    source: &Source,
+) -> Result<bool, anyhow::Error> {
+    let deleted = sqlx::query!(
+        r#"UPDATE subscriptions SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the subscriber as deleted.")?
+    .rows_affected();
+    if deleted == 0 {
+        return Ok(false);
+    }
+    delete_contact_points(transaction, subscriber_id).await?;
+    record(
+        &mut **transaction,
+        subscriber_id,
+        SubscriberEvent::Deleted,
+        source,
+    )
+    .await
+    .context("Failed to record the deletion of the subscriber.")?;
+    Ok(true)
+}
+
+/// Deletes the subscriber within `transaction`, history included: for erasures, which
+/// must leave nothing behind. `false` if there was no such subscriber.
+pub(crate) async fn purge_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    // The other tables that refer to subscribers cascade.
+    delete_contact_points(transaction, subscriber_id).await?;
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete the subscriber.")?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+async fn delete_contact_points(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the subscriber's confirmation tokens.")?;
+    sqlx::query!(
+        r#"DELETE FROM push_subscriptions WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the subscriber's push subscriptions.")?;
+    sqlx::query!(
+        r#"DELETE FROM sms_registrations WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the subscriber's SMS registration.")?;
+    Ok(())
+}
+
+fn decrypt(pii_cipher: &PiiCipher, subscriber_id: Uuid, stored: &str) -> Option<String> {
+    pii_cipher
+        .decrypt(stored)
+        .inspect_err(|error| {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                %subscriber_id,
+                "Failed to decrypt the details of a subscriber",
+            )
+        })
+        .ok()
+}
+
+/// `search` as a literal in a `LIKE` pattern.
+fn escape_like(search: &str) -> String {
+    search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("No subscriber with this id.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriberError::NotFound => StatusCode::NOT_FOUND,
+            SubscriberError::Conflict(_) => StatusCode::CONFLICT,
+            SubscriberError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            SubscriberError::ValidationError(e) => ApiError::validation(e),
+            SubscriberError::NotFound => ApiError::not_found(self.to_string()),
+            SubscriberError::Conflict(e) => ApiError::new(StatusCode::CONFLICT, "conflict", e),
+            SubscriberError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+
+    #[test]
+    fn like_wildcards_in_searches_are_literal() {
+        assert_eq!(escape_like(r"100%_off\"), r"100\%\_off\\");
+    }
+}
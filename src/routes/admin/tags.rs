@@ -0,0 +1,245 @@
+use crate::database::ReadPool;
+use crate::domain::TagName;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct TagSummary {
+    tag: String,
+    // Confirmed subscribers with the tag, i.e. who a segment on it would reach.
+    subscribers: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagsData {
+    tags: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EngagedReadersData {
+    newsletter_issue_id: Uuid,
+    engagement: EngagementKind,
+    tag: String,
+}
+
+/// Readers who clicked count as having opened the issue, as they do in its engagement.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum EngagementKind {
+    Opened,
+    Clicked,
+}
+
+#[tracing::instrument(name = "List subscriber tags", skip(pool))]
+pub async fn list_tags(pool: web::Data<ReadPool>) -> Result<HttpResponse, TagError> {
+    let tags = sqlx::query_as!(
+        TagSummary,
+        r#"
+        SELECT t.tag, COUNT(*) FILTER (WHERE s.status = 'confirmed') AS "subscribers!"
+        FROM subscriber_tags t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE s.deleted_at IS NULL
+        GROUP BY t.tag
+        ORDER BY t.tag
+        "#
+    )
+    .fetch_all(pool.get().await)
+    .await
+    .context("Failed to fetch subscriber tags from the database.")?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+#[tracing::instrument(name = "Get the tags of a subscriber", skip(pool))]
+pub async fn get_subscriber_tags(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, TagError> {
+    let subscriber_id = subscriber_id.into_inner();
+    ensure_subscriber_exists(&pool, subscriber_id).await?;
+    let tags = sqlx::query_scalar!(
+        r#"SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag"#,
+        subscriber_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the tags of a subscriber.")?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+/// Tags the subscriber already has are left alone.
+#[tracing::instrument(name = "Tag a subscriber", skip(body, pool))]
+pub async fn tag_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<TagsData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, TagError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let tags = body
+        .into_inner()
+        .tags
+        .into_iter()
+        .map(TagName::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TagError::ValidationError)?;
+    ensure_subscriber_exists(&pool, subscriber_id).await?;
+    add_tags(pool.get_ref(), subscriber_id, &tags)
+        .await
+        .context("Failed to tag a subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Tags every subscriber who opened (or clicked in) a tracked issue, e.g. to send a follow-up
+/// to its engaged readers only. Subscribers who already have the tag are left alone.
+#[tracing::instrument(
+    name = "Tag the engaged readers of an issue",
+    skip(body, pool),
+    fields(
+        newsletter_issue_id = %body.newsletter_issue_id,
+        engagement = ?body.engagement,
+    )
+)]
+pub async fn tag_engaged_readers(
+    body: web::Json<EngagedReadersData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, TagError> {
+    let EngagedReadersData {
+        newsletter_issue_id,
+        engagement,
+        tag,
+    } = body.into_inner();
+    let tag = TagName::parse(tag).map_err(TagError::ValidationError)?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT i.tracked,
+            EXISTS (
+                SELECT 1 FROM issue_statistics s
+                WHERE s.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "compacted!"
+        FROM newsletter_issues i WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the newsletter issue.")?
+    .ok_or_else(|| TagError::NotFound("There is no such newsletter issue.".into()))?;
+    if !issue.tracked {
+        return Err(TagError::ValidationError(
+            "The issue went out without tracking: nobody is known to have opened it.".into(),
+        ));
+    }
+    if issue.compacted {
+        return Err(TagError::ValidationError(
+            "The issue was compacted: only how many read it is known, not who.".into(),
+        ));
+    }
+    let tagged = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag, created_at)
+        SELECT DISTINCT t.subscriber_id, $2, now()
+        FROM tracking_events t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.newsletter_issue_id = $1
+          AND ($3 OR t.kind = 'click')
+          AND s.deleted_at IS NULL
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        tag.as_ref(),
+        matches!(engagement, EngagementKind::Opened),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to tag the engaged readers of an issue.")?
+    .rows_affected();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tag": tag.as_ref(), "tagged": tagged })))
+}
+
+#[tracing::instrument(name = "Untag a subscriber", skip(pool))]
+pub async fn untag_subscriber(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, TagError> {
+    let (subscriber_id, tag) = path.into_inner();
+    let deleted = sqlx::query!(
+        r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2"#,
+        subscriber_id,
+        tag
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to untag a subscriber.")?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(TagError::NotFound(
+            "The subscriber does not have this tag.".into(),
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Adds `tags` to the subscriber, skipping those they already have.
+#[tracing::instrument(name = "Add tags to a subscriber", skip(executor, tags))]
+pub(crate) async fn add_tags(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    tags: &[TagName],
+) -> Result<(), sqlx::Error> {
+    let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag, created_at)
+        SELECT $1, tag, now() FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        &tags as &[&str]
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+async fn ensure_subscriber_exists(pool: &PgPool, subscriber_id: Uuid) -> Result<(), TagError> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE id = $1 AND deleted_at IS NULL) AS "exists!""#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    if !exists {
+        return Err(TagError::NotFound("There is no such subscriber.".into()));
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum TagError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TagError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TagError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            TagError::NotFound(_) => StatusCode::NOT_FOUND,
+            TagError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Who can use the admin APIs, and with which role. Admins only.
+use crate::api_error::ApiError;
+use crate::authentication::{Role, check_password_strength, compute_password_hash};
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::telemetry::spawn_blocking_with_tracing;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Serialize)]
+pub struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewUserData {
+    username: String,
+    password: SecretString,
+    // Where password reset links go.
+    #[serde(default)]
+    email: Option<String>,
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleData {
+    role: String,
+}
+
+/// Usernames go in `Authorization: Basic` headers, where a colon ends them.
+fn parse_username(username: &str) -> Result<&str, UserError> {
+    let username = username.trim();
+    if username.is_empty()
+        || username.chars().count() > MAX_USERNAME_LENGTH
+        || username.contains(':')
+    {
+        return Err(UserError::ValidationError(format!(
+            "Usernames must be between 1 and {MAX_USERNAME_LENGTH} characters long, \
+            without colons."
+        )));
+    }
+    Ok(username)
+}
+
+#[tracing::instrument(name = "List users", skip_all)]
+pub async fn list_users(pool: web::Data<PgPool>) -> Result<HttpResponse, UserError> {
+    let users = sqlx::query_as!(
+        User,
+        r#"SELECT user_id, username, email, role FROM users ORDER BY username"#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch users from the database.")?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Unlike `zero2prod create-user`, this never resets the password of an existing user.
+#[tracing::instrument(
+    name = "Create a user",
+    skip_all,
+    fields(new_user = %body.username)
+)]
+pub async fn create_user(
+    body: web::Json<NewUserData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let NewUserData {
+        username,
+        password,
+        email,
+        role,
+    } = body.into_inner();
+    let username = parse_username(&username)?;
+    let role = Role::parse(&role).map_err(UserError::ValidationError)?;
+    let email = email
+        .map(|email| SubscriberEmail::parse(email.trim().to_owned()))
+        .transpose()
+        .map_err(|e| UserError::ValidationError(e.to_string()))?;
+    check_password_strength(&password).map_err(UserError::ValidationError)?;
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        email.as_ref().map(|email| email.as_ref()),
+        role.as_str(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to insert the user in the database.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(UserError::Conflict(format!(
+            "A user named `{username}` already exists."
+        )));
+    }
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Takes effect on the user's next request, logged-in sessions included.
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip_all,
+    fields(target_user = %target.as_str())
+)]
+pub async fn update_user_role(
+    target: web::Path<String>,
+    body: web::Json<RoleData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let role = Role::parse(&body.role).map_err(UserError::ValidationError)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let user_id = lock_user(&mut transaction, &target, role != Role::Admin).await?;
+    sqlx::query!(
+        r#"UPDATE users SET role = $2 WHERE user_id = $1"#,
+        user_id,
+        role.as_str()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the role of the user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The issues the user published are kept, without an author.
+#[tracing::instrument(
+    name = "Delete a user",
+    skip_all,
+    fields(target_user = %target.as_str())
+)]
+pub async fn delete_user(
+    target: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let user_id = lock_user(&mut transaction, &target, true).await?;
+    sqlx::query!(r#"DELETE FROM users WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the user from the database.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The id of the user named `username`, locked for the rest of `transaction`. If they are
+/// about to stop being an admin, they must not be the last one: nobody could manage users
+/// anymore.
+async fn lock_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    losing_admin: bool,
+) -> Result<Uuid, UserError> {
+    // Every admin is locked, so that two admins can't demote each other at the same time.
+    let admins =
+        sqlx::query_scalar!(r#"SELECT username FROM users WHERE role = 'admin' FOR UPDATE"#)
+            .fetch_all(&mut **transaction)
+            .await
+            .context("Failed to fetch the admins.")?;
+    let user = sqlx::query!(
+        r#"SELECT user_id, role FROM users WHERE username = $1 FOR UPDATE"#,
+        username
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the user.")?
+    .ok_or(UserError::NotFound)?;
+    if losing_admin && user.role == Role::Admin.as_str() && admins.len() <= 1 {
+        return Err(UserError::Conflict(
+            "There must be at least one admin left.".into(),
+        ));
+    }
+    Ok(user.user_id)
+}
+
+#[derive(thiserror::Error)]
+pub enum UserError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The user does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UserError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UserError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::Conflict(_) => StatusCode::CONFLICT,
+            UserError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            UserError::ValidationError(e) => ApiError::validation(e),
+            UserError::NotFound => ApiError::not_found(self.to_string()),
+            UserError::Conflict(e) => ApiError::new(StatusCode::CONFLICT, "conflict", e),
+            UserError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,99 @@
+use crate::audit_log::{Actor, Source};
+use crate::authentication::AuthenticatedUser;
+use crate::domain::ActionBaseUrl;
+use crate::email_client::EmailClient;
+use crate::pii::PiiCipher;
+use crate::routes::error_chain_fmt;
+use crate::signed_tokens::TokenSigner;
+use crate::startup::SubscriberCap;
+use crate::templates::EmailTemplates;
+use crate::waitlist::admit_from_waitlist;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct AdmitData {
+    count: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct AdmitResponse {
+    admitted: usize,
+    still_waitlisted: i64,
+}
+
+#[tracing::instrument(
+    name = "Admit waitlisted subscribers",
+    skip(
+        request,
+        user,
+        body,
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        subscriber_cap
+    ),
+    fields(count = body.count)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn admit_waitlisted(
+    request: HttpRequest,
+    user: web::ReqData<AuthenticatedUser>,
+    body: web::Json<AdmitData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    subscriber_cap: web::Data<SubscriberCap>,
+) -> Result<HttpResponse, WaitlistError> {
+    if body.count == 0 {
+        return Err(WaitlistError::ValidationError(
+            "Admit at least one subscriber.".into(),
+        ));
+    }
+    let admission = admit_from_waitlist(
+        &pool,
+        &email_client,
+        &templates,
+        &token_signer,
+        &pii_cipher,
+        action_base_url.as_ref().as_ref(),
+        subscriber_cap.0,
+        body.count,
+        &Source::request(Actor::User(user.user_id), &request),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(AdmitResponse {
+        admitted: admission.admitted,
+        still_waitlisted: admission.still_waitlisted,
+    }))
+}
+
+#[derive(thiserror::Error)]
+pub enum WaitlistError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WaitlistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WaitlistError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WaitlistError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WaitlistError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
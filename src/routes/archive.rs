@@ -0,0 +1,260 @@
+//! The public side of each list: its issues as an Atom feed and as a web archive, for
+//! readers who would rather not get them by email. Private issues are left out.
+use crate::api_error::ApiError;
+use crate::database::ReadPool;
+use crate::http_cache::{self, Audience};
+use crate::merge_fields::{Recipient, render_merge_fields};
+use crate::routes::{DEFAULT_LIST_SLUG, Newsletter, error_chain_fmt};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{ArchivePage, ArchivedIssuePage, AtomFeed, EmailTemplates};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// Feed readers only look at the latest entries; the archive has the rest.
+const FEED_LENGTH: i64 = 20;
+
+/// A public issue, with its merge fields resolved for an anonymous reader.
+#[derive(serde::Serialize)]
+pub struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    // Only loaded where the issues are shown in full.
+    html_content: Option<String>,
+    published_at: DateTime<Utc>,
+    // The UTC day, for display.
+    published_on: String,
+    // Its page in the archive.
+    link: String,
+}
+
+impl ArchivedIssue {
+    /// `link` is where its page is: `issue_link` for the archive served here.
+    pub(crate) fn new(
+        link: String,
+        newsletter_issue_id: Uuid,
+        title: String,
+        html_content: Option<String>,
+        published_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            newsletter_issue_id,
+            title,
+            html_content: html_content
+                .map(|html| render_merge_fields(&html, &Recipient::reader(), true).0),
+            published_at,
+            published_on: published_at.format("%Y-%m-%d").to_string(),
+            link,
+        }
+    }
+
+    pub(crate) fn newsletter_issue_id(&self) -> Uuid {
+        self.newsletter_issue_id
+    }
+}
+
+fn issue_link(base_url: &ApplicationBaseUrl, newsletter_issue_id: Uuid) -> String {
+    format!("{}/archive/{}", base_url.0, newsletter_issue_id)
+}
+
+/// The latest public issues of the list, in full. Served for the default list at
+/// `/feed.xml`, and for the others under `/newsletters/{newsletter_slug}`.
+#[tracing::instrument(
+    name = "Get the feed of a list",
+    skip_all,
+    fields(newsletter = %newsletter.slug)
+)]
+pub async fn atom_feed(
+    request: HttpRequest,
+    newsletter: Newsletter,
+    read_pool: web::Data<ReadPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let read_pool = read_pool.get().await;
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, html_content, published_at FROM newsletter_issues
+        WHERE newsletter_id = $1 AND NOT private
+        ORDER BY published_at DESC, newsletter_issue_id
+        LIMIT $2
+        "#,
+        newsletter.id,
+        FEED_LENGTH
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the issues of the feed.")?
+    .into_iter()
+    .map(|row| {
+        ArchivedIssue::new(
+            issue_link(&base_url, row.newsletter_issue_id),
+            row.newsletter_issue_id,
+            row.title,
+            Some(row.html_content),
+            row.published_at,
+        )
+    })
+    .collect::<Vec<_>>();
+    let last_modified = issues.first().map(|issue| issue.published_at);
+    let feed = templates
+        .atom_feed(&AtomFeed {
+            newsletter: &newsletter.name,
+            issues: &issues,
+            // A feed without entries still needs one: a fixed date keeps its ETag stable.
+            updated: last_modified.unwrap_or(DateTime::UNIX_EPOCH),
+            self_link: &format!("{}{}/feed.xml", base_url.0, list_path(&newsletter.slug)),
+            archive_link: &format!("{}{}/archive", base_url.0, list_path(&newsletter.slug)),
+        })
+        .context("Failed to render the feed.")?;
+    Ok(http_cache::respond(
+        &request,
+        ContentType("application/atom+xml; charset=utf-8".parse().unwrap()),
+        feed.into_bytes(),
+        last_modified,
+        Audience::Public,
+    ))
+}
+
+/// Every public issue of the list, most recent first, linking to their pages.
+#[tracing::instrument(
+    name = "Get the archive of a list",
+    skip_all,
+    fields(newsletter = %newsletter.slug)
+)]
+pub async fn archive(
+    request: HttpRequest,
+    newsletter: Newsletter,
+    read_pool: web::Data<ReadPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let read_pool = read_pool.get().await;
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, published_at FROM newsletter_issues
+        WHERE newsletter_id = $1 AND NOT private
+        ORDER BY published_at DESC, newsletter_issue_id
+        "#,
+        newsletter.id
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch the issues of the archive.")?
+    .into_iter()
+    .map(|row| {
+        ArchivedIssue::new(
+            issue_link(&base_url, row.newsletter_issue_id),
+            row.newsletter_issue_id,
+            row.title,
+            None,
+            row.published_at,
+        )
+    })
+    .collect::<Vec<_>>();
+    let page = templates
+        .archive_page(&ArchivePage {
+            newsletter: &newsletter.name,
+            issues: &issues,
+            feed_link: &format!("{}{}/feed.xml", base_url.0, list_path(&newsletter.slug)),
+        })
+        .context("Failed to render the archive.")?;
+    Ok(http_cache::respond(
+        &request,
+        ContentType::html(),
+        page.into_bytes(),
+        issues.first().map(|issue| issue.published_at),
+        Audience::Public,
+    ))
+}
+
+/// A public issue as subscribers got it, without what is personal to them.
+#[tracing::instrument(
+    name = "Get an archived issue",
+    skip(request, read_pool, templates, base_url)
+)]
+pub async fn archived_issue(
+    request: HttpRequest,
+    newsletter_issue_id: web::Path<Uuid>,
+    read_pool: web::Data<ReadPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let read_pool = read_pool.get().await;
+    let row = sqlx::query!(
+        r#"
+        SELECT i.title, i.html_content, i.published_at, n.slug, n.name AS newsletter
+        FROM newsletter_issues i JOIN newsletters n ON n.id = i.newsletter_id
+        WHERE i.newsletter_issue_id = $1 AND NOT i.private
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(read_pool)
+    .await
+    .context("Failed to fetch the archived issue.")?
+    .ok_or(ArchiveError::NotFound)?;
+    let issue = ArchivedIssue::new(
+        issue_link(&base_url, *newsletter_issue_id),
+        *newsletter_issue_id,
+        row.title,
+        Some(row.html_content),
+        row.published_at,
+    );
+    let page = templates
+        .archived_issue_page(&ArchivedIssuePage {
+            newsletter: &row.newsletter,
+            archive_link: &format!("{}{}/archive", base_url.0, list_path(&row.slug)),
+            issue: &issue,
+        })
+        .context("Failed to render the archived issue.")?;
+    Ok(http_cache::respond(
+        &request,
+        ContentType::html(),
+        page.into_bytes(),
+        Some(issue.published_at),
+        Audience::Public,
+    ))
+}
+
+/// Where the public routes of a list live: at the root for the default list.
+pub(crate) fn list_path(slug: &str) -> String {
+    if slug == DEFAULT_LIST_SLUG {
+        String::new()
+    } else {
+        format!("/newsletters/{slug}")
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("No public issue with this id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::NotFound => StatusCode::NOT_FOUND,
+            ArchiveError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            ArchiveError::NotFound => ApiError::not_found(self.to_string()),
+            ArchiveError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,251 @@
+use crate::domain::Language;
+use crate::routes::{
+    PreferenceKind, SubscriberToken, error_chain_fmt, generate_subscription_token, prefers_html,
+};
+use crate::templates::{EmailTemplates, PreferencesPage};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct CategoryPreference {
+    name: String,
+    description: String,
+    subscribed: bool,
+}
+
+/// Category name to whether the subscriber wants issues in it.
+/// Categories that are left out keep their current setting.
+#[derive(serde::Deserialize)]
+pub struct CategoryPreferencesData {
+    categories: HashMap<String, bool>,
+}
+
+/// Every issue category, and whether the subscriber gets issues in it. Browsers get the
+/// preferences page instead, in the subscriber's language.
+#[tracing::instrument(name = "Get category preferences", skip_all)]
+pub async fn get_category_preferences(
+    request: HttpRequest,
+    token: SubscriberToken<PreferenceKind>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+    let preferences = load_preferences(&pool, token.subscriber_id).await?;
+    if prefers_html(&request) {
+        return preferences_page(&request, &pool, &templates, &token, &preferences, false).await;
+    }
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[tracing::instrument(name = "Update category preferences", skip_all)]
+pub async fn update_category_preferences(
+    token: SubscriberToken<PreferenceKind>,
+    body: web::Json<CategoryPreferencesData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferencesError> {
+    let categories: Vec<String> = sqlx::query_scalar!(r#"SELECT name FROM issue_categories"#)
+        .fetch_all(pool.get_ref())
+        .await
+        .context("Failed to fetch issue categories from the database.")?;
+    if let Some(unknown) = body
+        .categories
+        .keys()
+        .find(|category| !categories.contains(category))
+    {
+        return Err(PreferencesError::ValidationError(format!(
+            "There is no category named `{unknown}`."
+        )));
+    }
+    store_preferences(&pool, token.subscriber_id, &body.categories).await?;
+
+    let preferences = load_preferences(&pool, token.subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+/// The form of the preferences page: a field for each category the subscriber wants,
+/// none for those they don't.
+#[tracing::instrument(name = "Submit the category preferences form", skip_all)]
+pub async fn submit_category_preferences(
+    request: HttpRequest,
+    token: SubscriberToken<PreferenceKind>,
+    form: web::Form<HashMap<String, String>>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, PreferencesError> {
+    let categories: HashMap<String, bool> =
+        sqlx::query_scalar!(r#"SELECT name FROM issue_categories"#)
+            .fetch_all(pool.get_ref())
+            .await
+            .context("Failed to fetch issue categories from the database.")?
+            .into_iter()
+            .map(|category| {
+                let subscribed = form.contains_key(&category);
+                (category, subscribed)
+            })
+            .collect();
+    store_preferences(&pool, token.subscriber_id, &categories).await?;
+
+    let preferences = load_preferences(&pool, token.subscriber_id).await?;
+    preferences_page(&request, &pool, &templates, &token, &preferences, true).await
+}
+
+async fn preferences_page(
+    request: &HttpRequest,
+    pool: &PgPool,
+    templates: &EmailTemplates,
+    token: &SubscriberToken<PreferenceKind>,
+    preferences: &[CategoryPreference],
+    saved: bool,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT n.name AS newsletter, s.language
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.id = $1
+        "#,
+        token.subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the list of the subscriber.")?;
+    let language = subscriber
+        .language
+        .as_deref()
+        .and_then(|l| Language::parse(l).ok());
+    // The form posts back here, token and all.
+    let action = request
+        .uri()
+        .path_and_query()
+        .map_or(request.path(), |path| path.as_str());
+    let page = templates
+        .preferences_page(
+            &PreferencesPage {
+                newsletter: &subscriber.newsletter,
+                categories: preferences,
+                action,
+                saved,
+            },
+            language.as_ref(),
+        )
+        .context("Failed to render the preferences page.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// The subscriber's preference token, handed out when they confirm and on every issue they
+/// get. Links carry it signed: see `TokenSigner`.
+#[tracing::instrument(name = "Get a preference token", skip(pool))]
+pub async fn get_or_create_preference_token(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    // The SELECT doesn't see the row the INSERT adds: one of the two returns the token.
+    sqlx::query_scalar!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO preference_tokens (subscriber_id, preference_token, created_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT (subscriber_id) DO NOTHING
+            RETURNING preference_token
+        )
+        SELECT preference_token AS "preference_token!" FROM inserted
+        UNION ALL
+        SELECT preference_token FROM preference_tokens WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        generate_subscription_token()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Categories that are left out keep their current setting.
+async fn store_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    categories: &HashMap<String, bool>,
+) -> Result<(), PreferencesError> {
+    let (subscribed, opted_out): (Vec<_>, Vec<_>) =
+        categories.iter().partition(|(_, subscribed)| **subscribed);
+    let subscribed: Vec<String> = subscribed.into_iter().map(|(c, _)| c.clone()).collect();
+    let opted_out: Vec<String> = opted_out.into_iter().map(|(c, _)| c.clone()).collect();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"DELETE FROM category_opt_outs WHERE subscriber_id = $1 AND category = ANY($2)"#,
+        subscriber_id,
+        &subscribed
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove category opt-outs.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO category_opt_outs (subscriber_id, category)
+        SELECT $1, category FROM UNNEST($2::text[]) AS category
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        &opted_out
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store category opt-outs.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update category preferences.")?;
+    Ok(())
+}
+
+async fn load_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<CategoryPreference>, PreferencesError> {
+    let preferences = sqlx::query_as!(
+        CategoryPreference,
+        r#"
+        SELECT c.name, c.description, NOT EXISTS (
+            SELECT 1 FROM category_opt_outs o
+            WHERE o.subscriber_id = $1 AND o.category = c.name
+        ) AS "subscribed!"
+        FROM issue_categories c
+        ORDER BY c.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch category preferences from the database.")?;
+    Ok(preferences)
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
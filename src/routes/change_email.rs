@@ -0,0 +1,292 @@
+//! Moving a subscription to another email address. The new address has to be confirmed
+//! first, through a link emailed to it: until then the subscription stays on the old one.
+use crate::api_error::ApiError;
+use crate::audit_log::{Actor, Source, SubscriberEvent, record};
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::merge_fields::escape;
+use crate::pii::PiiCipher;
+use crate::routes::{
+    PreferenceKind, SubscriberToken, error_chain_fmt, generate_subscription_token,
+    hash_subscription_token, is_well_formed_subscription_token,
+};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const EMAIL_CHANGE_TOKEN_LIFETIME: Duration = Duration::hours(24);
+
+#[derive(serde::Deserialize)]
+pub struct ChangeEmailData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChangeEmailToken {
+    token: String,
+}
+
+/// Asking again replaces the pending change, and the link sent for it.
+#[tracing::instrument(
+    name = "Request an email change",
+    skip_all,
+    fields(subscriber_id = %token.subscriber_id)
+)]
+pub async fn request_email_change(
+    token: SubscriberToken<PreferenceKind>,
+    form: web::Form<ChangeEmailData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, ChangeEmailError> {
+    let new_email = SubscriberEmail::parse(form.0.email)
+        .map_err(|e| ChangeEmailError::ValidationError(e.to_string()))?;
+    let subscription = sqlx::query!(
+        r#"
+        SELECT s.newsletter_id, n.name AS newsletter
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.id = $1 AND s.deleted_at IS NULL
+        "#,
+        token.subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber.")?
+    .ok_or(ChangeEmailError::UnknownToken)?;
+    let new_email_index = pii_cipher.email_index(new_email.as_ref());
+    // Also turns away the address the subscription is already on.
+    let already_subscribed = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscriptions
+            WHERE newsletter_id = $3 AND (email = $1 OR email_blind_index = $2)
+              AND deleted_at IS NULL
+        ) AS "exists!"
+        "#,
+        new_email.as_ref(),
+        new_email_index,
+        subscription.newsletter_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to look up the subscribers of the list.")?;
+    if already_subscribed {
+        return Err(ChangeEmailError::AlreadySubscribed);
+    }
+    let change_token = generate_subscription_token();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO pending_email_changes
+            (subscriber_id, new_email, new_email_blind_index, token_hash, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (subscriber_id) DO UPDATE SET
+            new_email = EXCLUDED.new_email,
+            new_email_blind_index = EXCLUDED.new_email_blind_index,
+            token_hash = EXCLUDED.token_hash,
+            created_at = EXCLUDED.created_at,
+            expires_at = EXCLUDED.expires_at
+        "#,
+        token.subscriber_id,
+        pii_cipher.encrypt(new_email.as_ref()),
+        new_email_index,
+        hash_subscription_token(&change_token),
+        now,
+        now + EMAIL_CHANGE_TOKEN_LIFETIME
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store a pending email change.")?;
+    send_email_change_email(
+        &email_client,
+        &new_email,
+        &subscription.newsletter,
+        &action_base_url,
+        &change_token,
+    )
+    .await?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// The link only works once. If the new address made it onto the list in the meantime,
+/// the subscription stays where it is.
+#[tracing::instrument(
+    name = "Confirm an email change",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn confirm_email_change(
+    request: HttpRequest,
+    parameters: web::Query<ChangeEmailToken>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ChangeEmailError> {
+    if !is_well_formed_subscription_token(&parameters.token) {
+        return Err(ChangeEmailError::UnknownToken);
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let change = sqlx::query!(
+        r#"
+        DELETE FROM pending_email_changes c
+        USING subscriptions s
+        WHERE c.token_hash = $1 AND c.expires_at > now()
+          AND s.id = c.subscriber_id AND s.deleted_at IS NULL
+        RETURNING c.subscriber_id, c.new_email, c.new_email_blind_index
+        "#,
+        hash_subscription_token(&parameters.token)
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to consume an email change token.")?
+    .ok_or(ChangeEmailError::UnknownToken)?;
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(&change.subscriber_id),
+    );
+    change_email(
+        &mut transaction,
+        change.subscriber_id,
+        &change.new_email,
+        change.new_email_blind_index.as_deref(),
+    )
+    .await?;
+    record(
+        &mut *transaction,
+        change.subscriber_id,
+        SubscriberEvent::EmailChanged,
+        &Source::request(Actor::Subscriber, &request),
+    )
+    .await
+    .context("Failed to record an email change.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email address.")?;
+    tracing::info!("Changed the email address of a subscriber");
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// `new_email` is as stored, encrypted or not: with encryption, conflicts can only be found
+/// through the blind index.
+#[tracing::instrument(
+    name = "Change the email of a subscriber",
+    skip(transaction, new_email, new_email_blind_index)
+)]
+async fn change_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &str,
+    new_email_blind_index: Option<&str>,
+) -> Result<(), ChangeEmailError> {
+    let changed = sqlx::query!(
+        r#"
+        UPDATE subscriptions s SET email = $2, email_blind_index = $3
+        WHERE s.id = $1 AND NOT EXISTS (
+            SELECT 1 FROM subscriptions other
+            WHERE other.newsletter_id = s.newsletter_id AND other.id <> s.id
+              AND (other.email = $2 OR other.email_blind_index = $3)
+              AND other.deleted_at IS NULL
+        )
+        "#,
+        subscriber_id,
+        new_email,
+        new_email_blind_index
+    )
+    .execute(&mut **transaction)
+    .await;
+    match changed {
+        Ok(changed) if changed.rows_affected() == 1 => Ok(()),
+        Ok(_) => Err(ChangeEmailError::AlreadySubscribed),
+        // Someone subscribed with the address concurrently.
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(ChangeEmailError::AlreadySubscribed)
+        }
+        Err(e) => Err(anyhow::Error::new(e)
+            .context("Failed to change the email of the subscriber.")
+            .into()),
+    }
+}
+
+async fn send_email_change_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    newsletter: &str,
+    action_base_url: &ActionBaseUrl,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/change_email/confirm?token={}",
+        action_base_url.as_ref(),
+        token
+    );
+    let plain_body = format!(
+        "Visit {confirmation_link} to receive {newsletter} at this address.\n\
+        The link expires in 24 hours. If you didn't ask for this, you can ignore this email."
+    );
+    let html_body = format!(
+        "Click <a href=\"{confirmation_link}\">here</a> to receive {} at this address.<br />\
+        The link expires in 24 hours. If you didn't ask for this, you can ignore this email.",
+        escape(newsletter)
+    );
+    email_client
+        .send_email(
+            recipient,
+            "Confirm your new email address",
+            &html_body,
+            &plain_body,
+            MessageCategory::Transactional,
+        )
+        .await
+        .context("Failed to send an email change confirmation email.")
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangeEmailError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("This email address is already subscribed to the list.")]
+    AlreadySubscribed,
+    #[error("The link is invalid or has expired.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangeEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ChangeEmailError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ChangeEmailError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ChangeEmailError::AlreadySubscribed => StatusCode::CONFLICT,
+            ChangeEmailError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ChangeEmailError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            ChangeEmailError::ValidationError(e) => ApiError::validation(e),
+            ChangeEmailError::AlreadySubscribed => ApiError::new(
+                StatusCode::CONFLICT,
+                "duplicate-subscriber",
+                self.to_string(),
+            ),
+            ChangeEmailError::UnknownToken => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "invalid-token", self.to_string())
+            }
+            ChangeEmailError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,343 @@
+//! Callbacks from the email provider, in Postmark's format.
+use crate::api_error::ApiError;
+use crate::audit_log::{Actor, Source, SubscriberEvent, record_all};
+use crate::configuration::EmailWebhookSettings;
+use crate::domain::SubscriberEmail;
+use crate::metrics::{DiscardedWebhookEvent, record_discarded_webhook_event};
+use crate::pii::PiiCipher;
+use crate::routes::{basic_authentication, error_chain_fmt};
+use crate::startup::EmailWebhooks;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Bounce kinds after which an address is not worth sending to again.
+/// Soft bounces, e.g. a full mailbox, are left alone.
+const PERMANENT_BOUNCE_TYPES: &[&str] = &["HardBounce", "BadEmailAddress", "SpamComplaint"];
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceData {
+    // `Bounce` or `SpamComplaint`.
+    record_type: String,
+    // Unique to the bounce, unlike `MessageID`.
+    #[serde(rename = "ID")]
+    id: i64,
+    // The message that bounced. Missing from complaints about emails sent elsewhere.
+    #[serde(rename = "MessageID", default)]
+    message_id: Option<String>,
+    #[serde(rename = "Type", default)]
+    bounce_type: Option<String>,
+    email: String,
+    bounced_at: DateTime<Utc>,
+}
+
+impl BounceData {
+    fn is_permanent(&self) -> bool {
+        self.record_type == "SpamComplaint"
+            || self
+                .bounce_type
+                .as_deref()
+                .is_some_and(|kind| PERMANENT_BOUNCE_TYPES.contains(&kind))
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeliveryData {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    delivered_at: DateTime<Utc>,
+}
+
+/// The delivery of an issue that a provider event is about, found by the id of its message.
+struct MessageDelivery {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    provider_event_at: Option<DateTime<Utc>>,
+}
+
+impl MessageDelivery {
+    fn has_newer_event_than(&self, occurred_at: DateTime<Utc>) -> bool {
+        self.provider_event_at
+            .is_some_and(|handled_at| handled_at >= occurred_at)
+    }
+}
+
+/// Marks the subscriber as `bounced` after a hard bounce or a spam complaint, so that they
+/// get no more issues, along with the delivery of the message that bounced. Answers `200`
+/// for anything it ignores too, so that the provider doesn't retry.
+///
+/// Bounces the provider sends again, or that are older than an event already handled for
+/// the delivery of their message, are ignored. A message that isn't an issue we sent, e.g. a
+/// confirmation email, still bounces the subscriber but marks no delivery.
+#[tracing::instrument(
+    name = "Handle a bounce",
+    skip(request, body, pool, pii_cipher, email_webhooks),
+    fields(record_type = %body.record_type, bounce_type = ?body.bounce_type, bounce_id = %body.id)
+)]
+pub async fn bounce_webhook(
+    request: HttpRequest,
+    body: web::Json<BounceData>,
+    pool: web::Data<PgPool>,
+    pii_cipher: web::Data<PiiCipher>,
+    email_webhooks: web::Data<EmailWebhooks>,
+) -> Result<HttpResponse, EmailWebhookError> {
+    let Some(settings) = &email_webhooks.0 else {
+        return Err(EmailWebhookError::NotEnabled);
+    };
+    if !is_authorised(&request, settings) {
+        return Err(EmailWebhookError::AuthError);
+    }
+    if !body.is_permanent() {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let BounceData {
+        id,
+        message_id,
+        email,
+        bounced_at,
+        ..
+    } = body.into_inner();
+    let email = SubscriberEmail::parse(email)
+        .map_err(|e| EmailWebhookError::ValidationError(e.to_string()))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !is_new_event(&mut transaction, &format!("bounce:{id}")).await? {
+        record_discarded_webhook_event(DiscardedWebhookEvent::Duplicate);
+        tracing::info!("Ignored a bounce the provider had already sent");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    // Every list the address is subscribed to: it bounces for all of them.
+    let subscriber_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE (email = $1 OR email_blind_index = $2)
+          AND status IN ('pending_confirmation', 'confirmed', 'waitlisted')
+          AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+        pii_cipher.email_index(email.as_ref())
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the bounced subscriber.")?;
+    if subscriber_ids.is_empty() {
+        commit_event(transaction).await?;
+        tracing::info!("The bounced address has no active subscriber");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let delivery = match &message_id {
+        Some(message_id) => message_delivery(&mut transaction, message_id).await?,
+        None => None,
+    };
+    match &delivery {
+        Some(delivery) if delivery.has_newer_event_than(bounced_at) => {
+            commit_event(transaction).await?;
+            record_discarded_webhook_event(DiscardedWebhookEvent::OutOfOrder);
+            tracing::info!("Ignored a bounce older than the latest delivery event");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Some(_) => {}
+        None => tracing::info!(
+            ?message_id,
+            "The bounced message is not a delivery of an issue"
+        ),
+    }
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'bounced' WHERE id = ANY($1)",
+        &subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the subscriber as bounced.")?;
+    if let Some(delivery) = &delivery {
+        set_delivery_status(&mut transaction, delivery, "bounced", bounced_at).await?;
+    }
+    record_all(
+        &mut *transaction,
+        &subscriber_ids,
+        SubscriberEvent::Bounced,
+        &Source::request(Actor::EmailProvider, &request),
+    )
+    .await
+    .context("Failed to record a bounce.")?;
+    commit_event(transaction).await?;
+    tracing::info!(?subscriber_ids, "Marked a subscriber as bounced");
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Marks the delivery of the message as `delivered`, unless the provider sent the event
+/// before or an event newer than it, e.g. a bounce, has already been handled. Messages that
+/// aren't an issue we sent are ignored.
+#[tracing::instrument(
+    name = "Handle a delivery",
+    skip(request, body, pool, email_webhooks),
+    fields(message_id = %body.message_id)
+)]
+pub async fn delivery_webhook(
+    request: HttpRequest,
+    body: web::Json<DeliveryData>,
+    pool: web::Data<PgPool>,
+    email_webhooks: web::Data<EmailWebhooks>,
+) -> Result<HttpResponse, EmailWebhookError> {
+    let Some(settings) = &email_webhooks.0 else {
+        return Err(EmailWebhookError::NotEnabled);
+    };
+    if !is_authorised(&request, settings) {
+        return Err(EmailWebhookError::AuthError);
+    }
+    let DeliveryData {
+        message_id,
+        delivered_at,
+    } = body.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !is_new_event(&mut transaction, &format!("delivery:{message_id}")).await? {
+        record_discarded_webhook_event(DiscardedWebhookEvent::Duplicate);
+        tracing::info!("Ignored a delivery the provider had already sent");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    match message_delivery(&mut transaction, &message_id).await? {
+        Some(delivery) if delivery.has_newer_event_than(delivered_at) => {
+            record_discarded_webhook_event(DiscardedWebhookEvent::OutOfOrder);
+            tracing::info!("Ignored a delivery older than the latest delivery event");
+        }
+        Some(delivery) => {
+            set_delivery_status(&mut transaction, &delivery, "delivered", delivered_at).await?;
+        }
+        None => {
+            record_discarded_webhook_event(DiscardedWebhookEvent::UnknownMessage);
+            tracing::info!("Ignored a delivery of a message that is not an issue we sent");
+        }
+    }
+    commit_event(transaction).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Remembers a provider event, returning whether it is the first time it was seen.
+/// Concurrent deliveries of the same event wait on each other, and only one gets `true`.
+async fn is_new_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event_id: &str,
+) -> Result<bool, anyhow::Error> {
+    let inserted = sqlx::query!(
+        "INSERT INTO email_webhook_events (event_id) VALUES ($1) ON CONFLICT DO NOTHING",
+        event_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record a webhook event.")?;
+    Ok(inserted.rows_affected() == 1)
+}
+
+/// Subscribers erased since the message was sent have no delivery left to find.
+async fn message_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    message_id: &str,
+) -> Result<Option<MessageDelivery>, anyhow::Error> {
+    sqlx::query_as!(
+        MessageDelivery,
+        r#"
+        SELECT newsletter_issue_id, subscriber_id, provider_event_at
+        FROM newsletter_delivery_attempts
+        WHERE provider_message_id = $1 AND status IN ('sent', 'delivered', 'bounced')
+        FOR UPDATE
+        "#,
+        message_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look up the delivery of a message.")
+}
+
+async fn set_delivery_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    delivery: &MessageDelivery,
+    status: &str,
+    occurred_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_delivery_attempts SET status = $3, provider_event_at = $4
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        status,
+        occurred_at
+    )
+    .execute(&mut **transaction)
+    .await
+    .with_context(|| format!("Failed to mark a delivery as {status}."))?;
+    Ok(())
+}
+
+async fn commit_event(transaction: Transaction<'_, Postgres>) -> Result<(), anyhow::Error> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a webhook event.")
+}
+
+fn is_authorised(request: &HttpRequest, settings: &EmailWebhookSettings) -> bool {
+    let Ok(credentials) = basic_authentication(request.headers()) else {
+        return false;
+    };
+    // Digests are compared rather than the passwords themselves, so that how long the
+    // comparison takes says nothing about the configured password.
+    credentials.username == settings.username
+        && Sha256::digest(credentials.password.expose_secret().as_bytes())
+            == Sha256::digest(settings.password.expose_secret().as_bytes())
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailWebhookError {
+    #[error("Email webhooks are not enabled.")]
+    NotEnabled,
+    #[error("Authentication failed.")]
+    AuthError,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailWebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EmailWebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            EmailWebhookError::NotEnabled => StatusCode::NOT_FOUND,
+            EmailWebhookError::AuthError => StatusCode::UNAUTHORIZED,
+            EmailWebhookError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            EmailWebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            EmailWebhookError::NotEnabled => ApiError::not_found(self.to_string()),
+            EmailWebhookError::ValidationError(e) => ApiError::validation(e),
+            EmailWebhookError::AuthError => ApiError::unauthorized("email_webhooks"),
+            EmailWebhookError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
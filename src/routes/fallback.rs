@@ -0,0 +1,165 @@
+//! Responses for requests that don't reach a handler: unknown paths (404) and
+//! known paths called with the wrong method (405). Both go by `authorization::POLICY`,
+//! which lists every route `startup::run` serves with its methods.
+use crate::authorization::POLICY;
+use actix_web::dev::ResourceDef;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, ContentType, Header, HeaderValue};
+use actix_web::{HttpRequest, HttpResponse, mime};
+
+// Paths further than this from every route get no suggestions.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The application's `default_service`, reached when no route matched the request.
+///
+/// Routes registered with `.route` only match their own method, so a known path
+/// called with another method ends up here too: it gets a 405 rather than a 404.
+pub async fn no_matching_route(req: HttpRequest) -> HttpResponse {
+    match allowed_methods(req.path()) {
+        Some(allowed_methods) => {
+            let detail = format!("Allowed methods: {allowed_methods}.");
+            let mut response =
+                fallback_response(&req, StatusCode::METHOD_NOT_ALLOWED, &detail, &[]);
+            if let Ok(allowed_methods) = HeaderValue::from_str(&allowed_methods) {
+                response
+                    .headers_mut()
+                    .insert(header::ALLOW, allowed_methods);
+            }
+            response
+        }
+        None => {
+            let suggestions = suggest_routes(req.path());
+            let detail = match suggestions.as_slice() {
+                [] => String::new(),
+                suggestions => format!("Did you mean {}?", suggestions.join(", ")),
+            };
+            fallback_response(&req, StatusCode::NOT_FOUND, &detail, &suggestions)
+        }
+    }
+}
+
+/// The methods of the first route in `POLICY` that matches `path`, which is the one routing
+/// picks as long as the policy lists routes in the order they are registered.
+fn allowed_methods(path: &str) -> Option<String> {
+    let (route, ..) = POLICY
+        .iter()
+        .find(|(route, ..)| ResourceDef::new(*route).is_match(path))?;
+    let methods: Vec<_> = POLICY
+        .iter()
+        .filter(|(other, ..)| other == route)
+        .map(|(_, method, _)| *method)
+        .collect();
+    Some(methods.join(", "))
+}
+
+/// JSON unless the client prefers HTML, as browsers do.
+fn fallback_response(
+    req: &HttpRequest,
+    status: StatusCode,
+    detail: &str,
+    suggestions: &[&str],
+) -> HttpResponse {
+    let reason = status.canonical_reason().unwrap_or_default();
+    if !prefers_html(req) {
+        return HttpResponse::build(status).json(serde_json::json!({
+            "error": reason,
+            "detail": detail,
+            "suggestions": suggestions,
+        }));
+    }
+    let suggestions: String = suggestions
+        .iter()
+        .map(|path| format!("<li><code>{path}</code></li>"))
+        .collect();
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>{reason} · zero2prod</title></head>\
+            <body><h1>{reason}</h1><p>{detail}</p><ul>{suggestions}</ul></body></html>"
+        ))
+}
+
+pub(crate) fn prefers_html(req: &HttpRequest) -> bool {
+    header::Accept::parse(req)
+        .ok()
+        .and_then(|accept| {
+            accept
+                .ranked()
+                .into_iter()
+                .find(|mime| *mime == mime::TEXT_HTML || *mime == mime::APPLICATION_JSON)
+        })
+        .is_some_and(|mime| mime == mime::TEXT_HTML)
+}
+
+/// Known routes that are a few edits away from `path`, closest first.
+fn suggest_routes(path: &str) -> Vec<&'static str> {
+    let mut candidates: Vec<_> = POLICY
+        .iter()
+        .map(|(route, ..)| (distance(path, route), *route))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .collect();
+    // The policy lists a route once per method.
+    candidates.sort();
+    candidates.dedup();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, route)| route)
+        .collect()
+}
+
+/// Edit distance between `path` and `route`, where `{parameters}` match any single segment.
+fn distance(path: &str, route: &str) -> usize {
+    let path_segments: Vec<_> = path.trim_end_matches('/').split('/').collect();
+    let route_segments: Vec<_> = route.split('/').collect();
+    if path_segments.len() == route_segments.len() {
+        path_segments
+            .iter()
+            .zip(route_segments)
+            .map(|(path_segment, route_segment)| {
+                if route_segment.starts_with('{') {
+                    0
+                } else {
+                    levenshtein(path_segment, route_segment)
+                }
+            })
+            .sum()
+    } else {
+        levenshtein(path, route)
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suggest_routes;
+
+    #[test]
+    fn near_miss_paths_get_suggestions() {
+        assert_eq!(suggest_routes("/subscription"), vec!["/subscriptions"]);
+        assert_eq!(suggest_routes("/health-check"), vec!["/health_check"]);
+        assert_eq!(
+            suggest_routes("/sponsors/42/clik"),
+            vec!["/sponsors/{slot_id}/click"]
+        );
+    }
+
+    #[test]
+    fn unrelated_paths_get_no_suggestions() {
+        assert!(suggest_routes("/wp-login.php").is_empty());
+    }
+}
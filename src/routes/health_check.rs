@@ -1,5 +1,110 @@
-use actix_web::HttpResponse;
+use crate::email_client::EmailClient;
+use crate::startup::Redis;
+use actix_web::{HttpResponse, web};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
+// Well below the timeouts of load balancers and orchestrators probing us.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests, whatever the state of its dependencies.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+// From best to worst, so that the overall status is the worst of its components.
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    // A dependency that isn't critical is failing: requests mostly still work.
+    Degraded,
+    Failing,
+}
+
+#[derive(serde::Serialize)]
+struct ComponentHealth {
+    status: Status,
+    // Whether the instance can't serve requests without it.
+    critical: bool,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    status: Status,
+    components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+/// Readiness: probes every dependency at once, and answers `503` if a critical one is failing
+/// so that the instance is taken out of rotation until it recovers.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    redis: web::Data<Redis>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let database = probe(true, async {
+        sqlx::query("SELECT 1")
+            .execute(pool.get_ref())
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from)
+    });
+    let redis = async {
+        match &redis.0 {
+            Some(cache) => Some(probe(true, cache.ping()).await),
+            None => None,
+        }
+    };
+    let email_provider = probe(false, async {
+        email_client.ping().await.map_err(anyhow::Error::from)
+    });
+    let (database, redis, email_provider) = tokio::join!(database, redis, email_provider);
+
+    let mut components =
+        BTreeMap::from([("database", database), ("email_provider", email_provider)]);
+    if let Some(redis) = redis {
+        components.insert("redis", redis);
+    }
+    let status = components
+        .values()
+        .map(|component| component.status)
+        .max()
+        .unwrap_or(Status::Ok);
+    let mut response = match status {
+        Status::Failing => HttpResponse::ServiceUnavailable(),
+        Status::Ok | Status::Degraded => HttpResponse::Ok(),
+    };
+    response.json(Readiness { status, components })
+}
+
+async fn probe(
+    critical: bool,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> ComponentHealth {
+    let started_at = Instant::now();
+    let error = match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{e:#}")),
+        Err(_) => Some(format!("Timed out after {} ms.", PROBE_TIMEOUT.as_millis())),
+    };
+    let status = match (&error, critical) {
+        (None, _) => Status::Ok,
+        (Some(_), true) => Status::Failing,
+        (Some(_), false) => Status::Degraded,
+    };
+    if let Some(error) = &error {
+        tracing::warn!(error, critical, "A dependency failed its readiness probe");
+    }
+    ComponentHealth {
+        status,
+        critical,
+        latency_ms: started_at.elapsed().as_millis(),
+        error,
+    }
+}
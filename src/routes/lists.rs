@@ -0,0 +1,81 @@
+//! The newsletters run from this deployment, called lists to tell them apart from
+//! the issues sent to them. Subscribers sign up to one list and get its issues only.
+use crate::routes::error_chain_fmt;
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// Where subscribers and issues went before there were several lists,
+/// and where they still go when no list is named. It can't be deleted.
+pub const DEFAULT_LIST_SLUG: &str = "default";
+
+/// As an extractor, the list is taken from a `{newsletter_slug}` path segment,
+/// or else is the default list.
+#[derive(Debug, Clone)]
+pub struct Newsletter {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    // Whether its issues go out with tracked links and an open pixel.
+    pub tracking_enabled: bool,
+}
+
+impl FromRequest for Newsletter {
+    type Error = NewsletterError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let slug = req
+            .match_info()
+            .get("newsletter_slug")
+            .unwrap_or(DEFAULT_LIST_SLUG)
+            .to_owned();
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        Box::pin(async move {
+            let pool = pool.context("The database pool is not registered as app data.")?;
+            get_newsletter(&pool, &slug)
+                .await
+                .context("Failed to fetch the newsletter.")?
+                .ok_or(NewsletterError::NotFound(slug))
+        })
+    }
+}
+
+#[tracing::instrument(name = "Get a newsletter", skip(pool))]
+pub async fn get_newsletter(pool: &PgPool, slug: &str) -> Result<Option<Newsletter>, sqlx::Error> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"SELECT id, slug, name, tracking_enabled FROM newsletters WHERE slug = $1"#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[derive(thiserror::Error)]
+pub enum NewsletterError {
+    #[error("There is no newsletter named `{0}`.")]
+    NotFound(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for NewsletterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for NewsletterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            NewsletterError::NotFound(_) => StatusCode::NOT_FOUND,
+            NewsletterError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
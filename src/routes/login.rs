@@ -0,0 +1,135 @@
+use crate::admin_notifications::notify_of_login;
+use crate::authentication::{AuthError, Credentials, validate_credentials};
+use crate::email_client::EmailClient;
+use crate::merge_fields::escape;
+use crate::rate_limit::reject_over_limit;
+use crate::routes::error_chain_fmt;
+use crate::session::TypedSession;
+use crate::startup::{AdminNotifications, LoginRateLimiter};
+use crate::templates::EmailTemplates;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::middleware::Next;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use secrecy::SecretString;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct LoginData {
+    username: String,
+    password: SecretString,
+}
+
+pub async fn login_form(session: TypedSession) -> Result<HttpResponse, LoginError> {
+    let flash = session
+        .take_flash()
+        .context("Failed to read the session.")?
+        .map(|message| format!("<p><i>{}</i></p>", escape(&message)))
+        .unwrap_or_default();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>Login · zero2prod</title></head><body>\
+            {flash}\
+            <form action=\"/login\" method=\"post\">\
+            <label>Username <input type=\"text\" name=\"username\"></label>\
+            <label>Password <input type=\"password\" name=\"password\"></label>\
+            <button type=\"submit\">Login</button>\
+            </form>\
+            <p><a href=\"/password_reset\">Forgot your password?</a></p>\
+            </body></html>"
+        )))
+}
+
+/// Runs before the credentials are checked, so that guesses over the limit cost no hashing.
+pub async fn limit_login_attempts(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let rate_limiter = req.app_data::<web::Data<LoginRateLimiter>>().cloned();
+    if let Some(rate_limiter) = rate_limiter
+        && let Some(response) = reject_over_limit(&rate_limiter.0, &req).await
+    {
+        tracing::warn!("Rejected a login attempt over the rate limit");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[tracing::instrument(
+    name = "Log in",
+    skip_all,
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    request: HttpRequest,
+    form: web::Form<LoginData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    admin_notifications: web::Data<AdminNotifications>,
+) -> Result<HttpResponse, LoginError> {
+    let LoginData { username, password } = form.into_inner();
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    let credentials = Credentials { username, password };
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .context("Failed to store the user id in the session.")?;
+            notify_of_login(
+                &pool,
+                &email_client,
+                &templates,
+                &admin_notifications,
+                user_id,
+                &request,
+            )
+            .await;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected a login attempt");
+            session
+                .insert_flash("Authentication failed.")
+                .context("Failed to store a message in the session.")?;
+            Ok(see_other("/login"))
+        }
+        Err(AuthError::UnexpectedError(e)) => Err(e.into()),
+    }
+}
+
+/// A `303 See Other`, so that the browser follows it with a GET.
+pub(crate) fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::metrics::render_metrics;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use sqlx::PgPool;
+
+/// For Prometheus to scrape.
+pub async fn metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, MetricsError> {
+    let body = render_metrics(&pool).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
+
+#[derive(thiserror::Error)]
+pub enum MetricsError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MetricsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for MetricsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MetricsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
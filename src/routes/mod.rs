@@ -1,9 +1,51 @@
+pub mod admin;
+pub mod archive;
+pub mod category_preferences;
+pub mod change_email;
+pub mod email_webhooks;
+pub mod fallback;
 pub mod health_check;
+pub mod lists;
+pub mod login;
+pub mod metrics;
 pub mod newsletter;
+pub mod newsletter_issues;
+pub mod newsletter_preview;
+pub mod password_reset;
+pub mod push;
+pub mod sms;
+pub mod sponsors;
+pub mod stay_subscribed;
+pub mod subscriber_data;
+pub mod subscriber_token;
 pub mod subscriptions;
 pub mod subscriptions_confirm;
+pub mod subscriptions_resend;
+pub mod tracking;
+pub mod unsubscribe;
 
+pub use admin::*;
+pub use archive::*;
+pub use category_preferences::*;
+pub use change_email::*;
+pub use email_webhooks::*;
+pub use fallback::*;
 pub use health_check::*;
+pub use lists::*;
+pub use login::*;
+pub use metrics::*;
 pub use newsletter::*;
+pub use newsletter_issues::*;
+pub use newsletter_preview::*;
+pub use password_reset::*;
+pub use push::*;
+pub use sms::*;
+pub use sponsors::*;
+pub use stay_subscribed::*;
+pub use subscriber_data::*;
+pub use subscriber_token::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_resend::*;
+pub use tracking::*;
+pub use unsubscribe::*;
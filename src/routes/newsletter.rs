@@ -1,49 +1,1053 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
-use actix_web::ResponseError;
+use crate::admin_notifications::{AdminNotification, notify};
+use crate::api_error::ApiError;
+use crate::authentication::{
+    AuthError, AuthenticatedUser, Credentials, get_role, validate_credentials,
+};
+use crate::branding::{apply_branding, load_branding};
+use crate::configuration::FrequencyCapSettings;
+use crate::content_checks::{ContentChecker, ContentReview, ContentWarning, SentIssue, sanitize};
+use crate::crypto::KeyRing;
+use crate::database::ReadPool;
+use crate::domain::{ActionBaseUrl, PhoneNumber, Segment, SubscriberEmail, TagName};
+use crate::email_client::{
+    EmailClient, EmailRecorder, EmailSender, MessageCategory, RecordedEmail,
+};
+use crate::issue_delivery::enqueue_deliveries;
+use crate::merge_fields::{MergeFieldError, Recipient, render_merge_fields};
+use crate::metrics::PendingDeliveries;
+use crate::pii::PiiCipher;
+use crate::routes::{
+    DEFAULT_LIST_SLUG, Newsletter, error_chain_fmt, get_newsletter, get_or_create_preference_token,
+    get_or_create_unsubscribe_token,
+};
+use crate::send_budget;
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use crate::sms_client::{SmsClient, get_sms_recipients};
+use crate::snippets::{load_snippets, resolve_snippets};
+use crate::sponsors::{
+    ActiveSponsorSlot, get_active_sponsor_slots, inject_sponsor_blocks, record_impressions,
+};
+use crate::startup::{AdminNotifications, ApplicationBaseUrl, FrequencyCap};
+use crate::templates::{EmailBody, EmailTemplates, NewsletterEmail};
+use crate::tracking::{IssueTracking, store_links};
+use crate::web_push::{
+    PushNotification, WebPushClient, WebPushError, delete_push_subscription, get_push_subscriptions,
+};
+use actix_web::http::header::HeaderMap;
+use actix_web::{HttpMessage, HttpRequest, ResponseError};
 use actix_web::{HttpResponse, http::StatusCode, web};
 use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, stream};
+use secrecy::SecretString;
 use sqlx::PgPool;
+use sqlx::types::Json;
+use std::time::Instant;
+use tracing::Instrument;
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
-    title: String,
-    content: Content,
+    pub(crate) title: String,
+    pub(crate) content: Content,
+    #[serde(flatten)]
+    pub(crate) targeting: Targeting,
+    // Also text a short announcement to subscribers who opted in to SMS.
+    #[serde(default)]
+    pub(crate) also_sms: bool,
+    // Go through the motions without storing or sending anything.
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    // Leave the issue out of the public feed and archive of its list.
+    #[serde(default)]
+    pub(crate) private: bool,
+    // How many batches the `issue_delivery` job sends of its queued deliveries in a turn,
+    // next to one for every other issue being delivered at priority 1.
+    #[serde(default = "default_priority")]
+    pub(crate) priority: i16,
+}
+
+const PRIORITIES: std::ops::RangeInclusive<i16> = 1..=10;
+// How many of the latest issues new ones are compared with, for content they repeat.
+const SIMILARITY_LOOKBACK: i64 = 200;
+
+pub(crate) fn default_priority() -> i16 {
+    *PRIORITIES.start()
+}
+
+/// Narrows an issue down from every confirmed subscriber of its list.
+#[derive(serde::Deserialize, Default)]
+pub struct Targeting {
+    // The slug of the list the issue goes to; the default list if there is none.
+    #[serde(default)]
+    pub(crate) newsletter: Option<String>,
+    #[serde(default)]
+    exclude: Exclusions,
+    // Only subscribers who haven't opted out of this category get the issue.
+    #[serde(default)]
+    category: Option<String>,
+    // Only subscribers with these tags get the issue.
+    #[serde(default)]
+    segment: Option<SegmentData>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SegmentData {
+    tags: Vec<String>,
+    // Whether subscribers need `any` (the default) or `all` of the tags.
+    #[serde(default, rename = "match")]
+    match_: SegmentMatch,
+}
+
+#[derive(serde::Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentMatch {
+    #[default]
+    Any,
+    All,
+}
+
+/// Subscribers to leave out of this issue, e.g. those who already got a similar announcement.
+#[derive(serde::Deserialize, Default)]
+pub struct Exclusions {
+    #[serde(default)]
+    emails: Vec<String>,
+    // Subscribers with any of these tags are left out.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// The exclusions an issue was published with, as they are kept with it: emails are stored
+/// the way subscribers' are, encrypted when PII encryption is on.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub(crate) struct StoredExclusions {
+    pub emails: Vec<String>,
+    pub tags: Vec<String>,
 }
 
 #[derive(serde::Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub(crate) html: String,
+    // Generated from the HTML when missing or blank.
+    #[serde(default)]
+    pub(crate) text: String,
 }
 
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "Publish a newsletter issue", skip_all)]
 pub async fn publish_newsletter(
+    user: web::ReqData<AuthenticatedUser>,
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
+    read_pool: web::Data<ReadPool>,
     email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    web_push: web::Data<Option<WebPushClient>>,
+    sms_client: web::Data<Option<SmsClient>>,
+    frequency_cap: web::Data<FrequencyCap>,
+    content_checker: web::Data<ContentChecker>,
+    admin_notifications: web::Data<AdminNotifications>,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
+    if body.dry_run {
+        let summary = dry_run_issue(
+            &body,
+            &pool,
+            &read_pool,
+            &templates,
+            &base_url,
+            &key_ring,
+            &token_signer,
+            &pii_cipher,
+            &action_base_url,
+            sms_client.as_ref().as_ref(),
+            frequency_cap.0.as_ref(),
+            &content_checker,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().json(summary));
+    }
+    let summary = publish_issue(
+        &body,
+        user.user_id,
+        &pool,
+        &read_pool,
+        &email_client,
+        &templates,
+        &base_url,
+        &key_ring,
+        &token_signer,
+        &pii_cipher,
+        &action_base_url,
+        web_push.as_ref().as_ref(),
+        sms_client.as_ref().as_ref(),
+        frequency_cap.0.as_ref(),
+        &content_checker,
+        &admin_notifications,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// The publisher a request was made by, from its `Authorization: Basic` header, along with
+/// their role. Records who they are on the current span, and puts them in the request's
+/// extensions.
+pub(crate) async fn authenticate_publisher(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let role = get_role(user_id, pool)
+        .await?
+        .context("The user was deleted while being authenticated.")?;
+    let user = AuthenticatedUser { user_id, role };
+    request.extensions_mut().insert(user);
+    Ok(user)
+}
+
+/// Renders the issue and sends it out, to email and to the other channels that are enabled.
+/// The issue is kept, along with who it was delivered to, for `GET /newsletters`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn publish_issue(
+    body: &BodyData,
+    author_id: Uuid,
+    pool: &PgPool,
+    read_pool: &ReadPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    base_url: &ApplicationBaseUrl,
+    key_ring: &KeyRing,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    web_push: Option<&WebPushClient>,
+    sms_client: Option<&SmsClient>,
+    frequency_cap: Option<&FrequencyCapSettings>,
+    content_checker: &ContentChecker,
+    admin_notifications: &AdminNotifications,
+) -> Result<PublishSummary, PublishError> {
+    check_priority(body)?;
+    let sms_client = sms_client_for(body, sms_client)?;
+    let issue = prepare_issue(pool, &body.content, &base_url.0, key_ring).await?;
+    let review = issue.review(&body.title, pool, content_checker).await?;
+    let audience =
+        resolve_audience(pool, read_pool, pii_cipher, &body.targeting, frequency_cap).await?;
+    let newsletter_issue_id = insert_newsletter_issue(pool, &audience, body, &issue, author_id)
+        .await
+        .context("Failed to store the newsletter issue.")?;
+    let tracking = audience
+        .newsletter
+        .tracking_enabled
+        .then(|| IssueTracking::new(&issue.html, &base_url.0, key_ring, newsletter_issue_id));
+    if let Some(tracking) = &tracking {
+        store_links(pool, tracking)
+            .await
+            .context("Failed to store the links of the newsletter issue.")?;
+    }
+    record_frequency_cap_skips(pool, newsletter_issue_id, &audience.over_frequency_cap)
+        .await
+        .context("Failed to record the subscribers skipped by the frequency cap.")?;
+    let mut summary = PublishSummary {
+        newsletter_issue_id,
+        delivered: 0,
+        queued: 0,
+        failed: 0,
+        skipped_over_frequency_cap: audience.over_frequency_cap.len() as i64,
+        sms_delivered: 0,
+        review,
+    };
+
+    let delivery = IssueDelivery {
+        records: pool,
+        email_sender: email_client,
+        templates,
+        token_signer,
+        action_base_url,
+        newsletter: &audience.newsletter.name,
+        issue: &issue,
+        tracking: tracking.as_ref(),
+        title: &body.title,
+        newsletter_issue_id,
+    };
+    let settings = email_client.delivery();
+    let mut pending_deliveries = PendingDeliveries::new(audience.recipients.len());
+    let mut remaining = audience.recipients.as_slice();
+    // Failed sends don't hold up the others: they are queued for a retry.
+    let mut failed = Vec::new();
+    let mut batch = 0;
+    while !remaining.is_empty() {
+        let mut batch_size = settings.batch_size.max(1).min(remaining.len());
+        if let Some(budget) = &settings.send_budget {
+            batch_size = send_budget::take(pool, budget, batch_size)
+                .await
+                .context("Failed to take from the send budget.")?;
+            if batch_size == 0 {
+                break;
+            }
+        }
+        let (recipients, rest) = remaining.split_at(batch_size);
+        let span = tracing::info_span!(
+            "Deliver a batch of an issue",
+            batch,
+            recipients = recipients.len(),
+            delivered = tracing::field::Empty,
+            elapsed_milliseconds = tracing::field::Empty,
+        );
+        let started_at = Instant::now();
+        let outcomes: Vec<_> = stream::iter(recipients)
+            .map(|subscriber| async { (subscriber.id, delivery.send_to(subscriber).await) })
+            .buffer_unordered(settings.concurrency.max(1))
+            .collect()
+            .instrument(span.clone())
+            .await;
+        for (subscriber_id, outcome) in outcomes {
+            pending_deliveries.attempted();
+            match outcome {
+                Ok(()) => summary.delivered += 1,
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        %subscriber_id,
+                        "Failed to deliver a newsletter issue, queueing it for a retry",
+                    );
+                    failed.push(subscriber_id);
+                }
+            }
+        }
+        span.record("delivered", summary.delivered);
+        span.record(
+            "elapsed_milliseconds",
+            started_at.elapsed().as_millis() as u64,
+        );
+        remaining = rest;
+        batch += 1;
+    }
+    // Over the send budget, or failed: the `issue_delivery` job sends to them as it refills.
+    summary.failed = failed.len() as i64;
+    let subscriber_ids: Vec<Uuid> = failed
+        .into_iter()
+        .chain(remaining.iter().map(|subscriber| subscriber.id))
+        .collect();
+    if !subscriber_ids.is_empty() {
+        enqueue_deliveries(pool, newsletter_issue_id, &subscriber_ids)
+            .await
+            .context("Failed to queue the rest of the deliveries of the newsletter issue.")?;
+        summary.queued = subscriber_ids.len() as i64;
+    }
+
+    record_impressions(pool, &issue.sponsor_slot_ids(), summary.delivered)
+        .await
+        .context("Failed to record sponsor impressions.")?;
+
+    // Private issues aren't in the archive: their announcements open the site itself.
+    let issue_url = if body.private {
+        base_url.0.clone()
+    } else {
+        format!("{}/archive/{}", base_url.0, newsletter_issue_id)
+    };
+    if let Some(web_push) = web_push {
+        let notification = PushNotification {
+            title: &body.title,
+            url: &issue_url,
+        };
+        notify_push_subscribers(
+            pool,
+            web_push,
+            &notification,
+            audience.newsletter.id,
+            audience.category,
+            audience.segment.as_ref(),
+        )
+        .await?;
+    }
+
+    if let Some(sms_client) = sms_client {
+        summary.sms_delivered = notify_sms_subscribers(
+            pool,
+            sms_client,
+            &body.title,
+            &issue_url,
+            audience.newsletter.id,
+            audience.category,
+            audience.segment.as_ref(),
+        )
+        .await?;
+    }
+
+    notify_publish(
+        pool,
+        email_client,
+        templates,
+        admin_notifications,
+        author_id,
+        &body.title,
+        &summary,
+    )
+    .await;
+    Ok(summary)
+}
+
+/// Tells the author how the publish went, and the admins too when more of its sends failed
+/// than `admin_notifications.delivery_failure_threshold` allows.
+async fn notify_publish(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    admin_notifications: &AdminNotifications,
+    author_id: Uuid,
+    title: &str,
+    summary: &PublishSummary,
+) {
+    let Some(settings) = &admin_notifications.0 else {
+        return;
+    };
+    let completed = AdminNotification::PublishCompleted {
+        title,
+        delivered: summary.delivered,
+        queued: summary.queued,
+        failed: summary.failed,
+        skipped_over_frequency_cap: summary.skipped_over_frequency_cap,
+    };
+    notify(
+        pool,
+        email_client,
+        templates,
+        admin_notifications,
+        author_id,
+        completed,
+    )
+    .await;
+    let attempted = summary.delivered + summary.failed;
+    if attempted > 0
+        && summary.failed as f64 / attempted as f64 > settings.delivery_failure_threshold
+    {
+        let failures = AdminNotification::DeliveryFailures {
+            title,
+            failed: summary.failed,
+            attempted,
+            threshold_percent: settings.delivery_failure_threshold * 100.0,
+        };
+        notify(
+            pool,
+            email_client,
+            templates,
+            admin_notifications,
+            author_id,
+            failures,
+        )
+        .await;
+    }
+}
+
+/// Goes through publishing up to the sends: the issue is rendered and checked, its audience
+/// resolved, and every recipient's email rendered, into an `EmailRecorder`. Nothing is stored
+/// and the send budget is left alone. The sample is the email of the first recipient, without
+/// open and click tracking and with an unsubscribe link that doesn't work.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "Dry-run a newsletter issue", skip_all)]
+pub(crate) async fn dry_run_issue(
+    body: &BodyData,
+    pool: &PgPool,
+    read_pool: &ReadPool,
+    templates: &EmailTemplates,
+    base_url: &ApplicationBaseUrl,
+    key_ring: &KeyRing,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    sms_client: Option<&SmsClient>,
+    frequency_cap: Option<&FrequencyCapSettings>,
+    content_checker: &ContentChecker,
+) -> Result<DryRunSummary, PublishError> {
+    check_priority(body)?;
+    let sms_client = sms_client_for(body, sms_client)?;
+    let issue = prepare_issue(pool, &body.content, &base_url.0, key_ring).await?;
+    let review = issue.review(&body.title, pool, content_checker).await?;
+    let audience =
+        resolve_audience(pool, read_pool, pii_cipher, &body.targeting, frequency_cap).await?;
+
+    // Sent the way publishing sends it, to a recorder, and with nothing written down.
+    let recorder = EmailRecorder::default();
+    let delivery = IssueDelivery {
+        records: &NoDeliveryRecords,
+        email_sender: &recorder,
+        templates,
+        token_signer,
+        action_base_url,
+        newsletter: &audience.newsletter.name,
+        issue: &issue,
+        // Tracked links need an issue to count clicks against.
+        tracking: None,
+        title: &body.title,
+        newsletter_issue_id: Uuid::nil(),
+    };
+    for subscriber in &audience.recipients {
+        delivery.send_to(subscriber).await?;
+    }
+
+    let sms_recipients = match sms_client {
+        Some(_) => get_sms_recipients(
+            pool,
+            audience.newsletter.id,
+            audience.category,
+            audience.segment.as_ref(),
+        )
+        .await
+        .context("Failed to fetch SMS recipients.")?
+        .len(),
+        None => 0,
+    };
+    Ok(DryRunSummary {
+        dry_run: true,
+        recipients: recorder.count(),
+        skipped_over_frequency_cap: audience.over_frequency_cap.len() as i64,
+        sms_recipients,
+        sample: recorder.first(),
+        review,
+    })
+}
 
+fn check_priority(body: &BodyData) -> Result<(), PublishError> {
+    if PRIORITIES.contains(&body.priority) {
+        Ok(())
+    } else {
+        Err(PublishError::ValidationError(format!(
+            "The priority must be between {} and {}.",
+            PRIORITIES.start(),
+            PRIORITIES.end()
+        )))
+    }
+}
+
+/// The SMS client to announce the issue with, if the publisher asked for it.
+fn sms_client_for<'a>(
+    body: &BodyData,
+    sms_client: Option<&'a SmsClient>,
+) -> Result<Option<&'a SmsClient>, PublishError> {
+    match (body.also_sms, sms_client) {
+        (false, _) => Ok(None),
+        (true, Some(sms_client)) => Ok(Some(sms_client)),
+        (true, None) => Err(PublishError::ValidationError(
+            "SMS announcements are not enabled.".into(),
+        )),
+    }
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct DryRunSummary {
+    dry_run: bool,
+    // Who would get the issue: over the send budget or not.
+    recipients: usize,
+    skipped_over_frequency_cap: i64,
+    sms_recipients: usize,
+    // None if there are no recipients.
+    sample: Option<RecordedEmail>,
+    #[serde(flatten)]
+    review: ContentReview,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct PublishSummary {
+    newsletter_issue_id: Uuid,
+    pub delivered: i64,
+    // Recipients over the send budget or whose send failed, left for the `issue_delivery` job.
+    pub queued: i64,
+    // Of those, the ones whose send failed.
+    pub failed: i64,
+    skipped_over_frequency_cap: i64,
+    sms_delivered: i64,
+    // What the issue was sent with regardless, such as broken links.
+    #[serde(flatten)]
+    pub review: ContentReview,
+}
+
+/// Sends an issue to its recipients, one `send_to` per recipient.
+pub(crate) struct IssueDelivery<'a> {
+    pub records: &'a dyn DeliveryRecords,
+    pub email_sender: &'a dyn EmailSender,
+    pub templates: &'a EmailTemplates,
+    pub token_signer: &'a TokenSigner,
+    pub action_base_url: &'a ActionBaseUrl,
+    // The name of the list, for the footer.
+    pub newsletter: &'a str,
+    pub issue: &'a RenderedIssue,
+    // Set if the list tracks opens and clicks.
+    pub tracking: Option<&'a IssueTracking<'a>>,
+    pub title: &'a str,
+    pub newsletter_issue_id: Uuid,
+}
+
+impl IssueDelivery<'_> {
+    /// Records the attempt, whether it went through or not.
+    pub async fn send_to(&self, subscriber: &ConfirmedSubscriber) -> Result<(), PublishError> {
+        let unsubscribe_token = self
+            .records
+            .unsubscribe_token(subscriber.id)
+            .await
+            .context("Failed to get an unsubscribe token.")?;
+        let unsubscribe_link =
+            unsubscribe_link(self.action_base_url, self.token_signer, &unsubscribe_token);
+        let preference_token = self
+            .records
+            .preference_token(subscriber.id)
+            .await
+            .context("Failed to get a preference token.")?;
+        let preferences_link =
+            preferences_link(self.action_base_url, self.token_signer, &preference_token);
+        let email = self.issue.email(
+            self.templates,
+            self.newsletter,
+            &Recipient {
+                name: &subscriber.name,
+                email: subscriber.email.as_ref(),
+            },
+            &FooterLinks {
+                unsubscribe: &unsubscribe_link,
+                preferences: &preferences_link,
+            },
+            self.tracking.map(|tracking| (tracking, subscriber.id)),
+        )?;
+        let sent = self
+            .email_sender
+            .send_email_with_id(
+                &subscriber.email,
+                self.title,
+                &email.html,
+                &email.text,
+                MessageCategory::Broadcast,
+            )
+            .await;
+        if let Err(error) = &sent {
+            self.records
+                .record_failure(self.newsletter_issue_id, subscriber.id, &error.to_string())
+                .await
+                .context("Failed to record a failed newsletter delivery.")?;
+        }
+        // diff bw context and with_context - with_context is lazy
+        // If the context you are adding is static - e.g. context("Oh no!") - they are equivalent.
+        // If the context you are adding has a runtime cost, use with_context - you avoid paying for the error
+        // path when the fallible operation succeeds - Using with_context, we only invoke format! if email delivery fails.
+        let message_id = sent
+            .with_context(|| format!("Failed to send newsletter issue to {}", subscriber.email))?;
+        self.records
+            .record_sent(
+                self.newsletter_issue_id,
+                subscriber.id,
+                message_id.as_deref(),
+            )
+            .await
+            .context("Failed to record a newsletter delivery.")?;
+        Ok(())
+    }
+}
+
+/// The link of an issue that unsubscribes whoever `unsubscribe_token` is of.
+pub(crate) fn unsubscribe_link(
+    action_base_url: &ActionBaseUrl,
+    token_signer: &TokenSigner,
+    unsubscribe_token: &str,
+) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        action_base_url.as_ref(),
+        token_signer.sign(TokenPurpose::Unsubscribe, unsubscribe_token)
+    )
+}
+
+/// The link of an issue to the preferences of whoever `preference_token` is of.
+pub(crate) fn preferences_link(
+    action_base_url: &ActionBaseUrl,
+    token_signer: &TokenSigner,
+    preference_token: &str,
+) -> String {
+    format!(
+        "{}/subscriptions/categories?token={}",
+        action_base_url.as_ref(),
+        token_signer.sign(TokenPurpose::Preferences, preference_token)
+    )
+}
+
+/// Where the deliveries of an issue are recorded: the database, or nowhere when nothing
+/// is actually delivered.
+#[async_trait::async_trait]
+pub(crate) trait DeliveryRecords: Send + Sync {
+    /// The token of the subscriber's unsubscribe link.
+    async fn unsubscribe_token(&self, subscriber_id: Uuid) -> Result<String, sqlx::Error>;
+
+    /// The token of the subscriber's preferences link.
+    async fn preference_token(&self, subscriber_id: Uuid) -> Result<String, sqlx::Error>;
+
+    async fn record_failure(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        error: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Counts towards the frequency cap, and keeps the provider's id for the message.
+    async fn record_sent(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        provider_message_id: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait::async_trait]
+impl DeliveryRecords for PgPool {
+    async fn unsubscribe_token(&self, subscriber_id: Uuid) -> Result<String, sqlx::Error> {
+        get_or_create_unsubscribe_token(self, subscriber_id).await
+    }
+
+    async fn preference_token(&self, subscriber_id: Uuid) -> Result<String, sqlx::Error> {
+        get_or_create_preference_token(self, subscriber_id).await
+    }
+
+    async fn record_failure(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        record_delivery_attempt(
+            self,
+            newsletter_issue_id,
+            subscriber_id,
+            "failed",
+            Some(error),
+            None,
+        )
+        .await
+    }
+
+    async fn record_sent(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        provider_message_id: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        record_delivery(self, subscriber_id, Some(newsletter_issue_id)).await?;
+        record_delivery_attempt(
+            self,
+            newsletter_issue_id,
+            subscriber_id,
+            "sent",
+            None,
+            provider_message_id,
+        )
+        .await
+    }
+}
+
+/// The token of the links of emails that weren't sent to anyone: they lead nowhere.
+pub(crate) const PREVIEW_TOKEN: &str = "preview";
+
+/// Records nothing, and hands out `PREVIEW_TOKEN` as every unsubscribe and preference token:
+/// for dry runs and benchmarks, which deliver nothing for real.
+pub(crate) struct NoDeliveryRecords;
+
+#[async_trait::async_trait]
+impl DeliveryRecords for NoDeliveryRecords {
+    async fn unsubscribe_token(&self, _subscriber_id: Uuid) -> Result<String, sqlx::Error> {
+        Ok(PREVIEW_TOKEN.into())
+    }
+
+    async fn preference_token(&self, _subscriber_id: Uuid) -> Result<String, sqlx::Error> {
+        Ok(PREVIEW_TOKEN.into())
+    }
+
+    async fn record_failure(&self, _: Uuid, _: Uuid, _: &str) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn record_sent(&self, _: Uuid, _: Uuid, _: Option<&str>) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+}
+
+/// The links at the bottom of an issue, for the subscriber it is sent to.
+pub(crate) struct FooterLinks<'a> {
+    pub unsubscribe: &'a str,
+    pub preferences: &'a str,
+}
+
+/// An issue with its snippets expanded, its HTML sanitized and sponsor blocks injected,
+/// ready to be personalised for each recipient.
+pub(crate) struct RenderedIssue {
+    pub html: String,
+    pub text: String,
+    pub sponsor_slots: Vec<ActiveSponsorSlot>,
+    // Set when the issue must not be published as it is, e.g. without a postal address.
+    pub compliance_error: Option<String>,
+    // The links of the content, to be resolved, and what sanitizing it found.
+    pub links: Vec<String>,
+    pub warnings: Vec<ContentWarning>,
+}
+
+impl RenderedIssue {
+    pub fn sponsor_slot_ids(&self) -> Vec<Uuid> {
+        self.sponsor_slots.iter().map(|slot| slot.id).collect()
+    }
+
+    /// The warnings of the issue titled `title`, with its links resolved, its spam score and
+    /// the latest issues it is much like.
+    pub async fn review(
+        &self,
+        title: &str,
+        pool: &PgPool,
+        content_checker: &ContentChecker,
+    ) -> Result<ContentReview, anyhow::Error> {
+        let sent = latest_issues(pool)
+            .await
+            .context("Failed to fetch the latest issues to compare the new one with.")?;
+        Ok(content_checker
+            .review(title, &self.text, &self.links, &self.warnings, &sent)
+            .await)
+    }
+
+    /// Resolve merge fields for `recipient`, failing on the first field that can't be resolved.
+    pub fn personalise(
+        &self,
+        recipient: &Recipient<'_>,
+    ) -> Result<(String, String), MergeFieldError> {
+        let (html, html_errors) = render_merge_fields(&self.html, recipient, true);
+        let (text, text_errors) = render_merge_fields(&self.text, recipient, false);
+        match html_errors.into_iter().chain(text_errors).next() {
+            Some(error) => Err(error),
+            None => Ok((html, text)),
+        }
+    }
+
+    /// The email `recipient` gets: the issue personalised for them, in the newsletter template.
+    /// `tracking` tracks their opens and clicks, for subscribers of lists that track them.
+    pub fn email(
+        &self,
+        templates: &EmailTemplates,
+        newsletter: &str,
+        recipient: &Recipient<'_>,
+        links: &FooterLinks<'_>,
+        tracking: Option<(&IssueTracking<'_>, Uuid)>,
+    ) -> Result<EmailBody, PublishError> {
+        let (mut html, text) = self
+            .personalise(recipient)
+            .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+        if let Some((tracking, subscriber_id)) = tracking {
+            html = tracking.track(&html, subscriber_id);
+        }
+        let email = templates.newsletter(&NewsletterEmail {
+            newsletter,
+            name: recipient.name,
+            html_content: &html,
+            text_content: &text,
+            unsubscribe_link: links.unsubscribe,
+            preferences_link: links.preferences,
+        })?;
+        Ok(email)
+    }
+}
+
+/// Renders the issue and checks it can be published as it is, before anybody gets it.
+pub(crate) async fn prepare_issue(
+    pool: &PgPool,
+    content: &Content,
+    base_url: &str,
+    key_ring: &KeyRing,
+) -> Result<RenderedIssue, PublishError> {
+    let issue = render_issue(pool, content, base_url, key_ring).await?;
+    if let Some(error) = issue.compliance_error {
+        return Err(PublishError::ValidationError(error));
+    }
+    // Merge fields don't depend on who the recipient is to resolve, only to render:
+    // check them once so that a typo is caught before anybody gets the issue.
+    if let Some(error) = issue.personalise(&Recipient::persona()).err() {
+        return Err(PublishError::ValidationError(error.to_string()));
+    }
+    Ok(issue)
+}
+
+#[tracing::instrument(name = "Render a newsletter issue", skip_all)]
+pub(crate) async fn render_issue(
+    pool: &PgPool,
+    content: &Content,
+    base_url: &str,
+    key_ring: &KeyRing,
+) -> Result<RenderedIssue, PublishError> {
+    // Expand `{{> snippet }}` references once, before fanning out to every subscriber.
+    let snippets = load_snippets(pool)
+        .await
+        .context("Failed to load snippets from the database.")?;
+    let html = resolve_snippets(&content.html, &snippets)
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    let text = resolve_snippets(&content.text, &snippets)
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    // Sponsor blocks and branding are ours: only what the publisher wrote is sanitized.
+    let content = sanitize(&html, &text).context("Failed to sanitize the issue.")?;
+
+    let sponsor_slots = get_active_sponsor_slots(pool, Utc::now())
+        .await
+        .context("Failed to fetch the active sponsor slots.")?;
+    let (html, text) = inject_sponsor_blocks(
+        &content.html,
+        &content.text,
+        &sponsor_slots,
+        base_url,
+        key_ring,
+    );
+    let branding = load_branding(pool)
+        .await
+        .context("Failed to load the branding.")?;
+    let (html, text) = apply_branding(&html, &text, &branding);
+    Ok(RenderedIssue {
+        html,
+        text,
+        sponsor_slots,
+        compliance_error: branding.compliance_error(),
+        links: content.links,
+        warnings: content.warnings,
+    })
+}
+
+#[tracing::instrument(name = "Notify push subscribers", skip_all)]
+async fn notify_push_subscribers(
+    pool: &PgPool,
+    web_push: &WebPushClient,
+    notification: &PushNotification<'_>,
+    newsletter_id: Uuid,
+    category: Option<&str>,
+    segment: Option<&Segment>,
+) -> Result<(), anyhow::Error> {
+    let payload = serde_json::to_vec(notification)?;
+    let subscriptions = get_push_subscriptions(pool, newsletter_id, category, segment)
+        .await
+        .context("Failed to fetch push subscriptions.")?;
+
+    for subscription in subscriptions {
+        match web_push.send(&subscription, &payload).await {
+            Ok(()) => {}
+            Err(WebPushError::SubscriptionGone) => {
+                delete_push_subscription(pool, &subscription.endpoint)
+                    .await
+                    .context("Failed to delete an expired push subscription.")?;
+            }
+            // Emails have already gone out: a flaky push service must not fail the publish.
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Failed to deliver a push notification to {}",
+                    subscription.endpoint
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Who an issue goes to, once exclusions, category opt-outs, the segment
+/// and the frequency cap are applied.
+pub(crate) struct Audience<'a> {
+    pub newsletter: Newsletter,
+    pub recipients: Vec<ConfirmedSubscriber>,
+    // Confirmed subscribers left out for having had too many emails lately.
+    pub over_frequency_cap: Vec<Uuid>,
+    pub category: Option<&'a str>,
+    pub segment: Option<Segment>,
+    pub exclusions: StoredExclusions,
+}
+
+/// The recipients are read from the replica, if there is one: the biggest read of a publish
+/// stays off the primary, at the cost of missing subscribers who confirmed moments ago.
+#[tracing::instrument(name = "Resolve the audience of an issue", skip_all)]
+pub(crate) async fn resolve_audience<'a>(
+    pool: &PgPool,
+    read_pool: &ReadPool,
+    pii_cipher: &PiiCipher,
+    targeting: &'a Targeting,
+    frequency_cap: Option<&FrequencyCapSettings>,
+) -> Result<Audience<'a>, PublishError> {
+    let slug = targeting.newsletter.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            PublishError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let excluded_emails = parse_excluded_emails(&targeting.exclude)?;
+    let excluded_email_indexes: Vec<String> = excluded_emails
+        .iter()
+        .filter_map(|email| pii_cipher.email_index(email))
+        .collect();
+    let excluded_tags = parse_excluded_tags(&targeting.exclude)?;
+    let category = targeting.category.as_deref();
+    if let Some(category) = category
+        && !category_exists(pool, category)
+            .await
+            .context("Failed to fetch the issue category.")?
+    {
+        return Err(PublishError::ValidationError(format!(
+            "There is no category named `{category}`."
+        )));
+    }
+    let segment = targeting
+        .segment
+        .as_ref()
+        .map(parse_segment)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    // Counted from when the issue goes out rather than per recipient: one send, one window.
+    let frequency_cap_start = frequency_cap.map(|cap| Utc::now() - cap.window());
+    let subscribers = get_confirmed_subscribers(
+        read_pool.get().await,
+        pii_cipher,
+        newsletter.id,
+        &excluded_emails,
+        &excluded_email_indexes,
+        &excluded_tags,
+        category,
+        segment.as_ref(),
+        frequency_cap_start,
+    )
+    .await?;
+
+    let mut audience = Audience {
+        newsletter,
+        recipients: Vec::with_capacity(subscribers.len()),
+        over_frequency_cap: Vec::new(),
+        category,
+        segment,
+        exclusions: StoredExclusions {
+            emails: excluded_emails
+                .iter()
+                .map(|email| pii_cipher.encrypt(email))
+                .collect(),
+            tags: excluded_tags,
+        },
+    };
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
+                if let Some(cap) = frequency_cap
+                    && subscriber.recent_deliveries >= cap.max_emails
+                {
+                    tracing::info!(
+                        subscriber_id = %subscriber.id,
+                        recent_deliveries = subscriber.recent_deliveries,
+                        reason = "frequency_cap",
+                        "Skipping a confirmed subscriber",
+                    );
+                    audience.over_frequency_cap.push(subscriber.id);
+                    continue;
+                }
+                audience.recipients.push(subscriber);
             }
-            // diff bw context and with_context - with_context is lazy
-            // If the context you are adding is static - e.g. context("Oh no!") - they are equivalent.
-            // If the context you are adding has a runtime cost, use with_context - you avoid paying for the error
-            // path when the fallible operation succeeds - Using with_context, we only invoke format! if email delivery fails.
             Err(error) => {
                 tracing::warn!(
                 // We record the error chain as a structured field on the log record.
@@ -57,17 +1061,118 @@ pub async fn publish_newsletter(
             }
         }
     }
+    Ok(audience)
+}
+
+fn parse_segment(segment: &SegmentData) -> Result<Segment, String> {
+    let tags = segment
+        .tags
+        .iter()
+        .map(|tag| TagName::parse(tag.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Segment::new(tags, segment.match_ == SegmentMatch::All)
+}
 
-    Ok(HttpResponse::Ok().finish())
+/// Emails are compared case-insensitively, so they are lowercased once here.
+fn parse_excluded_emails(exclusions: &Exclusions) -> Result<Vec<String>, PublishError> {
+    exclusions
+        .emails
+        .iter()
+        .map(|email| {
+            SubscriberEmail::parse(email.trim().to_owned())
+                .map(|email| email.as_ref().to_lowercase())
+                .map_err(|e| {
+                    PublishError::ValidationError(format!("{email} is not a valid exclusion: {e}"))
+                })
+        })
+        .collect()
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+fn parse_excluded_tags(exclusions: &Exclusions) -> Result<Vec<String>, PublishError> {
+    exclusions
+        .tags
+        .iter()
+        .map(|tag| {
+            TagName::parse(tag.clone())
+                .map(|tag| tag.as_ref().to_owned())
+                .map_err(|e| {
+                    PublishError::ValidationError(format!("{tag} is not a valid exclusion: {e}"))
+                })
+        })
+        .collect()
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
+/// Texts are kept short: the title and a link, the issue itself is in the email.
+#[tracing::instrument(name = "Notify SMS subscribers", skip_all)]
+async fn notify_sms_subscribers(
+    pool: &PgPool,
+    sms_client: &SmsClient,
+    title: &str,
+    url: &str,
+    newsletter_id: Uuid,
+    category: Option<&str>,
+    segment: Option<&Segment>,
+) -> Result<i64, anyhow::Error> {
+    let recipients = get_sms_recipients(pool, newsletter_id, category, segment)
+        .await
+        .context("Failed to fetch SMS recipients.")?;
+    let message = format!("{title} {url}");
+    let mut delivered = 0;
+    for recipient in recipients {
+        let recipient = match PhoneNumber::parse(recipient) {
+            Ok(recipient) => recipient,
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Skipping an SMS recipient. Their stored phone number is invalid",
+                );
+                continue;
+            }
+        };
+        // Emails have already gone out: a flaky SMS provider must not fail the publish.
+        match sms_client.send_sms(&recipient, &message).await {
+            Ok(()) => delivered += 1,
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Failed to deliver an SMS announcement",
+                );
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+pub(crate) struct ConfirmedSubscriber {
+    pub id: Uuid,
+    pub email: SubscriberEmail,
+    pub name: String,
+    // Issues they got since `frequency_cap_start`; zero without a frequency cap.
+    pub recent_deliveries: i64,
+}
+
+#[tracing::instrument(
+    name = "Get confirmed subscribers",
+    skip(
+        pool,
+        pii_cipher,
+        excluded_emails,
+        excluded_email_indexes,
+        excluded_tags
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    newsletter_id: Uuid,
+    excluded_emails: &[String],
+    // The blind indexes of `excluded_emails`, to match encrypted emails by.
+    excluded_email_indexes: &[String],
+    excluded_tags: &[String],
+    category: Option<&str>,
+    segment: Option<&Segment>,
+    frequency_cap_start: Option<DateTime<Utc>>,
     // We are returning a `Vec` of `Result`s in the happy case.
     // This allows the caller to bubble up errors due to network issues or other
     // transient failures using the `?` operator, while the compiler
@@ -96,25 +1201,224 @@ async fn get_confirmed_subscribers(
 
     let confirmed_subscribers = sqlx::query!(
         r#"
-        SELECT email
+        SELECT id, email, name, (
+            SELECT COUNT(*) FROM broadcast_deliveries
+            WHERE subscriber_id = subscriptions.id AND sent_at > $2
+        ) AS "recent_deliveries!"
         FROM subscriptions
         WHERE status = 'confirmed'
+          AND deleted_at IS NULL
+          AND newsletter_id = $7
+          AND lower(email) <> ALL($1)
+          AND (email_blind_index IS NULL OR email_blind_index <> ALL($4))
+          AND NOT EXISTS (
+            SELECT 1 FROM category_opt_outs
+            WHERE subscriber_id = subscriptions.id AND category = $3
+          )
+          AND ($5::text[] IS NULL OR (
+            SELECT COUNT(*) FROM subscriber_tags
+            WHERE subscriber_id = subscriptions.id AND tag = ANY($5)
+          ) >= $6)
+          AND NOT EXISTS (
+            SELECT 1 FROM subscriber_tags
+            WHERE subscriber_id = subscriptions.id AND tag = ANY($8)
+          )
         "#,
+        excluded_emails,
+        frequency_cap_start,
+        category,
+        excluded_email_indexes,
+        segment.map(Segment::tags) as Option<Vec<String>>,
+        segment.map_or(0, Segment::required_matches),
+        newsletter_id,
+        excluded_tags
     )
     .fetch_all(pool)
     .await?
     .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
+    .map(|r| {
+        let email = SubscriberEmail::parse(pii_cipher.decrypt(&r.email)?)
+            .map_err(|error| anyhow::anyhow!(error))?;
+        Ok(ConfirmedSubscriber {
+            id: r.id,
+            email,
+            name: pii_cipher.decrypt(&r.name)?,
+            recent_deliveries: r.recent_deliveries,
+        })
     })
     .collect();
 
     Ok(confirmed_subscribers)
 }
 
+async fn category_exists(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM issue_categories WHERE name = $1) AS "exists!""#,
+        name
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(exists)
+}
+
+async fn latest_issues(pool: &PgPool) -> Result<Vec<SentIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        SentIssue,
+        r#"
+        SELECT newsletter_issue_id, title, text_content AS text
+        FROM newsletter_issues
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        SIMILARITY_LOOKBACK
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Keeps the exclusions of `audience` with the issue, to tell later who was left out of it.
+#[tracing::instrument(name = "Store a newsletter issue", skip(pool, audience, body, issue))]
+async fn insert_newsletter_issue(
+    pool: &PgPool,
+    audience: &Audience<'_>,
+    body: &BodyData,
+    issue: &RenderedIssue,
+    author_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter = &audience.newsletter;
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, html_content, text_content, published_at, author_id,
+             newsletter_id, tracked, sponsor_slot_ids, private, delivery_priority, exclusions)
+        VALUES ($1, $2, $3, $4, now(), $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        newsletter_issue_id,
+        body.title,
+        issue.html,
+        issue.text,
+        author_id,
+        newsletter.id,
+        newsletter.tracking_enabled,
+        &issue.sponsor_slot_ids(),
+        body.private,
+        body.priority,
+        Json(&audience.exclusions) as _
+    )
+    .execute(pool)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Every issue a subscriber gets counts towards the frequency cap,
+/// so that turning the cap on takes past sends into account.
+pub(crate) async fn record_delivery(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO broadcast_deliveries (subscriber_id, sent_at, newsletter_issue_id)
+        VALUES ($1, now(), $2)
+        "#,
+        subscriber_id,
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Subscribers the frequency cap left out are recorded as `skipped_frequency_cap` attempts.
+#[tracing::instrument(name = "Record frequency cap skips", skip(pool, subscriber_ids))]
+async fn record_frequency_cap_skips(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_delivery_attempts
+            (newsletter_issue_id, subscriber_id, status, attempted_at)
+        SELECT $1, subscriber_id, 'skipped_frequency_cap', now()
+        FROM UNNEST($2::uuid[]) AS subscriber_id
+        "#,
+        newsletter_issue_id,
+        subscriber_ids
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// `status` is `sent` or `failed`; the provider's webhooks later move a `sent` attempt to
+/// `delivered` or `bounced`, and `skipped_frequency_cap` is recorded by
+/// `record_frequency_cap_skips`. Only the latest attempt is kept: a retried delivery replaces
+/// the one that failed. Sent ones keep the provider's id for the message, for its webhooks
+/// to be matched with.
+#[tracing::instrument(name = "Record a delivery attempt", skip(pool, error))]
+async fn record_delivery_attempt(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    status: &str,
+    error: Option<&str>,
+    provider_message_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_delivery_attempts
+            (newsletter_issue_id, subscriber_id, status, error, provider_message_id, attempted_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE SET
+            status = EXCLUDED.status,
+            error = EXCLUDED.error,
+            provider_message_id = EXCLUDED.provider_message_id,
+            attempted_at = EXCLUDED.attempted_at
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        status,
+        error,
+        provider_message_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The credentials of an `Authorization: Basic` header.
+pub(crate) fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // The password may contain colons, the username may not.
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: SecretString::from(password),
+    })
+}
+
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -128,7 +1432,16 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            PublishError::ValidationError(e) => ApiError::validation(e),
+            PublishError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
 }
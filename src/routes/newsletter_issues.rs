@@ -0,0 +1,525 @@
+//! The history of published issues, to audit what was sent and to whom.
+use crate::api_error::ApiError;
+use crate::database::ReadPool;
+use crate::http_cache::{self, Audience};
+use crate::pii::PiiCipher;
+use crate::routes::error_chain_fmt;
+use crate::routes::newsletter::StoredExclusions;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+const MAX_PER_PAGE: u32 = 100;
+const MAX_COMPARED_ISSUES: usize = 20;
+
+#[derive(serde::Deserialize)]
+pub struct Pagination {
+    #[serde(default = "first_page")]
+    page: u32,
+    #[serde(default = "default_per_page")]
+    per_page: u32,
+}
+
+fn first_page() -> u32 {
+    1
+}
+
+fn default_per_page() -> u32 {
+    20
+}
+
+impl Pagination {
+    fn validate(&self) -> Result<(), String> {
+        if self.page == 0 {
+            return Err("Pages are numbered from 1.".into());
+        }
+        if !(1..=MAX_PER_PAGE).contains(&self.per_page) {
+            return Err(format!(
+                "List between 1 and {} issues per page.",
+                MAX_PER_PAGE
+            ));
+        }
+        Ok(())
+    }
+
+    fn offset(&self) -> i64 {
+        i64::from(self.page - 1) * i64::from(self.per_page)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct IssuePage {
+    issues: Vec<IssueSummary>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    // The username of the publisher, unless they have since been deleted.
+    author: Option<String>,
+    delivered: i64,
+    // `None` if the issue went out without tracking.
+    engagement: Option<Engagement>,
+}
+
+/// How readers took a tracked issue. Readers who clicked count as having opened it,
+/// as the open pixel doesn't load for those who block images.
+#[derive(serde::Serialize)]
+pub struct Engagement {
+    opened: i64,
+    clicked: i64,
+    // Out of the subscribers it was delivered to; `None` if it was delivered to nobody.
+    open_rate: Option<f64>,
+    click_rate: Option<f64>,
+}
+
+impl Engagement {
+    fn of(tracked: bool, delivered: i64, opened: i64, clicked: i64) -> Option<Self> {
+        let rate = |count: i64| (delivered > 0).then(|| count as f64 / delivered as f64);
+        tracked.then(|| Engagement {
+            opened,
+            clicked,
+            open_rate: rate(opened),
+            click_rate: rate(clicked),
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Issue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    html_content: String,
+    text_content: String,
+    published_at: DateTime<Utc>,
+    author: Option<String>,
+    engagement: Option<Engagement>,
+    progress: DeliveryProgress,
+    exclusions: IssueExclusions,
+    deliveries: Vec<Delivery>,
+}
+
+/// Who the issue was published without.
+#[derive(serde::Serialize)]
+pub struct IssueExclusions {
+    // Emails that can't be decrypted are left out.
+    emails: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// How far the delivery of an issue got. Recipients over the send budget stay `queued`
+/// until the `issue_delivery` job gets to them. Queued deliveries whose last attempt failed
+/// count as `failed` while they wait for a retry, and once they are `dead_lettered`.
+#[derive(serde::Serialize)]
+pub struct DeliveryProgress {
+    sent: i64,
+    failed: i64,
+    bounced: i64,
+    queued: i64,
+    dead_lettered: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Delivery {
+    subscriber_id: Uuid,
+    // `None` if the stored email can't be decrypted, e.g. after its key was dropped.
+    email: Option<String>,
+    sent_at: DateTime<Utc>,
+}
+
+/// Most recent first.
+#[tracing::instrument(
+    name = "List newsletter issues",
+    skip(request, pagination, read_pool),
+    fields(
+        page = pagination.page,
+        per_page = pagination.per_page
+    )
+)]
+pub async fn list_newsletter_issues(
+    request: HttpRequest,
+    pagination: web::Query<Pagination>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, NewsletterIssueError> {
+    let read_pool = read_pool.get().await;
+    pagination
+        .validate()
+        .map_err(NewsletterIssueError::ValidationError)?;
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.published_at, u.username AS "author?",
+            i.tracked,
+            (SELECT COUNT(*) FROM broadcast_deliveries d
+             WHERE d.newsletter_issue_id = i.newsletter_issue_id) AS "delivered!",
+            COALESCE(st.opened, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id) AS "opened!",
+            COALESCE(st.clicked, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id AND t.kind = 'click') AS "clicked!"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        LEFT JOIN issue_statistics st ON st.newsletter_issue_id = i.newsletter_issue_id
+        ORDER BY i.published_at DESC, i.newsletter_issue_id
+        LIMIT $1 OFFSET $2
+        "#,
+        i64::from(pagination.per_page),
+        pagination.offset()
+    )
+    .fetch_all(read_pool)
+    .await
+    .context("Failed to fetch newsletter issues from the database.")?
+    .into_iter()
+    .map(|row| IssueSummary {
+        newsletter_issue_id: row.newsletter_issue_id,
+        title: row.title,
+        published_at: row.published_at,
+        author: row.author,
+        delivered: row.delivered,
+        engagement: Engagement::of(row.tracked, row.delivered, row.opened, row.clicked),
+    })
+    .collect();
+    let total = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(read_pool)
+        .await
+        .context("Failed to count newsletter issues.")?;
+    let page = IssuePage {
+        issues,
+        page: pagination.page,
+        per_page: pagination.per_page,
+        total,
+    };
+    Ok(http_cache::json(&request, &page, None, Audience::Private))
+}
+
+/// The issue as it was rendered, with every subscriber it was delivered to.
+#[tracing::instrument(name = "Get a newsletter issue", skip(request, pool, pii_cipher))]
+pub async fn get_newsletter_issue(
+    request: HttpRequest,
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    pii_cipher: web::Data<PiiCipher>,
+) -> Result<HttpResponse, NewsletterIssueError> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = sqlx::query!(
+        r#"
+        SELECT i.title, i.html_content, i.text_content, i.published_at, u.username AS "author?",
+            i.tracked, i.exclusions AS "exclusions: Json<StoredExclusions>",
+            COALESCE(st.opened, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id) AS "opened!",
+            COALESCE(st.clicked, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id AND t.kind = 'click') AS "clicked!",
+            COALESCE(st.sent, 0) + (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id
+               AND delivery_counts_as_sent(a.status)) AS "sent!",
+            (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id AND a.status = 'failed') AS "failed!",
+            (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id AND a.status = 'bounced') AS "bounced!",
+            (SELECT COUNT(*) FROM issue_delivery_queue q
+             WHERE q.newsletter_issue_id = i.newsletter_issue_id) AS "queued!",
+            (SELECT COUNT(*) FROM issue_delivery_dead_letters d
+             WHERE d.newsletter_issue_id = i.newsletter_issue_id) AS "dead_lettered!"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        LEFT JOIN issue_statistics st ON st.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issue from the database.")?
+    .ok_or(NewsletterIssueError::NotFound)?;
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT d.subscriber_id, s.email, d.sent_at
+        FROM broadcast_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1
+        ORDER BY d.sent_at, d.subscriber_id
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the deliveries of the newsletter issue.")?
+    .into_iter()
+    .map(|row| {
+        let email = pii_cipher
+            .decrypt(&row.email)
+            .inspect_err(|error| {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    subscriber_id = %row.subscriber_id,
+                    "Failed to decrypt the email of a recipient",
+                )
+            })
+            .ok();
+        Delivery {
+            subscriber_id: row.subscriber_id,
+            email,
+            sent_at: row.sent_at,
+        }
+    })
+    .collect::<Vec<_>>();
+    let Json(exclusions) = issue.exclusions;
+    let exclusions = IssueExclusions {
+        emails: exclusions
+            .emails
+            .iter()
+            .filter_map(|email| {
+                pii_cipher
+                    .decrypt(email)
+                    .inspect_err(|error| {
+                        tracing::warn!(
+                            error.cause_chain = ?error,
+                            "Failed to decrypt an excluded email",
+                        )
+                    })
+                    .ok()
+            })
+            .collect(),
+        tags: exclusions.tags,
+    };
+    let engagement = Engagement::of(
+        issue.tracked,
+        deliveries.len() as i64,
+        issue.opened,
+        issue.clicked,
+    );
+    let issue = Issue {
+        newsletter_issue_id,
+        title: issue.title,
+        html_content: issue.html_content,
+        text_content: issue.text_content,
+        published_at: issue.published_at,
+        author: issue.author,
+        engagement,
+        progress: DeliveryProgress {
+            sent: issue.sent,
+            failed: issue.failed,
+            bounced: issue.bounced,
+            queued: issue.queued,
+            dead_lettered: issue.dead_lettered,
+        },
+        exclusions,
+        deliveries,
+    };
+    // No `Last-Modified`: deliveries and engagement keep changing after publication.
+    Ok(http_cache::json(&request, &issue, None, Audience::Private))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CompareQuery {
+    // Comma-separated issue ids.
+    ids: String,
+}
+
+impl CompareQuery {
+    fn parse(&self) -> Result<Vec<Uuid>, String> {
+        let mut ids = self
+            .ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| Uuid::parse_str(id).map_err(|_| format!("`{id}` is not an issue id.")))
+            .collect::<Result<Vec<_>, _>>()?;
+        ids.sort();
+        ids.dedup();
+        if ids.is_empty() || ids.len() > MAX_COMPARED_ISSUES {
+            return Err(format!(
+                "Compare between 1 and {MAX_COMPARED_ISSUES} issues."
+            ));
+        }
+        Ok(ids)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueComparison {
+    issues: Vec<IssueMetrics>,
+    // One per list the compared issues belong to, over every issue it published.
+    baselines: Vec<ListBaseline>,
+}
+
+/// Rates are out of the recipients the issue counts as sent, `None` when it was sent to
+/// nobody; open and click rates are `None` too for issues sent without tracking.
+#[derive(serde::Serialize)]
+pub struct IssueMetrics {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    newsletter: String,
+    sent: i64,
+    // Failed and bounced.
+    failed: i64,
+    opened: i64,
+    clicked: i64,
+    // Recipients who unsubscribed before the list's next issue came out.
+    unsubscribed: i64,
+    open_rate: Option<f64>,
+    click_rate: Option<f64>,
+    unsubscribe_rate: Option<f64>,
+    // Out of the sent and failed recipients.
+    failure_rate: Option<f64>,
+}
+
+/// The average of each rate over the issues of the list it is known for.
+#[derive(serde::Serialize)]
+pub struct ListBaseline {
+    newsletter: String,
+    issues: usize,
+    open_rate: Option<f64>,
+    click_rate: Option<f64>,
+    unsubscribe_rate: Option<f64>,
+    failure_rate: Option<f64>,
+}
+
+/// Open, click, unsubscribe and failure rates of `?ids=`, side by side with the average
+/// rates of their lists.
+#[tracing::instrument(name = "Compare newsletter issues", skip(query, read_pool))]
+pub async fn compare_newsletter_issues(
+    query: web::Query<CompareQuery>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, NewsletterIssueError> {
+    let ids = query
+        .parse()
+        .map_err(NewsletterIssueError::ValidationError)?;
+    // Every issue of the lists involved: the baselines need them all.
+    let metrics: Vec<IssueMetrics> = sqlx::query!(
+        r#"
+        WITH issues AS (
+            SELECT i.newsletter_issue_id, i.title, i.published_at, i.tracked, n.slug,
+                LEAD(i.published_at) OVER (
+                    PARTITION BY i.newsletter_id ORDER BY i.published_at
+                ) AS next_published_at
+            FROM newsletter_issues i
+            JOIN newsletters n ON n.id = i.newsletter_id
+            WHERE i.newsletter_id IN (
+                SELECT newsletter_id FROM newsletter_issues WHERE newsletter_issue_id = ANY($1)
+            )
+        )
+        SELECT i.newsletter_issue_id, i.title, i.published_at, i.tracked, i.slug,
+            COALESCE(st.sent, 0) + (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id
+               AND delivery_counts_as_sent(a.status)) AS "sent!",
+            (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id
+               AND a.status IN ('failed', 'bounced')) AS "failed!",
+            COALESCE(st.opened, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id) AS "opened!",
+            COALESCE(st.clicked, 0) + (SELECT COUNT(DISTINCT t.subscriber_id) FROM tracking_events t
+             WHERE t.newsletter_issue_id = i.newsletter_issue_id AND t.kind = 'click') AS "clicked!",
+            COALESCE(st.unsubscribed, 0) + (SELECT COUNT(*) FROM newsletter_delivery_attempts a
+             JOIN subscriptions s ON s.id = a.subscriber_id
+             WHERE a.newsletter_issue_id = i.newsletter_issue_id
+               AND delivery_counts_as_sent(a.status)
+               AND s.unsubscribed_at >= i.published_at
+               AND (i.next_published_at IS NULL OR s.unsubscribed_at < i.next_published_at)
+            ) AS "unsubscribed!"
+        FROM issues i
+        LEFT JOIN issue_statistics st ON st.newsletter_issue_id = i.newsletter_issue_id
+        ORDER BY i.published_at, i.newsletter_issue_id
+        "#,
+        &ids
+    )
+    .fetch_all(read_pool.get().await)
+    .await
+    .context("Failed to compute the metrics of newsletter issues.")?
+    .into_iter()
+    .map(|row| {
+        let rate = |count: i64| (row.sent > 0).then(|| count as f64 / row.sent as f64);
+        let tracked_rate = |count: i64| rate(count).filter(|_| row.tracked);
+        IssueMetrics {
+            newsletter_issue_id: row.newsletter_issue_id,
+            title: row.title,
+            published_at: row.published_at,
+            newsletter: row.slug,
+            sent: row.sent,
+            failed: row.failed,
+            opened: row.opened,
+            clicked: row.clicked,
+            unsubscribed: row.unsubscribed,
+            open_rate: tracked_rate(row.opened),
+            click_rate: tracked_rate(row.clicked),
+            unsubscribe_rate: rate(row.unsubscribed),
+            failure_rate: (row.sent + row.failed > 0)
+                .then(|| row.failed as f64 / (row.sent + row.failed) as f64),
+        }
+    })
+    .collect();
+
+    let mut baselines: Vec<ListBaseline> = Vec::new();
+    for newsletter in metrics.iter().map(|issue| &issue.newsletter) {
+        if baselines.iter().any(|b| &b.newsletter == newsletter) {
+            continue;
+        }
+        let issues: Vec<_> = metrics
+            .iter()
+            .filter(|issue| &issue.newsletter == newsletter)
+            .collect();
+        let average = |rate: fn(&IssueMetrics) -> Option<f64>| {
+            let rates: Vec<f64> = issues.iter().filter_map(|issue| rate(issue)).collect();
+            (!rates.is_empty()).then(|| rates.iter().sum::<f64>() / rates.len() as f64)
+        };
+        baselines.push(ListBaseline {
+            newsletter: newsletter.clone(),
+            issues: issues.len(),
+            open_rate: average(|issue| issue.open_rate),
+            click_rate: average(|issue| issue.click_rate),
+            unsubscribe_rate: average(|issue| issue.unsubscribe_rate),
+            failure_rate: average(|issue| issue.failure_rate),
+        });
+    }
+    let issues: Vec<IssueMetrics> = metrics
+        .into_iter()
+        .filter(|issue| ids.contains(&issue.newsletter_issue_id))
+        .collect();
+    if issues.len() < ids.len() {
+        return Err(NewsletterIssueError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(IssueComparison { issues, baselines }))
+}
+
+#[derive(thiserror::Error)]
+pub enum NewsletterIssueError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("No newsletter issue with this id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for NewsletterIssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for NewsletterIssueError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            NewsletterIssueError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            NewsletterIssueError::NotFound => StatusCode::NOT_FOUND,
+            NewsletterIssueError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            NewsletterIssueError::ValidationError(e) => ApiError::validation(e),
+            NewsletterIssueError::NotFound => ApiError::not_found(self.to_string()),
+            NewsletterIssueError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
+}
@@ -0,0 +1,154 @@
+//! An issue as subscribers will get it, before it goes out to all of them: rendered
+//! the way publishing renders it, and either returned or sent to a single address.
+use crate::crypto::KeyRing;
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::merge_fields::Recipient;
+use crate::routes::{
+    Content, DEFAULT_LIST_SLUG, FooterLinks, PublishError, get_newsletter, prepare_issue,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{EmailBody, EmailTemplates};
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct IssuePreviewData {
+    title: String,
+    content: Content,
+    // The slug of the list, named in the footer; the default list if there is none.
+    #[serde(default)]
+    newsletter: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    #[serde(flatten)]
+    issue: IssuePreviewData,
+    // The only address the issue goes to.
+    to: String,
+    // For `{{ subscriber.name }}`; the stand-in persona's name if there is none.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssuePreview {
+    subject: String,
+    html: String,
+    text: String,
+}
+
+/// Renders the issue for a stand-in persona. It is checked the way publishing checks it:
+/// an issue that previews fine can be published. Like publishing, editors and admins only.
+#[tracing::instrument(name = "Preview a newsletter issue", skip_all)]
+pub async fn preview_newsletter(
+    body: web::Json<IssuePreviewData>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let email = render_test_email(
+        body.newsletter.as_deref(),
+        &body.content,
+        &Recipient::persona(),
+        &pool,
+        &templates,
+        &base_url,
+        &key_ring,
+        &action_base_url,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(IssuePreview {
+        subject: body.title.clone(),
+        html: email.html,
+        text: email.text,
+    }))
+}
+
+/// Sends the issue to `to` alone, with `[Test]` in front of its title. Nothing is recorded:
+/// the issue isn't stored, and doesn't count towards sponsor impressions or frequency caps.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "Send a test newsletter issue", skip_all)]
+pub async fn test_send_newsletter(
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    key_ring: web::Data<KeyRing>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let to = SubscriberEmail::parse(body.to.trim().to_owned())
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    let recipient = Recipient {
+        name: body.name.as_deref().unwrap_or(Recipient::persona().name),
+        email: to.as_ref(),
+    };
+    let email = render_test_email(
+        body.issue.newsletter.as_deref(),
+        &body.issue.content,
+        &recipient,
+        &pool,
+        &templates,
+        &base_url,
+        &key_ring,
+        &action_base_url,
+    )
+    .await?;
+    email_client
+        .send_email(
+            &to,
+            &format!("[Test] {}", body.issue.title),
+            &email.html,
+            &email.text,
+            MessageCategory::Broadcast,
+        )
+        .await
+        .context("Failed to send the test issue.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The recipient isn't a subscriber, so the unsubscribe link carries no token that works.
+/// `newsletter` is the slug of a list, the default one if `None`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn render_test_email(
+    newsletter: Option<&str>,
+    content: &Content,
+    recipient: &Recipient<'_>,
+    pool: &PgPool,
+    templates: &EmailTemplates,
+    base_url: &ApplicationBaseUrl,
+    key_ring: &KeyRing,
+    action_base_url: &ActionBaseUrl,
+) -> Result<EmailBody, PublishError> {
+    let slug = newsletter.unwrap_or(DEFAULT_LIST_SLUG);
+    let newsletter = get_newsletter(pool, slug)
+        .await
+        .context("Failed to fetch the newsletter.")?
+        .ok_or_else(|| {
+            PublishError::ValidationError(format!("There is no list named `{slug}`."))
+        })?;
+    let rendered = prepare_issue(pool, content, &base_url.0, key_ring).await?;
+    let unsubscribe_link = format!(
+        "{}/subscriptions/unsubscribe?token=preview",
+        action_base_url.as_ref()
+    );
+    let preferences_link = format!(
+        "{}/subscriptions/categories?token=preview",
+        action_base_url.as_ref()
+    );
+    rendered.email(
+        templates,
+        &newsletter.name,
+        recipient,
+        &FooterLinks {
+            unsubscribe: &unsubscribe_link,
+            preferences: &preferences_link,
+        },
+        None,
+    )
+}
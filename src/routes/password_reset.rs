@@ -0,0 +1,320 @@
+//! For admins locked out of their account: a time-limited reset link, sent to their email.
+use crate::admin_access::request_client_ip;
+use crate::admin_notifications::{AdminNotification, notify};
+use crate::authentication::{change_password, check_password_strength};
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::merge_fields::escape;
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, hash_subscription_token,
+    is_well_formed_subscription_token, see_other,
+};
+use crate::session::TypedSession;
+use crate::startup::AdminNotifications;
+use crate::templates::EmailTemplates;
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const PASSWORD_RESET_TOKEN_LIFETIME: Duration = Duration::hours(1);
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetLink {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetData {
+    token: String,
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+pub async fn password_reset_form(
+    session: TypedSession,
+) -> Result<HttpResponse, PasswordResetError> {
+    let flash = take_flash(&session)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>Reset password · zero2prod</title></head><body>\
+            {flash}\
+            <form action=\"/password_reset\" method=\"post\">\
+            <label>Email <input type=\"email\" name=\"email\"></label>\
+            <button type=\"submit\">Send a reset link</button>\
+            </form>\
+            <p><a href=\"/login\">&lt;- Back to login</a></p>\
+            </body></html>"
+        )))
+}
+
+/// Answers the same whether or not the address belongs to an admin, so that this can't be
+/// used to find out who they are.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, session, pool, email_client, action_base_url),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn request_password_reset(
+    form: web::Form<PasswordResetRequestData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, PasswordResetError> {
+    if let Ok(email) = SubscriberEmail::parse(form.0.email)
+        && let Some(user_id) = get_user_id_by_email(&pool, &email).await?
+    {
+        tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+        let token = generate_subscription_token();
+        store_password_reset_token(&pool, user_id, &hash_subscription_token(&token)).await?;
+        // Failing here would give away that the address is known: log it instead.
+        if let Err(e) =
+            send_password_reset_email(&email_client, &email, &action_base_url, &token).await
+        {
+            tracing::error!(error.cause_chain = ?e, "Failed to send a password reset email");
+        }
+    }
+    session
+        .insert_flash(
+            "If this address belongs to an admin, a link to reset their password is on its way.",
+        )
+        .context("Failed to store a message in the session.")?;
+    Ok(see_other("/password_reset"))
+}
+
+pub async fn password_reset_confirm_form(
+    link: web::Query<PasswordResetLink>,
+    session: TypedSession,
+) -> Result<HttpResponse, PasswordResetError> {
+    let flash = take_flash(&session)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>Reset password · zero2prod</title></head><body>\
+            {flash}\
+            <form action=\"/password_reset/confirm\" method=\"post\">\
+            <input type=\"hidden\" name=\"token\" value=\"{}\">\
+            <label>New password <input type=\"password\" name=\"new_password\"></label>\
+            <label>Confirm new password \
+            <input type=\"password\" name=\"new_password_check\"></label>\
+            <button type=\"submit\">Reset password</button>\
+            </form></body></html>",
+            escape(&link.token)
+        )))
+}
+
+/// Sets the new password and uses the token up, along with any other reset token of the admin.
+#[tracing::instrument(
+    name = "Reset a password",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reset_password(
+    request: HttpRequest,
+    form: web::Form<PasswordResetData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    admin_notifications: web::Data<AdminNotifications>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let PasswordResetData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+    if !is_well_formed_subscription_token(&token) {
+        return invalid_link(&session);
+    }
+    let rejection = if new_password.expose_secret() != new_password_check.expose_secret() {
+        Some("You entered two different new passwords - the field values must match.".into())
+    } else {
+        check_password_strength(&new_password).err()
+    };
+    if let Some(message) = rejection {
+        insert_flash(&session, &message)?;
+        // Well-formed tokens are alphanumeric: no need to encode it.
+        return Ok(see_other(&format!("/password_reset/confirm?token={token}")));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(user_id) =
+        consume_password_reset_token(&mut transaction, &hash_subscription_token(&token)).await?
+    else {
+        return invalid_link(&session);
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    change_password(user_id, new_password, &mut *transaction).await?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the other password reset tokens of the user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+    let notification = AdminNotification::PasswordChanged {
+        client_ip: request_client_ip(&request),
+    };
+    notify(
+        &pool,
+        &email_client,
+        &templates,
+        &admin_notifications,
+        user_id,
+        notification,
+    )
+    .await;
+    insert_flash(
+        &session,
+        "Your password has been reset. You can now log in.",
+    )?;
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(name = "Get a user by email", skip(pool, email))]
+async fn get_user_id_by_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM users WHERE lower(email) = lower($1)",
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email.")
+}
+
+#[tracing::instrument(name = "Store a password reset token", skip(pool, token_hash))]
+async fn store_password_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_hash: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (password_reset_token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        token_hash,
+        user_id,
+        Utc::now() + PASSWORD_RESET_TOKEN_LIFETIME
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(())
+}
+
+/// The user the token was issued to, unless it is unknown or has expired.
+#[tracing::instrument(name = "Consume a password reset token", skip_all)]
+async fn consume_password_reset_token(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token_hash: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        DELETE FROM password_reset_tokens
+        WHERE password_reset_token_hash = $1 AND expires_at > now()
+        RETURNING user_id
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to consume a password reset token.")
+}
+
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    action_base_url: &ActionBaseUrl,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let reset_link = format!(
+        "{}/password_reset/confirm?token={}",
+        action_base_url.as_ref(),
+        token
+    );
+    let plain_body = format!(
+        "Visit {} to choose a new password. The link expires in an hour.\n\
+        If you didn't ask for this, you can ignore this email.",
+        reset_link
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to choose a new password. The link expires in an hour.<br />\
+        If you didn't ask for this, you can ignore this email.",
+        reset_link
+    );
+    email_client
+        .send_email(
+            recipient,
+            "Reset your password",
+            &html_body,
+            &plain_body,
+            MessageCategory::Transactional,
+        )
+        .await
+        .context("Failed to send a password reset email.")
+}
+
+fn invalid_link(session: &TypedSession) -> Result<HttpResponse, PasswordResetError> {
+    insert_flash(
+        session,
+        "The reset link is invalid or has expired. Request a new one.",
+    )?;
+    Ok(see_other("/password_reset"))
+}
+
+fn take_flash(session: &TypedSession) -> Result<String, PasswordResetError> {
+    Ok(session
+        .take_flash()
+        .context("Failed to read the session.")?
+        .map(|message| format!("<p><i>{}</i></p>", escape(&message)))
+        .unwrap_or_default())
+}
+
+fn insert_flash(session: &TypedSession, message: &str) -> Result<(), PasswordResetError> {
+    session
+        .insert_flash(message)
+        .context("Failed to store a message in the session.")?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PasswordResetError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
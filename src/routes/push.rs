@@ -0,0 +1,134 @@
+use crate::http_cache::{self, Audience};
+use crate::routes::{PreferenceKind, SubscriberToken, SubscriberTokenError, error_chain_fmt};
+use crate::signed_tokens::TokenSigner;
+use crate::web_push::{PushSubscription, WebPushClient};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+/// `subscription` is the browser's `PushSubscription.toJSON()`.
+#[derive(serde::Deserialize)]
+pub struct PushSubscribeData {
+    preference_token: String,
+    subscription: SubscriptionData,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionData {
+    endpoint: String,
+    keys: SubscriptionKeys,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct VapidPublicKey {
+    public_key: String,
+}
+
+impl From<SubscriptionData> for PushSubscription {
+    fn from(data: SubscriptionData) -> Self {
+        Self {
+            endpoint: data.endpoint,
+            p256dh: data.keys.p256dh,
+            auth: data.keys.auth,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get the VAPID public key", skip(request, web_push))]
+pub async fn vapid_public_key(
+    request: HttpRequest,
+    web_push: web::Data<Option<WebPushClient>>,
+) -> Result<HttpResponse, PushError> {
+    let web_push = web_push.as_ref().as_ref().ok_or(PushError::Disabled)?;
+    let key = VapidPublicKey {
+        public_key: web_push.public_key(),
+    };
+    Ok(http_cache::json(&request, &key, None, Audience::Public))
+}
+
+#[tracing::instrument(
+    name = "Store a push subscription",
+    skip(body, pool, token_signer, web_push)
+)]
+pub async fn push_subscribe(
+    body: web::Json<PushSubscribeData>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+    web_push: web::Data<Option<WebPushClient>>,
+) -> Result<HttpResponse, PushError> {
+    if web_push.is_none() {
+        return Err(PushError::Disabled);
+    }
+    let PushSubscribeData {
+        preference_token,
+        subscription,
+    } = body.into_inner();
+    let subscription = PushSubscription::from(subscription);
+    subscription
+        .validate()
+        .map_err(PushError::ValidationError)?;
+
+    let token = SubscriberToken::<PreferenceKind>::resolve(&pool, &token_signer, &preference_token)
+        .await
+        .map_err(|e| match e {
+            SubscriberTokenError::UnexpectedError(e) => PushError::UnexpectedError(e),
+            _ => PushError::UnknownToken,
+        })?;
+
+    // Browsers hand out a new endpoint when they rotate keys, so the endpoint identifies the subscription.
+    sqlx::query!(
+        r#"
+        INSERT INTO push_subscriptions (endpoint, subscriber_id, p256dh, auth, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (endpoint) DO UPDATE
+        SET subscriber_id = EXCLUDED.subscriber_id, p256dh = EXCLUDED.p256dh, auth = EXCLUDED.auth
+        "#,
+        subscription.endpoint,
+        token.subscriber_id,
+        subscription.p256dh,
+        subscription.auth,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the push subscription in the database.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum PushError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Web Push notifications are not enabled.")]
+    Disabled,
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PushError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PushError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PushError::Disabled => StatusCode::NOT_FOUND,
+            PushError::UnknownToken => StatusCode::UNAUTHORIZED,
+            PushError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
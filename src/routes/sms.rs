@@ -0,0 +1,225 @@
+use crate::domain::PhoneNumber;
+use crate::routes::{PreferenceKind, SubscriberToken, SubscriberTokenError, error_chain_fmt};
+use crate::signed_tokens::TokenSigner;
+use crate::sms_client::SmsClient;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use rand::{Rng, thread_rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const VERIFICATION_CODE_TTL_MINUTES: i64 = 10;
+// Past this many wrong guesses, the subscriber has to register again for a new code.
+const MAX_VERIFICATION_ATTEMPTS: i32 = 5;
+
+#[derive(serde::Deserialize)]
+pub struct SmsRegisterData {
+    preference_token: String,
+    phone_number: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SmsVerifyData {
+    preference_token: String,
+    code: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SmsOptOutData {
+    preference_token: String,
+}
+
+/// Register (or replace) a subscriber's phone number and text them a verification code.
+/// They don't get any SMS announcements until the number is verified.
+#[tracing::instrument(
+    name = "Register a phone number",
+    skip(body, pool, token_signer, sms_client)
+)]
+pub async fn sms_register(
+    body: web::Json<SmsRegisterData>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+    sms_client: web::Data<Option<SmsClient>>,
+) -> Result<HttpResponse, SmsError> {
+    let sms_client = sms_client.as_ref().as_ref().ok_or(SmsError::Disabled)?;
+    let SmsRegisterData {
+        preference_token,
+        phone_number,
+    } = body.into_inner();
+    let phone_number = PhoneNumber::parse(phone_number).map_err(SmsError::ValidationError)?;
+    let subscriber_id = resolve_subscriber(&pool, &token_signer, &preference_token).await?;
+
+    let code = generate_verification_code();
+    sqlx::query!(
+        r#"
+        INSERT INTO sms_registrations (
+            subscriber_id, phone_number, verification_code_hash,
+            verification_code_expires_at, created_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (subscriber_id) DO UPDATE
+        SET phone_number = EXCLUDED.phone_number,
+            verification_code_hash = EXCLUDED.verification_code_hash,
+            verification_code_expires_at = EXCLUDED.verification_code_expires_at,
+            failed_verification_attempts = 0,
+            verified_at = NULL,
+            opted_in = FALSE
+        "#,
+        subscriber_id,
+        phone_number.as_ref(),
+        hash_verification_code(&code),
+        Utc::now() + Duration::minutes(VERIFICATION_CODE_TTL_MINUTES),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the phone number in the database.")?;
+
+    sms_client
+        .send_sms(
+            &phone_number,
+            &format!("Your newsletter verification code is {code}"),
+        )
+        .await
+        .context("Failed to send the verification code.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Check the code sent by `sms_register`. On success, the subscriber is opted in to SMS.
+#[tracing::instrument(
+    name = "Verify a phone number",
+    skip(body, pool, token_signer, sms_client)
+)]
+pub async fn sms_verify(
+    body: web::Json<SmsVerifyData>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+    sms_client: web::Data<Option<SmsClient>>,
+) -> Result<HttpResponse, SmsError> {
+    if sms_client.is_none() {
+        return Err(SmsError::Disabled);
+    }
+    let subscriber_id = resolve_subscriber(&pool, &token_signer, &body.preference_token).await?;
+    let registration = sqlx::query!(
+        r#"
+        SELECT verification_code_hash, verification_code_expires_at, failed_verification_attempts
+        FROM sms_registrations
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the phone number registration.")?
+    .ok_or(SmsError::NotRegistered)?;
+
+    let is_pending = registration
+        .verification_code_expires_at
+        .is_some_and(|expires_at| expires_at > Utc::now())
+        && registration.failed_verification_attempts < MAX_VERIFICATION_ATTEMPTS;
+    let is_correct = registration.verification_code_hash.as_deref()
+        == Some(hash_verification_code(body.code.trim()).as_str());
+    if !(is_pending && is_correct) {
+        sqlx::query!(
+            r#"
+            UPDATE sms_registrations
+            SET failed_verification_attempts = failed_verification_attempts + 1
+            WHERE subscriber_id = $1
+            "#,
+            subscriber_id
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to record a failed verification attempt.")?;
+        return Err(SmsError::ValidationError(
+            "The verification code is invalid or has expired.".into(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE sms_registrations
+        SET verified_at = now(), opted_in = TRUE,
+            verification_code_hash = NULL, verification_code_expires_at = NULL
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to mark the phone number as verified.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Stop SMS announcements. The email subscription is left alone.
+#[tracing::instrument(name = "Opt out of SMS", skip(body, pool, token_signer))]
+pub async fn sms_opt_out(
+    body: web::Json<SmsOptOutData>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> Result<HttpResponse, SmsError> {
+    let subscriber_id = resolve_subscriber(&pool, &token_signer, &body.preference_token).await?;
+    sqlx::query!(
+        r#"UPDATE sms_registrations SET opted_in = FALSE WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to opt out of SMS.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn resolve_subscriber(
+    pool: &PgPool,
+    token_signer: &TokenSigner,
+    preference_token: &str,
+) -> Result<Uuid, SmsError> {
+    SubscriberToken::<PreferenceKind>::resolve(pool, token_signer, preference_token)
+        .await
+        .map(|token| token.subscriber_id)
+        .map_err(|e| match e {
+            SubscriberTokenError::UnexpectedError(e) => SmsError::UnexpectedError(e),
+            _ => SmsError::UnknownToken,
+        })
+}
+
+fn generate_verification_code() -> String {
+    format!("{:06}", thread_rng().gen_range(0..1_000_000))
+}
+
+fn hash_verification_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+#[derive(thiserror::Error)]
+pub enum SmsError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("SMS announcements are not enabled.")]
+    Disabled,
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("No phone number was registered for this subscriber.")]
+    NotRegistered,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SmsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SmsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SmsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SmsError::Disabled | SmsError::NotRegistered => StatusCode::NOT_FOUND,
+            SmsError::UnknownToken => StatusCode::UNAUTHORIZED,
+            SmsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::crypto::KeyRing;
+use crate::routes::SponsorError;
+use crate::sponsors::{SPONSOR_CLICK_SIGNATURE, SPONSOR_OPEN_SIGNATURE};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A transparent 1x1 GIF, served as the open-tracking pixel of sponsor creatives and issues.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingParameters {
+    signature: Option<String>,
+}
+
+impl TrackingParameters {
+    pub(crate) fn is_valid_for(&self, purpose: &str, message: &[u8], keys: &KeyRing) -> bool {
+        self.signature
+            .as_deref()
+            .is_some_and(|signature| keys.verify(purpose, message, signature))
+    }
+}
+
+#[tracing::instrument(name = "Track a sponsor click", skip(parameters, pool, key_ring))]
+pub async fn sponsor_click(
+    slot_id: web::Path<Uuid>,
+    parameters: web::Query<TrackingParameters>,
+    pool: web::Data<PgPool>,
+    key_ring: web::Data<KeyRing>,
+) -> Result<HttpResponse, SponsorError> {
+    // Readers following a link that wasn't signed by us still get to the sponsor,
+    // the click just doesn't count.
+    let target_url =
+        if parameters.is_valid_for(SPONSOR_CLICK_SIGNATURE, slot_id.as_bytes(), &key_ring) {
+            sqlx::query_scalar!(
+            r#"UPDATE sponsor_slots SET clicks = clicks + 1 WHERE id = $1 RETURNING target_url"#,
+            *slot_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await
+        .context("Failed to record the sponsor click.")?
+        } else {
+            sqlx::query_scalar!(
+                r#"SELECT target_url FROM sponsor_slots WHERE id = $1"#,
+                *slot_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await
+            .context("Failed to fetch the sponsor slot from the database.")?
+        }
+        .ok_or(SponsorError::NotFound)?;
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, target_url))
+        .finish())
+}
+
+#[tracing::instrument(name = "Track a sponsor open", skip(parameters, pool, key_ring))]
+pub async fn sponsor_open(
+    slot_id: web::Path<Uuid>,
+    parameters: web::Query<TrackingParameters>,
+    pool: web::Data<PgPool>,
+    key_ring: web::Data<KeyRing>,
+) -> HttpResponse {
+    if !parameters.is_valid_for(SPONSOR_OPEN_SIGNATURE, slot_id.as_bytes(), &key_ring) {
+        return tracking_pixel();
+    }
+    // A broken image in the reader's inbox is worse than a lost data point:
+    // the pixel is served even if we fail to record the open.
+    if let Err(e) = sqlx::query!(
+        r#"UPDATE sponsor_slots SET opens = opens + 1 WHERE id = $1"#,
+        *slot_id
+    )
+    .execute(pool.get_ref())
+    .await
+    {
+        tracing::warn!(error.cause_chain = ?e, "Failed to record the sponsor open.");
+    }
+    tracking_pixel()
+}
+
+pub(crate) fn tracking_pixel() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "image/gif"))
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .body(TRACKING_PIXEL)
+}
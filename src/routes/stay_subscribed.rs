@@ -0,0 +1,88 @@
+use crate::domain::Language;
+use crate::routes::{RepermissionKind, SubscriberToken, error_chain_fmt};
+use crate::templates::{EmailTemplates, StayedPage};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// The link of a re-permission email: the subscriber won't be unsubscribed when the
+/// campaign closes. Following it again is fine; once the campaign unsubscribed them, or
+/// they left on their own, it is too late.
+#[tracing::instrument(name = "Stay subscribed", skip_all)]
+pub async fn stay_subscribed(
+    token: SubscriberToken<RepermissionKind>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, StayError> {
+    let request = sqlx::query!(
+        r#"
+        SELECT s.status, s.deleted_at, s.language, n.name AS newsletter
+        FROM subscriptions s
+        JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.id = $1
+        "#,
+        token.subscriber_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber asked to stay.")?;
+    if request.status != "confirmed" || request.deleted_at.is_some() {
+        return Err(StayError::NotSubscribed);
+    }
+    // The campaign may have closed in between: then it unsubscribed them.
+    let stayed = sqlx::query!(
+        r#"
+        UPDATE repermission_requests SET stayed_at = COALESCE(stayed_at, now())
+        WHERE campaign_id = $1 AND subscriber_id = $2 AND unsubscribed_at IS NULL
+        "#,
+        token.details,
+        token.subscriber_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to record that the subscriber stays.")?
+    .rows_affected();
+    if stayed == 0 {
+        return Err(StayError::NotSubscribed);
+    }
+    let language = request
+        .language
+        .as_deref()
+        .and_then(|l| Language::parse(l).ok());
+    let page = templates
+        .stayed_page(
+            &StayedPage {
+                newsletter: &request.newsletter,
+            },
+            language.as_ref(),
+        )
+        .context("Failed to render the stayed page.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+#[derive(thiserror::Error)]
+pub enum StayError {
+    #[error("You are no longer subscribed to this list: sign up again to get its issues.")]
+    NotSubscribed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for StayError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StayError::NotSubscribed => StatusCode::GONE,
+            StayError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
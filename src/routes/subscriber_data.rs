@@ -0,0 +1,407 @@
+//! Subscribers' rights over their data: a copy of everything we keep about them, or its
+//! erasure. Both need a time-limited link, emailed to the subscriber on request.
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::{EmailClient, MessageCategory};
+use crate::merge_fields::escape;
+use crate::pii::PiiCipher;
+use crate::routes::{
+    DataRequestKind, SubscriberToken, SubscriberTokenError, error_chain_fmt,
+    generate_subscription_token, hash_subscription_token, purge_subscriber,
+};
+use crate::signed_tokens::TokenSigner;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DATA_REQUEST_TOKEN_LIFETIME: Duration = Duration::hours(24);
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestToken {
+    token: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    subscriber_id: Uuid,
+    // The slug of the list the subscription is to.
+    newsletter: String,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    confirmation_reminder_sent_at: Option<DateTime<Utc>>,
+    invite_code: Option<String>,
+    attributes: serde_json::Value,
+    tags: Vec<String>,
+    category_opt_outs: Vec<String>,
+    push_subscriptions: Vec<PushSubscriptionData>,
+    sms_registration: Option<SmsRegistrationData>,
+    deliveries: Vec<DeliveryData>,
+}
+
+#[derive(serde::Serialize)]
+struct PushSubscriptionData {
+    endpoint: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct SmsRegistrationData {
+    phone_number: String,
+    verified_at: Option<DateTime<Utc>>,
+    opted_in: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryData {
+    newsletter_issue_id: Uuid,
+    title: String,
+    // `sent`, `failed` or `bounced`
+    status: String,
+    attempted_at: DateTime<Utc>,
+}
+
+/// Addresses that don't belong to a subscriber get a `200` too, so that this can't be used
+/// to find out who is subscribed. Addresses subscribed to several lists get an email, and
+/// links, per list.
+#[tracing::instrument(
+    name = "Request a copy or the erasure of subscriber data",
+    skip(form, pool, email_client, pii_cipher, action_base_url)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let email = SubscriberEmail::parse(form.0.email)
+        .map_err(|e| SubscriberDataError::ValidationError(e.to_string()))?;
+    for subscription in get_subscriptions_by_email(&pool, &pii_cipher, &email).await? {
+        tracing::info!(subscriber_id = %subscription.id, "Sending a data request link");
+        let token = generate_subscription_token();
+        store_data_request_token(&pool, subscription.id, &hash_subscription_token(&token)).await?;
+        send_data_request_email(
+            &email_client,
+            &email,
+            &subscription.newsletter,
+            &action_base_url,
+            &token,
+        )
+        .await?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Everything stored about the subscriber, as a JSON download. The link keeps working
+/// until it expires.
+#[tracing::instrument(
+    name = "Export subscriber data",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn export_subscriber_data(
+    token: SubscriberToken<DataRequestKind>,
+    pool: web::Data<PgPool>,
+    pii_cipher: web::Data<PiiCipher>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = token.subscriber_id;
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    let data = get_subscriber_data(&pool, &pii_cipher, subscriber_id).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(data))
+}
+
+/// Asks for a confirmation before erasing: following the link alone is not enough.
+pub async fn erase_subscriber_data_form(parameters: web::Query<DataRequestToken>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>Erase your data · zero2prod</title></head><body>\
+            <p>This deletes your subscription and everything we keep about you. \
+            It can't be undone.</p>\
+            <form action=\"/subscriptions/erase\" method=\"post\">\
+            <input type=\"hidden\" name=\"token\" value=\"{}\">\
+            <button type=\"submit\">Erase my data</button>\
+            </form></body></html>",
+            escape(&parameters.token)
+        ))
+}
+
+/// Irreversible: the subscriber, their tokens and their delivery history are deleted. What
+/// is left is an entry in `subscriber_erasures`, to show that the request was carried out.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn erase_subscriber_data(
+    form: web::Form<DataRequestToken>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let token =
+        SubscriberToken::<DataRequestKind>::resolve(&pool, &token_signer, &form.token).await?;
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(&token.subscriber_id),
+    );
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Another request may have erased the subscriber meanwhile.
+    let consumed = sqlx::query!(
+        "DELETE FROM data_request_tokens WHERE subscriber_id = $1",
+        token.subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to consume the data request tokens.")?
+    .rows_affected();
+    if consumed == 0 {
+        return Err(SubscriberTokenError::UnknownToken.into());
+    }
+    purge_subscriber(&mut transaction, token.subscriber_id).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_erasures (subscriber_id, requested_at, erased_at)
+        VALUES ($1, $2, now())
+        "#,
+        token.subscriber_id,
+        token.details
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the erasure of a subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    tracing::info!("Erased a subscriber on their request");
+    Ok(HttpResponse::Ok().finish())
+}
+
+struct Subscription {
+    id: Uuid,
+    // The name of the list.
+    newsletter: String,
+}
+
+#[tracing::instrument(name = "Get the subscriptions of an email address", skip_all)]
+async fn get_subscriptions_by_email(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    email: &SubscriberEmail,
+) -> Result<Vec<Subscription>, anyhow::Error> {
+    // Encrypted emails can only be found through their blind index.
+    sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT s.id, n.name AS newsletter
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.email = $1 OR s.email_blind_index = $2
+        ORDER BY n.slug
+        "#,
+        email.as_ref(),
+        pii_cipher.email_index(email.as_ref()),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to look up the subscriptions of an email address.")
+}
+
+#[tracing::instrument(name = "Store a data request token", skip(pool, token_hash))]
+async fn store_data_request_token(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    token_hash: &str,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO data_request_tokens
+            (data_request_token_hash, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token_hash,
+        subscriber_id,
+        now,
+        now + DATA_REQUEST_TOKEN_LIFETIME
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a data request token.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get the data of a subscriber", skip(pool, pii_cipher))]
+async fn get_subscriber_data(
+    pool: &PgPool,
+    pii_cipher: &PiiCipher,
+    subscriber_id: Uuid,
+) -> Result<SubscriberData, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT s.email, s.name, s.status, s.subscribed_at, s.confirmation_reminder_sent_at,
+            s.invite_code, s.attributes, n.slug AS newsletter
+        FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id
+        WHERE s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the subscriber.")?;
+    let tags = sqlx::query_scalar!(
+        r#"SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the tags of the subscriber.")?;
+    let category_opt_outs = sqlx::query_scalar!(
+        r#"SELECT category FROM category_opt_outs WHERE subscriber_id = $1 ORDER BY category"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the category opt-outs of the subscriber.")?;
+    let push_subscriptions = sqlx::query_as!(
+        PushSubscriptionData,
+        r#"
+        SELECT endpoint, created_at FROM push_subscriptions
+        WHERE subscriber_id = $1 ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the push subscriptions of the subscriber.")?;
+    let sms_registration = sqlx::query_as!(
+        SmsRegistrationData,
+        r#"
+        SELECT phone_number, verified_at, opted_in, created_at FROM sms_registrations
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the SMS registration of the subscriber.")?;
+    let deliveries = sqlx::query_as!(
+        DeliveryData,
+        r#"
+        SELECT a.newsletter_issue_id, i.title, a.status, a.attempted_at
+        FROM newsletter_delivery_attempts a
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE a.subscriber_id = $1
+        ORDER BY a.attempted_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the deliveries to the subscriber.")?;
+    Ok(SubscriberData {
+        subscriber_id,
+        newsletter: subscriber.newsletter,
+        email: pii_cipher
+            .decrypt(&subscriber.email)
+            .context("Failed to decrypt the email of the subscriber.")?,
+        name: pii_cipher
+            .decrypt(&subscriber.name)
+            .context("Failed to decrypt the name of the subscriber.")?,
+        status: subscriber.status,
+        subscribed_at: subscriber.subscribed_at,
+        confirmation_reminder_sent_at: subscriber.confirmation_reminder_sent_at,
+        invite_code: subscriber.invite_code,
+        attributes: subscriber.attributes,
+        tags,
+        category_opt_outs,
+        push_subscriptions,
+        sms_registration,
+        deliveries,
+    })
+}
+
+async fn send_data_request_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    newsletter: &str,
+    action_base_url: &ActionBaseUrl,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let export_link = format!(
+        "{}/subscriptions/export?token={}",
+        action_base_url.as_ref(),
+        token
+    );
+    let erase_link = format!(
+        "{}/subscriptions/erase?token={}",
+        action_base_url.as_ref(),
+        token
+    );
+    let plain_body = format!(
+        "Visit {export_link} to download everything we keep about your subscription \
+        to {newsletter},\n\
+        or {erase_link} to have it erased.\n\
+        The links expire in 24 hours. If you didn't ask for this, you can ignore this email."
+    );
+    let html_body = format!(
+        "Click <a href=\"{export_link}\">here</a> to download everything we keep about your \
+        subscription to {}, or <a href=\"{erase_link}\">here</a> to have it erased.<br />\
+        The links expire in 24 hours. If you didn't ask for this, you can ignore this email.",
+        escape(newsletter)
+    );
+    email_client
+        .send_email(
+            recipient,
+            "Your data",
+            &html_body,
+            &plain_body,
+            MessageCategory::Transactional,
+        )
+        .await
+        .context("Failed to send a data request email.")
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Token(#[from] SubscriberTokenError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberDataError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriberDataError::Token(e) => e.status_code(),
+            SubscriberDataError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
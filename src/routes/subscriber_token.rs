@@ -0,0 +1,327 @@
+use crate::routes::{error_chain_fmt, hash_subscription_token, is_well_formed_subscription_token};
+use crate::runtime_flags::{RuntimeFlag, is_enabled};
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, ResponseError, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// The subscriber a token of kind `K` was handed out to, from a link we emailed them.
+///
+/// As an extractor, the token is taken from a path segment named after `K::PARAMETER`,
+/// or else from the query parameter of that name.
+pub struct SubscriberToken<K: TokenKind = SubscriptionKind> {
+    pub subscriber_id: Uuid,
+    pub details: K::Details,
+}
+
+/// A kind of token emailed to subscribers: where links carry it, how it is checked before
+/// it is looked up, and what it is stored with.
+#[async_trait::async_trait]
+pub trait TokenKind: Sized + 'static {
+    const PARAMETER: &'static str;
+    type Details: Send;
+
+    /// The token as stored, from the one in a link. Garbage is turned away here, without
+    /// a trip to the database.
+    fn unwrap(signer: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError>;
+
+    /// `None` for tokens we don't know about.
+    async fn look_up(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error>;
+}
+
+/// The token of confirmation links, signed. It is deleted once used.
+pub struct SubscriptionKind;
+
+pub struct SubscriptionDetails {
+    pub redirect_to: Option<String>,
+    // Past it, the token can no longer confirm a subscription.
+    pub expires_at: DateTime<Utc>,
+}
+
+#[async_trait::async_trait]
+impl TokenKind for SubscriptionKind {
+    const PARAMETER: &'static str = "subscription_token";
+    type Details = SubscriptionDetails;
+
+    fn unwrap(signer: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError> {
+        signer
+            .verify(TokenPurpose::Subscription, token)
+            .map(str::to_owned)
+            .ok_or(SubscriberTokenError::MalformedToken)
+    }
+
+    #[tracing::instrument(name = "Get subscription token", skip_all)]
+    async fn look_up(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error> {
+        let row = if is_enabled(pool, RuntimeFlag::ReadTokenHashes).await? {
+            sqlx::query!(
+                r#"SELECT subscriber_id, redirect_to, expires_at FROM subscription_tokens WHERE subscription_token_hash = $1"#,
+                hash_subscription_token(token),
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|row| (row.subscriber_id, row.redirect_to, row.expires_at))
+        } else {
+            sqlx::query!(
+                r#"SELECT subscriber_id, redirect_to, expires_at FROM subscription_tokens WHERE subscription_token = $1"#,
+                token,
+            )
+            .fetch_optional(pool)
+            .await?
+            .map(|row| (row.subscriber_id, row.redirect_to, row.expires_at))
+        };
+        Ok(
+            row.map(|(subscriber_id, redirect_to, expires_at)| SubscriberToken {
+                subscriber_id,
+                details: SubscriptionDetails {
+                    redirect_to,
+                    expires_at,
+                },
+            }),
+        )
+    }
+}
+
+/// The token of the unsubscribe link at the bottom of issues, signed. Those of deleted
+/// subscribers are unknown.
+pub struct UnsubscribeKind;
+
+#[async_trait::async_trait]
+impl TokenKind for UnsubscribeKind {
+    const PARAMETER: &'static str = "token";
+    type Details = ();
+
+    fn unwrap(signer: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError> {
+        signer
+            .verify(TokenPurpose::Unsubscribe, token)
+            .map(str::to_owned)
+            .ok_or(SubscriberTokenError::MalformedToken)
+    }
+
+    #[tracing::instrument(name = "Get unsubscribe token", skip_all)]
+    async fn look_up(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error> {
+        let subscriber_id = sqlx::query_scalar!(
+            r#"
+            SELECT t.subscriber_id FROM unsubscribe_tokens t
+            JOIN subscriptions s ON s.id = t.subscriber_id
+            WHERE t.unsubscribe_token = $1 AND s.deleted_at IS NULL
+            "#,
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(subscriber_id.map(|subscriber_id| SubscriberToken {
+            subscriber_id,
+            details: (),
+        }))
+    }
+}
+
+/// The token of the links to a confirmed subscriber's preferences, signed: issue categories,
+/// Web Push, SMS and email changes. Those of deleted subscribers are unknown.
+pub struct PreferenceKind;
+
+#[async_trait::async_trait]
+impl TokenKind for PreferenceKind {
+    const PARAMETER: &'static str = "token";
+    type Details = ();
+
+    fn unwrap(signer: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError> {
+        signer
+            .verify(TokenPurpose::Preferences, token)
+            .map(str::to_owned)
+            .ok_or(SubscriberTokenError::MalformedToken)
+    }
+
+    #[tracing::instrument(name = "Get preference token", skip_all)]
+    async fn look_up(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error> {
+        let subscriber_id = sqlx::query_scalar!(
+            r#"
+            SELECT t.subscriber_id FROM preference_tokens t
+            JOIN subscriptions s ON s.id = t.subscriber_id
+            WHERE t.preference_token = $1 AND s.deleted_at IS NULL
+            "#,
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(subscriber_id.map(|subscriber_id| SubscriberToken {
+            subscriber_id,
+            details: (),
+        }))
+    }
+}
+
+/// The token of the links to export or erase a subscriber's data. It isn't signed, but only
+/// its hash is stored; expired ones are unknown, and malformed ones too.
+pub struct DataRequestKind;
+
+#[async_trait::async_trait]
+impl TokenKind for DataRequestKind {
+    const PARAMETER: &'static str = "token";
+    // When the link was asked for.
+    type Details = DateTime<Utc>;
+
+    fn unwrap(_: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError> {
+        if !is_well_formed_subscription_token(token) {
+            return Err(SubscriberTokenError::UnknownToken);
+        }
+        Ok(hash_subscription_token(token))
+    }
+
+    #[tracing::instrument(name = "Get data request token", skip_all)]
+    async fn look_up(
+        pool: &PgPool,
+        token_hash: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT subscriber_id, created_at FROM data_request_tokens
+            WHERE data_request_token_hash = $1 AND expires_at > now()
+            "#,
+            token_hash
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.map(|row| SubscriberToken {
+            subscriber_id: row.subscriber_id,
+            details: row.created_at,
+        }))
+    }
+}
+
+/// The token of the "stay subscribed" link of re-permission emails, signed.
+pub struct RepermissionKind;
+
+#[async_trait::async_trait]
+impl TokenKind for RepermissionKind {
+    const PARAMETER: &'static str = "token";
+    // The campaign that asked.
+    type Details = Uuid;
+
+    fn unwrap(signer: &TokenSigner, token: &str) -> Result<String, SubscriberTokenError> {
+        signer
+            .verify(TokenPurpose::Repermission, token)
+            .map(str::to_owned)
+            .ok_or(SubscriberTokenError::MalformedToken)
+    }
+
+    #[tracing::instrument(name = "Get re-permission token", skip_all)]
+    async fn look_up(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<SubscriberToken<Self>>, anyhow::Error> {
+        let row = sqlx::query!(
+            "SELECT campaign_id, subscriber_id FROM repermission_requests WHERE token = $1",
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.map(|row| SubscriberToken {
+            subscriber_id: row.subscriber_id,
+            details: row.campaign_id,
+        }))
+    }
+}
+
+impl SubscriberToken<SubscriptionKind> {
+    pub fn is_expired(&self) -> bool {
+        self.details.expires_at <= Utc::now()
+    }
+}
+
+impl<K: TokenKind> SubscriberToken<K> {
+    /// `token` is the token from a link. Invalid ones are turned away without a trip to the
+    /// database; only those that could be ours are looked up.
+    #[tracing::instrument(name = "Resolve a subscriber token", skip_all)]
+    pub async fn resolve(
+        pool: &PgPool,
+        signer: &TokenSigner,
+        token: &str,
+    ) -> Result<Self, SubscriberTokenError> {
+        let token = K::unwrap(signer, token)?;
+        K::look_up(pool, &token)
+            .await
+            .context("Failed to retrieve the subscriber associated with the provided token.")?
+            .ok_or(SubscriberTokenError::UnknownToken)
+    }
+}
+
+impl<K: TokenKind> FromRequest for SubscriberToken<K> {
+    type Error = SubscriberTokenError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = req
+            .match_info()
+            .get(K::PARAMETER)
+            .map(str::to_owned)
+            .or_else(|| {
+                web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                    .ok()
+                    .and_then(|parameters| parameters.into_inner().remove(K::PARAMETER))
+            });
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let signer = req.app_data::<web::Data<TokenSigner>>().cloned();
+        Box::pin(async move {
+            let token = token.ok_or(SubscriberTokenError::MissingToken)?;
+            let pool = pool.context("The database pool is not registered as app data.")?;
+            let signer = signer.context("The token signer is not registered as app data.")?;
+            Self::resolve(&pool, &signer, &token).await
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberTokenError {
+    #[error("A token is required.")]
+    MissingToken,
+    #[error("The token is invalid.")]
+    MalformedToken,
+    #[error("The token is unknown.")]
+    UnknownToken,
+    #[error(
+        "This confirmation link has expired. \
+        Ask for a new one with your email address at /subscriptions/resend_confirmation."
+    )]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberTokenError::MissingToken | SubscriberTokenError::MalformedToken => {
+                StatusCode::BAD_REQUEST
+            }
+            SubscriberTokenError::UnknownToken => StatusCode::UNAUTHORIZED,
+            SubscriberTokenError::ExpiredToken => StatusCode::GONE,
+            SubscriberTokenError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -1,11 +1,35 @@
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    api_error::ApiError,
+    audit_log::{Actor, Source, SubscriberEvent, record},
+    confirmation_outbox::{deliver_confirmation_email, enqueue_confirmation_email},
+    database,
+    domain::{
+        ActionBaseUrl, DomainError, Language, NewSubscriber, RedirectTarget, SignupAttributes,
+        SubscriberEmail, SubscriberName, TagName,
+    },
+    email_client::{EmailClient, MessageCategory},
+    http_cache::{self, Audience},
+    invites::{normalise_invite_code, redeem_invite},
+    pii::PiiCipher,
+    rate_limit::reject_over_limit,
+    routes::{Newsletter, add_tags, resend_confirmation_email},
+    runtime_flags::{RuntimeFlag, is_enabled},
+    signed_tokens::{TokenPurpose, TokenSigner},
+    signup_verification::{SignupVerifier, Solution, VerificationError},
+    startup::{
+        InviteOnly, RedirectAllowedHosts, ResendConfirmationRateLimiter, SignupFields,
+        SubscriberCap, SubscriptionRateLimiter,
+    },
+    templates::{ConfirmationEmail, EmailTemplates},
+    waitlist::{is_at_capacity, send_waitlist_email},
 };
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::http::StatusCode;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::middleware::Next;
 use actix_web::{
-    HttpResponse, ResponseError,
+    HttpRequest, HttpResponse, ResponseError,
     web::{Data, Form},
 };
 use anyhow::Context;
@@ -13,141 +37,536 @@ use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{Rng, thread_rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    // Where to send the subscriber once they have confirmed, e.g. a thank-you page
+    redirect_to: Option<String>,
+    // Required when the newsletter is invite-only
+    invite_code: Option<String>,
+    // Comma-separated, e.g. `rust,beginner`, for issues sent to a segment
+    tags: Option<String>,
+    // A language tag such as `fr`, for their emails and pages; English if unset
+    language: Option<String>,
+    // The token of the CAPTCHA widget, when signups are verified with hCaptcha or Turnstile;
+    // each posts it under its own name.
+    #[serde(rename = "h-captcha-response", alias = "cf-turnstile-response")]
+    captcha_response: Option<String>,
+    // A challenge from `GET /subscriptions/challenge` and its solution, when signups are
+    // verified with a proof of work.
+    pow_challenge: Option<String>,
+    pow_nonce: Option<String>,
+    // Answers to the configured signup fields, along with anything else the form sent
+    #[serde(flatten)]
+    attributes: HashMap<String, String>,
 }
 
 // If you provide a TryFrom implementation, your type automatically gets the corresponding TryInto implementation
 // hence can use try_into() instead of try_from()
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = InvalidSubscription;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+        let name =
+            SubscriberName::parse(value.name).map_err(|e| InvalidSubscription::field("name", e))?;
+        let email = SubscriberEmail::parse(value.email)
+            .map_err(|e| InvalidSubscription::field("email", e))?;
+        let language = value
+            .language
+            .filter(|language| !language.trim().is_empty())
+            .map(|language| Language::parse(&language))
+            .transpose()
+            .map_err(|message| InvalidSubscription {
+                field: Some("language"),
+                error: "invalid",
+                message,
+            })?;
+        Ok(Self {
+            email,
+            name,
+            language,
+        })
     }
 }
 
+/// Why a signup was turned away, as the extension members of the `400` problem.
+#[derive(Debug)]
+pub struct InvalidSubscription {
+    // The form field at fault, for forms to show the message next to it.
+    field: Option<&'static str>,
+    // `DomainError::code` for the name and email, `invalid` for everything else.
+    error: &'static str,
+    message: String,
+}
+
+impl InvalidSubscription {
+    fn field(field: &'static str, error: DomainError) -> Self {
+        Self {
+            field: Some(field),
+            error: error.code(),
+            message: error.to_string(),
+        }
+    }
+
+    fn problem(&self) -> ApiError {
+        let problem = ApiError::validation(&self.message).with_extension("error", self.error);
+        match self.field {
+            Some(field) => problem.with_extension("field", field),
+            None => problem,
+        }
+    }
+}
+
+impl From<String> for InvalidSubscription {
+    fn from(message: String) -> Self {
+        Self {
+            field: None,
+            error: "invalid",
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field {
+            Some(field) => write!(f, "Invalid {}: {}", field, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+const SUBSCRIPTION_TOKEN_LENGTH: usize = 25;
+/// How long a confirmation link works for. The token keeps identifying the subscriber
+/// afterwards, e.g. for their category preferences.
+pub(crate) const SUBSCRIPTION_TOKEN_LIFETIME: chrono::Duration = chrono::Duration::days(7);
+
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
-        .take(25)
+        .take(SUBSCRIPTION_TOKEN_LENGTH)
         .collect()
 }
 
+/// Hex-encoded SHA-256, as stored in `subscription_tokens.subscription_token_hash`.
+/// Matches `encode(sha256(...), 'hex')` in Postgres.
+pub fn hash_subscription_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Whether `token` could have been produced by `generate_subscription_token`.
+/// Lets us turn away garbage without a trip to the database.
+pub fn is_well_formed_subscription_token(token: &str) -> bool {
+    token.len() == SUBSCRIPTION_TOKEN_LENGTH && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Runs before the form is even parsed: signups over the limit never cost an email.
+pub async fn limit_subscription_attempts(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let rate_limiter = req.app_data::<Data<SubscriptionRateLimiter>>().cloned();
+    if let Some(rate_limiter) = rate_limiter
+        && let Some(response) = reject_over_limit(&rate_limiter.0, &req).await
+    {
+        tracing::warn!("Rejected a subscription attempt over the rate limit");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(
+        request,
+        form,
+        newsletter,
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        redirect_allowed_hosts,
+        subscriber_cap,
+        invite_only,
+        signup_fields,
+        resend_confirmation_rate_limiter,
+        signup_verifier
+    ),
     fields(
         subscriber_email = %form.email,
-        subscriber_name= %form.name
+        subscriber_name= %form.name,
+        newsletter = %newsletter.slug
     )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
-    form: Form<FormData>,
+    request: HttpRequest,
+    mut form: Form<FormData>,
+    newsletter: Newsletter,
     pool: Data<PgPool>,
     email_client: Data<EmailClient>,
-    base_url: Data<ApplicationBaseUrl>,
+    templates: Data<EmailTemplates>,
+    token_signer: Data<TokenSigner>,
+    pii_cipher: Data<PiiCipher>,
+    action_base_url: Data<ActionBaseUrl>,
+    redirect_allowed_hosts: Data<RedirectAllowedHosts>,
+    subscriber_cap: Data<SubscriberCap>,
+    invite_only: Data<InviteOnly>,
+    signup_fields: Data<SignupFields>,
+    resend_confirmation_rate_limiter: Data<ResendConfirmationRateLimiter>,
+    signup_verifier: Data<Option<SignupVerifier>>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let redirect_to = form
+        .0
+        .redirect_to
+        .take()
+        .map(|r| RedirectTarget::parse(r, &redirect_allowed_hosts.0))
+        .transpose()
+        .map_err(|e| SubscribeError::ValidationError(e.into()))?;
+    let invite_code = form
+        .0
+        .invite_code
+        .take()
+        .map(|code| normalise_invite_code(&code))
+        .filter(|code| !code.is_empty());
+    if invite_only.0 && invite_code.is_none() {
+        return Err(SubscribeError::ValidationError(
+            String::from("An invite code is required to subscribe.").into(),
+        ));
+    }
+    let attributes = SignupAttributes::parse(&form.attributes, &signup_fields.0)
+        .map_err(|e| SubscribeError::ValidationError(e.into()))?;
+    let tags = form
+        .0
+        .tags
+        .take()
+        .map(|tags| TagName::parse_list(&tags))
+        .transpose()
+        .map_err(|e| SubscribeError::ValidationError(e.into()))?
+        .unwrap_or_default();
+    let solution = (
+        form.0.captcha_response.take(),
+        form.0.pow_challenge.take(),
+        form.0.pow_nonce.take(),
+    );
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // After the other checks: a CAPTCHA can only be verified once, a form with a typo
+    // shouldn't need a new one.
+    if let Some(verifier) = signup_verifier.as_ref() {
+        let (captcha_response, challenge, nonce) = &solution;
+        verifier
+            .verify(&Solution {
+                captcha_response: captcha_response.as_deref(),
+                challenge: challenge.as_deref(),
+                nonce: nonce.as_deref(),
+            })
+            .await?;
+    }
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    if let Some(code) = &invite_code
+        && !redeem_invite(&mut transaction, code)
+            .await
+            .context("Failed to redeem the invite code.")?
+    {
+        return Err(SubscribeError::ValidationError(
+            String::from("The invite code is invalid, expired or used up.").into(),
+        ));
+    }
+    let waitlisted = is_at_capacity(&mut transaction, subscriber_cap.0)
+        .await
+        .context("Failed to check the subscriber cap.")?;
+    let status = if waitlisted {
+        "waitlisted"
+    } else {
+        "pending_confirmation"
+    };
+    let subscriber_id = match insert_subscriber(
+        &mut transaction,
+        &pii_cipher,
+        newsletter.id,
+        &new_subscriber,
+        status,
+        invite_code.as_deref(),
+        attributes,
+    )
+    .await
+    {
+        Ok(subscriber_id) => subscriber_id,
+        Err(SubscribeError::DuplicateSubscriber {
+            subscriber_id,
+            status,
+        }) => {
+            tracing::info!(%subscriber_id, status, "The email address is already subscribed");
+            // Signing up twice is harmless: rolling back gives the invite use back.
+            transaction
+                .rollback()
+                .await
+                .context("Failed to roll back the redemption of an invite.")?;
+            let mut transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            match status.as_str() {
+                "pending_confirmation" => {}
+                // Signing up again after unsubscribing takes a new confirmation.
+                "unsubscribed" => {
+                    resubscribe(&mut transaction, subscriber_id)
+                        .await
+                        .context("Failed to resubscribe an unsubscribed subscriber.")?;
+                    record(
+                        &mut *transaction,
+                        subscriber_id,
+                        SubscriberEvent::Subscribed,
+                        &Source::request(Actor::Subscriber, &request),
+                    )
+                    .await
+                    .context("Failed to record a resubscription.")?;
+                }
+                // Waitlisted subscribers get a link once they are admitted.
+                _ => return Ok(HttpResponse::Ok().finish()),
+            }
+            let response = resend_confirmation_email(
+                transaction,
+                &resend_confirmation_rate_limiter,
+                &pool,
+                &email_client,
+                &templates,
+                &token_signer,
+                &pii_cipher,
+                &action_base_url,
+                subscriber_id,
+            )
+            .await?;
+            return Ok(response);
+        }
+        Err(e) => return Err(e),
+    };
+    add_tags(&mut *transaction, subscriber_id, &tags)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to tag a new subscriber.")?;
+    record(
+        &mut *transaction,
+        subscriber_id,
+        SubscriberEvent::Subscribed,
+        &Source::request(Actor::Subscriber, &request),
+    )
+    .await
+    .context("Failed to record a new subscriber.")?;
+    if waitlisted {
+        // They get a confirmation link once they are admitted.
+        database::commit(transaction)
+            .await
+            .context("Failed to commit SQL transaction to store a new subscriber.")?;
+        send_waitlist_email(&email_client, &new_subscriber)
+            .await
+            .context("Failed to send a waitlist email.")?;
+        return Ok(HttpResponse::Ok().finish());
+    }
     let subscription_token = generate_subscription_token();
+    let subscription_token_hash = is_enabled(&mut *transaction, RuntimeFlag::WriteTokenHashes)
+        .await
+        .context("Failed to check a runtime flag.")?
+        .then(|| hash_subscription_token(&subscription_token));
 
     // store_token invokes 'Into' trait, so no need of map_err
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        subscription_token_hash.as_deref(),
+        redirect_to.as_ref(),
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
+    let outbox_id = enqueue_confirmation_email(&mut transaction, &subscription_token)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to enqueue the confirmation email of a new subscriber.")?;
 
-    transaction
-        .commit()
+    database::commit(transaction)
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
-    send_confirmation_email(
+    // The subscriber is stored: if the email can't be sent now, it will be later.
+    if let Err(error) = deliver_confirmation_email(
+        &pool,
         &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
+        &templates,
+        &token_signer,
+        &pii_cipher,
+        &action_base_url,
+        outbox_id,
     )
     .await
-    .context("Failed to send a confirmation email.")?;
+    {
+        tracing::warn!(
+            error.cause_chain = ?error,
+            "Failed to deliver a confirmation email, leaving it in the outbox"
+        );
+    }
 
     Ok(HttpResponse::Ok().finish())
 }
 
+/// The extra fields of the subscribe form, for embedded forms to render.
+pub async fn signup_fields_schema(
+    request: HttpRequest,
+    signup_fields: Data<SignupFields>,
+) -> HttpResponse {
+    http_cache::json(&request, &signup_fields.0, None, Audience::Public)
+}
+
+/// A proof-of-work challenge for the subscribe form to solve, when signups are verified
+/// that way.
+pub async fn signup_challenge(signup_verifier: Data<Option<SignupVerifier>>) -> HttpResponse {
+    match signup_verifier.as_ref() {
+        Some(SignupVerifier::ProofOfWork(proof_of_work)) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .json(proof_of_work.challenge()),
+        _ => ApiError::not_found("Signups are not verified with a proof of work.").error_response(),
+    }
+}
+
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber)
+    skip(
+        email_client,
+        templates,
+        token_signer,
+        new_subscriber,
+        subscription_token
+    )
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    newsletter_name: &str,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-    Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+        base_url,
+        token_signer.sign(TokenPurpose::Subscription, subscription_token)
     );
+    let email = ConfirmationEmail {
+        newsletter: newsletter_name,
+        name: new_subscriber.name.as_ref(),
+        confirmation_link: &confirmation_link,
+    };
+    let language = new_subscriber.language.as_ref();
+    let subject = templates.confirmation_subject(&email, language)?;
+    let body = templates.confirmation(&email, language)?;
 
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email(
+            &new_subscriber.email,
+            &subject,
+            &body.html,
+            &body.text,
+            MessageCategory::Transactional,
+        )
+        .await?;
+    Ok(())
 }
 
+/// Fails with `SubscribeError::DuplicateSubscriber` if the email address is already
+/// subscribed to the list, leaving the transaction usable.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
+    skip(new_subscriber, transaction, pii_cipher)
 )]
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
+    pii_cipher: &PiiCipher,
+    newsletter_id: Uuid,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+    status: &str,
+    invite_code: Option<&str>,
+    attributes: SignupAttributes,
+) -> Result<Uuid, SubscribeError> {
     let subscriber_id = Uuid::new_v4();
+    let email_index = pii_cipher.email_index(new_subscriber.email.as_ref());
 
-    sqlx::query!(
+    // The conflict can be on `email` or, with encrypted emails, on `email_blind_index`.
+    let inserted = sqlx::query!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, invite_code, attributes, email_blind_index, newsletter_id, language)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT DO NOTHING
         "#,
         subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        Utc::now()
+        pii_cipher.encrypt(new_subscriber.email.as_ref()),
+        pii_cipher.encrypt(new_subscriber.name.as_ref()),
+        Utc::now(),
+        status,
+        invite_code,
+        attributes.into_json(),
+        email_index,
+        newsletter_id,
+        new_subscriber.language.as_ref().map(AsRef::as_ref)
     )
     .execute(&mut **transaction)
     // The double dereference (**) gets us to the actual Transaction type, and then we take a mutable reference (&mut) to match the expected executor interface
     .await
-    .map_err(|e| {
-        // tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .context("Failed to insert new subscriber in the database.")?;
+    if inserted.rows_affected() == 1 {
+        return Ok(subscriber_id);
+    }
 
-    Ok(subscriber_id)
+    let existing = sqlx::query!(
+        r#"
+            SELECT id, status FROM subscriptions
+            WHERE newsletter_id = $3 AND (email = $1 OR email_blind_index = $2)
+              AND deleted_at IS NULL
+            FOR UPDATE
+        "#,
+        new_subscriber.email.as_ref(),
+        email_index,
+        newsletter_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look up the existing subscriber.")?
+    .context("A conflicting subscriber was deleted concurrently.")?;
+    Err(SubscribeError::DuplicateSubscriber {
+        subscriber_id: existing.id,
+        status: existing.status,
+    })
+}
+
+/// Moves an unsubscribed subscriber back to pending confirmation.
+#[tracing::instrument(name = "Resubscribing a subscriber", skip(transaction))]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation', subscribed_at = now()
+        WHERE id = $1 AND status = 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -158,12 +577,17 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    subscription_token_hash: Option<&str>,
+    redirect_to: Option<&RedirectTarget>,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, redirect_to, subscription_token_hash, expires_at)
+    VALUES ($1, $2, $3, $4, $5)"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        redirect_to.map(|r| r.as_ref()),
+        subscription_token_hash,
+        Utc::now() + SUBSCRIPTION_TOKEN_LIFETIME
     )
     .execute(&mut **transaction)
     .await
@@ -238,11 +662,27 @@ pub fn error_chain_fmt(
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    ValidationError(String),
+    ValidationError(InvalidSubscription),
+    #[error("The email address is already subscribed.")]
+    DuplicateSubscriber { subscriber_id: Uuid, status: String },
+    // The signup didn't prove it isn't a bot.
+    #[error("{0}")]
+    VerificationFailed(&'static str),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<VerificationError> for SubscribeError {
+    fn from(e: VerificationError) -> Self {
+        match e {
+            VerificationError::Rejected(reason) => SubscribeError::VerificationFailed(reason),
+            VerificationError::Unexpected(e) => SubscribeError::UnexpectedError(
+                e.context("Failed to verify that a signup isn't from a bot."),
+            ),
+        }
+    }
+}
+
 // // WITHOUT ANYHOW
 // #[derive(thiserror::Error)]
 // pub enum SubscribeError {
@@ -268,9 +708,26 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            // `subscribe` answers duplicates itself; this is for other callers.
+            SubscribeError::DuplicateSubscriber { .. } => StatusCode::CONFLICT,
+            SubscribeError::VerificationFailed(_) => StatusCode::BAD_REQUEST,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            SubscribeError::ValidationError(invalid) => invalid.problem(),
+            SubscribeError::DuplicateSubscriber { .. } => {
+                ApiError::new(self.status_code(), "duplicate-subscriber", self.to_string())
+            }
+            SubscribeError::VerificationFailed(reason) => {
+                ApiError::new(self.status_code(), "verification-failed", *reason)
+            }
+            SubscribeError::UnexpectedError(_) => ApiError::unexpected(),
+        };
+        error.error_response()
+    }
 }
 
 // // ---------------------------
@@ -1,62 +1,172 @@
-use actix_web::{HttpResponse, web};
+use crate::api_error::ApiError;
+use crate::audit_log::{Actor, Source, SubscriberEvent, record};
+use crate::domain::ActionBaseUrl;
+use crate::rate_limit::reject_over_limit;
+use crate::routes::{SubscriberToken, SubscriberTokenError, get_or_create_preference_token};
+use crate::signed_tokens::{TokenPurpose, TokenSigner};
+use crate::startup::ConfirmationRateLimiter;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ConnectionInfo, ServiceRequest, ServiceResponse};
+use actix_web::http::header::LOCATION;
+use actix_web::middleware::Next;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
 use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
-pub struct Parameters {
-    subscription_token: String,
+/// Confirmation attempts that were turned away since the process started,
+/// to spot someone trying to guess subscription tokens.
+#[derive(Default, serde::Serialize)]
+pub struct ConfirmationRejections {
+    rate_limited: AtomicU64,
+    malformed_token: AtomicU64,
+    unknown_token: AtomicU64,
+    expired_token: AtomicU64,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+impl ConfirmationRejections {
+    fn record(&self, counter: &AtomicU64, reason: &str) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(reason, "Rejected a confirmation attempt");
+    }
+}
 
-    match id {
-        // Non-existing token!
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+/// Runs before the subscription token is looked up, so that guesses over the limit
+/// never reach the database.
+pub async fn limit_confirmation_attempts(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let rate_limiter = req
+        .app_data::<web::Data<ConfirmationRateLimiter>>()
+        .cloned();
+    if let Some(rate_limiter) = rate_limiter
+        && let Some(response) = reject_over_limit(&rate_limiter.0, &req).await
+    {
+        if let Some(rejections) = req.app_data::<web::Data<ConfirmationRejections>>() {
+            rejections.record(&rejections.rate_limited, "rate limited");
         }
+        return Ok(req.into_response(response).map_into_right_body());
     }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id,
+/// What a confirmation answers with when it doesn't redirect.
+#[derive(serde::Serialize)]
+pub struct Confirmed {
+    // Signed, for the subscriber's preferences: the confirmation token is used up.
+    preference_token: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(
+        request,
+        token,
+        pool,
+        token_signer,
+        connection_info,
+        action_base_url,
+        rejections
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+)]
+pub async fn confirm(
+    request: HttpRequest,
+    token: Result<SubscriberToken, SubscriberTokenError>,
+    pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+    connection_info: ConnectionInfo,
+    action_base_url: web::Data<ActionBaseUrl>,
+    rejections: web::Data<ConfirmationRejections>,
+) -> HttpResponse {
+    // Confirmation links are only ever generated for the action domain.
+    if !action_base_url.matches_host(connection_info.host()) {
+        return ApiError::validation("Confirmation links are only valid on the action domain.")
+            .error_response();
+    }
+    let token = match token {
+        Ok(token) => token,
+        Err(error) => {
+            match &error {
+                SubscriberTokenError::MalformedToken => {
+                    rejections.record(&rejections.malformed_token, "malformed token")
+                }
+                SubscriberTokenError::UnknownToken => {
+                    rejections.record(&rejections.unknown_token, "unknown token")
+                }
+                _ => {}
+            }
+            return error.error_response();
+        }
+    };
+    if token.is_expired() {
+        rejections.record(&rejections.expired_token, "expired token");
+        return SubscriberTokenError::ExpiredToken.error_response();
+    }
 
-    Ok(())
+    let source = Source::request(Actor::Subscriber, &request);
+    if let Err(error) = confirm_subscriber(&pool, token.subscriber_id, &source).await {
+        tracing::error!(error.cause_chain = ?error, "Failed to confirm a subscriber");
+        return ApiError::unexpected().error_response();
+    }
+    match token.details.redirect_to {
+        // The target was checked against the allowlist when the subscriber signed up.
+        Some(redirect_to) => HttpResponse::SeeOther()
+            .insert_header((LOCATION, redirect_to))
+            .finish(),
+        None => match get_or_create_preference_token(&pool, token.subscriber_id).await {
+            Ok(preference_token) => HttpResponse::Ok().json(Confirmed {
+                preference_token: token_signer.sign(TokenPurpose::Preferences, &preference_token),
+            }),
+            Err(error) => {
+                tracing::error!(error.cause_chain = ?error, "Failed to get a preference token");
+                ApiError::unexpected().error_response()
+            }
+        },
+    }
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
+/// Deletes the subscriber's confirmation tokens, so that the link only works once: confirmed
+/// subscribers get a preference token instead. Only pending subscribers are confirmed, so
+/// that a link still in an inbox doesn't undo an unsubscription or a bounce.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
+pub async fn confirm_subscriber(
     pool: &PgPool,
-    subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token,
+    subscriber_id: Uuid,
+    source: &Source,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now())
+        WHERE id = $1 AND status = 'pending_confirmation' AND deleted_at IS NULL
+        "#,
+        subscriber_id,
     )
-    .fetch_optional(pool)
+    .execute(&mut *transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
-
-    Ok(result.map(|r| r.subscriber_id))
+    })?
+    .rows_affected();
+    if confirmed > 0 {
+        record(
+            &mut *transaction,
+            subscriber_id,
+            SubscriberEvent::Confirmed,
+            source,
+        )
+        .await?;
+    }
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
 }
@@ -0,0 +1,231 @@
+//! For pending subscribers whose confirmation email never arrived, e.g. because it landed in spam.
+use crate::confirmation_outbox::{deliver_confirmation_email, enqueue_confirmation_email};
+use crate::domain::{ActionBaseUrl, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::pii::PiiCipher;
+use crate::rate_limit::too_many_requests;
+use crate::routes::subscriptions::SUBSCRIPTION_TOKEN_LIFETIME;
+use crate::routes::{
+    Newsletter, error_chain_fmt, generate_subscription_token, hash_subscription_token,
+};
+use crate::runtime_flags::{RuntimeFlag, is_enabled};
+use crate::signed_tokens::TokenSigner;
+use crate::startup::ResendConfirmationRateLimiter;
+use crate::templates::EmailTemplates;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ResendConfirmationData {
+    email: String,
+}
+
+/// Addresses that don't belong to a pending subscriber get a `200` too, so that this
+/// can't be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(
+        form,
+        newsletter,
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        rate_limiter
+    ),
+    fields(
+        subscriber_email = %form.email,
+        newsletter = %newsletter.slug,
+        subscriber_id = tracing::field::Empty
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendConfirmationData>,
+    newsletter: Newsletter,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    token_signer: web::Data<TokenSigner>,
+    pii_cipher: web::Data<PiiCipher>,
+    action_base_url: web::Data<ActionBaseUrl>,
+    rate_limiter: web::Data<ResendConfirmationRateLimiter>,
+) -> Result<HttpResponse, ResendConfirmationError> {
+    let email = SubscriberEmail::parse(form.0.email)
+        .map_err(|e| ResendConfirmationError::ValidationError(e.to_string()))?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(subscriber_id) =
+        get_pending_subscriber(&mut transaction, &pii_cipher, newsletter.id, &email)
+            .await
+            .context("Failed to look up the pending subscriber.")?
+    else {
+        return Ok(HttpResponse::Ok().finish());
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    let response = resend_confirmation_email(
+        transaction,
+        &rate_limiter,
+        &pool,
+        &email_client,
+        &templates,
+        &token_signer,
+        &pii_cipher,
+        &action_base_url,
+        subscriber_id,
+    )
+    .await?;
+    Ok(response)
+}
+
+/// Rotates the confirmation token of a pending subscriber and emails them the new link,
+/// committing `transaction`. A `429` instead if they are over their limit.
+///
+/// Rotating the token drops the confirmation emails still in the outbox with the previous
+/// one: only the new link is sent.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn resend_confirmation_email(
+    mut transaction: Transaction<'static, Postgres>,
+    rate_limiter: &ResendConfirmationRateLimiter,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &ActionBaseUrl,
+    subscriber_id: Uuid,
+) -> Result<HttpResponse, anyhow::Error> {
+    // Per subscriber on top of the per-IP limit, so that nobody gets flooded with
+    // confirmation emails from many addresses.
+    if let Err(retry_after) = rate_limiter.0.check(subscriber_id).await {
+        tracing::warn!(%subscriber_id, "Rejected a confirmation resend over the rate limit");
+        return Ok(too_many_requests(retry_after));
+    }
+    let subscription_token = generate_subscription_token();
+    let subscription_token_hash = is_enabled(&mut *transaction, RuntimeFlag::WriteTokenHashes)
+        .await
+        .context("Failed to check a runtime flag.")?
+        .then(|| hash_subscription_token(&subscription_token));
+    rotate_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        subscription_token_hash.as_deref(),
+    )
+    .await
+    .context("Failed to rotate the confirmation token of a pending subscriber.")?;
+    let outbox_id = enqueue_confirmation_email(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to enqueue a confirmation email.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to rotate a confirmation token.")?;
+
+    if let Err(error) = deliver_confirmation_email(
+        pool,
+        email_client,
+        templates,
+        token_signer,
+        pii_cipher,
+        action_base_url,
+        outbox_id,
+    )
+    .await
+    {
+        tracing::warn!(
+            error.cause_chain = ?error,
+            "Failed to deliver a confirmation email, leaving it in the outbox"
+        );
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Get a pending subscriber by email",
+    skip(transaction, pii_cipher, email)
+)]
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    pii_cipher: &PiiCipher,
+    newsletter_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    // Encrypted emails can only be found through their blind index.
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE (email = $1 OR email_blind_index = $2)
+          AND newsletter_id = $3
+          AND status = 'pending_confirmation'
+          AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+        pii_cipher.email_index(email.as_ref()),
+        newsletter_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// Replaces the confirmation tokens of a subscriber with `subscription_token`, so that links
+/// from earlier emails stop working. Where they'd redirect to after confirming is kept.
+#[tracing::instrument(
+    name = "Rotate the confirmation token of a pending subscriber",
+    skip(transaction, subscription_token, subscription_token_hash)
+)]
+async fn rotate_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    subscription_token_hash: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH previous AS (
+            DELETE FROM subscription_tokens WHERE subscriber_id = $1 RETURNING redirect_to
+        )
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, redirect_to, subscription_token_hash, expires_at)
+        VALUES ($2, $1, (SELECT MAX(redirect_to) FROM previous), $3, $4)
+        "#,
+        subscriber_id,
+        subscription_token,
+        subscription_token_hash,
+        Utc::now() + SUBSCRIPTION_TOKEN_LIFETIME,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum ResendConfirmationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ResendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResendConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ResendConfirmationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ResendConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
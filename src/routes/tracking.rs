@@ -0,0 +1,113 @@
+//! Where the tracked links and open pixel of issues lead: see `crate::tracking`.
+use crate::crypto::KeyRing;
+use crate::routes::{TrackingParameters, error_chain_fmt, tracking_pixel};
+use crate::tracking::{
+    TRACKED_CLICK_SIGNATURE, TRACKED_OPEN_SIGNATURE, click_message, open_message,
+};
+use actix_web::http::StatusCode;
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Track a click", skip(parameters, pool, key_ring))]
+pub async fn track_click(
+    path: web::Path<(Uuid, Uuid, i32)>,
+    parameters: web::Query<TrackingParameters>,
+    pool: web::Data<PgPool>,
+    key_ring: web::Data<KeyRing>,
+) -> Result<HttpResponse, TrackingError> {
+    let (newsletter_issue_id, subscriber_id, link_id) = path.into_inner();
+    let url = sqlx::query_scalar!(
+        r#"SELECT url FROM newsletter_issue_links WHERE newsletter_issue_id = $1 AND link_id = $2"#,
+        newsletter_issue_id,
+        link_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the link from the database.")?
+    .ok_or(TrackingError::NotFound)?;
+    // Readers following a link that wasn't signed by us still get where it goes,
+    // the click just doesn't count.
+    let message = click_message(newsletter_issue_id, subscriber_id, link_id);
+    if parameters.is_valid_for(TRACKED_CLICK_SIGNATURE, message.as_bytes(), &key_ring) {
+        record_event(&pool, newsletter_issue_id, subscriber_id, Some(link_id)).await;
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .finish())
+}
+
+#[tracing::instrument(name = "Track an open", skip(parameters, pool, key_ring))]
+pub async fn track_open(
+    path: web::Path<(Uuid, Uuid)>,
+    parameters: web::Query<TrackingParameters>,
+    pool: web::Data<PgPool>,
+    key_ring: web::Data<KeyRing>,
+) -> HttpResponse {
+    let (newsletter_issue_id, subscriber_id) = path.into_inner();
+    let message = open_message(newsletter_issue_id, subscriber_id);
+    if parameters.is_valid_for(TRACKED_OPEN_SIGNATURE, message.as_bytes(), &key_ring) {
+        record_event(&pool, newsletter_issue_id, subscriber_id, None).await;
+    }
+    tracking_pixel()
+}
+
+/// A click if there is a `link_id`, an open otherwise. Nothing is recorded once the list
+/// has stopped tracking, even for issues sent while it did, nor for deleted subscribers, nor
+/// for issues whose events were compacted into their statistics. Failures are only logged: they must not get in the way of the reader.
+async fn record_event(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    link_id: Option<i32>,
+) {
+    let kind = if link_id.is_some() { "click" } else { "open" };
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO tracking_events
+            (id, newsletter_issue_id, subscriber_id, kind, link_id, occurred_at)
+        SELECT $1::uuid, $2::uuid, $3::uuid, $4::text, $5::int4, now()
+        WHERE EXISTS (
+            SELECT 1 FROM newsletter_issues i
+            JOIN newsletters n ON n.id = i.newsletter_id
+            WHERE i.newsletter_issue_id = $2 AND n.tracking_enabled
+        ) AND EXISTS (SELECT 1 FROM subscriptions WHERE id = $3 AND deleted_at IS NULL)
+          AND NOT EXISTS (SELECT 1 FROM issue_statistics WHERE newsletter_issue_id = $2)
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber_id,
+        kind,
+        link_id
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::warn!(error.cause_chain = ?e, "Failed to record the {kind} of an issue.");
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("The link does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::NotFound => StatusCode::NOT_FOUND,
+            TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
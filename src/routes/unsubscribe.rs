@@ -0,0 +1,125 @@
+use crate::audit_log::{Actor, Source, SubscriberEvent, record};
+use crate::domain::Language;
+use crate::routes::{
+    SubscriberToken, SubscriberTokenError, UnsubscribeKind, error_chain_fmt,
+    generate_subscription_token,
+};
+use crate::templates::{EmailTemplates, UnsubscribedPage};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Following the link again once unsubscribed is fine: it just succeeds again.
+/// Answers with a confirmation page in the subscriber's language.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip_all)]
+pub async fn unsubscribe(
+    request: HttpRequest,
+    token: SubscriberToken<UnsubscribeKind>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let unsubscribed = sqlx::query!(
+        r#"
+        UPDATE subscriptions s SET
+            status = 'unsubscribed',
+            -- Following the link again doesn't move the date.
+            unsubscribed_at = CASE WHEN s.status = 'unsubscribed' THEN s.unsubscribed_at ELSE now() END
+        FROM (SELECT id, status FROM subscriptions WHERE id = $1 FOR UPDATE) previous
+        WHERE s.id = previous.id AND s.deleted_at IS NULL
+        RETURNING s.id, previous.status AS previous_status, s.language,
+            (SELECT n.name FROM newsletters n WHERE n.id = s.newsletter_id) AS "newsletter!"
+        "#,
+        token.subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe the subscriber.")?
+    // Deleted since the token was looked up.
+    .ok_or(SubscriberTokenError::UnknownToken)?;
+    if unsubscribed.previous_status != "unsubscribed" {
+        record(
+            &mut *transaction,
+            unsubscribed.id,
+            SubscriberEvent::Unsubscribed,
+            &Source::request(Actor::Subscriber, &request),
+        )
+        .await
+        .context("Failed to record an unsubscription.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+    let language = unsubscribed
+        .language
+        .as_deref()
+        .and_then(|l| Language::parse(l).ok());
+    let page = templates
+        .unsubscribed_page(
+            &UnsubscribedPage {
+                newsletter: &unsubscribed.newsletter,
+            },
+            language.as_ref(),
+        )
+        .context("Failed to render the unsubscribe confirmation page.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// The subscriber's unsubscribe token, handed out on the first issue they get.
+/// Links carry it signed: see `TokenSigner`.
+#[tracing::instrument(name = "Get an unsubscribe token", skip(pool))]
+pub async fn get_or_create_unsubscribe_token(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    // The SELECT doesn't see the row the INSERT adds: one of the two returns the token.
+    sqlx::query_scalar!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO unsubscribe_tokens (subscriber_id, unsubscribe_token, created_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT (subscriber_id) DO NOTHING
+            RETURNING unsubscribe_token
+        )
+        SELECT unsubscribe_token AS "unsubscribe_token!" FROM inserted
+        UNION ALL
+        SELECT unsubscribe_token FROM unsubscribe_tokens WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        generate_subscription_token()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    Token(#[from] SubscriberTokenError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::Token(e) => e.status_code(),
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
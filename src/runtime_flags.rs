@@ -0,0 +1,85 @@
+//! Switches that are flipped at runtime rather than at deploy time.
+//!
+//! They let a schema change roll out while old and new versions of the application
+//! run side by side: new columns are only written, then only read, once every running
+//! instance knows about them.
+use sqlx::{PgExecutor, PgPool};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuntimeFlag {
+    // Store a hash alongside every new subscription token.
+    WriteTokenHashes,
+    // Look subscription tokens up by their hash rather than by their plaintext.
+    ReadTokenHashes,
+}
+
+impl RuntimeFlag {
+    pub const ALL: [RuntimeFlag; 2] = [RuntimeFlag::WriteTokenHashes, RuntimeFlag::ReadTokenHashes];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeFlag::WriteTokenHashes => "write_token_hashes",
+            RuntimeFlag::ReadTokenHashes => "read_token_hashes",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+}
+
+/// Flags are off until they are turned on.
+#[tracing::instrument(name = "Check a runtime flag", skip(executor))]
+pub async fn is_enabled(
+    executor: impl PgExecutor<'_>,
+    flag: RuntimeFlag,
+) -> Result<bool, sqlx::Error> {
+    let enabled = sqlx::query_scalar!(
+        r#"SELECT enabled FROM runtime_flags WHERE name = $1"#,
+        flag.name()
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(enabled.unwrap_or(false))
+}
+
+pub async fn enabled_flags(pool: &PgPool) -> Result<Vec<RuntimeFlag>, sqlx::Error> {
+    let names = sqlx::query_scalar!(r#"SELECT name FROM runtime_flags WHERE enabled"#)
+        .fetch_all(pool)
+        .await?;
+    Ok(names
+        .iter()
+        .filter_map(|name| RuntimeFlag::parse(name))
+        .collect())
+}
+
+pub async fn set_flag(
+    executor: impl PgExecutor<'_>,
+    flag: RuntimeFlag,
+    enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO runtime_flags (name, enabled, updated_at) VALUES ($1, $2, now())
+        ON CONFLICT (name) DO UPDATE SET enabled = $2, updated_at = now()
+        "#,
+        flag.name(),
+        enabled
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeFlag;
+
+    #[test]
+    fn flags_round_trip_through_their_names() {
+        for flag in RuntimeFlag::ALL {
+            assert_eq!(RuntimeFlag::parse(flag.name()), Some(flag));
+        }
+        assert_eq!(RuntimeFlag::parse("not_a_flag"), None);
+    }
+}
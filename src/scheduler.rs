@@ -0,0 +1,307 @@
+//! Periodic background work, run on the cron schedules in `scheduler.jobs`.
+//!
+//! Every instance runs the scheduler: a Postgres advisory lock per job makes sure that only
+//! one of them runs a given job at a time, the others skip that run. Jobs that aren't
+//! `exclusive` coordinate on their own and run everywhere.
+use crate::configuration::{SchedulerSettings, Settings};
+use crate::confirmation_outbox::ConfirmationOutbox;
+use crate::confirmation_reminders::ConfirmationReminders;
+use crate::email_client::EmailClient;
+use crate::issue_compaction::IssueCompaction;
+use crate::issue_delivery::IssueDeliveryQueue;
+use crate::panics::catch_worker_panic;
+use crate::repermission_campaigns::RepermissionCampaigns;
+use crate::session::SessionCleanup;
+use crate::shutdown::Shutdown;
+use crate::token_cleanup::TokenCleanup;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use futures_util::future::join_all;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Every job the scheduler knows about, whether or not it is enabled.
+pub const JOB_NAMES: &[&str] = &[
+    "confirmation_outbox",
+    "confirmation_reminders",
+    "issue_compaction",
+    "issue_delivery",
+    "repermission_campaigns",
+    "session_cleanup",
+    "token_cleanup",
+];
+
+#[async_trait::async_trait]
+pub trait Job: Send + Sync {
+    /// One of `JOB_NAMES`.
+    fn name(&self) -> &'static str;
+
+    /// Whether only one instance may run the job at a time. Jobs that lease their work item
+    /// by item can run on every instance at once.
+    fn exclusive(&self) -> bool {
+        true
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+    Panicked,
+    // Another instance held the job's lock.
+    Skipped,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct JobStatus {
+    schedule: String,
+    next_run_at: Option<DateTime<Utc>>,
+    last_run_at: Option<DateTime<Utc>>,
+    last_outcome: Option<JobOutcome>,
+}
+
+// Kept in memory, like panic counts: each instance reports on its own runs.
+static JOB_STATUSES: Mutex<BTreeMap<&'static str, JobStatus>> = Mutex::new(BTreeMap::new());
+
+/// Status of the jobs scheduled by this instance, by job name.
+pub fn job_statuses() -> BTreeMap<&'static str, JobStatus> {
+    JOB_STATUSES.lock().unwrap().clone()
+}
+
+fn update_status(name: &'static str, update: impl FnOnce(&mut JobStatus)) {
+    if let Some(status) = JOB_STATUSES.lock().unwrap().get_mut(name) {
+        update(status);
+    }
+}
+
+struct ScheduledJob {
+    job: Box<dyn Job>,
+    schedule: Schedule,
+}
+
+pub struct Scheduler {
+    pool: PgPool,
+    jobs: Vec<ScheduledJob>,
+}
+
+impl Scheduler {
+    /// Jobs without a schedule are left out. A schedule for a job that doesn't exist,
+    /// or that doesn't parse, is a configuration error.
+    pub fn new(
+        pool: PgPool,
+        settings: &SchedulerSettings,
+        jobs: Vec<Box<dyn Job>>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut schedules = parse_schedules(settings).map_err(anyhow::Error::msg)?;
+        let mut scheduled = Vec::new();
+        for job in jobs {
+            match schedules.remove(job.name()) {
+                Some((expression, schedule)) => {
+                    JOB_STATUSES.lock().unwrap().insert(
+                        job.name(),
+                        JobStatus {
+                            schedule: expression,
+                            next_run_at: None,
+                            last_run_at: None,
+                            last_outcome: None,
+                        },
+                    );
+                    scheduled.push(ScheduledJob { job, schedule });
+                }
+                None => tracing::info!(job = job.name(), "The job has no schedule: not running it"),
+            }
+        }
+        Ok(Self {
+            pool,
+            jobs: scheduled,
+        })
+    }
+
+    /// Every enabled job, on the schedules in the configuration. The jobs share `pool` and
+    /// `email_client` with the application, so that they count against the same limits.
+    pub fn from_configuration(
+        configuration: &Settings,
+        pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut jobs: Vec<Box<dyn Job>> = vec![Box::new(ConfirmationOutbox::from_configuration(
+            configuration,
+            pool.clone(),
+            email_client.clone(),
+        )?)];
+        if let Some(reminders) = ConfirmationReminders::from_configuration(
+            configuration,
+            pool.clone(),
+            email_client.clone(),
+        )? {
+            jobs.push(Box::new(reminders));
+        }
+        jobs.push(Box::new(IssueDeliveryQueue::from_configuration(
+            configuration,
+            pool.clone(),
+            email_client.clone(),
+        )?));
+        jobs.push(Box::new(RepermissionCampaigns::from_configuration(
+            configuration,
+            pool.clone(),
+            email_client,
+        )?));
+        if let Some(cleanup) = TokenCleanup::from_configuration(configuration, pool.clone()) {
+            jobs.push(Box::new(cleanup));
+        }
+        if let Some(cleanup) = SessionCleanup::from_configuration(configuration, pool.clone()) {
+            jobs.push(Box::new(cleanup));
+        }
+        if let Some(compaction) = IssueCompaction::from_configuration(configuration, pool.clone()) {
+            jobs.push(Box::new(compaction));
+        }
+        Self::new(pool, &configuration.scheduler, jobs)
+    }
+
+    /// Returns once shutdown is requested and the jobs that were running have finished,
+    /// or earlier if every schedule runs out.
+    pub async fn run_until_shutdown(self, shutdown: Shutdown) {
+        join_all(
+            self.jobs
+                .iter()
+                .map(|scheduled| run_on_schedule(&self.pool, scheduled, shutdown.clone())),
+        )
+        .await;
+    }
+}
+
+async fn run_on_schedule(pool: &PgPool, scheduled: &ScheduledJob, mut shutdown: Shutdown) {
+    let name = scheduled.job.name();
+    // Asked afresh after every run, so that a run overlapping the next slot doesn't queue it up.
+    while let Some(next_run_at) = scheduled.schedule.upcoming(Utc).next() {
+        update_status(name, |status| status.next_run_at = Some(next_run_at));
+        let delay = (next_run_at - Utc::now()).to_std().unwrap_or_default();
+        // A run that has started is never cut short: it is waited for on shutdown.
+        tokio::select! {
+            biased;
+            _ = shutdown.requested() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        let outcome = match run_job_once(pool, scheduled.job.as_ref()).await {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                tracing::error!(error.cause_chain = ?error, job = name, "Failed to run a scheduled job");
+                JobOutcome::Failed
+            }
+        };
+        update_status(name, |status| {
+            status.last_run_at = Some(next_run_at);
+            status.last_outcome = Some(outcome);
+        });
+    }
+    update_status(name, |status| status.next_run_at = None);
+}
+
+/// Run `job` unless it is `exclusive` and another instance is already running it.
+#[tracing::instrument(name = "Run a scheduled job", skip_all, fields(job = job.name()))]
+pub async fn run_job_once(pool: &PgPool, job: &dyn Job) -> Result<JobOutcome, anyhow::Error> {
+    if !job.exclusive() {
+        return Ok(run_job(job).await);
+    }
+    // Advisory locks belong to the session that took them: the same connection has to release it.
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let key = lock_key(job.name());
+    let locked = sqlx::query_scalar!(r#"SELECT pg_try_advisory_lock($1) AS "locked!""#, key)
+        .fetch_one(&mut *connection)
+        .await
+        .context("Failed to take the job's advisory lock.")?;
+    if !locked {
+        return Ok(JobOutcome::Skipped);
+    }
+
+    let outcome = run_job(job).await;
+
+    let unlocked = sqlx::query_scalar!(r#"SELECT pg_advisory_unlock($1) AS "unlocked!""#, key)
+        .fetch_one(&mut *connection)
+        .await;
+    if let Err(error) = unlocked {
+        // Closing the session releases the lock, rather than handing it back to the pool.
+        drop(connection.detach());
+        return Err(anyhow::Error::new(error).context("Failed to release the job's advisory lock."));
+    }
+    Ok(outcome)
+}
+
+async fn run_job(job: &dyn Job) -> JobOutcome {
+    match catch_worker_panic(job.name(), job.run()).await {
+        Some(Ok(())) => JobOutcome::Succeeded,
+        Some(Err(error)) => {
+            tracing::error!(error.cause_chain = ?error, "A scheduled job failed");
+            JobOutcome::Failed
+        }
+        None => JobOutcome::Panicked,
+    }
+}
+
+/// The advisory lock taken while `job_name` runs.
+pub fn lock_key(job_name: &str) -> i64 {
+    let digest = Sha256::digest(format!("zero2prod.scheduler.{job_name}"));
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn parse_schedules(
+    settings: &SchedulerSettings,
+) -> Result<BTreeMap<String, (String, Schedule)>, String> {
+    settings
+        .jobs
+        .iter()
+        .map(|(name, expression)| {
+            if !JOB_NAMES.contains(&name.as_str()) {
+                return Err(format!("There is no scheduled job named {name}."));
+            }
+            let schedule = Schedule::from_str(expression).map_err(|e| {
+                format!("{expression:?} is not a valid cron expression for {name}: {e}")
+            })?;
+            Ok((name.clone(), (expression.clone(), schedule)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_schedules;
+    use crate::configuration::SchedulerSettings;
+    use claim::{assert_err, assert_ok};
+
+    fn settings(name: &str, expression: &str) -> SchedulerSettings {
+        SchedulerSettings {
+            jobs: [(name.to_string(), expression.to_string())].into(),
+        }
+    }
+
+    #[test]
+    fn known_jobs_with_valid_expressions_are_scheduled() {
+        assert_ok!(parse_schedules(&settings(
+            "confirmation_reminders",
+            "0 */10 * * * *"
+        )));
+    }
+
+    #[test]
+    fn unknown_jobs_are_rejected() {
+        assert_err!(parse_schedules(&settings("weekly_digest", "0 0 * * * *")));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        assert_err!(parse_schedules(&settings(
+            "confirmation_reminders",
+            "every ten minutes"
+        )));
+    }
+}
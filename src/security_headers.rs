@@ -0,0 +1,69 @@
+//! Headers that harden every response: `X-Content-Type-Options: nosniff`, HSTS when the
+//! application is served over https, and a `Content-Security-Policy` on the admin pages.
+//! Headers a handler set itself are left alone.
+use crate::configuration::SecurityHeaderSettings;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+
+// The pages publishers use, whose forms must only ever post back here.
+const ADMIN_PAGES: &[&str] = &["/admin", "/login", "/password_reset"];
+
+pub struct SecurityHeaders {
+    // `None` over plain http, where browsers ignore it.
+    strict_transport_security: Option<HeaderValue>,
+    admin_content_security_policy: HeaderValue,
+}
+
+impl SecurityHeaders {
+    pub fn new(settings: &SecurityHeaderSettings, base_url: &str) -> Result<Self, String> {
+        let strict_transport_security = (base_url.starts_with("https://")
+            && settings.hsts_max_age_seconds > 0)
+            .then(|| HeaderValue::from_str(&format!("max-age={}", settings.hsts_max_age_seconds)))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let admin_content_security_policy =
+            HeaderValue::from_str(&settings.admin_content_security_policy)
+                .map_err(|_| "The admin Content-Security-Policy is not a valid header value.")?;
+        Ok(Self {
+            strict_transport_security,
+            admin_content_security_policy,
+        })
+    }
+}
+
+pub async fn set_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let security_headers = req.app_data::<web::Data<SecurityHeaders>>().cloned();
+    let path = req.match_info().as_str();
+    let is_admin_page = ADMIN_PAGES
+        .iter()
+        .any(|page| path == *page || path.starts_with(&format!("{page}/")));
+    let mut response = next.call(req).await?;
+    let headers = response.headers_mut();
+    let mut set = |name, value: HeaderValue| {
+        if !headers.contains_key(&name) {
+            headers.insert(name, value);
+        }
+    };
+    set(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if let Some(security_headers) = security_headers {
+        if let Some(value) = &security_headers.strict_transport_security {
+            set(header::STRICT_TRANSPORT_SECURITY, value.clone());
+        }
+        if is_admin_page {
+            set(
+                header::CONTENT_SECURITY_POLICY,
+                security_headers.admin_content_security_policy.clone(),
+            );
+        }
+    }
+    Ok(response)
+}
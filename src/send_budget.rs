@@ -0,0 +1,109 @@
+//! A global budget of issue deliveries, for providers that cap how many emails go out per
+//! hour. It is a token bucket kept in Postgres: every instance draws from the same one, and
+//! it survives restarts, so that a restart doesn't hand out a fresh hour's worth.
+use crate::database;
+use sqlx::PgPool;
+
+/// At most `messages_per_hour` issue deliveries, refilled continuously. Up to `burst` can go
+/// out at once after a quiet spell: a minute's worth by default. Confirmation and other
+/// transactional emails don't wait for the budget, so leave them some room below the cap.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SendBudgetSettings {
+    pub messages_per_hour: u32,
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl SendBudgetSettings {
+    fn capacity(&self) -> f64 {
+        self.burst.unwrap_or(self.messages_per_hour / 60).max(1) as f64
+    }
+
+    /// The tokens in a bucket that had `tokens` `elapsed_seconds` ago.
+    fn refill(&self, tokens: f64, elapsed_seconds: f64) -> f64 {
+        let refilled = elapsed_seconds.max(0.0) * self.messages_per_hour as f64 / 3600.0;
+        (tokens + refilled).min(self.capacity())
+    }
+}
+
+/// Takes up to `wanted` tokens from the budget, returning how many it got: the deliveries
+/// that can go out now.
+#[tracing::instrument(name = "Take from the send budget", skip(pool, settings))]
+pub async fn take(
+    pool: &PgPool,
+    settings: &SendBudgetSettings,
+    wanted: usize,
+) -> Result<usize, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO send_budget (id, tokens, refilled_at) VALUES (TRUE, $1, now())
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        settings.capacity()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // `now()` is when the transaction started: one that waited for the lock can find the
+    // bucket refilled after that, which `refill` treats as no time having passed.
+    let bucket = sqlx::query!(
+        r#"
+        SELECT tokens, EXTRACT(EPOCH FROM now() - refilled_at)::float8 AS "elapsed_seconds!"
+        FROM send_budget
+        FOR UPDATE
+        "#
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let available = settings.refill(bucket.tokens, bucket.elapsed_seconds);
+    let granted = (available.floor().max(0.0) as usize).min(wanted);
+    sqlx::query!(
+        r#"
+        UPDATE send_budget SET tokens = $1, refilled_at = GREATEST(refilled_at, now())
+        "#,
+        available - granted as f64
+    )
+    .execute(&mut *transaction)
+    .await?;
+    database::commit(transaction).await?;
+    Ok(granted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendBudgetSettings;
+
+    fn settings(messages_per_hour: u32, burst: Option<u32>) -> SendBudgetSettings {
+        SendBudgetSettings {
+            messages_per_hour,
+            burst,
+        }
+    }
+
+    #[test]
+    fn the_burst_defaults_to_a_minute_worth_of_messages() {
+        assert_eq!(settings(300, None).capacity(), 5.0);
+        assert_eq!(settings(30, None).capacity(), 1.0);
+        assert_eq!(settings(300, Some(50)).capacity(), 50.0);
+    }
+
+    #[test]
+    fn the_bucket_refills_at_the_hourly_rate() {
+        let settings = settings(300, Some(300));
+        assert_eq!(settings.refill(0.0, 60.0), 5.0);
+        assert_eq!(settings.refill(0.5, 12.0), 1.5);
+    }
+
+    #[test]
+    fn the_bucket_never_holds_more_than_the_burst() {
+        let settings = settings(300, None);
+        assert_eq!(settings.refill(0.0, 3600.0), 5.0);
+        // A burst lowered since the bucket was last refilled.
+        assert_eq!(settings.refill(20.0, 0.0), 5.0);
+    }
+
+    #[test]
+    fn a_refill_from_the_future_adds_nothing() {
+        assert_eq!(settings(300, None).refill(2.0, -3.0), 2.0);
+    }
+}
@@ -0,0 +1,401 @@
+//! Admin sessions, kept where `session_store` says: in the shared cache by default, so that
+//! every replica sees them when it's Redis, or in Postgres for deployments without Redis.
+use crate::cache::Cache;
+use crate::configuration::{SessionStoreBackend, Settings};
+use crate::scheduler::Job;
+use actix_session::storage::{
+    LoadError, SaveError, SessionKey, SessionStore, UpdateError, generate_session_key,
+};
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::cookie::time;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use sqlx::types::Json;
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+type SessionState = HashMap<String, String>;
+
+#[derive(Clone)]
+pub struct CacheSessionStore {
+    cache: Arc<dyn Cache>,
+}
+
+impl CacheSessionStore {
+    pub fn new(cache: Arc<dyn Cache>) -> Self {
+        Self { cache }
+    }
+
+    async fn store(
+        &self,
+        session_key: &SessionKey,
+        session_state: &SessionState,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        let value = serde_json::to_string(session_state)?;
+        self.cache
+            .set(&cache_key(session_key), &value, to_std(ttl))
+            .await
+    }
+}
+
+impl SessionStore for CacheSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let Some(value) = self
+            .cache
+            .get(&cache_key(session_key))
+            .await
+            .map_err(LoadError::Other)?
+        else {
+            return Ok(None);
+        };
+        serde_json::from_str(&value)
+            .map(Some)
+            .map_err(|e| LoadError::Deserialization(e.into()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.store(&session_key, &session_state, ttl)
+            .await
+            .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.store(&session_key, &session_state, ttl)
+            .await
+            .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        // The cache can't touch an entry's expiry on its own: write it back instead.
+        let cache_key = cache_key(session_key);
+        if let Some(value) = self.cache.get(&cache_key).await? {
+            self.cache.set(&cache_key, &value, to_std(ttl)).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.cache.delete(&cache_key(session_key)).await
+    }
+}
+
+fn cache_key(session_key: &SessionKey) -> String {
+    format!("session:{}", session_key.as_ref())
+}
+
+fn to_std(ttl: &time::Duration) -> Duration {
+    Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+/// Sessions in the `admin_sessions` table. Expired ones are ignored until the
+/// `session_cleanup` job deletes them.
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn store(
+        &self,
+        session_key: &SessionKey,
+        session_state: &SessionState,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO admin_sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (session_key)
+            DO UPDATE SET state = EXCLUDED.state, expires_at = EXCLUDED.expires_at
+            "#,
+            session_key.as_ref(),
+            Json(session_state) as _,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to store an admin session.")?;
+        Ok(())
+    }
+}
+
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let state = sqlx::query_scalar!(
+            r#"
+            SELECT state AS "state: Json<SessionState>"
+            FROM admin_sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load an admin session.")
+        .map_err(LoadError::Other)?;
+        Ok(state.map(|state| state.0))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.store(&session_key, &session_state, ttl)
+            .await
+            .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.store(&session_key, &session_state, ttl)
+            .await
+            .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "UPDATE admin_sessions SET expires_at = $2 WHERE session_key = $1",
+            session_key.as_ref(),
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to extend an admin session.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "DELETE FROM admin_sessions WHERE session_key = $1",
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete an admin session.")?;
+        Ok(())
+    }
+}
+
+fn expires_at(ttl: &time::Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+/// The store the session middleware is built with.
+#[derive(Clone)]
+pub enum AdminSessionStore {
+    Cache(CacheSessionStore),
+    Postgres(PostgresSessionStore),
+}
+
+impl SessionStore for AdminSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            AdminSessionStore::Cache(store) => store.load(session_key).await,
+            AdminSessionStore::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            AdminSessionStore::Cache(store) => store.save(session_state, ttl).await,
+            AdminSessionStore::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &time::Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            AdminSessionStore::Cache(store) => store.update(session_key, session_state, ttl).await,
+            AdminSessionStore::Postgres(store) => {
+                store.update(session_key, session_state, ttl).await
+            }
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            AdminSessionStore::Cache(store) => store.update_ttl(session_key, ttl).await,
+            AdminSessionStore::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            AdminSessionStore::Cache(store) => store.delete(session_key).await,
+            AdminSessionStore::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// Deletes the expired sessions of the Postgres store, as a scheduled job.
+pub struct SessionCleanup {
+    pool: PgPool,
+}
+
+impl SessionCleanup {
+    /// `None` unless sessions are kept in Postgres: the cache expires its own entries.
+    pub fn from_configuration(configuration: &Settings, pool: PgPool) -> Option<Self> {
+        matches!(configuration.session_store, SessionStoreBackend::Postgres)
+            .then_some(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for SessionCleanup {
+    fn name(&self) -> &'static str {
+        "session_cleanup"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        let deleted = delete_expired_sessions(&self.pool).await?;
+        tracing::info!(deleted, "Deleted expired admin sessions");
+        Ok(())
+    }
+}
+
+/// Returns how many sessions were deleted.
+#[tracing::instrument(name = "Delete expired admin sessions", skip_all)]
+pub async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let deleted = sqlx::query!("DELETE FROM admin_sessions WHERE expires_at <= now()")
+        .execute(pool)
+        .await
+        .context("Failed to delete expired admin sessions.")?
+        .rows_affected();
+    Ok(deleted)
+}
+
+/// The session of an admin, with typed accessors for what we keep in it.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    const FLASH_KEY: &'static str = "flash";
+
+    /// Issues a new session key, against session fixation: call it on login.
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    /// A one-off message for the next page rendered, e.g. why a login failed.
+    pub fn insert_flash(&self, message: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::FLASH_KEY, message)
+    }
+
+    pub fn take_flash(&self) -> Result<Option<String>, SessionGetError> {
+        let message = self.0.get(Self::FLASH_KEY)?;
+        self.0.remove(Self::FLASH_KEY);
+        Ok(message)
+    }
+
+    pub fn log_out(self) {
+        self.0.purge();
+    }
+}
+
+impl FromRequest for TypedSession {
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCache;
+
+    fn store() -> CacheSessionStore {
+        CacheSessionStore::new(Arc::new(InMemoryCache::new(100)))
+    }
+
+    fn state(value: &str) -> SessionState {
+        HashMap::from([("user_id".to_string(), value.to_string())])
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_load_until_deleted() {
+        let store = store();
+        let ttl = time::Duration::minutes(5);
+
+        let key = store.save(state("\"a\""), &ttl).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state("\"a\"")));
+
+        let key = store.update(key, state("\"b\""), &ttl).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state("\"b\"")));
+
+        store.delete(&key).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn every_session_gets_a_key_of_its_own() {
+        let store = store();
+        let ttl = time::Duration::minutes(5);
+
+        let first = store.save(state("\"a\""), &ttl).await.unwrap();
+        let second = store.save(state("\"a\""), &ttl).await.unwrap();
+
+        assert_ne!(first.as_ref(), second.as_ref());
+    }
+}
@@ -0,0 +1,57 @@
+//! Graceful shutdown: once requested, the server stops accepting connections and lets
+//! in-flight requests finish, and the scheduler lets running jobs finish.
+use tokio::sync::watch;
+
+/// Resolves once shutdown is requested. Every clone sees the same request.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+/// Requests a shutdown. Dropping it without triggering means shutdown is never requested.
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl Shutdown {
+    pub fn new() -> (ShutdownTrigger, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (ShutdownTrigger(sender), Self(receiver))
+    }
+
+    /// Requested on SIGTERM, e.g. from the orchestrator during a rollout, or on Ctrl-C.
+    pub fn on_signal() -> Self {
+        let (trigger, shutdown) = Self::new();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            tracing::info!("Shutdown requested, draining in-flight work");
+            trigger.trigger();
+        });
+        shutdown
+    }
+
+    pub async fn requested(&mut self) {
+        if self.0.wait_for(|requested| *requested).await.is_err() {
+            // The trigger is gone without having fired: it never will.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM.");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,134 @@
+//! Subscription, unsubscribe, preference and re-permission tokens as they appear in the
+//! links we email:
+//! `{token}.{signature}`, where `token` is the random value we store and `signature`
+//! comes from the `KeyRing`. A tampered or made-up token is turned away without a trip
+//! to the database; only well-signed ones are looked up.
+//!
+//! Links sent before tokens were signed carry the bare token. Those keep working until
+//! `signing.unsigned_tokens_accepted_until`, and are rejected as invalid afterwards.
+use crate::crypto::KeyRing;
+use crate::routes::is_well_formed_subscription_token;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy)]
+pub enum TokenPurpose {
+    Subscription,
+    Unsubscribe,
+    // The links of confirmed subscribers to their preferences.
+    Preferences,
+    // The "stay subscribed" link of a re-permission campaign.
+    Repermission,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Subscription => "subscription-token",
+            TokenPurpose::Unsubscribe => "unsubscribe-token",
+            TokenPurpose::Preferences => "preference-token",
+            TokenPurpose::Repermission => "repermission-token",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenSigner {
+    keys: KeyRing,
+    unsigned_tokens_accepted_until: Option<DateTime<Utc>>,
+}
+
+impl TokenSigner {
+    pub fn new(keys: KeyRing, unsigned_tokens_accepted_until: Option<DateTime<Utc>>) -> Self {
+        Self {
+            keys,
+            unsigned_tokens_accepted_until,
+        }
+    }
+
+    /// The token to put in a link, signed with the current key.
+    pub fn sign(&self, purpose: TokenPurpose, token: &str) -> String {
+        format!(
+            "{}.{}",
+            token,
+            self.keys.sign(purpose.as_str(), token.as_bytes())
+        )
+    }
+
+    /// The stored token carried by `signed_token`, or `None` if it is invalid:
+    /// malformed, not signed by one of our keys for `purpose`, or unsigned
+    /// past the grace period.
+    pub fn verify<'a>(&self, purpose: TokenPurpose, signed_token: &'a str) -> Option<&'a str> {
+        let token = match signed_token.split_once('.') {
+            Some((token, signature)) => self
+                .keys
+                .verify(purpose.as_str(), token.as_bytes(), signature)
+                .then_some(token)?,
+            None if self.accepts_unsigned_tokens() => signed_token,
+            None => return None,
+        };
+        is_well_formed_subscription_token(token).then_some(token)
+    }
+
+    fn accepts_unsigned_tokens(&self) -> bool {
+        self.unsigned_tokens_accepted_until
+            .is_some_and(|until| Utc::now() < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenPurpose, TokenSigner};
+    use crate::crypto::KeyRing;
+    use chrono::{Duration, Utc};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+
+    const TOKEN: &str = "abcdefghijklmnopqrstuvwxy";
+
+    fn signer(unsigned_tokens_accepted_until: Option<chrono::DateTime<Utc>>) -> TokenSigner {
+        let keys = HashMap::from([("k".to_string(), SecretString::from("k".repeat(32)))]);
+        TokenSigner::new(
+            KeyRing::new("k".into(), keys).unwrap(),
+            unsigned_tokens_accepted_until,
+        )
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let signer = signer(None);
+        let signed = signer.sign(TokenPurpose::Subscription, TOKEN);
+        assert_eq!(
+            signer.verify(TokenPurpose::Subscription, &signed),
+            Some(TOKEN)
+        );
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let signer = signer(None);
+        let signed = signer.sign(TokenPurpose::Subscription, TOKEN);
+        let tampered = signed.replacen('a', "b", 1);
+        assert_eq!(signer.verify(TokenPurpose::Subscription, &tampered), None);
+        assert_eq!(
+            signer.verify(TokenPurpose::Subscription, &format!("{}x", signed)),
+            None
+        );
+    }
+
+    #[test]
+    fn tokens_are_scoped_to_their_purpose() {
+        let signer = signer(None);
+        let signed = signer.sign(TokenPurpose::Subscription, TOKEN);
+        assert_eq!(signer.verify(TokenPurpose::Unsubscribe, &signed), None);
+    }
+
+    #[test]
+    fn unsigned_tokens_are_only_accepted_during_the_grace_period() {
+        let during = signer(Some(Utc::now() + Duration::days(1)));
+        let after = signer(Some(Utc::now() - Duration::days(1)));
+        assert_eq!(during.verify(TokenPurpose::Unsubscribe, TOKEN), Some(TOKEN));
+        assert_eq!(during.verify(TokenPurpose::Unsubscribe, "tooshort"), None);
+        assert_eq!(after.verify(TokenPurpose::Unsubscribe, TOKEN), None);
+        assert_eq!(signer(None).verify(TokenPurpose::Unsubscribe, TOKEN), None);
+    }
+}
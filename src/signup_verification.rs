@@ -0,0 +1,277 @@
+//! Optional verification of signups, against spam: either a CAPTCHA solved in the browser
+//! (hCaptcha or Cloudflare Turnstile) and checked with the provider, or a proof-of-work
+//! challenge from `GET /subscriptions/challenge`, solved by the form's script.
+use crate::cache::Cache;
+use crate::crypto::KeyRing;
+use crate::routes::error_chain_fmt;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CHALLENGE_SIGNATURE: &str = "signup-challenge";
+const CHALLENGE_NONCE_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    pub fn default_verify_url(self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+        }
+    }
+}
+
+/// What the subscribe form sent to prove it isn't a bot.
+#[derive(Default)]
+pub struct Solution<'a> {
+    // The token of the CAPTCHA widget.
+    pub captcha_response: Option<&'a str>,
+    pub challenge: Option<&'a str>,
+    pub nonce: Option<&'a str>,
+}
+
+#[derive(thiserror::Error)]
+pub enum VerificationError {
+    #[error("{0}")]
+    Rejected(&'static str),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub enum SignupVerifier {
+    Captcha(CaptchaVerifier),
+    ProofOfWork(ProofOfWork),
+}
+
+impl SignupVerifier {
+    pub async fn verify(&self, solution: &Solution<'_>) -> Result<(), VerificationError> {
+        match self {
+            SignupVerifier::Captcha(verifier) => verifier.verify(solution.captcha_response).await,
+            SignupVerifier::ProofOfWork(proof_of_work) => {
+                proof_of_work
+                    .verify(solution.challenge, solution.nonce)
+                    .await
+            }
+        }
+    }
+}
+
+pub struct CaptchaVerifier {
+    http_client: Client,
+    provider: CaptchaProvider,
+    verify_url: String,
+    secret: SecretString,
+}
+
+#[derive(serde::Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+}
+
+// hCaptcha and Turnstile answer in the same shape.
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(rename = "error-codes", default)]
+    error_codes: Vec<String>,
+}
+
+impl CaptchaVerifier {
+    pub fn new(
+        provider: CaptchaProvider,
+        verify_url: String,
+        secret: SecretString,
+        timeout: Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            provider,
+            verify_url,
+            secret,
+        }
+    }
+
+    #[tracing::instrument(name = "Verify a CAPTCHA", skip_all, fields(provider = ?self.provider))]
+    async fn verify(&self, response: Option<&str>) -> Result<(), VerificationError> {
+        let response = response
+            .filter(|response| !response.is_empty())
+            .ok_or(VerificationError::Rejected("The CAPTCHA is missing."))?;
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&VerifyRequest {
+                secret: self.secret.expose_secret(),
+                response,
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(anyhow::Error::from)?
+            .json()
+            .await
+            .map_err(anyhow::Error::from)?;
+        if !outcome.success {
+            tracing::info!(error_codes = ?outcome.error_codes, "Rejected a CAPTCHA");
+            return Err(VerificationError::Rejected("The CAPTCHA was not solved."));
+        }
+        Ok(())
+    }
+}
+
+/// A challenge to solve before subscribing, for `GET /subscriptions/challenge`.
+#[derive(serde::Serialize)]
+pub struct Challenge {
+    challenge: String,
+    difficulty_bits: u32,
+    expires_at: i64,
+}
+
+/// Signups have to come with a `nonce` such that the SHA-256 of `{challenge}:{nonce}`
+/// starts with `difficulty_bits` zero bits: cheap to check, costly to find for every
+/// spam signup. Challenges are signed rather than stored, and can only be used once.
+pub struct ProofOfWork {
+    key_ring: KeyRing,
+    cache: Arc<dyn Cache>,
+    difficulty_bits: u32,
+    challenge_lifetime: Duration,
+}
+
+impl ProofOfWork {
+    pub fn new(
+        key_ring: KeyRing,
+        cache: Arc<dyn Cache>,
+        difficulty_bits: u32,
+        challenge_lifetime: Duration,
+    ) -> Self {
+        Self {
+            key_ring,
+            cache,
+            difficulty_bits,
+            challenge_lifetime,
+        }
+    }
+
+    pub fn challenge(&self) -> Challenge {
+        let expires_at = Utc::now().timestamp() + self.challenge_lifetime.as_secs() as i64;
+        let nonce: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .map(char::from)
+            .take(CHALLENGE_NONCE_LENGTH)
+            .collect();
+        let message = format!("{expires_at}.{nonce}");
+        let signature = self.key_ring.sign(CHALLENGE_SIGNATURE, message.as_bytes());
+        Challenge {
+            challenge: format!("{message}.{signature}"),
+            difficulty_bits: self.difficulty_bits,
+            expires_at,
+        }
+    }
+
+    #[tracing::instrument(name = "Verify a proof of work", skip_all)]
+    async fn verify(
+        &self,
+        challenge: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<(), VerificationError> {
+        let (Some(challenge), Some(nonce)) = (challenge, nonce) else {
+            return Err(VerificationError::Rejected(
+                "The proof-of-work challenge and its solution are missing.",
+            ));
+        };
+        let invalid = VerificationError::Rejected("The proof-of-work challenge is invalid.");
+        // `{expires_at}.{nonce}.{signature}`, where the signature has dots of its own.
+        let mut parts = challenge.splitn(3, '.');
+        let (Some(expires_at), Some(challenge_nonce), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid);
+        };
+        let message = format!("{expires_at}.{challenge_nonce}");
+        if !self
+            .key_ring
+            .verify(CHALLENGE_SIGNATURE, message.as_bytes(), signature)
+        {
+            return Err(invalid);
+        }
+        let expires_at: i64 = expires_at.parse().map_err(|_| invalid)?;
+        let remaining = expires_at - Utc::now().timestamp();
+        if remaining <= 0 {
+            return Err(VerificationError::Rejected(
+                "The proof-of-work challenge has expired.",
+            ));
+        }
+        if !is_solution(challenge, nonce, self.difficulty_bits) {
+            return Err(VerificationError::Rejected(
+                "The proof-of-work challenge was not solved.",
+            ));
+        }
+        // Checked last, so that wrong answers don't use up the challenge.
+        let uses = self
+            .cache
+            .increment(
+                &format!("signup_challenge:{challenge_nonce}"),
+                Duration::from_secs(remaining as u64),
+            )
+            .await?;
+        if uses > 1 {
+            return Err(VerificationError::Rejected(
+                "The proof-of-work challenge was already used.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether the SHA-256 of `{challenge}:{nonce}` starts with `difficulty_bits` zero bits.
+pub fn is_solution(challenge: &str, nonce: &str, difficulty_bits: u32) -> bool {
+    let digest = Sha256::digest(format!("{challenge}:{nonce}"));
+    let mut zero_bits = 0;
+    for byte in digest {
+        zero_bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zero_bits >= difficulty_bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_solution;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn solutions_need_enough_leading_zero_bits() {
+        let challenge = "1700000000.abc.k.signature";
+        let nonce = (0u32..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| is_solution(challenge, nonce, 8))
+            .unwrap();
+
+        let digest = Sha256::digest(format!("{challenge}:{nonce}"));
+        assert_eq!(digest[0], 0);
+        let zero_bits = 8 + digest[1].leading_zeros();
+        assert!(is_solution(challenge, &nonce, zero_bits));
+        assert!(!is_solution(challenge, &nonce, zero_bits + 1));
+    }
+}
@@ -0,0 +1,162 @@
+//! SMS delivery through a Twilio-style API: messages are form-encoded and posted to
+//! `{base_url}/Accounts/{account_sid}/Messages.json`, authenticated with HTTP basic auth.
+use crate::domain::{PhoneNumber, Segment};
+use crate::telemetry::{outbound_request_span, trace_context_headers};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub struct SmsClient {
+    http_client: Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: SecretString,
+    sender: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendSmsRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    body: &'a str,
+}
+
+impl SmsClient {
+    pub fn new(
+        base_url: String,
+        account_sid: String,
+        auth_token: SecretString,
+        sender: String,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            account_sid,
+            auth_token,
+            sender,
+        }
+    }
+
+    pub async fn send_sms(
+        &self,
+        recipient: &PhoneNumber,
+        body: &str,
+    ) -> Result<(), reqwest::Error> {
+        let url = format!(
+            "{}/Accounts/{}/Messages.json",
+            self.base_url, self.account_sid
+        );
+        let span = outbound_request_span("POST", &url);
+        let response = self
+            .http_client
+            .post(&url)
+            .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+            .headers(trace_context_headers(&span))
+            .form(&SendSmsRequest {
+                from: &self.sender,
+                to: recipient.as_ref(),
+                body,
+            })
+            .send()
+            .instrument(span.clone())
+            .await?;
+        span.record("http.response.status_code", response.status().as_u16());
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Phone numbers of confirmed subscribers that verified them and still want texts,
+/// leaving out those who opted out of the issue's `category` or are outside its `segment`.
+#[tracing::instrument(name = "Get SMS recipients", skip(pool))]
+pub async fn get_sms_recipients(
+    pool: &PgPool,
+    newsletter_id: Uuid,
+    category: Option<&str>,
+    segment: Option<&Segment>,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT r.phone_number
+        FROM sms_registrations r
+        JOIN subscriptions s ON s.id = r.subscriber_id
+        WHERE s.status = 'confirmed' AND r.opted_in AND r.verified_at IS NOT NULL
+            AND s.newsletter_id = $4
+            AND NOT EXISTS (
+                SELECT 1 FROM category_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.category = $1
+            )
+            AND ($2::text[] IS NULL OR (
+                SELECT COUNT(*) FROM subscriber_tags t
+                WHERE t.subscriber_id = s.id AND t.tag = ANY($2)
+            ) >= $3)
+        "#,
+        category,
+        segment.map(Segment::tags) as Option<Vec<String>>,
+        segment.map_or(0, Segment::required_matches),
+        newsletter_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::PhoneNumber;
+    use crate::sms_client::SmsClient;
+    use claim::assert_err;
+    use wiremock::matchers::{body_string_contains, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn sms_client(base_url: String) -> SmsClient {
+        SmsClient::new(
+            base_url,
+            "AC123".into(),
+            "auth-token".to_string().into(),
+            "+15005550006".into(),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    fn recipient() -> PhoneNumber {
+        PhoneNumber::parse("+14155550123".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_sms_posts_a_form_to_the_account_messages_resource() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/Accounts/AC123/Messages.json"))
+            .and(method("POST"))
+            .and(header_exists("Authorization"))
+            .and(body_string_contains("To=%2B14155550123"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        sms_client(mock_server.uri())
+            .send_sms(&recipient(), "Hello")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_sms_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client(mock_server.uri())
+            .send_sms(&recipient(), "Hello")
+            .await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,164 @@
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+/// Snippets can include other snippets. We stop expanding past this depth
+/// so that a reference cycle can never recurse forever.
+const MAX_NESTING_DEPTH: usize = 8;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SnippetResolutionError {
+    #[error("The snippet `{0}` does not exist.")]
+    UnknownSnippet(String),
+    #[error("The snippet `{0}` is nested too deeply - does it include itself?")]
+    TooDeeplyNested(String),
+    #[error("A snippet reference is missing its closing `}}}}`.")]
+    UnterminatedReference,
+}
+
+struct SnippetReference<'a> {
+    // Everything before the `{{>` marker
+    prefix: &'a str,
+    name: &'a str,
+    // Everything after the closing `}}`
+    rest: &'a str,
+}
+
+/// Find the first `{{> name }}` reference in `body`, if any.
+fn next_reference(body: &str) -> Result<Option<SnippetReference<'_>>, SnippetResolutionError> {
+    let Some(start) = body.find("{{>") else {
+        return Ok(None);
+    };
+    let after_open = &body[start + 3..];
+    let end = after_open
+        .find("}}")
+        .ok_or(SnippetResolutionError::UnterminatedReference)?;
+    Ok(Some(SnippetReference {
+        prefix: &body[..start],
+        name: after_open[..end].trim(),
+        rest: &after_open[end + 2..],
+    }))
+}
+
+/// Return the names of the snippets directly referenced in `body`, in order of appearance.
+pub fn referenced_snippets(body: &str) -> Result<Vec<&str>, SnippetResolutionError> {
+    let mut names = Vec::new();
+    let mut rest = body;
+    while let Some(reference) = next_reference(rest)? {
+        names.push(reference.name);
+        rest = reference.rest;
+    }
+    Ok(names)
+}
+
+/// Replace every `{{> name }}` reference in `body` with the content of the matching snippet,
+/// expanding nested references as well.
+pub fn resolve_snippets(
+    body: &str,
+    snippets: &HashMap<String, String>,
+) -> Result<String, SnippetResolutionError> {
+    resolve(body, snippets, 0)
+}
+
+fn resolve(
+    body: &str,
+    snippets: &HashMap<String, String>,
+    depth: usize,
+) -> Result<String, SnippetResolutionError> {
+    let mut output = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(reference) = next_reference(rest)? {
+        let content = snippets
+            .get(reference.name)
+            .ok_or_else(|| SnippetResolutionError::UnknownSnippet(reference.name.into()))?;
+        if depth >= MAX_NESTING_DEPTH {
+            return Err(SnippetResolutionError::TooDeeplyNested(
+                reference.name.into(),
+            ));
+        }
+        output.push_str(reference.prefix);
+        output.push_str(&resolve(content, snippets, depth + 1)?);
+        rest = reference.rest;
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[tracing::instrument(name = "Load all snippets", skip(pool))]
+pub async fn load_snippets(pool: &PgPool) -> Result<HashMap<String, String>, sqlx::Error> {
+    let snippets = sqlx::query!(r#"SELECT name, content FROM snippets"#)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.name, r.content))
+        .collect();
+    Ok(snippets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SnippetResolutionError, referenced_snippets, resolve_snippets};
+    use claim::assert_err;
+    use std::collections::HashMap;
+
+    fn snippets(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn bodies_without_references_are_left_untouched() {
+        let body = "<p>Hello {{ there }}</p>";
+        assert_eq!(resolve_snippets(body, &HashMap::new()).unwrap(), body);
+    }
+
+    #[test]
+    fn references_are_replaced_with_snippet_content() {
+        let snippets = snippets(&[("footer", "Thanks for reading!")]);
+        let resolved = resolve_snippets("Issue body. {{> footer }}", &snippets).unwrap();
+        assert_eq!(resolved, "Issue body. Thanks for reading!");
+    }
+
+    #[test]
+    fn whitespace_inside_the_reference_is_optional() {
+        let snippets = snippets(&[("bio", "Ursula")]);
+        assert_eq!(resolve_snippets("{{>bio}}", &snippets).unwrap(), "Ursula");
+    }
+
+    #[test]
+    fn nested_references_are_expanded() {
+        let snippets = snippets(&[("footer", "Bye. {{> bio }}"), ("bio", "Ursula")]);
+        let resolved = resolve_snippets("{{> footer }}", &snippets).unwrap();
+        assert_eq!(resolved, "Bye. Ursula");
+    }
+
+    #[test]
+    fn unknown_snippets_are_rejected() {
+        let outcome = resolve_snippets("{{> missing }}", &HashMap::new());
+        assert_eq!(
+            outcome,
+            Err(SnippetResolutionError::UnknownSnippet("missing".into()))
+        );
+    }
+
+    #[test]
+    fn self_referencing_snippets_are_rejected() {
+        let snippets = snippets(&[("loop", "again {{> loop }}")]);
+        assert_err!(resolve_snippets("{{> loop }}", &snippets));
+    }
+
+    #[test]
+    fn unterminated_references_are_rejected() {
+        assert_eq!(
+            referenced_snippets("{{> footer"),
+            Err(SnippetResolutionError::UnterminatedReference)
+        );
+    }
+
+    #[test]
+    fn referenced_snippets_are_listed_in_order() {
+        let names = referenced_snippets("{{> a }} text {{> b }}").unwrap();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+}
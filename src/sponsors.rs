@@ -0,0 +1,155 @@
+use crate::crypto::KeyRing;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Every creative must contain this placeholder: it is swapped for the tracked
+/// click-through link when the creative is injected into an issue.
+pub const SPONSOR_LINK_PLACEHOLDER: &str = "{{ sponsor_link }}";
+
+// Tracking links are signed, so that clicks and opens can't be inflated by hitting
+// the tracking endpoints directly.
+pub const SPONSOR_CLICK_SIGNATURE: &str = "sponsor-click";
+pub const SPONSOR_OPEN_SIGNATURE: &str = "sponsor-open";
+
+pub struct ActiveSponsorSlot {
+    pub id: Uuid,
+    pub sponsor_name: String,
+    pub creative_html: String,
+}
+
+impl ActiveSponsorSlot {
+    pub fn click_url(&self, base_url: &str, keys: &KeyRing) -> String {
+        format!(
+            "{}/sponsors/{}/click?signature={}",
+            base_url,
+            self.id,
+            keys.sign(SPONSOR_CLICK_SIGNATURE, self.id.as_bytes())
+        )
+    }
+
+    pub fn open_url(&self, base_url: &str, keys: &KeyRing) -> String {
+        format!(
+            "{}/sponsors/{}/open?signature={}",
+            base_url,
+            self.id,
+            keys.sign(SPONSOR_OPEN_SIGNATURE, self.id.as_bytes())
+        )
+    }
+
+    fn html_block(&self, base_url: &str, keys: &KeyRing) -> String {
+        format!(
+            "{}<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" />",
+            self.creative_html
+                .replace(SPONSOR_LINK_PLACEHOLDER, &self.click_url(base_url, keys)),
+            self.open_url(base_url, keys)
+        )
+    }
+
+    fn text_block(&self, base_url: &str, keys: &KeyRing) -> String {
+        format!(
+            "Sponsored by {}: {}",
+            self.sponsor_name,
+            self.click_url(base_url, keys)
+        )
+    }
+}
+
+/// Append the creatives of all active sponsor slots to the HTML and plain-text bodies of an issue.
+pub fn inject_sponsor_blocks(
+    html: &str,
+    text: &str,
+    slots: &[ActiveSponsorSlot],
+    base_url: &str,
+    keys: &KeyRing,
+) -> (String, String) {
+    let mut html = html.to_owned();
+    let mut text = text.to_owned();
+    for slot in slots {
+        html.push_str(&slot.html_block(base_url, keys));
+        text.push_str("\n\n");
+        text.push_str(&slot.text_block(base_url, keys));
+    }
+    (html, text)
+}
+
+#[tracing::instrument(name = "Get active sponsor slots", skip(pool))]
+pub async fn get_active_sponsor_slots(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+) -> Result<Vec<ActiveSponsorSlot>, sqlx::Error> {
+    sqlx::query_as!(
+        ActiveSponsorSlot,
+        r#"
+        SELECT id, sponsor_name, creative_html
+        FROM sponsor_slots
+        WHERE starts_at <= $1 AND ends_at > $1
+        ORDER BY starts_at
+        "#,
+        now
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Record sponsor impressions", skip(pool, slot_ids))]
+pub async fn record_impressions(
+    pool: &PgPool,
+    slot_ids: &[Uuid],
+    delivered: i64,
+) -> Result<(), sqlx::Error> {
+    if slot_ids.is_empty() || delivered == 0 {
+        return Ok(());
+    }
+    sqlx::query!(
+        r#"UPDATE sponsor_slots SET impressions = impressions + $1 WHERE id = ANY($2)"#,
+        delivered,
+        slot_ids
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActiveSponsorSlot, inject_sponsor_blocks};
+    use crate::crypto::KeyRing;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn keys() -> KeyRing {
+        let key = SecretString::from("k".repeat(32));
+        KeyRing::new("k".into(), HashMap::from([("k".to_string(), key)])).unwrap()
+    }
+
+    fn slot() -> ActiveSponsorSlot {
+        ActiveSponsorSlot {
+            id: Uuid::nil(),
+            sponsor_name: "ACME".into(),
+            creative_html: "<a href=\"{{ sponsor_link }}\">ACME</a>".into(),
+        }
+    }
+
+    #[test]
+    fn issues_are_left_untouched_without_active_slots() {
+        let (html, text) = inject_sponsor_blocks("<p>Hi</p>", "Hi", &[], "http://x", &keys());
+        assert_eq!(html, "<p>Hi</p>");
+        assert_eq!(text, "Hi");
+    }
+
+    #[test]
+    fn creatives_link_to_the_tracked_click_url() {
+        let keys = keys();
+        let (html, text) = inject_sponsor_blocks("<p>Hi</p>", "Hi", &[slot()], "http://x", &keys);
+        let click_url = slot().click_url("http://x", &keys);
+        assert!(click_url.starts_with(&format!(
+            "http://x/sponsors/{}/click?signature=k.",
+            Uuid::nil()
+        )));
+        assert!(html.starts_with(&format!("<p>Hi</p><a href=\"{}\">ACME</a>", click_url)));
+        assert!(html.contains(&slot().open_url("http://x", &keys)));
+        assert_eq!(text, format!("Hi\n\nSponsored by ACME: {}", click_url));
+    }
+}
@@ -1,16 +1,79 @@
+use crate::access_log::record_access;
+use crate::admin_access::{AdminAllowlist, restrict_admin_access};
+use crate::api_error::{form_payload_error, json_payload_error, scope_trace_id};
+use crate::authorization::authorize_requests;
+use crate::cache::Cache;
+use crate::content_checks::ContentChecker;
+use crate::cors::{cors, validate as validate_cors};
+use crate::crypto::KeyRing;
+use crate::database::{ReadPool, wait_for_database, watch_pool_saturation};
+use crate::domain::{ActionBaseUrl, SignupField, SubscriberEmail};
 use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletter, subscribe};
+use crate::metrics::record_metrics;
+use crate::panics::catch_panics;
+use crate::pii::PiiCipher;
+use crate::preflight::{check_migrations, run_migrations};
+use crate::rate_limit::RateLimiter;
+use crate::routes::{
+    ConfirmationRejections, StatsCache, admin_console_script, admin_dashboard, admin_stats_page,
+    admin_subscribers_page, admit_waitlisted, archive, archived_issue, atom_feed, bounce_webhook,
+    bulk_unsubscribe, change_password_form, change_password_from_form, compare_newsletter_issues,
+    confirm, confirm_email_change, confirmation_stats, consistency_check, create_api_key,
+    create_category, create_invites, create_list, create_snippet, create_sponsor_slot, create_user,
+    delete_category, delete_list, delete_snippet, delete_sponsor_slot, delete_user,
+    delivery_webhook, erase_subscriber_data, erase_subscriber_data_form, export_archive,
+    export_mailbox, export_subscriber_data, funnel_stats, get_branding, get_category_preferences,
+    get_newsletter_issue, get_notification_preferences, get_repermission_campaign, get_snippet,
+    get_sponsor_report, get_subscriber, get_subscriber_history, get_subscriber_tags,
+    get_subscriber_timeline, health_check, import_subscribers, issue_calendar,
+    limit_confirmation_attempts, limit_login_attempts, limit_subscription_attempts, list_api_keys,
+    list_categories, list_flags, list_invites, list_lists, list_newsletter_issues,
+    list_repermission_campaigns, list_snippets, list_sponsor_slots, list_subscribers, list_tags,
+    list_users, log_out, login, login_form, metrics, no_matching_route, panic_stats,
+    password_reset_confirm_form, password_reset_form, preview_newsletter,
+    preview_newsletter_from_form, publish_newsletter, publish_newsletter_form,
+    publish_newsletter_from_form, push_subscribe, readiness, remove_subscriber, render_preview,
+    request_email_change, request_password_reset, request_subscriber_data, resend_confirmation,
+    reset_password, revoke_api_key, run_smoke_test, scheduler_status, signup_challenge,
+    signup_fields_schema, sms_opt_out, sms_register, sms_verify, sponsor_click, sponsor_open,
+    start_repermission_campaign, stay_subscribed, submit_category_preferences, subscribe,
+    tag_engaged_readers, tag_subscriber, test_send_newsletter, track_click, track_open,
+    unsubscribe, untag_subscriber, update_branding, update_category_preferences, update_flag,
+    update_list, update_notification_preferences, update_snippet, update_user_role,
+    vapid_public_key,
+};
+use crate::security_headers::{SecurityHeaders, set_security_headers};
+use crate::session::AdminSessionStore;
+use crate::shutdown::Shutdown;
+use crate::signed_tokens::TokenSigner;
+use crate::signup_verification::SignupVerifier;
+use crate::sms_client::SmsClient;
+use crate::templates::EmailTemplates;
+use crate::web_push::WebPushClient;
 
+use crate::configuration::AdminNotificationSettings;
+use crate::configuration::CacheSettings;
+use crate::configuration::CorsSettings;
 use crate::configuration::DatabaseSettings;
+use crate::configuration::EmailWebhookSettings;
+use crate::configuration::FrequencyCapSettings;
+use crate::configuration::PayloadLimitSettings;
 use crate::configuration::Settings;
+use crate::configuration::SmsSettings;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::{
     App, HttpServer,
     dev::Server,
+    middleware::from_fn,
     web::{self, Data},
 };
+use ipnet::IpNet;
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
@@ -20,27 +83,146 @@ pub struct Application {
 
 // take only reference
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new()
-        .acquire_timeout(std::time::Duration::from_secs(2))
-        .connect_lazy_with(configuration.with_db())
+    let pool = &configuration.pool;
+    let mut connect_options = configuration.with_db();
+    if let Some(statement_timeout) = pool.statement_timeout_milliseconds {
+        connect_options =
+            connect_options.options([("statement_timeout", statement_timeout.to_string())]);
+    }
+    let options = PgPoolOptions::new()
+        .max_connections(pool.max_connections)
+        .min_connections(pool.min_connections)
+        .acquire_timeout(Duration::from_millis(pool.acquire_timeout_milliseconds))
+        .idle_timeout(Duration::from_secs(pool.idle_timeout_seconds));
+    #[cfg(feature = "fault-injection")]
+    let options = options
+        .after_connect(|connection, _| Box::pin(crate::fault_injection::on_checkout(connection)))
+        .before_acquire(|connection, _| {
+            Box::pin(async {
+                crate::fault_injection::on_checkout(connection)
+                    .await
+                    .map(|()| true)
+            })
+        });
+    options.connect_lazy_with(connect_options)
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
+        let email_client = Arc::new(configuration.email_client.clone().client());
+        Self::build_with_shared(configuration, connection_pool, email_client).await
+    }
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token.clone(),
-            timeout,
+    /// `connection_pool` and `email_client` are shared with the scheduled jobs: for the
+    /// instance to stay within `database.pool.max_connections`, for transactional emails to go
+    /// ahead of the issues they deliver and for every email to count against the same rate
+    /// limit.
+    pub async fn build_with_shared(
+        configuration: Settings,
+        connection_pool: PgPool,
+        email_client: Arc<EmailClient>,
+    ) -> Result<Self, std::io::Error> {
+        wait_for_database(&connection_pool, &configuration.database.connect_retry)
+            .await
+            .map_err(std::io::Error::other)?;
+        watch_pool_saturation(connection_pool.clone());
+        if configuration.database.migrate_on_startup {
+            run_migrations(&connection_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+        // The replica is not waited for: reads fall back to the primary while it is down.
+        let read_pool = ReadPool::new(
+            connection_pool.clone(),
+            configuration
+                .database_replica
+                .as_ref()
+                .map(get_connection_pool),
         );
+        check_migrations(
+            &connection_pool,
+            configuration.application.on_migration_drift,
+        )
+        .await
+        .map_err(std::io::Error::other)?;
+
+        let action_base_url = configuration
+            .action_base_url()
+            .expect("Invalid action base url.");
+        SignupField::validate_all(&configuration.application.signup_fields)
+            .expect("Invalid signup fields.");
+        let key_ring = configuration
+            .signing
+            .key_ring()
+            .expect("Invalid signing keys.");
+        let token_signer = configuration
+            .signing
+            .token_signer()
+            .expect("Invalid signing keys.");
+        let pii_cipher = configuration
+            .pii_cipher()
+            .expect("Invalid PII encryption settings.");
+        let cache = configuration
+            .cache
+            .cache()
+            .await
+            .expect("Failed to set up the cache.");
+        let confirmation_rate_limiter = configuration
+            .confirmation_rate_limit
+            .limiter(cache.clone(), "confirm");
+        let subscription_rate_limiter = configuration
+            .subscription_rate_limit
+            .limiter(cache.clone(), "subscribe");
+        let login_rate_limiter = configuration
+            .login_rate_limit
+            .limiter(cache.clone(), "login");
+        let resend_confirmation_rate_limiter = configuration
+            .resend_confirmation_rate_limit
+            .limiter(cache.clone(), "resend_confirmation");
+        let signup_verifier = configuration.signup_verification.map(|settings| {
+            settings
+                .verifier(&configuration.signing, cache.clone())
+                .expect("Invalid signup verification settings.")
+        });
+        // The in-memory cache can't fail: only Redis is worth probing.
+        let redis =
+            matches!(configuration.cache, CacheSettings::Redis { .. }).then(|| cache.clone());
+        let stats_cache = StatsCache {
+            cache: cache.clone(),
+            ttl: configuration.application.stats_cache_ttl(),
+        };
+        let session_store = configuration
+            .session_store
+            .store(cache, &connection_pool)
+            .await
+            .expect("Failed to set up the session store.");
+        let session_key = configuration
+            .application
+            .session_key()
+            .expect("Invalid session key.");
+        let shutdown_timeout = configuration.application.shutdown_timeout();
+        let templates = EmailTemplates::load(&configuration.application.templates_dir)
+            .expect("Failed to load the email templates.");
+
+        let web_push_client = configuration
+            .web_push
+            .map(|settings| settings.client().expect("Invalid Web Push settings."));
+        let sms_client = configuration.sms.map(SmsSettings::client);
+        let smoke_test_sink = configuration
+            .smoke_test
+            .map(|settings| settings.sink().expect("Invalid smoke test sink address."));
+        let admin_allowlist = configuration
+            .admin_access
+            .map(|settings| settings.allowlist());
+        if let Some(cors) = &configuration.cors {
+            validate_cors(cors).map_err(std::io::Error::other)?;
+        }
+        let security_headers = SecurityHeaders::new(
+            &configuration.security_headers,
+            &configuration.application.base_url,
+        )
+        .map_err(std::io::Error::other)?;
 
         // We have removed the hard-coded `8000` - it's now coming from our settings!
         let address = format!(
@@ -55,8 +237,40 @@ impl Application {
         let server = run(
             listener,
             connection_pool,
+            read_pool,
             email_client,
+            templates,
             configuration.application.base_url,
+            action_base_url,
+            key_ring,
+            token_signer,
+            pii_cipher,
+            configuration.application.redirect_allowed_hosts,
+            web_push_client,
+            sms_client,
+            signup_verifier,
+            confirmation_rate_limiter,
+            subscription_rate_limiter,
+            login_rate_limiter,
+            resend_confirmation_rate_limiter,
+            configuration.application.max_active_subscribers,
+            configuration.application.invite_only,
+            configuration.application.signup_fields,
+            configuration.frequency_cap,
+            admin_allowlist,
+            configuration.application.trusted_proxies,
+            smoke_test_sink,
+            configuration.admin_notifications,
+            configuration.email_webhooks,
+            redis,
+            stats_cache,
+            session_store,
+            session_key,
+            configuration.application.payload_limits,
+            configuration.cors,
+            security_headers,
+            ContentChecker::new(&configuration.content_checks),
+            shutdown_timeout,
         )?;
         Ok(Self { port, server })
     }
@@ -65,9 +279,14 @@ impl Application {
         self.port
     }
 
-    // A more expressive name that makes it clear that
-    // this function only returns when the application is stopped.
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    /// Once `shutdown` is requested, stops accepting connections and returns when
+    /// in-flight requests have finished, or after the shutdown timeout.
+    pub async fn run_until_shutdown(self, mut shutdown: Shutdown) -> Result<(), std::io::Error> {
+        let handle = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.requested().await;
+            handle.stop(true).await;
+        });
         self.server.await
     }
 }
@@ -78,28 +297,523 @@ impl Application {
 // a raw `String` would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
+// Same reasoning as `ApplicationBaseUrl`.
+pub struct RedirectAllowedHosts(pub Vec<String>);
+
+pub struct ConfirmationRateLimiter(pub RateLimiter);
+
+pub struct SubscriptionRateLimiter(pub RateLimiter);
+
+pub struct LoginRateLimiter(pub RateLimiter);
+
+pub struct ResendConfirmationRateLimiter(pub RateLimiter);
+
+/// Proxies whose `X-Forwarded-For` is believed, for the client addresses of the audit log.
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+pub struct SubscriberCap(pub Option<u64>);
+
+pub struct InviteOnly(pub bool);
+
+pub struct SignupFields(pub Vec<SignupField>);
+
+pub struct FrequencyCap(pub Option<FrequencyCapSettings>);
+
+pub struct SmokeTestSink(pub Option<SubscriberEmail>);
+
+/// Users get no operational emails when it is `None`.
+pub struct AdminNotifications(pub Option<AdminNotificationSettings>);
+
+pub struct EmailWebhooks(pub Option<EmailWebhookSettings>);
+
+/// The cache, when it is Redis: for readiness probes.
+pub struct Redis(pub Option<Arc<dyn Cache>>);
+
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(json_payload_error)
+}
+
+// Built by each worker: unlike `JsonConfig`, it can't be sent across threads.
+fn form_config(limit: usize) -> web::FormConfig {
+    web::FormConfig::default()
+        .limit(limit)
+        .error_handler(form_payload_error)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    read_pool: ReadPool,
+    email_client: Arc<EmailClient>,
+    templates: EmailTemplates,
     base_url: String,
+    action_base_url: ActionBaseUrl,
+    key_ring: KeyRing,
+    token_signer: TokenSigner,
+    pii_cipher: PiiCipher,
+    redirect_allowed_hosts: Vec<String>,
+    web_push_client: Option<WebPushClient>,
+    sms_client: Option<SmsClient>,
+    signup_verifier: Option<SignupVerifier>,
+    confirmation_rate_limiter: RateLimiter,
+    subscription_rate_limiter: RateLimiter,
+    login_rate_limiter: RateLimiter,
+    resend_confirmation_rate_limiter: RateLimiter,
+    max_active_subscribers: Option<u64>,
+    invite_only: bool,
+    signup_fields: Vec<SignupField>,
+    frequency_cap: Option<FrequencyCapSettings>,
+    admin_allowlist: Option<AdminAllowlist>,
+    trusted_proxies: Vec<IpNet>,
+    smoke_test_sink: Option<SubscriberEmail>,
+    admin_notifications: Option<AdminNotificationSettings>,
+    email_webhooks: Option<EmailWebhookSettings>,
+    redis: Option<Arc<dyn Cache>>,
+    stats_cache: StatsCache,
+    session_store: AdminSessionStore,
+    session_key: Key,
+    payload_limits: PayloadLimitSettings,
+    cors_settings: Option<CorsSettings>,
+    security_headers: SecurityHeaders,
+    content_checker: ContentChecker,
+    shutdown_timeout: Duration,
 ) -> Result<Server, std::io::Error> {
+    // Browsers don't send secure cookies over plain http, e.g. to a local instance.
+    let secure_cookies = base_url.starts_with("https://");
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let action_base_url = Data::new(action_base_url);
+    let key_ring = Data::new(key_ring);
+    let token_signer = Data::new(token_signer);
+    let pii_cipher = Data::new(pii_cipher);
+    let redirect_allowed_hosts = Data::new(RedirectAllowedHosts(redirect_allowed_hosts));
     let db_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
+    let read_pool = Data::new(read_pool);
+    let email_client = Data::from(email_client);
+    let templates = Data::new(templates);
+    let web_push_client = Data::new(web_push_client);
+    let sms_client = Data::new(sms_client);
+    let signup_verifier = Data::new(signup_verifier);
+    let confirmation_rate_limiter = Data::new(ConfirmationRateLimiter(confirmation_rate_limiter));
+    let confirmation_rejections = Data::new(ConfirmationRejections::default());
+    let subscription_rate_limiter = Data::new(SubscriptionRateLimiter(subscription_rate_limiter));
+    let login_rate_limiter = Data::new(LoginRateLimiter(login_rate_limiter));
+    let resend_confirmation_rate_limiter = Data::new(ResendConfirmationRateLimiter(
+        resend_confirmation_rate_limiter,
+    ));
+    let subscriber_cap = Data::new(SubscriberCap(max_active_subscribers));
+    let invite_only = Data::new(InviteOnly(invite_only));
+    let signup_fields = Data::new(SignupFields(signup_fields));
+    let frequency_cap = Data::new(FrequencyCap(frequency_cap));
+    let admin_allowlist = Data::new(admin_allowlist);
+    let trusted_proxies = Data::new(TrustedProxies(trusted_proxies));
+    let smoke_test_sink = Data::new(SmokeTestSink(smoke_test_sink));
+    let admin_notifications = Data::new(AdminNotifications(admin_notifications));
+    let email_webhooks = Data::new(EmailWebhooks(email_webhooks));
+    let redis = Data::new(Redis(redis));
+    let stats_cache = Data::new(stats_cache);
+    let security_headers = Data::new(security_headers);
+    let content_checker = Data::new(content_checker);
+    #[cfg(feature = "fault-injection")]
+    let faults = Data::new(crate::fault_injection::Faults::default());
     // could have directly used email_client.clone() if we didnt have other fields than Client as its already Arc like
 
     let server = HttpServer::new(move || {
-        App::new()
-            .wrap(TracingLogger::default())
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
-            .route("/newsletters", web::post().to(publish_newsletter))
+        let app = App::new()
+            // Inside the session, which it reads the user from, and the admin allowlist, which
+            // turns requests away before their credentials are checked.
+            .wrap(from_fn(authorize_requests))
+            // Registered early so that they run inside `TracingLogger` and see the request id,
+            // and so that a caught panic is logged as a 500.
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), session_key.clone())
+                    .cookie_secure(secure_cookies)
+                    .build(),
+            )
+            .wrap(from_fn(restrict_admin_access))
+            .wrap(from_fn(catch_panics))
+            // Outside of the admin allowlist and panics, so that their responses get them too.
+            .wrap(from_fn(set_security_headers))
+            .wrap(cors(cors_settings.as_ref()))
+            .wrap(from_fn(scope_trace_id))
+            .wrap(from_fn(record_access))
+            .wrap(from_fn(record_metrics))
+            .wrap(TracingLogger::default());
+        // Test-only: arms faults on this application, which its requests then run into.
+        #[cfg(feature = "fault-injection")]
+        let app = app
+            .wrap(from_fn(crate::fault_injection::scope_faults))
+            .app_data(faults.clone())
+            .service(
+                web::resource("/_faults")
+                    .get(crate::fault_injection::armed_faults)
+                    .post(crate::fault_injection::arm_faults),
+            );
+        app.route("/health_check", web::get().to(health_check))
+            .route("/health_check/ready", web::get().to(readiness))
+            .route("/metrics", web::get().to(metrics))
+            .route(
+                "/subscriptions",
+                web::post()
+                    .to(subscribe)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            .route("/subscriptions/fields", web::get().to(signup_fields_schema))
+            .route("/subscriptions/challenge", web::get().to(signup_challenge))
+            .service(
+                web::resource("/subscriptions/categories")
+                    .route(web::get().to(get_category_preferences))
+                    .route(web::put().to(update_category_preferences))
+                    .route(web::post().to(submit_category_preferences))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/subscriptions/confirm")
+                    .wrap(from_fn(limit_confirmation_attempts))
+                    .route(web::get().to(confirm))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .route(
+                "/subscriptions/resend_confirmation",
+                // Every resend costs an email too: it shares the budget of signups.
+                web::post()
+                    .to(resend_confirmation)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe))
+            .route("/subscriptions/stay", web::get().to(stay_subscribed))
+            .route(
+                "/subscriptions/data_request",
+                // Costs an email too.
+                web::post()
+                    .to(request_subscriber_data)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            .route(
+                "/subscriptions/export",
+                web::get().to(export_subscriber_data),
+            )
+            .service(
+                web::resource("/subscriptions/erase")
+                    .route(web::get().to(erase_subscriber_data_form))
+                    .route(web::post().to(erase_subscriber_data))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .route(
+                "/subscriptions/change_email",
+                // Costs an email too.
+                web::post()
+                    .to(request_email_change)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            .route(
+                "/subscriptions/change_email/confirm",
+                web::get().to(confirm_email_change),
+            )
+            .service(
+                web::resource("/newsletters")
+                    .app_data(json_config(payload_limits.issue_bytes))
+                    .route(web::get().to(list_newsletter_issues))
+                    .route(web::post().to(publish_newsletter))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/newsletters/preview")
+                    .app_data(json_config(payload_limits.issue_bytes))
+                    .route(web::post().to(preview_newsletter))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/newsletters/test_send")
+                    .app_data(json_config(payload_limits.issue_bytes))
+                    .route(web::post().to(test_send_newsletter))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}",
+                web::get().to(get_newsletter_issue),
+            )
+            // `/subscriptions` and its resend are for the default list.
+            .route(
+                "/newsletters/{newsletter_slug}/subscriptions",
+                web::post()
+                    .to(subscribe)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            .route(
+                "/newsletters/{newsletter_slug}/subscriptions/resend_confirmation",
+                web::post()
+                    .to(resend_confirmation)
+                    .wrap(from_fn(limit_subscription_attempts)),
+            )
+            // So are the feed and the archive.
+            .route("/feed.xml", web::get().to(atom_feed))
+            .route("/archive", web::get().to(archive))
+            .route(
+                "/archive/{newsletter_issue_id}",
+                web::get().to(archived_issue),
+            )
+            .route(
+                "/newsletters/{newsletter_slug}/feed.xml",
+                web::get().to(atom_feed),
+            )
+            .route(
+                "/newsletters/{newsletter_slug}/archive",
+                web::get().to(archive),
+            )
+            .service(
+                web::resource("/login")
+                    .route(web::get().to(login_form))
+                    .route(web::post().to(login).wrap(from_fn(limit_login_attempts)))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/password_reset")
+                    .route(web::get().to(password_reset_form))
+                    // Every request can cost an email: it shares the budget of logins.
+                    .route(
+                        web::post()
+                            .to(request_password_reset)
+                            .wrap(from_fn(limit_login_attempts)),
+                    )
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/password_reset/confirm")
+                    .route(web::get().to(password_reset_confirm_form))
+                    .route(web::post().to(reset_password))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/dashboard")
+                    .route(web::get().to(admin_dashboard))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/dashboard/subscribers")
+                    .route(web::get().to(admin_subscribers_page))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/dashboard/stats")
+                    .route(web::get().to(admin_stats_page))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/console.js")
+                    .route(web::get().to(admin_console_script))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/newsletters")
+                    .app_data(form_config(payload_limits.issue_bytes))
+                    .route(web::get().to(publish_newsletter_form))
+                    .route(web::post().to(publish_newsletter_from_form))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/newsletters/preview")
+                    .app_data(form_config(payload_limits.issue_bytes))
+                    .route(web::post().to(preview_newsletter_from_form))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/logout")
+                    .route(web::post().to(log_out))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/password")
+                    .route(web::get().to(change_password_form))
+                    .route(web::post().to(change_password_from_form))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/newsletters/render_preview")
+                    .app_data(json_config(payload_limits.issue_bytes))
+                    .route(web::post().to(render_preview))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .route("/push/public_key", web::get().to(vapid_public_key))
+            .route("/push/subscribe", web::post().to(push_subscribe))
+            .route("/sms/register", web::post().to(sms_register))
+            .route("/sms/verify", web::post().to(sms_verify))
+            .route("/sms/opt_out", web::post().to(sms_opt_out))
+            .service(
+                web::scope("/admin/snippets")
+                    .route("", web::get().to(list_snippets))
+                    .route("", web::post().to(create_snippet))
+                    .route("/{name}", web::get().to(get_snippet))
+                    .route("/{name}", web::put().to(update_snippet))
+                    .route("/{name}", web::delete().to(delete_snippet)),
+            )
+            .service(
+                web::scope("/admin/sponsors")
+                    .route("", web::get().to(list_sponsor_slots))
+                    .route("", web::post().to(create_sponsor_slot))
+                    .route("/{slot_id}", web::delete().to(delete_sponsor_slot))
+                    .route("/{slot_id}/report", web::get().to(get_sponsor_report)),
+            )
+            .service(
+                web::scope("/admin/categories")
+                    .route("", web::get().to(list_categories))
+                    .route("", web::post().to(create_category))
+                    .route("/{name}", web::delete().to(delete_category)),
+            )
+            .service(
+                web::scope("/admin/lists")
+                    .route("", web::get().to(list_lists))
+                    .route("", web::post().to(create_list))
+                    .route("/{slug}", web::put().to(update_list))
+                    .route("/{slug}", web::delete().to(delete_list)),
+            )
+            .service(
+                web::scope("/admin/api_keys")
+                    .route("", web::get().to(list_api_keys))
+                    .route("", web::post().to(create_api_key))
+                    .route("/{api_key_id}", web::delete().to(revoke_api_key)),
+            )
+            .service(
+                web::scope("/admin/users")
+                    .route("", web::get().to(list_users))
+                    .route("", web::post().to(create_user))
+                    .route("/{username}", web::delete().to(delete_user))
+                    .route("/{username}/role", web::put().to(update_user_role)),
+            )
+            .service(
+                web::scope("/admin/subscribers")
+                    .route("", web::get().to(list_subscribers))
+                    .route("/import", web::post().to(import_subscribers))
+                    .route("/unsubscribe", web::post().to(bulk_unsubscribe))
+                    .service(
+                        web::resource("/{subscriber_id}")
+                            .route(web::get().to(get_subscriber))
+                            .route(web::delete().to(remove_subscriber))
+                            .default_service(web::to(no_matching_route)),
+                    )
+                    .route(
+                        "/{subscriber_id}/history",
+                        web::get().to(get_subscriber_history),
+                    )
+                    .route(
+                        "/{subscriber_id}/timeline",
+                        web::get().to(get_subscriber_timeline),
+                    )
+                    .service(
+                        web::scope("/{subscriber_id}/tags")
+                            .route("", web::get().to(get_subscriber_tags))
+                            .route("", web::post().to(tag_subscriber))
+                            .route("/{tag}", web::delete().to(untag_subscriber)),
+                    ),
+            )
+            .route("/admin/calendar", web::get().to(issue_calendar))
+            .route("/admin/tags", web::get().to(list_tags))
+            .route(
+                "/admin/tags/engaged_readers",
+                web::post().to(tag_engaged_readers),
+            )
+            .service(
+                web::scope("/admin/repermission_campaigns")
+                    .route("", web::get().to(list_repermission_campaigns))
+                    .route("", web::post().to(start_repermission_campaign))
+                    .route("/{campaign_id}", web::get().to(get_repermission_campaign)),
+            )
+            .service(
+                web::resource("/admin/branding")
+                    .route(web::get().to(get_branding))
+                    .route(web::put().to(update_branding))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::resource("/admin/notifications")
+                    .route(web::get().to(get_notification_preferences))
+                    .route(web::put().to(update_notification_preferences))
+                    .default_service(web::to(no_matching_route)),
+            )
+            .service(
+                web::scope("/admin/flags")
+                    .route("", web::get().to(list_flags))
+                    .route("/{name}", web::put().to(update_flag)),
+            )
+            .service(
+                web::scope("/admin/invites")
+                    .route("", web::get().to(list_invites))
+                    .route("", web::post().to(create_invites)),
+            )
+            .route("/admin/waitlist/admit", web::post().to(admit_waitlisted))
+            .route("/admin/stats", web::get().to(funnel_stats))
+            .route(
+                "/admin/stats/confirmations",
+                web::get().to(confirmation_stats),
+            )
+            .route(
+                "/admin/stats/issues/compare",
+                web::get().to(compare_newsletter_issues),
+            )
+            .route("/admin/stats/panics", web::get().to(panic_stats))
+            .route("/admin/scheduler/jobs", web::get().to(scheduler_status))
+            .route(
+                "/admin/maintenance/consistency_check",
+                web::post().to(consistency_check),
+            )
+            .route("/admin/archive/export", web::get().to(export_archive))
+            .route("/admin/archive/mailbox", web::get().to(export_mailbox))
+            .route("/admin/smoke_test", web::post().to(run_smoke_test))
+            .route("/email/webhooks/bounce", web::post().to(bounce_webhook))
+            .route("/email/webhooks/delivery", web::post().to(delivery_webhook))
+            .route("/sponsors/{slot_id}/click", web::get().to(sponsor_click))
+            .route("/sponsors/{slot_id}/open", web::get().to(sponsor_open))
+            .route(
+                "/t/{newsletter_issue_id}/{subscriber_id}/{link_id}",
+                web::get().to(track_click),
+            )
+            .route(
+                "/o/{newsletter_issue_id}/{subscriber_id}",
+                web::get().to(track_open),
+            )
+            // Routes added above must be listed in `authorization::POLICY` too, in the same
+            // order: it is what requests are authorized and 404s and 405s answered by.
+            .default_service(web::to(no_matching_route))
             .app_data(db_pool.clone())
+            .app_data(read_pool.clone())
             .app_data(email_client.clone())
+            .app_data(templates.clone())
             .app_data(base_url.clone())
+            .app_data(action_base_url.clone())
+            .app_data(key_ring.clone())
+            .app_data(token_signer.clone())
+            .app_data(pii_cipher.clone())
+            .app_data(redirect_allowed_hosts.clone())
+            .app_data(web_push_client.clone())
+            .app_data(sms_client.clone())
+            .app_data(signup_verifier.clone())
+            .app_data(confirmation_rate_limiter.clone())
+            .app_data(confirmation_rejections.clone())
+            .app_data(subscription_rate_limiter.clone())
+            .app_data(login_rate_limiter.clone())
+            .app_data(resend_confirmation_rate_limiter.clone())
+            .app_data(subscriber_cap.clone())
+            .app_data(invite_only.clone())
+            .app_data(signup_fields.clone())
+            .app_data(frequency_cap.clone())
+            .app_data(admin_allowlist.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(smoke_test_sink.clone())
+            .app_data(admin_notifications.clone())
+            .app_data(email_webhooks.clone())
+            .app_data(redis.clone())
+            .app_data(stats_cache.clone())
+            .app_data(security_headers.clone())
+            .app_data(content_checker.clone())
+            // Routes carrying the content of an issue override these with `issue_bytes`.
+            .app_data(json_config(payload_limits.json_bytes))
+            .app_data(form_config(payload_limits.form_bytes))
     })
+    // Stopped through `Application::run_until_shutdown` instead, along with the scheduler.
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
 
@@ -1,24 +1,149 @@
-use tracing::Subscriber;
+use crate::access_log::ACCESS_LOG_TARGET;
+use crate::configuration::TelemetrySettings;
+use opentelemetry::global;
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::task::JoinHandle;
+use tracing::level_filters::LevelFilter;
 use tracing::subscriber::set_global_default;
+use tracing::{Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::Targets;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{EnvFilter, Registry, layer::SubscriberExt};
 
-pub fn get_subscriber<Sink>(name: String, env_filter: String, sink: Sink) -> impl Subscriber + Send + Sync
+/// Application logs go to `sink`, filtered by `RUST_LOG` (or `env_filter`).
+/// Access logs go to `access_log_sink` as plain JSON lines, filtered by `ACCESS_LOG`
+/// (`info` by default, `off` to disable them).
+/// With a `tracer_provider`, the same spans are exported to OpenTelemetry too.
+pub fn get_subscriber<Sink, AccessLogSink>(
+    name: String,
+    env_filter: String,
+    sink: Sink,
+    access_log_sink: AccessLogSink,
+    tracer_provider: Option<&SdkTracerProvider>,
+) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    AccessLogSink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let env_filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let filter = || {
+        EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(&env_filter))
+            .add_directive(format!("{ACCESS_LOG_TARGET}=off").parse().unwrap())
+    };
+    let otlp_layer = tracer_provider.map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(name.clone()))
+            // sqlx logs its statements at trace: they become events of the span that ran them.
+            .with_filter(filter().add_directive("sqlx::query=trace".parse().unwrap()))
+    });
+    let env_filter = filter();
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+
+    let access_log_level = std::env::var("ACCESS_LOG")
+        .ok()
+        .and_then(|level| level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::INFO);
+    let access_log_layer = tracing_subscriber::fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(false)
+        .with_writer(access_log_sink)
+        .with_filter(Targets::new().with_target(ACCESS_LOG_TARGET, access_log_level));
+
     Registry::default()
-        .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(
+            JsonStorageLayer
+                .and_then(formatting_layer)
+                .with_filter(env_filter),
+        )
+        .with(access_log_layer)
+        .with(otlp_layer)
+}
+
+/// Exports spans to the OpenTelemetry collector at `otlp_endpoint`, over OTLP/HTTP, and
+/// carries traces across services in W3C `traceparent` headers. `None` without a collector.
+/// Shut the provider down before exiting, to flush the spans it holds.
+pub fn otlp_tracer_provider(
+    name: &str,
+    settings: &TelemetrySettings,
+) -> Result<Option<SdkTracerProvider>, anyhow::Error> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(name.to_owned())
+                .build(),
+        )
+        .build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
+}
+
+/// A span for an outbound HTTP request: enter it while sending, along with the headers
+/// of `trace_context_headers`.
+pub fn outbound_request_span(method: &str, url: &str) -> Span {
+    tracing::info_span!(
+        "Outbound HTTP request",
+        otel.kind = "client",
+        http.request.method = method,
+        url.full = url,
+        http.response.status_code = tracing::field::Empty,
+    )
+}
+
+/// The `traceparent` header of `span`, for the server of an outbound request to carry
+/// the trace on. Empty unless spans are exported.
+pub fn trace_context_headers(span: &Span) -> HeaderMap {
+    let context = span.context();
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Like `tokio::task::spawn_blocking`, but the closure runs inside the caller's span.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
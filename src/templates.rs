@@ -0,0 +1,507 @@
+//! Email bodies, the pages subscribers land on and the public archive of each list,
+//! rendered with Tera from the templates in `application.templates_dir`.
+//!
+//! Every email has an HTML and a plain-text template: `email/<name>.html` and
+//! `email/<name>.txt`. Only the HTML one is escaped. Debug builds read the templates
+//! from disk again on every render, so they can be edited without a restart.
+//!
+//! Translations sit next to the English templates, in a directory named after the language:
+//! `email/fr/confirmation.html` is what a subscriber who signed up in French gets, and
+//! `email/confirmation.html` if it is missing. Each template falls back on its own, so a
+//! translation can leave some out.
+//!
+//! Operational emails to users have a template set of their own, in `email/system/`: an
+//! HTML body, a plain-text one and a subject per notification, e.g.
+//! `email/system/new_login.html`, `email/system/new_login.txt` and
+//! `email/system/new_login_subject.txt`.
+//!
+//! The admin console's pages are in `pages/admin/`, and extend its `layout.html`. Those
+//! ending in `_rows.html` or `_preview.html` are fragments, which the pages include and
+//! the console swaps in on its own.
+use crate::domain::Language;
+use crate::merge_fields::escape;
+use crate::routes::{ArchivedIssue, CategoryPreference, SubscriberPage};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::sync::RwLock;
+use tera::Tera;
+
+const CONFIRMATION: &str = "email/confirmation";
+const CONFIRMATION_SUBJECT: &str = "email/confirmation_subject.txt";
+const NEWSLETTER: &str = "email/newsletter";
+const REPERMISSION: &str = "email/repermission";
+const REPERMISSION_SUBJECT: &str = "email/repermission_subject.txt";
+const UNSUBSCRIBED_PAGE: &str = "pages/unsubscribed.html";
+const STAYED_PAGE: &str = "pages/stayed.html";
+const PREFERENCES_PAGE: &str = "pages/preferences.html";
+const ARCHIVE_PAGE: &str = "pages/archive.html";
+const ARCHIVED_ISSUE_PAGE: &str = "pages/archived_issue.html";
+const ATOM_FEED: &str = "feed/atom.xml";
+const SYSTEM_NOTIFICATIONS: &str = "email/system";
+const DASHBOARD_PAGE: &str = "pages/admin/dashboard.html";
+const COMPOSER_PAGE: &str = "pages/admin/newsletters.html";
+const ISSUE_PREVIEW: &str = "pages/admin/issue_preview.html";
+const PASSWORD_PAGE: &str = "pages/admin/password.html";
+const SUBSCRIBERS_PAGE: &str = "pages/admin/subscribers.html";
+const SUBSCRIBER_ROWS: &str = "pages/admin/subscriber_rows.html";
+const STATS_PAGE: &str = "pages/admin/stats.html";
+
+/// A template of the crate's `templates` directory, by its name.
+macro_rules! built_in {
+    ($name:literal) => {
+        ($name, include_str!(concat!("../templates/", $name)))
+    };
+}
+
+/// Templates added after the email bodies, which a templates directory can leave out.
+const BUILT_IN: &[(&str, &str)] = &[
+    (
+        CONFIRMATION_SUBJECT,
+        include_str!("../templates/email/confirmation_subject.txt"),
+    ),
+    (
+        UNSUBSCRIBED_PAGE,
+        include_str!("../templates/pages/unsubscribed.html"),
+    ),
+    (
+        PREFERENCES_PAGE,
+        include_str!("../templates/pages/preferences.html"),
+    ),
+    (
+        ARCHIVE_PAGE,
+        include_str!("../templates/pages/archive.html"),
+    ),
+    (
+        ARCHIVED_ISSUE_PAGE,
+        include_str!("../templates/pages/archived_issue.html"),
+    ),
+    (ATOM_FEED, include_str!("../templates/feed/atom.xml")),
+    built_in!("email/repermission.html"),
+    built_in!("email/repermission.txt"),
+    built_in!("email/repermission_subject.txt"),
+    built_in!("pages/stayed.html"),
+    built_in!("email/system/new_login.html"),
+    built_in!("email/system/new_login.txt"),
+    built_in!("email/system/new_login_subject.txt"),
+    built_in!("email/system/password_changed.html"),
+    built_in!("email/system/password_changed.txt"),
+    built_in!("email/system/password_changed_subject.txt"),
+    built_in!("email/system/publish_completed.html"),
+    built_in!("email/system/publish_completed.txt"),
+    built_in!("email/system/publish_completed_subject.txt"),
+    built_in!("email/system/delivery_failures.html"),
+    built_in!("email/system/delivery_failures.txt"),
+    built_in!("email/system/delivery_failures_subject.txt"),
+    built_in!("pages/admin/layout.html"),
+    built_in!("pages/admin/dashboard.html"),
+    built_in!("pages/admin/newsletters.html"),
+    built_in!("pages/admin/issue_preview.html"),
+    built_in!("pages/admin/password.html"),
+    built_in!("pages/admin/subscribers.html"),
+    built_in!("pages/admin/subscriber_rows.html"),
+    built_in!("pages/admin/stats.html"),
+];
+
+pub struct EmailTemplates {
+    tera: RwLock<Tera>,
+}
+
+/// The two bodies of an email.
+pub struct EmailBody {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConfirmationEmail<'a> {
+    // The name of the list they signed up to.
+    pub newsletter: &'a str,
+    pub name: &'a str,
+    pub confirmation_link: &'a str,
+}
+
+/// Issues are rendered, and their merge fields resolved, before they get here: the
+/// content is passed through as it is, so `{{ ... }}` in an issue is never read as Tera.
+#[derive(serde::Serialize)]
+pub struct NewsletterEmail<'a> {
+    pub newsletter: &'a str,
+    pub name: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_link: &'a str,
+    pub preferences_link: &'a str,
+}
+
+/// Asks a subscriber of a re-permission campaign whether they want to stay subscribed.
+#[derive(serde::Serialize)]
+pub struct RepermissionEmail<'a> {
+    pub newsletter: &'a str,
+    pub name: &'a str,
+    pub stay_link: &'a str,
+    // When those who didn't click are unsubscribed, as a date.
+    pub closes_on: &'a str,
+}
+
+#[derive(serde::Serialize)]
+pub struct UnsubscribedPage<'a> {
+    pub newsletter: &'a str,
+}
+
+/// Where the link of a `RepermissionEmail` lands.
+#[derive(serde::Serialize)]
+pub struct StayedPage<'a> {
+    pub newsletter: &'a str,
+}
+
+/// The categories of issues a subscriber gets, as a form that posts to `action`.
+#[derive(serde::Serialize)]
+pub struct PreferencesPage<'a> {
+    pub newsletter: &'a str,
+    pub categories: &'a [CategoryPreference],
+    pub action: &'a str,
+    // Set once the form was submitted.
+    pub saved: bool,
+}
+
+/// The public issues of a list, most recent first.
+#[derive(serde::Serialize)]
+pub struct ArchivePage<'a> {
+    pub newsletter: &'a str,
+    pub issues: &'a [ArchivedIssue],
+    pub feed_link: &'a str,
+}
+
+#[derive(serde::Serialize)]
+pub struct ArchivedIssuePage<'a> {
+    pub newsletter: &'a str,
+    pub archive_link: &'a str,
+    #[serde(flatten)]
+    pub issue: &'a ArchivedIssue,
+}
+
+/// `updated` is when the most recent issue was published, as Atom requires one even
+/// for a feed without entries.
+#[derive(serde::Serialize)]
+pub struct AtomFeed<'a> {
+    pub newsletter: &'a str,
+    pub issues: &'a [ArchivedIssue],
+    pub updated: DateTime<Utc>,
+    pub self_link: &'a str,
+    pub archive_link: &'a str,
+}
+
+/// The home of the admin console, linking to the other pages `username` may use.
+#[derive(serde::Serialize)]
+pub struct DashboardPage<'a> {
+    pub username: &'a str,
+    pub can_publish: bool,
+}
+
+/// A form of the admin console, with what happened to its last submission.
+#[derive(serde::Serialize)]
+pub struct FormPage<'a> {
+    pub flash: Option<&'a str>,
+}
+
+/// An issue of the composer as the stand-in persona would get it, or why it can't be sent.
+#[derive(serde::Serialize)]
+pub struct IssuePreviewFragment<'a> {
+    pub error: Option<&'a str>,
+    pub subject: &'a str,
+    pub html: &'a str,
+    pub text: &'a str,
+}
+
+/// A page of subscribers, filtered by `status` and `search`. `previous_page` and
+/// `next_page` are links to the neighbouring pages with the same filters.
+#[derive(serde::Serialize)]
+pub struct SubscribersPage<'a> {
+    pub results: &'a SubscriberPage,
+    pub statuses: &'a [&'a str],
+    pub status: Option<&'a str>,
+    pub search: Option<&'a str>,
+    pub previous_page: &'a str,
+    pub next_page: &'a str,
+}
+
+/// The funnel stats, as `GET /admin/stats` answers with them.
+#[derive(serde::Serialize)]
+pub struct StatsPage<'a> {
+    pub stats: &'a serde_json::Value,
+}
+
+impl EmailTemplates {
+    /// Fails if any of the templates is missing or doesn't parse.
+    pub fn load(directory: &str) -> Result<Self, anyhow::Error> {
+        let mut tera = Tera::new(&format!("{directory}/**/*"))
+            .with_context(|| format!("Failed to load the templates in `{directory}`."))?;
+        tera.autoescape_on(vec![".html", ".xml"]);
+        tera.set_escape_fn(escape);
+        let mut built_in = Tera::default();
+        built_in
+            .add_raw_templates(BUILT_IN.iter().copied())
+            .context("Failed to parse the built-in templates.")?;
+        // Extended templates are kept across reloads; those in `directory` take precedence.
+        tera.extend(&built_in)?;
+        for name in [CONFIRMATION, NEWSLETTER] {
+            for extension in ["html", "txt"] {
+                let template = format!("{name}.{extension}");
+                if !tera.get_template_names().any(|loaded| loaded == template) {
+                    anyhow::bail!("The `{template}` template is missing from `{directory}`.");
+                }
+            }
+        }
+        Ok(Self {
+            tera: RwLock::new(tera),
+        })
+    }
+
+    pub fn confirmation(
+        &self,
+        email: &ConfirmationEmail<'_>,
+        language: Option<&Language>,
+    ) -> Result<EmailBody, anyhow::Error> {
+        self.render_email(CONFIRMATION, email, language)
+    }
+
+    pub fn confirmation_subject(
+        &self,
+        email: &ConfirmationEmail<'_>,
+        language: Option<&Language>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(CONFIRMATION_SUBJECT, email, language)
+    }
+
+    pub fn newsletter(&self, email: &NewsletterEmail<'_>) -> Result<EmailBody, anyhow::Error> {
+        self.render_email(NEWSLETTER, email, None)
+    }
+
+    pub fn repermission(
+        &self,
+        email: &RepermissionEmail<'_>,
+        language: Option<&Language>,
+    ) -> Result<(String, EmailBody), anyhow::Error> {
+        let subject = self.render(REPERMISSION_SUBJECT, email, language)?;
+        Ok((subject, self.render_email(REPERMISSION, email, language)?))
+    }
+
+    pub fn unsubscribed_page(
+        &self,
+        page: &UnsubscribedPage<'_>,
+        language: Option<&Language>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(UNSUBSCRIBED_PAGE, page, language)
+    }
+
+    pub fn stayed_page(
+        &self,
+        page: &StayedPage<'_>,
+        language: Option<&Language>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(STAYED_PAGE, page, language)
+    }
+
+    pub fn preferences_page(
+        &self,
+        page: &PreferencesPage<'_>,
+        language: Option<&Language>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(PREFERENCES_PAGE, page, language)
+    }
+
+    pub fn archive_page(&self, page: &ArchivePage<'_>) -> Result<String, anyhow::Error> {
+        self.render(ARCHIVE_PAGE, page, None)
+    }
+
+    pub fn archived_issue_page(
+        &self,
+        page: &ArchivedIssuePage<'_>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(ARCHIVED_ISSUE_PAGE, page, None)
+    }
+
+    pub fn atom_feed(&self, feed: &AtomFeed<'_>) -> Result<String, anyhow::Error> {
+        self.render(ATOM_FEED, feed, None)
+    }
+
+    pub fn dashboard_page(&self, page: &DashboardPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(DASHBOARD_PAGE, page, None)
+    }
+
+    pub fn composer_page(&self, page: &FormPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(COMPOSER_PAGE, page, None)
+    }
+
+    pub fn issue_preview(
+        &self,
+        fragment: &IssuePreviewFragment<'_>,
+    ) -> Result<String, anyhow::Error> {
+        self.render(ISSUE_PREVIEW, fragment, None)
+    }
+
+    pub fn password_page(&self, page: &FormPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(PASSWORD_PAGE, page, None)
+    }
+
+    pub fn subscribers_page(&self, page: &SubscribersPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(SUBSCRIBERS_PAGE, page, None)
+    }
+
+    /// The table of `subscribers_page` alone.
+    pub fn subscriber_rows(&self, page: &SubscribersPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(SUBSCRIBER_ROWS, page, None)
+    }
+
+    pub fn stats_page(&self, page: &StatsPage<'_>) -> Result<String, anyhow::Error> {
+        self.render(STATS_PAGE, page, None)
+    }
+
+    /// The subject and bodies of the `notification` operational email, e.g. `new_login`.
+    pub fn system_notification(
+        &self,
+        notification: &str,
+        values: &impl serde::Serialize,
+    ) -> Result<(String, EmailBody), anyhow::Error> {
+        let name = format!("{SYSTEM_NOTIFICATIONS}/{notification}");
+        let subject = self.render(&format!("{name}_subject.txt"), values, None)?;
+        Ok((subject, self.render_email(&name, values, None)?))
+    }
+
+    fn render_email(
+        &self,
+        name: &str,
+        values: &impl serde::Serialize,
+        language: Option<&Language>,
+    ) -> Result<EmailBody, anyhow::Error> {
+        Ok(EmailBody {
+            html: self.render(&format!("{name}.html"), values, language)?,
+            text: self.render(&format!("{name}.txt"), values, language)?,
+        })
+    }
+
+    fn render(
+        &self,
+        template: &str,
+        values: &impl serde::Serialize,
+        language: Option<&Language>,
+    ) -> Result<String, anyhow::Error> {
+        if cfg!(debug_assertions) {
+            self.tera
+                .write()
+                .unwrap()
+                .full_reload()
+                .context("Failed to reload the templates.")?;
+        }
+        let tera = self.tera.read().unwrap();
+        let template = localized(&tera, template, language);
+        let context = tera::Context::from_serialize(values)?;
+        // Editors end files with a newline: it is not part of the email.
+        tera.render(&template, &context)
+            .map(|body| body.trim_end().to_owned())
+            .with_context(|| format!("Failed to render the `{template}` template."))
+    }
+}
+
+/// The most specific translation of `template` there is, e.g. `email/pt-br/confirmation.html`,
+/// then `email/pt/confirmation.html`, then `template` itself.
+fn localized(tera: &Tera, template: &str, language: Option<&Language>) -> String {
+    let Some((directory, file)) = template.rsplit_once('/') else {
+        return template.to_owned();
+    };
+    language
+        .into_iter()
+        .flat_map(Language::fallbacks)
+        .map(|tag| format!("{directory}/{tag}/{file}"))
+        .find(|translation| tera.get_template_names().any(|name| name == translation))
+        .unwrap_or_else(|| template.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(concat!(env!("CARGO_MANIFEST_DIR"), "/templates")).unwrap()
+    }
+
+    #[test]
+    fn only_the_html_body_is_escaped() {
+        let body = templates()
+            .confirmation(
+                &ConfirmationEmail {
+                    newsletter: "Rust & Friends",
+                    name: "<Ursula>",
+                    confirmation_link: "https://example.com/confirm?token=abc",
+                },
+                None,
+            )
+            .unwrap();
+
+        assert!(body.html.contains("&lt;Ursula&gt;"));
+        assert!(body.html.contains("Rust &amp; Friends"));
+        assert!(
+            body.html
+                .contains("href=\"https://example.com/confirm?token=abc\"")
+        );
+        assert!(body.text.contains("<Ursula>"));
+        assert!(body.text.contains("Rust & Friends"));
+        assert!(body.text.contains("https://example.com/confirm?token=abc"));
+    }
+
+    #[test]
+    fn issue_content_is_not_rendered_again() {
+        let body = templates()
+            .newsletter(&NewsletterEmail {
+                newsletter: "Rust Weekly",
+                name: "Ursula",
+                html_content: "<p>{{ name }}</p>",
+                text_content: "{{ name }}",
+                unsubscribe_link: "https://example.com/unsubscribe",
+                preferences_link: "https://example.com/preferences",
+            })
+            .unwrap();
+
+        assert!(body.html.starts_with("<p>{{ name }}</p>"));
+        assert!(body.text.starts_with("{{ name }}"));
+    }
+
+    fn confirmation_subject(language: Option<&str>) -> String {
+        let language = language.map(|l| Language::parse(l).unwrap());
+        templates()
+            .confirmation_subject(
+                &ConfirmationEmail {
+                    newsletter: "Rust Weekly",
+                    name: "Ursula",
+                    confirmation_link: "https://example.com/confirm?token=abc",
+                },
+                language.as_ref(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn templates_are_looked_up_from_the_most_specific_language() {
+        assert_eq!(confirmation_subject(Some("fr")), "Bienvenue !");
+        assert_eq!(confirmation_subject(Some("fr-CA")), "Bienvenue !");
+    }
+
+    #[test]
+    fn languages_without_translations_fall_back_to_english() {
+        assert_eq!(confirmation_subject(Some("de")), "Welcome!");
+        assert_eq!(confirmation_subject(None), "Welcome!");
+    }
+
+    #[test]
+    fn missing_templates_are_reported_on_load() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(directory.join("email")).unwrap();
+        std::fs::write(directory.join("email/confirmation.html"), "Hi").unwrap();
+
+        let error = EmailTemplates::load(directory.to_str().unwrap())
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().contains("email/confirmation.txt"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
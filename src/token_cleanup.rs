@@ -0,0 +1,108 @@
+//! Deletes confirmation tokens that expired unused, and subscribers who never confirmed,
+//! as a scheduled job.
+use crate::audit_log::Source;
+use crate::configuration::{Settings, TokenCleanupSettings};
+use crate::routes::admin::delete_subscriber;
+use crate::scheduler::Job;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+pub struct TokenCleanup {
+    pool: PgPool,
+    settings: TokenCleanupSettings,
+}
+
+impl TokenCleanup {
+    /// `None` if the cleanup is disabled in the configuration.
+    pub fn from_configuration(configuration: &Settings, pool: PgPool) -> Option<Self> {
+        let settings = configuration.token_cleanup.clone()?;
+        Some(Self { pool, settings })
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for TokenCleanup {
+    fn name(&self) -> &'static str {
+        "token_cleanup"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        clean_up_tokens(&self.pool, &self.settings).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CleanupSummary {
+    pub expired_tokens: u64,
+    pub pruned_subscribers: u64,
+}
+
+/// Only pending subscribers have confirmation tokens: confirming deletes theirs, and
+/// the preference tokens of confirmed subscribers don't expire.
+#[tracing::instrument(name = "Clean up expired tokens", skip_all)]
+pub async fn clean_up_tokens(
+    pool: &PgPool,
+    settings: &TokenCleanupSettings,
+) -> Result<CleanupSummary, anyhow::Error> {
+    let pruned_subscribers = prune_pending_subscribers(pool, settings).await?;
+    let expired_tokens = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens t
+        USING subscriptions s
+        WHERE s.id = t.subscriber_id
+          AND s.status = 'pending_confirmation'
+          AND t.expires_at <= now()
+        "#
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired confirmation tokens.")?
+    .rows_affected();
+    tracing::info!(
+        expired_tokens,
+        pruned_subscribers,
+        "Cleaned up expired confirmation tokens"
+    );
+    Ok(CleanupSummary {
+        expired_tokens,
+        pruned_subscribers,
+    })
+}
+
+/// Deletes the subscribers that have been pending for longer than configured, keeping
+/// their records like any other deletion.
+/// They are locked first, so that one confirming concurrently is either kept or never was.
+async fn prune_pending_subscribers(
+    pool: &PgPool,
+    settings: &TokenCleanupSettings,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let stale = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE status = 'pending_confirmation' AND subscribed_at <= $1 AND deleted_at IS NULL
+        FOR UPDATE SKIP LOCKED
+        "#,
+        Utc::now() - settings.prune_pending_after()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscribers that never confirmed.")?;
+    let source = Source::job("token_cleanup");
+    let mut pruned = 0;
+    for subscriber_id in stale {
+        if delete_subscriber(&mut transaction, subscriber_id, &source).await? {
+            pruned += 1;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to prune pending subscribers.")?;
+    Ok(pruned)
+}
@@ -0,0 +1,217 @@
+//! Open and click tracking of issues, for lists that turn it on.
+//!
+//! Every recipient gets their own copy of the HTML: its links go through
+//! `/t/{newsletter_issue_id}/{subscriber_id}/{link_id}` on their way to where they
+//! pointed, and a pixel at `/o/{newsletter_issue_id}/{subscriber_id}` is loaded when the
+//! issue is opened. The plain-text part is left alone.
+use crate::crypto::KeyRing;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Tracking links are signed, so that opens and clicks can't be made up for any subscriber
+// by hitting the tracking endpoints directly.
+pub const TRACKED_CLICK_SIGNATURE: &str = "issue-click";
+pub const TRACKED_OPEN_SIGNATURE: &str = "issue-open";
+
+/// What is signed in the click link of `link_id` for `subscriber_id`.
+pub fn click_message(newsletter_issue_id: Uuid, subscriber_id: Uuid, link_id: i32) -> String {
+    format!("{newsletter_issue_id}/{subscriber_id}/{link_id}")
+}
+
+/// What is signed in the open pixel of `subscriber_id`.
+pub fn open_message(newsletter_issue_id: Uuid, subscriber_id: Uuid) -> String {
+    format!("{newsletter_issue_id}/{subscriber_id}")
+}
+
+/// The tracked links of an issue, numbered from 0.
+pub struct IssueTracking<'a> {
+    base_url: &'a str,
+    keys: &'a KeyRing,
+    newsletter_issue_id: Uuid,
+    // As they are written in the HTML, entities and all.
+    links: Vec<String>,
+}
+
+impl<'a> IssueTracking<'a> {
+    /// Every absolute `http(s)` link of `html` is tracked, except those to us, which are
+    /// either tracked already (sponsor links) or about the subscription, and those with
+    /// merge fields, which differ from one recipient to the next.
+    pub fn new(
+        html: &str,
+        base_url: &'a str,
+        keys: &'a KeyRing,
+        newsletter_issue_id: Uuid,
+    ) -> Self {
+        let mut links: Vec<String> = Vec::new();
+        for (_, link) in hrefs(html) {
+            let trackable = (link.starts_with("https://") || link.starts_with("http://"))
+                && !link.starts_with(base_url)
+                && !link.contains("{{");
+            if trackable && !links.iter().any(|known| known == link) {
+                links.push(link.to_owned());
+            }
+        }
+        Self {
+            base_url,
+            keys,
+            newsletter_issue_id,
+            links,
+        }
+    }
+
+    /// Where each link goes, by `link_id`.
+    pub fn targets(&self) -> impl Iterator<Item = (i32, String)> + '_ {
+        self.links
+            .iter()
+            .enumerate()
+            .map(|(link_id, link)| (link_id as i32, link.replace("&amp;", "&")))
+    }
+
+    pub fn click_url(&self, subscriber_id: Uuid, link_id: i32) -> String {
+        format!(
+            "{}/t/{}/{}/{}?signature={}",
+            self.base_url,
+            self.newsletter_issue_id,
+            subscriber_id,
+            link_id,
+            self.keys.sign(
+                TRACKED_CLICK_SIGNATURE,
+                click_message(self.newsletter_issue_id, subscriber_id, link_id).as_bytes()
+            )
+        )
+    }
+
+    pub fn open_url(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/o/{}/{}?signature={}",
+            self.base_url,
+            self.newsletter_issue_id,
+            subscriber_id,
+            self.keys.sign(
+                TRACKED_OPEN_SIGNATURE,
+                open_message(self.newsletter_issue_id, subscriber_id).as_bytes()
+            )
+        )
+    }
+
+    /// The copy of `html` for `subscriber_id`: tracked links, and the open pixel at the end.
+    pub fn track(&self, html: &str, subscriber_id: Uuid) -> String {
+        let mut tracked = String::with_capacity(html.len());
+        let mut rest = 0;
+        for (start, link) in hrefs(html) {
+            if let Some(link_id) = self.links.iter().position(|known| known == link) {
+                tracked.push_str(&html[rest..start]);
+                tracked.push_str(&self.click_url(subscriber_id, link_id as i32));
+                rest = start + link.len();
+            }
+        }
+        tracked.push_str(&html[rest..]);
+        tracked.push_str(&format!(
+            "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" />",
+            self.open_url(subscriber_id)
+        ));
+        tracked
+    }
+}
+
+/// The values of the `href` attributes of `html`, with where each starts.
+fn hrefs(html: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        loop {
+            let attribute = position + html[position..].find("href=")?;
+            position = attribute + "href=".len();
+            let quote = html[position..].chars().next()?;
+            if quote != '"' && quote != '\'' {
+                continue;
+            }
+            let start = position + 1;
+            let end = start + html[start..].find(quote)?;
+            position = end + 1;
+            return Some((start, &html[start..end]));
+        }
+    })
+}
+
+/// Keeps where the links of a tracked issue go, for `/t/...` to redirect to.
+#[tracing::instrument(name = "Store the links of an issue", skip_all)]
+pub async fn store_links(pool: &PgPool, tracking: &IssueTracking<'_>) -> Result<(), sqlx::Error> {
+    let (link_ids, urls): (Vec<i32>, Vec<String>) = tracking.targets().unzip();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_links (newsletter_issue_id, link_id, url)
+        SELECT $1, * FROM UNNEST($2::int4[], $3::text[])
+        "#,
+        tracking.newsletter_issue_id,
+        &link_ids,
+        &urls
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueTracking;
+    use crate::crypto::KeyRing;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn keys() -> KeyRing {
+        let key = SecretString::from("k".repeat(32));
+        KeyRing::new("k".into(), HashMap::from([("k".to_string(), key)])).unwrap()
+    }
+
+    #[test]
+    fn only_external_links_without_merge_fields_are_tracked() {
+        let keys = keys();
+        let html = "<a href=\"https://example.com/a?x=1&amp;y=2\">A</a>\
+            <a href='http://example.com/b'>B</a>\
+            <a href=\"https://example.com/a?x=1&amp;y=2\">A again</a>\
+            <a href=\"http://x/sponsors/1/click\">Sponsor</a>\
+            <a href=\"mailto:editor@example.com\">Mail</a>\
+            <a href=\"https://example.com/?e={{ subscriber.email }}\">Mine</a>";
+
+        let tracking = IssueTracking::new(html, "http://x", &keys, Uuid::nil());
+
+        assert_eq!(
+            tracking.targets().collect::<Vec<_>>(),
+            vec![
+                (0, "https://example.com/a?x=1&y=2".to_string()),
+                (1, "http://example.com/b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_recipient_gets_their_own_links_and_pixel() {
+        let keys = keys();
+        let html = "<p><a href=\"https://example.com\">Read</a> <a href=\"http://x/\">Us</a></p>";
+        let tracking = IssueTracking::new(html, "http://x", &keys, Uuid::nil());
+        let subscriber_id = Uuid::new_v4();
+
+        let tracked = tracking.track(html, subscriber_id);
+
+        assert_eq!(
+            tracked,
+            format!(
+                "<p><a href=\"{}\">Read</a> <a href=\"http://x/\">Us</a></p>\
+                <img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" />",
+                tracking.click_url(subscriber_id, 0),
+                tracking.open_url(subscriber_id)
+            )
+        );
+        assert!(tracking.click_url(subscriber_id, 0).starts_with(&format!(
+            "http://x/t/{}/{}/0?signature=k.",
+            Uuid::nil(),
+            subscriber_id
+        )));
+        assert_ne!(
+            tracked,
+            tracking.track(html, Uuid::new_v4()),
+            "Recipients must not share links."
+        );
+    }
+}
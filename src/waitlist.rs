@@ -0,0 +1,227 @@
+//! An optional cap on active subscribers. Past it, new signups are waitlisted and get
+//! admitted in signup order by an admin, each receiving their confirmation email then.
+use crate::audit_log::{Source, SubscriberEvent, record_all};
+use crate::domain::{Language, NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::{EmailClient, EmailError, MessageCategory};
+use crate::pii::PiiCipher;
+use crate::routes::subscriptions::{
+    generate_subscription_token, hash_subscription_token, send_confirmation_email, store_token,
+};
+use crate::runtime_flags::{RuntimeFlag, is_enabled};
+use crate::signed_tokens::TokenSigner;
+use crate::templates::EmailTemplates;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Serialises everything that counts active subscribers and then adds to them,
+// so that concurrent signups can't overshoot the cap.
+const SUBSCRIBER_CAP_LOCK: i64 = 0x7a65_726f_3270_7264;
+
+/// Pending and confirmed subscribers both count: a pending one can confirm at any time.
+async fn remaining_capacity(
+    transaction: &mut Transaction<'_, Postgres>,
+    max_active_subscribers: u64,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!("SELECT pg_advisory_xact_lock($1)", SUBSCRIBER_CAP_LOCK)
+        .execute(&mut **transaction)
+        .await?;
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM subscriptions
+        WHERE status IN ('pending_confirmation', 'confirmed') AND deleted_at IS NULL
+        "#
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(max_active_subscribers.saturating_sub(active as u64))
+}
+
+/// Whether a new signup has to go on the waitlist.
+/// Holds the cap lock until `transaction` ends.
+pub async fn is_at_capacity(
+    transaction: &mut Transaction<'_, Postgres>,
+    max_active_subscribers: Option<u64>,
+) -> Result<bool, sqlx::Error> {
+    match max_active_subscribers {
+        Some(max) => Ok(remaining_capacity(transaction, max).await? == 0),
+        None => Ok(false),
+    }
+}
+
+#[tracing::instrument(name = "Send a waitlist email", skip(email_client, new_subscriber))]
+pub async fn send_waitlist_email(
+    email_client: &EmailClient,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), EmailError> {
+    let plain_body = "Thanks for signing up to our newsletter!\n\
+        We aren't taking new subscribers right now, so you are on our waitlist. \
+        We'll email you a confirmation link as soon as there is room.";
+    let html_body = "Thanks for signing up to our newsletter!<br />\
+        We aren't taking new subscribers right now, so you are on our waitlist. \
+        We'll email you a confirmation link as soon as there is room.";
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            "You're on the waitlist",
+            html_body,
+            plain_body,
+            MessageCategory::Transactional,
+        )
+        .await
+}
+
+pub struct Admission {
+    pub admitted: usize,
+    pub still_waitlisted: i64,
+}
+
+struct WaitlistedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    // The name of the list they are waiting for.
+    newsletter: String,
+    language: Option<String>,
+}
+
+/// Move up to `count` of the longest-waiting subscribers to `pending_confirmation`,
+/// without going over the cap, and send them their confirmation email.
+/// Their `subscribed_at` is reset, so that confirmation reminders count from admission.
+#[tracing::instrument(
+    name = "Admit subscribers from the waitlist",
+    skip(pool, email_client, templates, token_signer, pii_cipher)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn admit_from_waitlist(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    token_signer: &TokenSigner,
+    pii_cipher: &PiiCipher,
+    action_base_url: &str,
+    max_active_subscribers: Option<u64>,
+    count: u64,
+    source: &Source,
+) -> Result<Admission, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let count = match max_active_subscribers {
+        Some(max) => count.min(
+            remaining_capacity(&mut transaction, max)
+                .await
+                .context("Failed to count active subscribers.")?,
+        ),
+        None => count,
+    };
+    let admitted = sqlx::query_as!(
+        WaitlistedSubscriber,
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation', subscribed_at = now()
+        WHERE id IN (
+            SELECT id FROM subscriptions WHERE status = 'waitlisted' AND deleted_at IS NULL
+            ORDER BY subscribed_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, email, name,
+            (SELECT n.name FROM newsletters n WHERE n.id = newsletter_id) AS "newsletter!",
+            language
+        "#,
+        count as i64
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to admit subscribers from the waitlist.")?;
+    let admitted_ids: Vec<_> = admitted.iter().map(|subscriber| subscriber.id).collect();
+    record_all(
+        &mut *transaction,
+        &admitted_ids,
+        SubscriberEvent::Admitted,
+        source,
+    )
+    .await
+    .context("Failed to record the admission of subscribers.")?;
+    let write_token_hashes = is_enabled(&mut *transaction, RuntimeFlag::WriteTokenHashes)
+        .await
+        .context("Failed to check a runtime flag.")?;
+    let mut confirmations = Vec::with_capacity(admitted.len());
+    for subscriber in admitted {
+        let token = generate_subscription_token();
+        let token_hash = write_token_hashes.then(|| hash_subscription_token(&token));
+        store_token(
+            &mut transaction,
+            subscriber.id,
+            &token,
+            token_hash.as_deref(),
+            None,
+        )
+        .await
+        .context("Failed to store the confirmation token for an admitted subscriber.")?;
+        confirmations.push((subscriber, token));
+    }
+    let still_waitlisted = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE status = 'waitlisted' AND deleted_at IS NULL"#
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to count waitlisted subscribers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to admit subscribers from the waitlist.")?;
+
+    let admitted = confirmations.len();
+    for (subscriber, token) in confirmations {
+        let new_subscriber = match (
+            pii_cipher
+                .decrypt(&subscriber.email)
+                .map_err(|e| e.to_string())
+                .and_then(|email| SubscriberEmail::parse(email).map_err(|e| e.to_string())),
+            pii_cipher
+                .decrypt(&subscriber.name)
+                .map_err(|e| e.to_string())
+                .and_then(|name| SubscriberName::parse(name).map_err(|e| e.to_string())),
+        ) {
+            (Ok(email), Ok(name)) => NewSubscriber {
+                email,
+                name,
+                language: subscriber
+                    .language
+                    .as_deref()
+                    .and_then(|l| Language::parse(l).ok()),
+            },
+            _ => {
+                tracing::warn!(
+                    subscriber_id = %subscriber.id,
+                    "Skipping the confirmation email of an admitted subscriber. Their stored details are invalid",
+                );
+                continue;
+            }
+        };
+        // They are admitted either way: a failed email is covered by confirmation reminders.
+        if let Err(error) = send_confirmation_email(
+            email_client,
+            templates,
+            token_signer,
+            &subscriber.newsletter,
+            new_subscriber,
+            action_base_url,
+            &token,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?error,
+                subscriber_id = %subscriber.id,
+                "Failed to send a confirmation email to an admitted subscriber",
+            );
+        }
+    }
+    Ok(Admission {
+        admitted,
+        still_waitlisted,
+    })
+}
@@ -0,0 +1,386 @@
+//! Web Push (RFC 8030) delivery, with VAPID authentication (RFC 8292)
+//! and `aes128gcm` payload encryption (RFC 8291).
+use crate::domain::Segment;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hkdf::Hkdf;
+use p256::PublicKey;
+use p256::ecdh::EphemeralSecret;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::RngCore;
+use reqwest::{Client, StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Push services must accept bodies of at least 4096 bytes: that's the record size we use,
+/// minus the header (86 bytes), the AEAD tag (16 bytes) and the padding delimiter (1 byte).
+const MAX_PAYLOAD_SIZE: usize = 3993;
+const RECORD_SIZE: u32 = 4096;
+// How long (in seconds) the push service should keep trying to deliver a notification.
+const TIME_TO_LIVE: u32 = 24 * 60 * 60;
+
+pub struct WebPushClient {
+    http_client: Client,
+    vapid_key: SigningKey,
+    subject: String,
+}
+
+/// A browser push subscription, as returned by `PushSubscription.toJSON()`.
+pub struct PushSubscription {
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+}
+
+/// What the service worker receives: it is expected to show `title` and open `url` on click.
+#[derive(serde::Serialize)]
+pub struct PushNotification<'a> {
+    pub title: &'a str,
+    pub url: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct VapidClaims<'a> {
+    aud: &'a str,
+    exp: i64,
+    sub: &'a str,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebPushError {
+    #[error("{0}")]
+    InvalidSubscription(String),
+    #[error("The push subscription has expired or was revoked by the browser.")]
+    SubscriptionGone,
+    #[error("The payload does not fit in a single push message.")]
+    PayloadTooLarge,
+    #[error("Failed to deliver the push message.")]
+    RequestError(#[from] reqwest::Error),
+}
+
+impl PushSubscription {
+    pub fn validate(&self) -> Result<(), String> {
+        self.endpoint_url()?;
+        self.keys()?;
+        Ok(())
+    }
+
+    fn endpoint_url(&self) -> Result<Url, String> {
+        match Url::parse(&self.endpoint) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+            _ => Err(format!("{} is not a valid push endpoint.", self.endpoint)),
+        }
+    }
+
+    fn keys(&self) -> Result<(PublicKey, Vec<u8>), String> {
+        let p256dh = URL_SAFE_NO_PAD
+            .decode(&self.p256dh)
+            .ok()
+            .and_then(|bytes| PublicKey::from_sec1_bytes(&bytes).ok())
+            .ok_or_else(|| "The `p256dh` key is not a valid P-256 public key.".to_string())?;
+        let auth = URL_SAFE_NO_PAD
+            .decode(&self.auth)
+            .ok()
+            .filter(|bytes| bytes.len() == 16)
+            .ok_or_else(|| "The `auth` secret must be 16 base64url-encoded bytes.".to_string())?;
+        Ok((p256dh, auth))
+    }
+}
+
+impl WebPushClient {
+    /// `vapid_private_key` is the base64url-encoded P-256 private scalar of the VAPID keypair.
+    pub fn new(
+        vapid_private_key: &SecretString,
+        subject: String,
+        timeout: std::time::Duration,
+    ) -> Result<Self, String> {
+        let vapid_key = URL_SAFE_NO_PAD
+            .decode(vapid_private_key.expose_secret())
+            .ok()
+            .and_then(|bytes| SigningKey::from_slice(&bytes).ok())
+            .ok_or_else(|| "The VAPID private key is not a valid P-256 private key.".to_string())?;
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Ok(Self {
+            http_client,
+            vapid_key,
+            subject,
+        })
+    }
+
+    /// The `applicationServerKey` browsers must use when subscribing.
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.vapid_key.verifying_key().to_encoded_point(false))
+    }
+
+    pub async fn send(
+        &self,
+        subscription: &PushSubscription,
+        payload: &[u8],
+    ) -> Result<(), WebPushError> {
+        let endpoint = subscription
+            .endpoint_url()
+            .map_err(WebPushError::InvalidSubscription)?;
+        let (p256dh, auth) = subscription
+            .keys()
+            .map_err(WebPushError::InvalidSubscription)?;
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(WebPushError::PayloadTooLarge);
+        }
+        let body = encrypt(&p256dh, &auth, payload);
+
+        let response = self
+            .http_client
+            .post(endpoint.clone())
+            .header("Authorization", self.vapid_authorization(&endpoint))
+            .header("TTL", TIME_TO_LIVE)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .body(body)
+            .send()
+            .await?;
+        // The push service tells us when the browser unsubscribed: the subscription
+        // will never work again and should be forgotten.
+        if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+            return Err(WebPushError::SubscriptionGone);
+        }
+        response.error_for_status()?;
+
+        Ok(())
+    }
+
+    fn vapid_authorization(&self, endpoint: &Url) -> String {
+        let audience = endpoint.origin().ascii_serialization();
+        let claims = VapidClaims {
+            aud: &audience,
+            exp: (chrono::Utc::now() + chrono::Duration::hours(12)).timestamp(),
+            sub: &self.subject,
+        };
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap())
+        );
+        let signature: Signature = self.vapid_key.sign(signing_input.as_bytes());
+        format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key()
+        )
+    }
+}
+
+/// Encrypt `payload` for the browser owning `ua_public`, as a single `aes128gcm` record.
+fn encrypt(ua_public: &PublicKey, auth_secret: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let as_secret = EphemeralSecret::random(&mut rng);
+    let as_public = as_secret.public_key().to_encoded_point(false);
+    let ua_public_bytes = ua_public.to_encoded_point(false);
+    let mut salt = [0u8; 16];
+    rng.fill_bytes(&mut salt);
+
+    let shared_secret = as_secret.diffie_hellman(ua_public);
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(ua_public_bytes.as_bytes());
+    key_info.extend_from_slice(as_public.as_bytes());
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth_secret), shared_secret.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .unwrap();
+
+    let prk = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+    let mut cek = [0u8; 16];
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .unwrap();
+    let mut nonce = [0u8; 12];
+    prk.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .unwrap();
+
+    // A single record, terminated by the last-record padding delimiter.
+    let mut plaintext = payload.to_vec();
+    plaintext.push(2);
+    let ciphertext = Aes128Gcm::new_from_slice(&cek)
+        .unwrap()
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .unwrap();
+
+    let mut body = salt.to_vec();
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public.as_bytes());
+    body.extend_from_slice(&ciphertext);
+    body
+}
+
+#[tracing::instrument(name = "Get push subscriptions of confirmed subscribers", skip(pool))]
+pub async fn get_push_subscriptions(
+    pool: &PgPool,
+    newsletter_id: Uuid,
+    category: Option<&str>,
+    segment: Option<&Segment>,
+) -> Result<Vec<PushSubscription>, sqlx::Error> {
+    sqlx::query_as!(
+        PushSubscription,
+        r#"
+        SELECT p.endpoint, p.p256dh, p.auth
+        FROM push_subscriptions p
+        JOIN subscriptions s ON s.id = p.subscriber_id
+        WHERE s.status = 'confirmed' AND s.newsletter_id = $4 AND NOT EXISTS (
+            SELECT 1 FROM category_opt_outs o
+            WHERE o.subscriber_id = s.id AND o.category = $1
+        ) AND ($2::text[] IS NULL OR (
+            SELECT COUNT(*) FROM subscriber_tags t
+            WHERE t.subscriber_id = s.id AND t.tag = ANY($2)
+        ) >= $3)
+        "#,
+        category,
+        segment.map(Segment::tags) as Option<Vec<String>>,
+        segment.map_or(0, Segment::required_matches),
+        newsletter_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Delete a push subscription", skip(pool))]
+pub async fn delete_push_subscription(pool: &PgPool, endpoint: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM push_subscriptions WHERE endpoint = $1"#,
+        endpoint
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PushSubscription, WebPushClient, encrypt};
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes128Gcm, Nonce};
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use claim::{assert_err, assert_ok};
+    use hkdf::Hkdf;
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::{Signature, VerifyingKey};
+    use p256::{PublicKey, SecretKey};
+    use secrecy::SecretString;
+    use sha2::Sha256;
+
+    const VAPID_PRIVATE_KEY: &str = "PNYI0LuJUpuedJqWFu-qyn2NynJBnob8ttd2rjjZaEE";
+
+    fn client() -> WebPushClient {
+        WebPushClient::new(
+            &SecretString::from(VAPID_PRIVATE_KEY),
+            "mailto:ops@example.com".into(),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    /// The browser side of RFC 8291.
+    fn decrypt(ua_secret: &SecretKey, auth_secret: &[u8], body: &[u8]) -> Vec<u8> {
+        let (salt, rest) = body.split_at(16);
+        let key_id_length = rest[4] as usize;
+        let (as_public, ciphertext) = rest[5..].split_at(key_id_length);
+        let ua_public = ua_secret.public_key().to_sec1_bytes();
+
+        let shared_secret = p256::ecdh::diffie_hellman(
+            ua_secret.to_nonzero_scalar(),
+            PublicKey::from_sec1_bytes(as_public).unwrap().as_affine(),
+        );
+        let mut key_info = b"WebPush: info\0".to_vec();
+        key_info.extend_from_slice(&ua_public);
+        key_info.extend_from_slice(as_public);
+        let mut ikm = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(auth_secret), shared_secret.raw_secret_bytes())
+            .expand(&key_info, &mut ikm)
+            .unwrap();
+        let prk = Hkdf::<Sha256>::new(Some(salt), &ikm);
+        let mut cek = [0u8; 16];
+        prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+            .unwrap();
+        let mut nonce = [0u8; 12];
+        prk.expand(b"Content-Encoding: nonce\0", &mut nonce)
+            .unwrap();
+
+        let mut plaintext = Aes128Gcm::new_from_slice(&cek)
+            .unwrap()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .unwrap();
+        assert_eq!(plaintext.pop(), Some(2));
+        plaintext
+    }
+
+    #[test]
+    fn payloads_can_be_decrypted_by_the_browser() {
+        let ua_secret = SecretKey::random(&mut rand::thread_rng());
+        let auth_secret = [7u8; 16];
+        let body = encrypt(&ua_secret.public_key(), &auth_secret, b"New issue!");
+        assert_eq!(
+            decrypt(&ua_secret, &auth_secret, &body),
+            b"New issue!".to_vec()
+        );
+    }
+
+    #[test]
+    fn vapid_tokens_are_signed_with_the_configured_key() {
+        let client = client();
+        let endpoint = reqwest::Url::parse("https://push.example.com/send/abc").unwrap();
+        let authorization = client.vapid_authorization(&endpoint);
+
+        let (token, public_key) = authorization
+            .strip_prefix("vapid t=")
+            .unwrap()
+            .split_once(", k=")
+            .unwrap();
+        assert_eq!(public_key, client.public_key());
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        let verifying_key =
+            VerifyingKey::from_sec1_bytes(&URL_SAFE_NO_PAD.decode(public_key).unwrap()).unwrap();
+        assert_ok!(verifying_key.verify(signing_input.as_bytes(), &signature));
+
+        let claims = signing_input.split('.').nth(1).unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], "https://push.example.com");
+        assert_eq!(claims["sub"], "mailto:ops@example.com");
+    }
+
+    #[test]
+    fn subscriptions_with_malformed_keys_are_rejected() {
+        let ua_public = SecretKey::random(&mut rand::thread_rng())
+            .public_key()
+            .to_sec1_bytes();
+        let valid = PushSubscription {
+            endpoint: "https://push.example.com/send/abc".into(),
+            p256dh: URL_SAFE_NO_PAD.encode(&ua_public),
+            auth: URL_SAFE_NO_PAD.encode([1u8; 16]),
+        };
+        assert_ok!(valid.validate());
+        assert_err!(
+            PushSubscription {
+                p256dh: URL_SAFE_NO_PAD.encode([4u8; 65]),
+                ..valid
+            }
+            .validate()
+        );
+    }
+
+    #[test]
+    fn invalid_vapid_keys_are_rejected() {
+        let key = SecretString::from("not-a-key");
+        assert!(
+            WebPushClient::new(&key, "mailto:ops@example.com".into(), Default::default()).is_err()
+        );
+    }
+}
@@ -0,0 +1,42 @@
+// Partial updates for the admin console. Forms, submit buttons and links with a
+// `data-target` are sent in the background with an `HX-Request` header, and the HTML
+// fragment they are answered with replaces the content of the element `data-target`
+// selects. Without JavaScript, they load a page like any other form or link.
+"use strict";
+
+async function swap(url, init, selector) {
+    const target = document.querySelector(selector);
+    init.headers = { "HX-Request": "true" };
+    init.credentials = "same-origin";
+    const response = await fetch(url, init);
+    target.innerHTML = await response.text();
+}
+
+document.addEventListener("submit", (event) => {
+    const form = event.target;
+    const submitter = event.submitter;
+    const selector = submitter?.dataset.target ?? form.dataset.target;
+    if (!selector) {
+        return;
+    }
+    event.preventDefault();
+    const url = new URL(submitter?.getAttribute("formaction") ?? form.action, location.href);
+    const data = new URLSearchParams(new FormData(form));
+    if (form.method.toLowerCase() === "get") {
+        url.search = data.toString();
+        history.replaceState(null, "", url);
+        swap(url, { method: "GET" }, selector);
+    } else {
+        swap(url, { method: "POST", body: data }, selector);
+    }
+});
+
+document.addEventListener("click", (event) => {
+    const link = event.target.closest("a[data-target]");
+    if (!link) {
+        return;
+    }
+    event.preventDefault();
+    history.replaceState(null, "", link.href);
+    swap(link.href, { method: "GET" }, link.dataset.target);
+});
@@ -0,0 +1,145 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app_with};
+use zero2prod::configuration::AdminAccessSettings;
+
+async fn spawn_app_allowing(allowed_networks: &[&str], trusted_proxies: &[&str]) -> TestApp {
+    spawn_app_with(|c| {
+        c.admin_access = Some(AdminAccessSettings {
+            allowed_networks: allowed_networks
+                .iter()
+                .map(|n| n.parse().unwrap())
+                .collect(),
+        });
+        c.application.trusted_proxies =
+            trusted_proxies.iter().map(|n| n.parse().unwrap()).collect();
+    })
+    .await
+}
+
+async fn get(app: &TestApp, path: &str, forwarded_for: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password));
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn admin_requests_from_outside_the_allowlist_are_forbidden() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &[]).await;
+
+    // Act
+    let response = get(&app, "/admin/flags", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn admin_requests_from_inside_the_allowlist_go_through() {
+    // Arrange
+    let app = spawn_app_allowing(&["127.0.0.0/8"], &[]).await;
+
+    // Act
+    let response = get(&app, "/admin/flags", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_allowlist_only_applies_to_admin_endpoints() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &[]).await;
+
+    // Act
+    let response = get(&app, "/health_check", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn percent_encoded_admin_paths_are_restricted_too() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &[]).await;
+
+    // Act
+    let response = get(&app, "/%61dmin/flags", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn forwarded_clients_are_checked_behind_a_trusted_proxy() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &["127.0.0.1/32"]).await;
+
+    // Act
+    let allowed = get(&app, "/admin/flags", Some("10.8.0.2")).await;
+    let blocked = get(&app, "/admin/flags", Some("203.0.113.9")).await;
+
+    // Assert
+    assert_eq!(allowed.status().as_u16(), 200);
+    assert_eq!(blocked.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn forwarding_headers_are_ignored_from_untrusted_peers() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &[]).await;
+
+    // Act
+    let response = get(&app, "/admin/flags", Some("10.8.0.2")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn blocked_admin_requests_are_logged() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &["127.0.0.1/32"]).await;
+
+    // Act
+    get(&app, "/admin/flags", Some("203.0.113.9")).await;
+    get(&app, "/admin/flags", Some("10.8.0.2")).await;
+    get(&app, "/health_check", Some("203.0.113.9")).await;
+
+    // Assert
+    let denials = sqlx::query!("SELECT route, source_ip FROM admin_access_denials")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(denials.len(), 1);
+    assert_eq!(denials[0].route, "GET /admin/flags");
+    assert_eq!(denials[0].source_ip.as_deref(), Some("203.0.113.9"));
+}
+
+#[tokio::test]
+async fn publishing_from_outside_the_allowlist_is_forbidden_and_logged() {
+    // Arrange
+    let app = spawn_app_allowing(&["10.8.0.0/16"], &[]).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let denials = sqlx::query!("SELECT route, source_ip FROM admin_access_denials")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(denials.len(), 1);
+    assert_eq!(denials[0].route, "POST /newsletters");
+    assert_eq!(denials[0].source_ip.as_deref(), Some("127.0.0.1"));
+}
@@ -0,0 +1,259 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn publish_form_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Logout
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Attempt to load the admin dashboard
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_publish_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_from_the_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_publish_newsletter(&publish_form_body()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logged_in_admins_can_publish_from_the_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit the form
+    let response = app.post_publish_newsletter(&publish_form_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter().await.text().await.unwrap();
+    assert!(
+        html_page
+            .contains("<p><i>The newsletter issue has been published to 1 subscribers.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn issues_rejected_from_the_dashboard_say_why() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Submit the form, without a postal address set
+    let response = app.post_publish_newsletter(&publish_form_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter().await.text().await.unwrap();
+    assert!(html_page.contains("The issue was not published: "));
+}
+
+#[tokio::test]
+async fn admin_apis_answer_anonymous_requests_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/branding", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.headers().contains_key("WWW-Authenticate"));
+}
+
+#[tokio::test]
+async fn admin_apis_accept_a_logged_in_session() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/branding", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_dashboard_links_to_the_pages_of_the_console() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+
+    // Assert
+    assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
+    for link in [
+        "/admin/dashboard/subscribers",
+        "/admin/newsletters",
+        "/admin/dashboard/stats",
+        "/admin/password",
+    ] {
+        assert!(html_page.contains(&format!("href=\"{link}\"")), "{link}");
+    }
+}
+
+#[tokio::test]
+async fn the_subscribers_page_lists_subscribers_and_filters_them_in_place() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.create_pending_subscriber("name=tolkien&email=tolkien%40gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let page = app.get_subscribers_page("", false).await;
+    let fragment = app
+        .get_subscribers_page("?status=confirmed&search=", true)
+        .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 200);
+    let page = page.text().await.unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("ursula_le_guin@gmail.com"));
+    assert!(page.contains("tolkien@gmail.com"));
+    assert_eq!(fragment.status().as_u16(), 200);
+    let fragment = fragment.text().await.unwrap();
+    assert!(!fragment.contains("<html"));
+    assert!(fragment.contains("ursula_le_guin@gmail.com"));
+    assert!(!fragment.contains("tolkien@gmail.com"));
+}
+
+#[tokio::test]
+async fn the_stats_page_shows_the_issues_published_recently() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    app.post_publish_newsletter(&publish_form_body()).await;
+
+    // Act
+    let response = app.get_stats_page().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<td>Newsletter title</td>"));
+    assert!(html_page.contains("100%"));
+}
+
+#[tokio::test]
+async fn the_composer_previews_an_issue_before_it_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_preview_newsletter(&publish_form_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let fragment = response.text().await.unwrap();
+    assert!(fragment.contains("<h2>Newsletter title</h2>"));
+    assert!(fragment.contains("srcdoc=\"&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(fragment.contains("Newsletter body as plain text"));
+}
+
+#[tokio::test]
+async fn the_composer_says_why_an_issue_could_not_be_published() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - without a postal address set
+    let response = app.post_preview_newsletter(&publish_form_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let fragment = response.text().await.unwrap();
+    assert!(fragment.contains("postal address"));
+    assert!(!fragment.contains("srcdoc"));
+}
@@ -0,0 +1,161 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app_with};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::AdminNotificationSettings;
+
+async fn spawn_app_with_notifications() -> TestApp {
+    spawn_app_with(|c| {
+        c.admin_notifications = Some(AdminNotificationSettings {
+            delivery_failure_threshold: 0.25,
+        })
+    })
+    .await
+}
+
+async fn put_notifications(app: &TestApp, notifications: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/notifications", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "notifications": notifications }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn log_in_with_user_agent(app: &TestApp, user_agent: &str) {
+    let response = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+/// The subjects of the emails sent to the test user.
+async fn notifications_received(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice::<serde_json::Value>(&request.body).unwrap())
+        .filter(|body| body["To"] == app.test_user.email.as_str())
+        .map(|body| body["Subject"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn users_are_told_of_logins_from_browsers_they_had_not_used() {
+    // Arrange
+    let app = spawn_app_with_notifications().await;
+    app.mount_email_server().await;
+
+    // Act
+    log_in_with_user_agent(&app, "Firefox").await;
+    log_in_with_user_agent(&app, "Firefox").await;
+    log_in_with_user_agent(&app, "Safari").await;
+
+    // Assert
+    assert_eq!(
+        notifications_received(&app).await,
+        vec!["New login to your account"]
+    );
+    let body = &app.email_server.received_requests().await.unwrap()[0].body;
+    assert!(String::from_utf8_lossy(body).contains("Safari"));
+}
+
+#[tokio::test]
+async fn publishers_get_a_summary_and_admins_are_alerted_of_failing_sends() {
+    // Arrange
+    let app = spawn_app_with_notifications().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(2).await;
+    put_notifications(
+        &app,
+        serde_json::json!(["publish_completed", "delivery_failures"]),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(body_string_contains("subscriber-1@example.com"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    app.mount_email_server().await;
+
+    // Act
+    let summary = app.publish(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(
+        notifications_received(&app).await,
+        vec![
+            "Published: Newsletter title",
+            "Deliveries of \"Newsletter title\" are failing",
+        ]
+    );
+    let alert = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&alert.body).contains("1 of the 2 sends"));
+    assert!(String::from_utf8_lossy(&alert.body).contains("the 25% alert threshold"));
+}
+
+#[tokio::test]
+async fn users_only_get_the_notifications_they_chose() {
+    // Arrange
+    let app = spawn_app_with_notifications().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish with the default notifications
+    app.publish(newsletter_request_body()).await;
+
+    // Act - Part 2 - Turn everything off
+    let updated: serde_json::Value = put_notifications(&app, serde_json::json!([]))
+        .await
+        .json()
+        .await
+        .unwrap();
+    log_in_with_user_agent(&app, "Firefox").await;
+    log_in_with_user_agent(&app, "Safari").await;
+
+    // Assert
+    assert_eq!(
+        updated,
+        serde_json::json!({ "email": app.test_user.email, "notifications": [] })
+    );
+    assert!(notifications_received(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_notifications_are_rejected() {
+    // Arrange
+    let app = spawn_app_with_notifications().await;
+
+    // Act
+    let response = put_notifications(&app, serde_json::json!(["new_login", "weekly_digest"])).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,394 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use uuid::Uuid;
+
+async fn delete_subscriber(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{subscriber_id}",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for request in [
+        client.get(format!("{}/admin/subscribers", &app.address)),
+        client.get(format!(
+            "{}/admin/subscribers/{}",
+            &app.address,
+            Uuid::new_v4()
+        )),
+        client.delete(format!(
+            "{}/admin/subscribers/{}",
+            &app.address,
+            Uuid::new_v4()
+        )),
+    ] {
+        // Act
+        let response = request.send().await.expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_most_recent_first_one_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_pending_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.create_pending_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await;
+    app.create_pending_subscriber("name=austen&email=jane_austen%40gmail.com")
+        .await;
+
+    // Act
+    let first: serde_json::Value = app
+        .get_admin_subscribers("?per_page=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let second: serde_json::Value = app
+        .get_admin_subscribers("?per_page=2&page=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        emails(&first),
+        vec!["jane_austen@gmail.com", "jrr_tolkien@gmail.com"]
+    );
+    assert_eq!(emails(&second), vec!["ursula_le_guin@gmail.com"]);
+    assert_eq!(first["total"], 3);
+    assert_eq!(second["page"], 2);
+    assert_eq!(first["subscribers"][0]["name"], "austen");
+    assert_eq!(first["subscribers"][0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_searched_by_email_or_name() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.create_pending_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await;
+    app.create_pending_subscriber("name=austen&email=jane_austen%40example.com")
+        .await;
+
+    // Act
+    let confirmed: serde_json::Value = app
+        .get_admin_subscribers("?status=confirmed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let by_email: serde_json::Value = app
+        .get_admin_subscribers("?search=GMAIL")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let by_name: serde_json::Value = app
+        .get_admin_subscribers("?search=Austen")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let pending_by_email: serde_json::Value = app
+        .get_admin_subscribers("?status=pending_confirmation&search=gmail")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(emails(&confirmed), vec!["ursula_le_guin@gmail.com"]);
+    assert_eq!(
+        emails(&by_email),
+        vec!["jrr_tolkien@gmail.com", "ursula_le_guin@gmail.com"]
+    );
+    assert_eq!(emails(&by_name), vec!["jane_austen@example.com"]);
+    assert_eq!(emails(&pending_by_email), vec!["jrr_tolkien@gmail.com"]);
+    assert_eq!(by_email["total"], 2);
+}
+
+#[tokio::test]
+async fn invalid_listing_parameters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for query in ["?status=asleep", "?page=0", "?per_page=0", "?per_page=101"] {
+        // Act
+        let response = app.get_admin_subscribers(query).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {query}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_looked_up_by_id() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust")
+        .await
+        .id;
+
+    // Act
+    let response = app
+        .get_admin_subscribers(&format!("/{subscriber_id}"))
+        .await;
+    let unknown = app
+        .get_admin_subscribers(&format!("/{}", Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["subscriber_id"], subscriber_id.to_string());
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["status"], "confirmed");
+    assert_eq!(subscriber["tags"], serde_json::json!(["rust"]));
+    assert_eq!(subscriber["deliveries"], 0);
+    assert!(subscriber["last_delivered_at"].is_null());
+    assert_eq!(unknown.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_looked_up_with_their_latest_deliveries_and_engagement() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    app.mount_email_server().await;
+    let mut issue_ids = Vec::new();
+    for _ in 0..6 {
+        let summary = app.publish(newsletter_request_body()).await;
+        issue_ids.push(summary["newsletter_issue_id"].as_str().unwrap().to_owned());
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        VALUES ($1, $3, $4, 'open', now()), ($2, $3, $4, 'click', now())
+        "#,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::parse_str(&issue_ids[0]).unwrap(),
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let plain: serde_json::Value = app
+        .get_admin_subscribers(&format!("/{subscriber_id}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let detailed: serde_json::Value = app
+        .get_admin_subscribers(&format!("/{subscriber_id}?include=deliveries,engagement"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let unknown = app
+        .get_admin_subscribers(&format!("/{subscriber_id}?include=plan"))
+        .await;
+
+    // Assert
+    assert!(plain.get("recent_deliveries").is_none());
+    assert!(plain.get("engagement").is_none());
+    assert_eq!(detailed["deliveries"], 6);
+    let recent: Vec<_> = detailed["recent_deliveries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|delivery| delivery["newsletter_issue_id"].as_str().unwrap())
+        .collect();
+    let latest: Vec<_> = issue_ids.iter().rev().take(5).map(String::as_str).collect();
+    assert_eq!(recent, latest);
+    assert_eq!(detailed["recent_deliveries"][0]["status"], "sent");
+    assert_eq!(detailed["engagement"]["opens"], 1);
+    assert_eq!(detailed["engagement"]["clicks"], 1);
+    assert_eq!(unknown.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_purges_their_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber = app
+        .create_pending_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = delete_subscriber(&app, subscriber.id).await;
+    let again = delete_subscriber(&app, subscriber.id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(again.status().as_u16(), 404);
+    let tokens = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber.id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens, 0);
+    let lookup = app
+        .get_admin_subscribers(&format!("/{}", subscriber.id))
+        .await;
+    assert_eq!(lookup.status().as_u16(), 404);
+    // The old confirmation link no longer works
+    let confirmation = reqwest::get(subscriber.confirmation_link).await.unwrap();
+    assert!(confirmation.status().is_client_error());
+}
+
+async fn post_bulk_unsubscribe(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/unsubscribe", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn subscribers_are_unsubscribed_in_bulk_once_the_preview_is_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    let tolkien = app
+        .create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await
+        .id;
+    let emails = "Ursula_Le_Guin@gmail.com\nnobody@example.com, not an address";
+
+    // Act - Part 1 - Preview
+    let preview: serde_json::Value =
+        post_bulk_unsubscribe(&app, serde_json::json!({ "emails": emails }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    let status = |id: Uuid| {
+        sqlx::query_scalar!("SELECT status FROM subscriptions WHERE id = $1", id)
+            .fetch_one(&app.db_pool)
+    };
+    assert_eq!(preview["dry_run"], true);
+    assert_eq!(preview["matched"], 1);
+    assert_eq!(preview["unknown"], 1);
+    assert_eq!(preview["invalid"], serde_json::json!(["not an address"]));
+    assert_eq!(status(le_guin).await.unwrap(), "confirmed");
+
+    // Act - Part 2 - Confirm
+    let report: serde_json::Value = post_bulk_unsubscribe(
+        &app,
+        serde_json::json!({ "emails": emails, "confirmation": preview["confirmation"] }),
+    )
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(report["unsubscribed"], 1);
+    assert_eq!(status(le_guin).await.unwrap(), "unsubscribed");
+    assert_eq!(status(tolkien).await.unwrap(), "confirmed");
+    let events = sqlx::query_scalar!(
+        "SELECT event FROM audit_log WHERE subscriber_id = $1 ORDER BY id",
+        le_guin
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.last().unwrap(), "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_bulk_unsubscribe_needs_a_preview_of_the_same_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let preview: serde_json::Value = post_bulk_unsubscribe(
+        &app,
+        serde_json::json!({ "emails": "ursula_le_guin@gmail.com" }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+
+    // Act
+    let forged = post_bulk_unsubscribe(
+        &app,
+        serde_json::json!({ "emails": "ursula_le_guin@gmail.com", "confirmation": "k.forged" }),
+    )
+    .await;
+    let other_list = post_bulk_unsubscribe(
+        &app,
+        serde_json::json!({
+            "emails": "ursula_le_guin@gmail.com, jrr_tolkien@gmail.com",
+            "confirmation": preview["confirmation"],
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(forged.status().as_u16(), 409);
+    // Tolkien isn't subscribed: the list matches the same subscribers as the preview.
+    assert_eq!(other_list.status().as_u16(), 200);
+    let unsubscribed = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE status = 'unsubscribed'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(unsubscribed, 1);
+}
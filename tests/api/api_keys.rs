@@ -0,0 +1,208 @@
+use crate::helpers::{TestApp, TestUser, spawn_app};
+use reqwest::Method;
+
+async fn send_as(
+    app: &TestApp,
+    user: &TestUser,
+    method: Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .request(method, format!("{}{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password));
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+/// Creates a publish-scoped key as the test user, returning its id and the key itself.
+async fn create_key(app: &TestApp) -> (String, String) {
+    let response = send_as(
+        app,
+        &app.test_user,
+        Method::POST,
+        "/admin/api_keys",
+        Some(serde_json::json!({ "name": "weekly digest", "scopes": ["publish"] })),
+    )
+    .await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    (
+        body["id"].as_str().unwrap().to_owned(),
+        body["key"].as_str().unwrap().to_owned(),
+    )
+}
+
+async fn publish_with_key(app: &TestApp, key: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .bearer_auth(key)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn a_publish_scoped_key_publishes_as_the_admin_who_created_it() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let (id, key) = create_key(&app).await;
+
+    // Act
+    let response = publish_with_key(&app, &key).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let author_id = sqlx::query_scalar!("SELECT author_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(author_id, Some(app.test_user.user_id));
+    let routes = sqlx::query_scalar!("SELECT route FROM api_key_usage")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(routes, ["POST /newsletters"]);
+
+    let keys: serde_json::Value =
+        send_as(&app, &app.test_user, Method::GET, "/admin/api_keys", None)
+            .await
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(keys[0]["id"], id.as_str());
+    assert_eq!(keys[0]["created_by"], app.test_user.username.as_str());
+    assert!(!keys[0]["last_used_at"].is_null());
+    assert!(keys[0].get("key").is_none());
+}
+
+#[tokio::test]
+async fn revoked_and_expired_keys_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let (revoked_id, revoked_key) = create_key(&app).await;
+    let (expired_id, expired_key) = create_key(&app).await;
+    let response = send_as(
+        &app,
+        &app.test_user,
+        Method::DELETE,
+        &format!("/admin/api_keys/{revoked_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(204, response.status().as_u16());
+    sqlx::query!(
+        "UPDATE api_keys SET expires_at = now() - interval '1 minute' WHERE id = $1",
+        uuid::Uuid::parse_str(&expired_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for key in [revoked_key, expired_key, "z2p_unknown".to_owned()] {
+        // Act
+        let response = publish_with_key(&app, &key).await;
+
+        // Assert
+        assert_eq!(401, response.status().as_u16(), "{key} was accepted.");
+    }
+    let issues = sqlx::query_scalar!("SELECT count(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, Some(0));
+}
+
+#[tokio::test]
+async fn keys_do_not_authenticate_other_routes() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, key) = create_key(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api_keys", &app.address))
+        .bearer_auth(&key)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn only_admins_manage_api_keys() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = TestUser::with_role("editor");
+    editor.store(&app.db_pool).await;
+
+    // Act
+    let response = send_as(
+        &app,
+        &editor,
+        Method::POST,
+        "/admin/api_keys",
+        Some(serde_json::json!({ "name": "weekly digest", "scopes": ["publish"] })),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn create_api_key_returns_a_400_for_invalid_data() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({ "name": "weekly digest", "scopes": ["admin"] }),
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({ "name": "weekly digest", "scopes": [] }),
+            "no scope",
+        ),
+        (
+            serde_json::json!({ "name": " ", "scopes": ["publish"] }),
+            "an empty name",
+        ),
+        (
+            serde_json::json!({
+                "name": "weekly digest",
+                "scopes": ["publish"],
+                "expires_at": "2020-01-01T00:00:00Z"
+            }),
+            "an expiry in the past",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = send_as(
+            &app,
+            &app.test_user,
+            Method::POST,
+            "/admin/api_keys",
+            Some(body),
+        )
+        .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {description}."
+        );
+    }
+}
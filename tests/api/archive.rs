@@ -0,0 +1,253 @@
+use crate::helpers::{TestApp, spawn_app};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::header;
+use std::io::{Cursor, Read};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn issue(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Hi {{ subscriber.name }}",
+            "html": "<p>Hi {{ subscriber.name }}</p>",
+        }
+    })
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::get(format!("{}{}", &app.address, path))
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn public_issues_are_in_the_feed_and_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let summary = app.publish(issue("Rust & Friends")).await;
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+
+    // Act
+    let feed = get(&app, "/feed.xml").await;
+    let archive = get(&app, "/archive").await;
+    let page = get(&app, &format!("/archive/{issue_id}")).await;
+
+    // Assert
+    assert_eq!(200, feed.status().as_u16());
+    assert_eq!(
+        feed.headers()[header::CONTENT_TYPE],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = feed.text().await.unwrap();
+    assert!(feed.contains("<title>Rust &amp; Friends</title>"));
+    assert!(feed.contains(&format!("<id>urn:uuid:{issue_id}</id>")));
+    assert!(feed.contains("&lt;p&gt;Hi reader&lt;/p&gt;"));
+    assert!(feed.contains(&format!("/archive/{issue_id}")));
+
+    assert_eq!(200, archive.status().as_u16());
+    let archive = archive.text().await.unwrap();
+    assert!(archive.contains("Rust &amp; Friends"));
+    assert!(archive.contains(&format!("/archive/{issue_id}")));
+
+    assert_eq!(200, page.status().as_u16());
+    assert!(page.text().await.unwrap().contains("<p>Hi reader</p>"));
+}
+
+#[tokio::test]
+async fn private_issues_are_left_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let mut body = issue("Subscribers only");
+    body["private"] = true.into();
+    let summary = app.publish(body).await;
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+
+    // Act
+    let feed = get(&app, "/feed.xml").await.text().await.unwrap();
+    let archive = get(&app, "/archive").await.text().await.unwrap();
+    let page = get(&app, &format!("/archive/{issue_id}")).await;
+
+    // Assert
+    assert!(!feed.contains("Subscribers only"));
+    assert!(!feed.contains("<entry>"));
+    assert!(!archive.contains("Subscribers only"));
+    assert_eq!(404, page.status().as_u16());
+    let problem: serde_json::Value = page.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/not-found");
+}
+
+#[tokio::test]
+async fn each_list_has_a_feed_and_an_archive_of_its_own() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    reqwest::Client::new()
+        .post(format!("{}/admin/lists", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "slug": "rust-weekly", "name": "Rust Weekly" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let mut body = issue("This week in Rust");
+    body["newsletter"] = "rust-weekly".into();
+    app.publish(body).await;
+
+    // Act
+    let default_feed = get(&app, "/feed.xml").await.text().await.unwrap();
+    let list_feed = get(&app, "/newsletters/rust-weekly/feed.xml").await;
+    let list_archive = get(&app, "/newsletters/rust-weekly/archive").await;
+    let unknown_list = get(&app, "/newsletters/go-weekly/archive").await;
+
+    // Assert
+    assert!(!default_feed.contains("This week in Rust"));
+    let list_feed = list_feed.text().await.unwrap();
+    assert!(list_feed.contains("<title>Rust Weekly</title>"));
+    assert!(list_feed.contains("This week in Rust"));
+    assert!(list_feed.contains("/newsletters/rust-weekly/feed.xml"));
+    assert!(
+        list_archive
+            .text()
+            .await
+            .unwrap()
+            .contains("This week in Rust")
+    );
+    assert_eq!(404, unknown_list.status().as_u16());
+}
+
+#[tokio::test]
+async fn feeds_can_be_revalidated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.publish(issue("Newsletter title")).await;
+    let response = get(&app, "/feed.xml").await;
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "public, no-cache"
+    );
+    assert!(response.headers().contains_key(header::LAST_MODIFIED));
+    let etag = response.headers()[header::ETAG].clone();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.xml", &app.address))
+        .header(header::IF_NONE_MATCH, etag)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(304, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_archive_exports_as_a_static_site_with_its_images() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    Mock::given(method("GET"))
+        .and(path("/logo.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"not really a png".to_vec()))
+        .mount(&app.email_server)
+        .await;
+    let logo = format!("{}/logo.png", app.email_server.uri());
+    let missing = format!("{}/missing.png", app.email_server.uri());
+    let mut body = issue("Rust & Friends");
+    body["content"]["html"] =
+        format!(r#"<p>Hi {{{{ subscriber.name }}}}</p><img src="{logo}"><img src="{missing}">"#)
+            .into();
+    let summary = app.publish(body).await;
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+    let mut private = issue("Subscribers only");
+    private["private"] = true.into();
+    app.publish(private).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/archive/export", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/zip");
+    let bundle = response.bytes().await.unwrap();
+    let mut bundle = zip::ZipArchive::new(Cursor::new(bundle)).unwrap();
+    let read = |bundle: &mut zip::ZipArchive<_>, name: &str| {
+        let mut content = String::new();
+        bundle
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    let index = read(&mut bundle, "index.html");
+    assert!(index.contains(&format!(r#"href="issues/{issue_id}.html""#)));
+    assert!(!index.contains("Subscribers only"));
+    let page = read(&mut bundle, &format!("issues/{issue_id}.html"));
+    assert!(page.contains("<p>Hi reader</p>"));
+    assert!(page.contains(r#"href="../index.html""#));
+    assert!(page.contains(&format!(r#"src="{missing}""#)));
+    let asset = bundle
+        .file_names()
+        .map(|name| name.unwrap().into_owned())
+        .find(|name| name.starts_with("assets/"))
+        .unwrap();
+    assert!(asset.ends_with(".png"));
+    assert!(page.contains(&format!(r#"src="../{asset}""#)));
+    assert_eq!(read(&mut bundle, &asset), "not really a png");
+    assert_eq!(bundle.len(), 3);
+}
+
+#[tokio::test]
+async fn every_issue_exports_as_a_message_of_a_mailbox() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.publish(issue("Rust & Friends")).await;
+    let mut private = issue("Déjà vu");
+    private["private"] = true.into();
+    app.publish(private).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/archive/mailbox", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/mbox");
+    let mailbox = response.text().await.unwrap();
+    let messages: Vec<&str> = mailbox
+        .split("\nFrom ")
+        .filter(|message| !message.is_empty())
+        .collect();
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].contains("\nSubject: Rust & Friends\n"));
+    assert!(messages[0].contains("\nTo: undisclosed-recipients:;\n"));
+    // Subjects that aren't ASCII are encoded.
+    assert!(messages[1].contains("\nSubject: =?UTF-8?B?RMOpasOgIHZ1?=\n"));
+    let html = messages[0]
+        .split("Content-Type: text/html; charset=utf-8\nContent-Transfer-Encoding: base64\n\n")
+        .nth(1)
+        .unwrap()
+        .split("\n--")
+        .next()
+        .unwrap()
+        .replace('\n', "");
+    let html = String::from_utf8(STANDARD.decode(html).unwrap()).unwrap();
+    assert!(html.contains("<p>Hi reader</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?token="));
+}
@@ -0,0 +1,100 @@
+use crate::helpers::{TestApp, spawn_app};
+
+async fn put_branding(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/branding", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_branding(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/branding", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn branding_is_empty_until_it_is_set() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_branding(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let branding: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(branding["footer_address"], serde_json::Value::Null);
+    assert_eq!(branding["social_links"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn updated_branding_is_returned_and_used_in_previews() {
+    // Arrange
+    let app = spawn_app().await;
+    let branding = serde_json::json!({
+        "logo_url": "https://example.com/logo.png",
+        "accent_color": "#ff6600",
+        "footer_address": "1 Main St, Springfield",
+        "social_links": [{"label": "Mastodon", "url": "https://example.social/@news"}]
+    });
+
+    // Act
+    let response = put_branding(&app, branding.clone()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved: serde_json::Value = get_branding(&app).await.json().await.unwrap();
+    assert_eq!(saved, branding);
+    let preview = app
+        .post_render_preview(serde_json::json!({
+            "content": {"html": "<p>Hi</p>", "text": "Hi"}
+        }))
+        .await;
+    let preview: serde_json::Value = preview.json().await.unwrap();
+    assert!(
+        preview["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("1 Main St, Springfield\nMastodon: https://example.social/@news")
+    );
+}
+
+#[tokio::test]
+async fn invalid_branding_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"accent_color": "orange"}),
+            "a named color",
+        ),
+        (
+            serde_json::json!({"logo_url": "javascript:alert(1)"}),
+            "a non-http logo url",
+        ),
+        (
+            serde_json::json!({"social_links": [{"label": "", "url": "https://example.com"}]}),
+            "an empty link label",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = put_branding(&app, body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
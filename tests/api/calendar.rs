@@ -0,0 +1,125 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app, spawn_app_with};
+use zero2prod::send_budget::SendBudgetSettings;
+
+async fn get_calendar(app: &TestApp, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/calendar{query}", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn the_calendar_lists_the_issues_of_the_month_by_day() {
+    // Arrange - Two emails at once, then one a minute
+    let app = spawn_app_with(|c| {
+        c.email_client.delivery.send_budget = Some(SendBudgetSettings {
+            messages_per_hour: 60,
+            burst: Some(2),
+        })
+    })
+    .await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    app.mount_email_server().await;
+    let last_month = app.publish(newsletter_request_body()).await;
+    let sending = app.publish(newsletter_request_body()).await;
+    // The first one went out a month ago, and its queued deliveries since.
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET published_at = '2026-09-30T23:30:00Z'
+        WHERE newsletter_issue_id = $1
+        "#,
+        uuid::Uuid::parse_str(last_month["newsletter_issue_id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET published_at = '2026-10-05T09:00:00Z'
+        WHERE newsletter_issue_id = $1
+        "#,
+        uuid::Uuid::parse_str(sending["newsletter_issue_id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1",
+        uuid::Uuid::parse_str(last_month["newsletter_issue_id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let october: serde_json::Value = get_calendar(&app, "?month=2026-10")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let september: serde_json::Value = get_calendar(&app, "?month=2026-09")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(october["month"], "2026-10");
+    assert_eq!(october["days"].as_array().unwrap().len(), 1);
+    assert_eq!(october["days"][0]["date"], "2026-10-05");
+    let issue = &october["days"][0]["issues"][0];
+    assert_eq!(issue["newsletter_issue_id"], sending["newsletter_issue_id"]);
+    assert_eq!(issue["newsletter"], "default");
+    assert_eq!(issue["status"], "sending");
+    assert_eq!(issue["recipients"], 3);
+    assert_eq!(issue["queued"], 3);
+    assert_eq!(issue["priority"], 1);
+    assert_eq!(october["warnings"], serde_json::json!([]));
+
+    assert_eq!(september["days"][0]["date"], "2026-09-30");
+    let issue = &september["days"][0]["issues"][0];
+    assert_eq!(
+        issue["newsletter_issue_id"],
+        last_month["newsletter_issue_id"]
+    );
+    assert_eq!(issue["status"], "sent");
+    assert_eq!(issue["queued"], 0);
+}
+
+#[tokio::test]
+async fn the_calendar_of_a_month_without_issues_is_empty() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_calendar(&app, "?month=2020-02").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let calendar: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        calendar,
+        serde_json::json!({"month": "2020-02", "days": [], "warnings": []})
+    );
+}
+
+#[tokio::test]
+async fn invalid_months_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for month in ["2026-13", "october", "2026-10-01"] {
+        // Act
+        let response = get_calendar(&app, &format!("?month={month}")).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "`{month}` should have been rejected"
+        );
+    }
+}
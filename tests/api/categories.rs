@@ -0,0 +1,306 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::signed_tokens::TokenPurpose;
+
+async fn create_category(app: &TestApp, name: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/categories", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "name": name, "description": "Long reads" }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn put_preferences(
+    app: &TestApp,
+    preference_token: &str,
+    categories: serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!(
+            "{}/subscriptions/categories?token={}",
+            &app.address, preference_token
+        ))
+        .json(&serde_json::json!({ "categories": categories }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn newsletter_in_category(category: &str) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["category"] = category.into();
+    body
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_categories_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = create_category(&app, "essays").await;
+    let duplicate = create_category(&app, "essays").await;
+    let invalid = create_category(&app, "Product Updates").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(duplicate.status().as_u16(), 409);
+    assert_eq!(invalid.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_get_every_category_until_they_opt_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    create_category(&app, "essays")
+        .await
+        .error_for_status()
+        .unwrap();
+    create_category(&app, "product-updates")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = put_preferences(
+        &app,
+        &preference_token,
+        serde_json::json!({ "essays": false }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let preferences: serde_json::Value = reqwest::get(format!(
+        "{}/subscriptions/categories?token={}",
+        &app.address, preference_token
+    ))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(preferences[0]["name"], "essays");
+    assert_eq!(preferences[0]["subscribed"], false);
+    assert_eq!(preferences[1]["name"], "product-updates");
+    assert_eq!(preferences[1]["subscribed"], true);
+}
+
+#[tokio::test]
+async fn preferences_for_unknown_categories_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+
+    // Act
+    let response = put_preferences(
+        &app,
+        &preference_token,
+        serde_json::json!({ "essays": false }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn preferences_require_a_known_preference_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/categories?token={}",
+        &app.address,
+        app.token_signer
+            .sign(TokenPurpose::Preferences, &"0".repeat(25))
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn issues_skip_subscribers_who_opted_out_of_their_category() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    create_category(&app, "essays")
+        .await
+        .error_for_status()
+        .unwrap();
+    create_category(&app, "product-updates")
+        .await
+        .error_for_status()
+        .unwrap();
+    put_preferences(
+        &app,
+        &preference_token,
+        serde_json::json!({ "essays": false }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let skipped = app.post_newsletters(newsletter_in_category("essays")).await;
+    let delivered = app
+        .post_newsletters(newsletter_in_category("product-updates"))
+        .await;
+
+    // Assert
+    let skipped: serde_json::Value = skipped.json().await.unwrap();
+    assert_eq!(skipped["delivered"], 0);
+    let delivered: serde_json::Value = delivered.json().await.unwrap();
+    assert_eq!(delivered["delivered"], 1);
+    // Mock verifies on Drop that only one issue went out
+}
+
+#[tokio::test]
+async fn issues_in_an_unknown_category_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_in_category("essays")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn browsers_get_a_preferences_page_in_the_language_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    create_category(&app, "essays")
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET language = 'fr'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/categories?token={}",
+            &app.address, preference_token
+        ))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("lang=\"fr\""));
+    assert!(page.contains("name=\"essays\" checked"));
+}
+
+#[tokio::test]
+async fn the_preferences_form_opts_out_of_every_unchecked_category() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    for category in ["essays", "product-updates"] {
+        create_category(&app, category)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/categories?token={}",
+            &app.address, preference_token
+        ))
+        .form(&[("essays", "on")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Your preferences have been saved."));
+    let opted_out = sqlx::query_scalar!("SELECT category FROM category_opt_outs")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(opted_out, vec!["product-updates".to_string()]);
+}
+
+#[tokio::test]
+async fn issues_link_to_the_preferences_of_their_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let (_, link) = body["TextBody"]
+        .as_str()
+        .unwrap()
+        .split_once("Preferences: ")
+        .unwrap();
+    let mut link = reqwest::Url::parse(link.lines().next().unwrap()).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    // Act
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1;
+    assert_eq!(token, preference_token);
+}
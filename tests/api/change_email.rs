@@ -0,0 +1,207 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_change_email(
+    app: &TestApp,
+    preference_token: &str,
+    email: &str,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/change_email?token={}",
+            &app.address, preference_token
+        ))
+        .form(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Asks for the change and returns the confirmation link emailed to the new address.
+async fn request_change(app: &TestApp, preference_token: &str, email: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = post_change_email(app, preference_token, email).await;
+    assert_eq!(202, response.status().as_u16());
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], email);
+    app.get_confirmation_links(&email_request).html
+}
+
+async fn subscribed_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_email_is_only_changed_once_the_new_address_is_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+
+    // Act - Part 1 - Ask for the change
+    let confirmation_link = request_change(&app, &token, "ursula@earthsea.org").await;
+
+    // Assert - Part 1 - The old address is kept meanwhile
+    assert_eq!(subscribed_emails(&app).await, ["ursula_le_guin@gmail.com"]);
+
+    // Act - Part 2 - Confirm it
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert - Part 2
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscribed_emails(&app).await, ["ursula@earthsea.org"]);
+    let events = sqlx::query_scalar!("SELECT event FROM audit_log ORDER BY id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.last().unwrap(), "email_changed");
+}
+
+#[tokio::test]
+async fn confirmation_links_only_work_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let confirmation_link = request_change(&app, &token, "ursula@earthsea.org").await;
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn asking_again_replaces_the_pending_change() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let first_link = request_change(&app, &token, "ursula@earthsea.org").await;
+    let second_link = request_change(&app, &token, "ursula@anarres.org").await;
+
+    // Act
+    let first_response = reqwest::get(first_link).await.unwrap();
+    let second_response = reqwest::get(second_link).await.unwrap();
+
+    // Assert
+    assert_eq!(401, first_response.status().as_u16());
+    assert_eq!(200, second_response.status().as_u16());
+    assert_eq!(subscribed_emails(&app).await, ["ursula@anarres.org"]);
+}
+
+#[tokio::test]
+async fn addresses_already_on_the_list_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula%40earthsea.org")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["ursula@earthsea.org", "ursula_le_guin@gmail.com"] {
+        // Act
+        let response = post_change_email(&app, &token, email).await;
+
+        // Assert
+        assert_eq!(
+            409,
+            response.status().as_u16(),
+            "The API did not reject {email}."
+        );
+    }
+}
+
+#[tokio::test]
+async fn confirming_fails_if_the_address_joined_the_list_in_the_meantime() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let confirmation_link = request_change(&app, &token, "ursula@earthsea.org").await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula%40earthsea.org")
+        .await;
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!(
+        subscribed_emails(&app).await,
+        ["ursula@earthsea.org", "ursula_le_guin@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn change_email_returns_a_400_for_an_invalid_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+
+    // Act
+    let response = post_change_email(&app, &token, "definitely-not-an-email").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/change_email/confirm?token={}",
+        &app.address,
+        "a".repeat(25)
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
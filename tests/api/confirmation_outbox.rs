@@ -0,0 +1,168 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::confirmation_outbox::{RelaySummary, relay_confirmation_emails};
+use zero2prod::domain::ActionBaseUrl;
+use zero2prod::email_client::RetryPolicy;
+use zero2prod::pii::PiiCipher;
+use zero2prod::templates::EmailTemplates;
+
+/// Every attempt of the email client fails, until the mock is used up.
+async fn fail_next_email(app: &TestApp) {
+    let attempts = RetryPolicy::default().max_attempts.into();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(attempts)
+        .expect(attempts)
+        .mount(&app.email_server)
+        .await;
+}
+
+/// The attempts at each email of the outbox, and whether it was delivered.
+async fn outbox(app: &TestApp) -> Vec<(i32, bool)> {
+    sqlx::query!(
+        "SELECT attempts, delivered_at FROM confirmation_email_outbox ORDER BY enqueued_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.attempts, row.delivered_at.is_some()))
+    .collect()
+}
+
+/// Runs the `confirmation_outbox` job, pretending that the retries are due.
+async fn relay(app: &TestApp) -> RelaySummary {
+    sqlx::query!("UPDATE confirmation_email_outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    relay_confirmation_emails(
+        &app.db_pool,
+        &app.email_client,
+        &EmailTemplates::load("templates").unwrap(),
+        &app.token_signer,
+        &PiiCipher::disabled(),
+        &ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn new_subscribers_are_stored_even_if_their_confirmation_email_fails() {
+    // Arrange
+    let app = spawn_app().await;
+    fail_next_email(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(outbox(&app).await, vec![(1, false)]);
+}
+
+#[tokio::test]
+async fn confirmation_emails_left_in_the_outbox_are_relayed() {
+    // Arrange
+    let app = spawn_app().await;
+    fail_next_email(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let summary = relay(&app).await;
+
+    // Assert
+    assert_eq!(
+        summary,
+        RelaySummary {
+            delivered: 1,
+            failed: 0
+        }
+    );
+    assert_eq!(outbox(&app).await, vec![(2, true)]);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn delivered_confirmation_emails_are_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let summary = relay(&app).await;
+
+    // Assert
+    assert_eq!(
+        summary,
+        RelaySummary {
+            delivered: 0,
+            failed: 0
+        }
+    );
+}
+
+#[tokio::test]
+async fn resending_drops_the_email_with_the_previous_link_from_the_outbox() {
+    // Arrange
+    let app = spawn_app().await;
+    fail_next_email(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+    let summary = relay(&app).await;
+
+    // Assert
+    assert_eq!(
+        summary,
+        RelaySummary {
+            delivered: 0,
+            failed: 0
+        }
+    );
+    assert_eq!(outbox(&app).await, vec![(1, true)]);
+}
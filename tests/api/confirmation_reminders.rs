@@ -0,0 +1,188 @@
+use crate::helpers::{TestApp, spawn_app};
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::ConfirmationReminderSettings;
+use zero2prod::confirmation_outbox::relay_confirmation_emails;
+use zero2prod::confirmation_reminders::send_due_reminders;
+use zero2prod::domain::ActionBaseUrl;
+use zero2prod::email_client::RetryPolicy;
+use zero2prod::pii::PiiCipher;
+use zero2prod::templates::EmailTemplates;
+
+fn settings() -> ConfirmationReminderSettings {
+    ConfirmationReminderSettings {
+        delay_hours: 48,
+        suppressed_domains: vec!["spamtrap.com".into()],
+    }
+}
+
+/// Subscribe `email` and pretend it happened `hours_ago`.
+async fn sign_up_hours_ago(app: &TestApp, email: &str, hours_ago: i64) {
+    let subscriber = app
+        .create_pending_subscriber(&format!("name=le%20guin&email={}", email))
+        .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = $1 WHERE id = $2",
+        Utc::now() - Duration::hours(hours_ago),
+        subscriber.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn send_reminders(app: &TestApp) -> usize {
+    send_due_reminders(
+        &app.db_pool,
+        &app.email_client,
+        &EmailTemplates::load("templates").unwrap(),
+        &app.token_signer,
+        &PiiCipher::disabled(),
+        &ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap(),
+        &settings(),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_reminded_once_after_the_delay() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_hours_ago(&app, "ursula_le_guin%40gmail.com", 50).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_run = send_reminders(&app).await;
+    let second_run = send_reminders(&app).await;
+
+    // Assert
+    assert_eq!(first_run, 1);
+    assert_eq!(second_run, 0);
+    // Mock verifies on Drop that a single reminder went out
+}
+
+#[tokio::test]
+async fn subscribers_are_not_reminded_before_the_delay() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_hours_ago(&app, "ursula_le_guin%40gmail.com", 1).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let sent = send_reminders(&app).await;
+
+    // Assert
+    assert_eq!(sent, 0);
+}
+
+#[tokio::test]
+async fn subscribers_on_suppressed_domains_are_not_reminded() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_hours_ago(&app, "ursula%40SpamTrap.com", 50).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let sent = send_reminders(&app).await;
+
+    // Assert
+    assert_eq!(sent, 0);
+}
+
+#[tokio::test]
+async fn failed_reminders_are_retried_from_the_confirmation_outbox() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_hours_ago(&app, "ursula_le_guin%40gmail.com", 50).await;
+    // Every attempt of the first run fails.
+    let attempts = RetryPolicy::default().max_attempts.into();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(attempts)
+        .expect(attempts)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let failed_run = send_reminders(&app).await;
+    let second_run = send_reminders(&app).await;
+    sqlx::query!("UPDATE confirmation_email_outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let relayed = relay_confirmation_emails(
+        &app.db_pool,
+        &app.email_client,
+        &EmailTemplates::load("templates").unwrap(),
+        &app.token_signer,
+        &PiiCipher::disabled(),
+        &ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(failed_run, 1);
+    // Not enqueued twice.
+    assert_eq!(second_run, 0);
+    assert_eq!(relayed.delivered, 1);
+    let reminder = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&reminder.body).contains("haven't confirmed"));
+}
+
+#[tokio::test]
+async fn stats_count_confirmations_that_followed_a_reminder() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_hours_ago(&app, "ursula_le_guin%40gmail.com", 50).await;
+    sign_up_hours_ago(&app, "le_guin%40gmail.com", 50).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    send_reminders(&app).await;
+    let reminder = &app.email_server.received_requests().await.unwrap()[2];
+    let confirmation_link = app.get_confirmation_links(reminder).html;
+
+    // Act
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let stats = app.get_confirmation_stats().await;
+    assert_eq!(stats["pending"], 1);
+    assert_eq!(stats["confirmed"], 1);
+    assert_eq!(stats["reminded"], 2);
+    assert_eq!(stats["confirmed_after_reminder"], 1);
+}
@@ -0,0 +1,238 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publishes `body` to the confirmed subscriber, returning the summary and the email they got.
+async fn publish_and_read_email(
+    app: &TestApp,
+    body: serde_json::Value,
+) -> (serde_json::Value, serde_json::Value) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let summary = app.publish(body).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    (
+        summary,
+        serde_json::from_slice(&email_request.body).unwrap(),
+    )
+}
+
+fn codes(summary: &serde_json::Value) -> Vec<&str> {
+    summary["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|warning| warning["code"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn unsafe_html_is_stripped_before_the_issue_is_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let (summary, email) = publish_and_read_email(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body",
+                "html": "<p onmouseover=\"steal()\">Newsletter body</p><script>steal()</script>",
+            }
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(codes(&summary), ["unsafe_html_removed"]);
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<p>Newsletter body</p>"));
+    assert!(!html.contains("steal()"));
+    let stored = sqlx::query_scalar!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!stored.contains("<script>"));
+}
+
+#[tokio::test]
+async fn the_text_part_is_generated_from_the_html_when_missing() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let (summary, email) = publish_and_read_email(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": "<h1>This week</h1><p>Hi {{ subscriber.name }}!</p>" }
+        }),
+    )
+    .await;
+
+    // Assert
+    assert!(codes(&summary).is_empty());
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(text.contains("This week"));
+    assert!(text.contains("Hi le guin!"));
+    assert!(!text.contains("<p>"));
+}
+
+#[tokio::test]
+async fn relative_and_broken_links_are_reported_but_the_issue_still_goes_out() {
+    // Arrange
+    let app = spawn_app_with(|c| c.content_checks.resolve_links = true).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/fine"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/gone"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&app.email_server)
+        .await;
+    let server = app.email_server.uri();
+
+    // Act
+    let (summary, _) = publish_and_read_email(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body",
+                "html": format!(
+                    "<a href=\"{server}/fine\">Fine</a> <a href=\"{server}/gone\">Gone</a> \
+                    <a href=\"/about\">About</a>"
+                ),
+            }
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(summary["delivered"], 1);
+    assert_eq!(codes(&summary), ["relative_link", "broken_link"]);
+    let broken = summary["warnings"][1]["message"].as_str().unwrap();
+    assert!(broken.contains(&format!("{server}/gone")));
+    assert!(broken.contains("404"));
+}
+
+#[tokio::test]
+async fn spammy_issues_get_a_warning() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let (summary, _) = publish_and_read_email(
+        &app,
+        serde_json::json!({
+            "title": "YOU ARE A WINNER",
+            "content": {
+                "text": "Congratulations!!! Click here, act now: this is a limited time offer.",
+                "html": "<p>Congratulations!!! Click here, act now: this is a limited time offer.</p>",
+            }
+        }),
+    )
+    .await;
+
+    // Assert
+    assert!(summary["spam_score"].as_f64().unwrap() >= 5.0);
+    assert_eq!(codes(&summary), ["spam_triggers"]);
+}
+
+#[tokio::test]
+async fn dry_runs_report_the_same_warnings() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>Body</p><img src=\"logo.png\">" },
+            "dry_run": true,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["spam_score"], 0.0);
+    assert_eq!(codes(&summary), ["relative_link"]);
+}
+
+#[tokio::test]
+async fn issues_much_like_one_already_sent_get_a_warning() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let text = "This week we look at async traits, the new borrow checker and how to \
+        profile a slow build, along with the crates we liked and the talks of the month.";
+    let sent = app
+        .publish(serde_json::json!({
+            "title": "This week in Rust",
+            "content": { "text": text, "html": format!("<p>{text}</p>") },
+        }))
+        .await;
+    let dry_run = |text: &str| {
+        app.post_newsletters(serde_json::json!({
+            "title": "Next week in Rust",
+            "content": { "text": text, "html": format!("<p>{text}</p>") },
+            "dry_run": true,
+        }))
+    };
+
+    // Act
+    let recycled: serde_json::Value = dry_run(&format!("{text} See you next week!"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let new: serde_json::Value = dry_run("A new release is out, with faster incremental builds.")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(codes(&recycled), ["similar_content"]);
+    let similar = recycled["similar_issues"].as_array().unwrap();
+    assert_eq!(similar.len(), 1);
+    assert_eq!(
+        similar[0]["newsletter_issue_id"],
+        sent["newsletter_issue_id"]
+    );
+    assert_eq!(similar[0]["title"], "This week in Rust");
+    assert!(similar[0]["similarity"].as_f64().unwrap() >= 0.8);
+    assert!(
+        recycled["warnings"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("This week in Rust")
+    );
+    assert!(codes(&new).is_empty());
+    assert_eq!(new["similar_issues"], serde_json::json!([]));
+}
@@ -0,0 +1,106 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use reqwest::{Method, header};
+use zero2prod::configuration::CorsSettings;
+
+const APP_ORIGIN: &str = "https://app.example.com";
+
+fn cors_settings() -> CorsSettings {
+    CorsSettings {
+        allowed_origins: vec![APP_ORIGIN.into()],
+        allowed_methods: vec!["GET".into(), "POST".into()],
+        allow_credentials: false,
+        max_age_seconds: 600,
+    }
+}
+
+async fn preflight(address: &str, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(Method::OPTIONS, format!("{address}/subscriptions"))
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn allowed_origins_can_call_the_api() {
+    // Arrange
+    let app = spawn_app_with(|c| c.cors = Some(cors_settings())).await;
+
+    // Act
+    let preflight = preflight(&app.address, APP_ORIGIN).await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .header(header::ORIGIN, APP_ORIGIN)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, preflight.status().as_u16());
+    assert_eq!(
+        preflight.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        APP_ORIGIN
+    );
+    assert_eq!(preflight.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+    assert!(
+        !preflight
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+    );
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        APP_ORIGIN
+    );
+}
+
+#[tokio::test]
+async fn credentials_are_only_allowed_when_configured() {
+    // Arrange
+    let mut settings = cors_settings();
+    settings.allow_credentials = true;
+    let app = spawn_app_with(|c| c.cors = Some(settings)).await;
+
+    // Act
+    let response = preflight(&app.address, APP_ORIGIN).await;
+
+    // Assert
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+        "true"
+    );
+}
+
+#[tokio::test]
+async fn other_origins_are_refused() {
+    // Arrange
+    let configured = spawn_app_with(|c| c.cors = Some(cors_settings())).await;
+    let unconfigured = spawn_app().await;
+
+    for address in [&configured.address, &unconfigured.address] {
+        // Act
+        let preflight = preflight(address, "https://evil.example.com").await;
+        let response = reqwest::Client::new()
+            .get(format!("{address}/health_check"))
+            .header(header::ORIGIN, "https://evil.example.com")
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(400, preflight.status().as_u16());
+        assert!(
+            !preflight
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+        assert_eq!(200, response.status().as_u16());
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::helpers::{TestApp, configure_database, spawn_app_with};
+use std::time::Instant;
+use uuid::Uuid;
+use wiremock::matchers::{any, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
+#[tokio::test]
+async fn queries_past_the_statement_timeout_are_cancelled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.database.pool.statement_timeout_milliseconds = Some(100)).await;
+
+    // Act
+    let outcome = sqlx::query("SELECT pg_sleep(1)")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    let error = outcome.unwrap_err();
+    let code = error.as_database_error().unwrap().code().unwrap();
+    // query_canceled
+    assert_eq!(code, "57014");
+}
+
+#[tokio::test]
+async fn startup_gives_up_once_the_database_is_still_unreachable_after_its_retries() {
+    // Arrange
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.application.port = 0;
+    // Nothing listens there
+    configuration.database.port = 1;
+    configuration.database.pool.acquire_timeout_milliseconds = 100;
+    configuration.database.connect_retry.max_attempts = 3;
+    configuration
+        .database
+        .connect_retry
+        .initial_backoff_milliseconds = 50;
+    let started_at = Instant::now();
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    let error = outcome
+        .err()
+        .expect("The application started without a database.");
+    assert!(error.to_string().contains("after 3 attempt(s)"), "{error}");
+    // Two backoffs of at least half of 50ms and 100ms
+    assert!(started_at.elapsed().as_millis() >= 75);
+}
+
+/// How many subscribers `GET /admin/subscribers` sees, and how many a new issue goes to.
+async fn read_subscribers(app: &TestApp) -> (u64, u64) {
+    let page: serde_json::Value = app.get_admin_subscribers("").await.json().await.unwrap();
+    let summary: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    (
+        page["total"].as_u64().unwrap(),
+        summary["delivered"].as_u64().unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn read_heavy_queries_go_to_the_replica() {
+    // Arrange
+    let mut replica = None;
+    let app = spawn_app_with(|c| {
+        let mut settings = c.database.clone();
+        settings.database_name = Uuid::new_v4().to_string();
+        replica = Some(settings.clone());
+        c.database_replica = Some(settings);
+    })
+    .await;
+    // A database of its own, which doesn't get what is written to the primary: what is read
+    // from it tells the two apart.
+    configure_database(&replica.unwrap()).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (listed, delivered) = read_subscribers(&app).await;
+
+    // Assert
+    assert_eq!(listed, 0);
+    assert_eq!(delivered, 0);
+}
+
+#[tokio::test]
+async fn reads_fall_back_to_the_primary_while_the_replica_is_unreachable() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let mut replica = c.database.clone();
+        // Nothing listens there
+        replica.port = 1;
+        replica.pool.acquire_timeout_milliseconds = 100;
+        c.database_replica = Some(replica);
+    })
+    .await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (listed, delivered) = read_subscribers(&app).await;
+
+    // Assert
+    assert_eq!(listed, 1);
+    assert_eq!(delivered, 1);
+}
@@ -0,0 +1,406 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app, spawn_app_with};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::SecretString;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::EmailWebhookSettings;
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn spawn_app_with_webhooks() -> TestApp {
+    spawn_app_with(|c| {
+        c.email_webhooks = Some(EmailWebhookSettings {
+            username: "postmark".into(),
+            password: SecretString::from("webhook-password"),
+        })
+    })
+    .await
+}
+
+async fn post_bounce(app: &TestApp, password: &str, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/email/webhooks/bounce", &app.address))
+        .basic_auth("postmark", Some(password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_delivery(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/email/webhooks/delivery", &app.address))
+        .basic_auth("postmark", Some("webhook-password"))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn bounce(record_type: &str, bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": record_type,
+        "ID": 42,
+        "MessageID": Uuid::new_v4(),
+        "Type": bounce_type,
+        "Email": EMAIL,
+        "BouncedAt": Utc::now(),
+    })
+}
+
+fn hard_bounce_at(message_id: &str, bounced_at: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 42,
+        "MessageID": message_id,
+        "Type": "HardBounce",
+        "Email": EMAIL,
+        "BouncedAt": bounced_at,
+    })
+}
+
+fn delivery_at(message_id: &str, delivered_at: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Delivery",
+        "MessageID": message_id,
+        "Recipient": EMAIL,
+        "DeliveredAt": delivered_at,
+    })
+}
+
+async fn subscription_status(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn delivery_statuses(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!("SELECT status FROM newsletter_delivery_attempts ORDER BY attempted_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+/// The provider's ids of the messages issues were sent in, oldest first.
+async fn message_ids(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT provider_message_id AS "provider_message_id!" FROM newsletter_delivery_attempts
+        WHERE provider_message_id IS NOT NULL
+        ORDER BY attempted_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn hard_bounces_stop_issues_going_to_the_subscriber() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "MessageID": "issue" })),
+        )
+        // Only the issue sent before the bounce.
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.publish(newsletter_request_body()).await;
+
+    // Act
+    let response = post_bounce(
+        &app,
+        "webhook-password",
+        hard_bounce_at("issue", Utc::now()),
+    )
+    .await;
+    app.publish(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "bounced");
+    assert_eq!(delivery_statuses(&app).await, ["bounced"]);
+}
+
+#[tokio::test]
+async fn bounced_deliveries_are_not_counted_as_sent() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+
+    // Act
+    let message_id = &message_ids(&app).await[0];
+    post_bounce(
+        &app,
+        "webhook-password",
+        hard_bounce_at(message_id, Utc::now()),
+    )
+    .await;
+
+    // Assert
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+    let issue: serde_json::Value = app
+        .get_newsletter_issues(&format!("/{issue_id}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["progress"]["sent"], 0);
+    assert_eq!(issue["progress"]["bounced"], 1);
+    let stats: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/stats", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["issues"][0]["sent"], 0);
+    assert_eq!(stats["issues"][0]["bounced"], 1);
+}
+
+#[tokio::test]
+async fn spam_complaints_mark_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = post_bounce(
+        &app,
+        "webhook-password",
+        bounce("SpamComplaint", "SpamComplaint"),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "bounced");
+}
+
+#[tokio::test]
+async fn soft_bounces_are_ignored() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = post_bounce(&app, "webhook-password", bounce("Bounce", "SoftBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn bounces_with_the_wrong_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = post_bounce(&app, "not-the-password", bounce("Bounce", "HardBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_bounce_webhook_is_disabled_without_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_bounce(&app, "webhook-password", bounce("Bounce", "HardBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["failed"]);
+}
+
+/// Subscribes the test address and sends them one issue, returning the provider's id of
+/// its message.
+async fn spawn_app_with_one_issue_sent() -> (TestApp, String) {
+    let app = spawn_app_with_webhooks().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.mount_email_server().await;
+    app.publish(newsletter_request_body()).await;
+    let message_id = message_ids(&app).await.remove(0);
+    (app, message_id)
+}
+
+#[tokio::test]
+async fn deliveries_are_recorded() {
+    // Arrange
+    let (app, message_id) = spawn_app_with_one_issue_sent().await;
+
+    // Act
+    let response = post_delivery(&app, delivery_at(&message_id, Utc::now())).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["delivered"]);
+}
+
+#[tokio::test]
+async fn deliveries_of_unknown_messages_are_ignored() {
+    // Arrange
+    let (app, _) = spawn_app_with_one_issue_sent().await;
+
+    // Act
+    let response = post_delivery(&app, delivery_at("a-confirmation-email", Utc::now())).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["sent"]);
+}
+
+#[tokio::test]
+async fn a_late_bounce_marks_the_issue_it_is_about_rather_than_the_latest_one() {
+    // Arrange
+    let (app, first_message_id) = spawn_app_with_one_issue_sent().await;
+    app.publish(newsletter_request_body()).await;
+
+    // Act
+    let response = post_bounce(
+        &app,
+        "webhook-password",
+        hard_bounce_at(&first_message_id, Utc::now()),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["bounced", "sent"]);
+    assert_eq!(subscription_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn a_late_delivery_does_not_overwrite_a_newer_bounce() {
+    // Arrange
+    let (app, message_id) = spawn_app_with_one_issue_sent().await;
+    let delivered_at = Utc::now();
+    post_bounce(
+        &app,
+        "webhook-password",
+        hard_bounce_at(&message_id, delivered_at + Duration::seconds(5)),
+    )
+    .await;
+
+    // Act
+    let response = post_delivery(&app, delivery_at(&message_id, delivered_at)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["bounced"]);
+    assert_eq!(subscription_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn a_late_bounce_does_not_overwrite_a_newer_delivery() {
+    // Arrange
+    let (app, message_id) = spawn_app_with_one_issue_sent().await;
+    let bounced_at = Utc::now();
+    post_delivery(
+        &app,
+        delivery_at(&message_id, bounced_at + Duration::seconds(5)),
+    )
+    .await;
+
+    // Act
+    let response = post_bounce(
+        &app,
+        "webhook-password",
+        hard_bounce_at(&message_id, bounced_at),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["delivered"]);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn bounces_sent_again_are_ignored() {
+    // Arrange
+    let app = spawn_app_with_webhooks().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let bounce = hard_bounce_at("a-confirmation-email", Utc::now());
+    post_bounce(&app, "webhook-password", bounce.clone()).await;
+    // E.g. the address was fixed and they were let back in.
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = post_bounce(&app, "webhook-password", bounce).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+    let metrics = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains(r#"email_webhook_events_discarded_total{reason="duplicate"}"#));
+}
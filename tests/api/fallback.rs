@@ -0,0 +1,123 @@
+use crate::helpers::{route_path, spawn_app};
+use reqwest::Method;
+use zero2prod::authorization::POLICY;
+
+#[tokio::test]
+async fn unknown_paths_get_a_json_404_with_suggestions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscription", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["suggestions"], serde_json::json!(["/subscriptions"]));
+}
+
+#[tokio::test]
+async fn browsers_get_an_html_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/not-a-page", &app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(
+        response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html")
+    );
+}
+
+#[tokio::test]
+async fn wrong_methods_get_a_405_with_an_allow_header() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/snippets", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 405);
+    assert_eq!(response.headers()["Allow"], "GET, POST");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "Method Not Allowed");
+}
+
+#[tokio::test]
+async fn wrong_methods_on_rate_limited_resources_get_a_405_too() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/confirm", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 405);
+    assert_eq!(response.headers()["Allow"], "GET");
+}
+
+#[tokio::test]
+async fn every_listed_route_reaches_a_handler() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut routes: Vec<_> = POLICY
+        .iter()
+        .map(|(route, method, _)| (*route, *method))
+        .collect();
+    // Logging out ends the session the admin pages need, so it goes last.
+    routes.sort_by_key(|(route, _)| *route == "/admin/logout");
+
+    for (route, method) in routes {
+        let path = route_path(route);
+
+        // Act
+        let response = app
+            .api_client
+            .request(
+                Method::from_bytes(method.as_bytes()).unwrap(),
+                format!("{}{}", &app.address, path),
+            )
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        let status = response.status().as_u16();
+        let body = response.text().await.unwrap();
+        // Handlers answer 404s with a problem document, the fallback with suggestions. An
+        // admin is only refused routes the authorization policy doesn't know.
+        let fell_through = matches!(status, 404 | 405)
+            && serde_json::from_str::<serde_json::Value>(&body)
+                .is_ok_and(|body| body.get("suggestions").is_some());
+        assert!(
+            !fell_through && status != 403,
+            "{method} {route} didn't reach a handler: {status} {body}"
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app, spawn_app_with};
+use std::collections::HashSet;
+use zero2prod::domain::ActionBaseUrl;
+use zero2prod::fault_injection::{FaultPlan, Faults};
+use zero2prod::issue_delivery::{QueueSummary, deliver_queued_issues};
+use zero2prod::pii::PiiCipher;
+use zero2prod::send_budget::SendBudgetSettings;
+use zero2prod::templates::EmailTemplates;
+
+async fn arm(app: &TestApp, plan: serde_json::Value) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/_faults", &app.address))
+        .json(&plan)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+}
+
+async fn armed(app: &TestApp) -> serde_json::Value {
+    reqwest::get(format!("{}/_faults", &app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Who the emails sent so far went to.
+async fn recipients(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+/// Confirmed subscribers at `subscriber-{n}@example.com`, with an issue published to them
+/// and queued for them again.
+async fn queue_an_issue(app: &TestApp, count: usize) {
+    app.set_postal_address().await;
+    app.mount_email_server().await;
+    for n in 0..count {
+        app.post_subscriptions(format!(
+            "name=subscriber&email=subscriber-{n}%40example.com"
+        ))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    for email_request in app.email_server.received_requests().await.unwrap() {
+        reqwest::get(app.get_confirmation_links(&email_request).html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    app.publish(newsletter_request_body()).await;
+    requeue_everybody(app).await;
+}
+
+async fn requeue_everybody(app: &TestApp) {
+    sqlx::query!("DELETE FROM issue_delivery_queue")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, enqueued_at)
+        SELECT i.newsletter_issue_id, s.id, now()
+        FROM newsletter_issues i, subscriptions s WHERE s.status = 'confirmed'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn queued_recipients(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT s.email FROM issue_delivery_queue q JOIN subscriptions s ON s.id = q.subscriber_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn deliver_queue(app: &TestApp, faults: &Faults) -> Result<QueueSummary, anyhow::Error> {
+    let templates = EmailTemplates::load("templates").unwrap();
+    let action_base_url = ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap();
+    faults
+        .scope(deliver_queued_issues(
+            &app.db_pool,
+            &app.email_client,
+            &templates,
+            &app.key_ring,
+            &app.token_signer,
+            &PiiCipher::disabled(),
+            &app.address,
+            &action_base_url,
+        ))
+        .await
+}
+
+#[tokio::test]
+async fn a_signup_is_stored_in_full_or_not_at_all_whichever_query_fails() {
+    // Arrange
+    let app = spawn_app().await;
+    app.mount_email_server().await;
+    let mut failed_signups = 0;
+
+    for n in 1.. {
+        let email = format!("subscriber-{n}@example.com");
+        arm(&app, serde_json::json!({ "fail_connection": n })).await;
+
+        // Act
+        let response = app
+            .post_subscriptions(format!(
+                "name=subscriber&email=subscriber-{n}%40example.com"
+            ))
+            .await;
+
+        // Assert
+        if armed(&app).await["fail_connection"].is_number() {
+            // The signup took fewer than `n` connections: a failure of each was tried.
+            assert_eq!(200, response.status().as_u16());
+            break;
+        }
+        let stored = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE email = $1"#,
+            email
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        match response.status().as_u16() {
+            500 => {
+                failed_signups += 1;
+                assert_eq!(
+                    stored, 0,
+                    "Connection {n} failed, yet the signup was stored."
+                );
+                assert!(!recipients(&app).await.contains(&email));
+            }
+            // The subscriber was stored before the connection failed: the confirmation
+            // email waits in the outbox.
+            200 => assert_eq!(stored, 1),
+            status => panic!("Connection {n} failed with an unexpected {status}."),
+        }
+    }
+    assert!(failed_signups > 0);
+    arm(&app, serde_json::json!({})).await;
+    assert_eq!(armed(&app).await, serde_json::json!(FaultPlan::default()));
+}
+
+#[tokio::test]
+async fn a_signup_whose_commit_drops_is_not_stored_and_can_be_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    app.mount_email_server().await;
+    arm(&app, serde_json::json!({ "drop_commit": true })).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string();
+
+    // Act - Part 1 - The commit drops
+    let dropped = app.post_subscriptions(body.clone()).await;
+    let stored = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let emails_sent = recipients(&app).await.len();
+
+    // Act - Part 2 - Signing up again
+    let retried = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(500, dropped.status().as_u16());
+    assert_eq!(stored, 0);
+    assert_eq!(emails_sent, 0);
+    assert_eq!(200, retried.status().as_u16());
+    assert_eq!(recipients(&app).await, ["ursula_le_guin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn the_delivery_worker_loses_no_delivery_whichever_query_fails() {
+    // Arrange
+    let app = spawn_app().await;
+    queue_an_issue(&app, 2).await;
+    let subscribers: HashSet<_> = ["subscriber-0@example.com", "subscriber-1@example.com"]
+        .map(String::from)
+        .into();
+    let mut failed_runs = 0;
+
+    for n in 1.. {
+        requeue_everybody(&app).await;
+        app.email_server.reset().await;
+        app.mount_email_server().await;
+        let faults = Faults::default();
+        faults.arm(&FaultPlan {
+            fail_connection: Some(n),
+            ..Default::default()
+        });
+
+        // Act
+        let outcome = deliver_queue(&app, &faults).await;
+
+        // Assert
+        if faults.armed().fail_connection.is_some() {
+            // The run took fewer than `n` connections: a failure of each was tried.
+            assert_eq!(outcome.unwrap().delivered, 2);
+            assert!(queued_recipients(&app).await.is_empty());
+            break;
+        }
+        // Either the run stops, or the delivery at hand fails and is retried.
+        failed_runs += 1;
+        // Sent, or still queued to be sent again.
+        let mut covered: HashSet<_> = recipients(&app).await.into_iter().collect();
+        covered.extend(queued_recipients(&app).await);
+        assert_eq!(
+            covered, subscribers,
+            "Connection {n} failed and lost a delivery."
+        );
+    }
+    assert!(failed_runs > 0);
+}
+
+#[tokio::test]
+async fn a_dropped_send_budget_commit_sends_nothing_and_spends_nothing() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_client.delivery.send_budget = Some(SendBudgetSettings {
+            messages_per_hour: 60,
+            burst: Some(2),
+        })
+    })
+    .await;
+    queue_an_issue(&app, 1).await;
+    let last_refilled = || async {
+        sqlx::query_scalar!("SELECT refilled_at FROM send_budget")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+    };
+    let refilled_at = last_refilled().await;
+    app.email_server.reset().await;
+    app.mount_email_server().await;
+    let faults = Faults::default();
+    faults.arm(&FaultPlan {
+        drop_commit: true,
+        ..Default::default()
+    });
+
+    // Act - Part 1 - The commit drops
+    let dropped = deliver_queue(&app, &faults).await;
+    let refilled_after_drop = last_refilled().await;
+    let emails_sent = recipients(&app).await.len();
+
+    // Act - Part 2 - The next run
+    let next = deliver_queue(&app, &faults).await.unwrap();
+
+    // Assert
+    assert!(dropped.is_err());
+    assert_eq!(emails_sent, 0);
+    // Last taken from when publishing.
+    assert_eq!(refilled_after_drop, refilled_at);
+    assert_eq!(next.delivered, 1);
+    assert!(queued_recipients(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn workers_with_slow_sends_never_send_the_same_delivery_twice() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.batch_size = 1).await;
+    queue_an_issue(&app, 4).await;
+    app.email_server.reset().await;
+    app.mount_email_server().await;
+    // Slow enough that both workers are sending at the same time.
+    let faults = Faults::default();
+    faults.arm(&FaultPlan {
+        email_delay_milliseconds: Some(200),
+        ..Default::default()
+    });
+
+    // Act
+    let (first, second) = tokio::join!(deliver_queue(&app, &faults), deliver_queue(&app, &faults));
+
+    // Assert
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert_eq!(first.delivered + second.delivered, 4);
+    assert!(first.delivered > 0 && second.delivered > 0);
+    let sent = recipients(&app).await;
+    let unique: HashSet<_> = sent.iter().collect();
+    assert_eq!(sent.len(), 4);
+    assert_eq!(unique.len(), 4);
+}
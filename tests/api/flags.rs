@@ -0,0 +1,157 @@
+use crate::helpers::{ConfirmationLinks, TestApp, TestUser, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscribe(app: &TestApp, email: &str) -> ConfirmationLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=le%20guin&email={}", email))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+#[tokio::test]
+async fn flags_are_off_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let flags: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/flags", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        flags,
+        serde_json::json!([
+            { "name": "write_token_hashes", "enabled": false },
+            { "name": "read_token_hashes", "enabled": false },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn unknown_flags_return_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.put_flag("not_a_flag", true).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn token_hashes_cannot_be_read_before_they_are_written() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.put_flag("read_token_hashes", true).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn token_hashes_cannot_stop_being_written_while_they_are_read() {
+    // Arrange
+    let app = spawn_app().await;
+    app.put_flag("write_token_hashes", true).await;
+    app.put_flag("read_token_hashes", true).await;
+
+    // Act
+    let response = app.put_flag("write_token_hashes", false).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn tokens_created_before_and_after_hashing_keep_working_once_hashes_are_read() {
+    // Arrange
+    let app = spawn_app().await;
+    let before = subscribe(&app, "ursula_le_guin%40gmail.com").await;
+    app.put_flag("write_token_hashes", true)
+        .await
+        .error_for_status()
+        .unwrap();
+    let after = subscribe(&app, "le_guin%40gmail.com").await;
+
+    // Act
+    app.put_flag("read_token_hashes", true)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    for links in [before, after] {
+        reqwest::get(links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    let pending = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM subscriptions WHERE status <> 'confirmed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(pending.count, 0);
+}
+
+#[tokio::test]
+async fn only_admins_can_change_flags() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = TestUser::with_role("editor");
+    editor.store(&app.db_pool).await;
+    let url = format!("{}/admin/flags/write_token_hashes", app.address);
+    let body = serde_json::json!({ "enabled": true });
+
+    // Act
+    let anonymous = reqwest::Client::new()
+        .put(&url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let as_editor = reqwest::Client::new()
+        .put(&url)
+        .basic_auth(&editor.username, Some(&editor.password))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(as_editor.status().as_u16(), 403);
+    let enabled =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM runtime_flags WHERE enabled"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(enabled, 0);
+}
@@ -0,0 +1,166 @@
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_funnel_stats(app: &TestApp, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/stats{}", app.address, query))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn funnel_stats(app: &TestApp) -> serde_json::Value {
+    get_funnel_stats(app, "").await.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn the_funnel_counts_signups_and_confirmations() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_pending_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.create_confirmed_subscriber("name=le%20guin&email=octavia_butler%40gmail.com")
+        .await;
+
+    // Act
+    let stats = funnel_stats(&app).await;
+
+    // Assert
+    let daily = stats["daily"].as_array().unwrap();
+    assert_eq!(daily.len(), 30);
+    let today = daily.last().unwrap();
+    assert_eq!(today["subscribed"], 2);
+    assert_eq!(today["confirmed"], 1);
+    assert_eq!(today["unsubscribed"], 0);
+    assert_eq!(daily[0]["subscribed"], 0);
+    assert_eq!(stats["confirmation_rate"], 0.5);
+    assert_eq!(stats["unsubscribe_rate"], 0.0);
+    assert!(stats["time_to_confirm_seconds"]["p50"].as_f64().unwrap() >= 0.0);
+    assert!(stats["time_to_confirm_seconds"]["p99"].is_number());
+}
+
+#[tokio::test]
+async fn an_empty_list_has_no_rates() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let stats = funnel_stats(&app).await;
+
+    // Assert
+    assert!(stats["confirmation_rate"].is_null());
+    assert!(stats["unsubscribe_rate"].is_null());
+    assert!(stats["time_to_confirm_seconds"]["p50"].is_null());
+    assert_eq!(stats["issues"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn the_funnel_reports_the_delivery_success_of_each_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let stats = funnel_stats(&app).await;
+
+    // Assert
+    let issues = stats["issues"].as_array().unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0]["title"], "Newsletter title");
+    assert_eq!(issues[0]["sent"], 1);
+    assert_eq!(issues[0]["failed"], 0);
+    assert_eq!(issues[0]["success_rate"], 1.0);
+}
+
+#[tokio::test]
+async fn funnel_stats_are_served_from_the_cache_until_they_expire() {
+    // Arrange
+    let cached = spawn_app().await;
+    let uncached = spawn_app_with(|c| c.application.stats_cache_seconds = 0).await;
+
+    for (app, expected_signups) in [(&cached, 0), (&uncached, 1)] {
+        let before = funnel_stats(app).await;
+        app.create_pending_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .await;
+
+        // Act
+        let after = funnel_stats(app).await;
+
+        // Assert
+        assert_eq!(
+            after["daily"].as_array().unwrap().last().unwrap()["subscribed"],
+            expected_signups
+        );
+        assert_eq!(
+            after["generated_at"] == before["generated_at"],
+            expected_signups == 0
+        );
+    }
+}
+
+#[tokio::test]
+async fn funnel_stats_go_back_at_most_a_year() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let too_far = get_funnel_stats(&app, "?days=366").await;
+    let zero = get_funnel_stats(&app, "?days=0").await;
+    let a_week = get_funnel_stats(&app, "?days=7").await;
+
+    // Assert
+    assert_eq!(too_far.status().as_u16(), 400);
+    assert_eq!(zero.status().as_u16(), 400);
+    let problem: serde_json::Value = zero.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    let a_week: serde_json::Value = a_week.json().await.unwrap();
+    assert_eq!(a_week["days"], 7);
+    assert_eq!(a_week["daily"].as_array().unwrap().len(), 7);
+}
+
+#[tokio::test]
+async fn stats_take_a_viewer() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+
+    for path in [
+        "/admin/stats",
+        "/admin/stats/confirmations",
+        "/admin/stats/panics",
+        "/admin/scheduler/jobs",
+    ] {
+        let url = format!("{}{}", &app.address, path);
+
+        // Act
+        let anonymous = reqwest::get(&url).await.unwrap();
+        let as_viewer = reqwest::Client::new()
+            .get(&url)
+            .basic_auth(&viewer.username, Some(&viewer.password))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(anonymous.status().as_u16(), 401, "{path}");
+        assert_eq!(as_viewer.status().as_u16(), 200, "{path}");
+    }
+}
@@ -1,4 +1,6 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use sqlx::{Connection, Executor, PgConnection};
+use zero2prod::configuration::get_configuration;
 
 #[tokio::test]
 async fn health_check_works() {
@@ -6,7 +8,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request");
@@ -14,3 +16,82 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn readiness_reports_every_component() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/health_check/ready", &app.address))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["components"]["database"]["status"], "ok");
+    assert_eq!(body["components"]["database"]["critical"], true);
+    assert_eq!(body["components"]["email_provider"]["status"], "ok");
+    // The in-memory cache isn't probed.
+    assert!(body["components"].get("redis").is_none());
+}
+
+#[tokio::test]
+async fn an_unreachable_email_provider_only_degrades_readiness() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        // Nothing listens on the discard port.
+        c.email_client.base_url = "http://127.0.0.1:9".into();
+    })
+    .await;
+
+    // Act
+    let response = reqwest::get(format!("{}/health_check/ready", &app.address))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["email_provider"]["status"], "degraded");
+    assert!(body["components"]["email_provider"]["error"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_fails_without_the_database() {
+    // Arrange
+    let app = spawn_app().await;
+    let database_name: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    app.db_pool.close().await;
+    let configuration = get_configuration().unwrap();
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap();
+    connection
+        .execute(format!(r#"DROP DATABASE "{database_name}" WITH (FORCE);"#).as_str())
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/health_check/ready", &app.address))
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "failing");
+    assert_eq!(body["components"]["database"]["status"], "failing");
+
+    // The liveness probe doesn't care.
+    let response = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
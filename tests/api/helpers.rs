@@ -1,20 +1,100 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
+use zero2prod::crypto::KeyRing;
+use zero2prod::email_client::EmailClient;
+use zero2prod::shutdown::{Shutdown, ShutdownTrigger};
+use zero2prod::signed_tokens::TokenSigner;
 use zero2prod::startup::{Application, get_connection_pool};
 
 use zero2prod::{
-    configuration::{DatabaseSettings, get_configuration},
+    configuration::{DatabaseSettings, Settings, SmsSettings, WebPushSettings, get_configuration},
     telemetry::{get_subscriber, init_subscriber},
 };
 
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub email_client: EmailClient,
+    pub key_ring: KeyRing,
+    pub token_signer: TokenSigner,
+    pub test_user: TestUser,
+    // Keeps the session cookie between requests and doesn't follow redirects.
+    pub api_client: reqwest::Client,
+    pub shutdown: ShutdownTrigger,
+}
+
+/// A publisher, allowed to send out issues.
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+    // Where password reset links go.
+    pub email: String,
+    // `admin`, `editor` or `viewer`.
+    pub role: &'static str,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
+            role: "admin",
+        }
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
+        Self {
+            role,
+            ..Self::generate()
+        }
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &self.username,
+                "password": &self.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // The cheapest parameters Argon2 accepts: tests only need the hash to verify,
+        // and verification reads its parameters back from the hash.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(Params::MIN_M_COST, 1, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash, email, role) \
+            VALUES ($1, $2, $3, $4, $5)",
+            self.user_id,
+            self.username,
+            password_hash,
+            self.email,
+            self.role,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 /// Confirmation links embedded in the request to the email API.
@@ -23,22 +103,67 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+/// A subscriber signed up through the subscription form.
+pub struct TestSubscriber {
+    pub id: Uuid,
+    pub confirmation_link: reqwest::Url,
+    /// Handed out when they confirm, `None` until then.
+    pub preference_token: Option<String>,
+}
+
+impl TestSubscriber {
+    /// The token of their preference pages, for confirmed subscribers.
+    pub fn preference_token(&self) -> String {
+        self.preference_token
+            .clone()
+            .expect("Only confirmed subscribers have a preference token")
+    }
+}
+
+/// A newsletter issue with nothing but a title and a body, as posted to `/newsletters`.
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
     if std::env::var("TEST_LOG").is_ok() {
         // TEST_LOG=true cargo test health_check_works | bunyan
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            std::io::stdout,
+            None,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            std::io::sink,
+            None,
+        );
         // std::io::sink discards all writes
         init_subscriber(subscriber);
     };
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a chance to tweak the configuration first.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -51,6 +176,24 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        c.application.redirect_allowed_hosts = vec!["example.com".into()];
+        c.web_push = Some(WebPushSettings {
+            vapid_private_key: "PNYI0LuJUpuedJqWFu-qyn2NynJBnob8ttd2rjjZaEE"
+                .to_string()
+                .into(),
+            subject: "mailto:ops@example.com".into(),
+            timeout_milliseconds: 1000,
+        });
+        c.sms = Some(SmsSettings {
+            base_url: email_server.uri(),
+            account_sid: "AC123".into(),
+            auth_token: "sms-secret-token".to_string().into(),
+            sender: "+15005550006".into(),
+            timeout_milliseconds: 1000,
+        });
+        // Issues link to made-up sites: only the tests about links resolve them.
+        c.content_checks.resolve_links = false;
+        customise(&mut c);
         c
     };
 
@@ -63,16 +206,29 @@ pub async fn spawn_app() -> TestApp {
 
     // Get the port before spawning the application
     let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
-    TestApp {
+    let (shutdown, requested) = Shutdown::new();
+    tokio::spawn(application.run_until_shutdown(requested));
+    let test_app = TestApp {
         address,
+        shutdown,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         port: application_port,
-    }
+        email_client: configuration.email_client.client(),
+        key_ring: configuration.signing.key_ring().unwrap(),
+        token_signer: configuration.signing.token_signer().unwrap(),
+        test_user: TestUser::generate(),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
-async fn configure_database(config: &DatabaseSettings) -> PgPool {
+pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
     // Create database
     let mut connection = PgConnection::connect_with(&config.without_db())
         .await
@@ -99,7 +255,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -107,15 +263,389 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/resend_confirmation",
+                &self.address
+            ))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// Publishes `body` to every confirmed subscriber and returns the summary of the issue.
+    pub async fn publish(&self, body: serde_json::Value) -> serde_json::Value {
+        self.post_newsletters(body)
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/newsletters`, e.g. `?page=2` or `/{id}`.
+    pub async fn get_newsletter_issues(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `path` is relative to `/admin/subscribers`, e.g. `?status=confirmed` or `/{id}`.
+    pub async fn get_admin_subscribers(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.get_with_session("/login").await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.get_with_session("/admin/dashboard").await
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.get_with_session("/admin/newsletters").await
+    }
+
+    pub async fn post_publish_newsletter<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `fragment` asks for the table alone, as the console script does.
+    pub async fn get_subscribers_page(&self, query: &str, fragment: bool) -> reqwest::Response {
+        let mut request = self
+            .api_client
+            .get(format!("{}/admin/dashboard/subscribers{}", &self.address, query));
+        if fragment {
+            request = request.header("HX-Request", "true");
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    pub async fn get_stats_page(&self) -> reqwest::Response {
+        self.get_with_session("/admin/dashboard/stats").await
+    }
+
+    pub async fn post_preview_newsletter<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/preview", &self.address))
+            .header("HX-Request", "true")
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.get_with_session("/admin/password").await
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password_reset", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_password_reset_html(&self) -> String {
+        self.get_with_session("/password_reset")
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_password_reset_confirm<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password_reset/confirm", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn get_with_session(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Issues can't be published without a postal address in their footer.
+    pub async fn set_postal_address(&self) {
+        reqwest::Client::new()
+            .put(format!("{}/admin/branding", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "footer_address": "1 Main St, Springfield" }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn post_render_preview(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/admin/newsletters/render_preview",
+                &self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_push_subscription(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/push/subscribe", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_snippet(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/snippets", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_snippet(&self, name: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/snippets/{}", &self.address, name))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_flag(&self, name: &str, enabled: bool) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/flags/{}", &self.address, name))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "enabled": enabled }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_invites(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/invites", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_waitlist_admission(&self, count: u64) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/waitlist/admit", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "count": count }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_sponsor_slot(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/sponsors", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_sponsor_report(&self, slot_id: &str) -> serde_json::Value {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/sponsors/{}/report",
+                &self.address, slot_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_confirmation_stats(&self) -> serde_json::Value {
+        reqwest::Client::new()
+            .get(format!("{}/admin/stats/confirmations", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// Answers every email sent from now on with a 200 and, like Postmark, a new `MessageID`.
+    pub async fn mount_email_server(&self) {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(|_: &wiremock::Request| {
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "MessageID": Uuid::new_v4() }))
+            })
+            .mount(&self.email_server)
+            .await;
+    }
+
+    /// Signs up with the subscription form `body`, leaving the subscriber to confirm.
+    pub async fn create_pending_subscriber(&self, body: &str) -> TestSubscriber {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let id =
+            sqlx::query_scalar!("SELECT id FROM subscriptions ORDER BY subscribed_at DESC LIMIT 1")
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap();
+        TestSubscriber {
+            id,
+            confirmation_link: self.get_confirmation_links(&email_request).html,
+            preference_token: None,
+        }
+    }
+
+    /// Signs up with the subscription form `body` and follows the confirmation link.
+    pub async fn create_confirmed_subscriber(&self, body: &str) -> TestSubscriber {
+        let mut subscriber = self.create_pending_subscriber(body).await;
+        let confirmed: serde_json::Value = reqwest::get(subscriber.confirmation_link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        subscriber.preference_token = Some(confirmed["preference_token"].as_str().unwrap().into());
+        subscriber
+    }
+
+    /// Confirmed subscribers at `subscriber-{n}@example.com`.
+    pub async fn create_confirmed_subscribers(&self, count: usize) {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(count as u64)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        for n in 0..count {
+            self.post_subscriptions(format!(
+                "name=subscriber&email=subscriber-{n}%40example.com"
+            ))
+            .await
+            .error_for_status()
+            .unwrap();
+        }
+        for email_request in self.email_server.received_requests().await.unwrap() {
+            reqwest::get(self.get_confirmation_links(&email_request).html)
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap();
+        }
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         // Extract the link from one of the request fields.
@@ -134,8 +664,25 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+/// A path to `route`, with a fresh uuid for each of its `{parameters}`.
+pub fn route_path(route: &str) -> String {
+    route
+        .split('/')
+        .map(|segment| match segment.starts_with('{') {
+            true => Uuid::new_v4().to_string(),
+            false => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
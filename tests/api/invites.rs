@@ -0,0 +1,238 @@
+use crate::helpers::{TestApp, TestUser, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn spawn_invite_only_app() -> TestApp {
+    let app = spawn_app_with(|c| c.application.invite_only = true).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn create_invite(app: &TestApp, body: serde_json::Value) -> String {
+    let response: serde_json::Value = app
+        .post_invites(body)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    response["codes"][0].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn signups_without_an_invite_are_rejected_in_invite_only_mode() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn signups_with_an_invite_are_attributed_to_it() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let code = create_invite(&app, serde_json::json!({})).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&invite_code={}",
+            code.to_lowercase()
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT invite_code FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.invite_code, Some(code));
+    let invites: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/invites", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(invites[0]["uses"], 1);
+    assert_eq!(invites[0]["subscribers"], 1);
+}
+
+#[tokio::test]
+async fn invites_cannot_be_used_more_than_allowed() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let code = create_invite(&app, serde_json::json!({ "max_uses": 2 })).await;
+
+    // Act
+    let statuses: Vec<u16> = {
+        let mut statuses = Vec::new();
+        for email in ["a%40gmail.com", "b%40gmail.com", "c%40gmail.com"] {
+            let response = app
+                .post_subscriptions(format!(
+                    "name=le%20guin&email={}&invite_code={}",
+                    email, code
+                ))
+                .await;
+            statuses.push(response.status().as_u16());
+        }
+        statuses
+    };
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200, 400]);
+}
+
+#[tokio::test]
+async fn signing_up_again_does_not_use_up_an_invite() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let code = create_invite(&app, serde_json::json!({ "max_uses": 2 })).await;
+
+    // Act
+    let statuses: Vec<u16> = {
+        let mut statuses = Vec::new();
+        for email in ["a%40gmail.com", "a%40gmail.com", "b%40gmail.com"] {
+            let response = app
+                .post_subscriptions(format!(
+                    "name=le%20guin&email={}&invite_code={}",
+                    email, code
+                ))
+                .await;
+            statuses.push(response.status().as_u16());
+        }
+        statuses
+    };
+
+    // Assert
+    assert_eq!(statuses, vec![200, 200, 200]);
+    let uses = sqlx::query!("SELECT uses FROM invites WHERE code = $1", code)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .uses;
+    assert_eq!(uses, 2);
+}
+
+#[tokio::test]
+async fn expired_and_unknown_invites_are_rejected() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let code = create_invite(
+        &app,
+        serde_json::json!({ "expires_at": "2100-01-01T00:00:00Z" }),
+    )
+    .await;
+    sqlx::query!("UPDATE invites SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    for code in [code.as_str(), "NOTANINVITE1"] {
+        // Act
+        let response = app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com&invite_code={}",
+                code
+            ))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn invites_can_be_created_in_bulk() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+
+    // Act
+    let response: serde_json::Value = app
+        .post_invites(serde_json::json!({ "count": 50 }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response["codes"].as_array().unwrap().len(), 50);
+}
+
+#[tokio::test]
+async fn invalid_invite_requests_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let test_cases = vec![
+        (serde_json::json!({ "count": 0 }), "no invites"),
+        (serde_json::json!({ "count": 1001 }), "too many invites"),
+        (serde_json::json!({ "max_uses": 0 }), "no uses"),
+        (
+            serde_json::json!({ "expires_at": "2000-01-01T00:00:00Z" }),
+            "already expired",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_invites(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn creating_invites_takes_an_editor() {
+    // Arrange
+    let app = spawn_invite_only_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let url = format!("{}/admin/invites", &app.address);
+    let body = serde_json::json!({ "count": 1, "max_uses": 1 });
+
+    // Act
+    let anonymous = reqwest::Client::new()
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let as_viewer = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(as_viewer.status().as_u16(), 403);
+    let invites = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM invites"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(invites, 0);
+}
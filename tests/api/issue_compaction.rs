@@ -0,0 +1,181 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use uuid::Uuid;
+use zero2prod::configuration::IssueCompactionSettings;
+use zero2prod::issue_compaction::{CompactionSummary, compact_issues};
+
+fn settings() -> IssueCompactionSettings {
+    IssueCompactionSettings {
+        grace_period_days: 90,
+    }
+}
+
+/// Publishes a tracked issue to both subscribers and returns its id.
+async fn publish_tracked_issue(app: &TestApp) -> Uuid {
+    reqwest::Client::new()
+        .put(format!("{}/admin/lists/default", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "tracking": true }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let summary = app.publish(newsletter_request_body()).await;
+    Uuid::parse_str(summary["newsletter_issue_id"].as_str().unwrap()).unwrap()
+}
+
+async fn record(app: &TestApp, newsletter_issue_id: Uuid, subscriber_id: Uuid, kind: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber_id,
+        kind
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn get_json(app: &TestApp, path: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// What the reports say of `newsletter_issue_id`, and of the engagement of `subscriber_id`.
+async fn reports(
+    app: &TestApp,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> serde_json::Value {
+    let issue = get_json(app, &format!("/newsletters/{newsletter_issue_id}")).await;
+    let comparison = get_json(
+        app,
+        &format!("/admin/stats/issues/compare?ids={newsletter_issue_id}"),
+    )
+    .await;
+    let subscriber = get_json(
+        app,
+        &format!("/admin/subscribers/{subscriber_id}?include=engagement"),
+    )
+    .await;
+    serde_json::json!({
+        "engagement": issue["engagement"],
+        "deliveries": issue["deliveries"],
+        "comparison": comparison["issues"],
+        "subscriber": subscriber["engagement"],
+    })
+}
+
+#[tokio::test]
+async fn compacted_issues_report_what_they_did_before() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    let tolkien = app
+        .create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await
+        .id;
+    app.mount_email_server().await;
+    let old_issue = publish_tracked_issue(&app).await;
+    record(&app, old_issue, le_guin, "open").await;
+    record(&app, old_issue, le_guin, "click").await;
+    record(&app, old_issue, tolkien, "open").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now() WHERE id = $1",
+        tolkien
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = now() - interval '100 days' WHERE newsletter_issue_id = $1",
+        old_issue
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let recent_issue = publish_tracked_issue(&app).await;
+    record(&app, recent_issue, le_guin, "open").await;
+    let before = reports(&app, old_issue, le_guin).await;
+
+    // Act
+    let summary = compact_issues(&app.db_pool, &settings()).await.unwrap();
+    let again = compact_issues(&app.db_pool, &settings()).await.unwrap();
+
+    // Assert
+    assert_eq!(
+        summary,
+        CompactionSummary {
+            compacted_issues: 1,
+            deleted_attempts: 2,
+            deleted_events: 3,
+        }
+    );
+    assert_eq!(again.compacted_issues, 0);
+    assert_eq!(reports(&app, old_issue, le_guin).await, before);
+    assert_eq!(before["comparison"][0]["sent"], 2);
+    assert_eq!(before["comparison"][0]["opened"], 2);
+    assert_eq!(before["comparison"][0]["clicked"], 1);
+    assert_eq!(before["comparison"][0]["unsubscribed"], 1);
+    assert_eq!(before["subscriber"]["opens"], 2);
+    let recent_events = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM tracking_events WHERE newsletter_issue_id = $1"#,
+        recent_issue
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(recent_events, 1);
+}
+
+#[tokio::test]
+async fn readers_of_compacted_issues_cannot_be_tagged() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.mount_email_server().await;
+    let issue = publish_tracked_issue(&app).await;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = now() - interval '100 days' WHERE newsletter_issue_id = $1",
+        issue
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    compact_issues(&app.db_pool, &settings()).await.unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/tags/engaged_readers", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "newsletter_issue_id": issue,
+            "engagement": "opened",
+            "tag": "fans",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
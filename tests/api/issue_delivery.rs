@@ -0,0 +1,379 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app, spawn_app_with};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::ActionBaseUrl;
+use zero2prod::issue_delivery::{DeliveryQueueSettings, QueueSummary, deliver_queued_issues};
+use zero2prod::pii::PiiCipher;
+use zero2prod::send_budget::SendBudgetSettings;
+use zero2prod::templates::EmailTemplates;
+
+/// Two emails at once, then one a minute.
+async fn spawn_app_with_send_budget() -> TestApp {
+    let app = spawn_app_with(|c| {
+        c.email_client.delivery.send_budget = Some(SendBudgetSettings {
+            messages_per_hour: 60,
+            burst: Some(2),
+        })
+    })
+    .await;
+    app.set_postal_address().await;
+    app
+}
+
+async fn get_progress(app: &TestApp, newsletter_issue_id: &serde_json::Value) -> serde_json::Value {
+    let issue: serde_json::Value = app
+        .get_newsletter_issues(&format!("/{}", newsletter_issue_id.as_str().unwrap()))
+        .await
+        .json()
+        .await
+        .unwrap();
+    issue["progress"].clone()
+}
+
+/// Pretend the send budget was last used `minutes` ago.
+async fn wait_for_budget(app: &TestApp, minutes: i32) {
+    sqlx::query!(
+        "UPDATE send_budget SET refilled_at = refilled_at - make_interval(mins => $1)",
+        minutes
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn deliver_queue(app: &TestApp) -> QueueSummary {
+    deliver_queued_issues(
+        &app.db_pool,
+        &app.email_client,
+        &EmailTemplates::load("templates").unwrap(),
+        &app.key_ring,
+        &app.token_signer,
+        &PiiCipher::disabled(),
+        &app.address,
+        &ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn recipients_over_the_send_budget_are_queued_and_get_the_issue_as_it_refills() {
+    // Arrange
+    let app = spawn_app_with_send_budget().await;
+    app.create_confirmed_subscribers(5).await;
+    app.mount_email_server().await;
+
+    // Act - Part 1 - Publish
+    let summary = app.publish(newsletter_request_body()).await;
+    assert_eq!(summary["delivered"], 2);
+    assert_eq!(summary["queued"], 3);
+    let issue_id = &summary["newsletter_issue_id"];
+    assert_eq!(
+        get_progress(&app, issue_id).await,
+        serde_json::json!({"sent": 2, "failed": 0, "bounced": 0, "queued": 3, "dead_lettered": 0})
+    );
+
+    // Act - Part 2 - The budget hasn't refilled yet
+    assert_eq!(
+        deliver_queue(&app).await,
+        QueueSummary {
+            delivered: 0,
+            failed: 0,
+            dead_lettered: 0,
+        }
+    );
+
+    // Act - Part 3 - Two minutes later
+    wait_for_budget(&app, 2).await;
+    let summary = deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(summary.delivered, 2);
+    assert_eq!(
+        get_progress(&app, issue_id).await,
+        serde_json::json!({"sent": 4, "failed": 0, "bounced": 0, "queued": 1, "dead_lettered": 0})
+    );
+    // Five confirmations, then four issues.
+    let received = app.email_server.received_requests().await.unwrap();
+    assert_eq!(received.len(), 5 + 4);
+}
+
+#[tokio::test]
+async fn the_send_budget_is_shared_by_every_issue() {
+    // Arrange
+    let app = spawn_app_with_send_budget().await;
+    app.create_confirmed_subscribers(2).await;
+    app.mount_email_server().await;
+    app.publish(newsletter_request_body()).await;
+
+    // Act
+    let summary = app.publish(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(summary["delivered"], 0);
+    assert_eq!(summary["queued"], 2);
+}
+
+#[tokio::test]
+async fn the_oldest_issue_in_the_queue_goes_out_first() {
+    // Arrange
+    let app = spawn_app_with_send_budget().await;
+    app.create_confirmed_subscribers(2).await;
+    app.mount_email_server().await;
+    app.publish(newsletter_request_body()).await;
+    let first = app.publish(newsletter_request_body()).await;
+    let second = app.publish(newsletter_request_body()).await;
+
+    // Act
+    wait_for_budget(&app, 60).await;
+    deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(
+        get_progress(&app, &first["newsletter_issue_id"]).await["queued"],
+        0
+    );
+    assert_eq!(
+        get_progress(&app, &second["newsletter_issue_id"]).await["queued"],
+        2
+    );
+}
+
+#[tokio::test]
+async fn queued_subscribers_who_unsubscribe_do_not_get_the_issue() {
+    // Arrange
+    let app = spawn_app_with_send_budget().await;
+    app.create_confirmed_subscribers(3).await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    assert_eq!(summary["queued"], 1);
+    let newsletter_issue_id: Uuid = summary["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = (SELECT subscriber_id FROM issue_delivery_queue WHERE newsletter_issue_id = $1)
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    wait_for_budget(&app, 60).await;
+    let summary = deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(summary.delivered, 0);
+    assert_eq!(
+        get_progress(&app, &serde_json::json!(newsletter_issue_id.to_string())).await,
+        serde_json::json!({"sent": 2, "failed": 0, "bounced": 0, "queued": 0, "dead_lettered": 0})
+    );
+}
+
+/// Queues the deliveries of an issue to every confirmed subscriber again, as if the send
+/// budget had run out before any of them.
+async fn requeue_everybody(app: &TestApp, newsletter_issue_id: &serde_json::Value) -> Uuid {
+    let newsletter_issue_id: Uuid = newsletter_issue_id.as_str().unwrap().parse().unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, enqueued_at)
+        SELECT $1, id, now() FROM subscriptions WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    newsletter_issue_id
+}
+
+/// Publishes an issue titled `title`, then queues it for every subscriber again.
+async fn publish_and_requeue(app: &TestApp, title: &str, priority: i16) {
+    let mut body = newsletter_request_body();
+    body["title"] = title.into();
+    body["priority"] = priority.into();
+    let summary = app.publish(body).await;
+    requeue_everybody(app, &summary["newsletter_issue_id"]).await;
+}
+
+/// The titles of the issues sent from the queue, in the order they went out.
+async fn queued_issues_sent(app: &TestApp, n_subscribers: usize) -> Vec<String> {
+    let received = app.email_server.received_requests().await.unwrap();
+    // Skip the confirmations and the sends when the issues were published.
+    received[n_subscribers * 3..]
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn issues_in_the_queue_take_turns() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.batch_size = 1).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    app.mount_email_server().await;
+    publish_and_requeue(&app, "Large", 1).await;
+    publish_and_requeue(&app, "Urgent", 1).await;
+
+    // Act
+    deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(
+        queued_issues_sent(&app, 3).await,
+        ["Large", "Urgent", "Large", "Urgent", "Large", "Urgent"]
+    );
+}
+
+#[tokio::test]
+async fn issues_with_a_higher_priority_get_more_batches_per_turn() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.batch_size = 1).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    app.mount_email_server().await;
+    publish_and_requeue(&app, "Large", 1).await;
+    publish_and_requeue(&app, "Urgent", 2).await;
+
+    // Act
+    deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(
+        queued_issues_sent(&app, 3).await,
+        ["Large", "Urgent", "Urgent", "Large", "Urgent", "Large"]
+    );
+}
+
+#[tokio::test]
+async fn workers_sharing_the_queue_never_send_the_same_delivery_twice() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.batch_size = 1).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(6).await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    requeue_everybody(&app, &summary["newsletter_issue_id"]).await;
+
+    // Act
+    let (first, second) = tokio::join!(deliver_queue(&app), deliver_queue(&app));
+
+    // Assert
+    assert_eq!(first.delivered + second.delivered, 6);
+    // Six confirmations, then the issue twice: once published, once from the queue.
+    let received = app.email_server.received_requests().await.unwrap();
+    assert_eq!(received.len(), 6 + 6 + 6);
+}
+
+#[tokio::test]
+async fn leased_deliveries_are_left_alone_until_their_lease_runs_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(1).await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    let newsletter_issue_id = requeue_everybody(&app, &summary["newsletter_issue_id"]).await;
+    // A worker leased it, then died.
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = 1, execute_after = now() + interval '5 minutes'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1 - Leased
+    let leased = deliver_queue(&app).await;
+
+    // Act - Part 2 - The lease ran out
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let lapsed = deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(leased.delivered, 0);
+    assert_eq!(lapsed.delivered, 1);
+    assert_eq!(
+        get_progress(&app, &serde_json::json!(newsletter_issue_id.to_string())).await["queued"],
+        0
+    );
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_then_dead_lettered() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_client.delivery.queue = DeliveryQueueSettings {
+            visibility_timeout_seconds: 300,
+            max_retries: 1,
+            retry_backoff_seconds: 60,
+        }
+    })
+    .await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(1).await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    let issue_id = summary["newsletter_issue_id"].clone();
+    requeue_everybody(&app, &issue_id).await;
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - The first attempt fails
+    let first = deliver_queue(&app).await;
+    // Act - Part 2 - The retry isn't due yet
+    let too_early = deliver_queue(&app).await;
+    // Act - Part 3 - The retry fails too
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let retry = deliver_queue(&app).await;
+
+    // Assert
+    assert_eq!(
+        first,
+        QueueSummary {
+            delivered: 0,
+            failed: 1,
+            dead_lettered: 0,
+        }
+    );
+    assert_eq!(too_early.failed, 0);
+    assert_eq!(
+        retry,
+        QueueSummary {
+            delivered: 0,
+            failed: 1,
+            dead_lettered: 1,
+        }
+    );
+    let progress = get_progress(&app, &issue_id).await;
+    assert_eq!(progress["queued"], 0);
+    assert_eq!(progress["dead_lettered"], 1);
+    let dead_letter = sqlx::query!("SELECT n_retries, last_error FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.n_retries, 2);
+    assert!(dead_letter.last_error.contains("422"));
+}
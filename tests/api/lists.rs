@@ -0,0 +1,315 @@
+use crate::helpers::{TestApp, TestUser, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_list(app: &TestApp, slug: &str, name: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/lists", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "slug": slug, "name": name }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_lists(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("{}/admin/lists", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn delete_list(app: &TestApp, slug: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!("{}/admin/lists/{}", &app.address, slug))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_list_subscriptions(app: &TestApp, slug: &str, body: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/newsletters/{}/subscriptions",
+            &app.address, slug
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Subscribes `email` to the list and follows the confirmation link.
+async fn create_confirmed_list_subscriber(app: &TestApp, slug: &str, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    post_list_subscriptions(app, slug, &format!("name=le%20guin&email={email}"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn lists_can_be_created_renamed_and_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let created = create_list(&app, "rust-weekly", "Rust Weekly").await;
+    let renamed = reqwest::Client::new()
+        .put(format!("{}/admin/lists/rust-weekly", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "name": "This Week in Rust" }))
+        .send()
+        .await
+        .unwrap();
+    let lists = get_lists(&app).await;
+    let deleted = delete_list(&app, "rust-weekly").await;
+
+    // Assert
+    assert_eq!(created.status().as_u16(), 201);
+    assert_eq!(renamed.status().as_u16(), 204);
+    let lists = lists.as_array().unwrap();
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[1]["slug"], "rust-weekly");
+    assert_eq!(lists[1]["name"], "This Week in Rust");
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(get_lists(&app).await.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_lists_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly", "Rust Weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let invalid_slug = create_list(&app, "Rust Weekly", "Rust Weekly").await;
+    let empty_name = create_list(&app, "go-weekly", " ").await;
+    let duplicate = create_list(&app, "rust-weekly", "Another Rust Weekly").await;
+
+    // Assert
+    assert_eq!(invalid_slug.status().as_u16(), 400);
+    assert_eq!(empty_name.status().as_u16(), 400);
+    assert_eq!(duplicate.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_default_list_and_lists_with_subscribers_cant_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly", "Rust Weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula_le_guin%40gmail.com").await;
+
+    // Act
+    let default = delete_list(&app, "default").await;
+    let in_use = delete_list(&app, "rust-weekly").await;
+    let unknown = delete_list(&app, "go-weekly").await;
+
+    // Assert
+    assert_eq!(default.status().as_u16(), 409);
+    assert_eq!(in_use.status().as_u16(), 409);
+    assert_eq!(unknown.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_list_subscriptions(
+        &app,
+        "go-weekly",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_names_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly", "Rust Weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    post_list_subscriptions(
+        &app,
+        "rust-weekly",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Welcome to Rust Weekly, le guin!")
+    );
+}
+
+#[tokio::test]
+async fn the_same_address_can_subscribe_to_several_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly", "Rust Weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    create_confirmed_list_subscriber(&app, "default", "ursula_le_guin%40gmail.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula_le_guin%40gmail.com").await;
+
+    // Assert
+    let saved: Vec<String> = sqlx::query_scalar(
+        "SELECT n.slug FROM subscriptions s JOIN newsletters n ON n.id = s.newsletter_id \
+        WHERE s.status = 'confirmed' ORDER BY n.slug",
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved, vec!["default", "rust-weekly"]);
+}
+
+#[tokio::test]
+async fn issues_only_go_to_the_subscribers_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    create_list(&app, "rust-weekly", "Rust Weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    create_confirmed_list_subscriber(&app, "default", "ursula_le_guin%40gmail.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "octavia_butler%40gmail.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+            "newsletter": "rust-weekly",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "octavia_butler@gmail.com");
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("you subscribed to Rust Weekly")
+    );
+}
+
+#[tokio::test]
+async fn issues_for_an_unknown_list_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+            "newsletter": "go-weekly",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn viewers_can_read_lists_but_not_change_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let url = format!("{}/admin/lists", &app.address);
+
+    // Act
+    let anonymous = reqwest::get(&url).await.unwrap();
+    let read = reqwest::Client::new()
+        .get(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .send()
+        .await
+        .unwrap();
+    let create = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({ "slug": "rust-weekly", "name": "Rust Weekly" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(create.status().as_u16(), 403);
+}
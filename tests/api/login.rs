@@ -0,0 +1,137 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+use zero2prod::configuration::RateLimitSettings;
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Try to login
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // Act - Part 3 - Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+    assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn a_wrong_password_does_not_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "not-the-password"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn login_attempts_are_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_rate_limit = RateLimitSettings {
+            max_requests: 2,
+            window_seconds: 60,
+        }
+    })
+    .await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password"
+    });
+    for _ in 0..2 {
+        assert_is_redirect_to(&app.post_login(&login_body).await, "/login");
+    }
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+    // The login form itself can still be loaded.
+    assert!(app.get_login_html().await.contains("<form"));
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_rate_limited_by_their_own_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_rate_limit = RateLimitSettings {
+            max_requests: 2,
+            window_seconds: 60,
+        };
+        c.application.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    })
+    .await;
+    let login_from = |client_ip: &'static str| {
+        app.api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-Forwarded-For", client_ip)
+            .form(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": "not-the-password"
+            }))
+            .send()
+    };
+    for _ in 0..2 {
+        assert_is_redirect_to(&login_from("203.0.113.1").await.unwrap(), "/login");
+    }
+
+    // Act
+    let limited = login_from("203.0.113.1").await.unwrap();
+    let other_client = login_from("203.0.113.2").await.unwrap();
+
+    // Assert
+    assert_eq!(limited.status().as_u16(), 429);
+    // Two requests a minute: the next one is allowed half a minute after the first.
+    let retry_after: u64 = limited.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((29..=30).contains(&retry_after));
+    assert_is_redirect_to(&other_client, "/login");
+}
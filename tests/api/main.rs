@@ -1,5 +1,62 @@
+mod admin_access;
+mod admin_dashboard;
+mod admin_notifications;
+mod admin_subscribers;
+mod api_keys;
+mod archive;
+mod branding;
+mod calendar;
+mod categories;
+mod change_email;
+mod change_password;
+mod confirmation_outbox;
+mod confirmation_reminders;
+mod content_checks;
+mod cors;
+mod database;
+mod email_webhooks;
+mod fallback;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
+mod flags;
+mod funnel_stats;
 mod health_check;
 mod helpers;
+mod invites;
+mod issue_compaction;
+mod issue_delivery;
+mod lists;
+mod login;
+mod maintenance;
+mod metrics;
+mod migration_drift;
 mod newsletter;
+mod newsletter_issues;
+mod newsletter_preview;
+mod password_reset;
+mod payload_limits;
+mod pii_encryption;
+mod push;
+mod render_preview;
+mod repermission_campaigns;
+mod roles;
+mod scheduler;
+mod security_headers;
+mod session_store;
+mod shutdown;
+mod signup_verification;
+mod smoke_test;
+mod sms;
+mod snippets;
+mod sponsors;
+mod subscriber_data;
+mod subscriber_history;
+mod subscriber_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend;
+mod tags;
+mod token_cleanup;
+mod tracking;
+mod unsubscribe;
+mod waitlist;
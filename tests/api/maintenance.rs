@@ -0,0 +1,152 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use uuid::Uuid;
+
+async fn post_consistency_check(app: &TestApp, repair: bool) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/maintenance/consistency_check?repair={repair}",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Leaves one inconsistency of each kind behind, as a crash or a bug between two writes
+/// could, and returns the issue whose delivery counts disagree.
+async fn make_inconsistencies(app: &TestApp) -> Uuid {
+    let reader = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let deleted = app
+        .create_pending_subscriber("name=octavia&email=octavia%40example.com")
+        .await;
+    let settled = app
+        .create_pending_subscriber("name=iain&email=iain%40example.com")
+        .await;
+    app.set_postal_address().await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    let newsletter_issue_id: Uuid = summary["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // Deleted without its token being dropped.
+    sqlx::query!(
+        "UPDATE subscriptions SET deleted_at = now() WHERE id = $1",
+        deleted.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Confirmed with a confirmation email still to go.
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
+        settled.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_outbox (id, subscription_token, enqueued_at, next_attempt_at)
+        SELECT $1, subscription_token, now(), now() + interval '1 hour'
+        FROM subscription_tokens WHERE subscriber_id = $2
+        "#,
+        Uuid::new_v4(),
+        settled.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Queued again after it went out, and its delivery never recorded; queued for the
+    // deleted subscriber too.
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, enqueued_at)
+        VALUES ($1, $2, now()), ($1, $3, now())
+        "#,
+        newsletter_issue_id,
+        reader.id,
+        deleted.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "DELETE FROM broadcast_deliveries WHERE subscriber_id = $1",
+        reader.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    newsletter_issue_id
+}
+
+#[tokio::test]
+async fn a_consistency_check_reports_inconsistencies_without_repairing_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = make_inconsistencies(&app).await;
+
+    // Act
+    let first = post_consistency_check(&app, false).await;
+    let second = post_consistency_check(&app, false).await;
+
+    // Assert
+    let expected = serde_json::json!({
+        "repaired": false,
+        "contact_points_of_deleted_subscribers": 1,
+        "confirmation_emails_of_settled_subscribers": 1,
+        "departed_queue_entries": 1,
+        "queue_entries_already_sent": 1,
+        "mismatched_delivery_counts": [{
+            "newsletter_issue_id": newsletter_issue_id,
+            "recorded_deliveries": 0,
+            "accepted_attempts": 1,
+        }],
+    });
+    assert_eq!(first, expected);
+    assert_eq!(second, expected);
+}
+
+#[tokio::test]
+async fn a_consistency_check_can_repair_what_it_finds() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = make_inconsistencies(&app).await;
+
+    // Act
+    let repair = post_consistency_check(&app, true).await;
+    let check = post_consistency_check(&app, false).await;
+
+    // Assert
+    assert_eq!(repair["repaired"], true);
+    assert_eq!(repair["contact_points_of_deleted_subscribers"], 1);
+    assert_eq!(repair["departed_queue_entries"], 1);
+    assert_eq!(repair["queue_entries_already_sent"], 1);
+    assert_eq!(
+        check,
+        serde_json::json!({
+            "repaired": false,
+            "contact_points_of_deleted_subscribers": 0,
+            "confirmation_emails_of_settled_subscribers": 0,
+            "departed_queue_entries": 0,
+            "queue_entries_already_sent": 0,
+            // Only reported.
+            "mismatched_delivery_counts": [{
+                "newsletter_issue_id": newsletter_issue_id,
+                "recorded_deliveries": 0,
+                "accepted_attempts": 1,
+            }],
+        })
+    );
+}
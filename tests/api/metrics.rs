@@ -0,0 +1,67 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_metrics(address: &str) -> String {
+    let response = reqwest::get(format!("{address}/metrics"))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain; version=0.0.4")
+    );
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_route_pattern() {
+    // Arrange
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/newsletters/not-a-uuid", &app.address))
+        .await
+        .unwrap();
+
+    // Act
+    let metrics = get_metrics(&app.address).await;
+
+    // Assert
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#)
+    );
+    assert!(
+        metrics
+            .contains(r#"http_request_duration_seconds_count{method="GET",route="/health_check"}"#)
+    );
+    // Ids in the path are folded into the pattern.
+    assert!(metrics.contains(r#"route="/newsletters/{newsletter_issue_id}""#));
+    assert!(!metrics.contains("not-a-uuid"));
+}
+
+#[tokio::test]
+async fn subscribers_are_counted_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let metrics = get_metrics(&app.address).await;
+
+    // Assert
+    assert!(metrics.contains("subscribers{status=\"pending_confirmation\"} 1\n"));
+    assert!(metrics.contains("# TYPE email_send_failures_total counter"));
+    assert!(metrics.contains("newsletter_deliveries_pending "));
+}
@@ -0,0 +1,131 @@
+use crate::helpers::configure_database;
+use sqlx::{Connection, Executor, PgConnection};
+use uuid::Uuid;
+use zero2prod::configuration::{Settings, get_configuration};
+use zero2prod::preflight::{MigrationDrift, OnMigrationDrift, detect_migration_drift};
+use zero2prod::startup::{Application, get_connection_pool};
+
+async fn migrated_configuration(on_migration_drift: OnMigrationDrift) -> Settings {
+    let mut c = get_configuration().expect("Failed to read configuration.");
+    c.database.database_name = Uuid::new_v4().to_string();
+    c.application.port = 0;
+    c.application.on_migration_drift = on_migration_drift;
+    configure_database(&c.database).await;
+    c
+}
+
+#[tokio::test]
+async fn an_up_to_date_database_has_no_drift() {
+    // Arrange
+    let configuration = migrated_configuration(OnMigrationDrift::Fail).await;
+    let pool = get_connection_pool(&configuration.database);
+
+    // Act
+    let drift = detect_migration_drift(&pool).await.unwrap();
+
+    // Assert
+    assert_eq!(drift, vec![]);
+    assert!(Application::build(configuration).await.is_ok());
+}
+
+#[tokio::test]
+async fn missing_unknown_and_edited_migrations_are_reported() {
+    // Arrange
+    let configuration = migrated_configuration(OnMigrationDrift::Fail).await;
+    let pool = get_connection_pool(&configuration.database);
+    let versions: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(versions[versions.len() - 1])
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = $1")
+        .bind(versions[0])
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+        VALUES (99990101000000, 'from the future', true, '\\x00', 0)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // Act
+    let drift = detect_migration_drift(&pool).await.unwrap();
+
+    // Assert
+    assert_eq!(drift.len(), 3);
+    assert_eq!(drift[0], MigrationDrift::ChecksumMismatch(versions[0]));
+    assert!(matches!(drift[1], MigrationDrift::Pending(v, _) if v == versions[versions.len() - 1]));
+    assert_eq!(drift[2], MigrationDrift::Unknown(99990101000000));
+}
+
+#[tokio::test]
+async fn the_application_refuses_to_start_on_drift_when_configured_to_fail() {
+    // Arrange
+    let configuration = migrated_configuration(OnMigrationDrift::Fail).await;
+    let pool = get_connection_pool(&configuration.database);
+    sqlx::query("DELETE FROM _sqlx_migrations")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Act
+    let application = Application::build(configuration).await;
+
+    // Assert
+    assert!(application.is_err());
+}
+
+#[tokio::test]
+async fn the_application_starts_despite_drift_when_configured_to_warn() {
+    // Arrange
+    let configuration = migrated_configuration(OnMigrationDrift::Warn).await;
+    let pool = get_connection_pool(&configuration.database);
+    sqlx::query("DELETE FROM _sqlx_migrations")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Act
+    let application = Application::build(configuration).await;
+
+    // Assert
+    assert!(application.is_ok());
+}
+
+#[tokio::test]
+async fn the_application_migrates_an_empty_database_when_configured_to() {
+    // Arrange
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.port = 0;
+    configuration.application.on_migration_drift = OnMigrationDrift::Fail;
+    configuration.database.migrate_on_startup = true;
+    PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap()
+        .execute(
+            format!(
+                r#"CREATE DATABASE "{}";"#,
+                configuration.database.database_name
+            )
+            .as_str(),
+        )
+        .await
+        .unwrap();
+    let pool = get_connection_pool(&configuration.database);
+
+    // Act
+    let application = Application::build(configuration).await;
+
+    // Assert
+    assert!(application.is_ok());
+    assert_eq!(detect_migration_drift(&pool).await.unwrap(), vec![]);
+}
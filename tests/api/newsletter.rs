@@ -1,6 +1,10 @@
-use crate::helpers::{ConfirmationLinks, TestApp, spawn_app};
-use wiremock::matchers::{any, method, path};
+use crate::helpers::{
+    ConfirmationLinks, TestApp, newsletter_request_body, spawn_app, spawn_app_with,
+};
+use chrono::{Duration, Utc};
+use wiremock::matchers::{any, body_string_contains, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::FrequencyCapSettings;
 
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
@@ -37,6 +41,7 @@ async fn newsletters_returns_400_for_invalid_data() {
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
+    app.set_postal_address().await;
     create_unconfirmed_subscriber(&app).await;
     Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
@@ -64,7 +69,9 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
@@ -86,6 +93,116 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // Mock verifies on Drop that we have sent the newsletter email
 }
 
+#[tokio::test]
+async fn newsletters_resolve_snippet_references() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.post_snippet(serde_json::json!({"name": "footer", "content": "Thanks for reading!"}))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+    "text": "Newsletter body. {{> footer }}",
+    "html": "<p>Newsletter body.</p>{{> footer }}",
+    }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(
+        "Newsletter body. Thanks for reading!\n\n--\n1 Main St, Springfield\n\n\
+        You are getting this because you subscribed to our newsletter.\nPreferences: "
+    ));
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.starts_with("<p>Newsletter body.</p>Thanks for reading!<hr"));
+    assert!(html_body.contains("1 Main St, Springfield</p>"));
+}
+
+#[tokio::test]
+async fn newsletters_referencing_unknown_snippets_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+    "text": "Newsletter body. {{> missing }}",
+    "html": "<p>Newsletter body.</p>",
+    }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_include_active_sponsor_creatives() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let created: serde_json::Value = app
+        .post_sponsor_slot(serde_json::json!({
+            "sponsor_name": "ACME",
+            "creative_html": "<a href=\"{{ sponsor_link }}\">Try ACME</a>",
+            "target_url": "https://acme.example.com",
+            "starts_at": Utc::now() - Duration::days(1),
+            "ends_at": Utc::now() + Duration::days(1),
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let slot_id = created["id"].as_str().unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "content": {
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let click_url = format!("/sponsors/{}/click", slot_id);
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&click_url));
+    assert!(body["TextBody"].as_str().unwrap().contains(&click_url));
+    let report = app.get_sponsor_report(slot_id).await;
+    assert_eq!(report["impressions"], 1);
+}
+
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     let _mock_guard = Mock::given(path("/email"))
@@ -109,16 +226,547 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .unwrap()
         .pop()
         .unwrap();
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
+}
+
+#[tokio::test]
+async fn merge_fields_are_personalised_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ subscriber.name }}!",
+                "html": "<p>Hi {{ subscriber.name }}!</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Hi le guin!\n\n--\n1 Main St, Springfield")
+    );
+    assert!(
+        body["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("<p>Hi le guin!</p><hr")
+    );
+}
+
+#[tokio::test]
+async fn issues_with_unknown_merge_fields_are_rejected_before_sending() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ subscriber.first_name }}!",
+                "html": "<p>Hi!</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn excluded_emails_do_not_receive_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "exclude": { "emails": ["Ursula_Le_Guin@gmail.com"] }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_excluded_emails_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "exclude": { "emails": ["not-an-email"] }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn priorities_out_of_range_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut body = newsletter_request_body();
+    body["priority"] = 11.into();
+
+    // Act
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let issues = sqlx::query_scalar!("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, Some(0));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    // We can then reuse the same helper and just add
-    // an extra step to actually call the confirmation link!
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn subscribers_over_the_frequency_cap_are_skipped() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.frequency_cap = Some(FrequencyCapSettings {
+            max_emails: 1,
+            window_hours: 24,
+        });
+    })
+    .await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    // Act
+    let first: serde_json::Value = app
+        .post_newsletters(newsletter_request_body.clone())
+        .await
+        .json()
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    let second: serde_json::Value = app
+        .post_newsletters(newsletter_request_body)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(first["delivered"], 1);
+    assert_eq!(second["delivered"], 0);
+    assert_eq!(second["skipped_over_frequency_cap"], 1);
+    let skip = sqlx::query!(
+        "SELECT status FROM newsletter_delivery_attempts WHERE newsletter_issue_id = $1",
+        uuid::Uuid::parse_str(second["newsletter_issue_id"].as_str().unwrap()).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(skip.status, "skipped_frequency_cap");
+}
+
+#[tokio::test]
+async fn issues_are_rejected_without_a_postal_address() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    assert_eq!(problem["title"], "Bad Request");
+    assert!(
+        problem["detail"]
+            .as_str()
+            .unwrap()
+            .contains("postal address")
+    );
+    assert!(!problem["trace_id"].as_str().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_postal_address_can_be_placed_with_a_merge_field() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Write to us at {{ sender.address }}.",
+                "html": "<p>Write to us at {{ sender.address }}.</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Write to us at 1 Main St, Springfield.")
+    );
+}
+
+async fn post_newsletters_as(
+    app: &TestApp,
+    credentials: Option<(&str, &str)>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }));
+    if let Some((username, password)) = credentials {
+        request = request.basic_auth(username, Some(password));
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+fn assert_is_basic_auth_challenge(response: &reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_newsletters_as(&app, None).await;
+
+    // Assert
+    assert_is_basic_auth_challenge(&response);
+}
+
+#[tokio::test]
+async fn unknown_users_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response = post_newsletters_as(&app, Some(("not-a-publisher", "password"))).await;
+
+    // Assert
+    assert_is_basic_auth_challenge(&response);
+}
+
+#[tokio::test]
+async fn wrong_passwords_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response =
+        post_newsletters_as(&app, Some((&app.test_user.username, "not-the-password"))).await;
+
+    // Assert
+    assert_is_basic_auth_challenge(&response);
+}
+
+#[tokio::test]
+async fn issues_are_sent_to_several_subscribers_at_once() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.concurrency = 4).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(4).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500)))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+    let started_at = std::time::Instant::now();
+
+    // Act
+    let summary: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(summary["delivered"], 4);
+    // One after the other, they'd have taken at least 2s
+    assert!(started_at.elapsed() < std::time::Duration::from_millis(1500));
+}
+
+#[tokio::test]
+async fn a_failed_send_is_queued_for_a_retry_without_holding_up_the_others() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.delivery.batch_size = 1).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    Mock::given(path("/email"))
+        .and(body_string_contains("subscriber-1@example.com"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let summary = app.publish(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(summary["delivered"], 2);
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(summary["queued"], 1);
+    let queued = sqlx::query!(
+        r#"
+        SELECT a.status
+        FROM issue_delivery_queue q
+        JOIN newsletter_delivery_attempts a USING (newsletter_issue_id, subscriber_id)
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].status, "failed");
+}
+
+#[tokio::test]
+async fn issues_are_sent_no_faster_than_the_provider_rate_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_client.delivery.concurrency = 10;
+        c.email_client.delivery.max_messages_per_second = Some(5);
+    })
+    .await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    let started_at = std::time::Instant::now();
+
+    // Act
+    let summary: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(summary["delivered"], 3);
+    // 200ms between sends
+    assert!(started_at.elapsed() >= std::time::Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn dry_runs_render_a_sample_without_sending_or_storing_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscribers(3).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ subscriber.name }}!",
+                "html": "<p>Hi {{ subscriber.name }}!</p>",
+            },
+            "exclude": { "emails": ["subscriber-0@example.com"] },
+            "dry_run": true,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["dry_run"], true);
+    assert_eq!(summary["recipients"], 2);
+    assert_eq!(summary["sample"]["subject"], "Newsletter title");
+    assert!(
+        summary["sample"]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Hi subscriber!")
+    );
+    assert_ne!(summary["sample"]["to"], "subscriber-0@example.com");
+    let issues = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, 0);
+    let unsubscribe_tokens =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM unsubscribe_tokens"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(unsubscribe_tokens, 0);
+    let deliveries =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM broadcast_deliveries"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(deliveries, 0);
+}
+
+#[tokio::test]
+async fn dry_runs_are_checked_the_way_issues_are() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Hi {{ subscriber.nickname }}!",
+                "html": "<p>Hi!</p>",
+            },
+            "dry_run": true,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
 }
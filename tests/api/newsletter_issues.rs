@@ -0,0 +1,306 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn issue_titled(title: &str) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["title"] = title.into();
+    body
+}
+
+#[tokio::test]
+async fn published_issues_are_kept_with_who_they_were_delivered_to() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let summary = app.publish(issue_titled("Newsletter title")).await;
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+
+    // Act
+    let issue: serde_json::Value = app
+        .get_newsletter_issues(&format!("/{issue_id}"))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(issue["title"], "Newsletter title");
+    assert_eq!(issue["author"], app.test_user.username.as_str());
+    assert!(
+        issue["text_content"]
+            .as_str()
+            .unwrap()
+            .contains("Newsletter body as plain text")
+    );
+    let deliveries = issue["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["email"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn issues_are_kept_with_who_they_were_published_without() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.mount_email_server().await;
+    let mut body = issue_titled("Newsletter title");
+    body["exclude"] = serde_json::json!({
+        "emails": ["Ursula_Le_Guin@gmail.com"],
+        "tags": ["beta"],
+    });
+    let summary = app.publish(body).await;
+    let issue_id = summary["newsletter_issue_id"].as_str().unwrap();
+
+    // Act
+    let issue: serde_json::Value = app
+        .get_newsletter_issues(&format!("/{issue_id}"))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        issue["exclusions"],
+        serde_json::json!({
+            "emails": ["ursula_le_guin@gmail.com"],
+            "tags": ["beta"],
+        })
+    );
+    assert_eq!(issue["deliveries"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn issues_are_listed_most_recent_first_a_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    for title in ["First", "Second", "Third"] {
+        app.publish(issue_titled(title)).await;
+    }
+
+    // Act
+    let first_page: serde_json::Value = app
+        .get_newsletter_issues("?per_page=2")
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let second_page: serde_json::Value = app
+        .get_newsletter_issues("?page=2&per_page=2")
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let titles = |page: &serde_json::Value| -> Vec<String> {
+        page["issues"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|issue| issue["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(titles(&first_page), ["Third", "Second"]);
+    assert_eq!(titles(&second_page), ["First"]);
+    assert_eq!(first_page["total"], 3);
+}
+
+#[tokio::test]
+async fn unknown_issues_are_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .get_newsletter_issues(&format!("/{}", uuid::Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn invalid_pages_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for query in ["?page=0", "?per_page=0", "?per_page=101"] {
+        // Act
+        let response = app.get_newsletter_issues(query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "for {query}");
+    }
+}
+
+#[tokio::test]
+async fn the_history_requires_a_publisher_login() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/newsletters", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+}
+
+#[tokio::test]
+async fn unchanged_issues_are_revalidated_with_their_etag() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.publish(issue_titled("First issue")).await;
+    let response = app.get_newsletter_issues("").await;
+    assert_eq!(response.headers()["Cache-Control"], "private, no-cache");
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+    let revalidate = || {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .header("If-None-Match", &etag)
+            .send()
+    };
+
+    // Act
+    let unchanged = revalidate().await.unwrap();
+    app.publish(issue_titled("Second issue")).await;
+    let changed = revalidate().await.unwrap();
+
+    // Assert
+    assert_eq!(unchanged.status().as_u16(), 304);
+    assert_eq!(unchanged.headers()["ETag"], etag.as_str());
+    assert!(unchanged.bytes().await.unwrap().is_empty());
+    assert_eq!(changed.status().as_u16(), 200);
+    assert_ne!(changed.headers()["ETag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn revalidating_still_requires_a_publisher_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app.get_newsletter_issues("").await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/newsletters", &app.address))
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn compare_issues(app: &TestApp, ids: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/stats/issues/compare", &app.address))
+        .query(&[("ids", ids)])
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn issues_are_compared_with_the_average_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    app.create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await;
+    app.mount_email_server().await;
+    let first = app.publish(issue_titled("First")).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now() WHERE id = $1",
+        le_guin
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let second = app.publish(issue_titled("Second")).await;
+    let ids = format!(
+        "{},{}",
+        first["newsletter_issue_id"].as_str().unwrap(),
+        second["newsletter_issue_id"].as_str().unwrap()
+    );
+
+    // Act
+    let comparison: serde_json::Value = compare_issues(&app, &ids)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let issues = comparison["issues"].as_array().unwrap();
+    assert_eq!(issues[0]["title"], "First");
+    assert_eq!(issues[0]["sent"], 2);
+    assert_eq!(issues[0]["unsubscribed"], 1);
+    assert_eq!(issues[0]["unsubscribe_rate"], 0.5);
+    // Sent without tracking.
+    assert!(issues[0]["open_rate"].is_null());
+    assert_eq!(issues[1]["sent"], 1);
+    assert_eq!(issues[1]["unsubscribe_rate"], 0.0);
+    assert_eq!(
+        comparison["baselines"],
+        serde_json::json!([{
+            "newsletter": "default",
+            "issues": 2,
+            "open_rate": null,
+            "click_rate": null,
+            "unsubscribe_rate": 0.25,
+            "failure_rate": 0.0,
+        }])
+    );
+}
+
+#[tokio::test]
+async fn comparing_unknown_or_invalid_issues_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let unknown = compare_issues(&app, &uuid::Uuid::new_v4().to_string()).await;
+    let invalid = compare_issues(&app, "not-an-id").await;
+    let empty = compare_issues(&app, "").await;
+
+    // Assert
+    assert_eq!(unknown.status().as_u16(), 404);
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(empty.status().as_u16(), 400);
+}
@@ -0,0 +1,161 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_issue_endpoint(
+    app: &TestApp,
+    endpoint: &str,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters/{}", &app.address, endpoint))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ subscriber.name }}!",
+            "html": "<p>Hi {{ subscriber.name }}!</p>",
+        }
+    })
+}
+
+async fn stored_issues(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn previews_are_rendered_like_published_issues_without_sending_them() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_issue_endpoint(&app, "preview", issue()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let preview: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(preview["subject"], "Newsletter title");
+    let text = preview["text"].as_str().unwrap();
+    assert!(text.contains("Hi Ursula Le Guin!"));
+    assert!(text.contains("1 Main St, Springfield"));
+    assert!(text.contains("you subscribed to our newsletter"));
+    assert!(text.contains("/subscriptions/unsubscribe?token="));
+    assert!(
+        preview["html"]
+            .as_str()
+            .unwrap()
+            .contains("<p>Hi Ursula Le Guin!</p>")
+    );
+    assert_eq!(stored_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn previews_are_rejected_like_published_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    let unknown_merge_field = serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Hi {{ subscriber.age }}", "html": "<p>Hi</p>" },
+    });
+    let unknown_list = serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Hi", "html": "<p>Hi</p>" },
+        "newsletter": "go-weekly",
+    });
+
+    // Act
+    let without_postal_address = post_issue_endpoint(&app, "preview", issue()).await;
+    app.set_postal_address().await;
+    let unknown_merge_field = post_issue_endpoint(&app, "preview", unknown_merge_field).await;
+    let unknown_list = post_issue_endpoint(&app, "preview", unknown_list).await;
+
+    // Assert
+    assert_eq!(without_postal_address.status().as_u16(), 400);
+    assert_eq!(unknown_merge_field.status().as_u16(), 400);
+    assert_eq!(unknown_list.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_given_address() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut body = issue();
+    body["to"] = "editor@example.com".into();
+    body["name"] = "Editor".into();
+
+    // Act
+    let response = post_issue_endpoint(&app, "test_send", body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["To"], "editor@example.com");
+    assert_eq!(email["Subject"], "[Test] Newsletter title");
+    assert!(email["TextBody"].as_str().unwrap().contains("Hi Editor!"));
+    assert_eq!(stored_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn test_sends_to_an_invalid_address_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let mut body = issue();
+    body["to"] = "not-an-email".into();
+
+    // Act
+    let response = post_issue_endpoint(&app, "test_send", body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn previews_and_test_sends_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut body = issue();
+    body["to"] = "editor@example.com".into();
+
+    for endpoint in ["preview", "test_send"] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters/{}", &app.address, endpoint))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
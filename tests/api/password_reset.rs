@@ -0,0 +1,149 @@
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// The token in the reset link emailed to the test user.
+async fn request_reset_token(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_password_reset(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/password_reset");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let reset_link = app.get_confirmation_links(email_request).html;
+    assert_eq!(reset_link.path(), "/password_reset/confirm");
+    reset_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+fn reset_body(token: &str, new_password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    })
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_password_reset("nobody@example.com").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password_reset");
+    let html_page = app.get_password_reset_html().await;
+    assert!(html_page.contains("a link to reset their password is on its way"));
+}
+
+#[tokio::test]
+async fn a_reset_link_sets_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_password_reset_confirm(&reset_body(&token, &new_password))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_links_work_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    let first_password = Uuid::new_v4().to_string();
+    app.post_password_reset_confirm(&reset_body(&token, &first_password))
+        .await;
+
+    // Act
+    let response = app
+        .post_password_reset_confirm(&reset_body(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password_reset");
+    let html_page = app.get_password_reset_html().await;
+    assert!(html_page.contains("The reset link is invalid or has expired."));
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &first_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_password_reset_confirm(&reset_body(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password_reset");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn weak_passwords_keep_the_reset_link_usable() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+
+    // Act - Part 1 - A password that is too short
+    let response = app
+        .post_password_reset_confirm(&reset_body(&token, "hunter2"))
+        .await;
+    assert_is_redirect_to(&response, &format!("/password_reset/confirm?token={token}"));
+
+    // Act - Part 2 - A good one
+    let response = app
+        .post_password_reset_confirm(&reset_body(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
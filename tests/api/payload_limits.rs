@@ -0,0 +1,80 @@
+use crate::helpers::{TestApp, spawn_app_with};
+
+async fn spawn_app_with_small_limits() -> TestApp {
+    spawn_app_with(|c| {
+        c.application.payload_limits.json_bytes = 1024;
+        c.application.payload_limits.form_bytes = 1024;
+        c.application.payload_limits.issue_bytes = 16 * 1024;
+    })
+    .await
+}
+
+async fn assert_payload_too_large(response: reqwest::Response, limit: usize) {
+    assert_eq!(response.status().as_u16(), 413);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/payload-too-large");
+    assert_eq!(problem["limit"], limit);
+}
+
+fn issue(content: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": content,
+            "html": format!("<p>{content}</p>"),
+        }
+    })
+}
+
+#[tokio::test]
+async fn oversized_issues_are_rejected_with_a_413() {
+    // Arrange
+    let app = spawn_app_with_small_limits().await;
+
+    // Act
+    let response = app.post_newsletters(issue(&"a".repeat(32 * 1024))).await;
+
+    // Assert
+    assert_payload_too_large(response, 16 * 1024).await;
+}
+
+#[tokio::test]
+async fn issues_get_a_larger_limit_than_other_json_bodies() {
+    // Arrange
+    let app = spawn_app_with_small_limits().await;
+    app.set_postal_address().await;
+    let content = "a".repeat(4 * 1024);
+
+    // Act
+    let issue_response = app.post_newsletters(issue(&content)).await;
+    let category_response = reqwest::Client::new()
+        .post(format!("{}/admin/categories", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "name": "essays", "description": content }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(issue_response.status().as_u16(), 200);
+    assert_payload_too_large(category_response, 1024).await;
+}
+
+#[tokio::test]
+async fn oversized_signup_forms_are_rejected_with_a_413() {
+    // Arrange
+    let app = spawn_app_with_small_limits().await;
+    let name = "a".repeat(4 * 1024);
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("name={name}&email=ursula_le_guin%40gmail.com"))
+        .await;
+
+    // Assert
+    assert_payload_too_large(response, 1024).await;
+}
@@ -0,0 +1,182 @@
+use crate::helpers::{newsletter_request_body, spawn_app, spawn_app_with};
+use secrecy::SecretString;
+use std::collections::HashMap;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::PiiEncryptionSettings;
+use zero2prod::pii::{PiiCipher, encrypt_stored_pii};
+
+fn pii_encryption() -> PiiEncryptionSettings {
+    PiiEncryptionSettings {
+        current_key_id: "test".into(),
+        keys: HashMap::from([(
+            "test".to_string(),
+            SecretString::from("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY="),
+        )]),
+        blind_index_key: SecretString::from("test-blind-index-key-not-for-production"),
+    }
+}
+
+fn pii_cipher() -> PiiCipher {
+    let settings = pii_encryption();
+    PiiCipher::new(
+        settings.current_key_id,
+        settings.keys,
+        settings.blind_index_key,
+    )
+    .unwrap()
+}
+
+fn newsletter_excluding(excluded_emails: &[&str]) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["exclude"] = serde_json::json!({ "emails": excluded_emails });
+    body
+}
+
+#[tokio::test]
+async fn subscriber_details_are_stored_encrypted() {
+    // Arrange
+    let app = spawn_app_with(|c| c.pii_encryption = Some(pii_encryption())).await;
+
+    // Act
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, name, email_blind_index FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert!(!saved.email.contains("ursula_le_guin"));
+    assert!(!saved.name.contains("le guin"));
+    let cipher = pii_cipher();
+    assert_eq!(
+        cipher.decrypt(&saved.email).unwrap(),
+        "ursula_le_guin@gmail.com"
+    );
+    assert_eq!(cipher.decrypt(&saved.name).unwrap(), "le guin");
+    assert_eq!(
+        saved.email_blind_index,
+        cipher.email_index("ursula_le_guin@gmail.com")
+    );
+}
+
+#[tokio::test]
+async fn encrypted_subscribers_still_get_issues() {
+    // Arrange
+    let app = spawn_app_with(|c| c.pii_encryption = Some(pii_encryption())).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_excluding(&[])).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn excluded_emails_match_encrypted_subscribers() {
+    // Arrange
+    let app = spawn_app_with(|c| c.pii_encryption = Some(pii_encryption())).await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_excluding(&["Ursula_Le_Guin@gmail.com"]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // The exclusions kept with the issue are encrypted too.
+    let exclusions = sqlx::query_scalar!("SELECT exclusions FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let stored = exclusions["emails"][0].as_str().unwrap();
+    assert!(!stored.contains("ursula_le_guin"));
+    assert_eq!(
+        pii_cipher().decrypt(stored).unwrap(),
+        "ursula_le_guin@gmail.com"
+    );
+}
+
+#[tokio::test]
+async fn encrypted_emails_cannot_subscribe_twice() {
+    // Arrange
+    let app = spawn_app_with(|c| c.pii_encryption = Some(pii_encryption())).await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    // Answered like any repeat signup of a confirmed subscriber.
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribers_stored_in_plaintext_are_encrypted_by_the_backfill() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=banks&email=iain_banks%40gmail.com",
+    ] {
+        app.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    let cipher = pii_cipher();
+
+    // Act
+    // One row per batch, so that the backfill has to go round more than once.
+    let encrypted = encrypt_stored_pii(&app.db_pool, &cipher, 1).await.unwrap();
+    let encrypted_again = encrypt_stored_pii(&app.db_pool, &cipher, 1).await.unwrap();
+
+    // Assert
+    assert_eq!(encrypted, 2);
+    assert_eq!(encrypted_again, 0);
+    let saved = sqlx::query!("SELECT email, name, email_blind_index FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    for row in saved {
+        let email = cipher.decrypt(&row.email).unwrap();
+        assert_ne!(row.email, email);
+        assert_ne!(row.name, cipher.decrypt(&row.name).unwrap());
+        assert_eq!(row.email_blind_index, cipher.email_index(&email));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::helpers::{newsletter_request_body, spawn_app};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hkdf::Hkdf;
+use p256::{PublicKey, SecretKey};
+use sha2::Sha256;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const AUTH_SECRET: [u8; 16] = [1u8; 16];
+
+fn browser_subscription(endpoint: String) -> serde_json::Value {
+    browser_subscription_for(&SecretKey::random(&mut rand::thread_rng()), endpoint)
+}
+
+fn browser_subscription_for(ua_secret: &SecretKey, endpoint: String) -> serde_json::Value {
+    serde_json::json!({
+        "endpoint": endpoint,
+        "keys": {
+            "p256dh": URL_SAFE_NO_PAD.encode(ua_secret.public_key().to_sec1_bytes()),
+            "auth": URL_SAFE_NO_PAD.encode(AUTH_SECRET),
+        }
+    })
+}
+
+/// The notification a push request carries, decrypted the way the browser does (RFC 8291).
+fn decrypt_notification(ua_secret: &SecretKey, body: &[u8]) -> serde_json::Value {
+    let (salt, rest) = body.split_at(16);
+    let key_id_length = rest[4] as usize;
+    let (as_public, ciphertext) = rest[5..].split_at(key_id_length);
+
+    let shared_secret = p256::ecdh::diffie_hellman(
+        ua_secret.to_nonzero_scalar(),
+        PublicKey::from_sec1_bytes(as_public).unwrap().as_affine(),
+    );
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(&ua_secret.public_key().to_sec1_bytes());
+    key_info.extend_from_slice(as_public);
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&AUTH_SECRET), shared_secret.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .unwrap();
+    let prk = Hkdf::<Sha256>::new(Some(salt), &ikm);
+    let mut cek = [0u8; 16];
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .unwrap();
+    let mut nonce = [0u8; 12];
+    prk.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .unwrap();
+
+    let mut plaintext = Aes128Gcm::new_from_slice(&cek)
+        .unwrap()
+        .decrypt(Nonce::from_slice(&nonce), ciphertext)
+        .unwrap();
+    // The last-record padding delimiter
+    plaintext.pop();
+    serde_json::from_slice(&plaintext).unwrap()
+}
+
+#[tokio::test]
+async fn push_subscriptions_with_an_unknown_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_push_subscription(serde_json::json!({
+            "preference_token": "unknown",
+            "subscription": browser_subscription("https://push.example.com/abc".into()),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn push_subscriptions_with_malformed_keys_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+
+    // Act
+    let response = app
+        .post_push_subscription(serde_json::json!({
+            "preference_token": preference_token,
+            "subscription": {
+                "endpoint": "https://push.example.com/abc",
+                "keys": { "p256dh": "not-a-key", "auth": "not-a-secret" }
+            },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn publishing_notifies_subscribed_browsers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let ua_secret = SecretKey::random(&mut rand::thread_rng());
+    let endpoint = format!("{}/push/abc", app.email_server.uri());
+    app.post_push_subscription(serde_json::json!({
+        "preference_token": preference_token,
+        "subscription": browser_subscription_for(&ua_secret, endpoint),
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/push/abc"))
+        .and(method("POST"))
+        .and(header("Content-Encoding", "aes128gcm"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let summary = app.publish(newsletter_request_body()).await;
+
+    // Assert
+    let push_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|request| request.url.path() == "/push/abc")
+        .unwrap();
+    let notification = decrypt_notification(&ua_secret, &push_request.body);
+    assert_eq!(notification["title"], "Newsletter title");
+    let url = notification["url"].as_str().unwrap();
+    assert!(url.ends_with(&format!(
+        "/archive/{}",
+        summary["newsletter_issue_id"].as_str().unwrap()
+    )));
+}
+
+#[tokio::test]
+async fn expired_push_subscriptions_are_forgotten() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let endpoint = format!("{}/push/abc", app.email_server.uri());
+    app.post_push_subscription(serde_json::json!({
+        "preference_token": preference_token,
+        "subscription": browser_subscription(endpoint),
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/push/abc"))
+        .respond_with(ResponseTemplate::new(410))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let remaining = sqlx::query!("SELECT endpoint FROM push_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+}
@@ -0,0 +1,259 @@
+use crate::helpers::{TestApp, TestUser, spawn_app};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn previews_are_rendered_for_an_existing_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let preview: serde_json::Value = app
+        .post_render_preview(serde_json::json!({
+            "subscriber_id": subscriber_id,
+            "content": {
+                "text": "Hi {{ subscriber.name }} <{{ subscriber.email }}>",
+                "html": "<p>Hi {{ subscriber.name }}</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(preview["text"], "Hi le guin <ursula_le_guin@gmail.com>");
+    assert_eq!(preview["html"], "<p>Hi le guin</p>");
+    assert_eq!(preview["errors"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn previews_can_use_a_synthetic_persona() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let preview: serde_json::Value = app
+        .post_render_preview(serde_json::json!({
+            "persona": { "name": "<Ged>", "email": "ged@example.com" },
+            "content": {
+                "text": "Hi {{ subscriber.name }}",
+                "html": "<p>Hi {{ subscriber.name }}</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(preview["text"], "Hi <Ged>");
+    assert_eq!(preview["html"], "<p>Hi &lt;Ged&gt;</p>");
+}
+
+#[tokio::test]
+async fn previews_report_unresolved_merge_fields() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_render_preview(serde_json::json!({
+            "content": {
+                "text": "Hi {{ subscriber.first_name }}",
+                "html": "<p>Hi {{ subscriber.name }}</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let preview: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(preview["text"], "Hi {{ subscriber.first_name }}");
+    assert_eq!(preview["errors"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn previews_for_an_unknown_subscriber_are_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_render_preview(serde_json::json!({
+            "subscriber_id": uuid::Uuid::new_v4(),
+            "content": { "text": "Hi", "html": "<p>Hi</p>" }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/not-found");
+    assert!(problem["trace_id"].is_string());
+}
+
+#[tokio::test]
+async fn previews_report_a_missing_postal_address() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let preview: serde_json::Value = app
+        .post_render_preview(serde_json::json!({
+            "content": {"text": "Hi", "html": "<p>Hi</p>"}
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(preview["compliance_errors"].as_array().unwrap().len(), 1);
+    app.set_postal_address().await;
+    let preview: serde_json::Value = app
+        .post_render_preview(serde_json::json!({
+            "content": {"text": "Hi", "html": "<p>Hi</p>"}
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(preview["compliance_errors"], serde_json::json!([]));
+}
+
+/// Two confirmed subscribers, ursula_le_guin@gmail.com and ged@earthsea.org.
+async fn create_audience(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=ged&email=ged%40earthsea.org",
+    ] {
+        app.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn previews_can_sample_the_audience_of_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_audience(&app).await;
+
+    // Act
+    let preview: serde_json::Value = app
+        .post_render_preview(serde_json::json!({
+            "content": {"text": "Hi", "html": "<p>Hi</p>"},
+            "audience": {"exclude": {"emails": ["ged@earthsea.org"]}}
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(preview["audience"]["recipients"], 1);
+    // The test user is an admin.
+    assert_eq!(
+        preview["audience"]["sample"],
+        serde_json::json!(["ursula_le_guin@gmail.com"])
+    );
+}
+
+#[tokio::test]
+async fn audience_samples_are_masked_for_editors() {
+    // Arrange
+    let app = spawn_app().await;
+    create_audience(&app).await;
+    let editor = TestUser::with_role("editor");
+    editor.store(&app.db_pool).await;
+
+    // Act
+    let preview: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters/render_preview", &app.address))
+        .basic_auth(&editor.username, Some(&editor.password))
+        .json(&serde_json::json!({
+            "content": {"text": "Hi", "html": "<p>Hi</p>"},
+            "audience": {"exclude": {"emails": ["ged@earthsea.org"]}}
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        preview["audience"]["sample"],
+        serde_json::json!(["u***@gmail.com"])
+    );
+}
+
+#[tokio::test]
+async fn oversized_audience_samples_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_render_preview(serde_json::json!({
+            "content": {"text": "Hi", "html": "<p>Hi</p>"},
+            "audience": {"sample_size": 1000}
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn previews_take_an_editor() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let preview_as = |user: Option<&TestUser>| {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/admin/newsletters/render_preview", &app.address))
+            .json(&serde_json::json!({ "content": {"text": "Hi", "html": "<p>Hi</p>"} }));
+        if let Some(user) = user {
+            request = request.basic_auth(&user.username, Some(&user.password));
+        }
+        request.send()
+    };
+
+    // Act
+    let anonymous = preview_as(None).await.unwrap();
+    let as_viewer = preview_as(Some(&viewer)).await.unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(as_viewer.status().as_u16(), 403);
+}
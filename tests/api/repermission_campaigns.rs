@@ -0,0 +1,277 @@
+use crate::helpers::{TestApp, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::ActionBaseUrl;
+use zero2prod::pii::PiiCipher;
+use zero2prod::repermission_campaigns::{CampaignSummary, run_repermission_campaigns};
+use zero2prod::signed_tokens::TokenPurpose;
+use zero2prod::templates::EmailTemplates;
+
+async fn tag(app: &TestApp, subscriber_id: Uuid, tag: &str) {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/{subscriber_id}/tags",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "tags": [tag] }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn start_campaign(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/repermission_campaigns", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_campaign(app: &TestApp, campaign_id: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!(
+            "{}/admin/repermission_campaigns/{campaign_id}",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn run_campaigns(app: &TestApp) -> CampaignSummary {
+    run_repermission_campaigns(
+        &app.db_pool,
+        &app.email_client,
+        &EmailTemplates::load("templates").unwrap(),
+        &app.token_signer,
+        &PiiCipher::disabled(),
+        &ActionBaseUrl::parse("http://127.0.0.1".into(), false).unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// The "stay subscribed" link of the email sent to `email`.
+async fn stay_link(app: &TestApp, email: &str) -> reqwest::Url {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|request| serde_json::from_slice::<serde_json::Value>(&request.body).unwrap())
+        .find(|body| body["To"] == email && body["Subject"] != "Welcome!")
+        .unwrap();
+    let raw_link = linkify::LinkFinder::new()
+        .links(request["TextBody"].as_str().unwrap())
+        .map(|link| link.as_str().to_owned())
+        .find(|link| link.contains("/subscriptions/stay"))
+        .unwrap();
+    let mut link = reqwest::Url::parse(&raw_link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query_scalar!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn subscribers_who_dont_stay_are_unsubscribed_when_the_campaign_closes() {
+    // Arrange
+    let app = spawn_app().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    let tolkien = app
+        .create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com")
+        .await
+        .id;
+    let butler = app
+        .create_confirmed_subscriber("name=butler&email=octavia_butler%40gmail.com")
+        .await
+        .id;
+    tag(&app, le_guin, "imported").await;
+    tag(&app, tolkien, "imported").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let campaign: serde_json::Value = start_campaign(
+        &app,
+        serde_json::json!({ "tag": "imported", "window_days": 14 }),
+    )
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    let campaign_id = campaign["campaign_id"].as_str().unwrap();
+    let sending = run_campaigns(&app).await;
+    let stayed = reqwest::get(stay_link(&app, "ursula_le_guin@gmail.com").await)
+        .await
+        .unwrap();
+    let awaiting = get_campaign(&app, campaign_id).await;
+    sqlx::query!("UPDATE repermission_campaigns SET closes_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let closing = run_campaigns(&app).await;
+    let too_late = reqwest::get(stay_link(&app, "jrr_tolkien@gmail.com").await)
+        .await
+        .unwrap();
+    let stayed_again = reqwest::get(stay_link(&app, "ursula_le_guin@gmail.com").await)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(campaign["requested"], 2);
+    assert_eq!(campaign["sent"], 0);
+    assert_eq!(
+        sending,
+        CampaignSummary {
+            sent: 2,
+            failed: 0,
+            unsubscribed: 0,
+        }
+    );
+    assert_eq!(stayed.status().as_u16(), 200);
+    assert!(stayed.text().await.unwrap().contains("still subscribed"));
+    assert_eq!(awaiting["sent"], 2);
+    assert_eq!(awaiting["stayed"], 1);
+    assert_eq!(awaiting["awaiting"], 1);
+    assert_eq!(closing.unsubscribed, 1);
+    assert_eq!(status(&app, le_guin).await, "confirmed");
+    assert_eq!(status(&app, tolkien).await, "unsubscribed");
+    assert_eq!(status(&app, butler).await, "confirmed");
+    assert_eq!(too_late.status().as_u16(), 410);
+    assert_eq!(stayed_again.status().as_u16(), 200);
+    let closed = get_campaign(&app, campaign_id).await;
+    assert!(!closed["closed_at"].is_null());
+    assert_eq!(closed["unsubscribed"], 1);
+    assert_eq!(closed["awaiting"], 0);
+    let actor = sqlx::query_scalar!(
+        "SELECT actor FROM audit_log WHERE subscriber_id = $1 AND event = 'unsubscribed'",
+        tolkien
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(actor, "repermission_campaigns");
+}
+
+#[tokio::test]
+async fn subscribers_the_email_could_not_reach_are_left_alone() {
+    // Arrange
+    let app = spawn_app().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    tag(&app, le_guin, "imported").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    start_campaign(
+        &app,
+        serde_json::json!({ "tag": "imported", "window_days": 14 }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let sending = run_campaigns(&app).await;
+    sqlx::query!("UPDATE repermission_campaigns SET closes_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let closing = run_campaigns(&app).await;
+
+    // Assert
+    assert_eq!(sending.failed, 1);
+    assert_eq!(closing.unsubscribed, 0);
+    assert_eq!(status(&app, le_guin).await, "confirmed");
+}
+
+#[tokio::test]
+async fn campaigns_need_a_segment_and_a_window() {
+    // Arrange
+    let app = spawn_app().await;
+    let le_guin = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    tag(&app, le_guin, "imported").await;
+
+    // Act
+    let nobody = start_campaign(
+        &app,
+        serde_json::json!({ "tag": "nobody", "window_days": 14 }),
+    )
+    .await;
+    let no_window = start_campaign(
+        &app,
+        serde_json::json!({ "tag": "imported", "window_days": 0 }),
+    )
+    .await;
+    let unknown_list = start_campaign(
+        &app,
+        serde_json::json!({ "tag": "imported", "window_days": 14, "newsletter": "nope" }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(nobody.status().as_u16(), 400);
+    assert_eq!(no_window.status().as_u16(), 400);
+    assert_eq!(unknown_list.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn stay_links_with_invalid_or_unknown_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let unknown = app
+        .token_signer
+        .sign(TokenPurpose::Repermission, "abcdefghijklmnopqrstuvwxy");
+
+    // Act
+    let get_stay = |token: String| {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/stay", &app.address))
+            .query(&[("token", token)])
+            .send()
+    };
+    let invalid = get_stay("not-a-token".into()).await.unwrap();
+    let unknown = get_stay(unknown).await.unwrap();
+
+    // Assert
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(unknown.status().as_u16(), 401);
+}
@@ -0,0 +1,408 @@
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, route_path, spawn_app};
+use reqwest::Method;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+use zero2prod::authorization::{Access, POLICY};
+
+async fn stored_user(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    user
+}
+
+async fn send_as(
+    app: &TestApp,
+    user: &TestUser,
+    method: Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .request(method, format!("{}{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password));
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+fn issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+    })
+}
+
+#[tokio::test]
+async fn viewers_can_read_but_not_publish() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let viewer = stored_user(&app, "viewer").await;
+
+    // Act
+    let subscribers = send_as(&app, &viewer, Method::GET, "/admin/subscribers", None).await;
+    let issues = send_as(&app, &viewer, Method::GET, "/newsletters", None).await;
+    let publish = send_as(&app, &viewer, Method::POST, "/newsletters", Some(issue())).await;
+    let preview = send_as(
+        &app,
+        &viewer,
+        Method::POST,
+        "/newsletters/preview",
+        Some(issue()),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(subscribers.status().as_u16(), 200);
+    assert_eq!(issues.status().as_u16(), 200);
+    assert_eq!(preview.status().as_u16(), 403);
+    assert_eq!(publish.status().as_u16(), 403);
+    let problem: serde_json::Value = publish.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/forbidden");
+    assert_eq!(
+        problem["detail"],
+        "This requires the `editor` role, and you are `viewer`."
+    );
+}
+
+#[tokio::test]
+async fn editors_can_publish_but_not_delete_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let editor = stored_user(&app, "editor").await;
+    let subscriber = format!("/admin/subscribers/{}", uuid::Uuid::new_v4());
+
+    // Act
+    let publish = send_as(&app, &editor, Method::POST, "/newsletters", Some(issue())).await;
+    let delete = send_as(&app, &editor, Method::DELETE, &subscriber, None).await;
+    let delete_as_admin = send_as(&app, &app.test_user, Method::DELETE, &subscriber, None).await;
+
+    // Assert
+    assert_eq!(publish.status().as_u16(), 200);
+    assert_eq!(delete.status().as_u16(), 403);
+    // Admins get past the role check, to a subscriber that doesn't exist.
+    assert_eq!(delete_as_admin.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn only_admins_can_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = stored_user(&app, "editor").await;
+
+    // Act
+    let list = send_as(&app, &editor, Method::GET, "/admin/users", None).await;
+    let promote = send_as(
+        &app,
+        &editor,
+        Method::PUT,
+        &format!("/admin/users/{}/role", editor.username),
+        Some(serde_json::json!({ "role": "admin" })),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(list.status().as_u16(), 403);
+    assert_eq!(promote.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn admins_create_users_and_change_their_roles() {
+    // Arrange
+    let app = spawn_app().await;
+    let admin = &app.test_user;
+
+    // Act - Part 1 - Create a viewer
+    let response = send_as(
+        &app,
+        admin,
+        Method::POST,
+        "/admin/users",
+        Some(serde_json::json!({
+            "username": "le-guin",
+            "password": "a long enough password",
+            "role": "viewer",
+        })),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // Act - Part 2 - Promote them
+    let response = send_as(
+        &app,
+        admin,
+        Method::PUT,
+        "/admin/users/le-guin/role",
+        Some(serde_json::json!({ "role": "editor" })),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Act - Part 3 - They are listed with their new role
+    let users: serde_json::Value = send_as(&app, admin, Method::GET, "/admin/users", None)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let le_guin = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|user| user["username"] == "le-guin")
+        .unwrap();
+    assert_eq!(le_guin["role"], "editor");
+
+    // Act - Part 4 - The username is taken
+    let response = send_as(
+        &app,
+        admin,
+        Method::POST,
+        "/admin/users",
+        Some(serde_json::json!({
+            "username": "le-guin",
+            "password": "another long password",
+            "role": "admin",
+        })),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_last_admin_cannot_be_demoted_or_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let admin = &app.test_user;
+    let path = format!("/admin/users/{}", admin.username);
+
+    // Act
+    let demote = send_as(
+        &app,
+        admin,
+        Method::PUT,
+        &format!("{path}/role"),
+        Some(serde_json::json!({ "role": "editor" })),
+    )
+    .await;
+    let delete = send_as(&app, admin, Method::DELETE, &path, None).await;
+    let second_admin = stored_user(&app, "admin").await;
+    let delete_with_another_admin = send_as(&app, &second_admin, Method::DELETE, &path, None).await;
+
+    // Assert
+    assert_eq!(demote.status().as_u16(), 409);
+    assert_eq!(delete.status().as_u16(), 409);
+    assert_eq!(delete_with_another_admin.status().as_u16(), 204);
+}
+
+#[tokio::test]
+async fn invalid_roles_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = send_as(
+        &app,
+        &app.test_user,
+        Method::PUT,
+        &format!("/admin/users/{}/role", app.test_user.username),
+        Some(serde_json::json!({ "role": "owner" })),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn viewers_are_not_offered_the_publish_form() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = stored_user(&app, "viewer").await;
+    viewer.login(&app).await;
+
+    // Act
+    let dashboard = app.get_admin_dashboard().await.text().await.unwrap();
+    let form = app.get_publish_newsletter().await;
+    let publish = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .await;
+
+    // Assert
+    assert!(!dashboard.contains("Send a newsletter issue"));
+    assert_eq!(form.status().as_u16(), 403);
+    assert_eq!(publish.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn deleted_users_are_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = stored_user(&app, "editor").await;
+    editor.login(&app).await;
+
+    // Act
+    let response = send_as(
+        &app,
+        &app.test_user,
+        Method::DELETE,
+        &format!("/admin/users/{}", editor.username),
+        None,
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+/// A client with a session `user` is logged in on, that doesn't follow redirects.
+async fn session_of(app: &TestApp, user: &TestUser) -> reqwest::Client {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+/// Every route `POLICY` gives a permission, with its parameters filled in, the `Access` it
+/// takes and the role that has the permission. Logging out goes last, as it ends sessions.
+fn guarded_routes() -> Vec<(Method, String, Access, Role)> {
+    let mut routes: Vec<_> = POLICY
+        .iter()
+        .filter_map(|(route, method, access)| {
+            let role = access.permission()?.role();
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            Some((method, route_path(route), *access, role))
+        })
+        .collect();
+    routes.sort_by_key(|(_, path, ..)| path == "/admin/logout");
+    routes
+}
+
+#[tokio::test]
+async fn every_guarded_route_takes_a_user_with_its_role() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = stored_user(&app, "viewer").await;
+    let editor = stored_user(&app, "editor").await;
+    let no_credentials = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let sessions = [
+        session_of(&app, &viewer).await,
+        session_of(&app, &editor).await,
+        session_of(&app, &app.test_user).await,
+    ];
+
+    let users = [&viewer, &editor, &app.test_user];
+
+    for (method, path, access, role) in guarded_routes() {
+        // Users and sessions are in the order of their roles.
+        let rank = role as usize;
+        // Pages only take sessions, the JSON APIs take `Basic` credentials too.
+        let send = |user: usize| {
+            let url = format!("{}{}", &app.address, path);
+            match access {
+                Access::Page(_) => sessions[user].request(method.clone(), url),
+                _ => reqwest::Client::new()
+                    .request(method.clone(), url)
+                    .basic_auth(&users[user].username, Some(&users[user].password)),
+            }
+            .send()
+        };
+
+        // Act
+        let anonymous = no_credentials
+            .request(method.clone(), format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .unwrap();
+        let under_privileged = match rank {
+            0 => None,
+            rank => Some(send(rank - 1).await.unwrap()),
+        };
+        let privileged = send(rank).await.unwrap();
+
+        // Assert
+        match access {
+            Access::Page(_) => assert_is_redirect_to(&anonymous, "/login"),
+            _ => assert_eq!(anonymous.status().as_u16(), 401, "{method} {path}"),
+        }
+        if let Some(response) = under_privileged {
+            assert_eq!(response.status().as_u16(), 403, "{method} {path}");
+        }
+        let status = privileged.status().as_u16();
+        // Logging out is the one page that sends its users to the login page.
+        let sent_to_login = path != "/admin/logout"
+            && privileged
+                .headers()
+                .get("Location")
+                .is_some_and(|location| location == "/login");
+        assert!(
+            ![401, 403].contains(&status) && !sent_to_login,
+            "{method} {path} refused a {role}: {status}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn logged_in_users_can_call_the_admin_apis() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let editor = stored_user(&app, "editor").await;
+    editor.login(&app).await;
+    let call = |method: Method, path: &str, body: Option<serde_json::Value>| {
+        let mut request = app
+            .api_client
+            .request(method, format!("{}{}", &app.address, path));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        request.send()
+    };
+
+    // Act
+    let subscribers = call(Method::GET, "/admin/subscribers", None).await.unwrap();
+    let issues = call(Method::GET, "/newsletters", None).await.unwrap();
+    let preview = call(Method::POST, "/newsletters/preview", Some(issue()))
+        .await
+        .unwrap();
+    let publish = call(Method::POST, "/newsletters", Some(issue()))
+        .await
+        .unwrap();
+    let users = call(Method::GET, "/admin/users", None).await.unwrap();
+
+    // Assert
+    assert_eq!(subscribers.status().as_u16(), 200);
+    assert_eq!(issues.status().as_u16(), 200);
+    assert_eq!(preview.status().as_u16(), 200);
+    assert_eq!(publish.status().as_u16(), 200);
+    // The session is checked for its role like credentials are.
+    assert_eq!(users.status().as_u16(), 403);
+}
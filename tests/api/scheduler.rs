@@ -0,0 +1,114 @@
+use crate::helpers::spawn_app;
+use sqlx::Connection;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zero2prod::scheduler::{Job, JobOutcome, lock_key, run_job_once};
+
+#[derive(Default)]
+struct CountingJob {
+    runs: AtomicUsize,
+    // Runs on every instance at once.
+    shared: bool,
+}
+
+#[async_trait::async_trait]
+impl Job for CountingJob {
+    fn name(&self) -> &'static str {
+        "confirmation_reminders"
+    }
+
+    fn exclusive(&self) -> bool {
+        !self.shared
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+struct PanickingJob;
+
+#[async_trait::async_trait]
+impl Job for PanickingJob {
+    fn name(&self) -> &'static str {
+        "confirmation_reminders"
+    }
+
+    async fn run(&self) -> Result<(), anyhow::Error> {
+        panic!("boom")
+    }
+}
+
+#[tokio::test]
+async fn a_job_is_skipped_while_another_instance_holds_its_lock() {
+    // Arrange
+    let app = spawn_app().await;
+    let job = CountingJob::default();
+    let mut other_instance = app.db_pool.acquire().await.unwrap();
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(lock_key(job.name()))
+        .execute(&mut *other_instance)
+        .await
+        .unwrap();
+
+    // Act
+    let outcome = run_job_once(&app.db_pool, &job).await.unwrap();
+
+    // Assert
+    assert_eq!(outcome, JobOutcome::Skipped);
+    assert_eq!(job.runs.load(Ordering::Relaxed), 0);
+    // Closing the connection doesn't wait for the server to release its locks.
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(lock_key(job.name()))
+        .execute(&mut *other_instance)
+        .await
+        .unwrap();
+    let outcome = run_job_once(&app.db_pool, &job).await.unwrap();
+    assert_eq!(outcome, JobOutcome::Succeeded);
+    assert_eq!(job.runs.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn jobs_that_are_not_exclusive_run_while_another_instance_runs_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let job = CountingJob {
+        shared: true,
+        ..CountingJob::default()
+    };
+    let mut other_instance = app.db_pool.acquire().await.unwrap();
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(lock_key(job.name()))
+        .execute(&mut *other_instance)
+        .await
+        .unwrap();
+
+    // Act
+    let outcome = run_job_once(&app.db_pool, &job).await.unwrap();
+
+    // Assert
+    assert_eq!(outcome, JobOutcome::Succeeded);
+    assert_eq!(job.runs.load(Ordering::Relaxed), 1);
+    other_instance.detach().close().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_panicking_job_releases_its_lock() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = run_job_once(&app.db_pool, &PanickingJob).await.unwrap();
+
+    // Assert
+    assert_eq!(outcome, JobOutcome::Panicked);
+    // Locks are reentrant within a session: check none is left rather than taking it again.
+    let held_locks: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM pg_locks WHERE locktype = 'advisory' \
+        AND database = (SELECT oid FROM pg_database WHERE datname = current_database())",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(held_locks, 0);
+}
@@ -0,0 +1,77 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use reqwest::header;
+
+#[tokio::test]
+async fn every_response_is_marked_nosniff() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for path in ["/health_check", "/no/such/route"] {
+        // Act
+        let response = reqwest::get(format!("{}{}", &app.address, path))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff",
+            "{path}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_admin_pages_get_a_content_security_policy() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let login = reqwest::get(format!("{}/login", &app.address))
+        .await
+        .unwrap();
+    let health_check = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    let policy = login.headers()[header::CONTENT_SECURITY_POLICY]
+        .to_str()
+        .unwrap();
+    assert!(policy.contains("frame-ancestors 'none'"));
+    assert!(
+        !health_check
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY)
+    );
+}
+
+#[tokio::test]
+async fn hsts_is_only_sent_for_https_deployments() {
+    // Arrange
+    let http = spawn_app().await;
+    let https = spawn_app_with(|c| {
+        c.application.base_url = "https://newsletter.example.com".into();
+        c.security_headers.hsts_max_age_seconds = 600;
+    })
+    .await;
+
+    // Act
+    let http = reqwest::get(format!("{}/health_check", &http.address))
+        .await
+        .unwrap();
+    let https = reqwest::get(format!("{}/health_check", &https.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(
+        !http
+            .headers()
+            .contains_key(header::STRICT_TRANSPORT_SECURITY)
+    );
+    assert_eq!(
+        https.headers()[header::STRICT_TRANSPORT_SECURITY],
+        "max-age=600"
+    );
+}
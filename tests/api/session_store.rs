@@ -0,0 +1,71 @@
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app_with};
+use zero2prod::configuration::SessionStoreBackend;
+use zero2prod::session::delete_expired_sessions;
+
+async fn spawn_app_keeping_sessions_in(backend: SessionStoreBackend) -> TestApp {
+    spawn_app_with(|c| c.session_store = backend).await
+}
+
+async fn stored_sessions(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM admin_sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admin_sessions_can_be_kept_in_postgres() {
+    // Arrange
+    let app = spawn_app_keeping_sessions_in(SessionStoreBackend::Postgres).await;
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    // Assert
+    assert_eq!(stored_sessions(&app).await, 1);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Logout
+    let response = app.post_logout().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(stored_sessions(&app).await, 0);
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_postgres_sessions_are_ignored_then_deleted() {
+    // Arrange
+    let app = spawn_app_keeping_sessions_in(SessionStoreBackend::Postgres).await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE admin_sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+    let deleted = delete_expired_sessions(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(deleted, 1);
+    assert_eq!(stored_sessions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn admin_sessions_can_be_kept_in_memory() {
+    // Arrange
+    let app = spawn_app_keeping_sessions_in(SessionStoreBackend::Memory).await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_sessions(&app).await, 0);
+}
@@ -0,0 +1,58 @@
+use crate::helpers::spawn_app;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn in_flight_requests_finish_after_shutdown_is_requested() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let client = reqwest::Client::new();
+    let in_flight = tokio::spawn(
+        client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send(),
+    );
+    // Let the request reach the (slow) email provider
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Act
+    app.shutdown.trigger();
+
+    // Assert
+    let response = in_flight
+        .await
+        .unwrap()
+        .expect("The in-flight request was dropped.");
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn new_connections_are_refused_once_shutdown_is_requested() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Assert
+    let outcome = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await;
+    assert!(outcome.is_err());
+}
@@ -0,0 +1,200 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::{CaptchaSettings, SignupVerificationSettings};
+use zero2prod::signup_verification::is_solution;
+
+const FORM: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Turnstile, checked against the mock server.
+async fn spawn_app_with_captcha() -> TestApp {
+    spawn_app_with(|c| {
+        c.signup_verification = Some(SignupVerificationSettings::Turnstile(CaptchaSettings {
+            secret: "turnstile-secret".to_string().into(),
+            verify_url: Some(format!("{}/siteverify", c.email_client.base_url)),
+            timeout_milliseconds: 1000,
+        }))
+    })
+    .await
+}
+
+async fn spawn_app_with_proof_of_work() -> TestApp {
+    spawn_app_with(|c| {
+        c.signup_verification = Some(SignupVerificationSettings::ProofOfWork {
+            difficulty_bits: 8,
+            challenge_lifetime_seconds: 60,
+        })
+    })
+    .await
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn assert_verification_failed(response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/verification-failed");
+}
+
+/// A challenge from the application, and a nonce solving it.
+async fn solve_challenge(app: &TestApp) -> (String, String) {
+    let challenge: serde_json::Value =
+        reqwest::get(format!("{}/subscriptions/challenge", &app.address))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    let difficulty_bits = challenge["difficulty_bits"].as_u64().unwrap() as u32;
+    let challenge = challenge["challenge"].as_str().unwrap().to_owned();
+    let nonce = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| is_solution(&challenge, nonce, difficulty_bits))
+        .unwrap();
+    (challenge, nonce)
+}
+
+#[tokio::test]
+async fn signups_with_a_solved_captcha_are_accepted() {
+    // Arrange
+    let app = spawn_app_with_captcha().await;
+    app.mount_email_server().await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=turnstile-secret"))
+        .and(body_string_contains("response=solved-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "error-codes": [],
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("{FORM}&cf-turnstile-response=solved-token"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn signups_without_a_valid_captcha_are_rejected() {
+    // Arrange
+    let app = spawn_app_with_captcha().await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false,
+            "error-codes": ["invalid-input-response"],
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let missing = app.post_subscriptions(FORM.into()).await;
+    let invalid = app
+        .post_subscriptions(format!("{FORM}&cf-turnstile-response=made-up"))
+        .await;
+
+    // Assert
+    assert_verification_failed(missing).await;
+    assert_verification_failed(invalid).await;
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn invalid_forms_are_rejected_before_the_captcha_is_checked() {
+    // Arrange
+    let app = spawn_app_with_captcha().await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=not-an-email&cf-turnstile-response=token".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["field"], "email");
+}
+
+#[tokio::test]
+async fn signups_with_a_solved_proof_of_work_are_accepted_once() {
+    // Arrange
+    let app = spawn_app_with_proof_of_work().await;
+    app.mount_email_server().await;
+    let (challenge, nonce) = solve_challenge(&app).await;
+    // Challenges are URL-safe.
+    let solution = format!("pow_challenge={challenge}&pow_nonce={nonce}");
+
+    // Act
+    let first = app.post_subscriptions(format!("{FORM}&{solution}")).await;
+    let replayed = app
+        .post_subscriptions(format!("name=ged&email=ged%40earthsea.example&{solution}"))
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_verification_failed(replayed).await;
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn signups_without_a_solved_proof_of_work_are_rejected() {
+    // Arrange
+    let app = spawn_app_with_proof_of_work().await;
+    let (challenge, _) = solve_challenge(&app).await;
+    let wrong_nonce = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| !is_solution(&challenge, nonce, 8))
+        .unwrap();
+
+    // Act
+    let missing = app.post_subscriptions(FORM.into()).await;
+    let unsolved = app
+        .post_subscriptions(format!(
+            "{FORM}&pow_challenge={challenge}&pow_nonce={wrong_nonce}"
+        ))
+        .await;
+    let forged = app
+        .post_subscriptions(format!(
+            "{FORM}&pow_challenge=9999999999.forged.k.signature&pow_nonce=0"
+        ))
+        .await;
+
+    // Assert
+    assert_verification_failed(missing).await;
+    assert_verification_failed(unsolved).await;
+    assert_verification_failed(forged).await;
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn challenges_are_only_handed_out_for_proof_of_work() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/challenge", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
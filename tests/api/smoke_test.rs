@@ -0,0 +1,132 @@
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::SmokeTestSettings;
+
+async fn post_smoke_test(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/smoke_test", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn spawn_app_with_smoke_tests() -> TestApp {
+    spawn_app_with(|c| {
+        c.smoke_test = Some(SmokeTestSettings {
+            sink_email: "sink@example.com".into(),
+        })
+    })
+    .await
+}
+
+async fn remaining_subscribers(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn smoke_tests_take_an_admin() {
+    // Arrange
+    let app = spawn_app_with_smoke_tests().await;
+    let editor = TestUser::with_role("editor");
+    editor.store(&app.db_pool).await;
+    let url = format!("{}/admin/smoke_test", &app.address);
+
+    // Act
+    let anonymous = reqwest::Client::new().post(&url).send().await.unwrap();
+    let as_editor = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&editor.username, Some(&editor.password))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(as_editor.status().as_u16(), 403);
+    assert_eq!(remaining_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn smoke_tests_are_not_found_unless_configured() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_smoke_test(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_smoke_test_runs_through_confirmation_and_delivery() {
+    // Arrange
+    let app = spawn_app_with_smoke_tests().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The confirmation email and the test issue.
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_smoke_test(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["passed"], true);
+    let steps: Vec<_> = report["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|step| step["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            "subscribe",
+            "send_confirmation",
+            "confirm",
+            "deliver_issue",
+            "verify_delivery",
+            "clean_up"
+        ]
+    );
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert!(body["To"].as_str().unwrap().starts_with("sink+smoke-"));
+    assert_eq!(remaining_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_failed_smoke_test_reports_the_failing_step_and_cleans_up() {
+    // Arrange
+    let app = spawn_app_with_smoke_tests().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_smoke_test(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["passed"], false);
+    let steps = report["steps"].as_array().unwrap();
+    assert_eq!(steps[1]["name"], "send_confirmation");
+    assert_eq!(steps[1]["passed"], false);
+    assert!(steps[1]["error"].is_string());
+    assert_eq!(steps[2]["name"], "clean_up");
+    assert_eq!(steps[2]["passed"], true);
+    assert_eq!(remaining_subscribers(&app).await, 0);
+}
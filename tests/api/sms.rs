@@ -0,0 +1,217 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SMS_PATH: &str = "/Accounts/AC123/Messages.json";
+
+async fn post_sms(app: &TestApp, action: &str, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/sms/{}", &app.address, action))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Register a phone number and return the verification code that was texted to it.
+async fn register_phone_number(app: &TestApp, preference_token: &str) -> String {
+    let _mock_guard = Mock::given(path(SMS_PATH))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    post_sms(
+        app,
+        "register",
+        serde_json::json!({
+            "preference_token": preference_token,
+            "phone_number": "+1 415 555 0123",
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let sms_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Vec<(String, String)> = serde_urlencoded::from_bytes(&sms_request.body).unwrap();
+    let text = &body.iter().find(|(key, _)| key == "Body").unwrap().1;
+    text.rsplit(' ').next().unwrap().to_owned()
+}
+
+fn newsletter_also_by_sms() -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["also_sms"] = true.into();
+    body
+}
+
+#[tokio::test]
+async fn registering_with_an_unknown_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_sms(
+        &app,
+        "register",
+        serde_json::json!({
+            "preference_token": "aaaaaaaaaaaaaaaaaaaaaaaaa",
+            "phone_number": "+14155550123",
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn registering_an_invalid_phone_number_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+
+    // Act
+    let response = post_sms(
+        &app,
+        "register",
+        serde_json::json!({
+            "preference_token": preference_token,
+            "phone_number": "555-0123",
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_wrong_verification_code_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let code = register_phone_number(&app, &preference_token).await;
+    let wrong_code = if code == "000000" { "000001" } else { "000000" };
+
+    // Act
+    let response = post_sms(
+        &app,
+        "verify",
+        serde_json::json!({"preference_token": preference_token, "code": wrong_code}),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn verified_subscribers_get_an_sms_for_issues_flagged_also_sms() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let code = register_phone_number(&app, &preference_token).await;
+    post_sms(
+        &app,
+        "verify",
+        serde_json::json!({"preference_token": preference_token, "code": code}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path(SMS_PATH))
+        .and(method("POST"))
+        .and(body_string_contains("To=%2B14155550123"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_also_by_sms()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["sms_delivered"], 1);
+    let sms_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .rfind(|request| request.url.path() == SMS_PATH)
+        .unwrap();
+    let body: Vec<(String, String)> = serde_urlencoded::from_bytes(&sms_request.body).unwrap();
+    let text = &body.iter().find(|(key, _)| key == "Body").unwrap().1;
+    assert!(text.ends_with(&format!(
+        "/archive/{}",
+        summary["newsletter_issue_id"].as_str().unwrap()
+    )));
+}
+
+#[tokio::test]
+async fn subscribers_who_opted_out_get_no_sms() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let preference_token = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .preference_token();
+    let code = register_phone_number(&app, &preference_token).await;
+    post_sms(
+        &app,
+        "verify",
+        serde_json::json!({"preference_token": preference_token, "code": code}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    post_sms(
+        &app,
+        "opt_out",
+        serde_json::json!({"preference_token": preference_token}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path(SMS_PATH))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_also_by_sms()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
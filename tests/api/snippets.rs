@@ -0,0 +1,74 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn creating_a_snippet_returns_201() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let response = app
+        .post_snippet(serde_json::json!({"name": "footer", "content": "Thanks for reading!"}))
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn creating_a_snippet_twice_returns_409() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "footer", "content": "Thanks for reading!"});
+    app.post_snippet(body.clone()).await;
+    // Act
+    let response = app.post_snippet(body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn snippets_with_invalid_names_or_references_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "Footer Blurb", "content": "Bye"}),
+            "invalid name",
+        ),
+        (
+            serde_json::json!({"name": "footer", "content": "{{> missing }}"}),
+            "unknown reference",
+        ),
+        (
+            serde_json::json!({"name": "footer", "content": "{{> footer }}"}),
+            "self reference",
+        ),
+    ];
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_snippet(body).await;
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn snippets_still_in_use_cannot_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_snippet(serde_json::json!({"name": "bio", "content": "Ursula"}))
+        .await;
+    app.post_snippet(serde_json::json!({"name": "footer", "content": "Bye. {{> bio }}"}))
+        .await;
+    // Act
+    let response = app.delete_snippet("bio").await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Once the dependent snippet is gone the deletion goes through
+    assert_eq!(app.delete_snippet("footer").await.status().as_u16(), 204);
+    assert_eq!(app.delete_snippet("bio").await.status().as_u16(), 204);
+}
@@ -0,0 +1,177 @@
+use crate::helpers::{TestApp, spawn_app};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use zero2prod::sponsors::{SPONSOR_CLICK_SIGNATURE, SPONSOR_OPEN_SIGNATURE};
+
+fn active_slot() -> serde_json::Value {
+    serde_json::json!({
+        "sponsor_name": "ACME",
+        "creative_html": "<a href=\"{{ sponsor_link }}\">Try ACME</a>",
+        "target_url": "https://acme.example.com/landing",
+        "starts_at": Utc::now() - Duration::days(1),
+        "ends_at": Utc::now() + Duration::days(1),
+    })
+}
+
+/// A tracking url signed the way issues sign them.
+fn tracking_url(app: &TestApp, slot_id: &str, action: &str, purpose: &str) -> String {
+    let slot_id: Uuid = slot_id.parse().unwrap();
+    format!(
+        "{}/sponsors/{}/{}?signature={}",
+        &app.address,
+        slot_id,
+        action,
+        app.key_ring.sign(purpose, slot_id.as_bytes())
+    )
+}
+
+#[tokio::test]
+async fn invalid_sponsor_slots_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut without_placeholder = active_slot();
+    without_placeholder["creative_html"] = "<p>ACME</p>".into();
+    let mut invalid_url = active_slot();
+    invalid_url["target_url"] = "not-a-url".into();
+    let mut ends_before_start = active_slot();
+    ends_before_start["ends_at"] = serde_json::json!(Utc::now() - Duration::days(2));
+    let test_cases = vec![
+        (without_placeholder, "the creative has no tracked link"),
+        (invalid_url, "the target url is invalid"),
+        (ends_before_start, "the slot ends before it starts"),
+    ];
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_sponsor_slot(body).await;
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn clicks_are_counted_and_redirected_to_the_sponsor() {
+    // Arrange
+    let app = spawn_app().await;
+    let created: serde_json::Value = app
+        .post_sponsor_slot(active_slot())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let slot_id = created["id"].as_str().unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client
+        .get(tracking_url(
+            &app,
+            slot_id,
+            "click",
+            SPONSOR_CLICK_SIGNATURE,
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://acme.example.com/landing"
+    );
+    let report = app.get_sponsor_report(slot_id).await;
+    assert_eq!(report["clicks"], 1);
+    assert_eq!(report["opens"], 0);
+}
+
+#[tokio::test]
+async fn unsigned_clicks_are_redirected_but_not_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    let created: serde_json::Value = app
+        .post_sponsor_slot(active_slot())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let slot_id = created["id"].as_str().unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    // A signature for another purpose must not be accepted either.
+    let test_cases = vec![
+        format!("{}/sponsors/{}/click", &app.address, slot_id),
+        format!(
+            "{}/sponsors/{}/click?signature=local.AAAA",
+            &app.address, slot_id
+        ),
+        tracking_url(&app, slot_id, "click", SPONSOR_OPEN_SIGNATURE),
+    ];
+
+    for url in test_cases {
+        // Act
+        let response = client.get(url).send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 302);
+    }
+    let report = app.get_sponsor_report(slot_id).await;
+    assert_eq!(report["clicks"], 0);
+}
+
+#[tokio::test]
+async fn opens_are_counted_and_served_a_tracking_pixel() {
+    // Arrange
+    let app = spawn_app().await;
+    let created: serde_json::Value = app
+        .post_sponsor_slot(active_slot())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let slot_id = created["id"].as_str().unwrap();
+
+    // Act
+    let response = reqwest::get(tracking_url(&app, slot_id, "open", SPONSOR_OPEN_SIGNATURE))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let report = app.get_sponsor_report(slot_id).await;
+    assert_eq!(report["opens"], 1);
+}
+
+#[tokio::test]
+async fn unsigned_opens_are_served_a_pixel_but_not_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    let created: serde_json::Value = app
+        .post_sponsor_slot(active_slot())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let slot_id = created["id"].as_str().unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/sponsors/{}/open", &app.address, slot_id))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let report = app.get_sponsor_report(slot_id).await;
+    assert_eq!(report["opens"], 0);
+}
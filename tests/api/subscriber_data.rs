@@ -0,0 +1,204 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_data_request(app: &TestApp, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/data_request", &app.address))
+        .form(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// The token in the export and erasure links emailed to the subscriber.
+async fn request_data_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = post_data_request(app, "ursula_le_guin@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let links: Vec<_> = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+        .collect();
+    let paths: Vec<_> = links.iter().map(reqwest::Url::path).collect();
+    assert_eq!(paths, vec!["/subscriptions/export", "/subscriptions/erase"]);
+    links[0]
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn get_export(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/subscriptions/export", &app.address))
+        .query(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_erase(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/erase", &app.address))
+        .form(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_data_request(&app, "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_export_link_returns_everything_stored_about_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust")
+        .await
+        .id;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.email_server.reset().await;
+    let token = request_data_token(&app).await;
+
+    // Act
+    let response = get_export(&app, &token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        r#"attachment; filename="subscriber-data.json""#
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber_id"], subscriber_id.to_string());
+    assert_eq!(data["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(data["name"], "le guin");
+    assert_eq!(data["status"], "confirmed");
+    assert_eq!(data["tags"], serde_json::json!(["rust"]));
+    assert_eq!(data["deliveries"][0]["title"], "Newsletter title");
+    assert_eq!(data["deliveries"][0]["status"], "sent");
+    assert!(data["sms_registration"].is_null());
+}
+
+#[tokio::test]
+async fn erasing_deletes_the_subscriber_and_records_the_erasure() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust")
+        .await
+        .id;
+    let token = request_data_token(&app).await;
+
+    // Act
+    let form = reqwest::get(format!(
+        "{}/subscriptions/erase?token={token}",
+        &app.address
+    ))
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+    let response = post_erase(&app, &token).await;
+
+    // Assert
+    assert!(form.contains(&format!(r#"name="token" value="{token}""#)));
+    assert_eq!(response.status().as_u16(), 200);
+    let remaining = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM subscriptions)
+            + (SELECT COUNT(*) FROM subscription_tokens)
+            + (SELECT COUNT(*) FROM subscriber_tags) AS "count!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+    let erasure =
+        sqlx::query!("SELECT subscriber_id, requested_at, erased_at FROM subscriber_erasures")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(erasure.subscriber_id, subscriber_id);
+    assert!(erasure.requested_at <= erasure.erased_at);
+    // The links stop working
+    assert_eq!(post_erase(&app, &token).await.status().as_u16(), 401);
+    assert_eq!(get_export(&app, &token).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unknown_or_expired_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust")
+        .await;
+    let token = request_data_token(&app).await;
+    sqlx::query!("UPDATE data_request_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    for token in [token.as_str(), "not-a-token", "aaaaaaaaaaaaaaaaaaaaaaaaa"] {
+        // Act
+        let export = get_export(&app, token).await;
+        let erase = post_erase(&app, token).await;
+
+        // Assert
+        assert_eq!(export.status().as_u16(), 401);
+        assert_eq!(erase.status().as_u16(), 401);
+    }
+    let subscribers = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 1);
+}
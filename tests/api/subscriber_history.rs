@@ -0,0 +1,277 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use uuid::Uuid;
+use zero2prod::routes::get_or_create_unsubscribe_token;
+use zero2prod::signed_tokens::TokenPurpose;
+
+const FORM: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Subscribe, returning the new subscriber's id and their confirmation link.
+async fn subscribe(app: &TestApp) -> (Uuid, reqwest::Url) {
+    app.post_subscriptions(FORM.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let subscriber_id =
+        sqlx::query_scalar!("SELECT id FROM subscriptions ORDER BY subscribed_at DESC LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    (
+        subscriber_id,
+        app.get_confirmation_links(&email_request).html,
+    )
+}
+
+async fn unsubscribe(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    let token = get_or_create_unsubscribe_token(&app.db_pool, subscriber_id)
+        .await
+        .unwrap();
+    reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        &app.address,
+        app.token_signer.sign(TokenPurpose::Unsubscribe, &token)
+    ))
+    .await
+    .unwrap()
+}
+
+async fn delete_subscriber(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{subscriber_id}",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_history(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    app.get_admin_subscribers(&format!("/{subscriber_id}/history"))
+        .await
+}
+
+#[tokio::test]
+async fn the_history_of_a_subscriber_tells_who_changed_their_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.mount_email_server().await;
+    let (subscriber_id, confirmation_link) = subscribe(&app).await;
+    let follow_unsubscribe_link = || async {
+        unsubscribe(&app, subscriber_id)
+            .await
+            .error_for_status()
+            .unwrap();
+    };
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // A confirmation link works once; unsubscribing twice changes nothing the second time.
+    let again = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(again.status().as_u16(), 401);
+    follow_unsubscribe_link().await;
+    follow_unsubscribe_link().await;
+    // The old link no longer confirms them: signing up again does.
+    let (_, confirmation_link) = subscribe(&app).await;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    follow_unsubscribe_link().await;
+    assert_eq!(delete_subscriber(&app, subscriber_id).await.status(), 204);
+
+    // Act
+    let response = get_history(&app, subscriber_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let history: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(history["status"], "unsubscribed");
+    assert!(history["deleted_at"].is_string());
+    let events: Vec<_> = history["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| {
+            (
+                event["event"].as_str().unwrap(),
+                event["actor"].as_str().unwrap(),
+                event["username"].as_str(),
+                event["source_ip"].as_str(),
+            )
+        })
+        .collect();
+    let username = Some(app.test_user.username.as_str());
+    assert_eq!(
+        events,
+        vec![
+            ("subscribed", "subscriber", None, Some("127.0.0.1")),
+            ("confirmed", "subscriber", None, Some("127.0.0.1")),
+            ("unsubscribed", "subscriber", None, Some("127.0.0.1")),
+            ("subscribed", "subscriber", None, Some("127.0.0.1")),
+            ("confirmed", "subscriber", None, Some("127.0.0.1")),
+            ("unsubscribed", "subscriber", None, Some("127.0.0.1")),
+            ("deleted", "user", username, Some("127.0.0.1")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_kept_but_left_out_and_can_sign_up_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.mount_email_server().await;
+    let (deleted_id, _) = subscribe(&app).await;
+    assert_eq!(delete_subscriber(&app, deleted_id).await.status(), 204);
+
+    // Act
+    let (subscriber_id, _) = subscribe(&app).await;
+
+    // Assert
+    assert_ne!(subscriber_id, deleted_id);
+    let page: serde_json::Value = app.get_admin_subscribers("").await.json().await.unwrap();
+    assert_eq!(page["total"], 1);
+    assert_eq!(
+        page["subscribers"][0]["subscriber_id"],
+        subscriber_id.to_string()
+    );
+    let deleted = app.get_admin_subscribers(&format!("/{deleted_id}")).await;
+    assert_eq!(deleted.status().as_u16(), 404);
+    let history: serde_json::Value = get_history(&app, deleted_id).await.json().await.unwrap();
+    assert_eq!(history["events"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn the_history_of_an_unknown_subscriber_is_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_history(&app, Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_history_of_a_subscriber_needs_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/admin/subscribers/{}/history",
+        &app.address,
+        Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn get_timeline(app: &TestApp, subscriber_id: Uuid, query: &str) -> serde_json::Value {
+    app.get_admin_subscribers(&format!("/{subscriber_id}/timeline{query}"))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn event_names(timeline: &serde_json::Value) -> Vec<&str> {
+    timeline["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_timeline_of_a_subscriber_merges_their_history_deliveries_and_opens() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let subscriber = app.create_confirmed_subscriber(FORM).await;
+    app.mount_email_server().await;
+    let summary = app.publish(newsletter_request_body()).await;
+    let newsletter_issue_id: Uuid = summary["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        VALUES ($1, $2, $3, 'open', now() + interval '1 minute')
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let timeline = get_timeline(&app, subscriber.id, "").await;
+
+    // Assert
+    assert_eq!(
+        event_names(&timeline),
+        ["opened", "issue_sent", "confirmed", "subscribed"]
+    );
+    assert_eq!(timeline["total"], 4);
+    let opened = &timeline["events"][0];
+    assert_eq!(
+        opened["newsletter_issue_id"],
+        newsletter_issue_id.to_string()
+    );
+    assert_eq!(opened["title"], "Newsletter title");
+    assert_eq!(timeline["events"][2]["actor"], "subscriber");
+}
+
+#[tokio::test]
+async fn the_timeline_of_a_subscriber_is_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let subscriber = app.create_confirmed_subscriber(FORM).await;
+    app.mount_email_server().await;
+    app.publish(newsletter_request_body()).await;
+
+    // Act
+    let timeline = get_timeline(&app, subscriber.id, "?page=2&per_page=2").await;
+
+    // Assert
+    assert_eq!(event_names(&timeline), ["subscribed"]);
+    assert_eq!(timeline["total"], 3);
+}
+
+#[tokio::test]
+async fn the_timeline_of_an_unknown_subscriber_is_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .get_admin_subscribers(&format!("/{}/timeline", Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,186 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_import(app: &TestApp, query: &str, file: &str) -> reqwest::Response {
+    let form = reqwest::multipart::Form::new().part(
+        "file",
+        reqwest::multipart::Part::text(file.to_owned()).file_name("subscribers.csv"),
+    );
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/import{}",
+            &app.address, query
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// The `(email, status)` of every subscriber, sorted by email.
+async fn saved_subscribers(app: &TestApp) -> Vec<(String, String)> {
+    let page: serde_json::Value = app.get_admin_subscribers("").await.json().await.unwrap();
+    let mut subscribers: Vec<(String, String)> = page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["email"].as_str().unwrap().to_owned(),
+                s["status"].as_str().unwrap().to_owned(),
+            )
+        })
+        .collect();
+    subscribers.sort();
+    subscribers
+}
+
+#[tokio::test]
+async fn a_csv_is_imported_as_confirmed_subscribers_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "Email Address,First Name,Last Name\n\
+        ursula_le_guin@gmail.com,Ursula,Le Guin\n\
+        octavia_butler@gmail.com,Octavia,Butler\n";
+
+    // Act
+    let response = post_import(&app, "", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["errors"], serde_json::json!([]));
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![
+            ("octavia_butler@gmail.com".into(), "confirmed".into()),
+            ("ursula_le_guin@gmail.com".into(), "confirmed".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pending_imports_are_sent_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let json = r#"[{"email": "ursula_le_guin@gmail.com", "name": "le guin"}]"#;
+
+    // Act
+    let response = post_import(&app, "?status=pending_confirmation", json).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![(
+            "ursula_le_guin@gmail.com".into(),
+            "pending_confirmation".into()
+        )]
+    );
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("ursula_le_guin@gmail.com".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn invalid_rows_and_duplicates_are_reported_and_the_rest_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        not-an-email,someone\n\
+        octavia_butler@gmail.com,\n\
+        ursula_le_guin@gmail.com,le guin\n";
+
+    // Act
+    let response = post_import(&app, "", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    let errors: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["row"].as_u64().unwrap(), e["field"].as_str().unwrap()))
+        .collect();
+    assert_eq!(errors, vec![(2, "email"), (3, "name"), (4, "email")]);
+    assert_eq!(report["errors"][2]["error"], "duplicate");
+    assert_eq!(saved_subscribers(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn imports_are_rejected_with_a_400_if_the_file_or_status_is_invalid() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("", "name\nle guin\n", "a CSV without an email column"),
+        ("", "[{\"email\": ", "a truncated JSON array"),
+        (
+            "?status=unsubscribed",
+            "email,name\nursula_le_guin@gmail.com,le guin\n",
+            "an unknown status",
+        ),
+        (
+            "?newsletter=go-weekly",
+            "email,name\nursula_le_guin@gmail.com,le guin\n",
+            "an unknown list",
+        ),
+    ];
+
+    for (query, file, description) in test_cases {
+        // Act
+        let response = post_import(&app, query, file).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the import was {}.",
+            description
+        );
+    }
+    assert!(saved_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn imports_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    let form = reqwest::multipart::Form::new().text("file", "email,name\n");
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -1,6 +1,10 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::RateLimitSettings;
+use zero2prod::domain::SignupField;
+use zero2prod::routes::get_or_create_unsubscribe_token;
+use zero2prod::signed_tokens::TokenPurpose;
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -71,6 +75,10 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
         ("name=Ursula&email=", "empty email"),
         ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+        (
+            "name=Ursula&email=ursula_le_guin%40gmail.com&redirect_to=https%3A%2F%2Fevil.com",
+            "redirect to a host that is not allowed",
+        ),
     ];
     for (body, description) in test_cases {
         // Act
@@ -85,6 +93,53 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_reports_which_field_is_invalid_and_why() {
+    // Arrange
+    let app = spawn_app().await;
+    let long_name = "a".repeat(257);
+    let test_cases = vec![
+        (
+            "name=&email=ursula_le_guin%40gmail.com".to_string(),
+            "name",
+            "empty",
+        ),
+        (
+            format!("name={long_name}&email=ursula_le_guin%40gmail.com"),
+            "name",
+            "too_long",
+        ),
+        (
+            "name=Ursula&email=definitely-not-an-email".to_string(),
+            "email",
+            "invalid_email",
+        ),
+        (
+            "name=Ursula&email=ursula_le_guin%40gmail.com&language=klingon%2Fqo".to_string(),
+            "language",
+            "invalid",
+        ),
+    ];
+    for (body, field, error) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body.clone()).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json"
+        );
+        let payload: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(payload["type"], "/problems/validation-error");
+        assert_eq!(payload["status"], 400);
+        assert_eq!(payload["field"], field, "Wrong field for {body}.");
+        assert_eq!(payload["error"], error, "Wrong error for {body}.");
+        assert!(payload["detail"].is_string());
+        assert!(payload["trace_id"].is_string());
+    }
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     // Arrange
@@ -101,11 +156,99 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     // Assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     // The two links should be identical
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[tokio::test]
+async fn confirmation_emails_are_rendered_from_the_configured_templates() {
+    // Arrange
+    let templates_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(templates_dir.join("email")).unwrap();
+    for (file, template) in [
+        (
+            "confirmation.html",
+            "<p>Hi {{ name }}!</p><a href=\"{{ confirmation_link }}\">Confirm</a>",
+        ),
+        (
+            "confirmation.txt",
+            "Hi {{ name }}! Confirm at {{ confirmation_link }}",
+        ),
+        ("newsletter.html", "{{ html_content | safe }}"),
+        ("newsletter.txt", "{{ text_content }}"),
+    ] {
+        std::fs::write(templates_dir.join("email").join(file), template).unwrap();
+    }
+    let app = spawn_app_with(|c| {
+        c.application.templates_dir = templates_dir.to_str().unwrap().to_owned();
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(
+        body["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("<p>Hi le guin!</p>")
+    );
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Hi le guin! Confirm at ")
+    );
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+    std::fs::remove_dir_all(templates_dir).unwrap();
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_sent_in_the_language_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&language=fr_CA".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let language = sqlx::query_scalar!("SELECT language FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(language.as_deref(), Some("fr-ca"));
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Bienvenue !");
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Bienvenue dans ")
+    );
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
 // to see good logs, npm i -g bunyan
 // export RUST_LOG="sqlx=error,info" export TEST_LOG=enabled cargo t subscribe_fails_if_there_is_a_fatal_database_error | bunyan
 #[tokio::test]
@@ -123,3 +266,258 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+async fn spawn_app_with_a_company_field() -> TestApp {
+    let app = spawn_app_with(|c| {
+        c.application.signup_fields = vec![SignupField {
+            name: "company".into(),
+            required: true,
+            max_length: 100,
+        }];
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+#[tokio::test]
+async fn subscribe_stores_answers_to_configured_signup_fields() {
+    // Arrange
+    let app = spawn_app_with_a_company_field().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&company=Earthsea";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.attributes, serde_json::json!({"company": "Earthsea"}));
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_when_a_required_signup_field_is_missing() {
+    // Arrange
+    let app = spawn_app_with_a_company_field().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn signup_fields_are_published_for_embedded_forms() {
+    // Arrange
+    let app = spawn_app_with_a_company_field().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/fields", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let fields: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        fields,
+        serde_json::json!([{"name": "company", "required": true, "max_length": 100}])
+    );
+}
+
+#[tokio::test]
+async fn subscription_attempts_are_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscription_rate_limit = RateLimitSettings {
+            max_requests: 2,
+            window_seconds: 60,
+        }
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // Signups over the limit don't get a confirmation email.
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    for email in ["ursula%40gmail.com", "le_guin%40gmail.com"] {
+        app.post_subscriptions(format!("name=le%20guin&email={email}"))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn other_subscription_endpoints_do_not_count_towards_the_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscription_rate_limit = RateLimitSettings {
+            max_requests: 1,
+            window_seconds: 60,
+        }
+    })
+    .await;
+
+    for _ in 0..3 {
+        // Act
+        let response = reqwest::get(format!("{}/subscriptions/fields", app.address))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let requests = app.email_server.received_requests().await.unwrap();
+    reqwest::get(app.get_confirmation_links(&requests[1]).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let subscribers = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_returns_a_200_and_sends_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The first confirmation email only.
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_takes_a_new_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let token = get_or_create_unsubscribe_token(&app.db_pool, subscriber_id)
+        .await
+        .unwrap();
+    reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        &app.address,
+        app.token_signer.sign(TokenPurpose::Unsubscribe, &token)
+    ))
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+    // Act - Part 1 - Subscribe again
+    let response = app.post_subscriptions(body.into()).await;
+    let status_before_confirming = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 2 - Confirm
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status_before_confirming, "pending_confirmation");
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
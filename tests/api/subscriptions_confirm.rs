@@ -1,6 +1,8 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use chrono::{Duration, Utc};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::signed_tokens::TokenPurpose;
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -16,7 +18,7 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     // Act
     let response = reqwest::get(confirmation_links.html).await.unwrap();
     // Assert
@@ -36,7 +38,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -55,3 +57,407 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirming_redirects_to_the_requested_continuation() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com\
+        &redirect_to=https%3A%2F%2Fexample.com%2Fthank-you";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client.get(confirmation_links.html).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/thank-you"
+    );
+}
+
+#[tokio::test]
+async fn confirmations_for_another_host_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("Host", "www.example.com")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirmations_that_fail_answer_with_a_problem_to_report() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN confirmed_at;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/internal-error");
+    assert!(problem["trace_id"].is_string());
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let signed = app
+        .token_signer
+        .sign(TokenPurpose::Subscription, &"a".repeat(25));
+    let test_cases = vec![
+        ("tooshort".to_string(), "too short"),
+        ("aaaaaaaaaaaaaaaaaaaaaaaaaa".into(), "too long"),
+        ("aaaaaaaaaaaa%27aaaaaaaaaaaa".into(), "not alphanumeric"),
+        ("a".repeat(25), "not signed"),
+        (signed.replacen('a', "b", 1), "tampered with"),
+        (
+            app.token_signer
+                .sign(TokenPurpose::Unsubscribe, &"a".repeat(25)),
+            "signed for unsubscribing",
+        ),
+    ];
+
+    for (token, description) in test_cases {
+        // Act
+        let response = reqwest::get(format!(
+            "{}/subscriptions/confirm?subscription_token={}",
+            app.address, token
+        ))
+        .await
+        .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the token was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        app.address,
+        app.token_signer
+            .sign(TokenPurpose::Subscription, &"a".repeat(25))
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn confirmation_attempts_are_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        app.address,
+        app.token_signer
+            .sign(TokenPurpose::Subscription, &"a".repeat(25))
+    );
+    for _ in 0..10 {
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = reqwest::get(&url).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn rejected_attempts_are_counted_in_the_confirmation_stats() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirm = |token: String| {
+        reqwest::get(format!(
+            "{}/subscriptions/confirm?subscription_token={}",
+            app.address, token
+        ))
+    };
+    confirm("malformed".into()).await.unwrap();
+    for _ in 0..10 {
+        confirm(
+            app.token_signer
+                .sign(TokenPurpose::Subscription, &"a".repeat(25)),
+        )
+        .await
+        .unwrap();
+    }
+
+    // Act
+    let stats = app.get_confirmation_stats().await;
+
+    // Assert
+    assert_eq!(stats["rejected_attempts"]["malformed_token"], 1);
+    assert_eq!(stats["rejected_attempts"]["unknown_token"], 9);
+    assert_eq!(stats["rejected_attempts"]["rate_limited"], 1);
+}
+
+#[tokio::test]
+async fn requests_without_a_token_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+/// Subscribes someone and returns their stored token, as links sent before tokens
+/// were signed carried it.
+async fn unsigned_confirmation_token(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query_scalar!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unsigned_tokens_are_accepted_during_the_grace_period() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.signing.unsigned_tokens_accepted_until = Some(Utc::now() + Duration::days(1));
+    })
+    .await;
+    let token = unsigned_confirmation_token(&app).await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        app.address, token
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsigned_tokens_are_rejected_with_a_400_after_the_grace_period() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.signing.unsigned_tokens_accepted_until = Some(Utc::now() - Duration::days(1));
+    })
+    .await;
+    let token = unsigned_confirmation_token(&app).await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        app.address, token
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_a_410_offering_a_resend() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("/subscriptions/resend_confirmation")
+    );
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_unsubscribed_or_bounced_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    for status in ["unsubscribed", "bounced"] {
+        sqlx::query!("UPDATE subscriptions SET status = $1", status)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+
+        // Act
+        let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        let saved = sqlx::query!("SELECT status FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(saved.status, status);
+    }
+}
+
+#[tokio::test]
+async fn confirming_deletes_the_link_and_hands_out_a_preference_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber = app
+        .create_pending_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = reqwest::get(subscriber.confirmation_link.clone())
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let preference_token = body["preference_token"].as_str().unwrap();
+    let tokens = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber.id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens, 0);
+    let again = reqwest::get(subscriber.confirmation_link.clone())
+        .await
+        .unwrap();
+    assert_eq!(again.status().as_u16(), 401);
+    let preferences = reqwest::get(format!(
+        "{}/subscriptions/categories?token={}",
+        app.address, preference_token
+    ))
+    .await
+    .unwrap();
+    assert_eq!(preferences.status().as_u16(), 200);
+}
@@ -0,0 +1,125 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::RateLimitSettings;
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn sign_up(app: &TestApp) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn expect_emails(app: &TestApp, expected_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn a_resent_confirmation_link_replaces_the_previous_one() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_emails(&app, 2).await;
+    sign_up(&app).await;
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&requests[0]).html;
+    let second_link = app.get_confirmation_links(&requests[1]).html;
+    assert_ne!(first_link, second_link);
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    reqwest::get(second_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_a_200_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_get_no_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_emails(&app, 1).await;
+    sign_up(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resends_are_rate_limited_per_subscriber() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.resend_confirmation_rate_limit = RateLimitSettings {
+            max_requests: 1,
+            window_seconds: 3600,
+        }
+    })
+    .await;
+    // The signup and a single resend.
+    expect_emails(&app, 2).await;
+    sign_up(&app).await;
+    app.post_resend_confirmation(EMAIL)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn resending_to_an_invalid_address_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("definitely-not-an-email")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
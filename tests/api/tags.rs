@@ -0,0 +1,301 @@
+use crate::helpers::{TestApp, TestUser, newsletter_request_body, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_tags(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/{subscriber_id}/tags",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_tags(
+    app: &TestApp,
+    subscriber_id: Uuid,
+    tags: serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/{subscriber_id}/tags",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "tags": tags }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn delete_tag(app: &TestApp, subscriber_id: Uuid, tag: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{subscriber_id}/tags/{tag}",
+            &app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn newsletter_to_segment(segment: serde_json::Value) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["segment"] = segment;
+    body
+}
+
+#[tokio::test]
+async fn tags_given_when_subscribing_are_stored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let subscriber_id = app
+        .create_confirmed_subscriber(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust%2C%20beta%2Crust",
+        )
+        .await
+        .id;
+
+    // Assert
+    let tags: Vec<String> = get_tags(&app, subscriber_id).await.json().await.unwrap();
+    assert_eq!(tags, vec!["beta", "rust"]);
+}
+
+#[tokio::test]
+async fn invalid_tags_are_rejected_when_subscribing() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=Not%20A%20Tag".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn admins_can_tag_and_untag_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+
+    // Act - Part 1 - Tag
+    let response = post_tags(&app, subscriber_id, serde_json::json!(["rust", "beta"])).await;
+    assert_eq!(response.status().as_u16(), 204);
+    // Tagging again is a no-op
+    let response = post_tags(&app, subscriber_id, serde_json::json!(["rust"])).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Act - Part 2 - Untag
+    let response = delete_tag(&app, subscriber_id, "beta").await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Assert
+    let tags: Vec<String> = get_tags(&app, subscriber_id).await.json().await.unwrap();
+    assert_eq!(tags, vec!["rust"]);
+    let summary: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/tags", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        summary,
+        serde_json::json!([{ "tag": "rust", "subscribers": 1 }])
+    );
+}
+
+#[tokio::test]
+async fn tagging_rejects_invalid_tags_and_unknown_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+
+    // Act
+    let invalid = post_tags(&app, subscriber_id, serde_json::json!(["Not A Tag"])).await;
+    let unknown = post_tags(&app, Uuid::new_v4(), serde_json::json!(["rust"])).await;
+    let not_tagged = delete_tag(&app, subscriber_id, "rust").await;
+
+    // Assert
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(unknown.status().as_u16(), 404);
+    assert_eq!(not_tagged.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn viewers_can_read_tags_but_not_change_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let url = format!("{}/admin/subscribers/{subscriber_id}/tags", &app.address);
+
+    // Act
+    let anonymous = reqwest::get(&url).await.unwrap();
+    let read = reqwest::Client::new()
+        .get(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .send()
+        .await
+        .unwrap();
+    let tag = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({ "tags": ["rust"] }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(tag.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn issues_only_go_to_subscribers_in_their_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    let both = app
+        .create_confirmed_subscriber(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust%2Cbeta",
+        )
+        .await
+        .id;
+    app.create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com&tags=rust")
+        .await;
+    app.create_confirmed_subscriber("name=austen&email=jane_austen%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let any: serde_json::Value = app
+        .post_newsletters(newsletter_to_segment(
+            serde_json::json!({ "tags": ["rust", "beta"] }),
+        ))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let all: serde_json::Value = app
+        .post_newsletters(newsletter_to_segment(
+            serde_json::json!({ "tags": ["rust", "beta"], "match": "all" }),
+        ))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(any["delivered"], 2);
+    assert_eq!(all["delivered"], 1);
+    let last_recipient = sqlx::query_scalar!(
+        "SELECT subscriber_id FROM broadcast_deliveries ORDER BY sent_at DESC LIMIT 1"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(last_recipient, both);
+}
+
+#[tokio::test]
+async fn subscribers_with_an_excluded_tag_do_not_get_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=rust%2Cbeta",
+    )
+    .await;
+    let tolkien = app
+        .create_confirmed_subscriber("name=tolkien&email=jrr_tolkien%40gmail.com&tags=rust")
+        .await
+        .id;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "exclude": { "tags": ["beta"] },
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response["delivered"], 1);
+    let recipients = sqlx::query_scalar!("SELECT subscriber_id FROM broadcast_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(recipients, vec![tolkien]);
+}
+
+#[tokio::test]
+async fn empty_or_invalid_segments_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+
+    for segment in [
+        serde_json::json!({ "tags": [] }),
+        serde_json::json!({ "tags": ["Not A Tag"] }),
+        serde_json::json!({ "tags": ["rust"], "match": "most" }),
+    ] {
+        // Act
+        let response = app
+            .post_newsletters(newsletter_to_segment(segment.clone()))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject the segment {segment}"
+        );
+    }
+}
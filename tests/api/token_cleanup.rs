@@ -0,0 +1,130 @@
+use crate::helpers::{TestApp, spawn_app};
+use chrono::{Duration, Utc};
+use zero2prod::configuration::TokenCleanupSettings;
+use zero2prod::token_cleanup::{CleanupSummary, clean_up_tokens};
+
+fn settings() -> TokenCleanupSettings {
+    TokenCleanupSettings {
+        prune_pending_after_days: 30,
+    }
+}
+
+/// Subscribe `email`, pretend it happened `days_ago` and that the token expires `expires_in`
+/// from now.
+async fn sign_up_days_ago(app: &TestApp, email: &str, days_ago: i64, expires_in: Duration) {
+    let subscriber = app
+        .create_pending_subscriber(&format!("name=le%20guin&email={}", email))
+        .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = $1 WHERE id = $2",
+        Utc::now() - Duration::days(days_ago),
+        subscriber.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE subscription_tokens SET expires_at = $1 WHERE subscriber_id = $2",
+        Utc::now() + expires_in,
+        subscriber.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Of the subscribers that weren't deleted.
+async fn subscriber_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!("SELECT email FROM subscriptions WHERE deleted_at IS NULL ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn expired_tokens_of_pending_subscribers_are_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_days_ago(&app, "expired%40example.com", 8, -Duration::days(1)).await;
+    sign_up_days_ago(&app, "fresh%40example.com", 1, Duration::days(6)).await;
+
+    // Act
+    let cleanup = clean_up_tokens(&app.db_pool, &settings()).await.unwrap();
+
+    // Assert
+    assert_eq!(
+        cleanup,
+        CleanupSummary {
+            expired_tokens: 1,
+            pruned_subscribers: 0
+        }
+    );
+    let remaining = sqlx::query_scalar!(
+        r#"
+        SELECT s.email FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining, vec!["fresh@example.com"]);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_keep_their_expired_token() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_days_ago(&app, "confirmed%40example.com", 8, -Duration::days(1)).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let cleanup = clean_up_tokens(&app.db_pool, &settings()).await.unwrap();
+
+    // Assert
+    assert_eq!(cleanup.expired_tokens, 0);
+    let tokens = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, 1);
+}
+
+#[tokio::test]
+async fn subscribers_pending_for_too_long_are_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    sign_up_days_ago(&app, "stale%40example.com", 31, -Duration::days(24)).await;
+    sign_up_days_ago(&app, "recent%40example.com", 29, -Duration::days(22)).await;
+    sign_up_days_ago(&app, "confirmed%40example.com", 31, -Duration::days(24)).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'confirmed' WHERE email = 'confirmed@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let cleanup = clean_up_tokens(&app.db_pool, &settings()).await.unwrap();
+
+    // Assert
+    assert_eq!(cleanup.pruned_subscribers, 1);
+    assert_eq!(
+        subscriber_emails(&app).await,
+        vec!["confirmed@example.com", "recent@example.com"]
+    );
+    let deletion = sqlx::query!(
+        r#"
+        SELECT a.event, a.actor FROM audit_log a JOIN subscriptions s ON s.id = a.subscriber_id
+        WHERE s.email = 'stale@example.com' ORDER BY a.id DESC LIMIT 1
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deletion.event, "deleted");
+    assert_eq!(deletion.actor, "token_cleanup");
+}
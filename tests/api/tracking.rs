@@ -0,0 +1,304 @@
+use crate::helpers::{TestApp, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn set_tracking(app: &TestApp, enabled: bool) {
+    reqwest::Client::new()
+        .put(format!("{}/admin/lists/default", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "tracking": enabled }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Publishes an issue linking to `https://example.com/article`, returning its id and the
+/// HTML the subscriber got.
+async fn publish_issue(app: &TestApp) -> (String, String) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let summary: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Read https://example.com/article",
+                "html": "<p><a href=\"https://example.com/article\">Read</a></p>",
+            },
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    (
+        summary["newsletter_issue_id"].as_str().unwrap().to_owned(),
+        email["HtmlBody"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// The first link in `html` to `/{prefix}/...` on the application.
+fn tracking_link(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let raw_link = linkify::LinkFinder::new()
+        .links(html)
+        .map(|link| link.as_str().to_owned())
+        .find(|link| link.contains(&format!("/{prefix}/")))
+        .unwrap();
+    let mut link = reqwest::Url::parse(&raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn engagement(app: &TestApp, newsletter_issue_id: &str) -> serde_json::Value {
+    let issue: serde_json::Value = app
+        .get_newsletter_issues(&format!("/{newsletter_issue_id}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    issue["engagement"].clone()
+}
+
+fn no_redirects() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn issues_of_lists_without_tracking_are_sent_as_they_are() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let (newsletter_issue_id, html) = publish_issue(&app).await;
+
+    // Assert
+    assert!(html.contains("<a href=\"https://example.com/article\">"));
+    assert!(!html.contains("/o/"));
+    assert!(engagement(&app, &newsletter_issue_id).await.is_null());
+}
+
+#[tokio::test]
+async fn opens_and_clicks_of_tracked_issues_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    set_tracking(&app, true).await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let (newsletter_issue_id, html) = publish_issue(&app).await;
+    assert!(!html.contains("href=\"https://example.com/article\""));
+    assert_eq!(
+        engagement(&app, &newsletter_issue_id).await,
+        serde_json::json!({ "opened": 0, "clicked": 0, "open_rate": 0.0, "click_rate": 0.0 })
+    );
+
+    // Act
+    let click = no_redirects()
+        .get(tracking_link(&app, &html, "t"))
+        .send()
+        .await
+        .unwrap();
+    let open = reqwest::get(tracking_link(&app, &html, "o")).await.unwrap();
+
+    // Assert
+    assert_eq!(click.status().as_u16(), 302);
+    assert_eq!(click.headers()["Location"], "https://example.com/article");
+    assert_eq!(open.status().as_u16(), 200);
+    assert_eq!(open.headers()["Content-Type"], "image/gif");
+    assert_eq!(
+        engagement(&app, &newsletter_issue_id).await,
+        serde_json::json!({ "opened": 1, "clicked": 1, "open_rate": 1.0, "click_rate": 1.0 })
+    );
+}
+
+#[tokio::test]
+async fn tracking_links_without_a_valid_signature_are_followed_but_not_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    set_tracking(&app, true).await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let (newsletter_issue_id, html) = publish_issue(&app).await;
+    let mut click_link = tracking_link(&app, &html, "t");
+    click_link.set_query(Some("signature=k.forged"));
+    let mut open_link = tracking_link(&app, &html, "o");
+    open_link.set_query(None);
+
+    // Act
+    let click = no_redirects().get(click_link).send().await.unwrap();
+    let open = reqwest::get(open_link).await.unwrap();
+
+    // Assert
+    assert_eq!(click.status().as_u16(), 302);
+    assert_eq!(open.status().as_u16(), 200);
+    assert_eq!(engagement(&app, &newsletter_issue_id).await["opened"], 0);
+}
+
+#[tokio::test]
+async fn nothing_is_recorded_once_the_list_stops_tracking() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    set_tracking(&app, true).await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let (newsletter_issue_id, html) = publish_issue(&app).await;
+    set_tracking(&app, false).await;
+
+    // Act
+    let open = reqwest::get(tracking_link(&app, &html, "o")).await.unwrap();
+
+    // Assert
+    assert_eq!(open.status().as_u16(), 200);
+    assert_eq!(engagement(&app, &newsletter_issue_id).await["opened"], 0);
+}
+
+#[tokio::test]
+async fn nothing_is_recorded_for_deleted_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    set_tracking(&app, true).await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let (_, html) = publish_issue(&app).await;
+    sqlx::query!("UPDATE subscriptions SET deleted_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let click = no_redirects()
+        .get(tracking_link(&app, &html, "t"))
+        .send()
+        .await
+        .unwrap();
+    let open = reqwest::get(tracking_link(&app, &html, "o")).await.unwrap();
+
+    // Assert
+    assert_eq!(click.status().as_u16(), 302);
+    assert_eq!(open.status().as_u16(), 200);
+    let events = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM tracking_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events, 0);
+}
+
+async fn tag_engaged_readers(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/tags/engaged_readers", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn readers_who_opened_or_clicked_an_issue_can_be_tagged() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    set_tracking(&app, true).await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .id;
+    let (newsletter_issue_id, html) = publish_issue(&app).await;
+    reqwest::get(tracking_link(&app, &html, "o")).await.unwrap();
+
+    // Act
+    let clicked: serde_json::Value = tag_engaged_readers(
+        &app,
+        serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "engagement": "clicked",
+            "tag": "clicked-first-issue",
+        }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let opened: serde_json::Value = tag_engaged_readers(
+        &app,
+        serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "engagement": "opened",
+            "tag": "read-first-issue",
+        }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(clicked["tagged"], 0);
+    assert_eq!(opened["tagged"], 1);
+    let tags = sqlx::query_scalar!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tags, vec!["read-first-issue"]);
+}
+
+#[tokio::test]
+async fn readers_of_untracked_or_unknown_issues_cannot_be_tagged() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    let (newsletter_issue_id, _) = publish_issue(&app).await;
+
+    // Act
+    let untracked = tag_engaged_readers(
+        &app,
+        serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "engagement": "opened",
+            "tag": "engaged",
+        }),
+    )
+    .await;
+    let unknown = tag_engaged_readers(
+        &app,
+        serde_json::json!({
+            "newsletter_issue_id": uuid::Uuid::new_v4(),
+            "engagement": "opened",
+            "tag": "engaged",
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(untracked.status().as_u16(), 400);
+    assert_eq!(unknown.status().as_u16(), 404);
+}
@@ -0,0 +1,185 @@
+use crate::helpers::{TestApp, newsletter_request_body, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::signed_tokens::TokenPurpose;
+
+/// The unsubscribe link at the bottom of the plain-text body of an issue.
+fn unsubscribe_link(app: &TestApp, email_request: &wiremock::Request) -> reqwest::Url {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let (_, link) = body["TextBody"]
+        .as_str()
+        .unwrap()
+        .rsplit_once("Unsubscribe: ")
+        .unwrap();
+    let mut link = reqwest::Url::parse(link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn get_unsubscribe(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        &app.address, token
+    ))
+    .await
+    .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_no_longer_get_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // Only the first issue goes out.
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+
+    // Act
+    let response = reqwest::get(unsubscribe_link(&app, email_request))
+        .await
+        .unwrap();
+    let second_issue = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(second_issue.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_is_in_the_language_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    sqlx::query!("UPDATE subscriptions SET language = 'fr'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+
+    // Act
+    let response = reqwest::get(unsubscribe_link(&app, email_request))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html")
+    );
+    let page = response.text().await.unwrap();
+    assert!(page.contains("lang=\"fr\""));
+}
+
+#[tokio::test]
+async fn a_subscriber_gets_the_same_unsubscribe_link_in_every_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_postal_address().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..2 {
+        app.post_newsletters(newsletter_request_body())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(
+        unsubscribe_link(&app, &requests[1]),
+        unsubscribe_link(&app, &requests[2])
+    );
+    let html: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert!(
+        html["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .ends_with(">Unsubscribe</a></p>")
+    );
+}
+
+#[tokio::test]
+async fn invalid_unsubscribe_tokens_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let signed = app
+        .token_signer
+        .sign(TokenPurpose::Subscription, "abcdefghijklmnopqrstuvwxy");
+
+    for token in ["abcdefghijklmnopqrstuvwxy", "not-a-token", &signed] {
+        // Act
+        let response = get_unsubscribe(&app, token).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn unknown_unsubscribe_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app
+        .token_signer
+        .sign(TokenPurpose::Unsubscribe, "abcdefghijklmnopqrstuvwxy");
+
+    // Act
+    let response = get_unsubscribe(&app, &token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribing_without_a_token_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/unsubscribe", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,236 @@
+use crate::helpers::{TestApp, TestUser, spawn_app, spawn_app_with};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn spawn_capped_app(max_active_subscribers: u64) -> TestApp {
+    let app = spawn_app_with(|c| {
+        c.application.max_active_subscribers = Some(max_active_subscribers);
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn signups_past_the_cap_are_waitlisted() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+
+    // Act
+    for email in ["a%40gmail.com", "b%40gmail.com"] {
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    assert_eq!(
+        statuses(&app).await,
+        vec![
+            ("a@gmail.com".into(), "pending_confirmation".into()),
+            ("b@gmail.com".into(), "waitlisted".into()),
+        ]
+    );
+    let emails = app.email_server.received_requests().await.unwrap();
+    let waitlist_email: serde_json::Value = serde_json::from_slice(&emails[1].body).unwrap();
+    assert_eq!(waitlist_email["Subject"], "You're on the waitlist");
+    assert!(
+        !waitlist_email["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("subscription_token")
+    );
+}
+
+#[tokio::test]
+async fn signups_are_not_waitlisted_without_a_cap() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=a%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(
+        statuses(&app).await,
+        vec![("a@gmail.com".into(), "pending_confirmation".into())]
+    );
+}
+
+#[tokio::test]
+async fn nobody_is_admitted_while_the_cap_is_reached() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+    for email in ["a%40gmail.com", "b%40gmail.com"] {
+        app.post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let response: serde_json::Value = app
+        .post_waitlist_admission(10)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response["admitted"], 0);
+    assert_eq!(response["still_waitlisted"], 1);
+}
+
+#[tokio::test]
+async fn admitted_subscribers_are_taken_in_signup_order_and_can_confirm() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+    for email in ["a%40gmail.com", "c%40gmail.com", "b%40gmail.com"] {
+        app.post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    // Make room, as if `a` had left.
+    sqlx::query!("DELETE FROM subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM subscriptions WHERE email = 'a@gmail.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response: serde_json::Value = app
+        .post_waitlist_admission(10)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response["admitted"], 1);
+    assert_eq!(response["still_waitlisted"], 1);
+    let confirmation_email = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&confirmation_email);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        statuses(&app).await,
+        vec![
+            ("b@gmail.com".into(), "waitlisted".into()),
+            ("c@gmail.com".into(), "confirmed".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admitting_nobody_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+
+    // Act
+    let response = app.post_waitlist_admission(0).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn admitting_takes_an_editor() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let url = format!("{}/admin/waitlist/admit", &app.address);
+    let body = serde_json::json!({ "count": 1 });
+
+    // Act
+    let anonymous = reqwest::Client::new()
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let as_viewer = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(as_viewer.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn admissions_are_recorded_as_made_by_the_user() {
+    // Arrange
+    let app = spawn_capped_app(1).await;
+    app.post_subscriptions("name=le%20guin&email=a%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_subscriptions("name=le%20guin&email=b%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("DELETE FROM subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM subscriptions WHERE email = 'a@gmail.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    app.post_waitlist_admission(1)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let admission = sqlx::query!("SELECT actor, user_id FROM audit_log WHERE event = 'admitted'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(admission.actor, "user");
+    assert_eq!(admission.user_id, Some(app.test_user.user_id));
+}
@@ -0,0 +1,269 @@
+//! The validation rules applied to subscriber data.
+//!
+//! `SubscriberName::parse` and `SubscriberEmail::parse` in `zero2prod` use the default rules,
+//! re-exported there as `zero2prod::domain::validation`.
+//! Other services - and the subscribe widget, which compiles this crate to wasm32 - can use
+//! the same rules directly, or tweak them with the builder methods:
+//!
+//! ```
+//! use zero2prod_validation::{EmailRules, NameRules};
+//!
+//! let name_rules = NameRules::default().max_graphemes(64);
+//! assert!(name_rules.validate("Ursula Le Guin").is_ok());
+//!
+//! let email_rules = EmailRules::default().block_domain("mailinator.com");
+//! assert!(email_rules.validate("ursula@mailinator.com").is_err());
+//! ```
+use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+use validator::ValidateEmail;
+
+/// Why a name or an email address was rejected.
+/// The messages don't repeat the value, so that they are safe to show and to log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainError {
+    /// Empty, or only whitespace.
+    Empty,
+    /// Longer than `max`: graphemes for names, bytes for emails.
+    TooLong {
+        max: usize,
+    },
+    ForbiddenCharacters,
+    InvalidEmail,
+    /// On a blocked domain, or not on the allowlist.
+    DomainNotAccepted {
+        domain: String,
+    },
+}
+
+impl DomainError {
+    /// A stable identifier of the kind of error, for clients to branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DomainError::Empty => "empty",
+            DomainError::TooLong { .. } => "too_long",
+            DomainError::ForbiddenCharacters => "forbidden_characters",
+            DomainError::InvalidEmail => "invalid_email",
+            DomainError::DomainNotAccepted { .. } => "domain_not_accepted",
+        }
+    }
+}
+
+impl fmt::Display for DomainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainError::Empty => write!(f, "Must not be empty."),
+            DomainError::TooLong { max } => write!(f, "Must be at most {} characters long.", max),
+            DomainError::ForbiddenCharacters => {
+                write!(f, "Contains characters that are not allowed.")
+            }
+            DomainError::InvalidEmail => write!(f, "Is not a valid email address."),
+            DomainError::DomainNotAccepted { domain } => {
+                write!(
+                    f,
+                    "Subscriptions from {} addresses are not accepted.",
+                    domain
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DomainError {}
+
+/// Characters that are never allowed in a subscriber name by default.
+/// They are commonly used in injection attacks against HTML and SQL.
+pub const DEFAULT_FORBIDDEN_NAME_CHARACTERS: [char; 9] =
+    ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
+/// Rules for subscriber names.
+///
+/// A name is valid when:
+/// - it is not empty and not made only of whitespace;
+/// - it is at most `max_graphemes` graphemes long (256 by default).
+///   We count graphemes rather than bytes or `char`s, so that "å" counts as one;
+/// - it contains none of the forbidden characters
+///   (`DEFAULT_FORBIDDEN_NAME_CHARACTERS` by default).
+#[derive(Clone, Debug)]
+pub struct NameRules {
+    max_graphemes: usize,
+    forbidden_characters: Vec<char>,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            max_graphemes: 256,
+            forbidden_characters: DEFAULT_FORBIDDEN_NAME_CHARACTERS.to_vec(),
+        }
+    }
+}
+
+impl NameRules {
+    pub fn max_graphemes(mut self, max_graphemes: usize) -> Self {
+        self.max_graphemes = max_graphemes;
+        self
+    }
+
+    /// Replace the set of forbidden characters.
+    pub fn forbidden_characters(mut self, characters: impl IntoIterator<Item = char>) -> Self {
+        self.forbidden_characters = characters.into_iter().collect();
+        self
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), DomainError> {
+        if name.trim().is_empty() {
+            return Err(DomainError::Empty);
+        }
+        // Stops counting past the limit: a huge name costs no more than a long one.
+        if name.graphemes(true).nth(self.max_graphemes).is_some() {
+            return Err(DomainError::TooLong {
+                max: self.max_graphemes,
+            });
+        }
+        if name.chars().any(|c| self.forbidden_characters.contains(&c)) {
+            return Err(DomainError::ForbiddenCharacters);
+        }
+        Ok(())
+    }
+}
+
+/// Rules for subscriber email addresses.
+///
+/// An email is valid when:
+/// - it is a syntactically valid address (as checked by the `validator` crate);
+/// - it is at most `max_length` bytes long (254 by default, the limit set by RFC 5321);
+/// - its domain is not blocked;
+/// - if an allowlist of domains is configured, its domain is on it.
+///
+/// Domains are compared case-insensitively.
+#[derive(Clone, Debug)]
+pub struct EmailRules {
+    max_length: usize,
+    blocked_domains: Vec<String>,
+    allowed_domains: Vec<String>,
+}
+
+impl Default for EmailRules {
+    fn default() -> Self {
+        Self {
+            max_length: 254,
+            blocked_domains: Vec::new(),
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+impl EmailRules {
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Reject every address on `domain`.
+    pub fn block_domain(mut self, domain: impl Into<String>) -> Self {
+        self.blocked_domains.push(domain.into().to_lowercase());
+        self
+    }
+
+    /// Only accept addresses on the allowed domains.
+    /// Every address is accepted (subject to the other rules) until the first domain is allowed.
+    pub fn allow_domain(mut self, domain: impl Into<String>) -> Self {
+        self.allowed_domains.push(domain.into().to_lowercase());
+        self
+    }
+
+    pub fn validate(&self, email: &str) -> Result<(), DomainError> {
+        if email.trim().is_empty() {
+            return Err(DomainError::Empty);
+        }
+        if email.len() > self.max_length {
+            return Err(DomainError::TooLong {
+                max: self.max_length,
+            });
+        }
+        if !email.validate_email() {
+            return Err(DomainError::InvalidEmail);
+        }
+        // `validate_email` guarantees there is an `@`.
+        let domain = email.rsplit('@').next().unwrap_or_default().to_lowercase();
+        let is_blocked = self.blocked_domains.contains(&domain);
+        let is_not_allowed =
+            !self.allowed_domains.is_empty() && !self.allowed_domains.contains(&domain);
+        if is_blocked || is_not_allowed {
+            return Err(DomainError::DomainNotAccepted { domain });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DomainError, EmailRules, NameRules};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn the_name_length_limit_is_configurable() {
+        let rules = NameRules::default().max_graphemes(5);
+        assert_ok!(rules.validate("Ursul"));
+        assert_err!(rules.validate("Ursula"));
+    }
+
+    #[test]
+    fn graphemes_are_counted_rather_than_bytes() {
+        let rules = NameRules::default().max_graphemes(1);
+        assert_ok!(rules.validate("å"));
+    }
+
+    #[test]
+    fn forbidden_characters_are_configurable() {
+        let rules = NameRules::default().forbidden_characters(['#']);
+        assert_ok!(rules.validate("Ursula (Le Guin)"));
+        assert_err!(rules.validate("Ursula #1"));
+    }
+
+    #[test]
+    fn each_broken_name_rule_has_its_own_error() {
+        let rules = NameRules::default().max_graphemes(5);
+        assert_eq!(rules.validate("  "), Err(DomainError::Empty));
+        assert_eq!(
+            rules.validate("Ursula"),
+            Err(DomainError::TooLong { max: 5 })
+        );
+        assert_eq!(rules.validate("<b>"), Err(DomainError::ForbiddenCharacters));
+    }
+
+    #[test]
+    fn each_broken_email_rule_has_its_own_error() {
+        let rules = EmailRules::default().block_domain("mailinator.com");
+        assert_eq!(rules.validate(""), Err(DomainError::Empty));
+        assert_eq!(rules.validate("ursula"), Err(DomainError::InvalidEmail));
+        assert_eq!(
+            rules.validate("ursula@mailinator.com"),
+            Err(DomainError::DomainNotAccepted {
+                domain: "mailinator.com".into()
+            })
+        );
+    }
+
+    #[test]
+    fn emails_longer_than_the_limit_are_rejected() {
+        let rules = EmailRules::default().max_length(16);
+        assert_ok!(rules.validate("ursula@gmail.com"));
+        assert_err!(rules.validate("ursula1@gmail.com"));
+    }
+
+    #[test]
+    fn blocked_domains_are_rejected_regardless_of_case() {
+        let rules = EmailRules::default().block_domain("Mailinator.com");
+        assert_err!(rules.validate("ursula@MAILINATOR.com"));
+        assert_ok!(rules.validate("ursula@gmail.com"));
+    }
+
+    #[test]
+    fn only_allowed_domains_are_accepted_once_an_allowlist_is_set() {
+        let rules = EmailRules::default().allow_domain("example.com");
+        assert_ok!(rules.validate("ursula@example.com"));
+        assert_err!(rules.validate("ursula@gmail.com"));
+    }
+}